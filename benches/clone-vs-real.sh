@@ -13,3 +13,32 @@ redis-benchmark $opts -t $benches
 echo "Clone Redis"
 echo "------------------------------------------------------------------------"
 redis-benchmark $opts -t $benches -p 8080
+
+# The scaling curve: the clone is started with each number of shards in turn.
+# Random keys (-r) spread the load across the shards and several benchmark
+# threads are needed to saturate more than one of them. Nothing is saved when
+# it is stopped, so each run starts empty, and its directory is a temporary
+# one in case anything is written there.
+scaling_benches="set,get,incr"
+scaling_opts="-q -c 200 --threads 4 -r 1000000 -n 1000000"
+scaling_port=8081
+shard_counts=${SHARDS:-"1 2 4 8"}
+clone=${CLONE:-"target/release/redis-clone"}
+
+scaling_dir=$(mktemp -d)
+trap 'rm -rf "$scaling_dir"' EXIT
+
+echo "Clone Redis scaling"
+echo "------------------------------------------------------------------------"
+for shards in $shard_counts; do
+  RUST_LOG=warn $clone --port $scaling_port --shards $shards --save "" --dir "$scaling_dir" \
+    > /dev/null 2>&1 &
+  pid=$!
+  sleep 1
+
+  echo "shards=$shards"
+  redis-benchmark $scaling_opts -t $scaling_benches -p $scaling_port
+
+  kill $pid
+  wait $pid 2> /dev/null
+done
@@ -0,0 +1,222 @@
+//! Compares the incremental RESP decoder with the previous one, which read
+//! each request a line at a time into freshly allocated buffers and copied
+//! out every argument. The number of allocations per request is printed
+//! before the throughput benchmarks.
+
+use byte_string::ByteString;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::executor::block_on;
+use redis_clone::protocol::{Decoder, Limits};
+use stats_alloc::{Region, StatsAlloc, INSTRUMENTED_SYSTEM};
+use std::alloc::System;
+
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
+
+/// The decoder as it was before it was made incremental, copied from the
+/// baseline `src/protocol.rs`. A `ByteString` is now changed through
+/// `to_mut`, otherwise it is unchanged.
+mod previous {
+    const MAX_ARRAY_SIZE: usize = 1024 * 1024;
+    const MAX_BULK_STR_SIZE: usize = 512 * 1024 * 1024;
+    const MAX_LINE_LENGTH: usize = 64 * 1024;
+    const LF: u8 = b'\n';
+    const CRLF: &[u8] = b"\r\n";
+
+    use byte_string::{ByteStr, ByteString};
+    use redis_clone::protocol::{ProtoError, ProtoResult};
+    use std::convert::{TryFrom, TryInto};
+    use std::marker::Unpin;
+    use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+    pub async fn decode<T: AsyncBufRead + Unpin + Send>(
+        mut stream: T,
+    ) -> ProtoResult<Vec<ByteString>> {
+        let mut buffer = vec![];
+        let (type_sym, value_str) = read_header(&mut stream, &mut buffer).await?;
+
+        if type_sym != b'*' {
+            return Err(ProtoError::UnsupportedSymbol(type_sym.into()));
+        }
+
+        let len = value_str.parse().or(Err(ProtoError::InvalidArraySize))?;
+        let value = read_array(&mut stream, len).await?;
+        Ok(value)
+    }
+
+    async fn read_header<'a>(
+        stream: &mut (impl AsyncBufRead + Unpin + Send),
+        buffer: &'a mut Vec<u8>,
+    ) -> ProtoResult<(u8, ByteStr<'a>)> {
+        read_line(stream, buffer).await?;
+
+        let (&type_sym, tail) = buffer
+            .split_first()
+            .ok_or_else(|| ProtoError::from("Error parsing resp header structure"))?;
+
+        Ok((type_sym, ByteStr::from(tail)))
+    }
+
+    async fn read_line(
+        stream: &mut (impl AsyncBufRead + Unpin + Send),
+        buffer: &mut Vec<u8>,
+    ) -> ProtoResult<()> {
+        let limit = MAX_LINE_LENGTH.try_into().unwrap();
+        let num_bytes = stream.take(limit).read_until(LF, buffer).await?;
+
+        // If we got nothing then we can assume the connection has closed
+        if num_bytes == 0 {
+            return Err(ProtoError::ConnectionClosed);
+        }
+        // We must have at least 2 bytes for CRLF
+        if num_bytes < 2 {
+            return Err(ProtoError::InvalidTerminator);
+        }
+        // The line must be terminated by CRLF
+        if &buffer[(num_bytes - 2)..] != CRLF {
+            // We may be missing the CRLF because the line limit has been exceeded
+            if num_bytes == MAX_LINE_LENGTH {
+                return Err(ProtoError::ExceededMaxLineLength);
+            }
+
+            return Err(ProtoError::InvalidTerminator);
+        }
+
+        // Drop the CRLF
+        buffer.truncate(num_bytes - 2);
+
+        Ok(())
+    }
+
+    async fn read_bulk_string(
+        stream: &mut (impl AsyncBufRead + Unpin + Send),
+        len: i64,
+    ) -> ProtoResult<ByteString> {
+        let len = usize::try_from(len).or(Err(ProtoError::InvalidBulkStringSize))?;
+
+        if len > MAX_BULK_STR_SIZE {
+            return Err(ProtoError::InvalidBulkStringSize);
+        }
+
+        let mut buffer = ByteString::from(vec![0; len + 2]);
+        stream.read_exact(&mut buffer).await?;
+
+        // Drop the trailing end of line chars
+        buffer.to_mut().truncate(len);
+
+        Ok(buffer)
+    }
+
+    async fn read_array(
+        stream: &mut (impl AsyncBufRead + Unpin + Send),
+        len: i64,
+    ) -> ProtoResult<Vec<ByteString>> {
+        // We don't need to support empty or null arrays in requests
+        if len == 0 || len == -1 {
+            return Err(ProtoError::EmptyRequest);
+        }
+
+        let len = usize::try_from(len).or(Err(ProtoError::InvalidArraySize))?;
+
+        if len > MAX_ARRAY_SIZE {
+            return Err(ProtoError::InvalidArraySize);
+        }
+
+        let mut elements = Vec::with_capacity(len);
+        let mut buffer = vec![];
+
+        for _ in 0..len {
+            buffer.clear();
+
+            let (type_sym, value_str) = read_header(stream, &mut buffer).await?;
+            if type_sym != b'$' {
+                return Err(ProtoError::UnsupportedSymbol(type_sym.into()));
+            }
+
+            let len = value_str
+                .parse()
+                .or(Err(ProtoError::InvalidBulkStringSize))?;
+            let value = read_bulk_string(stream, len).await?;
+
+            elements.push(value);
+        }
+
+        Ok(elements)
+    }
+}
+
+fn set_requests(count: usize, value_len: usize) -> Vec<u8> {
+    let value = "v".repeat(value_len);
+    let request = format!(
+        "*3\r\n$3\r\nSET\r\n$16\r\nkey:000000000042\r\n${}\r\n{}\r\n",
+        value_len, value
+    );
+
+    request.repeat(count).into_bytes()
+}
+
+fn decode_previous(mut input: &[u8], count: usize) {
+    for _ in 0..count {
+        let request = block_on(previous::decode(&mut input)).expect("a valid request");
+        assert_eq!(request.len(), 3);
+    }
+}
+
+fn decode_incremental(mut input: &[u8], count: usize) {
+    let mut decoder = Decoder::new();
+
+    for _ in 0..count {
+        block_on(decoder.decode(&mut input, Limits::default())).expect("a valid request");
+        let request: Vec<ByteString> = decoder
+            .take_args()
+            .into_iter()
+            .map(ByteString::shared)
+            .collect();
+        assert_eq!(request.len(), 3);
+    }
+}
+
+fn allocations(_: &mut Criterion) {
+    let count = 1000;
+    let input = set_requests(count, 64);
+
+    let region = Region::new(GLOBAL);
+    decode_previous(&input, count);
+    let previous = region.change().allocations;
+
+    let region = Region::new(GLOBAL);
+    decode_incremental(&input, count);
+    let incremental = region.change().allocations;
+
+    println!(
+        "allocations per SET request: previous {:.1}, incremental {:.1}",
+        previous as f64 / count as f64,
+        incremental as f64 / count as f64
+    );
+}
+
+fn throughput(c: &mut Criterion) {
+    let count = 1000;
+    let mut group = c.benchmark_group("decode");
+
+    for &value_len in &[16, 1024, 64 * 1024] {
+        let input = set_requests(count, value_len);
+        group.throughput(Throughput::Bytes(input.len() as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("previous", value_len),
+            &input,
+            |b, input| b.iter(|| decode_previous(input, count)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("incremental", value_len),
+            &input,
+            |b, input| b.iter(|| decode_incremental(input, count)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, allocations, throughput);
+criterion_main!(benches);
@@ -1,12 +1,13 @@
 #![forbid(unsafe_code)]
 
+use bytes::Bytes;
 use std::borrow::Cow;
 
 mod from_bytes;
 use from_bytes::from_bytes;
 pub use from_bytes::{Number, ParseIntError};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct ByteStr<'inner> {
     bytes: &'inner [u8],
 }
@@ -55,11 +56,49 @@ impl<'outer> ByteStr<'outer> {
 
         ByteString::from(uppered_bytes)
     }
+
+    /// A quoted representation with non-printable bytes escaped, in the same
+    /// way as Redis's `sdscatrepr`
+    pub fn to_repr(&self) -> String {
+        let mut repr = String::with_capacity(self.bytes.len() + 2);
+
+        repr.push('"');
+        for &b in self.bytes {
+            match b {
+                b'\\' => repr.push_str("\\\\"),
+                b'"' => repr.push_str("\\\""),
+                b'\n' => repr.push_str("\\n"),
+                b'\r' => repr.push_str("\\r"),
+                b'\t' => repr.push_str("\\t"),
+                0x07 => repr.push_str("\\a"),
+                0x08 => repr.push_str("\\b"),
+                b' '..=b'~' => repr.push(char::from(b)),
+                _ => repr.push_str(&format!("\\x{:02x}", b)),
+            }
+        }
+        repr.push('"');
+
+        repr
+    }
 }
 
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+/// An owned string of bytes, or a slice of a shared buffer such as the one
+/// a request was read into. A shared string is copied when it is cloned or
+/// changed, so a long lived copy never holds on to the whole buffer.
+#[derive(Default)]
 pub struct ByteString {
-    bytes: Vec<u8>,
+    bytes: Repr,
+}
+
+enum Repr {
+    Owned(Vec<u8>),
+    Shared(Bytes),
+}
+
+impl Default for Repr {
+    fn default() -> Self {
+        Repr::Owned(vec![])
+    }
 }
 
 impl ByteString {
@@ -67,12 +106,34 @@ impl ByteString {
         Default::default()
     }
 
+    /// A string sharing the buffer `bytes` is a slice of, without copying it
+    pub fn shared(bytes: Bytes) -> Self {
+        ByteString {
+            bytes: Repr::Shared(bytes),
+        }
+    }
+
     pub fn as_byte_str(&self) -> ByteStr<'_> {
-        ByteStr::new(&self.bytes)
+        ByteStr::new(self.as_ref())
     }
 
     pub fn into_vec(self) -> Vec<u8> {
-        self.bytes
+        match self.bytes {
+            Repr::Owned(bytes) => bytes,
+            Repr::Shared(bytes) => bytes.to_vec(),
+        }
+    }
+
+    /// The bytes to change in place, copied out of a shared buffer first
+    pub fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let Repr::Shared(bytes) = &self.bytes {
+            self.bytes = Repr::Owned(bytes.to_vec());
+        }
+
+        match &mut self.bytes {
+            Repr::Owned(bytes) => bytes,
+            Repr::Shared(_) => unreachable!(),
+        }
     }
 
     pub fn parse<T: Number>(&self) -> Result<T, ParseIntError> {
@@ -86,6 +147,10 @@ impl ByteString {
     pub fn to_uppercase(&self) -> Self {
         self.as_byte_str().to_uppercase()
     }
+
+    pub fn to_repr(&self) -> String {
+        self.as_byte_str().to_repr()
+    }
 }
 
 mod impl_from {
@@ -104,15 +169,15 @@ mod impl_from {
 
     impl From<Vec<u8>> for ByteString {
         fn from(other: Vec<u8>) -> Self {
-            ByteString { bytes: other }
+            ByteString {
+                bytes: Repr::Owned(other),
+            }
         }
     }
 
     impl From<String> for ByteString {
         fn from(other: String) -> Self {
-            ByteString {
-                bytes: other.into_bytes(),
-            }
+            ByteString::from(other.into_bytes())
         }
     }
 
@@ -121,9 +186,7 @@ mod impl_from {
         T: AsRef<[u8]> + ?Sized,
     {
         fn from(other: &T) -> Self {
-            ByteString {
-                bytes: other.as_ref().to_vec(),
-            }
+            ByteString::from(other.as_ref().to_vec())
         }
     }
 }
@@ -139,7 +202,71 @@ mod impl_as_ref {
 
     impl AsRef<[u8]> for ByteString {
         fn as_ref(&self) -> &[u8] {
-            self.bytes.as_ref()
+            match &self.bytes {
+                Repr::Owned(bytes) => bytes,
+                Repr::Shared(bytes) => bytes,
+            }
+        }
+    }
+}
+
+mod impl_borrow {
+    use super::*;
+    use std::borrow::Borrow;
+
+    // Allows maps keyed by ByteString to be searched with a byte slice, the
+    // derived Hash, Eq and Ord agree with those of the slice
+    impl Borrow<[u8]> for ByteString {
+        fn borrow(&self) -> &[u8] {
+            self.as_ref()
+        }
+    }
+}
+
+// Clone, comparison and hashing follow the bytes, wherever they are held
+mod impl_bytes {
+    use super::*;
+    use std::cmp::Ordering;
+    use std::fmt::{self, Debug};
+    use std::hash::{Hash, Hasher};
+
+    impl Clone for ByteString {
+        fn clone(&self) -> Self {
+            ByteString::from(self.as_ref())
+        }
+    }
+
+    impl Debug for ByteString {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("ByteString")
+                .field("bytes", &self.as_ref())
+                .finish()
+        }
+    }
+
+    impl PartialEq for ByteString {
+        fn eq(&self, other: &Self) -> bool {
+            self.as_ref() == other.as_ref()
+        }
+    }
+
+    impl Eq for ByteString {}
+
+    impl PartialOrd for ByteString {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for ByteString {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.as_ref().cmp(other.as_ref())
+        }
+    }
+
+    impl Hash for ByteString {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.as_ref().hash(state);
         }
     }
 }
@@ -174,16 +301,16 @@ mod impl_deref {
     }
 
     impl Deref for ByteString {
-        type Target = Vec<u8>;
+        type Target = [u8];
 
         fn deref(&self) -> &Self::Target {
-            &self.bytes
+            self.as_ref()
         }
     }
 
     impl DerefMut for ByteString {
         fn deref_mut(&mut self) -> &mut Self::Target {
-            &mut self.bytes
+            self.to_mut()
         }
     }
 }
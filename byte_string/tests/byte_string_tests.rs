@@ -108,8 +108,31 @@ mod deref {
     #[test]
     fn test_byte_string_mut_deref() {
         let mut a = ByteString::from(b"hello");
-        a.push(b'a');
-        assert_eq!(a.len(), 6);
+        a[0] = b'j';
+        a.to_mut().push(b'a');
+        assert_eq!(a, ByteString::from(b"jelloa"));
+    }
+}
+
+mod shared {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_byte_string_shared() {
+        let buffer = Bytes::from_static(b"get hello");
+        let a = ByteString::shared(buffer.slice(4..));
+        assert_eq!(a, ByteString::from(b"hello"));
+        assert_eq!(a.clone(), a);
+    }
+
+    #[test]
+    fn test_byte_string_shared_mut() {
+        let buffer = Bytes::from_static(b"get hello");
+        let mut a = ByteString::shared(buffer.slice(4..));
+        a.to_mut().push(b'!');
+        assert_eq!(a, ByteString::from(b"hello!"));
+        assert_eq!(buffer, Bytes::from_static(b"get hello"));
     }
 }
 
@@ -198,6 +221,18 @@ mod ascii_compat {
         let b = a.to_uppercase();
         assert_eq!(b, "ABCABC123\x01".into())
     }
+
+    #[test]
+    fn test_byte_str_to_repr() {
+        let a: ByteStr = b"a \"b\"\\\r\n\t\x07\x08\x01\xff".into();
+        assert_eq!(a.to_repr(), r#""a \"b\"\\\r\n\t\a\b\x01\xff""#)
+    }
+
+    #[test]
+    fn test_byte_string_to_repr() {
+        let a: ByteString = "set".into();
+        assert_eq!(a.to_repr(), "\"set\"")
+    }
 }
 
 mod in_collections {
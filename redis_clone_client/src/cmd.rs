@@ -0,0 +1,122 @@
+//! Commands, and pipelines of them, built up from their arguments
+
+use crate::{
+    connection::Connection,
+    error::Result,
+    types::{FromValue, ToArg},
+    value::Value,
+};
+
+/// A command along with its arguments
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cmd {
+    args: Vec<Vec<u8>>,
+}
+
+/// Starts a command, such as `cmd("SET").arg("key").arg(1)`
+pub fn cmd(name: &str) -> Cmd {
+    Cmd::new(name)
+}
+
+impl Cmd {
+    pub fn new(name: &str) -> Self {
+        Self {
+            args: vec![name.to_arg()],
+        }
+    }
+
+    pub fn arg<A: ToArg>(mut self, arg: A) -> Self {
+        self.args.push(arg.to_arg());
+        self
+    }
+
+    pub fn args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: ToArg,
+    {
+        self.args.extend(args.into_iter().map(|arg| arg.to_arg()));
+        self
+    }
+
+    /// The command name followed by its arguments
+    pub fn argv(&self) -> &[Vec<u8>] {
+        &self.args
+    }
+
+    /// Runs the command, converting its reply
+    pub async fn query<T: FromValue>(&self, conn: &mut Connection) -> Result<T> {
+        conn.query(self).await
+    }
+
+    /// Appends the command as a RESP array of bulk strings
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend(format!("*{}\r\n", self.args.len()).as_bytes());
+        for arg in &self.args {
+            out.extend(format!("${}\r\n", arg.len()).as_bytes());
+            out.extend(arg);
+            out.extend(b"\r\n");
+        }
+    }
+}
+
+/// Commands sent together without waiting for each reply in turn. Their
+/// replies are returned as an array, any error replies included.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pipeline {
+    cmds: Vec<Cmd>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cmd(mut self, cmd: Cmd) -> Self {
+        self.cmds.push(cmd);
+        self
+    }
+
+    pub fn push(&mut self, cmd: Cmd) {
+        self.cmds.push(cmd);
+    }
+
+    pub fn len(&self) -> usize {
+        self.cmds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cmds.is_empty()
+    }
+
+    /// Runs the commands, converting the array of their replies, such as
+    /// into a tuple or a `Vec<Value>`
+    pub async fn query<T: FromValue>(&self, conn: &mut Connection) -> Result<T> {
+        let replies = conn.pipeline(&self.cmds).await?;
+        T::from_value(Value::Array(replies))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut out = vec![];
+        cmd("SET").arg("key").arg(12).arg(b"").encode(&mut out);
+        assert_eq!(
+            out,
+            b"*4\r\n$3\r\nSET\r\n$3\r\nkey\r\n$2\r\n12\r\n$0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_args() {
+        let cmd = cmd("DEL").args(&["a", "b"]).args(vec![String::from("c")]);
+        assert_eq!(
+            cmd.argv(),
+            &[b"DEL".to_vec(), b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+    }
+}
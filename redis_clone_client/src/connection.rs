@@ -0,0 +1,517 @@
+//! A connection to the server, and the typed API of the commands run over it
+
+use crate::{
+    cmd::{cmd, Cmd},
+    decoder::Decoder,
+    error::{Error, Result},
+    pubsub::PubSub,
+    types::{FromValue, ToArg},
+    value::Value,
+};
+use log::debug;
+use std::path::Path;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs, UnixStream},
+};
+
+/// The version of RESP the server replies in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Resp2,
+    /// Switched to with HELLO, which the clone doesn't support
+    Resp3,
+}
+
+/// How a connection is set up once connected
+#[derive(Clone, Debug)]
+pub struct ConnectOptions {
+    pub protocol: Protocol,
+    /// Given to the server by CLIENT SETNAME
+    pub client_name: Option<String>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            protocol: Protocol::Resp2,
+            client_name: None,
+        }
+    }
+}
+
+/// The streams a connection may be over
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+pub struct Connection {
+    stream: Box<dyn Stream>,
+    decoder: Decoder,
+    protocol: Protocol,
+    /// Replies the server owes for commands sent, including those of
+    /// commands whose caller gave up waiting
+    pending: usize,
+    /// Whether the connection failed and can't be used any more
+    broken: bool,
+}
+
+impl Connection {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::connect_with(addr, &ConnectOptions::default()).await
+    }
+
+    pub async fn connect_with<A: ToSocketAddrs>(addr: A, options: &ConnectOptions) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Self::from_stream(stream, options).await
+    }
+
+    pub async fn connect_unix<P: AsRef<Path>>(path: P, options: &ConnectOptions) -> Result<Self> {
+        let stream = UnixStream::connect(path).await?;
+        Self::from_stream(stream, options).await
+    }
+
+    /// Sets up a connection over a stream that is already connected, such as
+    /// one over TLS
+    pub async fn from_stream<S>(stream: S, options: &ConnectOptions) -> Result<Self>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut conn = Self {
+            stream: Box::new(stream),
+            decoder: Decoder::new(),
+            protocol: Protocol::Resp2,
+            pending: 0,
+            broken: false,
+        };
+
+        if options.protocol == Protocol::Resp3 {
+            let mut hello = cmd("HELLO").arg(3);
+            if let Some(name) = &options.client_name {
+                hello = hello.arg("SETNAME").arg(name);
+            }
+            conn.query::<Value>(&hello).await?;
+            conn.protocol = Protocol::Resp3;
+        } else if let Some(name) = &options.client_name {
+            conn.query::<()>(&cmd("CLIENT").arg("SETNAME").arg(name))
+                .await?;
+        }
+
+        Ok(conn)
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Whether the connection can run another command straight away, which
+    /// it can't if it has failed or is still owed replies
+    pub fn is_idle(&self) -> bool {
+        !self.broken && self.pending == 0
+    }
+
+    /// Runs a command, converting its reply. An error reply is returned as
+    /// `Error::Server`.
+    pub async fn query<T: FromValue>(&mut self, cmd: &Cmd) -> Result<T> {
+        self.send(std::slice::from_ref(cmd)).await?;
+        match self.replies(1).await?.pop() {
+            Some(Value::Error(msg)) => Err(Error::Server(msg)),
+            Some(value) => T::from_value(value),
+            None => unreachable!("one reply was read"),
+        }
+    }
+
+    /// Runs several commands at once, returning their replies
+    pub(crate) async fn pipeline(&mut self, cmds: &[Cmd]) -> Result<Vec<Value>> {
+        self.send(cmds).await?;
+        self.replies(cmds.len()).await
+    }
+
+    /// Sends commands without waiting for their replies, which are then read
+    /// by `receive`
+    pub async fn send(&mut self, cmds: &[Cmd]) -> Result<()> {
+        let mut out = vec![];
+        for cmd in cmds {
+            cmd.encode(&mut out);
+        }
+
+        self.pending += cmds.len();
+        let written = self.stream.write_all(&out).await;
+        self.check(written.map_err(Error::from))
+    }
+
+    /// Waits for the next value from the server, which is either a reply or
+    /// data pushed by the server, such as a Pub/Sub message
+    pub async fn receive(&mut self) -> Result<Value> {
+        let value = self.decoder.decode(&mut self.stream).await;
+        let value = self.check(value)?;
+
+        match value {
+            Value::Push(_) => (),
+            _ => self.pending = self.pending.saturating_sub(1),
+        }
+        Ok(value)
+    }
+
+    /// Reads the last `count` replies owed, skipping those of commands that
+    /// were given up on and any pushed data
+    async fn replies(&mut self, count: usize) -> Result<Vec<Value>> {
+        let mut replies = Vec::with_capacity(count);
+
+        while self.pending > 0 {
+            let skip = self.pending > count;
+            match self.receive().await? {
+                Value::Push(push) => debug!("Ignoring pushed data {:?}", push),
+                _ if skip => debug!("Skipping the reply to an abandoned command"),
+                reply => replies.push(reply),
+            }
+        }
+
+        Ok(replies)
+    }
+
+    /// Marks the connection as broken when an error leaves it unusable
+    fn check<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(Error::Io(_)) | Err(Error::Protocol(_)) | Err(Error::ConnectionClosed) = result {
+            self.broken = true;
+        }
+        result
+    }
+
+    /// Turns the connection into one for receiving Pub/Sub messages
+    pub fn into_pubsub(self) -> PubSub {
+        PubSub::new(self)
+    }
+
+    // Keys
+
+    pub async fn del<K: ToArg>(&mut self, keys: &[K]) -> Result<usize> {
+        self.query(&cmd("DEL").args(keys)).await
+    }
+
+    pub async fn exists<K: ToArg>(&mut self, keys: &[K]) -> Result<usize> {
+        self.query(&cmd("EXISTS").args(keys)).await
+    }
+
+    pub async fn expire<K: ToArg>(&mut self, key: K, seconds: i64) -> Result<bool> {
+        self.query(&cmd("EXPIRE").arg(key).arg(seconds)).await
+    }
+
+    pub async fn persist<K: ToArg>(&mut self, key: K) -> Result<bool> {
+        self.query(&cmd("PERSIST").arg(key)).await
+    }
+
+    /// The seconds until the key expires, -1 if it doesn't or -2 if there is
+    /// no such key
+    pub async fn ttl<K: ToArg>(&mut self, key: K) -> Result<i64> {
+        self.query(&cmd("TTL").arg(key)).await
+    }
+
+    pub async fn keys<P: ToArg, T: FromValue>(&mut self, pattern: P) -> Result<T> {
+        self.query(&cmd("KEYS").arg(pattern)).await
+    }
+
+    /// The name of the type of the key's value, see TYPE
+    pub async fn key_type<K: ToArg>(&mut self, key: K) -> Result<String> {
+        self.query(&cmd("TYPE").arg(key)).await
+    }
+
+    // Strings
+
+    pub async fn get<K: ToArg, T: FromValue>(&mut self, key: K) -> Result<T> {
+        self.query(&cmd("GET").arg(key)).await
+    }
+
+    pub async fn set<K: ToArg, V: ToArg>(&mut self, key: K, value: V) -> Result<()> {
+        self.query(&cmd("SET").arg(key).arg(value)).await
+    }
+
+    /// Sets the key to expire after the given number of seconds
+    pub async fn set_ex<K: ToArg, V: ToArg>(
+        &mut self,
+        key: K,
+        value: V,
+        seconds: u64,
+    ) -> Result<()> {
+        self.query(&cmd("SET").arg(key).arg(value).arg("EX").arg(seconds))
+            .await
+    }
+
+    pub async fn mget<K: ToArg, T: FromValue>(&mut self, keys: &[K]) -> Result<T> {
+        self.query(&cmd("MGET").args(keys)).await
+    }
+
+    pub async fn mset<K: ToArg, V: ToArg>(&mut self, pairs: &[(K, V)]) -> Result<()> {
+        let mut mset = cmd("MSET");
+        for (key, value) in pairs {
+            mset = mset.arg(key).arg(value);
+        }
+        self.query(&mset).await
+    }
+
+    pub async fn incr<K: ToArg>(&mut self, key: K) -> Result<i64> {
+        self.query(&cmd("INCR").arg(key)).await
+    }
+
+    pub async fn incr_by<K: ToArg>(&mut self, key: K, by: i64) -> Result<i64> {
+        self.query(&cmd("INCRBY").arg(key).arg(by)).await
+    }
+
+    pub async fn decr<K: ToArg>(&mut self, key: K) -> Result<i64> {
+        self.query(&cmd("DECR").arg(key)).await
+    }
+
+    pub async fn decr_by<K: ToArg>(&mut self, key: K, by: i64) -> Result<i64> {
+        self.query(&cmd("DECRBY").arg(key).arg(by)).await
+    }
+
+    // Lists
+
+    /// Returns the length of the list once pushed
+    pub async fn lpush<K: ToArg, V: ToArg>(&mut self, key: K, values: &[V]) -> Result<usize> {
+        self.query(&cmd("LPUSH").arg(key).args(values)).await
+    }
+
+    /// Returns the length of the list once pushed
+    pub async fn rpush<K: ToArg, V: ToArg>(&mut self, key: K, values: &[V]) -> Result<usize> {
+        self.query(&cmd("RPUSH").arg(key).args(values)).await
+    }
+
+    pub async fn lpop<K: ToArg, T: FromValue>(&mut self, key: K) -> Result<T> {
+        self.query(&cmd("LPOP").arg(key)).await
+    }
+
+    pub async fn rpop<K: ToArg, T: FromValue>(&mut self, key: K) -> Result<T> {
+        self.query(&cmd("RPOP").arg(key)).await
+    }
+
+    pub async fn llen<K: ToArg>(&mut self, key: K) -> Result<usize> {
+        self.query(&cmd("LLEN").arg(key)).await
+    }
+
+    pub async fn lindex<K: ToArg, T: FromValue>(&mut self, key: K, index: i64) -> Result<T> {
+        self.query(&cmd("LINDEX").arg(key).arg(index)).await
+    }
+
+    pub async fn lset<K: ToArg, V: ToArg>(&mut self, key: K, index: i64, value: V) -> Result<()> {
+        self.query(&cmd("LSET").arg(key).arg(index).arg(value))
+            .await
+    }
+
+    pub async fn lrange<K: ToArg, T: FromValue>(
+        &mut self,
+        key: K,
+        start: i64,
+        stop: i64,
+    ) -> Result<T> {
+        self.query(&cmd("LRANGE").arg(key).arg(start).arg(stop))
+            .await
+    }
+
+    pub async fn ltrim<K: ToArg>(&mut self, key: K, start: i64, stop: i64) -> Result<()> {
+        self.query(&cmd("LTRIM").arg(key).arg(start).arg(stop))
+            .await
+    }
+
+    /// Returns the number of values removed
+    pub async fn lrem<K: ToArg, V: ToArg>(
+        &mut self,
+        key: K,
+        count: i64,
+        value: V,
+    ) -> Result<usize> {
+        self.query(&cmd("LREM").arg(key).arg(count).arg(value))
+            .await
+    }
+
+    // Hashes
+
+    /// Returns the number of fields added
+    pub async fn hset<K: ToArg, F: ToArg, V: ToArg>(
+        &mut self,
+        key: K,
+        pairs: &[(F, V)],
+    ) -> Result<usize> {
+        let mut hset = cmd("HSET").arg(key);
+        for (field, value) in pairs {
+            hset = hset.arg(field).arg(value);
+        }
+        self.query(&hset).await
+    }
+
+    pub async fn hget<K: ToArg, F: ToArg, T: FromValue>(&mut self, key: K, field: F) -> Result<T> {
+        self.query(&cmd("HGET").arg(key).arg(field)).await
+    }
+
+    pub async fn hmget<K: ToArg, F: ToArg, T: FromValue>(
+        &mut self,
+        key: K,
+        fields: &[F],
+    ) -> Result<T> {
+        self.query(&cmd("HMGET").arg(key).args(fields)).await
+    }
+
+    /// The fields and values of the hash, such as into a `HashMap`
+    pub async fn hgetall<K: ToArg, T: FromValue>(&mut self, key: K) -> Result<T> {
+        self.query(&cmd("HGETALL").arg(key)).await
+    }
+
+    // Pub/Sub
+
+    /// Returns the number of clients the message was sent to
+    pub async fn publish<C: ToArg, M: ToArg>(&mut self, channel: C, message: M) -> Result<usize> {
+        self.query(&cmd("PUBLISH").arg(channel).arg(message)).await
+    }
+
+    // Server
+
+    pub async fn flushdb(&mut self) -> Result<()> {
+        self.query(&cmd("FLUSHDB")).await
+    }
+
+    /// The text of INFO, of every section or of those given
+    pub async fn info(&mut self, sections: &[&str]) -> Result<String> {
+        self.query(&cmd("INFO").args(sections)).await
+    }
+
+    pub async fn config_get<T: FromValue>(&mut self, pattern: &str) -> Result<T> {
+        self.query(&cmd("CONFIG").arg("GET").arg(pattern)).await
+    }
+
+    pub async fn config_set<V: ToArg>(&mut self, parameter: &str, value: V) -> Result<()> {
+        self.query(&cmd("CONFIG").arg("SET").arg(parameter).arg(value))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+
+    /// A server that answers each request read with the next of `replies`,
+    /// sending back the requests it was sent once the client has gone
+    fn fake_server(
+        mut stream: DuplexStream,
+        replies: Vec<&'static [u8]>,
+    ) -> tokio::task::JoinHandle<Vec<u8>> {
+        tokio::spawn(async move {
+            let mut received = vec![];
+            for reply in replies {
+                let mut buffer = [0; 1024];
+                let n = stream.read(&mut buffer).await.unwrap();
+                received.extend_from_slice(&buffer[..n]);
+                stream.write_all(reply).await.unwrap();
+            }
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        })
+    }
+
+    async fn connect(
+        replies: Vec<&'static [u8]>,
+    ) -> (Connection, tokio::task::JoinHandle<Vec<u8>>) {
+        let (client, server) = duplex(64 * 1024);
+        let server = fake_server(server, replies);
+        let conn = Connection::from_stream(client, &ConnectOptions::default())
+            .await
+            .unwrap();
+        (conn, server)
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let (mut conn, server) = connect(vec![b"+OK\r\n", b"$1\r\nv\r\n", b"$-1\r\n"]).await;
+
+        conn.set("k", "v").await.unwrap();
+        assert_eq!(conn.get::<_, String>("k").await.unwrap(), "v");
+        assert_eq!(conn.get::<_, Option<String>>("x").await.unwrap(), None);
+        assert!(conn.is_idle());
+
+        drop(conn);
+        assert_eq!(
+            server.await.unwrap(),
+            b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n*2\r\n$3\r\nGET\r\n$1\r\nx\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_reply() {
+        let (mut conn, _server) = connect(vec![b"-WRONGTYPE Operation\r\n", b":1\r\n"]).await;
+
+        let err = conn.incr("k").await.unwrap_err();
+        assert_eq!(err.code(), Some("WRONGTYPE"));
+
+        // The connection is still usable
+        assert!(conn.is_idle());
+        assert_eq!(conn.incr("k").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_closed() {
+        let (client, server) = duplex(64 * 1024);
+        let mut conn = Connection::from_stream(client, &ConnectOptions::default())
+            .await
+            .unwrap();
+        drop(server);
+
+        assert!(matches!(
+            conn.get::<_, Value>("k").await,
+            Err(Error::ConnectionClosed) | Err(Error::Io(_))
+        ));
+        assert!(!conn.is_idle());
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_reply_is_skipped() {
+        let (client, mut server) = duplex(64 * 1024);
+        let mut conn = Connection::from_stream(client, &ConnectOptions::default())
+            .await
+            .unwrap();
+
+        // The first query is given up on before its reply arrives
+        let abandoned = tokio::time::timeout(
+            std::time::Duration::from_millis(10),
+            conn.get::<_, Value>("slow"),
+        )
+        .await;
+        assert!(abandoned.is_err());
+        assert!(!conn.is_idle());
+
+        server
+            .write_all(b"$4\r\nslow\r\n$4\r\nfast\r\n")
+            .await
+            .unwrap();
+        assert_eq!(conn.get::<_, String>("fast").await.unwrap(), "fast");
+        assert!(conn.is_idle());
+    }
+
+    #[tokio::test]
+    async fn test_resp3() {
+        let (client, server) = duplex(64 * 1024);
+        let server = fake_server(
+            server,
+            vec![
+                b"%1\r\n+proto\r\n:3\r\n",
+                b">3\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n_\r\n%1\r\n$1\r\nf\r\n$1\r\nv\r\n",
+            ],
+        );
+        let options = ConnectOptions {
+            protocol: Protocol::Resp3,
+            client_name: Some("test".to_owned()),
+        };
+        let mut conn = Connection::from_stream(client, &options).await.unwrap();
+        assert_eq!(conn.protocol(), Protocol::Resp3);
+
+        // Pushed data is skipped while waiting for a reply
+        let hash: HashMap<String, String> = conn.hgetall("h").await.unwrap();
+        assert_eq!(hash["f"], "v");
+
+        drop(conn);
+        let received = server.await.unwrap();
+        assert!(received
+            .starts_with(b"*4\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$7\r\nSETNAME\r\n$4\r\ntest\r\n"));
+    }
+}
@@ -0,0 +1,429 @@
+//! Decodes the replies sent by the server, in either RESP2 or RESP3, as
+//! defined here: https://redis.io/docs/reference/protocol-spec/
+//!
+//! This is the counterpart to the server's decoder of requests. Replies are
+//! parsed incrementally from a buffer, so a large reply arriving over many
+//! reads is only parsed once, and the aggregates still being parsed are kept
+//! on a stack rather than by recursion.
+
+use crate::{
+    error::{Error, Result},
+    value::Value,
+};
+use std::{convert::TryFrom, str};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The size of the reads from the connection
+const READ_SIZE: usize = 16 * 1024;
+/// Aggregates are only allocated up front to this length, as the length is
+/// given by the server and may not be honest
+const MAX_PREALLOCATE: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Array,
+    Set,
+    Push,
+    Map,
+    /// Attributes describe the value that follows them, they are skipped
+    Attribute,
+}
+
+/// An aggregate whose elements are still being parsed
+#[derive(Debug)]
+struct Aggregate {
+    kind: Kind,
+    /// The elements yet to be parsed, counting the keys and values of a map
+    /// separately
+    remaining: usize,
+    items: Vec<Value>,
+}
+
+impl Aggregate {
+    /// The value of the aggregate once complete, `None` for an attribute
+    fn finish(self) -> Option<Value> {
+        let items = self.items;
+        let value = match self.kind {
+            Kind::Array => Value::Array(items),
+            Kind::Set => Value::Set(items),
+            Kind::Push => Value::Push(items),
+            Kind::Map => {
+                let mut items = items.into_iter();
+                let mut pairs = Vec::with_capacity(items.len() / 2);
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    pairs.push((key, value));
+                }
+                Value::Map(pairs)
+            }
+            Kind::Attribute => return None,
+        };
+
+        Some(value)
+    }
+}
+
+/// What the next line of the buffer holds
+enum Frame {
+    Value(Value),
+    Aggregate(Aggregate),
+}
+
+/// Decodes the replies received over a connection
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// Where the bytes yet to be parsed start
+    pos: usize,
+    stack: Vec<Aggregate>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads from the stream until a whole reply has been parsed. Nothing is
+    /// lost if this is cancelled, the next call picks up where it was.
+    pub async fn decode<T: AsyncRead + Unpin>(&mut self, stream: &mut T) -> Result<Value> {
+        loop {
+            if let Some(value) = self.parse()? {
+                return Ok(value);
+            }
+
+            if self.read(stream).await? == 0 {
+                return Err(Error::ConnectionClosed);
+            }
+        }
+    }
+
+    /// Adds bytes received from the server, to be parsed by `parse`
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.compact();
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Parses as much of a reply as has been received, returning it once it
+    /// is complete
+    pub fn parse(&mut self) -> Result<Option<Value>> {
+        loop {
+            let mut value = match self.frame()? {
+                Some(Frame::Value(value)) => Some(value),
+                Some(Frame::Aggregate(aggregate)) if aggregate.remaining == 0 => aggregate.finish(),
+                Some(Frame::Aggregate(aggregate)) => {
+                    self.stack.push(aggregate);
+                    continue;
+                }
+                None => return Ok(None),
+            };
+
+            // A value completes the aggregates it is the last element of
+            while let Some(complete) = value.take() {
+                let top = match self.stack.last_mut() {
+                    Some(top) => top,
+                    None => return Ok(Some(complete)),
+                };
+
+                top.items.push(complete);
+                top.remaining -= 1;
+                if top.remaining == 0 {
+                    value = self.stack.pop().and_then(Aggregate::finish);
+                }
+            }
+        }
+    }
+
+    /// Reads more from the stream into the buffer, returning the number of
+    /// bytes read
+    async fn read<T: AsyncRead + Unpin>(&mut self, stream: &mut T) -> Result<usize> {
+        self.compact();
+        self.buffer.reserve(READ_SIZE);
+        Ok(stream.read_buf(&mut self.buffer).await?)
+    }
+
+    /// Drops what has already been parsed from the front of the buffer
+    fn compact(&mut self) {
+        if self.pos > 0 {
+            self.buffer.drain(..self.pos);
+            self.pos = 0;
+        }
+    }
+
+    /// Parses the next line and, for the types that have one, the string
+    /// that follows it. Nothing is consumed unless all of it has been read.
+    fn frame(&mut self) -> Result<Option<Frame>> {
+        let rest = &self.buffer[self.pos..];
+        let line_len = match rest.windows(2).position(|w| w == b"\r\n") {
+            Some(line_len) => line_len,
+            None => return Ok(None),
+        };
+        let (&sym, line) = rest[..line_len]
+            .split_first()
+            .ok_or_else(|| protocol_error("empty line"))?;
+        let line = str::from_utf8(line).map_err(|_| protocol_error("invalid UTF-8"))?;
+        let mut consumed = line_len + 2;
+
+        let frame = match sym {
+            b'+' => Frame::Value(Value::SimpleString(line.to_owned())),
+            b'-' => Frame::Value(Value::Error(line.to_owned())),
+            b':' => Frame::Value(Value::Integer(parse(line)?)),
+            b'_' => Frame::Value(Value::Nil),
+            b',' => Frame::Value(Value::Double(parse(line)?)),
+            b'(' => Frame::Value(Value::BigNumber(line.to_owned())),
+            b'#' => match line {
+                "t" => Frame::Value(Value::Boolean(true)),
+                "f" => Frame::Value(Value::Boolean(false)),
+                _ => return Err(protocol_error("invalid boolean")),
+            },
+            b'$' | b'!' | b'=' => {
+                let len: i64 = parse(line)?;
+                if len == -1 && sym == b'$' {
+                    Frame::Value(Value::Nil)
+                } else {
+                    let len = usize::try_from(len).map_err(|_| protocol_error("invalid length"))?;
+                    let end = consumed
+                        .checked_add(len)
+                        .and_then(|end| end.checked_add(2))
+                        .ok_or_else(|| protocol_error("invalid length"))?;
+                    let bytes = match rest.get(consumed..end) {
+                        Some(bytes) => &bytes[..len],
+                        None => return Ok(None),
+                    };
+                    consumed = end;
+                    Frame::Value(string_value(sym, bytes)?)
+                }
+            }
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                let len: i64 = parse(line)?;
+                if len == -1 && sym == b'*' {
+                    Frame::Value(Value::Nil)
+                } else {
+                    let len = usize::try_from(len).map_err(|_| protocol_error("invalid length"))?;
+                    let (kind, remaining) = match sym {
+                        b'*' => (Kind::Array, len),
+                        b'~' => (Kind::Set, len),
+                        b'>' => (Kind::Push, len),
+                        b'%' => (Kind::Map, len.saturating_mul(2)),
+                        _ => (Kind::Attribute, len.saturating_mul(2)),
+                    };
+                    Frame::Aggregate(Aggregate {
+                        kind,
+                        remaining,
+                        items: Vec::with_capacity(remaining.min(MAX_PREALLOCATE)),
+                    })
+                }
+            }
+            sym => {
+                let msg = format!("unsupported type '{}'", char::from(sym));
+                return Err(protocol_error(&msg));
+            }
+        };
+
+        self.pos += consumed;
+        Ok(Some(frame))
+    }
+}
+
+/// The value of a bulk string, bulk error or verbatim string
+fn string_value(sym: u8, bytes: &[u8]) -> Result<Value> {
+    let value = match sym {
+        b'$' => Value::BulkString(bytes.to_vec()),
+        b'!' => Value::Error(String::from_utf8_lossy(bytes).into_owned()),
+        _ => {
+            let text = str::from_utf8(bytes).map_err(|_| protocol_error("invalid UTF-8"))?;
+            match (text.get(..3), text.get(3..4), text.get(4..)) {
+                (Some(format), Some(":"), Some(text)) => Value::Verbatim {
+                    format: format.to_owned(),
+                    text: text.to_owned(),
+                },
+                _ => return Err(protocol_error("invalid verbatim string")),
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+fn parse<T: str::FromStr>(line: &str) -> Result<T> {
+    line.parse()
+        .map_err(|_| protocol_error(&format!("invalid number '{}'", line)))
+}
+
+fn protocol_error(msg: &str) -> Error {
+    Error::Protocol(msg.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(bytes: &[u8]) -> Vec<Value> {
+        let mut decoder = Decoder::new();
+        decoder.extend(bytes);
+
+        let mut values = vec![];
+        while let Some(value) = decoder.parse().unwrap() {
+            values.push(value);
+        }
+        assert_eq!(
+            decoder.pos,
+            decoder.buffer.len(),
+            "all of the input is parsed"
+        );
+        values
+    }
+
+    fn decode_one(bytes: &[u8]) -> Value {
+        let mut values = decode_all(bytes);
+        assert_eq!(values.len(), 1);
+        values.pop().unwrap()
+    }
+
+    fn bulk(s: &str) -> Value {
+        Value::BulkString(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_resp2() {
+        assert_eq!(decode_one(b"+OK\r\n"), Value::SimpleString("OK".into()));
+        assert_eq!(
+            decode_one(b"-ERR unknown\r\n"),
+            Value::Error("ERR unknown".into())
+        );
+        assert_eq!(decode_one(b":-42\r\n"), Value::Integer(-42));
+        assert_eq!(decode_one(b"$5\r\na\r\nbc\r\n"), bulk("a\r\nbc"));
+        assert_eq!(decode_one(b"$0\r\n\r\n"), bulk(""));
+        assert_eq!(decode_one(b"$-1\r\n"), Value::Nil);
+        assert_eq!(decode_one(b"*-1\r\n"), Value::Nil);
+        assert_eq!(decode_one(b"*0\r\n"), Value::Array(vec![]));
+        assert_eq!(
+            decode_one(b"*3\r\n:1\r\n*2\r\n$1\r\na\r\n$-1\r\n*0\r\n"),
+            Value::Array(vec![
+                Value::Integer(1),
+                Value::Array(vec![bulk("a"), Value::Nil]),
+                Value::Array(vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_resp3() {
+        assert_eq!(decode_one(b"_\r\n"), Value::Nil);
+        assert_eq!(decode_one(b"#t\r\n"), Value::Boolean(true));
+        assert_eq!(decode_one(b"#f\r\n"), Value::Boolean(false));
+        assert_eq!(decode_one(b",1.5\r\n"), Value::Double(1.5));
+        assert_eq!(decode_one(b",-inf\r\n"), Value::Double(f64::NEG_INFINITY));
+        assert_eq!(
+            decode_one(b"(3492890328409238509324850943850943825024385\r\n"),
+            Value::BigNumber("3492890328409238509324850943850943825024385".into())
+        );
+        assert_eq!(
+            decode_one(b"!21\r\nSYNTAX invalid syntax\r\n"),
+            Value::Error("SYNTAX invalid syntax".into())
+        );
+        assert_eq!(
+            decode_one(b"=15\r\ntxt:Some string\r\n"),
+            Value::Verbatim {
+                format: "txt".into(),
+                text: "Some string".into(),
+            }
+        );
+        assert_eq!(
+            decode_one(b"%2\r\n+first\r\n:1\r\n+second\r\n%0\r\n"),
+            Value::Map(vec![
+                (Value::SimpleString("first".into()), Value::Integer(1)),
+                (Value::SimpleString("second".into()), Value::Map(vec![])),
+            ])
+        );
+        assert_eq!(
+            decode_one(b"~2\r\n+a\r\n+b\r\n"),
+            Value::Set(vec![
+                Value::SimpleString("a".into()),
+                Value::SimpleString("b".into()),
+            ])
+        );
+        assert_eq!(
+            decode_one(b">3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$2\r\nhi\r\n"),
+            Value::Push(vec![bulk("message"), bulk("ch"), bulk("hi")])
+        );
+    }
+
+    #[test]
+    fn test_attributes_are_skipped() {
+        assert_eq!(
+            decode_one(b"|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.19\r\n*2\r\n:2\r\n:3\r\n"),
+            Value::Array(vec![Value::Integer(2), Value::Integer(3)])
+        );
+        assert_eq!(
+            decode_one(b"*2\r\n|1\r\n+ttl\r\n:3600\r\n:2\r\n:3\r\n"),
+            Value::Array(vec![Value::Integer(2), Value::Integer(3)])
+        );
+    }
+
+    #[test]
+    fn test_several_replies() {
+        assert_eq!(
+            decode_all(b"+OK\r\n:1\r\n*1\r\n$1\r\nx\r\n"),
+            vec![
+                Value::SimpleString("OK".into()),
+                Value::Integer(1),
+                Value::Array(vec![bulk("x")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_incremental() {
+        let reply = b"*3\r\n$5\r\nhello\r\n%1\r\n:1\r\n$-1\r\n+done\r\n";
+        let mut decoder = Decoder::new();
+
+        // Fed a byte at a time, the reply is only complete at the very end
+        for &byte in &reply[..reply.len() - 1] {
+            decoder.extend(&[byte]);
+            assert_eq!(decoder.parse().unwrap(), None);
+        }
+        decoder.extend(&reply[reply.len() - 1..]);
+
+        assert_eq!(
+            decoder.parse().unwrap(),
+            Some(Value::Array(vec![
+                bulk("hello"),
+                Value::Map(vec![(Value::Integer(1), Value::Nil)]),
+                Value::SimpleString("done".into()),
+            ]))
+        );
+        assert_eq!(decoder.buffer.len(), decoder.pos);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let invalid = |bytes: &[u8]| {
+            let mut decoder = Decoder::new();
+            decoder.extend(bytes);
+            decoder.parse().is_err()
+        };
+
+        assert!(invalid(b"?\r\n"));
+        assert!(invalid(b"\r\n"));
+        assert!(invalid(b":one\r\n"));
+        assert!(invalid(b"$-2\r\n"));
+        assert!(invalid(b"#x\r\n"));
+        assert!(invalid(b"=3\r\ntxt\r\n"));
+        assert!(invalid(b"*?\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let mut stream: &[u8] = b"$3\r\nfoo\r\n:7\r\n";
+        let mut decoder = Decoder::new();
+
+        assert_eq!(decoder.decode(&mut stream).await.unwrap(), bulk("foo"));
+        assert_eq!(
+            decoder.decode(&mut stream).await.unwrap(),
+            Value::Integer(7)
+        );
+        assert!(matches!(
+            decoder.decode(&mut stream).await,
+            Err(Error::ConnectionClosed)
+        ));
+    }
+}
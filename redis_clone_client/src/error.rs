@@ -0,0 +1,55 @@
+use std::error::Error as StdError;
+use std::fmt::{self, Display};
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The server closed the connection
+    ConnectionClosed,
+    /// The server sent something that isn't valid RESP
+    Protocol(String),
+    /// An error reply, such as `ERR syntax error`
+    Server(String),
+    /// A reply that can't be converted to the type asked for
+    Type(String),
+}
+
+impl Error {
+    /// The error code of an error reply, such as `WRONGTYPE`
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Self::Server(msg) => msg.split(' ').next(),
+            _ => None,
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(source) => write!(f, "{}", source),
+            Self::ConnectionClosed => write!(f, "Connection closed"),
+            Self::Protocol(msg) => write!(f, "Protocol error: {}", msg),
+            Self::Server(msg) => write!(f, "{}", msg),
+            Self::Type(msg) => write!(f, "Type error: {}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(other: io::Error) -> Self {
+        Self::Io(other)
+    }
+}
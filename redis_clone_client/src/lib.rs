@@ -0,0 +1,41 @@
+#![forbid(unsafe_code)]
+
+//! An async client for redis_clone, which also works with Redis itself.
+//!
+//! Replies are decoded from RESP2, or from RESP3 when it is asked for with
+//! `ConnectOptions`, and converted to the type wanted with `FromValue`:
+//!
+//! ```no_run
+//! # async fn example() -> redis_clone_client::Result<()> {
+//! use redis_clone_client::{cmd, Connection, Pipeline};
+//!
+//! let mut conn = Connection::connect("127.0.0.1:6379").await?;
+//! conn.set("key", 1).await?;
+//! let value: i64 = conn.incr("key").await?;
+//!
+//! let (first, second): (i64, Option<String>) = Pipeline::new()
+//!     .cmd(cmd("INCR").arg("key"))
+//!     .cmd(cmd("GET").arg("missing"))
+//!     .query(&mut conn)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod cmd;
+mod connection;
+mod decoder;
+mod error;
+mod pool;
+mod pubsub;
+mod types;
+mod value;
+
+pub use cmd::{cmd, Cmd, Pipeline};
+pub use connection::{ConnectOptions, Connection, Protocol};
+pub use decoder::Decoder;
+pub use error::{Error, Result};
+pub use pool::{Pool, PooledConnection};
+pub use pubsub::{Message, PubSub};
+pub use types::{FromValue, ToArg};
+pub use value::Value;
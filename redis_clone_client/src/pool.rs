@@ -0,0 +1,146 @@
+//! A pool of connections shared between tasks
+
+use crate::{
+    connection::{ConnectOptions, Connection},
+    error::Result,
+};
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+struct Inner {
+    addr: String,
+    options: ConnectOptions,
+    idle: Mutex<Vec<Connection>>,
+    /// Limits the connections that are in use or idle
+    permits: Arc<Semaphore>,
+}
+
+/// Hands out connections to the server, reusing those that are given back,
+/// up to a maximum number of connections. Clones share the same pool.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+impl Pool {
+    pub fn new<A: Into<String>>(addr: A, max_size: usize) -> Self {
+        Self::with_options(addr, max_size, ConnectOptions::default())
+    }
+
+    pub fn with_options<A: Into<String>>(
+        addr: A,
+        max_size: usize,
+        options: ConnectOptions,
+    ) -> Self {
+        assert!(max_size > 0, "A pool needs at least one connection");
+
+        Self {
+            inner: Arc::new(Inner {
+                addr: addr.into(),
+                options,
+                idle: Mutex::new(vec![]),
+                permits: Arc::new(Semaphore::new(max_size)),
+            }),
+        }
+    }
+
+    /// Takes an idle connection, or makes a new one, waiting for one to be
+    /// given back if there are already as many as allowed
+    pub async fn get(&self) -> Result<PooledConnection> {
+        let permit = Arc::clone(&self.inner.permits)
+            .acquire_owned()
+            .await
+            .expect("The semaphore is never closed");
+
+        let idle = self.inner.idle.lock().unwrap().pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => Connection::connect_with(self.inner.addr.as_str(), &self.inner.options).await?,
+        };
+
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: Arc::clone(&self.inner),
+            _permit: permit,
+        })
+    }
+
+    /// The number of connections waiting to be reused
+    pub fn idle(&self) -> usize {
+        self.inner.idle.lock().unwrap().len()
+    }
+}
+
+/// A connection taken from a pool, which is given back when dropped unless
+/// it failed or was left waiting for a reply
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Arc<Inner>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("Only taken on drop")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn.as_mut().expect("Only taken on drop")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if conn.is_idle() {
+                self.pool.idle.lock().unwrap().push(conn);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::{net::TcpListener, time::timeout};
+
+    #[tokio::test]
+    async fn test_pool() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let accepted = tokio::spawn(async move {
+            let mut streams = vec![];
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+
+        let pool = Pool::new(addr, 2);
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+
+        // No more connections than the maximum are made
+        assert!(timeout(Duration::from_millis(20), pool.get())
+            .await
+            .is_err());
+
+        drop(first);
+        assert_eq!(pool.idle(), 1);
+        let third = pool.clone().get().await.unwrap();
+        assert_eq!(pool.idle(), 0);
+
+        drop(second);
+        drop(third);
+        assert_eq!(pool.idle(), 2);
+
+        accepted.abort();
+    }
+}
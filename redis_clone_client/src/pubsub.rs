@@ -0,0 +1,282 @@
+//! Receiving the messages of the channels and patterns subscribed to
+
+use crate::{
+    cmd::cmd,
+    connection::Connection,
+    error::{Error, Result},
+    types::ToArg,
+    value::Value,
+};
+use log::debug;
+use std::collections::{HashSet, VecDeque};
+
+/// A message published to a channel subscribed to, or to one matching a
+/// pattern subscribed to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub channel: Vec<u8>,
+    /// The pattern the channel matched, for messages of PSUBSCRIBE
+    pub pattern: Option<Vec<u8>>,
+    pub payload: Vec<u8>,
+}
+
+/// What the server sends a subscribed client
+enum Event {
+    Message(Message),
+    /// A reply to SUBSCRIBE and the like, one for each channel or pattern
+    Confirmation {
+        kind: String,
+        name: Option<Vec<u8>>,
+    },
+}
+
+/// A connection that is subscribed to channels or patterns. Messages that
+/// arrive while subscribing or unsubscribing are kept for `on_message`.
+pub struct PubSub {
+    conn: Connection,
+    channels: HashSet<Vec<u8>>,
+    patterns: HashSet<Vec<u8>>,
+    messages: VecDeque<Message>,
+}
+
+impl PubSub {
+    pub(crate) fn new(conn: Connection) -> Self {
+        Self {
+            conn,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            messages: VecDeque::new(),
+        }
+    }
+
+    pub async fn subscribe<C: ToArg>(&mut self, channels: &[C]) -> Result<()> {
+        self.change("SUBSCRIBE", channels, channels.len()).await
+    }
+
+    pub async fn psubscribe<P: ToArg>(&mut self, patterns: &[P]) -> Result<()> {
+        self.change("PSUBSCRIBE", patterns, patterns.len()).await
+    }
+
+    /// Unsubscribes from the channels given, or from all of them if none are
+    pub async fn unsubscribe<C: ToArg>(&mut self, channels: &[C]) -> Result<()> {
+        let count = match channels.len() {
+            0 => self.channels.len().max(1),
+            count => count,
+        };
+        self.change("UNSUBSCRIBE", channels, count).await
+    }
+
+    /// Unsubscribes from the patterns given, or from all of them if none are
+    pub async fn punsubscribe<P: ToArg>(&mut self, patterns: &[P]) -> Result<()> {
+        let count = match patterns.len() {
+            0 => self.patterns.len().max(1),
+            count => count,
+        };
+        self.change("PUNSUBSCRIBE", patterns, count).await
+    }
+
+    /// The channels subscribed to
+    pub fn channels(&self) -> impl Iterator<Item = &[u8]> {
+        self.channels.iter().map(Vec::as_slice)
+    }
+
+    /// The patterns subscribed to
+    pub fn patterns(&self) -> impl Iterator<Item = &[u8]> {
+        self.patterns.iter().map(Vec::as_slice)
+    }
+
+    /// Waits for the next message
+    pub async fn on_message(&mut self) -> Result<Message> {
+        if let Some(message) = self.messages.pop_front() {
+            return Ok(message);
+        }
+
+        loop {
+            match self.receive().await? {
+                Event::Message(message) => return Ok(message),
+                Event::Confirmation { kind, .. } => {
+                    debug!("Ignoring an unexpected {} confirmation", kind)
+                }
+            }
+        }
+    }
+
+    /// Sends a command that changes the subscriptions and waits for the
+    /// `count` confirmations the server sends in reply
+    async fn change<A: ToArg>(&mut self, command: &str, args: &[A], count: usize) -> Result<()> {
+        self.conn.send(&[cmd(command).args(args)]).await?;
+
+        let kind = command.to_ascii_lowercase();
+        let mut confirmed = 0;
+        while confirmed < count {
+            match self.receive().await? {
+                Event::Message(message) => self.messages.push_back(message),
+                Event::Confirmation { kind: other, .. } if other != kind => {
+                    debug!("Ignoring an unexpected {} confirmation", other)
+                }
+                Event::Confirmation { .. } => confirmed += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the next message or confirmation, keeping track of the
+    /// subscriptions confirmed
+    async fn receive(&mut self) -> Result<Event> {
+        let event = parse(self.conn.receive().await?)?;
+
+        if let Event::Confirmation {
+            kind,
+            name: Some(name),
+        } = &event
+        {
+            match kind.as_str() {
+                "subscribe" => self.channels.insert(name.clone()),
+                "unsubscribe" => self.channels.remove(name),
+                "psubscribe" => self.patterns.insert(name.clone()),
+                "punsubscribe" => self.patterns.remove(name),
+                _ => false,
+            };
+        }
+        Ok(event)
+    }
+}
+
+/// Parses what the server sent, which is an array in RESP2 and pushed data
+/// in RESP3
+fn parse(value: Value) -> Result<Event> {
+    let items = match value {
+        Value::Array(items) | Value::Push(items) => items,
+        Value::Error(msg) => return Err(Error::Server(msg)),
+        value => {
+            return Err(Error::Protocol(format!(
+                "Unexpected {:?} while subscribed",
+                value
+            )))
+        }
+    };
+
+    let invalid = || Error::Protocol(format!("Invalid Pub/Sub data {:?}", items));
+    let bytes = |index: usize| {
+        items
+            .get(index)
+            .and_then(Value::as_bytes)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(invalid)
+    };
+
+    let kind = String::from_utf8(bytes(0)?)
+        .map_err(|_| invalid())?
+        .to_ascii_lowercase();
+    match kind.as_str() {
+        "message" if items.len() == 3 => Ok(Event::Message(Message {
+            channel: bytes(1)?,
+            pattern: None,
+            payload: bytes(2)?,
+        })),
+        "pmessage" if items.len() == 4 => Ok(Event::Message(Message {
+            pattern: Some(bytes(1)?),
+            channel: bytes(2)?,
+            payload: bytes(3)?,
+        })),
+        "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" if items.len() == 3 => {
+            Ok(Event::Confirmation {
+                kind,
+                // Unsubscribing from everything when there's nothing to
+                // unsubscribe from is confirmed with a nil name
+                name: bytes(1).ok(),
+            })
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectOptions;
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let (client, mut server) = duplex(64 * 1024);
+        let conn = Connection::from_stream(client, &ConnectOptions::default())
+            .await
+            .unwrap();
+        let mut pubsub = conn.into_pubsub();
+
+        // A message arrives between the confirmations
+        server
+            .write_all(
+                b"*3\r\n$9\r\nsubscribe\r\n$1\r\na\r\n:1\r\n\
+                  *3\r\n$7\r\nmessage\r\n$1\r\na\r\n$5\r\nfirst\r\n\
+                  *3\r\n$9\r\nsubscribe\r\n$1\r\nb\r\n:2\r\n",
+            )
+            .await
+            .unwrap();
+        pubsub.subscribe(&["a", "b"]).await.unwrap();
+
+        let mut channels: Vec<_> = pubsub.channels().collect();
+        channels.sort_unstable();
+        assert_eq!(channels, vec![b"a", b"b"]);
+
+        // RESP3 pushes are handled too
+        server
+            .write_all(
+                b">3\r\n$10\r\npsubscribe\r\n$2\r\nc*\r\n:3\r\n\
+                  >4\r\n$8\r\npmessage\r\n$2\r\nc*\r\n$2\r\ncd\r\n$6\r\nsecond\r\n",
+            )
+            .await
+            .unwrap();
+        pubsub.psubscribe(&["c*"]).await.unwrap();
+
+        assert_eq!(
+            pubsub.on_message().await.unwrap(),
+            Message {
+                channel: b"a".to_vec(),
+                pattern: None,
+                payload: b"first".to_vec()
+            }
+        );
+        assert_eq!(
+            pubsub.on_message().await.unwrap(),
+            Message {
+                channel: b"cd".to_vec(),
+                pattern: Some(b"c*".to_vec()),
+                payload: b"second".to_vec()
+            }
+        );
+
+        server
+            .write_all(
+                b"*3\r\n$11\r\nunsubscribe\r\n$1\r\nb\r\n:2\r\n\
+                  *3\r\n$11\r\nunsubscribe\r\n$1\r\na\r\n:1\r\n",
+            )
+            .await
+            .unwrap();
+        pubsub.unsubscribe::<&str>(&[]).await.unwrap();
+        assert_eq!(pubsub.channels().count(), 0);
+        assert_eq!(pubsub.patterns().count(), 1);
+
+        drop(pubsub);
+        let mut sent = vec![];
+        server.read_to_end(&mut sent).await.unwrap();
+        assert_eq!(
+            sent,
+            b"*3\r\n$9\r\nSUBSCRIBE\r\n$1\r\na\r\n$1\r\nb\r\n\
+              *2\r\n$10\r\nPSUBSCRIBE\r\n$2\r\nc*\r\n\
+              *1\r\n$11\r\nUNSUBSCRIBE\r\n"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse(Value::Integer(1)).is_err());
+        assert!(parse(Value::Array(vec![])).is_err());
+        assert!(matches!(
+            parse(Value::Error("ERR no".into())),
+            Err(Error::Server(_))
+        ));
+    }
+}
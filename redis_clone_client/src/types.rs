@@ -0,0 +1,361 @@
+//! Conversions between Rust types and the arguments and replies of commands
+
+use crate::{
+    error::{Error, Result},
+    value::Value,
+};
+use std::{collections::HashMap, convert::TryFrom, hash::Hash, str};
+
+/// A type a reply can be converted to. An error reply is converted to
+/// `Error::Server`, unless it is kept as a `Value`.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> Result<Self>;
+
+    /// Converts the bytes of a string into a `Vec` of this type. Only `u8`
+    /// does, so that a string can be had as a `Vec<u8>`.
+    fn from_bytes(_bytes: Vec<u8>) -> Option<Vec<Self>> {
+        None
+    }
+}
+
+/// A type that can be given as an argument of a command
+pub trait ToArg {
+    fn to_arg(&self) -> Vec<u8>;
+}
+
+fn type_error(value: &Value, wanted: &str) -> Error {
+    match value {
+        Value::Error(msg) => Error::Server(msg.clone()),
+        value => Error::Type(format!("Can't convert {:?} to {}", value, wanted)),
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Error(msg) => Err(Error::Server(msg)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::SimpleString(s) | Value::BigNumber(s) => Ok(s),
+            Value::Verbatim { text, .. } => Ok(text),
+            Value::BulkString(bytes) => {
+                String::from_utf8(bytes).map_err(|_| Error::Type("Invalid UTF-8".to_owned()))
+            }
+            Value::Integer(n) => Ok(n.to_string()),
+            Value::Double(n) => Ok(n.to_string()),
+            value => Err(type_error(&value, "a string")),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Integer(n) => Ok(n),
+            Value::SimpleString(ref s) | Value::BigNumber(ref s) => {
+                s.parse().map_err(|_| type_error(&value, "an integer"))
+            }
+            Value::BulkString(ref bytes) => str::from_utf8(bytes)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| type_error(&value, "an integer")),
+            value => Err(type_error(&value, "an integer")),
+        }
+    }
+}
+
+macro_rules! from_value_via_i64 {
+    ($($t:ty),*) => {
+        $(
+            impl FromValue for $t {
+                fn from_value(value: Value) -> Result<Self> {
+                    let n = i64::from_value(value)?;
+                    <$t>::try_from(n).map_err(|_| {
+                        Error::Type(format!("{} is out of range of {}", n, stringify!($t)))
+                    })
+                }
+            }
+        )*
+    };
+}
+
+from_value_via_i64!(i32, u32, u64, usize);
+
+impl FromValue for u8 {
+    fn from_value(value: Value) -> Result<Self> {
+        let n = i64::from_value(value)?;
+        u8::try_from(n).map_err(|_| Error::Type(format!("{} is out of range of u8", n)))
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Option<Vec<Self>> {
+        Some(bytes)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Double(n) => Ok(n),
+            Value::Integer(n) => Ok(n as f64),
+            ref value => value
+                .as_bytes()
+                .and_then(|bytes| str::from_utf8(bytes).ok())
+                .and_then(|s| s.parse().ok())
+                .ok_or_else(|| type_error(value, "a float")),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            Value::Integer(n) => Ok(n != 0),
+            Value::SimpleString(ref s) if s == "OK" => Ok(true),
+            Value::Nil => Ok(false),
+            value => Err(type_error(&value, "a boolean")),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Array(items) | Value::Set(items) | Value::Push(items) => {
+                items.into_iter().map(T::from_value).collect()
+            }
+            Value::Map(pairs) => pairs
+                .into_iter()
+                .flat_map(|(key, value)| vec![key, value])
+                .map(T::from_value)
+                .collect(),
+            Value::BulkString(bytes) => match T::from_bytes(bytes) {
+                Some(converted) => Ok(converted),
+                None => Err(Error::Type("Can't convert a string to a list".to_owned())),
+            },
+            Value::Nil => Ok(vec![]),
+            value => Err(type_error(&value, "a list")),
+        }
+    }
+}
+
+/// From a RESP3 map, or from a RESP2 array of keys and values, such as the
+/// reply to HGETALL
+impl<K, V> FromValue for HashMap<K, V>
+where
+    K: FromValue + Eq + Hash,
+    V: FromValue,
+{
+    fn from_value(value: Value) -> Result<Self> {
+        match value {
+            Value::Map(pairs) => pairs
+                .into_iter()
+                .map(|(key, value)| Ok((K::from_value(key)?, V::from_value(value)?)))
+                .collect(),
+            Value::Array(items) => {
+                if items.len() % 2 != 0 {
+                    return Err(Error::Type("Odd number of items for a map".to_owned()));
+                }
+                let mut items = items.into_iter();
+                let mut map = HashMap::with_capacity(items.len() / 2);
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    map.insert(K::from_value(key)?, V::from_value(value)?);
+                }
+                Ok(map)
+            }
+            Value::Nil => Ok(HashMap::new()),
+            value => Err(type_error(&value, "a map")),
+        }
+    }
+}
+
+/// Tuples are converted from arrays of the same length, such as the replies
+/// to a pipeline
+macro_rules! from_value_for_tuple {
+    ($len:expr => $($t:ident),+) => {
+        impl<$($t: FromValue),+> FromValue for ($($t,)+) {
+            fn from_value(value: Value) -> Result<Self> {
+                match value {
+                    Value::Array(items) if items.len() == $len => {
+                        let mut items = items.into_iter();
+                        Ok(($($t::from_value(items.next().expect("length was checked"))?,)+))
+                    }
+                    value => Err(type_error(&value, concat!("a tuple of ", $len))),
+                }
+            }
+        }
+    };
+}
+
+from_value_for_tuple!(1 => A);
+from_value_for_tuple!(2 => A, B);
+from_value_for_tuple!(3 => A, B, C);
+from_value_for_tuple!(4 => A, B, C, D);
+from_value_for_tuple!(5 => A, B, C, D, E);
+from_value_for_tuple!(6 => A, B, C, D, E, F);
+
+impl ToArg for [u8] {
+    fn to_arg(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> ToArg for [u8; N] {
+    fn to_arg(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl ToArg for Vec<u8> {
+    fn to_arg(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl ToArg for str {
+    fn to_arg(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl ToArg for String {
+    fn to_arg(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+}
+
+impl<T: ToArg + ?Sized> ToArg for &T {
+    fn to_arg(&self) -> Vec<u8> {
+        (**self).to_arg()
+    }
+}
+
+macro_rules! to_arg_via_display {
+    ($($t:ty),*) => {
+        $(
+            impl ToArg for $t {
+                fn to_arg(&self) -> Vec<u8> {
+                    self.to_string().into_bytes()
+                }
+            }
+        )*
+    };
+}
+
+to_arg_via_display!(i32, i64, u32, u64, usize, f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bulk(s: &str) -> Value {
+        Value::BulkString(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_scalars() {
+        assert_eq!(String::from_value(bulk("abc")).unwrap(), "abc");
+        assert_eq!(
+            String::from_value(Value::SimpleString("OK".into())).unwrap(),
+            "OK"
+        );
+        assert_eq!(i64::from_value(Value::Integer(-3)).unwrap(), -3);
+        assert_eq!(i64::from_value(bulk("42")).unwrap(), 42);
+        assert_eq!(usize::from_value(Value::Integer(7)).unwrap(), 7);
+        assert!(usize::from_value(Value::Integer(-1)).is_err());
+        assert_eq!(f64::from_value(bulk("1.5")).unwrap(), 1.5);
+        assert_eq!(f64::from_value(Value::Double(2.5)).unwrap(), 2.5);
+        assert!(bool::from_value(Value::Integer(1)).unwrap());
+        assert!(!bool::from_value(Value::Nil).unwrap());
+        assert!(bool::from_value(Value::Boolean(true)).unwrap());
+
+        assert!(matches!(i64::from_value(bulk("x")), Err(Error::Type(_))));
+    }
+
+    #[test]
+    fn test_error_replies() {
+        let error = || Value::Error("WRONGTYPE Operation".into());
+
+        match String::from_value(error()) {
+            Err(err @ Error::Server(_)) => assert_eq!(err.code(), Some("WRONGTYPE")),
+            other => panic!("expected a server error, got {:?}", other),
+        }
+        assert!(matches!(<()>::from_value(error()), Err(Error::Server(_))));
+        assert_eq!(Value::from_value(error()).unwrap(), error());
+    }
+
+    #[test]
+    fn test_bytes_and_lists() {
+        assert_eq!(Vec::<u8>::from_value(bulk("ab")).unwrap(), b"ab".to_vec());
+        assert_eq!(
+            Vec::<String>::from_value(Value::Array(vec![bulk("a"), bulk("b")])).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            Vec::<Vec<u8>>::from_value(Value::Array(vec![bulk("a")])).unwrap(),
+            vec![b"a".to_vec()]
+        );
+        assert_eq!(
+            Vec::<Option<String>>::from_value(Value::Array(vec![bulk("a"), Value::Nil])).unwrap(),
+            vec![Some("a".to_owned()), None]
+        );
+        assert!(Vec::<String>::from_value(bulk("ab")).is_err());
+        assert_eq!(Option::<String>::from_value(Value::Nil).unwrap(), None);
+    }
+
+    #[test]
+    fn test_maps() {
+        let resp2 = Value::Array(vec![bulk("a"), bulk("1"), bulk("b"), bulk("2")]);
+        let resp3 = Value::Map(vec![(bulk("a"), bulk("1")), (bulk("b"), bulk("2"))]);
+
+        for value in [resp2, resp3] {
+            let map = HashMap::<String, i64>::from_value(value).unwrap();
+            assert_eq!(map.len(), 2);
+            assert_eq!(map["a"], 1);
+            assert_eq!(map["b"], 2);
+        }
+
+        assert!(HashMap::<String, String>::from_value(Value::Array(vec![bulk("a")])).is_err());
+    }
+
+    #[test]
+    fn test_tuples() {
+        let value = Value::Array(vec![Value::SimpleString("OK".into()), Value::Integer(2)]);
+        assert_eq!(
+            <(String, i64)>::from_value(value.clone()).unwrap(),
+            ("OK".to_owned(), 2)
+        );
+        assert!(<(String, i64, i64)>::from_value(value).is_err());
+    }
+
+    #[test]
+    fn test_to_arg() {
+        assert_eq!("a".to_arg(), b"a");
+        assert_eq!(String::from("b").to_arg(), b"b");
+        assert_eq!(b"c"[..].to_arg(), b"c");
+        assert_eq!(b"d".to_arg(), b"d");
+        assert_eq!((-12_i64).to_arg(), b"-12");
+        assert_eq!(1.5_f64.to_arg(), b"1.5");
+    }
+}
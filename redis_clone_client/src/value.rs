@@ -0,0 +1,42 @@
+/// A reply from the server, of any of the types of RESP2 or RESP3
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// The RESP3 null, or a nil bulk string or array in RESP2
+    Nil,
+    SimpleString(String),
+    /// A simple or bulk error
+    Error(String),
+    Integer(i64),
+    BulkString(Vec<u8>),
+    Array(Vec<Value>),
+    Double(f64),
+    Boolean(bool),
+    /// An integer too large for an `i64`, in decimal
+    BigNumber(String),
+    /// A string along with its format, such as `txt` or `mkd`
+    Verbatim {
+        format: String,
+        text: String,
+    },
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    /// Data sent other than in reply to a command, such as Pub/Sub messages
+    /// in RESP3
+    Push(Vec<Value>),
+}
+
+impl Value {
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Self::Nil)
+    }
+
+    /// The bytes of a string value, whichever kind of string it is
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::SimpleString(s) => Some(s.as_bytes()),
+            Self::BulkString(bytes) => Some(bytes),
+            Self::Verbatim { text, .. } => Some(text.as_bytes()),
+            _ => None,
+        }
+    }
+}
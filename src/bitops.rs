@@ -0,0 +1,217 @@
+//! Bit level access to strings, as used by the bitmap commands. Bits are
+//! numbered from the most significant bit of the first byte, and anything
+//! past the end of a string reads as 0.
+
+use std::convert::TryFrom;
+
+pub fn get_bit(bytes: &[u8], offset: u64) -> bool {
+    usize::try_from(offset / 8)
+        .ok()
+        .and_then(|byte| bytes.get(byte))
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Sets a bit, which must be within the string, returning its old value
+pub fn set_bit(bytes: &mut [u8], offset: u64, value: bool) -> bool {
+    let byte = &mut bytes[(offset / 8) as usize];
+    let mask = 0x80 >> (offset % 8);
+    let old = *byte & mask != 0;
+
+    if value {
+        *byte |= mask;
+    } else {
+        *byte &= !mask;
+    }
+
+    old
+}
+
+/// The number of set bits between the inclusive bit offsets
+pub fn count_bits(bytes: &[u8], start: u64, end: u64) -> u64 {
+    let first = (start / 8) as usize;
+    let last = (end / 8) as usize;
+
+    let mut count: u64 = bytes[first..=last]
+        .iter()
+        .map(|byte| u64::from(byte.count_ones()))
+        .sum();
+    // Leave out the bits of the first and last bytes outside of the range
+    count -= u64::from((bytes[first] & !(0xff >> (start % 8))).count_ones());
+    count -= u64::from((bytes[last] & ((1 << (7 - end % 8)) - 1)).count_ones());
+
+    count
+}
+
+/// The offset of the first bit set to `value` between the inclusive offsets
+pub fn find_bit(bytes: &[u8], value: bool, start: u64, end: u64) -> Option<u64> {
+    let skip = if value { 0x00 } else { 0xff };
+    let mut offset = start;
+
+    while offset <= end {
+        // Whole bytes without a match are skipped in one go
+        if offset.is_multiple_of(8) && offset + 7 <= end && bytes[(offset / 8) as usize] == skip {
+            offset += 8;
+            continue;
+        }
+
+        if get_bit(bytes, offset) == value {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+
+    None
+}
+
+/// Reads an unsigned integer of `bits` bits, at most 64
+pub fn get_unsigned(bytes: &[u8], offset: u64, bits: u32) -> u64 {
+    (0..u64::from(bits)).fold(0, |value, i| {
+        (value << 1) | u64::from(get_bit(bytes, offset + i))
+    })
+}
+
+/// Reads a two's complement integer of `bits` bits, at most 64
+pub fn get_signed(bytes: &[u8], offset: u64, bits: u32) -> i64 {
+    let value = get_unsigned(bytes, offset, bits);
+    let shift = 64 - bits;
+
+    ((value << shift) as i64) >> shift
+}
+
+/// Writes the low `bits` bits of a value, which must be within the string
+pub fn set_unsigned(bytes: &mut [u8], offset: u64, bits: u32, value: u64) {
+    for i in 0..u64::from(bits) {
+        let bit = (value >> (u64::from(bits) - 1 - i)) & 1 != 0;
+        set_bit(bytes, offset + i, bit);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// Adds an increment to an unsigned integer of `bits` bits, handling
+/// overflow as requested. `None` means the operation failed.
+pub fn add_unsigned(value: u64, increment: i64, bits: u32, overflow: Overflow) -> Option<u64> {
+    let max = u64::MAX >> (64 - bits);
+    let sum = i128::from(value) + i128::from(increment);
+
+    if sum >= 0 && sum <= i128::from(max) {
+        return Some(sum as u64);
+    }
+
+    match overflow {
+        Overflow::Wrap => Some(sum as u64 & max),
+        Overflow::Sat if sum < 0 => Some(0),
+        Overflow::Sat => Some(max),
+        Overflow::Fail => None,
+    }
+}
+
+/// Adds an increment to a signed integer of `bits` bits, handling overflow
+/// as requested. `None` means the operation failed.
+pub fn add_signed(value: i64, increment: i64, bits: u32, overflow: Overflow) -> Option<i64> {
+    let max = i64::MAX >> (64 - bits);
+    let min = -max - 1;
+    let sum = i128::from(value) + i128::from(increment);
+
+    if sum >= i128::from(min) && sum <= i128::from(max) {
+        return Some(sum as i64);
+    }
+
+    match overflow {
+        Overflow::Wrap => {
+            let shift = 64 - bits;
+            Some(((sum as u64) << shift) as i64 >> shift)
+        }
+        Overflow::Sat if sum < 0 => Some(min),
+        Overflow::Sat => Some(max),
+        Overflow::Fail => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_and_set_bit() {
+        let mut bytes = vec![0; 2];
+
+        assert!(!set_bit(&mut bytes, 1, true));
+        assert!(!set_bit(&mut bytes, 15, true));
+        assert!(set_bit(&mut bytes, 15, true));
+        assert_eq!(bytes, [0x40, 0x01]);
+
+        assert!(get_bit(&bytes, 1));
+        assert!(!get_bit(&bytes, 2));
+        assert!(!get_bit(&bytes, 1000));
+    }
+
+    #[test]
+    fn test_count_bits() {
+        let bytes = b"foobar";
+
+        assert_eq!(count_bits(bytes, 0, 47), 26);
+        assert_eq!(count_bits(bytes, 8, 15), 6);
+        assert_eq!(count_bits(bytes, 5, 30), 17);
+        assert_eq!(count_bits(bytes, 1, 1), 1);
+        assert_eq!(count_bits(bytes, 0, 0), 0);
+    }
+
+    #[test]
+    fn test_find_bit() {
+        let bytes = [0xff, 0xf0, 0x00];
+
+        assert_eq!(find_bit(&bytes, false, 0, 23), Some(12));
+        assert_eq!(find_bit(&bytes, true, 0, 23), Some(0));
+        assert_eq!(find_bit(&bytes, true, 12, 23), None);
+        assert_eq!(find_bit(&bytes, false, 0, 7), None);
+        assert_eq!(find_bit(&bytes, true, 2, 7), Some(2));
+    }
+
+    #[test]
+    fn test_bitfields() {
+        let mut bytes = vec![0; 9];
+
+        set_unsigned(&mut bytes, 3, 5, 0b10110);
+        assert_eq!(bytes[0], 0b0001_0110);
+        assert_eq!(get_unsigned(&bytes, 3, 5), 0b10110);
+        assert_eq!(get_signed(&bytes, 3, 5), -10);
+
+        set_unsigned(&mut bytes, 7, 64, u64::MAX);
+        assert_eq!(get_unsigned(&bytes, 7, 64), u64::MAX);
+        assert_eq!(get_signed(&bytes, 7, 64), -1);
+        assert_eq!(get_unsigned(&bytes, 60, 8), 0xff);
+        assert_eq!(get_unsigned(&bytes, 68, 8), 0xe0);
+    }
+
+    #[test]
+    fn test_add_unsigned() {
+        assert_eq!(add_unsigned(250, 10, 8, Overflow::Wrap), Some(4));
+        assert_eq!(add_unsigned(250, 10, 8, Overflow::Sat), Some(255));
+        assert_eq!(add_unsigned(250, 10, 8, Overflow::Fail), None);
+        assert_eq!(add_unsigned(5, -10, 8, Overflow::Wrap), Some(251));
+        assert_eq!(add_unsigned(5, -10, 8, Overflow::Sat), Some(0));
+        assert_eq!(add_unsigned(5, -5, 8, Overflow::Fail), Some(0));
+        assert_eq!(
+            add_unsigned(0, i64::MAX, 63, Overflow::Fail),
+            Some(i64::MAX as u64)
+        );
+    }
+
+    #[test]
+    fn test_add_signed() {
+        assert_eq!(add_signed(120, 10, 8, Overflow::Wrap), Some(-126));
+        assert_eq!(add_signed(120, 10, 8, Overflow::Sat), Some(127));
+        assert_eq!(add_signed(-120, -10, 8, Overflow::Sat), Some(-128));
+        assert_eq!(add_signed(-120, -10, 8, Overflow::Fail), None);
+        assert_eq!(add_signed(i64::MAX, 1, 64, Overflow::Wrap), Some(i64::MIN));
+        assert_eq!(add_signed(i64::MAX, 1, 64, Overflow::Sat), Some(i64::MAX));
+        assert_eq!(add_signed(i64::MIN, 0, 8, Overflow::Sat), Some(-128));
+        assert_eq!(add_signed(-1, 0, 1, Overflow::Fail), Some(-1));
+    }
+}
@@ -0,0 +1,21 @@
+//! Commands such as XREAD BLOCK, which wait for keys to be written to. As in
+//! Redis's `blocked.c`, a blocked command is executed again once one of its
+//! keys is signalled as ready, until it replies or its timeout expires.
+
+use crate::response::Response;
+use byte_string::ByteString;
+use std::time::Duration;
+
+/// Asks for the client of the command being executed to be blocked. A
+/// command which blocks adds nothing to the reply.
+#[derive(Debug)]
+pub struct BlockRequest {
+    pub keys: Vec<ByteString>,
+    /// How long to wait for, or forever when `None`
+    pub timeout: Option<Duration>,
+    /// The command to execute again once a key is ready, when it differs
+    /// from the original. XREAD uses this to replace `$` with the last ID.
+    pub argv: Option<Vec<ByteString>>,
+    /// The reply sent when the timeout expires
+    pub timeout_reply: Response,
+}
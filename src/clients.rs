@@ -0,0 +1,631 @@
+use crate::{
+    output_buffer::{ClientClass, OutputBuffer, OutputLimits},
+    response::Response,
+};
+use byte_string::ByteString;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display, Write},
+    net::SocketAddr,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    sync::{Arc, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PauseMode {
+    Write,
+    All,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplyMode {
+    On,
+    Off,
+    Skip,
+}
+
+/// The address of either end of a client's connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientAddr {
+    Tcp(SocketAddr),
+    /// The path of the Unix socket, which both ends share
+    Unix(PathBuf),
+    /// An in-process client, see `LocalClient`
+    Local,
+}
+
+impl ClientAddr {
+    pub fn is_unix(&self) -> bool {
+        matches!(self, Self::Unix(_))
+    }
+
+    /// Whether the client is on the same host, as checked by protected mode
+    pub fn is_local(&self) -> bool {
+        match self {
+            Self::Tcp(addr) => addr.ip().is_loopback(),
+            Self::Unix(_) | Self::Local => true,
+        }
+    }
+}
+
+impl Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            // As Redis, which gives a Unix socket a port of 0
+            Self::Unix(path) => write!(f, "{}:0", path.display()),
+            Self::Local => write!(f, "local:0"),
+        }
+    }
+}
+
+impl From<SocketAddr> for ClientAddr {
+    fn from(other: SocketAddr) -> Self {
+        Self::Tcp(other)
+    }
+}
+
+/// What a client last did on a shard, which only knows about the commands it
+/// ran itself
+#[derive(Clone, Debug)]
+pub struct Activity {
+    pub last_interaction: Instant,
+    pub last_command: Option<ByteString>,
+    /// Bytes received from the client that have not been parsed yet
+    pub qbuf: usize,
+    /// Bytes used by the arguments of the last command
+    pub argv_mem: usize,
+    /// Whether the client is waiting on a blocking command, such as XREAD
+    pub blocked: bool,
+}
+
+impl Activity {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_interaction: now,
+            last_command: None,
+            qbuf: 0,
+            argv_mem: 0,
+            blocked: false,
+        }
+    }
+
+    /// Combines what the client did on two shards: its latest command is
+    /// the one that counts, and it is blocked if it is on either
+    pub fn merge(&mut self, other: &Activity) {
+        if other.last_interaction > self.last_interaction {
+            self.last_interaction = other.last_interaction;
+            self.last_command = other.last_command.clone();
+            self.qbuf = other.qbuf;
+            self.argv_mem = other.argv_mem;
+        }
+        self.blocked |= other.blocked;
+    }
+}
+
+/// A connected client as tracked by a shard. Each shard keeps a copy of
+/// every client, which the commands changing it are run against on all of
+/// them, but only the first shard's copy can kill the client.
+#[derive(Debug)]
+pub struct Client {
+    pub id: u64,
+    pub addr: ClientAddr,
+    pub laddr: ClientAddr,
+    pub name: Option<ByteString>,
+    pub created: Instant,
+    pub activity: Activity,
+    pub reply_mode: ReplyMode,
+    pub no_evict: bool,
+    /// Whether the client is in MONITOR mode
+    pub monitor: bool,
+    pub channels: BTreeSet<ByteString>,
+    pub patterns: BTreeSet<ByteString>,
+    kill_switch: Option<oneshot::Sender<()>>,
+    /// Data sent to the client outside of the replies to its own commands
+    push_sender: mpsc::Sender<Response>,
+    output: Arc<OutputBuffer>,
+}
+
+impl Client {
+    pub fn new(
+        id: u64,
+        addr: ClientAddr,
+        laddr: ClientAddr,
+        kill_switch: oneshot::Sender<()>,
+        push_sender: mpsc::Sender<Response>,
+    ) -> Self {
+        let now = Instant::now();
+
+        Self {
+            id,
+            addr,
+            laddr,
+            name: None,
+            created: now,
+            activity: Activity::new(now),
+            reply_mode: ReplyMode::On,
+            no_evict: false,
+            monitor: false,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+            kill_switch: Some(kill_switch),
+            push_sender,
+            output: Arc::new(OutputBuffer::new()),
+        }
+    }
+
+    /// A copy of the client for another shard, which can't kill it
+    pub fn for_shard(&self) -> Self {
+        Self {
+            id: self.id,
+            addr: self.addr.clone(),
+            laddr: self.laddr.clone(),
+            name: self.name.clone(),
+            created: self.created,
+            activity: self.activity.clone(),
+            reply_mode: self.reply_mode,
+            no_evict: self.no_evict,
+            monitor: self.monitor,
+            channels: self.channels.clone(),
+            patterns: self.patterns.clone(),
+            kill_switch: None,
+            push_sender: self.push_sender.clone(),
+            output: self.output(),
+        }
+    }
+
+    /// Asks the connection to close. The connection finishes writing any
+    /// reply in progress, so a client can kill itself.
+    pub fn kill(&mut self) {
+        if let Some(kill_switch) = self.kill_switch.take() {
+            let _ = kill_switch.send(());
+        }
+    }
+
+    /// The number of channels and patterns the client is subscribed to.
+    /// Whilst non-zero the client may only issue Pub/Sub commands.
+    pub fn subscription_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.kill_switch.is_none()
+    }
+
+    /// The output buffer shared with the connection
+    pub fn output(&self) -> Arc<OutputBuffer> {
+        Arc::clone(&self.output)
+    }
+
+    /// Whether the client has been idle for longer than `timeout`, given
+    /// its activity across the shards. As in Redis, clients that are
+    /// blocked, subscribed or monitoring are expected to be quiet and never
+    /// time out.
+    pub fn is_timed_out(&self, activity: &Activity, timeout: Duration, now: Instant) -> bool {
+        !activity.blocked
+            && self.class() == ClientClass::Normal
+            && now.saturating_duration_since(activity.last_interaction) > timeout
+    }
+
+    /// Which of the `client-output-buffer-limit` classes the client is in
+    pub fn class(&self) -> ClientClass {
+        if self.monitor {
+            ClientClass::Replica
+        } else if self.subscription_count() > 0 {
+            ClientClass::PubSub
+        } else {
+            ClientClass::Normal
+        }
+    }
+
+    /// Queues data to be written to the client without waiting. The data is
+    /// dropped if the client has fallen too far behind, and the connection
+    /// is closed if that takes its output buffer past the limits.
+    pub fn push(&self, response: Response, limits: OutputLimits) -> bool {
+        let len = response.len();
+        if !self.output.queue(len, limits, Instant::now()) {
+            return false;
+        }
+
+        let pushed = self.push_sender.try_send(response).is_ok();
+        if !pushed {
+            self.output.written(len);
+        }
+        pushed
+    }
+
+    fn flags(&self, activity: &Activity) -> String {
+        let mut flags = String::new();
+
+        if self.is_killed() {
+            flags.push('A');
+        }
+        if self.no_evict {
+            flags.push('e');
+        }
+        if self.monitor {
+            flags.push('O');
+        }
+        if self.subscription_count() > 0 {
+            flags.push('P');
+        }
+        if activity.blocked {
+            flags.push('b');
+        }
+        if self.addr.is_unix() {
+            flags.push('U');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+
+        flags
+    }
+
+    /// Formats the client in the same way as Redis's CLIENT LIST, given its
+    /// activity across the shards
+    pub fn info_string(&self, activity: &Activity, now: Instant) -> String {
+        let mut info = String::new();
+
+        write!(
+            info,
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db=0 sub={} psub={} qbuf={} argv-mem={} omem={} cmd={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            self.name.as_ref().map(|n| n.to_string()).unwrap_or_default(),
+            now.saturating_duration_since(self.created).as_secs(),
+            now.saturating_duration_since(activity.last_interaction).as_secs(),
+            self.flags(activity),
+            self.channels.len(),
+            self.patterns.len(),
+            activity.qbuf,
+            activity.argv_mem,
+            self.output.queued(),
+            activity.last_command
+                .as_ref()
+                .map(|c| c.to_string())
+                .unwrap_or_else(|| "NULL".to_owned()),
+        )
+        .expect("failed to format client info");
+
+        info
+    }
+}
+
+/// The registry of connected clients, with the state that applies to all of
+/// them such as CLIENT PAUSE
+pub struct Clients {
+    clients: BTreeMap<u64, Client>,
+    paused: Option<(PauseMode, Instant)>,
+}
+
+impl Clients {
+    pub fn new() -> Self {
+        Self {
+            clients: BTreeMap::new(),
+            paused: None,
+        }
+    }
+
+    pub fn add(&mut self, client: Client) {
+        self.clients.insert(client.id, client);
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Client> {
+        self.clients.remove(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Client> {
+        self.clients.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Client> {
+        self.clients.get_mut(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Client> {
+        self.clients.values_mut()
+    }
+
+    pub fn monitors(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values().filter(|c| c.monitor)
+    }
+
+    pub fn pause(&mut self, mode: PauseMode, duration: Duration) {
+        let until = Instant::now() + duration;
+
+        // An ALL pause is never weakened by a later WRITE pause, as in Redis
+        let mode = match self.paused {
+            Some((PauseMode::All, _)) => PauseMode::All,
+            _ => mode,
+        };
+        let until = match self.paused {
+            Some((_, existing)) if existing > until => existing,
+            _ => until,
+        };
+
+        self.paused = Some((mode, until));
+    }
+
+    pub fn unpause(&mut self) {
+        self.paused = None;
+    }
+
+    /// The active pause mode, if any. Expired pauses are cleared.
+    pub fn pause_mode(&mut self, now: Instant) -> Option<PauseMode> {
+        match self.paused {
+            Some((_, until)) if now >= until => {
+                self.paused = None;
+                None
+            }
+            Some((mode, _)) => Some(mode),
+            None => None,
+        }
+    }
+}
+
+/// A shard's copy of the clients, locked, along with the client whose
+/// command the shard is executing
+pub struct ClientsGuard<'a> {
+    clients: MutexGuard<'a, Clients>,
+    current: Option<u64>,
+}
+
+impl<'a> ClientsGuard<'a> {
+    pub fn new(clients: MutexGuard<'a, Clients>, current: Option<u64>) -> Self {
+        Self { clients, current }
+    }
+
+    pub fn current_id(&self) -> Option<u64> {
+        self.current
+    }
+
+    pub fn current(&self) -> Option<&Client> {
+        self.current.and_then(|id| self.clients.get(id))
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut Client> {
+        match self.current {
+            Some(id) => self.clients.get_mut(id),
+            None => None,
+        }
+    }
+}
+
+impl Deref for ClientsGuard<'_> {
+    type Target = Clients;
+
+    fn deref(&self) -> &Clients {
+        &self.clients
+    }
+}
+
+impl DerefMut for ClientsGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Clients {
+        &mut self.clients
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn tcp(addr: &str) -> ClientAddr {
+        addr.parse::<SocketAddr>().unwrap().into()
+    }
+
+    fn new_client(id: u64) -> (Client, oneshot::Receiver<()>) {
+        let (sender, receiver) = oneshot::channel();
+        let (push_sender, _) = mpsc::channel(1);
+        let addr = tcp("127.0.0.1:1234");
+        let laddr = tcp("127.0.0.1:8080");
+
+        (Client::new(id, addr, laddr, sender, push_sender), receiver)
+    }
+
+    #[test]
+    fn test_info_string() {
+        let (mut client, _) = new_client(7);
+        client.name = Some("worker".into());
+        client.activity.last_command = Some("get".into());
+
+        assert_eq!(
+            client.info_string(&client.activity, client.created),
+            "id=7 addr=127.0.0.1:1234 laddr=127.0.0.1:8080 name=worker age=0 idle=0 flags=N db=0 sub=0 psub=0 qbuf=0 argv-mem=0 omem=0 cmd=get"
+        );
+    }
+
+    #[test]
+    fn test_unix_socket_client() {
+        let (sender, _) = oneshot::channel();
+        let (push_sender, _) = mpsc::channel(1);
+        let addr = ClientAddr::Unix("/tmp/redis.sock".into());
+        let client = Client::new(3, addr.clone(), addr, sender, push_sender);
+
+        assert!(client
+            .info_string(&client.activity, client.created)
+            .starts_with(
+                "id=3 addr=/tmp/redis.sock:0 laddr=/tmp/redis.sock:0 name= age=0 idle=0 flags=U "
+            ));
+    }
+
+    #[test]
+    fn test_kill() {
+        let (mut client, mut receiver) = new_client(1);
+
+        assert!(!client.is_killed());
+        client.kill();
+        assert!(client.is_killed());
+        assert_eq!(receiver.try_recv(), Ok(()));
+        assert!(client.flags(&client.activity).contains('A'));
+    }
+
+    #[test]
+    fn test_push() {
+        let (sender, _) = oneshot::channel();
+        let (push_sender, mut push_receiver) = mpsc::channel(1);
+        let addr = tcp("127.0.0.1:1234");
+        let client = Client::new(1, addr.clone(), addr, sender, push_sender);
+
+        assert!(client.push(Response::new(), OutputLimits::default()));
+        // The client has fallen behind so the data is dropped
+        assert!(!client.push(Response::new(), OutputLimits::default()));
+        assert!(push_receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_push_past_output_limits() {
+        let (sender, _) = oneshot::channel();
+        let (push_sender, mut push_receiver) = mpsc::channel(8);
+        let addr = tcp("127.0.0.1:1234");
+        let client = Client::new(1, addr.clone(), addr, sender, push_sender);
+        let limits = OutputLimits::new(10, 0, 0);
+
+        let mut response = Response::new();
+        response.add_simple_string("hello");
+        assert!(client.push(response, limits));
+        assert_eq!(client.output().queued(), 8);
+
+        let mut response = Response::new();
+        response.add_simple_string("hello");
+        assert!(!client.push(response, limits));
+        assert!(client.output().is_overflowed());
+        assert!(push_receiver.try_recv().is_ok());
+        assert!(push_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_class() {
+        let (mut client, _) = new_client(1);
+        assert_eq!(client.class(), ClientClass::Normal);
+
+        client.channels.insert("news".into());
+        assert_eq!(client.class(), ClientClass::PubSub);
+
+        client.monitor = true;
+        assert_eq!(client.class(), ClientClass::Replica);
+    }
+
+    #[test]
+    fn test_is_timed_out() {
+        let (mut client, _) = new_client(1);
+        let mut activity = client.activity.clone();
+        let timeout = Duration::from_secs(10);
+        let later = activity.last_interaction + Duration::from_secs(11);
+
+        assert!(!client.is_timed_out(&activity, timeout, activity.last_interaction + timeout));
+        assert!(client.is_timed_out(&activity, timeout, later));
+
+        activity.blocked = true;
+        assert!(!client.is_timed_out(&activity, timeout, later));
+
+        activity.blocked = false;
+        client.channels.insert("news".into());
+        assert!(!client.is_timed_out(&activity, timeout, later));
+    }
+
+    #[test]
+    fn test_merge_activity() {
+        let now = Instant::now();
+        let mut activity = Activity::new(now);
+        activity.last_command = Some("get".into());
+
+        let mut other = Activity::new(now + Duration::from_secs(1));
+        other.last_command = Some("set".into());
+        other.argv_mem = 4;
+
+        // The latest command counts
+        let mut merged = activity.clone();
+        merged.merge(&other);
+        assert_eq!(merged.last_command, Some("set".into()));
+        assert_eq!(merged.argv_mem, 4);
+
+        other.merge(&activity);
+        assert_eq!(other.last_command, Some("set".into()));
+
+        // Being blocked on any shard counts
+        activity.blocked = true;
+        other.merge(&activity);
+        assert!(other.blocked);
+    }
+
+    #[test]
+    fn test_for_shard() {
+        let (mut client, mut receiver) = new_client(1);
+        client.name = Some("worker".into());
+        client.channels.insert("news".into());
+
+        let mut copy = client.for_shard();
+        assert_eq!(copy.name, client.name);
+        assert_eq!(copy.channels, client.channels);
+        assert!(Arc::ptr_eq(&copy.output(), &client.output()));
+
+        // Only the original can kill the client
+        copy.kill();
+        assert!(receiver.try_recv().is_err());
+        client.kill();
+        assert_eq!(receiver.try_recv(), Ok(()));
+    }
+
+    #[test]
+    fn test_is_local() {
+        assert!(tcp("127.0.0.1:6379").is_local());
+        assert!(tcp("[::1]:6379").is_local());
+        assert!(!tcp("10.0.0.1:6379").is_local());
+        assert!(ClientAddr::Unix("/tmp/redis.sock".into()).is_local());
+        assert!(ClientAddr::Local.is_local());
+    }
+
+    #[test]
+    fn test_current() {
+        let clients = Mutex::new(Clients::new());
+        clients.lock().unwrap().add(new_client(1).0);
+        clients.lock().unwrap().add(new_client(2).0);
+
+        assert!(ClientsGuard::new(clients.lock().unwrap(), None)
+            .current()
+            .is_none());
+
+        let mut guard = ClientsGuard::new(clients.lock().unwrap(), Some(2));
+        assert_eq!(guard.current().map(|c| c.id), Some(2));
+
+        guard.remove(2);
+        assert!(guard.current().is_none());
+        assert_eq!(guard.len(), 1);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut clients = Clients::new();
+        let now = Instant::now();
+
+        assert_eq!(clients.pause_mode(now), None);
+
+        clients.pause(PauseMode::All, Duration::from_secs(10));
+        assert_eq!(clients.pause_mode(now), Some(PauseMode::All));
+
+        // A WRITE pause does not downgrade an existing ALL pause
+        clients.pause(PauseMode::Write, Duration::from_secs(1));
+        assert_eq!(clients.pause_mode(now), Some(PauseMode::All));
+
+        // The pause expires
+        assert_eq!(clients.pause_mode(now + Duration::from_secs(11)), None);
+
+        clients.pause(PauseMode::Write, Duration::from_secs(10));
+        clients.unpause();
+        assert_eq!(clients.pause_mode(now), None);
+    }
+}
@@ -1,20 +1,72 @@
 use crate::{
-    db::Database, errors::Result, request::Request, response::Response, response_ext::ResponseExt,
+    db::{DataType, Database},
+    errors::Result,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
 };
-use byte_string::ByteStr;
+use byte_string::{ByteStr, ByteString};
+use std::{convert::TryFrom, sync::Arc};
 
+mod bitops;
+mod connection;
+mod geo;
 mod hash_type;
+mod hyperloglog;
 mod keyspace;
 mod list_type;
+mod pubsub;
+mod scripting;
 mod server;
+mod stream_type;
 mod string_type;
 
 type RedisCommandProc = fn(db: &mut Database, req: &Request, resp: &mut Response) -> Result<()>;
 
+/// Runs a command registered on top of those built in, see [`Registry`]. It
+/// is implemented for closures of the same signature as the built in
+/// commands.
+pub trait CommandHandler: Send + Sync + 'static {
+    fn call(&self, db: &mut Database, request: &Request, response: &mut Response) -> Result<()>;
+}
+
+impl<F> CommandHandler for F
+where
+    F: Fn(&mut Database, &Request, &mut Response) -> Result<()> + Send + Sync + 'static,
+{
+    fn call(&self, db: &mut Database, request: &Request, response: &mut Response) -> Result<()> {
+        self(db, request, response)
+    }
+}
+
+pub enum Handler {
+    Builtin(RedisCommandProc),
+    Custom(Arc<dyn CommandHandler>),
+}
+
 pub struct RedisCommand<'a> {
     pub name: &'a [u8],
-    pub handler: RedisCommandProc,
+    pub handler: Handler,
     pub arity: i32,
+    pub flags: &'a [&'a str],
+    pub keys: KeySpec,
+}
+
+/// Where the keys of a command are found in its arguments, which is how
+/// a command is routed to the shard holding them
+#[derive(Clone, Copy)]
+pub enum KeySpec {
+    None,
+    /// The first key, last key and step between keys, as reported by
+    /// COMMAND. A negative last key counts back from the end.
+    Range(i32, i32, i32),
+    /// Keys whose positions depend on the other arguments
+    Movable(fn(&[ByteString]) -> Vec<usize>),
+    /// The command works on the whole keyspace, such as KEYS
+    All,
+    /// The command works on the whole keyspace when its arguments call for
+    /// it, such as SHUTDOWN when it saves the dataset
+    AllWhen(fn(&[ByteString]) -> bool),
 }
 
 impl RedisCommand<'_> {
@@ -29,7 +81,79 @@ impl RedisCommand<'_> {
             return Ok(());
         }
 
-        (self.handler)(db, request, response)
+        match &self.handler {
+            Handler::Builtin(handler) => handler(db, request, response),
+            Handler::Custom(handler) => handler.call(db, request, response),
+        }
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Whether the command works on the whole keyspace, given its
+    /// arguments and the command name
+    pub fn wants_all_keys(&self, argv: &[ByteString]) -> bool {
+        match self.keys {
+            KeySpec::All => true,
+            KeySpec::AllWhen(applies) => applies(argv),
+            _ => false,
+        }
+    }
+
+    /// The positions of the keys in `argv`, the command name included
+    pub fn key_positions(&self, argv: &[ByteString]) -> Vec<usize> {
+        match self.keys {
+            KeySpec::None | KeySpec::All | KeySpec::AllWhen(_) => vec![],
+            KeySpec::Range(first, last, step) => {
+                let argc = argv.len() as i64;
+                let last = if last < 0 {
+                    argc + i64::from(last)
+                } else {
+                    i64::from(last)
+                };
+
+                (i64::from(first)..=last.min(argc - 1))
+                    .step_by(step as usize)
+                    .map(|i| i as usize)
+                    .collect()
+            }
+            KeySpec::Movable(positions) => positions(argv),
+        }
+    }
+}
+
+/// The keys of EVAL and FCALL, which follow their number
+fn numkeys_keys(argv: &[ByteString]) -> Vec<usize> {
+    let numkeys = argv
+        .get(2)
+        .and_then(|n| n.parse::<i64>().ok())
+        .and_then(|n| usize::try_from(n).ok())
+        .unwrap_or(0);
+
+    (3..argv.len().min(numkeys.saturating_add(3))).collect()
+}
+
+/// SHUTDOWN saves the dataset unless told not to, or there are no save
+/// points
+fn shutdown_may_save(argv: &[ByteString]) -> bool {
+    !argv[1..]
+        .iter()
+        .any(|arg| arg.eq_ignore_ascii_case(b"nosave"))
+}
+
+/// The keys of XREAD and XREADGROUP, which are the first half of the
+/// arguments after STREAMS
+fn streams_keys(argv: &[ByteString]) -> Vec<usize> {
+    match argv
+        .iter()
+        .position(|arg| arg.eq_ignore_ascii_case(b"streams"))
+    {
+        Some(streams) => {
+            let count = (argv.len() - streams - 1) / 2;
+            (streams + 1..streams + 1 + count).collect()
+        }
+        None => vec![],
     }
 }
 
@@ -38,185 +162,696 @@ fn is_valid_arity(arity: i64, given: i64) -> bool {
     arity == given || (arity < 0 && given >= arity.abs())
 }
 
-static COMMAND_TABLE: &[RedisCommand] = &[
+static COMMAND_TABLE: &[RedisCommand<'static>] = &[
     RedisCommand {
         name: b"get",
-        handler: string_type::get_command,
+        handler: Handler::Builtin(string_type::get_command),
         arity: 2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"set",
-        handler: string_type::set_command,
+        handler: Handler::Builtin(string_type::set_command),
         arity: -3,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"mget",
-        handler: string_type::mget_command,
+        handler: Handler::Builtin(string_type::mget_command),
         arity: -2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, -1, 1),
     },
     RedisCommand {
         name: b"mset",
-        handler: string_type::mset_command,
+        handler: Handler::Builtin(string_type::mset_command),
         arity: -3,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, -1, 2),
     },
     RedisCommand {
         name: b"del",
-        handler: keyspace::del_command,
+        handler: Handler::Builtin(keyspace::del_command),
         arity: -2,
+        flags: &["write"],
+        keys: KeySpec::Range(1, -1, 1),
     },
     RedisCommand {
         name: b"exists",
-        handler: keyspace::exists_command,
+        handler: Handler::Builtin(keyspace::exists_command),
         arity: -2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, -1, 1),
     },
     RedisCommand {
         name: b"expire",
-        handler: keyspace::expire_command,
+        handler: Handler::Builtin(keyspace::expire_command),
         arity: 3,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"persist",
-        handler: keyspace::persist_command,
+        handler: Handler::Builtin(keyspace::persist_command),
         arity: 2,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"ttl",
-        handler: keyspace::ttl_command,
+        handler: Handler::Builtin(keyspace::ttl_command),
         arity: 2,
+        flags: &["readonly", "random", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"incr",
-        handler: string_type::incr_command,
+        handler: Handler::Builtin(string_type::incr_command),
         arity: 2,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"decr",
-        handler: string_type::decr_command,
+        handler: Handler::Builtin(string_type::decr_command),
         arity: 2,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"incrby",
-        handler: string_type::incrby_command,
+        handler: Handler::Builtin(string_type::incrby_command),
         arity: 3,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"decrby",
-        handler: string_type::decrby_command,
+        handler: Handler::Builtin(string_type::decrby_command),
         arity: 3,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"rpush",
-        handler: list_type::rpush_command,
+        handler: Handler::Builtin(list_type::rpush_command),
         arity: -3,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"lpush",
-        handler: list_type::lpush_command,
+        handler: Handler::Builtin(list_type::lpush_command),
         arity: -3,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"linsert",
-        handler: list_type::linsert_command,
+        handler: Handler::Builtin(list_type::linsert_command),
         arity: 5,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"rpop",
-        handler: list_type::rpop_command,
+        handler: Handler::Builtin(list_type::rpop_command),
         arity: 2,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"lpop",
-        handler: list_type::lpop_command,
+        handler: Handler::Builtin(list_type::lpop_command),
         arity: 2,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"llen",
-        handler: list_type::llen_command,
+        handler: Handler::Builtin(list_type::llen_command),
         arity: 2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"lindex",
-        handler: list_type::lindex_command,
+        handler: Handler::Builtin(list_type::lindex_command),
         arity: 3,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"lset",
-        handler: list_type::lset_command,
+        handler: Handler::Builtin(list_type::lset_command),
         arity: 4,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"lrange",
-        handler: list_type::lrange_command,
+        handler: Handler::Builtin(list_type::lrange_command),
         arity: 4,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"ltrim",
-        handler: list_type::ltrim_command,
+        handler: Handler::Builtin(list_type::ltrim_command),
         arity: 4,
+        flags: &["write"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"lrem",
-        handler: list_type::lrem_command,
+        handler: Handler::Builtin(list_type::lrem_command),
         arity: 4,
+        flags: &["write"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"hset",
-        handler: hash_type::hset_command,
+        handler: Handler::Builtin(hash_type::hset_command),
         arity: -4,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"hget",
-        handler: hash_type::hget_command,
+        handler: Handler::Builtin(hash_type::hget_command),
         arity: 3,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"hmset",
-        handler: hash_type::hmset_command,
+        handler: Handler::Builtin(hash_type::hmset_command),
         arity: -4,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"hmget",
-        handler: hash_type::hmget_command,
+        handler: Handler::Builtin(hash_type::hmget_command),
         arity: -3,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"hgetall",
-        handler: hash_type::hgetall_command,
+        handler: Handler::Builtin(hash_type::hgetall_command),
+        arity: 2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xadd",
+        handler: Handler::Builtin(stream_type::xadd_command),
+        arity: -5,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xrange",
+        handler: Handler::Builtin(stream_type::xrange_command),
+        arity: -4,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xrevrange",
+        handler: Handler::Builtin(stream_type::xrevrange_command),
+        arity: -4,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xlen",
+        handler: Handler::Builtin(stream_type::xlen_command),
         arity: 2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xdel",
+        handler: Handler::Builtin(stream_type::xdel_command),
+        arity: -3,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xtrim",
+        handler: Handler::Builtin(stream_type::xtrim_command),
+        arity: -4,
+        flags: &["write"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xread",
+        handler: Handler::Builtin(stream_type::xread_command),
+        arity: -4,
+        flags: &["readonly", "movablekeys"],
+        keys: KeySpec::Movable(streams_keys),
+    },
+    RedisCommand {
+        name: b"xreadgroup",
+        handler: Handler::Builtin(stream_type::xreadgroup_command),
+        arity: -7,
+        flags: &["write", "movablekeys"],
+        keys: KeySpec::Movable(streams_keys),
+    },
+    RedisCommand {
+        name: b"xgroup",
+        handler: Handler::Builtin(stream_type::xgroup_command),
+        arity: -2,
+        flags: &["write"],
+        keys: KeySpec::Range(2, 2, 1),
+    },
+    RedisCommand {
+        name: b"xack",
+        handler: Handler::Builtin(stream_type::xack_command),
+        arity: -4,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xpending",
+        handler: Handler::Builtin(stream_type::xpending_command),
+        arity: -3,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xclaim",
+        handler: Handler::Builtin(stream_type::xclaim_command),
+        arity: -6,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xautoclaim",
+        handler: Handler::Builtin(stream_type::xautoclaim_command),
+        arity: -6,
+        flags: &["write", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"xinfo",
+        handler: Handler::Builtin(stream_type::xinfo_command),
+        arity: -2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(2, 2, 1),
+    },
+    RedisCommand {
+        name: b"setbit",
+        handler: Handler::Builtin(bitops::setbit_command),
+        arity: -4,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"getbit",
+        handler: Handler::Builtin(bitops::getbit_command),
+        arity: 3,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"bitcount",
+        handler: Handler::Builtin(bitops::bitcount_command),
+        arity: -2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"bitpos",
+        handler: Handler::Builtin(bitops::bitpos_command),
+        arity: -3,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"bitop",
+        handler: Handler::Builtin(bitops::bitop_command),
+        arity: -4,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(2, -1, 1),
+    },
+    RedisCommand {
+        name: b"bitfield",
+        handler: Handler::Builtin(bitops::bitfield_command),
+        arity: -2,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"bitfield_ro",
+        handler: Handler::Builtin(bitops::bitfield_ro_command),
+        arity: -2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"geoadd",
+        handler: Handler::Builtin(geo::geoadd_command),
+        arity: -5,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"geopos",
+        handler: Handler::Builtin(geo::geopos_command),
+        arity: -2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"geodist",
+        handler: Handler::Builtin(geo::geodist_command),
+        arity: -4,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"geohash",
+        handler: Handler::Builtin(geo::geohash_command),
+        arity: -2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"geosearch",
+        handler: Handler::Builtin(geo::geosearch_command),
+        arity: -7,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"geosearchstore",
+        handler: Handler::Builtin(geo::geosearchstore_command),
+        arity: -8,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, 2, 1),
+    },
+    RedisCommand {
+        name: b"pfadd",
+        handler: Handler::Builtin(hyperloglog::pfadd_command),
+        arity: -2,
+        flags: &["write", "denyoom", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
+    },
+    RedisCommand {
+        name: b"pfcount",
+        handler: Handler::Builtin(hyperloglog::pfcount_command),
+        arity: -2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(1, -1, 1),
+    },
+    RedisCommand {
+        name: b"pfmerge",
+        handler: Handler::Builtin(hyperloglog::pfmerge_command),
+        arity: -2,
+        flags: &["write", "denyoom"],
+        keys: KeySpec::Range(1, -1, 1),
     },
     RedisCommand {
         name: b"command",
-        handler: server::command_command,
+        handler: Handler::Builtin(server::command_command),
         arity: -1,
+        flags: &["random", "loading", "stale"],
+        keys: KeySpec::None,
     },
     RedisCommand {
         name: b"debug",
-        handler: server::debug_command,
+        handler: Handler::Builtin(server::debug_command),
         arity: -2,
+        flags: &["admin", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
     },
     RedisCommand {
         name: b"flushdb",
-        handler: server::flushdb_command,
+        handler: Handler::Builtin(server::flushdb_command),
         arity: -1,
+        flags: &["write"],
+        keys: KeySpec::All,
+    },
+    RedisCommand {
+        name: b"config",
+        handler: Handler::Builtin(server::config_command),
+        arity: -2,
+        flags: &["admin", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"client",
+        handler: Handler::Builtin(connection::client_command),
+        arity: -2,
+        flags: &["admin", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"info",
+        handler: Handler::Builtin(server::info_command),
+        arity: -1,
+        flags: &["random", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"shutdown",
+        handler: Handler::Builtin(server::shutdown_command),
+        arity: -1,
+        flags: &["admin", "noscript", "loading", "stale"],
+        keys: KeySpec::AllWhen(shutdown_may_save),
+    },
+    RedisCommand {
+        name: b"slowlog",
+        handler: Handler::Builtin(server::slowlog_command),
+        arity: -2,
+        flags: &["admin", "random", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"eval",
+        handler: Handler::Builtin(scripting::eval_command),
+        arity: -3,
+        flags: &["noscript", "movablekeys"],
+        keys: KeySpec::Movable(numkeys_keys),
+    },
+    RedisCommand {
+        name: b"evalsha",
+        handler: Handler::Builtin(scripting::evalsha_command),
+        arity: -3,
+        flags: &["noscript", "movablekeys"],
+        keys: KeySpec::Movable(numkeys_keys),
+    },
+    RedisCommand {
+        name: b"script",
+        handler: Handler::Builtin(scripting::script_command),
+        arity: -2,
+        flags: &["noscript"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"function",
+        handler: Handler::Builtin(scripting::function_command),
+        arity: -2,
+        flags: &["noscript"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"fcall",
+        handler: Handler::Builtin(scripting::fcall_command),
+        arity: -3,
+        flags: &["noscript", "movablekeys"],
+        keys: KeySpec::Movable(numkeys_keys),
+    },
+    RedisCommand {
+        name: b"fcall_ro",
+        handler: Handler::Builtin(scripting::fcall_ro_command),
+        arity: -3,
+        flags: &["noscript", "readonly", "movablekeys"],
+        keys: KeySpec::Movable(numkeys_keys),
+    },
+    RedisCommand {
+        name: b"monitor",
+        handler: Handler::Builtin(server::monitor_command),
+        arity: 1,
+        flags: &["admin", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"subscribe",
+        handler: Handler::Builtin(pubsub::subscribe_command),
+        arity: -2,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"unsubscribe",
+        handler: Handler::Builtin(pubsub::unsubscribe_command),
+        arity: -1,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"psubscribe",
+        handler: Handler::Builtin(pubsub::psubscribe_command),
+        arity: -2,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"punsubscribe",
+        handler: Handler::Builtin(pubsub::punsubscribe_command),
+        arity: -1,
+        flags: &["pubsub", "noscript", "loading", "stale"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"publish",
+        handler: Handler::Builtin(pubsub::publish_command),
+        arity: 3,
+        flags: &["pubsub", "loading", "stale", "fast"],
+        keys: KeySpec::None,
+    },
+    RedisCommand {
+        name: b"pubsub",
+        handler: Handler::Builtin(pubsub::pubsub_command),
+        arity: -2,
+        flags: &["pubsub", "random", "loading", "stale"],
+        keys: KeySpec::None,
     },
     RedisCommand {
         name: b"keys",
-        handler: keyspace::keys_command,
+        handler: Handler::Builtin(keyspace::keys_command),
         arity: 2,
+        flags: &["readonly", "sortforscript"],
+        keys: KeySpec::All,
     },
     RedisCommand {
         name: b"type",
-        handler: keyspace::type_command,
+        handler: Handler::Builtin(keyspace::type_command),
         arity: 2,
+        flags: &["readonly", "fast"],
+        keys: KeySpec::Range(1, 1, 1),
     },
     RedisCommand {
         name: b"object",
-        handler: keyspace::object_command,
+        handler: Handler::Builtin(keyspace::object_command),
         arity: -2,
+        flags: &["readonly"],
+        keys: KeySpec::Range(2, 2, 1),
     },
 ];
 
-pub fn lookup(name: ByteStr) -> Option<&RedisCommand> {
+/// The flags a command may be given, as reported by COMMAND
+const COMMAND_FLAGS: &[&str] = &[
+    "write",
+    "readonly",
+    "denyoom",
+    "admin",
+    "pubsub",
+    "noscript",
+    "random",
+    "sortforscript",
+    "loading",
+    "stale",
+    "fast",
+    "movablekeys",
+];
+
+/// A command to register, described as the built in ones are
+#[derive(Clone, Copy)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    /// The number of arguments including the command name, or the minimum
+    /// number when negative
+    pub arity: i32,
+    pub flags: &'static [&'static str],
+    pub keys: KeySpec,
+}
+
+/// The commands and data types registered on top of those built in, like
+/// those of a Redis module. They are registered before the server starts,
+/// see `ServerBuilder::registry`.
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<RedisCommand<'static>>,
+    types: Vec<&'static str>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a command, which mustn't have the name of another
+    pub fn register(&mut self, spec: CommandSpec, handler: impl CommandHandler) -> Result<()> {
+        if spec.name.is_empty() || spec.name.bytes().any(|c| c.is_ascii_whitespace()) {
+            return Err(format!("Invalid command name '{}'", spec.name).into());
+        }
+        if self.lookup(spec.name.into()).is_some() {
+            return Err(format!("Command '{}' already exists", spec.name).into());
+        }
+        if spec.arity == 0 {
+            return Err(format!("Invalid arity for command '{}'", spec.name).into());
+        }
+        if let Some(flag) = spec.flags.iter().find(|f| !COMMAND_FLAGS.contains(f)) {
+            return Err(format!("Invalid flag '{}' for command '{}'", flag, spec.name).into());
+        }
+
+        self.commands.push(RedisCommand {
+            name: spec.name.as_bytes(),
+            handler: Handler::Custom(Arc::new(handler)),
+            arity: spec.arity,
+            flags: spec.flags,
+            keys: spec.keys,
+        });
+
+        Ok(())
+    }
+
+    /// Adds a data type, whose values are reported by TYPE as `name`. As in
+    /// Redis the name is nine characters long, of letters, digits, `-` and
+    /// `_`, and mustn't be that of another type.
+    pub fn register_type(&mut self, name: &'static str) -> Result<DataType> {
+        let is_valid = name.len() == 9
+            && name
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+        if !is_valid {
+            return Err(format!("Invalid data type name '{}'", name).into());
+        }
+        if self.types.contains(&name) {
+            return Err(format!("Data type '{}' already exists", name).into());
+        }
+
+        self.types.push(name);
+        Ok(DataType::new(name))
+    }
+
+    pub fn lookup(&self, name: ByteStr<'_>) -> Option<&RedisCommand<'static>> {
+        lookup(name).or_else(|| {
+            self.commands
+                .iter()
+                .find(|c| name.eq_ignore_ascii_case(c.name))
+        })
+    }
+
+    /// Every command, those built in first
+    pub fn iter(&self) -> impl Iterator<Item = &RedisCommand<'static>> {
+        COMMAND_TABLE.iter().chain(&self.commands)
+    }
+
+    pub fn count(&self) -> usize {
+        COMMAND_TABLE.len() + self.commands.len()
+    }
+}
+
+/// A built in command
+fn lookup(name: ByteStr<'_>) -> Option<&'static RedisCommand<'static>> {
     COMMAND_TABLE
         .iter()
         .find(|c| name.eq_ignore_ascii_case(c.name))
@@ -246,6 +881,127 @@ mod tests {
         assert!(is_valid_arity(-2, 3));
     }
 
+    fn positions(name: &str, argv: &[&str]) -> Vec<usize> {
+        let argv: Vec<ByteString> = argv.iter().map(ByteString::from).collect();
+        lookup(name.into()).unwrap().key_positions(&argv)
+    }
+
+    #[test]
+    fn test_key_positions() {
+        assert_eq!(positions("get", &["get", "k"]), vec![1]);
+        assert_eq!(positions("mget", &["mget", "a", "b", "c"]), vec![1, 2, 3]);
+        assert_eq!(positions("mset", &["mset", "a", "1", "b", "2"]), vec![1, 3]);
+        assert_eq!(positions("bitop", &["bitop", "and", "d", "a"]), vec![2, 3]);
+        assert_eq!(
+            positions("object", &["object", "help"]),
+            Vec::<usize>::new()
+        );
+        assert_eq!(positions("info", &["info"]), Vec::<usize>::new());
+        assert_eq!(positions("keys", &["keys", "*"]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_movable_key_positions() {
+        assert_eq!(
+            positions("eval", &["eval", "s", "2", "a", "b", "c"]),
+            vec![3, 4]
+        );
+        assert_eq!(
+            positions("fcall", &["fcall", "f", "0", "a"]),
+            Vec::<usize>::new()
+        );
+        assert_eq!(positions("eval", &["eval", "s", "9", "a"]), vec![3]);
+        assert_eq!(
+            positions("eval", &["eval", "s", "-1", "a"]),
+            Vec::<usize>::new()
+        );
+
+        let xread = &["xread", "count", "1", "STREAMS", "a", "b", "0", "0"];
+        assert_eq!(positions("xread", xread), vec![4, 5]);
+        let xreadgroup = &["xreadgroup", "group", "g", "c", "streams", "a", ">"];
+        assert_eq!(positions("xreadgroup", xreadgroup), vec![5]);
+    }
+
+    fn spec(name: &'static str) -> CommandSpec {
+        CommandSpec {
+            name,
+            arity: -2,
+            flags: &["write"],
+            keys: KeySpec::Range(1, -1, 1),
+        }
+    }
+
+    fn custom(_: &mut Database, _: &Request, response: &mut Response) -> Result<()> {
+        response.add_simple_string("custom");
+        Ok(())
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = Registry::new();
+        registry.register(spec("my.cmd"), custom).unwrap();
+        assert_eq!(registry.count(), COMMAND_TABLE.len() + 1);
+
+        let cmd = registry.lookup("MY.CMD".into()).unwrap();
+        assert_eq!(cmd.name, b"my.cmd");
+        assert!(cmd.has_flag("write"));
+        let argv: Vec<ByteString> = vec!["my.cmd".into(), "a".into(), "b".into()];
+        assert_eq!(cmd.key_positions(&argv), vec![1, 2]);
+        assert_eq!(registry.iter().last().unwrap().name, b"my.cmd");
+
+        let mut db = Database::new();
+        let mut response = Response::new();
+        let request = Request::try_from(argv).unwrap();
+        cmd.execute(&mut db, &request, &mut response).unwrap();
+        assert_eq!(response.as_bytes(), b"+custom\r\n");
+
+        // The arity is checked before the handler is called
+        let mut response = Response::new();
+        let request = Request::try_from(vec![ByteString::from("my.cmd")]).unwrap();
+        cmd.execute(&mut db, &request, &mut response).unwrap();
+        assert!(response
+            .as_string()
+            .starts_with("-ERR wrong number of arguments"));
+
+        assert!(registry.lookup("get".into()).is_some());
+        assert!(registry.lookup("xxx".into()).is_none());
+    }
+
+    #[test]
+    fn test_register_invalid() {
+        let mut registry = Registry::new();
+        registry.register(spec("my.cmd"), custom).unwrap();
+
+        assert!(registry.register(spec("my.cmd"), custom).is_err());
+        assert!(registry.register(spec("GET"), custom).is_err());
+        assert!(registry.register(spec(""), custom).is_err());
+        assert!(registry.register(spec("my cmd"), custom).is_err());
+
+        let zero_arity = CommandSpec {
+            arity: 0,
+            ..spec("my.zero")
+        };
+        assert!(registry.register(zero_arity, custom).is_err());
+        let unknown_flag = CommandSpec {
+            flags: &["write", "speedy"],
+            ..spec("my.flag")
+        };
+        assert!(registry.register(unknown_flag, custom).is_err());
+
+        assert_eq!(registry.count(), COMMAND_TABLE.len() + 1);
+    }
+
+    #[test]
+    fn test_register_type() {
+        let mut registry = Registry::new();
+        let data_type = registry.register_type("mytype-01").unwrap();
+        assert_eq!(data_type.name(), "mytype-01");
+
+        assert!(registry.register_type("mytype-01").is_err());
+        assert!(registry.register_type("short").is_err());
+        assert!(registry.register_type("my type 1").is_err());
+    }
+
     #[test]
     #[should_panic]
     fn test_is_valid_arity_panics_on_zero() {
@@ -0,0 +1,596 @@
+use crate::{
+    bitops::{self, Overflow},
+    db::{Database, RObj},
+    errors::Result,
+    notify,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+};
+use byte_string::ByteString;
+use std::{
+    borrow::Cow,
+    convert::{TryFrom, TryInto},
+};
+
+/// The bytes of a string value, including those of an integer
+fn string_bytes(object: &RObj) -> Option<Cow<'_, [u8]>> {
+    match object {
+        RObj::String(bytes) => Some(Cow::Borrowed(bytes)),
+        RObj::Int(n) => Some(Cow::Owned(n.to_string().into_bytes())),
+        _ => None,
+    }
+}
+
+/// Runs `f` on the string held by a key, creating an empty one when the key
+/// doesn't exist. `None` means the key holds another type, which has been
+/// replied to.
+fn modify_string<T>(
+    db: &mut Database,
+    key: &ByteString,
+    response: &mut Response,
+    f: impl FnOnce(&mut ByteString, &mut Response) -> T,
+) -> Option<T> {
+    if !db.exists(key) {
+        db.insert(key.clone(), RObj::String(ByteString::new()));
+    }
+
+    let object = db.get_mut(key)?;
+    let result = match object.string_bytes_mut() {
+        Some(bytes) => f(bytes, response),
+        None => {
+            response.add_reply_wrong_type();
+            return None;
+        }
+    };
+    object.shrink_to_int();
+
+    Some(result)
+}
+
+/// Grows a string with zeroes to at least `len` bytes, returning whether it grew
+fn grow(bytes: &mut ByteString, len: u64) -> bool {
+    let len = usize::try_from(len).unwrap_or(usize::MAX);
+    if bytes.len() >= len {
+        return false;
+    }
+
+    bytes.to_mut().resize(len, 0);
+    true
+}
+
+/// Parses a bit offset, which may be given as a multiple of a BITFIELD
+/// type's width like `#2`, and must lie within the maximum string size.
+fn parse_bit_offset(arg: &ByteString, width: Option<u32>, max_bulk_len: u64) -> Option<u64> {
+    let (digits, multiplier) = match (arg.strip_prefix(b"#"), width) {
+        (Some(digits), Some(width)) => (digits, width.into()),
+        _ => (&arg[..], 1),
+    };
+
+    let offset: i64 = ByteString::from(digits).parse().ok()?;
+    let offset = u64::try_from(offset).ok()?.checked_mul(multiplier)?;
+    if offset / 8 >= max_bulk_len {
+        return None;
+    }
+
+    Some(offset)
+}
+
+fn parse_bit_offset_or_reply(
+    db: &Database,
+    arg: &ByteString,
+    width: Option<u32>,
+    response: &mut Response,
+) -> Option<u64> {
+    let offset = parse_bit_offset(arg, width, db.config().proto_max_bulk_len);
+    if offset.is_none() {
+        response.add_error("ERR bit offset is not an integer or out of range");
+    }
+
+    offset
+}
+
+/// Resolves a BITCOUNT or BITPOS range, whose ends count from the end of the
+/// string when negative, to inclusive bit offsets. `None` means it's empty.
+fn bit_range(len: usize, start: i64, end: i64, is_bit: bool) -> Option<(u64, u64)> {
+    let total = if is_bit { len as i64 * 8 } else { len as i64 };
+    let resolve = |index: i64| if index < 0 { total + index } else { index }.max(0);
+    let start = resolve(start);
+    let end = resolve(end).min(total - 1);
+
+    if start > end {
+        return None;
+    }
+
+    let (start, end) = (start as u64, end as u64);
+    if is_bit {
+        Some((start, end))
+    } else {
+        Some((start * 8, end * 8 + 7))
+    }
+}
+
+/// Parses the optional `start end [BYTE|BIT]` arguments of BITCOUNT and
+/// BITPOS, replying with an error when they are invalid.
+fn parse_range_or_reply(
+    args: &[ByteString],
+    response: &mut Response,
+) -> Option<(Option<i64>, Option<i64>, bool)> {
+    let mut parsed = (None, None, false);
+
+    if let Some(start) = args.first() {
+        match start.parse() {
+            Ok(start) => parsed.0 = Some(start),
+            Err(_) => {
+                response.add_reply_not_a_number();
+                return None;
+            }
+        }
+    }
+
+    if let Some(end) = args.get(1) {
+        match end.parse() {
+            Ok(end) => parsed.1 = Some(end),
+            Err(_) => {
+                response.add_reply_not_a_number();
+                return None;
+            }
+        }
+    }
+
+    match args.get(2) {
+        Some(unit) if unit.eq_ignore_ascii_case(b"bit") => parsed.2 = true,
+        Some(unit) if unit.eq_ignore_ascii_case(b"byte") => {}
+        Some(_) => {
+            response.add_error("ERR syntax error");
+            return None;
+        }
+        None => {}
+    }
+
+    Some(parsed)
+}
+
+pub(crate) fn setbit_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let offset = match parse_bit_offset_or_reply(db, request.arg(1)?, None, response) {
+        Some(offset) => offset,
+        None => return Ok(()),
+    };
+    let value = match request.arg(2)?.as_ref() {
+        b"0" => false,
+        b"1" => true,
+        _ => {
+            response.add_error("ERR bit is not an integer or out of range");
+            return Ok(());
+        }
+    };
+
+    let modified = modify_string(db, key, response, |bytes, response| {
+        let grew = grow(bytes, offset / 8 + 1);
+        let old = bitops::set_bit(bytes, offset, value);
+        response.add_integer(old.into());
+
+        grew || old != value
+    });
+
+    if modified == Some(true) {
+        db.notify_keyspace_event(notify::STRING, "setbit", key);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn getbit_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let offset = match parse_bit_offset_or_reply(db, request.arg(1)?, None, response) {
+        Some(offset) => offset,
+        None => return Ok(()),
+    };
+
+    match db.get(key) {
+        Some(object) => match string_bytes(object) {
+            Some(bytes) => response.add_integer(bitops::get_bit(&bytes, offset).into()),
+            None => response.add_reply_wrong_type(),
+        },
+        None => response.add_integer(0),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn bitcount_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let args = &request.arguments()[1..];
+    if args.len() == 1 || args.len() > 3 {
+        response.add_error("ERR syntax error");
+        return Ok(());
+    }
+
+    let (start, end, is_bit) = match parse_range_or_reply(args, response) {
+        Some(range) => range,
+        None => return Ok(()),
+    };
+
+    let bytes = match db.get(key) {
+        Some(object) => match string_bytes(object) {
+            Some(bytes) => bytes,
+            None => {
+                response.add_reply_wrong_type();
+                return Ok(());
+            }
+        },
+        None => {
+            response.add_integer(0);
+            return Ok(());
+        }
+    };
+
+    let range = bit_range(bytes.len(), start.unwrap_or(0), end.unwrap_or(-1), is_bit);
+    let count = range.map_or(0, |(start, end)| bitops::count_bits(&bytes, start, end));
+    response.add_integer(count.try_into()?);
+
+    Ok(())
+}
+
+pub(crate) fn bitpos_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let bit: i64 = parse_or_reply_with_err!(request.arg(1)?, response);
+    let bit = match bit {
+        0 => false,
+        1 => true,
+        _ => {
+            response.add_error("ERR The bit argument must be 1 or 0.");
+            return Ok(());
+        }
+    };
+
+    let args = &request.arguments()[2..];
+    if args.len() > 3 {
+        response.add_error("ERR syntax error");
+        return Ok(());
+    }
+
+    let (start, end, is_bit) = match parse_range_or_reply(args, response) {
+        Some(range) => range,
+        None => return Ok(()),
+    };
+
+    // A missing key is an infinite run of clear bits
+    let bytes = match db.get(key) {
+        Some(object) => match string_bytes(object) {
+            Some(bytes) => bytes,
+            None => {
+                response.add_reply_wrong_type();
+                return Ok(());
+            }
+        },
+        None => {
+            response.add_integer(if bit { -1 } else { 0 });
+            return Ok(());
+        }
+    };
+
+    let end_given = end.is_some();
+    let (start, end) = match bit_range(bytes.len(), start.unwrap_or(0), end.unwrap_or(-1), is_bit) {
+        Some(range) => range,
+        None => {
+            response.add_integer(-1);
+            return Ok(());
+        }
+    };
+
+    match bitops::find_bit(&bytes, bit, start, end) {
+        Some(position) => response.add_integer(position.try_into()?),
+        // Looking for a clear bit without an explicit end, the string is
+        // treated as padded with clear bits to the right
+        None if !bit && !end_given => response.add_integer((end + 1).try_into()?),
+        None => response.add_integer(-1),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn bitop_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let operation = request.arg(0)?.to_lowercase();
+    let dest = request.arg(1)?;
+    let keys = &request.arguments()[2..];
+
+    let combine: fn(u8, u8) -> u8 = match operation.as_ref() {
+        b"and" => |a, b| a & b,
+        b"or" => |a, b| a | b,
+        b"xor" => |a, b| a ^ b,
+        b"not" => |a, _| !a,
+        _ => {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+    };
+
+    if operation.as_ref() == b"not" && keys.len() != 1 {
+        response.add_error("ERR BITOP NOT must be called with a single source key.");
+        return Ok(());
+    }
+
+    // Missing keys are treated as empty strings
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in keys {
+        match db.get(key) {
+            Some(object) => match string_bytes(object) {
+                Some(bytes) => sources.push(bytes.into_owned()),
+                None => {
+                    response.add_reply_wrong_type();
+                    return Ok(());
+                }
+            },
+            None => sources.push(Vec::new()),
+        }
+    }
+
+    // Shorter strings are padded with zeroes
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    let mut result = sources[0].clone();
+    result.resize(len, 0);
+    if sources.len() == 1 {
+        result.iter_mut().for_each(|byte| *byte = combine(*byte, 0));
+    }
+    for source in &sources[1..] {
+        for (i, byte) in result.iter_mut().enumerate() {
+            *byte = combine(*byte, source.get(i).copied().unwrap_or(0));
+        }
+    }
+
+    if result.is_empty() {
+        if db.remove(dest).is_some() {
+            db.notify_keyspace_event(notify::GENERIC, "del", dest);
+        }
+    } else {
+        let mut value = RObj::String(ByteString::from(result));
+        value.shrink_to_int();
+        db.remove(dest);
+        db.insert(dest.clone(), value);
+        db.notify_keyspace_event(notify::STRING, "set", dest);
+    }
+    response.add_integer(len.try_into()?);
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BitfieldOp {
+    Get,
+    Set(i64),
+    IncrBy(i64),
+}
+
+/// A single GET, SET or INCRBY of a BITFIELD command
+struct Bitfield {
+    op: BitfieldOp,
+    signed: bool,
+    width: u32,
+    offset: u64,
+    overflow: Overflow,
+}
+
+impl Bitfield {
+    fn get(&self, bytes: &[u8]) -> i64 {
+        if self.signed {
+            bitops::get_signed(bytes, self.offset, self.width)
+        } else {
+            // Unsigned fields are at most 63 bits wide
+            bitops::get_unsigned(bytes, self.offset, self.width) as i64
+        }
+    }
+
+    /// Performs a SET or INCRBY, returning the reply, or `None` when it
+    /// failed to overflow, and whether the field changed
+    fn modify(&self, bytes: &mut [u8]) -> (Option<i64>, bool) {
+        let old = self.get(bytes);
+        let (value, increment) = match self.op {
+            BitfieldOp::Set(value) => (value, 0),
+            BitfieldOp::IncrBy(increment) => (old, increment),
+            BitfieldOp::Get => return (Some(old), false),
+        };
+
+        let new = if self.signed {
+            bitops::add_signed(value, increment, self.width, self.overflow)
+        } else {
+            bitops::add_unsigned(value as u64, increment, self.width, self.overflow)
+                .map(|new| new as i64)
+        };
+
+        match new {
+            Some(new) => {
+                bitops::set_unsigned(bytes, self.offset, self.width, new as u64);
+                let reply = if let BitfieldOp::Set(_) = self.op {
+                    old
+                } else {
+                    new
+                };
+                (Some(reply), old != new)
+            }
+            None => (None, false),
+        }
+    }
+}
+
+/// Parses a BITFIELD type such as `i16` or `u8`
+fn parse_bitfield_type(arg: &ByteString) -> Option<(bool, u32)> {
+    let (&sign, width) = arg.split_first()?;
+    let signed = match sign.to_ascii_lowercase() {
+        b'i' => true,
+        b'u' => false,
+        _ => return None,
+    };
+
+    let width: i64 = ByteString::from(width).parse().ok()?;
+    let width = u32::try_from(width).ok()?;
+    let max = if signed { 64 } else { 63 };
+    if width == 0 || width > max {
+        return None;
+    }
+
+    Some((signed, width))
+}
+
+pub(crate) fn bitfield_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_bitfield_command(db, request, response, false)
+}
+
+pub(crate) fn bitfield_ro_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_bitfield_command(db, request, response, true)
+}
+
+fn generic_bitfield_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+    read_only: bool,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let mut args = &request.arguments()[1..];
+    let mut overflow = Overflow::Wrap;
+    let mut fields = Vec::new();
+
+    while let Some(sub_command) = args.first() {
+        let sub_command = sub_command.to_lowercase();
+        let (op_args, rest) = match sub_command.as_ref() {
+            b"overflow" if args.len() >= 2 => args.split_at(2),
+            b"get" if args.len() >= 3 => args.split_at(3),
+            b"set" | b"incrby" if args.len() >= 4 => args.split_at(4),
+            _ => {
+                response.add_error("ERR syntax error");
+                return Ok(());
+            }
+        };
+        args = rest;
+
+        if sub_command.as_ref() == b"overflow" {
+            let kind = op_args[1].to_lowercase();
+            overflow = match kind.as_ref() {
+                b"wrap" => Overflow::Wrap,
+                b"sat" => Overflow::Sat,
+                b"fail" => Overflow::Fail,
+                _ => {
+                    response.add_error("ERR Invalid OVERFLOW type specified");
+                    return Ok(());
+                }
+            };
+            continue;
+        }
+
+        let (signed, width) = match parse_bitfield_type(&op_args[1]) {
+            Some(field_type) => field_type,
+            None => {
+                response.add_error(
+                    "ERR Invalid bitfield type. Use something like i16 u8. \
+                     Note that u64 is not supported but i64 is.",
+                );
+                return Ok(());
+            }
+        };
+
+        let offset = match parse_bit_offset_or_reply(db, &op_args[2], Some(width), response) {
+            Some(offset) => offset,
+            None => return Ok(()),
+        };
+
+        let op = match sub_command.as_ref() {
+            b"set" => BitfieldOp::Set(parse_or_reply_with_err!(op_args[3], response)),
+            b"incrby" => BitfieldOp::IncrBy(parse_or_reply_with_err!(op_args[3], response)),
+            _ => BitfieldOp::Get,
+        };
+
+        fields.push(Bitfield {
+            op,
+            signed,
+            width,
+            offset,
+            overflow,
+        });
+    }
+
+    let len = fields
+        .iter()
+        .filter(|field| field.op != BitfieldOp::Get)
+        .map(|field| (field.offset + u64::from(field.width) - 1) / 8 + 1)
+        .max();
+
+    let len = match len {
+        Some(_) if read_only => {
+            response.add_error("ERR BITFIELD_RO only supports the GET subcommand");
+            return Ok(());
+        }
+        Some(len) => len,
+        None => {
+            // Only GETs, which don't create the key
+            let bytes = match db.get(key) {
+                Some(object) => match string_bytes(object) {
+                    Some(bytes) => bytes,
+                    None => {
+                        response.add_reply_wrong_type();
+                        return Ok(());
+                    }
+                },
+                None => Cow::Borrowed(&[][..]),
+            };
+
+            response.add_array_len(fields.len().try_into()?);
+            for field in &fields {
+                response.add_integer(field.get(&bytes));
+            }
+            return Ok(());
+        }
+    };
+
+    let modified = modify_string(db, key, response, |bytes, response| {
+        let mut modified = grow(bytes, len);
+
+        response.add_array_len(fields.len() as i64);
+        for field in &fields {
+            match field.modify(bytes) {
+                (Some(reply), changed) => {
+                    response.add_integer(reply);
+                    modified |= changed;
+                }
+                (None, _) => response.add_null_string(),
+            }
+        }
+
+        modified
+    });
+
+    if modified == Some(true) {
+        db.notify_keyspace_event(notify::STRING, "setbit", key);
+    }
+
+    Ok(())
+}
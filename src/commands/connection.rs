@@ -0,0 +1,322 @@
+use crate::{
+    clients::{PauseMode, ReplyMode},
+    db::Database,
+    errors::Result,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+};
+use byte_string::ByteString;
+use std::{convert::TryInto, time::Duration, time::Instant};
+
+const CLIENT_HELP: &[&str] = &[
+    "ID -- Return the ID of the current connection.",
+    "INFO -- Return information about the current client connection.",
+    "GETNAME -- Return the name of the current connection.",
+    "KILL <ip:port> -- Kill connection made from <ip:port>.",
+    "KILL <option> <value> [<option> <value> [...]] -- Kill connections. Options are: ID <client-id>, ADDR <ip:port>, LADDR <ip:port>, TYPE normal|pubsub, MAXAGE <maxage>, SKIPME yes|no.",
+    "LIST [TYPE normal|pubsub] [ID <client-id> [<client-id> ...]] -- Return information about client connections.",
+    "NO-EVICT on|off -- Protect the current client connection from client eviction.",
+    "PAUSE <timeout> [WRITE|ALL] -- Suspend all, or just write, clients for <timeout> milliseconds.",
+    "REPLY on|off|skip -- Control the replies sent to the current connection.",
+    "SETNAME <name> -- Assign the name <name> to the current connection.",
+    "UNPAUSE -- Stop the current client pause, resuming traffic.",
+];
+
+pub(crate) fn client_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    let sub_command = req.arg(0)?.to_lowercase();
+    let args = &req.arguments()[1..];
+
+    match (sub_command.as_ref(), args.len()) {
+        (b"help", 0) => reply.add_reply_help(req.command(), CLIENT_HELP),
+        (b"id", 0) => match db.clients().current_id() {
+            Some(id) => reply.add_integer(id.try_into()?),
+            None => reply.add_null_string(),
+        },
+        (b"info", 0) => match (db.client_activity(), db.clients().current()) {
+            (activity, Some(client)) => {
+                let activity = activity.get(&client.id).unwrap_or(&client.activity);
+                let mut info = client.info_string(activity, Instant::now());
+                info.push('\n');
+                reply.add_bulk_string(info);
+            }
+            (_, None) => reply.add_null_string(),
+        },
+        (b"getname", 0) => match db.clients().current().and_then(|c| c.name.as_ref()) {
+            Some(name) => reply.add_bulk_string(name),
+            None => reply.add_null_string(),
+        },
+        (b"setname", 1) => {
+            let name = &args[0];
+
+            if name.iter().any(|&c| c <= b' ' || c > b'~') {
+                reply.add_error(
+                    "ERR Client names cannot contain spaces, newlines or special characters.",
+                );
+                return Ok(());
+            }
+
+            if let Some(client) = db.clients().current_mut() {
+                client.name = if name.is_empty() {
+                    None
+                } else {
+                    Some(name.clone())
+                };
+            }
+
+            reply.add_simple_string("OK");
+        }
+        (b"list", _) => client_list(db, args, reply)?,
+        (b"kill", n) if n > 0 => client_kill(db, args, reply)?,
+        (b"pause", 1) | (b"pause", 2) => {
+            let timeout: i64 = match args[0].parse() {
+                Ok(n) if n >= 0 => n,
+                _ => {
+                    reply.add_error("ERR timeout is not an integer or out of range");
+                    return Ok(());
+                }
+            };
+
+            let mode = match args.get(1).map(|m| m.to_lowercase()) {
+                None => PauseMode::All,
+                Some(m) if m.as_ref() == b"all" => PauseMode::All,
+                Some(m) if m.as_ref() == b"write" => PauseMode::Write,
+                Some(_) => {
+                    reply.add_error("ERR syntax error");
+                    return Ok(());
+                }
+            };
+
+            db.clients()
+                .pause(mode, Duration::from_millis(timeout.try_into()?));
+            reply.add_simple_string("OK");
+        }
+        (b"unpause", 0) => {
+            db.clients().unpause();
+            reply.add_simple_string("OK");
+        }
+        (b"reply", 1) => {
+            let mode = match args[0].to_lowercase().as_ref() {
+                b"on" => ReplyMode::On,
+                b"off" => ReplyMode::Off,
+                b"skip" => ReplyMode::Skip,
+                _ => {
+                    reply.add_error("ERR syntax error");
+                    return Ok(());
+                }
+            };
+
+            if let Some(client) = db.clients().current_mut() {
+                // Skipping is meaningless when replies are already off
+                if !(mode == ReplyMode::Skip && client.reply_mode == ReplyMode::Off) {
+                    client.reply_mode = mode;
+                }
+            }
+
+            // The OFF and SKIP forms don't reply at all
+            if mode == ReplyMode::On {
+                reply.add_simple_string("OK");
+            }
+        }
+        (b"no-evict", 1) => {
+            let no_evict = match args[0].to_lowercase().as_ref() {
+                b"on" => true,
+                b"off" => false,
+                _ => {
+                    reply.add_error("ERR syntax error");
+                    return Ok(());
+                }
+            };
+
+            if let Some(client) = db.clients().current_mut() {
+                client.no_evict = no_evict;
+            }
+
+            reply.add_simple_string("OK");
+        }
+        _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
+    }
+
+    Ok(())
+}
+
+fn client_list(db: &Database, args: &[ByteString], reply: &mut Response) -> Result<()> {
+    let mut ids: Option<Vec<u64>> = None;
+    let mut pubsub: Option<bool> = None;
+    let mut args = args;
+
+    while !args.is_empty() {
+        match (args[0].to_lowercase().as_ref(), args.len()) {
+            (b"type", n) if n >= 2 => {
+                match args[1].to_lowercase().as_ref() {
+                    b"normal" => pubsub = Some(false),
+                    b"pubsub" => pubsub = Some(true),
+                    // There is no replication so there are never any
+                    b"master" | b"replica" | b"slave" => ids = Some(vec![]),
+                    _ => {
+                        let msg = format!("ERR Unknown client type '{}'", args[1]);
+                        reply.add_error(&msg);
+                        return Ok(());
+                    }
+                }
+                args = &args[2..];
+            }
+            (b"id", n) if n >= 2 => {
+                let mut requested = vec![];
+                for id in &args[1..] {
+                    match id.parse::<i64>().ok().and_then(|id| id.try_into().ok()) {
+                        Some(id) if id > 0 => requested.push(id),
+                        _ => {
+                            reply.add_error("ERR Invalid client ID");
+                            return Ok(());
+                        }
+                    }
+                }
+                ids = Some(requested);
+                args = &[];
+            }
+            _ => {
+                reply.add_error("ERR syntax error");
+                return Ok(());
+            }
+        }
+    }
+
+    let now = Instant::now();
+    let activity = db.client_activity();
+    let mut list = String::new();
+
+    for client in db.clients().iter() {
+        let is_pubsub = client.subscription_count() > 0;
+
+        if ids.as_ref().is_none_or(|ids| ids.contains(&client.id))
+            && pubsub.is_none_or(|pubsub| pubsub == is_pubsub)
+        {
+            let activity = activity.get(&client.id).unwrap_or(&client.activity);
+            list.push_str(&client.info_string(activity, now));
+            list.push('\n');
+        }
+    }
+
+    reply.add_bulk_string(list);
+
+    Ok(())
+}
+
+#[derive(Default)]
+struct KillFilter {
+    id: Option<u64>,
+    addr: Option<String>,
+    laddr: Option<String>,
+    max_age: Option<u64>,
+    pubsub: Option<bool>,
+    skip_me: bool,
+    no_matches: bool,
+}
+
+fn client_kill(db: &mut Database, args: &[ByteString], reply: &mut Response) -> Result<()> {
+    // Old style: CLIENT KILL <ip:port>. Addresses are compared as they are
+    // shown by CLIENT LIST, as by Redis.
+    if args.len() == 1 {
+        let addr = args[0].to_string();
+
+        match db.clients().iter_mut().find(|c| c.addr.to_string() == addr) {
+            Some(client) => {
+                client.kill();
+                reply.add_simple_string("OK");
+            }
+            None => reply.add_error("ERR No such client"),
+        }
+
+        return Ok(());
+    }
+
+    if !args.len().is_multiple_of(2) {
+        reply.add_error("ERR syntax error");
+        return Ok(());
+    }
+
+    let mut filter = KillFilter {
+        skip_me: true,
+        ..Default::default()
+    };
+
+    for pair in args.chunks(2) {
+        let value = &pair[1];
+
+        match pair[0].to_lowercase().as_ref() {
+            b"id" => match value.parse::<i64>().ok().and_then(|id| id.try_into().ok()) {
+                Some(id) if id > 0 => filter.id = Some(id),
+                _ => {
+                    reply.add_error("ERR client-id should be greater than 0");
+                    return Ok(());
+                }
+            },
+            b"addr" => filter.addr = Some(value.to_string()),
+            b"laddr" => filter.laddr = Some(value.to_string()),
+            b"maxage" => match value.parse::<i64>() {
+                Ok(age) if age >= 0 => filter.max_age = Some(age.try_into()?),
+                _ => {
+                    reply.add_reply_not_a_number();
+                    return Ok(());
+                }
+            },
+            b"type" => match value.to_lowercase().as_ref() {
+                b"normal" => filter.pubsub = Some(false),
+                b"pubsub" => filter.pubsub = Some(true),
+                b"master" | b"replica" | b"slave" => filter.no_matches = true,
+                _ => {
+                    let msg = format!("ERR Unknown client type '{}'", value);
+                    reply.add_error(&msg);
+                    return Ok(());
+                }
+            },
+            b"skipme" => match value.to_lowercase().as_ref() {
+                b"yes" => filter.skip_me = true,
+                b"no" => filter.skip_me = false,
+                _ => {
+                    reply.add_error("ERR syntax error");
+                    return Ok(());
+                }
+            },
+            _ => {
+                reply.add_error("ERR syntax error");
+                return Ok(());
+            }
+        }
+    }
+
+    let current_id = db.clients().current_id();
+    let now = Instant::now();
+    let mut killed = 0;
+
+    if !filter.no_matches {
+        for client in db.clients().iter_mut() {
+            let matches = filter.id.is_none_or(|id| id == client.id)
+                && filter
+                    .addr
+                    .as_ref()
+                    .is_none_or(|addr| *addr == client.addr.to_string())
+                && filter
+                    .laddr
+                    .as_ref()
+                    .is_none_or(|laddr| *laddr == client.laddr.to_string())
+                && filter.max_age.is_none_or(|max_age| {
+                    now.saturating_duration_since(client.created).as_secs() >= max_age
+                })
+                && filter
+                    .pubsub
+                    .is_none_or(|pubsub| pubsub == (client.subscription_count() > 0))
+                && !(filter.skip_me && Some(client.id) == current_id);
+
+            if matches && !client.is_killed() {
+                client.kill();
+                killed += 1;
+            }
+        }
+    }
+
+    reply.add_integer(killed);
+
+    Ok(())
+}
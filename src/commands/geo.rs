@@ -0,0 +1,643 @@
+use crate::{
+    db::{Database, RObj},
+    errors::Result,
+    geohash::{self, Shape},
+    notify,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+    sorted_set::SortedSet,
+};
+use byte_string::ByteString;
+use std::convert::TryInto;
+
+fn parse_f64(arg: &ByteString) -> Option<f64> {
+    std::str::from_utf8(arg)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|value: &f64| !value.is_nan())
+}
+
+/// Parses a longitude and latitude pair, replying with an error when it
+/// isn't one or lies outside of the area geohashes can index
+fn parse_position_or_reply(args: &[ByteString], response: &mut Response) -> Option<(f64, f64)> {
+    let (longitude, latitude) = match (parse_f64(&args[0]), parse_f64(&args[1])) {
+        (Some(longitude), Some(latitude)) => (longitude, latitude),
+        _ => {
+            response.add_error("ERR value is not a valid float");
+            return None;
+        }
+    };
+
+    if !geohash::is_valid(longitude, latitude) {
+        response.add_error(&format!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            longitude, latitude
+        ));
+        return None;
+    }
+
+    Some((longitude, latitude))
+}
+
+/// The number of meters in a unit
+fn parse_unit_or_reply(arg: &ByteString, response: &mut Response) -> Option<f64> {
+    let unit = arg.to_lowercase();
+
+    match unit.as_ref() {
+        b"m" => Some(1.0),
+        b"km" => Some(1000.0),
+        b"ft" => Some(0.3048),
+        b"mi" => Some(1609.34),
+        _ => {
+            response.add_error("ERR unsupported unit provided. please use M, KM, FT, MI");
+            None
+        }
+    }
+}
+
+/// Formats a coordinate with up to 17 decimals, as Redis does
+fn format_coordinate(value: f64) -> String {
+    let formatted = format!("{:.17}", value);
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_owned()
+}
+
+fn format_distance(meters: f64, unit: f64) -> String {
+    format!("{:.4}", meters / unit)
+}
+
+fn add_reply_position(response: &mut Response, (longitude, latitude): (f64, f64)) {
+    response.add_array_len(2);
+    response.add_bulk_string(format_coordinate(longitude));
+    response.add_bulk_string(format_coordinate(latitude));
+}
+
+pub(crate) fn geoadd_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let mut args = &request.arguments()[1..];
+    let (mut nx, mut xx, mut ch) = (false, false, false);
+
+    while let Some(arg) = args.first() {
+        let arg = arg.to_lowercase();
+        match arg.as_ref() {
+            b"nx" => nx = true,
+            b"xx" => xx = true,
+            b"ch" => ch = true,
+            _ => break,
+        }
+        args = &args[1..];
+    }
+
+    if nx && xx {
+        response.add_error("ERR XX and NX options at the same time are not compatible");
+        return Ok(());
+    }
+
+    if args.is_empty() || !args.len().is_multiple_of(3) {
+        response
+            .add_error("ERR syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... ");
+        return Ok(());
+    }
+
+    let mut points = Vec::with_capacity(args.len() / 3);
+    for triple in args.chunks(3) {
+        match parse_position_or_reply(triple, response) {
+            Some((longitude, latitude)) => {
+                points.push((&triple[2], geohash::score(longitude, latitude)))
+            }
+            None => return Ok(()),
+        }
+    }
+
+    if !db.exists(key) {
+        // Only updating existing members leaves a missing key alone
+        if xx {
+            response.add_integer(0);
+            return Ok(());
+        }
+        db.insert(key.clone(), RObj::SortedSet(SortedSet::new()));
+    }
+
+    let limits = db.config().zset_listpack_limits();
+    let zset = match db.get_mut(key) {
+        Some(RObj::SortedSet(zset)) => zset,
+        _ => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+    };
+
+    let (mut added, mut updated) = (0, 0);
+    for (member, score) in points {
+        match zset.score(member) {
+            Some(_) if nx => {}
+            Some(old) => {
+                if old != score {
+                    zset.insert(member.clone(), score, limits);
+                    updated += 1;
+                }
+            }
+            None if xx => {}
+            None => {
+                zset.insert(member.clone(), score, limits);
+                added += 1;
+            }
+        }
+    }
+
+    if added + updated > 0 {
+        db.notify_keyspace_event(notify::ZSET, "zadd", key);
+    }
+    response.add_integer(if ch { added + updated } else { added });
+
+    Ok(())
+}
+
+/// The sorted set held by a key, `Err` meaning it holds another type, which
+/// has been replied to
+fn lookup_sorted_set<'a>(
+    db: &'a mut Database,
+    key: &ByteString,
+    response: &mut Response,
+) -> std::result::Result<Option<&'a SortedSet>, ()> {
+    match db.get(key) {
+        Some(RObj::SortedSet(zset)) => Ok(Some(zset)),
+        Some(_) => {
+            response.add_reply_wrong_type();
+            Err(())
+        }
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn geopos_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let members = &request.arguments()[1..];
+    let zset = match lookup_sorted_set(db, request.arg(0)?, response) {
+        Ok(zset) => zset,
+        Err(()) => return Ok(()),
+    };
+
+    response.add_array_len(members.len().try_into()?);
+    for member in members {
+        match zset.and_then(|zset| zset.score(member)) {
+            Some(score) => add_reply_position(response, geohash::position(score)),
+            None => response.add_null_array(),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn geodist_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let unit = match request.arguments() {
+        [_, _, _] => 1.0,
+        [_, _, _, unit] => match parse_unit_or_reply(unit, response) {
+            Some(unit) => unit,
+            None => return Ok(()),
+        },
+        _ => {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+    };
+
+    let zset = match lookup_sorted_set(db, request.arg(0)?, response) {
+        Ok(zset) => zset,
+        Err(()) => return Ok(()),
+    };
+
+    let score = |i| zset.and_then(|zset| zset.score(request.arg(i).ok()?));
+    match (score(1), score(2)) {
+        (Some(first), Some(second)) => {
+            let (long1, lat1) = geohash::position(first);
+            let (long2, lat2) = geohash::position(second);
+            let distance = geohash::distance(long1, lat1, long2, lat2);
+            response.add_bulk_string(format_distance(distance, unit));
+        }
+        _ => response.add_null_string(),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn geohash_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let members = &request.arguments()[1..];
+    let zset = match lookup_sorted_set(db, request.arg(0)?, response) {
+        Ok(zset) => zset,
+        Err(()) => return Ok(()),
+    };
+
+    response.add_array_len(members.len().try_into()?);
+    for member in members {
+        match zset.and_then(|zset| zset.score(member)) {
+            Some(score) => response.add_bulk_string(geohash::to_string(score)),
+            None => response.add_null_string(),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Sort {
+    None,
+    Asc,
+    Desc,
+}
+
+enum SearchFrom<'a> {
+    Member(&'a ByteString),
+    Position(f64, f64),
+}
+
+/// The options of a GEOSEARCH or GEOSEARCHSTORE
+struct SearchOptions<'a> {
+    from: Option<SearchFrom<'a>>,
+    /// The shape, in the given unit, and the number of meters in the unit
+    by: Option<(Shape, f64)>,
+    sort: Sort,
+    count: Option<usize>,
+    any: bool,
+    with_dist: bool,
+    with_hash: bool,
+    with_coord: bool,
+    store_dist: bool,
+}
+
+/// A member found by a search
+struct Found<'a> {
+    member: &'a [u8],
+    score: f64,
+    distance: f64,
+    position: (f64, f64),
+}
+
+fn parse_search_options_or_reply<'a>(
+    mut args: &'a [ByteString],
+    store: bool,
+    response: &mut Response,
+) -> Option<SearchOptions<'a>> {
+    let mut options = SearchOptions {
+        from: None,
+        by: None,
+        sort: Sort::None,
+        count: None,
+        any: false,
+        with_dist: false,
+        with_hash: false,
+        with_coord: false,
+        store_dist: false,
+    };
+
+    while let Some(arg) = args.first() {
+        let arg = arg.to_lowercase();
+        let rest = &args[1..];
+
+        let used = match arg.as_ref() {
+            b"frommember" if !rest.is_empty() => {
+                if let Some(SearchFrom::Position(..)) = options.from {
+                    response.add_error("ERR syntax error");
+                    return None;
+                }
+                options.from = Some(SearchFrom::Member(&rest[0]));
+                1
+            }
+            b"fromlonlat" if rest.len() >= 2 => {
+                if let Some(SearchFrom::Member(_)) = options.from {
+                    response.add_error("ERR syntax error");
+                    return None;
+                }
+                let (longitude, latitude) = parse_position_or_reply(rest, response)?;
+                options.from = Some(SearchFrom::Position(longitude, latitude));
+                2
+            }
+            b"byradius" if rest.len() >= 2 => {
+                if let Some((Shape::Box { .. }, _)) = options.by {
+                    response.add_error("ERR syntax error");
+                    return None;
+                }
+                let radius = match parse_f64(&rest[0]) {
+                    Some(radius) if radius < 0.0 => {
+                        response.add_error("ERR radius cannot be negative");
+                        return None;
+                    }
+                    Some(radius) => radius,
+                    None => {
+                        response.add_error("ERR need numeric radius");
+                        return None;
+                    }
+                };
+                let unit = parse_unit_or_reply(&rest[1], response)?;
+                options.by = Some((Shape::Radius(radius), unit));
+                2
+            }
+            b"bybox" if rest.len() >= 3 => {
+                if let Some((Shape::Radius(_), _)) = options.by {
+                    response.add_error("ERR syntax error");
+                    return None;
+                }
+                let width = match parse_f64(&rest[0]) {
+                    Some(width) => width,
+                    None => {
+                        response.add_error("ERR need numeric width");
+                        return None;
+                    }
+                };
+                let height = match parse_f64(&rest[1]) {
+                    Some(height) => height,
+                    None => {
+                        response.add_error("ERR need numeric height");
+                        return None;
+                    }
+                };
+                if width < 0.0 || height < 0.0 {
+                    response.add_error("ERR height or width cannot be negative");
+                    return None;
+                }
+                let unit = parse_unit_or_reply(&rest[2], response)?;
+                options.by = Some((Shape::Box { width, height }, unit));
+                3
+            }
+            b"asc" => {
+                options.sort = Sort::Asc;
+                0
+            }
+            b"desc" => {
+                options.sort = Sort::Desc;
+                0
+            }
+            b"count" if !rest.is_empty() => {
+                let count: i64 = match rest[0].parse() {
+                    Ok(count) => count,
+                    Err(_) => {
+                        response.add_reply_not_a_number();
+                        return None;
+                    }
+                };
+                if count <= 0 {
+                    response.add_error("ERR COUNT must be > 0");
+                    return None;
+                }
+                options.count = count.try_into().ok();
+                1
+            }
+            b"any" => {
+                options.any = true;
+                0
+            }
+            b"withdist" => {
+                options.with_dist = true;
+                0
+            }
+            b"withhash" => {
+                options.with_hash = true;
+                0
+            }
+            b"withcoord" => {
+                options.with_coord = true;
+                0
+            }
+            b"storedist" if store => {
+                options.store_dist = true;
+                0
+            }
+            _ => {
+                response.add_error("ERR syntax error");
+                return None;
+            }
+        };
+
+        args = &rest[used..];
+    }
+
+    Some(options)
+}
+
+pub(crate) fn geosearch_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_geosearch_command(db, request, response, false)
+}
+
+pub(crate) fn geosearchstore_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_geosearch_command(db, request, response, true)
+}
+
+fn generic_geosearch_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+    store: bool,
+) -> Result<()> {
+    let command = request.command().to_uppercase();
+    let (dest, src, args) = if store {
+        (
+            Some(request.arg(0)?),
+            request.arg(1)?,
+            &request.arguments()[2..],
+        )
+    } else {
+        (None, request.arg(0)?, &request.arguments()[1..])
+    };
+
+    if let Err(()) = lookup_sorted_set(db, src, response) {
+        return Ok(());
+    }
+
+    let mut options = match parse_search_options_or_reply(args, store, response) {
+        Some(options) => options,
+        None => return Ok(()),
+    };
+
+    if store && (options.with_dist || options.with_hash || options.with_coord) {
+        response.add_error(&format!(
+            "ERR {} is not compatible with WITHDIST, WITHHASH and WITHCOORD options",
+            command
+        ));
+        return Ok(());
+    }
+
+    let (from, (shape, unit)) = match (options.from.take(), options.by) {
+        (Some(from), Some(by)) => (from, by),
+        (None, _) => {
+            response.add_error(&format!(
+                "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for {}",
+                command.to_lowercase()
+            ));
+            return Ok(());
+        }
+        (_, None) => {
+            response.add_error(&format!(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for {}",
+                command.to_lowercase()
+            ));
+            return Ok(());
+        }
+    };
+
+    if options.any && options.count.is_none() {
+        response.add_error("ERR the ANY argument requires COUNT argument");
+        return Ok(());
+    }
+
+    let limits = db.config().zset_listpack_limits();
+    let zset = match db.get(src) {
+        Some(RObj::SortedSet(zset)) => zset,
+        _ => {
+            match dest {
+                Some(dest) => {
+                    if db.remove(dest).is_some() {
+                        db.notify_keyspace_event(notify::GENERIC, "del", dest);
+                    }
+                    response.add_integer(0);
+                }
+                None => response.add_array_len(0),
+            }
+            return Ok(());
+        }
+    };
+
+    // Returning the closest N members means sorting them all first
+    if options.count.is_some() && options.sort == Sort::None && !options.any {
+        options.sort = Sort::Asc;
+    }
+
+    let center = match from {
+        SearchFrom::Position(longitude, latitude) => (longitude, latitude),
+        SearchFrom::Member(member) => match zset.score(member) {
+            Some(score) => geohash::position(score),
+            None => {
+                response.add_error("ERR could not decode requested zset member");
+                return Ok(());
+            }
+        },
+    };
+
+    let shape = match shape {
+        Shape::Radius(radius) => Shape::Radius(radius * unit),
+        Shape::Box { width, height } => Shape::Box {
+            width: width * unit,
+            height: height * unit,
+        },
+    };
+
+    // With ANY the search stops as soon as enough members are found
+    let limit = options.count.filter(|_| options.any);
+    let mut found = Vec::new();
+    'ranges: for (min, max) in geohash::search_ranges(shape, center) {
+        for (member, score) in zset.range_by_score(min, max) {
+            let position = geohash::position(score);
+            if let Some(distance) = shape.distance_if_within(center, position.0, position.1) {
+                found.push(Found {
+                    member,
+                    score,
+                    distance,
+                    position,
+                });
+
+                if limit.is_some_and(|limit| found.len() >= limit) {
+                    break 'ranges;
+                }
+            }
+        }
+    }
+
+    match options.sort {
+        Sort::Asc => found.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        Sort::Desc => found.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        Sort::None => {}
+    }
+    if let Some(count) = options.count {
+        found.truncate(count);
+    }
+
+    let dest = match dest {
+        Some(dest) => dest,
+        None => {
+            reply_found(response, &found, &options, unit)?;
+            return Ok(());
+        }
+    };
+
+    let mut result = SortedSet::new();
+    for point in &found {
+        let score = if options.store_dist {
+            point.distance / unit
+        } else {
+            point.score
+        };
+        result.insert(ByteString::from(point.member), score, limits);
+    }
+
+    let len = result.len();
+    if len > 0 {
+        db.remove(dest);
+        db.insert(dest.clone(), RObj::SortedSet(result));
+        db.notify_keyspace_event(notify::ZSET, "geosearchstore", dest);
+    } else if db.remove(dest).is_some() {
+        db.notify_keyspace_event(notify::GENERIC, "del", dest);
+    }
+    response.add_integer(len.try_into()?);
+
+    Ok(())
+}
+
+fn reply_found(
+    response: &mut Response,
+    found: &[Found],
+    options: &SearchOptions,
+    unit: f64,
+) -> Result<()> {
+    let extra = [options.with_dist, options.with_hash, options.with_coord]
+        .iter()
+        .filter(|&&with| with)
+        .count();
+
+    response.add_array_len(found.len().try_into()?);
+    for point in found {
+        if extra == 0 {
+            response.add_bulk_string(point.member);
+            continue;
+        }
+
+        response.add_array_len((1 + extra).try_into()?);
+        response.add_bulk_string(point.member);
+        if options.with_dist {
+            response.add_bulk_string(format_distance(point.distance, unit));
+        }
+        if options.with_hash {
+            response.add_integer(point.score as i64);
+        }
+        if options.with_coord {
+            add_reply_position(response, point.position);
+        }
+    }
+
+    Ok(())
+}
@@ -1,12 +1,24 @@
 use crate::{
     db::{Database, RObj},
     errors::Result,
+    hash::Hash,
+    listpack::ListpackLimits,
+    notify,
     request::Request,
     response::Response,
     response_ext::ResponseExt,
 };
 use byte_string::ByteString;
-use std::{collections::HashMap, convert::TryInto};
+use std::convert::TryInto;
+
+/// Sets the fields and values given in pairs, returning the number of new
+/// fields
+fn set_fields(hash: &mut Hash, values: &[ByteString], limits: ListpackLimits) -> usize {
+    values
+        .chunks(2)
+        .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone(), limits))
+        .count()
+}
 
 fn generic_hset_command(
     db: &mut Database,
@@ -24,29 +36,26 @@ fn generic_hset_command(
         return Ok(());
     }
 
+    let limits = db.config().hash_listpack_limits();
+
     match db.get_mut(key) {
         Some(RObj::Hash(ref mut hash)) => {
-            let prev_len = hash.len();
-            let new_hash = values
-                .chunks(2)
-                .map(|pair| (pair[0].clone(), pair[1].clone()));
-            hash.extend(new_hash);
+            let count_keys_added = set_fields(hash, values, limits);
 
             if respond_with_count {
-                let count_keys_added = hash.len() - prev_len;
                 response.add_integer(count_keys_added.try_into()?);
             } else {
                 response.add_simple_string("OK");
             }
+
+            db.notify_keyspace_event(notify::HASH, "hset", key);
         }
         Some(_) => response.add_reply_wrong_type(),
         None => {
-            let new_hash = values
-                .chunks(2)
-                .map(|pair| (pair[0].clone(), pair[1].clone()))
-                .collect::<HashMap<ByteString, ByteString>>();
-            let count_keys_added = new_hash.len();
+            let mut new_hash = Hash::new();
+            let count_keys_added = set_fields(&mut new_hash, values, limits);
             db.insert(key.clone(), RObj::Hash(new_hash));
+            db.notify_keyspace_event(notify::HASH, "hset", key);
 
             if respond_with_count {
                 response.add_integer(count_keys_added.try_into()?);
@@ -140,7 +149,7 @@ pub(crate) fn hgetall_command(
             let len: i64 = hash.len().try_into()?;
             response.add_array_len(len * 2);
 
-            for (key, value) in hash {
+            for (key, value) in hash.iter() {
                 response.add_bulk_string(key);
                 response.add_bulk_string(value);
             }
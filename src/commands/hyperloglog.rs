@@ -0,0 +1,182 @@
+use crate::{
+    db::{Database, RObj},
+    errors::Result,
+    hyperloglog::{self, HllError, REGISTERS},
+    notify,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+};
+use byte_string::ByteString;
+
+fn add_reply_hll_error(response: &mut Response, error: HllError) {
+    match error {
+        HllError::Invalid => {
+            response.add_error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+        }
+        HllError::Corrupted => response.add_error("INVALIDOBJ Corrupted HLL object detected"),
+    }
+}
+
+/// The sketch held by an object, replying with an error if it doesn't hold one
+fn hll_or_reply<'a>(object: &'a mut RObj, response: &mut Response) -> Option<&'a mut ByteString> {
+    match object {
+        RObj::String(hll) => match hyperloglog::validate(hll) {
+            Ok(()) => Some(hll),
+            Err(error) => {
+                add_reply_hll_error(response, error);
+                None
+            }
+        },
+        RObj::Int(_) => {
+            add_reply_hll_error(response, HllError::Invalid);
+            None
+        }
+        _ => {
+            response.add_reply_wrong_type();
+            None
+        }
+    }
+}
+
+/// The maximum of each register across the sketches held by the keys, and
+/// whether any of them is dense
+fn union_or_reply(
+    db: &mut Database,
+    keys: &[ByteString],
+    response: &mut Response,
+) -> Option<(Vec<u8>, bool)> {
+    let mut max = vec![0; REGISTERS];
+    let mut any_dense = false;
+
+    for key in keys {
+        let hll = match db.get_mut(key) {
+            Some(object) => hll_or_reply(object, response)?,
+            None => continue,
+        };
+
+        any_dense |= hyperloglog::is_dense(hll);
+        match hyperloglog::registers(hll) {
+            Ok(registers) => max
+                .iter_mut()
+                .zip(registers)
+                .for_each(|(max, register)| *max = register.max(*max)),
+            Err(error) => {
+                add_reply_hll_error(response, error);
+                return None;
+            }
+        }
+    }
+
+    Some((max, any_dense))
+}
+
+pub(crate) fn pfadd_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let sparse_max_bytes = db.config().hll_sparse_max_bytes;
+    let mut updated = false;
+
+    if !db.exists(key) {
+        db.insert(key.clone(), RObj::String(hyperloglog::new().into()));
+        updated = true;
+    }
+
+    let hll = match db.get_mut(key).and_then(|o| hll_or_reply(o, response)) {
+        Some(hll) => hll,
+        None => return Ok(()),
+    };
+
+    for element in &request.arguments()[1..] {
+        match hyperloglog::add(hll.to_mut(), element, sparse_max_bytes) {
+            Ok(changed) => updated |= changed,
+            Err(error) => {
+                add_reply_hll_error(response, error);
+                return Ok(());
+            }
+        }
+    }
+
+    if updated {
+        db.notify_keyspace_event(notify::STRING, "pfadd", key);
+    }
+    response.add_integer(updated.into());
+
+    Ok(())
+}
+
+pub(crate) fn pfcount_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let keys = request.arguments();
+
+    // A single key uses, and updates, the cardinality cached in the sketch
+    if let [key] = keys {
+        let hll = match db.get_mut(key) {
+            Some(object) => match hll_or_reply(object, response) {
+                Some(hll) => hll,
+                None => return Ok(()),
+            },
+            None => {
+                response.add_integer(0);
+                return Ok(());
+            }
+        };
+
+        match hyperloglog::count(hll) {
+            Ok(card) => response.add_integer(card as i64),
+            Err(error) => add_reply_hll_error(response, error),
+        }
+        return Ok(());
+    }
+
+    // Several keys are counted as the union of their registers
+    if let Some((max, _)) = union_or_reply(db, keys, response) {
+        response.add_integer(hyperloglog::count_registers(&max) as i64);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn pfmerge_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let dest = request.arg(0)?;
+    let sparse_max_bytes = db.config().hll_sparse_max_bytes;
+
+    // The destination's own registers take part in the union
+    let (max, use_dense) = match union_or_reply(db, request.arguments(), response) {
+        Some(union) => union,
+        None => return Ok(()),
+    };
+
+    if !db.exists(dest) {
+        db.insert(dest.clone(), RObj::String(hyperloglog::new().into()));
+    }
+
+    if let Some(RObj::String(hll)) = db.get_mut(dest) {
+        let merged = if use_dense {
+            hyperloglog::to_dense(hll.to_mut())
+        } else {
+            Ok(())
+        }
+        .and_then(|()| hyperloglog::merge_registers(hll.to_mut(), &max, sparse_max_bytes));
+
+        if let Err(error) = merged {
+            add_reply_hll_error(response, error);
+            return Ok(());
+        }
+    }
+
+    db.notify_keyspace_event(notify::STRING, "pfadd", dest);
+    response.add_simple_string("OK");
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use crate::{
     db::{Database, RObj},
     errors::Result,
+    notify,
     request::Request,
     response::Response,
     response_ext::ResponseExt,
@@ -19,6 +20,7 @@ pub(crate) fn del_command(
 
     for key in request.arguments() {
         if db.remove(key).is_some() {
+            db.notify_keyspace_event(notify::GENERIC, "del", key);
             count += 1;
         }
     }
@@ -77,6 +79,9 @@ pub(crate) fn type_command(
                 RObj::Int(_) | RObj::String(_) => "string",
                 RObj::List(_) => "list",
                 RObj::Hash(_) => "hash",
+                RObj::Stream(_) => "stream",
+                RObj::SortedSet(_) => "zset",
+                RObj::Custom(custom) => custom.data_type().name(),
             };
 
             response.add_simple_string(type_name);
@@ -121,8 +126,11 @@ pub(crate) fn object_command(
                         let type_name = match value {
                             RObj::Int(_) => "int",
                             RObj::String(_) => "byte_string",
-                            RObj::List(_) => "vecdeque",
-                            RObj::Hash(_) => "hash_map",
+                            RObj::List(list) => list.encoding(),
+                            RObj::Hash(hash) => hash.encoding(),
+                            RObj::Stream(_) => "stream",
+                            RObj::SortedSet(zset) => zset.encoding(),
+                            RObj::Custom(_) => "raw",
                         };
 
                         response.add_bulk_string(type_name);
@@ -151,7 +159,7 @@ pub(crate) fn expire_command(
 ) -> Result<()> {
     let key = request.arg(0)?;
 
-    if db.get(key).is_none() {
+    if !db.exists(key) {
         response.add_integer(0);
         return Ok(());
     }
@@ -160,12 +168,16 @@ pub(crate) fn expire_command(
 
     if !seconds.is_positive() {
         db.remove(key);
+        db.notify_keyspace_event(notify::GENERIC, "del", key);
         response.add_integer(1);
         return Ok(());
     }
 
     let expires_at = Instant::now() + Duration::from_secs(seconds.try_into()?);
     let res = db.set_expire(key, expires_at);
+    if res {
+        db.notify_keyspace_event(notify::GENERIC, "expire", key);
+    }
     response.add_integer(res.into());
 
     Ok(())
@@ -178,12 +190,15 @@ pub(crate) fn persist_command(
 ) -> Result<()> {
     let key = request.arg(0)?;
 
-    if db.get(key).is_none() {
+    if !db.exists(key) {
         response.add_integer(0);
         return Ok(());
     }
 
     let res = db.persist(key);
+    if res {
+        db.notify_keyspace_event(notify::GENERIC, "persist", key);
+    }
     response.add_integer(res.into());
     Ok(())
 }
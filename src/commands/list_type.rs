@@ -1,11 +1,11 @@
 use crate::{
     db::{Database, RObj},
     errors::Result,
+    notify,
     request::Request,
     response::Response,
     response_ext::ResponseExt,
 };
-use byte_string::ByteString;
 use std::convert::TryInto;
 
 pub(crate) fn rpush_command(
@@ -15,16 +15,24 @@ pub(crate) fn rpush_command(
 ) -> Result<()> {
     let key = request.arg(0)?;
     let values = &request.arguments()[1..];
+    let limits = db.config().list_listpack_limits();
 
     match db.get_mut(key) {
         Some(RObj::List(ref mut list)) => {
-            list.extend(values.to_owned());
+            values
+                .iter()
+                .for_each(|v| list.push_back(v.to_owned(), limits));
 
             response.add_integer(list.len().try_into()?);
+            db.notify_keyspace_event(notify::LIST, "rpush", key);
         }
         Some(_) => response.add_reply_wrong_type(),
         None => {
-            db.insert(key.to_owned(), RObj::new_list_from(values.to_owned()));
+            db.insert(
+                key.to_owned(),
+                RObj::new_list_from(values.to_owned(), limits),
+            );
+            db.notify_keyspace_event(notify::LIST, "rpush", key);
             response.add_integer(values.len().try_into()?);
         }
     }
@@ -39,18 +47,23 @@ pub(crate) fn lpush_command(
 ) -> Result<()> {
     let key = request.arg(0)?;
     let values = &request.arguments()[1..];
+    let limits = db.config().list_listpack_limits();
 
     match db.get_mut(key) {
         Some(RObj::List(ref mut list)) => {
-            values.iter().for_each(|v| list.push_front(v.to_owned()));
+            values
+                .iter()
+                .for_each(|v| list.push_front(v.to_owned(), limits));
 
             response.add_integer(list.len().try_into()?);
+            db.notify_keyspace_event(notify::LIST, "lpush", key);
         }
         Some(_) => response.add_reply_wrong_type(),
         None => {
             let len = values.len().try_into()?;
             let iter_reversed = values.iter().rev().cloned();
-            db.insert(key.to_owned(), RObj::new_list_from(iter_reversed));
+            db.insert(key.to_owned(), RObj::new_list_from(iter_reversed, limits));
+            db.notify_keyspace_event(notify::LIST, "lpush", key);
 
             response.add_integer(len);
         }
@@ -65,13 +78,15 @@ pub(crate) fn linsert_command(
     response: &mut Response,
 ) -> Result<()> {
     let key = request.arg(0)?;
+    let limits = db.config().list_listpack_limits();
 
     match db.get_mut(key) {
         Some(RObj::List(ref mut list)) => {
             let side = request.arg(1)?;
             let pivot = request.arg(2)?;
 
-            if let Some(idx) = list.iter().position(|elem| elem == pivot) {
+            let position = list.iter().position(|elem| elem == pivot.as_ref());
+            if let Some(idx) = position {
                 let idx = match side.to_lowercase().as_ref() {
                     b"before" => idx,
                     b"after" => idx + 1,
@@ -82,9 +97,10 @@ pub(crate) fn linsert_command(
                 };
 
                 let value = request.arg(3)?;
-                list.insert(idx, value.clone());
+                list.insert(idx, value.clone(), limits);
 
                 response.add_integer(list.len().try_into()?);
+                db.notify_keyspace_event(notify::LIST, "linsert", key);
             } else {
                 response.add_integer(-1);
             }
@@ -107,6 +123,7 @@ pub(crate) fn rpop_command(
         Some(RObj::List(ref mut list)) => {
             if let Some(value) = list.pop_back() {
                 response.add_bulk_string(&value);
+                db.notify_keyspace_event(notify::LIST, "rpop", key);
             } else {
                 response.add_null_string();
             }
@@ -131,6 +148,7 @@ pub(crate) fn lpop_command(
         Some(RObj::List(ref mut list)) => {
             if let Some(value) = list.pop_front() {
                 response.add_bulk_string(&value);
+                db.notify_keyspace_event(notify::LIST, "lpop", key);
             } else {
                 response.add_null_string();
             }
@@ -198,6 +216,7 @@ pub(crate) fn lset_command(
     response: &mut Response,
 ) -> Result<()> {
     let key = request.arg(0)?;
+    let limits = db.config().list_listpack_limits();
 
     match db.get_mut(key) {
         Some(RObj::List(list)) => {
@@ -211,13 +230,10 @@ pub(crate) fn lset_command(
 
             let index: usize = index.try_into()?;
 
-            if let Some(existing_value) = list.get_mut(index) {
-                let new_value = request.arg(2)?;
-                existing_value.clear();
-                existing_value.extend(new_value.as_ref());
-                existing_value.shrink_to_fit();
-
+            let new_value = request.arg(2)?;
+            if list.set(index, new_value.clone(), limits) {
                 response.add_simple_string("OK");
+                db.notify_keyspace_event(notify::LIST, "lset", key);
             } else {
                 response.add_error("ERR index out of range");
             }
@@ -286,17 +302,13 @@ pub(crate) fn ltrim_command(
 
             if start_index > end_index || end_index < 0 || start_index >= list.len().try_into()? {
                 db.remove(key);
+                db.notify_keyspace_event(notify::LIST, "ltrim", key);
+                db.notify_keyspace_event(notify::GENERIC, "del", key);
             } else {
-                let (start_index, mut end_index) = clamp(start_index, end_index, list.len())?;
-
-                if start_index > 0 {
-                    list.drain(0..start_index);
-                    end_index -= start_index;
-                }
+                let (start_index, end_index) = clamp(start_index, end_index, list.len())?;
+                list.trim(start_index, end_index);
 
-                if end_index < list.len() - 1 {
-                    list.drain((end_index + 1)..);
-                }
+                db.notify_keyspace_event(notify::LIST, "ltrim", key);
             }
 
             response.add_simple_string("OK");
@@ -315,41 +327,34 @@ pub(crate) fn lrem_command(
 ) -> Result<()> {
     let key = request.arg(0)?;
 
-    match db.remove(key) {
+    match db.get_mut(key) {
         Some(RObj::List(list)) => {
-            let mut to_remove: i64 = parse_arg_or_reply_with_err!(1, request, response);
+            let to_remove: i64 = parse_arg_or_reply_with_err!(1, request, response);
             let obj = request.arg(2)?;
-            let mut removed = 0;
-            let mut reverse = false;
-
-            let maybe_rev_iter: Box<dyn DoubleEndedIterator<Item = &ByteString>> = if to_remove < 0
-            {
-                to_remove = -to_remove;
-                reverse = true;
-                Box::new(list.iter().rev())
-            } else {
-                Box::new(list.iter())
+            let limit = match to_remove {
+                0 => usize::MAX,
+                n => n.unsigned_abs().try_into().unwrap_or(usize::MAX),
             };
 
-            let filtered: Vec<&ByteString> = maybe_rev_iter
-                .filter(|entry| {
-                    if (to_remove == 0 || removed < to_remove) && entry == &obj {
-                        removed += 1;
-                        false
-                    } else {
-                        true
-                    }
-                })
-                .collect();
-
-            let result_iter: Box<dyn DoubleEndedIterator<Item = &ByteString>> = if reverse {
-                Box::new(filtered.iter().rev().cloned())
+            // A negative count removes the matches nearest the tail first
+            let len = list.len();
+            let matches = |(_, entry): &(usize, &[u8])| *entry == obj.as_ref();
+            let mut indexes: Vec<usize> = if to_remove < 0 {
+                let iter = list.iter().rev().enumerate().filter(matches);
+                iter.map(|(i, _)| len - 1 - i).take(limit).collect()
             } else {
-                Box::new(filtered.iter().cloned())
+                let iter = list.iter().enumerate().filter(matches);
+                iter.map(|(i, _)| i).take(limit).collect()
             };
+            indexes.sort_unstable();
 
-            db.insert(key.to_owned(), RObj::new_list_from(result_iter.cloned()));
-            response.add_integer(removed);
+            list.retain(|i| indexes.binary_search(&i).is_err());
+
+            let removed = indexes.len();
+            if removed > 0 {
+                db.notify_keyspace_event(notify::LIST, "lrem", key);
+            }
+            response.add_integer(removed.try_into()?);
         }
         Some(_) => response.add_reply_wrong_type(),
         None => response.add_integer(0),
@@ -0,0 +1,199 @@
+use crate::{
+    db::Database,
+    errors::{Error, Result},
+    pubsub::add_subscription_reply,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+};
+use byte_string::ByteString;
+use std::convert::TryInto;
+
+fn current_client_id(db: &Database) -> Result<u64> {
+    db.clients()
+        .current_id()
+        .ok_or_else(|| Error::from("Pub/Sub requires a connected client"))
+}
+
+pub(crate) fn subscribe_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let client_id = current_client_id(db)?;
+
+    for channel in req.arguments() {
+        if let Some(client) = db.clients().get_mut(client_id) {
+            if client.channels.insert(channel.clone()) {
+                db.pubsub().subscribe(client_id, channel);
+            }
+        }
+
+        let count = db
+            .clients()
+            .get(client_id)
+            .map_or(0, |c| c.subscription_count());
+        add_subscription_reply(reply, "subscribe", Some(channel), count);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn unsubscribe_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let client_id = current_client_id(db)?;
+
+    // Without arguments the client is unsubscribed from every channel
+    let channels: Vec<ByteString> = match req.arguments() {
+        [] => db
+            .clients()
+            .get(client_id)
+            .map(|c| c.channels.iter().cloned().collect())
+            .unwrap_or_default(),
+        channels => channels.to_vec(),
+    };
+
+    if channels.is_empty() {
+        let count = db
+            .clients()
+            .get(client_id)
+            .map_or(0, |c| c.subscription_count());
+        add_subscription_reply(reply, "unsubscribe", None, count);
+        return Ok(());
+    }
+
+    for channel in &channels {
+        if let Some(client) = db.clients().get_mut(client_id) {
+            if client.channels.remove(channel) {
+                db.pubsub().unsubscribe(client_id, channel);
+            }
+        }
+
+        let count = db
+            .clients()
+            .get(client_id)
+            .map_or(0, |c| c.subscription_count());
+        add_subscription_reply(reply, "unsubscribe", Some(channel), count);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn psubscribe_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let client_id = current_client_id(db)?;
+
+    for pattern in req.arguments() {
+        if let Some(client) = db.clients().get_mut(client_id) {
+            if client.patterns.insert(pattern.clone()) {
+                db.pubsub().psubscribe(client_id, pattern);
+            }
+        }
+
+        let count = db
+            .clients()
+            .get(client_id)
+            .map_or(0, |c| c.subscription_count());
+        add_subscription_reply(reply, "psubscribe", Some(pattern), count);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn punsubscribe_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let client_id = current_client_id(db)?;
+
+    // Without arguments the client is unsubscribed from every pattern
+    let patterns: Vec<ByteString> = match req.arguments() {
+        [] => db
+            .clients()
+            .get(client_id)
+            .map(|c| c.patterns.iter().cloned().collect())
+            .unwrap_or_default(),
+        patterns => patterns.to_vec(),
+    };
+
+    if patterns.is_empty() {
+        let count = db
+            .clients()
+            .get(client_id)
+            .map_or(0, |c| c.subscription_count());
+        add_subscription_reply(reply, "punsubscribe", None, count);
+        return Ok(());
+    }
+
+    for pattern in &patterns {
+        if let Some(client) = db.clients().get_mut(client_id) {
+            if client.patterns.remove(pattern) {
+                db.pubsub().punsubscribe(client_id, pattern);
+            }
+        }
+
+        let count = db
+            .clients()
+            .get(client_id)
+            .map_or(0, |c| c.subscription_count());
+        add_subscription_reply(reply, "punsubscribe", Some(pattern), count);
+    }
+
+    Ok(())
+}
+
+pub(crate) fn publish_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let channel = req.arg(0)?;
+    let message = req.arg(1)?;
+
+    let receivers = db.publish(channel, message);
+    reply.add_integer(receivers.try_into()?);
+
+    Ok(())
+}
+
+const PUBSUB_HELP: &[&str] = &[
+    "CHANNELS [<pattern>] -- Return the currently active channels matching a pattern (default: all).",
+    "NUMPAT -- Return number of subscriptions to patterns.",
+    "NUMSUB [channel-1 .. channel-N] -- Returns the number of subscribers for the specified channels (excluding patterns, default: none).",
+];
+
+pub(crate) fn pubsub_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    let sub_command = req.arg(0)?.to_lowercase();
+    let args = &req.arguments()[1..];
+
+    match (sub_command.as_ref(), args.len()) {
+        (b"help", 0) => reply.add_reply_help(req.command(), PUBSUB_HELP),
+        (b"channels", 0) | (b"channels", 1) => {
+            let pubsub = db.pubsub();
+            let channels = pubsub.channels(args.first());
+
+            reply.add_array_len(channels.len().try_into()?);
+            for channel in channels {
+                reply.add_bulk_string(channel);
+            }
+        }
+        (b"numsub", _) => {
+            reply.add_array_len((args.len() * 2).try_into()?);
+            for channel in args {
+                reply.add_bulk_string(channel);
+                reply.add_integer(db.pubsub().num_subscribers(channel).try_into()?);
+            }
+        }
+        (b"numpat", 0) => reply.add_integer(db.pubsub().num_patterns().try_into()?),
+        _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use crate::{
+    db::Database,
+    errors::Result,
+    functions::{Functions, Library, RestorePolicy},
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+};
+use byte_string::ByteString;
+use std::convert::TryInto;
+
+/// Splits the arguments following the script into its keys and arguments,
+/// adding an error to the reply when `numkeys` is invalid
+fn keys_and_args<'a>(
+    req: &'a Request,
+    reply: &mut Response,
+) -> Result<Option<(&'a [ByteString], &'a [ByteString])>> {
+    let rest = &req.arguments()[2..];
+    let numkeys: i64 = match req.arg(1)?.parse() {
+        Ok(numkeys) => numkeys,
+        Err(_) => {
+            reply.add_reply_not_a_number();
+            return Ok(None);
+        }
+    };
+
+    if numkeys < 0 {
+        reply.add_error("ERR Number of keys can't be negative");
+        return Ok(None);
+    }
+    if numkeys as usize > rest.len() {
+        reply.add_error("ERR Number of keys can't be greater than number of args");
+        return Ok(None);
+    }
+
+    Ok(Some(rest.split_at(numkeys as usize)))
+}
+
+pub(crate) fn eval_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    let (keys, args) = match keys_and_args(req, reply)? {
+        Some(keys_and_args) => keys_and_args,
+        None => return Ok(()),
+    };
+
+    let scripting = db.scripting();
+    let mut scripting = scripting.lock().unwrap_or_else(|e| e.into_inner());
+
+    match scripting.load(req.arg(0)?) {
+        Ok(sha) => {
+            scripting.run(db, &sha, keys, args, reply);
+        }
+        Err(msg) => reply.add_error(&msg),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn evalsha_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let (keys, args) = match keys_and_args(req, reply)? {
+        Some(keys_and_args) => keys_and_args,
+        None => return Ok(()),
+    };
+
+    let scripting = db.scripting();
+    let scripting = scripting.lock().unwrap_or_else(|e| e.into_inner());
+    let sha = req.arg(0)?.to_string();
+
+    if !scripting.run(db, &sha, keys, args, reply) {
+        reply.add_error("NOSCRIPT No matching script. Please use EVAL.");
+    }
+
+    Ok(())
+}
+
+const SCRIPT_HELP: &[&str] = &[
+    "EXISTS <sha1> [<sha1> ...] -- Return information about the existence of the scripts in the script cache.",
+    "FLUSH [ASYNC|SYNC] -- Flush the Lua scripts cache. Very dangerous on replicas.",
+    "KILL -- Kill the currently executing Lua script.",
+    "LOAD <script> -- Load a script into the scripts cache, without executing it.",
+];
+
+pub(crate) fn script_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    let sub_command = req.arg(0)?.to_lowercase();
+    let args = &req.arguments()[1..];
+
+    match (sub_command.as_ref(), args) {
+        (b"help", []) => reply.add_reply_help(req.command(), SCRIPT_HELP),
+        (b"load", [body]) => {
+            let scripting = db.scripting();
+            let mut scripting = scripting.lock().unwrap_or_else(|e| e.into_inner());
+
+            match scripting.load(body) {
+                Ok(sha) => reply.add_bulk_string(sha),
+                Err(msg) => reply.add_error(&msg),
+            }
+        }
+        (b"exists", shas) if !shas.is_empty() => {
+            let scripting = db.scripting();
+            let scripting = scripting.lock().unwrap_or_else(|e| e.into_inner());
+
+            reply.add_array_len(shas.len().try_into()?);
+            for sha in shas {
+                reply.add_integer(scripting.exists(&sha.to_string()) as i64);
+            }
+        }
+        (b"flush", []) => flush_scripts(db, reply),
+        (b"flush", [mode])
+            if mode.eq_ignore_ascii_case(b"async") || mode.eq_ignore_ascii_case(b"sync") =>
+        {
+            flush_scripts(db, reply)
+        }
+        (b"flush", _) => {
+            reply.add_error("ERR SCRIPT FLUSH only support SYNC|ASYNC option");
+        }
+        // A running script is killed from the connection, as the API is busy
+        // running it, so nothing is running by the time this is reached
+        (b"kill", []) => reply.add_error("NOTBUSY No scripts in execution right now."),
+        _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
+    }
+
+    Ok(())
+}
+
+fn flush_scripts(db: &mut Database, reply: &mut Response) {
+    db.scripting()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .flush();
+    reply.add_simple_string("OK");
+}
+
+fn fcall(db: &mut Database, req: &Request, reply: &mut Response, read_only: bool) -> Result<()> {
+    let (keys, args) = match keys_and_args(req, reply)? {
+        Some(keys_and_args) => keys_and_args,
+        None => return Ok(()),
+    };
+
+    let functions = db.functions();
+    let functions = functions.lock().unwrap_or_else(|e| e.into_inner());
+    let name = req.arg(0)?.to_string();
+
+    functions.call(db, &name, keys, args, read_only, reply);
+
+    Ok(())
+}
+
+pub(crate) fn fcall_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    fcall(db, req, reply, false)
+}
+
+pub(crate) fn fcall_ro_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    fcall(db, req, reply, true)
+}
+
+const FUNCTION_HELP: &[&str] = &[
+    "LOAD [REPLACE] <FUNCTION CODE> -- Create a new library with the given library name and code.",
+    "DELETE <LIBRARY NAME> -- Delete the given library.",
+    "LIST [LIBRARYNAME PATTERN] [WITHCODE] -- Return general information on all the libraries.",
+    "KILL -- Kill the current running function.",
+    "FLUSH [ASYNC|SYNC] -- Delete all the libraries.",
+    "DUMP -- Return a serialized payload representing the current libraries, can be restored using FUNCTION RESTORE.",
+    "RESTORE <PAYLOAD> [FLUSH|APPEND|REPLACE] -- Restore the libraries represented by the given payload, it is possible to give a restore policy.",
+];
+
+pub(crate) fn function_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let sub_command = req.arg(0)?.to_lowercase();
+    let args = &req.arguments()[1..];
+    let functions = db.functions();
+    let mut functions = functions.lock().unwrap_or_else(|e| e.into_inner());
+
+    match (sub_command.as_ref(), args) {
+        (b"help", []) => reply.add_reply_help(req.command(), FUNCTION_HELP),
+        (b"load", [options @ .., code]) => {
+            let mut replace = false;
+            for option in options {
+                if option.eq_ignore_ascii_case(b"replace") {
+                    replace = true;
+                } else {
+                    reply.add_error(&format!("ERR Unknown option given: {}", option));
+                    return Ok(());
+                }
+            }
+
+            match functions.load(code, replace) {
+                Ok(name) => reply.add_bulk_string(name),
+                Err(msg) => reply.add_error(&msg),
+            }
+        }
+        (b"list", options) => function_list(&functions, options, reply)?,
+        (b"delete", [name]) => {
+            if functions.delete(&name.to_string()) {
+                reply.add_simple_string("OK");
+            } else {
+                reply.add_error("ERR Library not found");
+            }
+        }
+        (b"flush", []) => {
+            functions.flush();
+            reply.add_simple_string("OK");
+        }
+        (b"flush", [mode])
+            if mode.eq_ignore_ascii_case(b"async") || mode.eq_ignore_ascii_case(b"sync") =>
+        {
+            functions.flush();
+            reply.add_simple_string("OK");
+        }
+        (b"flush", _) => {
+            reply.add_error("ERR FUNCTION FLUSH only supports SYNC|ASYNC option");
+        }
+        (b"dump", []) => reply.add_bulk_string(functions.dump()),
+        (b"restore", [payload, policy @ ..]) if policy.len() <= 1 => {
+            let policy = match policy.first().map(|p| p.to_lowercase()) {
+                None => RestorePolicy::Append,
+                Some(p) if p.as_ref() == b"append" => RestorePolicy::Append,
+                Some(p) if p.as_ref() == b"replace" => RestorePolicy::Replace,
+                Some(p) if p.as_ref() == b"flush" => RestorePolicy::Flush,
+                Some(_) => {
+                    reply.add_error("ERR Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE.");
+                    return Ok(());
+                }
+            };
+
+            match functions.restore(payload, policy) {
+                Ok(()) => reply.add_simple_string("OK"),
+                Err(msg) => reply.add_error(&msg),
+            }
+        }
+        // As with SCRIPT KILL, a running function is killed from the
+        // connection, so nothing is running by the time this is reached
+        (b"kill", []) => reply.add_error("NOTBUSY No scripts in execution right now."),
+        _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
+    }
+
+    Ok(())
+}
+
+fn function_list(
+    functions: &Functions,
+    options: &[ByteString],
+    reply: &mut Response,
+) -> Result<()> {
+    let mut with_code = false;
+    let mut pattern = None;
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        if option.eq_ignore_ascii_case(b"withcode") {
+            with_code = true;
+        } else if option.eq_ignore_ascii_case(b"libraryname") {
+            match options.next() {
+                Some(p) => pattern = Some(p),
+                None => {
+                    reply.add_error("ERR library name argument was not given");
+                    return Ok(());
+                }
+            }
+        } else {
+            reply.add_error(&format!("ERR Unknown argument {}", option));
+            return Ok(());
+        }
+    }
+
+    let libraries: Vec<&Library> = functions
+        .libraries()
+        .filter(|library| pattern.is_none_or(|p| byte_glob::glob(p, library.name.as_bytes())))
+        .collect();
+
+    reply.add_array_len(libraries.len().try_into()?);
+    for library in libraries {
+        reply.add_array_len(if with_code { 8 } else { 6 });
+        reply.add_bulk_string("library_name");
+        reply.add_bulk_string(&library.name);
+        reply.add_bulk_string("engine");
+        reply.add_bulk_string("LUA");
+        reply.add_bulk_string("functions");
+
+        reply.add_array_len(library.functions.len().try_into()?);
+        for function in library.functions.values() {
+            reply.add_array_len(6);
+            reply.add_bulk_string("name");
+            reply.add_bulk_string(&function.name);
+            reply.add_bulk_string("description");
+            match &function.description {
+                Some(description) => reply.add_bulk_string(description),
+                None => reply.add_null_string(),
+            }
+            reply.add_bulk_string("flags");
+            reply.add_array_len(function.flags.len().try_into()?);
+            for flag in &function.flags {
+                reply.add_bulk_string(flag);
+            }
+        }
+
+        if with_code {
+            reply.add_bulk_string("library_code");
+            reply.add_bulk_string(&library.code);
+        }
+    }
+
+    Ok(())
+}
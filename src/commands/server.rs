@@ -1,27 +1,51 @@
-use super::{RedisCommand, COMMAND_TABLE};
+use super::{KeySpec, RedisCommand, Registry};
 use crate::{
-    db::Database, errors::Error, errors::Result, request::Request, response::Response,
+    db::Database,
+    errors::Error,
+    errors::Result,
+    rdb,
+    request::Request,
+    response::Response,
     response_ext::ResponseExt,
+    shutdown::ShutdownFlags,
+    stats::{bytes_to_human, used_memory, used_memory_rss},
 };
-use std::convert::TryInto;
+use byte_string::ByteString;
+use log::warn;
+use std::{
+    convert::TryInto,
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const REDIS_CLONE_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 const COMMAND_HELP: &[&str] = &[
     "(no subcommand) -- Return details about all Redis commands.",
     "COUNT -- Return the total number of commands in this Redis server.",
-    "GETKEYS -- NOT SUPPORTED.",
+    "GETKEYS <full-command> -- Return the keys from a full Redis command.",
     "INFO [command-name ...] -- Return details about multiple Redis commands.",
 ];
 
-pub(crate) fn command_command(_: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+pub(crate) fn command_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let registry = db.registry();
+
     match req.maybe_arg(0) {
         Some(sub_command) => match sub_command.to_lowercase().as_ref() {
             b"help" => reply.add_reply_help(req.command(), COMMAND_HELP),
-            b"count" => reply.add_integer(COMMAND_TABLE.len().try_into()?),
+            b"count" => reply.add_integer(registry.count().try_into()?),
+            b"getkeys" if req.arguments().len() > 1 => {
+                command_getkeys(&registry, &req.arguments()[1..], reply)?
+            }
             b"info" => {
                 let requested = &req.arguments()[1..];
                 reply.add_array_len(requested.len().try_into()?);
                 for cmd in requested {
-                    match super::lookup(cmd.as_byte_str()) {
+                    match registry.lookup(cmd.as_byte_str()) {
                         Some(cmd) => command_reply(reply, cmd),
                         None => reply.add_null_array(),
                     }
@@ -30,9 +54,9 @@ pub(crate) fn command_command(_: &mut Database, req: &Request, reply: &mut Respo
             _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
         },
         None => {
-            reply.add_array_len(COMMAND_TABLE.len().try_into()?);
+            reply.add_array_len(registry.count().try_into()?);
 
-            for cmd in COMMAND_TABLE {
+            for cmd in registry.iter() {
                 command_reply(reply, cmd);
             }
         }
@@ -42,9 +66,114 @@ pub(crate) fn command_command(_: &mut Database, req: &Request, reply: &mut Respo
 }
 
 fn command_reply(reply: &mut Response, cmd: &RedisCommand) {
-    reply.add_array_len(2);
+    reply.add_array_len(6);
     reply.add_bulk_string(cmd.name);
     reply.add_integer(cmd.arity.into());
+    reply.add_array_len(cmd.flags.len() as i64);
+    for flag in cmd.flags {
+        reply.add_simple_string(flag);
+    }
+
+    let (first, last, step) = match cmd.keys {
+        KeySpec::Range(first, last, step) => (first, last, step),
+        _ => (0, 0, 0),
+    };
+    reply.add_integer(first.into());
+    reply.add_integer(last.into());
+    reply.add_integer(step.into());
+}
+
+fn command_getkeys(registry: &Registry, argv: &[ByteString], reply: &mut Response) -> Result<()> {
+    let cmd = match registry.lookup(argv[0].as_byte_str()) {
+        Some(cmd) => cmd,
+        None => {
+            reply.add_error("ERR Invalid command specified");
+            return Ok(());
+        }
+    };
+
+    if !super::is_valid_arity(cmd.arity.into(), argv.len().try_into()?) {
+        reply.add_error("ERR Invalid number of arguments specified for command");
+        return Ok(());
+    }
+
+    let positions = cmd.key_positions(argv);
+    if positions.is_empty() {
+        reply.add_error("ERR The command has no key arguments");
+        return Ok(());
+    }
+
+    reply.add_array_len(positions.len().try_into()?);
+    for position in positions {
+        reply.add_bulk_string(&argv[position]);
+    }
+
+    Ok(())
+}
+
+const CONFIG_HELP: &[&str] = &[
+    "GET <pattern> [<pattern> ...] -- Return parameters matching the glob-like patterns and their values.",
+    "SET <parameter> <value> [<parameter> <value> ...] -- Set the configuration parameters to the given values.",
+    "RESETSTAT -- Reset statistics reported by the INFO command.",
+    "REWRITE -- Rewrite the configuration file.",
+];
+
+pub(crate) fn config_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    let sub_command = req.arg(0)?.to_lowercase();
+    let args = &req.arguments()[1..];
+
+    match (sub_command.as_ref(), args.len()) {
+        (b"help", 0) => reply.add_reply_help(req.command(), CONFIG_HELP),
+        (b"get", n) if n > 0 => {
+            let config = db.config();
+            let mut matches: Vec<(&str, String)> = vec![];
+
+            for pattern in args {
+                for (name, value) in config.get(&pattern.to_lowercase()) {
+                    if !matches.iter().any(|(n, _)| *n == name) {
+                        matches.push((name, value));
+                    }
+                }
+            }
+
+            reply.add_array_len((matches.len() * 2).try_into()?);
+            for (name, value) in matches {
+                reply.add_bulk_string(name);
+                reply.add_bulk_string(value);
+            }
+        }
+        (b"set", n) if n > 0 && n % 2 == 0 => {
+            // Apply all the changes to a copy so that either all or none of
+            // them take effect
+            let mut config = db.config().clone();
+
+            for pair in args.chunks(2) {
+                let name = pair[0].to_string();
+                if let Err(e) = config.set(&name, &pair[1].to_string()) {
+                    let msg = format!(
+                        "ERR CONFIG SET failed (possibly related to argument '{}') - {}",
+                        name, e
+                    );
+                    reply.add_error(&msg);
+                    return Ok(());
+                }
+            }
+
+            *db.config_mut() = config;
+            reply.add_simple_string("OK");
+        }
+        (b"resetstat", 0) => {
+            db.stats_mut().reset();
+            reply.add_simple_string("OK");
+        }
+        (b"rewrite", 0) => match db.config().rewrite() {
+            Ok(()) => reply.add_simple_string("OK"),
+            Err(e) => reply.add_error(&format!("ERR {}", e)),
+        },
+        _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
+    }
+
+    Ok(())
 }
 
 const DEBUG_HELP: &[&str] = &[
@@ -67,6 +196,89 @@ pub(crate) fn debug_command(_: &mut Database, req: &Request, reply: &mut Respons
     Ok(())
 }
 
+const SLOWLOG_HELP: &[&str] = &[
+    "GET [<count>] -- Return top <count> entries from the slowlog (default: 10, -1 means all).",
+    "LEN -- Return the length of the slowlog.",
+    "RESET -- Reset the slowlog.",
+];
+
+/// The number of entries SLOWLOG GET returns by default
+const SLOWLOG_GET_DEFAULT_COUNT: i64 = 10;
+
+pub(crate) fn slowlog_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let sub_command = req.arg(0)?.to_lowercase();
+    let args = &req.arguments()[1..];
+
+    match (sub_command.as_ref(), args.len()) {
+        (b"help", 0) => reply.add_reply_help(req.command(), SLOWLOG_HELP),
+        (b"len", 0) => reply.add_integer(db.slowlog_entries().len().try_into()?),
+        (b"reset", 0) => {
+            db.slowlog().reset();
+            reply.add_simple_string("OK");
+        }
+        (b"get", n) if n <= 1 => {
+            let count = match args.first().map(|c| c.parse::<i64>()) {
+                None => SLOWLOG_GET_DEFAULT_COUNT,
+                Some(Ok(count)) if count >= -1 => count,
+                Some(_) => {
+                    reply.add_error("ERR count should be greater than or equal to -1");
+                    return Ok(());
+                }
+            };
+            let entries = db.slowlog_entries();
+            let count = match count {
+                -1 => entries.len(),
+                count => entries.len().min(count.try_into()?),
+            };
+
+            reply.add_array_len(count.try_into()?);
+            for entry in entries.iter().take(count) {
+                reply.add_array_len(6);
+                reply.add_integer(entry.id.try_into()?);
+                reply.add_integer(entry.timestamp.try_into()?);
+                reply.add_integer(entry.duration.as_micros().try_into()?);
+                reply.add_array_len(entry.argv.len().try_into()?);
+                for arg in &entry.argv {
+                    reply.add_bulk_string(arg);
+                }
+                reply.add_bulk_string(
+                    entry
+                        .client_addr
+                        .as_ref()
+                        .map(|addr| addr.to_string())
+                        .unwrap_or_default(),
+                );
+                match &entry.client_name {
+                    Some(name) => reply.add_bulk_string(name),
+                    None => reply.add_bulk_string(""),
+                }
+            }
+        }
+        _ => reply.add_reply_subcommand_syntax_error(req.command(), sub_command.as_byte_str()),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn monitor_command(db: &mut Database, _: &Request, reply: &mut Response) -> Result<()> {
+    if let Some(client) = db.clients().current_mut() {
+        // As with Redis a repeated MONITOR is silently ignored
+        if client.monitor {
+            return Ok(());
+        }
+
+        client.monitor = true;
+    }
+
+    reply.add_simple_string("OK");
+
+    Ok(())
+}
+
 pub(crate) fn flushdb_command(
     db: &mut Database,
     _request: &Request,
@@ -78,3 +290,245 @@ pub(crate) fn flushdb_command(
 
     Ok(())
 }
+
+/// Shuts the server down once the command has returned, which is then done
+/// by the network, saving the dataset first unless told otherwise. On
+/// success the client gets no reply, as in Redis, its connection is closed
+/// instead.
+pub(crate) fn shutdown_command(
+    db: &mut Database,
+    req: &Request,
+    reply: &mut Response,
+) -> Result<()> {
+    let flags = match ShutdownFlags::parse(req.arguments()) {
+        Ok(Some(flags)) => flags,
+        // A shutdown never waits on replicas, so there's none to abort
+        Ok(None) => {
+            reply.add_error("ERR No shutdown in progress.");
+            return Ok(());
+        }
+        Err(()) => {
+            reply.add_error("ERR syntax error");
+            return Ok(());
+        }
+    };
+
+    // The dataset is saved here so that a failure can be reported, and again
+    // once the connections have finished the commands they are in the middle
+    // of. The shard has been lent every key for it.
+    if flags.save.unwrap_or_else(|| db.config().persists()) {
+        if let Err(e) = rdb::save(db) {
+            warn!("Error trying to save the DB: {}", e);
+            if !flags.force {
+                reply.add_error("ERR Errors trying to SHUTDOWN. Check logs.");
+                return Ok(());
+            }
+        }
+    }
+
+    warn!("User requested shutdown...");
+    db.shutdown().request(flags);
+
+    Ok(())
+}
+
+/// The sections reported when INFO is given no arguments or `default`
+const DEFAULT_INFO_SECTIONS: &[&[u8]] = &[b"server", b"clients", b"memory", b"stats", b"keyspace"];
+
+/// Every section, as reported for `all` or `everything`
+const ALL_INFO_SECTIONS: &[&[u8]] = &[
+    b"server",
+    b"clients",
+    b"memory",
+    b"stats",
+    b"commandstats",
+    b"keyspace",
+];
+
+pub(crate) fn info_command(db: &mut Database, req: &Request, reply: &mut Response) -> Result<()> {
+    let requested: Vec<ByteString> = req.arguments().iter().map(|s| s.to_lowercase()).collect();
+
+    let sections: Vec<&[u8]> = if requested.is_empty() {
+        DEFAULT_INFO_SECTIONS.to_vec()
+    } else {
+        ALL_INFO_SECTIONS
+            .iter()
+            .copied()
+            .filter(|section| {
+                requested.iter().any(|r| match r.as_ref() {
+                    b"all" | b"everything" => true,
+                    b"default" => DEFAULT_INFO_SECTIONS.contains(section),
+                    name => name == *section,
+                })
+            })
+            .collect()
+    };
+
+    let mut info = String::new();
+
+    for section in sections {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+
+        match section {
+            b"server" => info_server(db, &mut info)?,
+            b"clients" => info_clients(db, &mut info)?,
+            b"memory" => info_memory(db, &mut info)?,
+            b"stats" => info_stats(db, &mut info)?,
+            b"commandstats" => info_commandstats(db, &mut info)?,
+            b"keyspace" => info_keyspace(db, &mut info)?,
+            _ => unreachable!(),
+        }
+    }
+
+    reply.add_bulk_string(info);
+
+    Ok(())
+}
+
+fn info_server(db: &Database, info: &mut String) -> std::fmt::Result {
+    let stats = db.total_stats();
+    let uptime = stats.uptime().as_secs();
+    let server_time_usec = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_micros());
+
+    write!(info, "# Server\r\n")?;
+    write!(info, "redis_version:{}\r\n", REDIS_CLONE_VERSION)?;
+    write!(info, "redis_mode:standalone\r\n")?;
+    write!(
+        info,
+        "os:{} {}\r\n",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )?;
+    write!(info, "arch_bits:{}\r\n", std::mem::size_of::<usize>() * 8)?;
+    write!(info, "process_id:{}\r\n", std::process::id())?;
+    write!(info, "server_time_usec:{}\r\n", server_time_usec)?;
+    write!(info, "tcp_port:{}\r\n", db.config().port)?;
+    write!(info, "uptime_in_seconds:{}\r\n", uptime)?;
+    write!(info, "uptime_in_days:{}\r\n", uptime / (3600 * 24))?;
+    write!(
+        info,
+        "config_file:{}\r\n",
+        db.config()
+            .config_file()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default()
+    )?;
+
+    Ok(())
+}
+
+fn info_clients(db: &Database, info: &mut String) -> std::fmt::Result {
+    write!(info, "# Clients\r\n")?;
+    write!(info, "connected_clients:{}\r\n", db.clients().len())?;
+    write!(info, "maxclients:{}\r\n", db.config().maxclients)?;
+    let blocked = db.client_activity().values().filter(|a| a.blocked).count();
+    write!(info, "blocked_clients:{}\r\n", blocked)?;
+
+    Ok(())
+}
+
+fn info_memory(db: &Database, info: &mut String) -> std::fmt::Result {
+    let used_memory = used_memory();
+    let used_memory_peak = db.total_stats().used_memory_peak.max(used_memory);
+    let used_memory_rss = used_memory_rss();
+
+    write!(info, "# Memory\r\n")?;
+    write!(info, "used_memory:{}\r\n", used_memory)?;
+    write!(
+        info,
+        "used_memory_human:{}\r\n",
+        bytes_to_human(used_memory)
+    )?;
+    write!(info, "used_memory_rss:{}\r\n", used_memory_rss)?;
+    write!(
+        info,
+        "used_memory_rss_human:{}\r\n",
+        bytes_to_human(used_memory_rss)
+    )?;
+    write!(info, "used_memory_peak:{}\r\n", used_memory_peak)?;
+    write!(
+        info,
+        "used_memory_peak_human:{}\r\n",
+        bytes_to_human(used_memory_peak)
+    )?;
+
+    let config = db.config();
+    let maxmemory = config.maxmemory.try_into().unwrap_or(usize::MAX);
+    write!(info, "maxmemory:{}\r\n", maxmemory)?;
+    write!(info, "maxmemory_human:{}\r\n", bytes_to_human(maxmemory))?;
+    write!(
+        info,
+        "maxmemory_policy:{}\r\n",
+        config.maxmemory_policy.name()
+    )?;
+
+    Ok(())
+}
+
+fn info_stats(db: &Database, info: &mut String) -> std::fmt::Result {
+    let stats = db.total_stats();
+
+    write!(info, "# Stats\r\n")?;
+    write!(
+        info,
+        "total_connections_received:{}\r\n",
+        stats.total_connections_received
+    )?;
+    write!(
+        info,
+        "total_commands_processed:{}\r\n",
+        stats.total_commands_processed
+    )?;
+    write!(
+        info,
+        "instantaneous_ops_per_sec:{}\r\n",
+        stats.instantaneous_ops_per_sec()
+    )?;
+    write!(
+        info,
+        "rejected_connections:{}\r\n",
+        stats.rejected_connections
+    )?;
+    write!(info, "expired_keys:{}\r\n", stats.expired_keys)?;
+    write!(info, "evicted_keys:{}\r\n", stats.evicted_keys)?;
+    write!(info, "keyspace_hits:{}\r\n", stats.keyspace_hits)?;
+    write!(info, "keyspace_misses:{}\r\n", stats.keyspace_misses)?;
+
+    Ok(())
+}
+
+fn info_commandstats(db: &Database, info: &mut String) -> std::fmt::Result {
+    write!(info, "# Commandstats\r\n")?;
+
+    for (name, stats) in db.total_stats().command_stats() {
+        write!(
+            info,
+            "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+            ByteString::from(name),
+            stats.calls,
+            stats.usec,
+            stats.usec_per_call()
+        )?;
+    }
+
+    Ok(())
+}
+
+fn info_keyspace(db: &Database, info: &mut String) -> std::fmt::Result {
+    write!(info, "# Keyspace\r\n")?;
+
+    if db.keys_count() > 0 {
+        write!(
+            info,
+            "db0:keys={},expires={}\r\n",
+            db.keys_count(),
+            db.expires_count()
+        )?;
+    }
+
+    Ok(())
+}
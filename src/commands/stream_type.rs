@@ -0,0 +1,1571 @@
+use crate::{
+    blocking::BlockRequest,
+    db::{Database, RObj},
+    errors::Result,
+    notify,
+    request::Request,
+    response::Response,
+    response_ext::ResponseExt,
+    stream::{
+        AddError, AddId, ClaimOptions, ConsumerGroup, Fields, Stream, StreamId, Trim, NODE_ENTRIES,
+    },
+};
+use byte_string::ByteString;
+use std::{
+    convert::TryInto,
+    ops::Bound,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const INVALID_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
+/// The trimming options of XADD and XTRIM
+struct TrimArgs {
+    trim: Trim,
+    approx: bool,
+    limit: Option<usize>,
+}
+
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` starting at `*i`, which
+/// is left after the options. Replies with an error and returns `None` when
+/// they are invalid.
+fn parse_trim_args(
+    args: &[ByteString],
+    i: &mut usize,
+    response: &mut Response,
+) -> Option<TrimArgs> {
+    let strategy = &args[*i];
+    *i += 1;
+
+    let mut approx = false;
+    match args.get(*i) {
+        Some(arg) if arg.as_ref() == b"~" => {
+            approx = true;
+            *i += 1;
+        }
+        Some(arg) if arg.as_ref() == b"=" => *i += 1,
+        _ => (),
+    }
+
+    let threshold = match args.get(*i) {
+        Some(threshold) => threshold,
+        None => {
+            response.add_error("ERR syntax error");
+            return None;
+        }
+    };
+    *i += 1;
+
+    let trim = if strategy.eq_ignore_ascii_case(b"maxlen") {
+        match threshold.parse::<i64>() {
+            Ok(max_len) if max_len >= 0 => Trim::MaxLen(max_len as u64),
+            Ok(_) => {
+                response.add_error("ERR The MAXLEN argument must be >= 0.");
+                return None;
+            }
+            Err(_) => {
+                response.add_reply_not_a_number();
+                return None;
+            }
+        }
+    } else {
+        match StreamId::parse(threshold, 0) {
+            Some(min_id) => Trim::MinId(min_id),
+            None => {
+                response.add_error(INVALID_ID);
+                return None;
+            }
+        }
+    };
+
+    let mut limit = None;
+    if args
+        .get(*i)
+        .is_some_and(|arg| arg.eq_ignore_ascii_case(b"limit"))
+    {
+        let count = match args.get(*i + 1).map(|arg| arg.parse::<i64>()) {
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                response.add_reply_not_a_number();
+                return None;
+            }
+            None => {
+                response.add_error("ERR syntax error");
+                return None;
+            }
+        };
+        if count < 0 {
+            response.add_error("ERR The LIMIT argument must be >= 0.");
+            return None;
+        }
+        if !approx {
+            response
+                .add_error("ERR syntax error, LIMIT cannot be used without the special ~ option");
+            return None;
+        }
+        *i += 2;
+
+        // A limit of zero means no limit
+        if count > 0 {
+            limit = Some(count as usize);
+        }
+    } else if approx {
+        limit = Some(100 * NODE_ENTRIES);
+    }
+
+    Some(TrimArgs {
+        trim,
+        approx,
+        limit,
+    })
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn add_reply_entry(response: &mut Response, id: &StreamId, fields: &Fields) {
+    response.add_array_len(2);
+    response.add_bulk_string(id.to_string());
+    response.add_array_len(fields.len() as i64);
+    for value in fields {
+        response.add_bulk_string(value);
+    }
+}
+
+pub(crate) fn xadd_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let args = request.arguments();
+
+    let mut no_mkstream = false;
+    let mut trim_args = None;
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg.eq_ignore_ascii_case(b"nomkstream") {
+            no_mkstream = true;
+            i += 1;
+        } else if arg.eq_ignore_ascii_case(b"maxlen") || arg.eq_ignore_ascii_case(b"minid") {
+            if trim_args.is_some() {
+                response.add_error(
+                    "ERR syntax error, MAXLEN and MINID options at the same time are not compatible",
+                );
+                return Ok(());
+            }
+            match parse_trim_args(args, &mut i, response) {
+                Some(parsed) => trim_args = Some(parsed),
+                None => return Ok(()),
+            }
+        } else {
+            break;
+        }
+    }
+
+    let fields = args.get(i + 1..).unwrap_or(&[]);
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        response.add_reply_wrong_number_of_arguments(request.command());
+        return Ok(());
+    }
+
+    let add_id = match AddId::parse(&args[i]) {
+        Some(add_id) => add_id,
+        None => {
+            response.add_error(INVALID_ID);
+            return Ok(());
+        }
+    };
+
+    let stream = match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => Some(stream),
+        Some(_) => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+        None if no_mkstream => {
+            response.add_null_string();
+            return Ok(());
+        }
+        None => None,
+    };
+
+    let empty = Stream::new();
+    let id = match stream
+        .as_deref()
+        .unwrap_or(&empty)
+        .next_id(add_id, now_ms())
+    {
+        Ok(id) => id,
+        Err(AddError::TooSmall) => {
+            response.add_error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+            );
+            return Ok(());
+        }
+        Err(AddError::Zero) => {
+            response.add_error("ERR The ID specified in XADD must be greater than 0-0");
+            return Ok(());
+        }
+        Err(AddError::Exhausted) => {
+            response.add_error(
+                "ERR The stream has exhausted the last possible ID, unable to add more items",
+            );
+            return Ok(());
+        }
+    };
+
+    let mut evicted = 0;
+    let fields = fields.to_vec();
+    match stream {
+        Some(stream) => {
+            stream.add(id, fields);
+            if let Some(ref t) = trim_args {
+                evicted = stream.trim(t.trim, t.approx, t.limit);
+            }
+        }
+        None => {
+            let mut stream = Stream::new();
+            stream.add(id, fields);
+            if let Some(ref t) = trim_args {
+                evicted = stream.trim(t.trim, t.approx, t.limit);
+            }
+            db.insert(key.clone(), RObj::Stream(stream));
+        }
+    }
+
+    response.add_bulk_string(id.to_string());
+    db.notify_keyspace_event(notify::STREAM, "xadd", key);
+    if evicted > 0 {
+        db.notify_keyspace_event(notify::STREAM, "xtrim", key);
+    }
+    db.signal_key_ready(key);
+
+    Ok(())
+}
+
+/// Parses an interval bound of XRANGE: `-`, `+`, an ID, or an exclusive `(`ID
+fn parse_range_bound(
+    arg: &[u8],
+    is_start: bool,
+) -> std::result::Result<Bound<StreamId>, &'static str> {
+    match arg {
+        b"-" => return Ok(Bound::Included(StreamId::MIN)),
+        b"+" => return Ok(Bound::Included(StreamId::MAX)),
+        _ => (),
+    }
+
+    let missing_seq = if is_start { 0 } else { u64::MAX };
+    match arg.strip_prefix(b"(") {
+        Some(id) => {
+            let id = StreamId::parse(id, missing_seq).ok_or(INVALID_ID)?;
+            let id = if is_start { id.incr() } else { id.decr() };
+            match id {
+                Some(id) => Ok(Bound::Included(id)),
+                None if is_start => Err("ERR invalid start ID for the interval"),
+                None => Err("ERR invalid end ID for the interval"),
+            }
+        }
+        None => StreamId::parse(arg, missing_seq)
+            .map(Bound::Included)
+            .ok_or(INVALID_ID),
+    }
+}
+
+fn generic_xrange_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+    rev: bool,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let (start, end) = if rev {
+        (request.arg(2)?, request.arg(1)?)
+    } else {
+        (request.arg(1)?, request.arg(2)?)
+    };
+
+    let range = match (
+        parse_range_bound(start, true),
+        parse_range_bound(end, false),
+    ) {
+        (Ok(start), Ok(end)) => (start, end),
+        (Err(e), _) | (_, Err(e)) => {
+            response.add_error(e);
+            return Ok(());
+        }
+    };
+
+    let mut count = None;
+    match request.arguments().get(3..) {
+        Some([]) | None => (),
+        Some([option, value]) if option.eq_ignore_ascii_case(b"count") => {
+            let n: i64 = parse_or_reply_with_err!(value, response);
+            count = Some(n.max(0) as usize);
+        }
+        Some(_) => {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+    }
+
+    match db.get(key) {
+        Some(RObj::Stream(stream)) => {
+            let count = count.unwrap_or(usize::MAX);
+            let entries: Vec<_> = if rev {
+                stream.range(range).rev().take(count).collect()
+            } else {
+                stream.range(range).take(count).collect()
+            };
+
+            response.add_array_len(entries.len().try_into()?);
+            for (id, fields) in entries {
+                add_reply_entry(response, id, fields);
+            }
+        }
+        Some(_) => response.add_reply_wrong_type(),
+        None => response.add_array_len(0),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn xrange_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_xrange_command(db, request, response, false)
+}
+
+pub(crate) fn xrevrange_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_xrange_command(db, request, response, true)
+}
+
+pub(crate) fn xlen_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+
+    match db.get(key) {
+        Some(RObj::Stream(stream)) => response.add_integer(stream.len().try_into()?),
+        Some(_) => response.add_reply_wrong_type(),
+        None => response.add_integer(0),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn xdel_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+
+    let mut ids = vec![];
+    for arg in &request.arguments()[1..] {
+        match StreamId::parse(arg, 0) {
+            Some(id) => ids.push(id),
+            None => {
+                response.add_error(INVALID_ID);
+                return Ok(());
+            }
+        }
+    }
+
+    match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => {
+            let deleted = ids.iter().filter(|id| stream.delete(id)).count();
+
+            response.add_integer(deleted.try_into()?);
+            if deleted > 0 {
+                db.notify_keyspace_event(notify::STREAM, "xdel", key);
+            }
+        }
+        Some(_) => response.add_reply_wrong_type(),
+        None => response.add_integer(0),
+    }
+
+    Ok(())
+}
+
+pub(crate) fn xtrim_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let args = request.arguments();
+
+    let strategy = request.arg(1)?;
+    if !strategy.eq_ignore_ascii_case(b"maxlen") && !strategy.eq_ignore_ascii_case(b"minid") {
+        response.add_error("ERR syntax error");
+        return Ok(());
+    }
+
+    let mut i = 1;
+    let trim_args = match parse_trim_args(args, &mut i, response) {
+        Some(trim_args) => trim_args,
+        None => return Ok(()),
+    };
+    if i != args.len() {
+        response.add_error("ERR syntax error");
+        return Ok(());
+    }
+
+    match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => {
+            let evicted = stream.trim(trim_args.trim, trim_args.approx, trim_args.limit);
+
+            response.add_integer(evicted.try_into()?);
+            if evicted > 0 {
+                db.notify_keyspace_event(notify::STREAM, "xtrim", key);
+            }
+        }
+        Some(_) => response.add_reply_wrong_type(),
+        None => response.add_integer(0),
+    }
+
+    Ok(())
+}
+
+/// The ID XREAD and XREADGROUP read a stream after
+enum ReadFrom {
+    Id(StreamId),
+    /// `>`: the entries never delivered to the group
+    NewEntries,
+}
+
+fn generic_xread_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+    xreadgroup: bool,
+) -> Result<()> {
+    let args = request.arguments();
+
+    let mut group = None;
+    let mut count = None;
+    let mut block = None;
+    let mut no_ack = false;
+    let mut streams_at = None;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg.eq_ignore_ascii_case(b"streams") {
+            streams_at = Some(i + 1);
+            break;
+        }
+        if xreadgroup && arg.eq_ignore_ascii_case(b"noack") {
+            no_ack = true;
+            i += 1;
+            continue;
+        }
+
+        let value = match args.get(i + 1) {
+            Some(value) => value,
+            None => {
+                response.add_error("ERR syntax error");
+                return Ok(());
+            }
+        };
+        if arg.eq_ignore_ascii_case(b"count") {
+            let n: i64 = parse_or_reply_with_err!(value, response);
+            // A count of zero means no limit
+            count = Some(n.max(0) as usize).filter(|&n| n > 0);
+        } else if arg.eq_ignore_ascii_case(b"block") {
+            match value.parse::<i64>() {
+                Ok(ms) if ms >= 0 => block = Some(ms as u64),
+                Ok(_) => {
+                    response.add_error("ERR timeout is negative");
+                    return Ok(());
+                }
+                Err(_) => {
+                    response.add_error("ERR timeout is not an integer or out of range");
+                    return Ok(());
+                }
+            }
+        } else if arg.eq_ignore_ascii_case(b"group") {
+            if !xreadgroup {
+                response.add_error(
+                    "ERR The GROUP option is only supported by XREADGROUP. You called XREAD instead.",
+                );
+                return Ok(());
+            }
+            match args.get(i + 2) {
+                Some(consumer) => group = Some((value, consumer)),
+                None => {
+                    response.add_error("ERR syntax error");
+                    return Ok(());
+                }
+            }
+            i += 1;
+        } else {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+        i += 2;
+    }
+
+    let streams = match streams_at {
+        Some(at) => &args[at..],
+        None => {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+    };
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        let msg = format!(
+            "ERR Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+            request.command().to_lowercase()
+        );
+        response.add_error(&msg);
+        return Ok(());
+    }
+    if xreadgroup && group.is_none() {
+        response.add_error("ERR Missing GROUP option for XREADGROUP");
+        return Ok(());
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    let now = now_ms();
+
+    // `$` is resolved up front, so entries added whilst blocked are read
+    let mut read_from = Vec::with_capacity(ids.len());
+    for (key, id) in keys.iter().zip(ids) {
+        let stream = match db.get_mut(key) {
+            Some(RObj::Stream(ref mut stream)) => Some(stream),
+            Some(_) => {
+                response.add_reply_wrong_type();
+                return Ok(());
+            }
+            None => None,
+        };
+
+        let last_id = stream
+            .as_ref()
+            .map_or(StreamId::MIN, |stream| stream.last_id());
+
+        if let Some((group, consumer)) = group {
+            let created = match stream.and_then(|stream| stream.group_mut(group)) {
+                Some(group) => group.touch_consumer(consumer, now),
+                None => {
+                    let msg = format!(
+                        "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                        key, group
+                    );
+                    response.add_error(&msg);
+                    return Ok(());
+                }
+            };
+            if created {
+                db.notify_keyspace_event(notify::STREAM, "xgroup-createconsumer", key);
+            }
+        }
+
+        let from = match id.as_ref() {
+            b"$" if xreadgroup => {
+                response.add_error("ERR The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set.");
+                return Ok(());
+            }
+            b"$" => ReadFrom::Id(last_id),
+            b">" if xreadgroup => ReadFrom::NewEntries,
+            b">" => {
+                response.add_error("ERR The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option.");
+                return Ok(());
+            }
+            id => match StreamId::parse(id, 0) {
+                Some(id) => ReadFrom::Id(id),
+                None => {
+                    response.add_error(INVALID_ID);
+                    return Ok(());
+                }
+            },
+        };
+        read_from.push(from);
+    }
+
+    let mut reply = Response::new();
+    let mut served = 0;
+    for (key, from) in keys.iter().zip(&read_from) {
+        let stream = match db.get_mut(key) {
+            Some(RObj::Stream(ref mut stream)) => stream,
+            _ => continue,
+        };
+
+        match (group, from) {
+            (Some((group, consumer)), ReadFrom::NewEntries) => {
+                let entries = stream
+                    .read_group(group, consumer, count, no_ack, now)
+                    .unwrap_or_default();
+                if entries.is_empty() {
+                    continue;
+                }
+
+                reply.add_array_len(2);
+                reply.add_bulk_string(key);
+                reply.add_array_len(entries.len().try_into()?);
+                for (id, fields) in &entries {
+                    add_reply_entry(&mut reply, id, fields);
+                }
+            }
+            (Some((group, consumer)), ReadFrom::Id(after)) => {
+                let history = stream
+                    .read_group_history(group, consumer, *after, count, now)
+                    .unwrap_or_default();
+
+                reply.add_array_len(2);
+                reply.add_bulk_string(key);
+                reply.add_array_len(history.len().try_into()?);
+                for (id, fields) in &history {
+                    match fields {
+                        Some(fields) => add_reply_entry(&mut reply, id, fields),
+                        None => {
+                            reply.add_array_len(2);
+                            reply.add_bulk_string(id.to_string());
+                            reply.add_null_array();
+                        }
+                    }
+                }
+            }
+            (None, ReadFrom::Id(after)) => {
+                let entries: Vec<_> = stream
+                    .after(*after)
+                    .take(count.unwrap_or(usize::MAX))
+                    .collect();
+                if entries.is_empty() {
+                    continue;
+                }
+
+                reply.add_array_len(2);
+                reply.add_bulk_string(key);
+                reply.add_array_len(entries.len().try_into()?);
+                for (id, fields) in entries {
+                    add_reply_entry(&mut reply, id, fields);
+                }
+            }
+            (None, ReadFrom::NewEntries) => unreachable!("> is only accepted with a group"),
+        }
+        served += 1;
+    }
+
+    if served > 0 {
+        response.add_array_len(served);
+        response.append(reply);
+        return Ok(());
+    }
+
+    if let Some(ms) = block {
+        // XREADGROUP blocks only when reading new entries, which it does
+        // again as is
+        let argv = if xreadgroup {
+            None
+        } else {
+            let mut argv = request.argv().to_vec();
+            let ids_at = argv.len() - read_from.len();
+            for (arg, from) in argv[ids_at..].iter_mut().zip(&read_from) {
+                if let ReadFrom::Id(id) = from {
+                    *arg = id.to_string().into();
+                }
+            }
+            Some(argv)
+        };
+
+        let mut timeout_reply = Response::new();
+        timeout_reply.add_null_array();
+
+        let block_request = BlockRequest {
+            keys: keys.to_vec(),
+            timeout: Some(ms).filter(|&ms| ms > 0).map(Duration::from_millis),
+            argv,
+            timeout_reply,
+        };
+        if db.block_current_client(block_request) {
+            return Ok(());
+        }
+    }
+
+    response.add_null_array();
+
+    Ok(())
+}
+
+pub(crate) fn xread_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_xread_command(db, request, response, false)
+}
+
+pub(crate) fn xreadgroup_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    generic_xread_command(db, request, response, true)
+}
+
+fn add_reply_no_group(response: &mut Response, key: &ByteString, group: &ByteString) {
+    let msg = format!(
+        "NOGROUP No such consumer group '{}' for key name '{}'",
+        group, key
+    );
+    response.add_error(&msg);
+}
+
+/// Parses the ID given to XGROUP CREATE and SETID, where `$` is the last ID
+fn parse_group_id(arg: &[u8], stream: Option<&Stream>) -> Option<StreamId> {
+    if arg == b"$" {
+        return Some(stream.map_or(StreamId::MIN, |stream| stream.last_id()));
+    }
+
+    StreamId::parse(arg, 0)
+}
+
+/// Parses `[ENTRIESREAD entries-read]`, where -1 means unknown. Replies with
+/// an error and returns `None` when it is invalid.
+fn parse_entries_read(options: &[ByteString], response: &mut Response) -> Option<Option<u64>> {
+    match options {
+        [] => Some(None),
+        [option, value] if option.eq_ignore_ascii_case(b"entriesread") => {
+            match value.parse::<i64>() {
+                Ok(-1) => Some(None),
+                Ok(n) if n >= 0 => Some(Some(n as u64)),
+                Ok(_) => {
+                    response.add_error("ERR value for ENTRIESREAD must be positive or -1");
+                    None
+                }
+                Err(_) => {
+                    response.add_reply_not_a_number();
+                    None
+                }
+            }
+        }
+        _ => {
+            response.add_error("ERR syntax error");
+            None
+        }
+    }
+}
+
+const XGROUP_HELP: &[&str] = &[
+    "CREATE <key> <groupname> <id|$> [option]",
+    "    Create a new consumer group. Options are:",
+    "    * MKSTREAM",
+    "      Create the empty stream if it does not exist.",
+    "    * ENTRIESREAD entries_read",
+    "      Set the group's entries_read counter (internal use).",
+    "CREATECONSUMER <key> <groupname> <consumer>",
+    "    Create a new consumer in the specified group.",
+    "DELCONSUMER <key> <groupname> <consumer>",
+    "    Remove the specified consumer.",
+    "DESTROY <key> <groupname>",
+    "    Remove the specified group.",
+    "SETID <key> <groupname> <id|$> [ENTRIESREAD entries_read]",
+    "    Set the current group ID and entries_read counter.",
+];
+
+pub(crate) fn xgroup_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let sub_command = request.arg(0)?.to_lowercase();
+    let args = &request.arguments()[1..];
+
+    let (key, group, rest) = match (sub_command.as_ref(), args) {
+        (b"help", []) => {
+            response.add_reply_help(request.command(), XGROUP_HELP);
+            return Ok(());
+        }
+        (b"create", [key, group, rest @ ..]) if !rest.is_empty() && rest.len() <= 4 => {
+            (key, group, rest)
+        }
+        (b"setid", [key, group, rest @ ..]) if rest.len() == 1 || rest.len() == 3 => {
+            (key, group, rest)
+        }
+        (b"destroy", [key, group]) => (key, group, &[][..]),
+        (b"createconsumer", [key, group, consumer]) | (b"delconsumer", [key, group, consumer]) => {
+            (key, group, std::slice::from_ref(consumer))
+        }
+        _ => {
+            response
+                .add_reply_subcommand_syntax_error(request.command(), sub_command.as_byte_str());
+            return Ok(());
+        }
+    };
+
+    let mut mkstream = false;
+    let mut options = rest.get(1..).unwrap_or(&[]);
+    if sub_command.as_ref() == b"create"
+        && options
+            .first()
+            .is_some_and(|option| option.eq_ignore_ascii_case(b"mkstream"))
+    {
+        mkstream = true;
+        options = &options[1..];
+    }
+
+    let stream = match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => Some(stream),
+        Some(_) => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+        None => None,
+    };
+    if stream.is_none() && !mkstream {
+        response.add_error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.");
+        return Ok(());
+    }
+
+    match sub_command.as_ref() {
+        b"create" => {
+            let id = match parse_group_id(&rest[0], stream.as_deref()) {
+                Some(id) => id,
+                None => {
+                    response.add_error(INVALID_ID);
+                    return Ok(());
+                }
+            };
+            let entries_read = match parse_entries_read(options, response) {
+                Some(entries_read) => entries_read,
+                None => return Ok(()),
+            };
+
+            let created = match stream {
+                Some(stream) => stream.create_group(group, id, entries_read),
+                None => {
+                    let mut stream = Stream::new();
+                    stream.create_group(group, id, entries_read);
+                    db.insert(key.clone(), RObj::Stream(stream));
+                    true
+                }
+            };
+
+            if created {
+                response.add_simple_string("OK");
+                db.notify_keyspace_event(notify::STREAM, "xgroup-create", key);
+            } else {
+                response.add_error("BUSYGROUP Consumer Group name already exists");
+            }
+        }
+        b"setid" => {
+            let stream = stream.expect("the key exists");
+            let id = match parse_group_id(&rest[0], Some(stream)) {
+                Some(id) => id,
+                None => {
+                    response.add_error(INVALID_ID);
+                    return Ok(());
+                }
+            };
+            let entries_read = match parse_entries_read(options, response) {
+                Some(entries_read) => entries_read,
+                None => return Ok(()),
+            };
+
+            match stream.group_mut(group) {
+                Some(group) => {
+                    group.set_last_id(id, entries_read);
+                    response.add_simple_string("OK");
+                    db.notify_keyspace_event(notify::STREAM, "xgroup-setid", key);
+                }
+                None => add_reply_no_group(response, key, group),
+            }
+        }
+        b"destroy" => {
+            let stream = stream.expect("the key exists");
+            if stream.destroy_group(group) {
+                response.add_integer(1);
+                db.notify_keyspace_event(notify::STREAM, "xgroup-destroy", key);
+                // Clients blocked reading from the group find it gone
+                db.signal_key_ready(key);
+            } else {
+                response.add_integer(0);
+            }
+        }
+        b"createconsumer" => {
+            let stream = stream.expect("the key exists");
+            match stream.group_mut(group) {
+                Some(group) => {
+                    let created = group.create_consumer(&rest[0], now_ms());
+                    response.add_integer(created as i64);
+                    if created {
+                        db.notify_keyspace_event(notify::STREAM, "xgroup-createconsumer", key);
+                    }
+                }
+                None => add_reply_no_group(response, key, group),
+            }
+        }
+        _ => {
+            let stream = stream.expect("the key exists");
+            match stream.group_mut(group) {
+                Some(group) => match group.delete_consumer(&rest[0]) {
+                    Some(pending) => {
+                        response.add_integer(pending.try_into()?);
+                        db.notify_keyspace_event(notify::STREAM, "xgroup-delconsumer", key);
+                    }
+                    None => response.add_integer(0),
+                },
+                None => add_reply_no_group(response, key, group),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn xack_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let group = request.arg(1)?;
+
+    let mut ids = vec![];
+    for arg in &request.arguments()[2..] {
+        match StreamId::parse(arg, 0) {
+            Some(id) => ids.push(id),
+            None => {
+                response.add_error(INVALID_ID);
+                return Ok(());
+            }
+        }
+    }
+
+    match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => match stream.group_mut(group) {
+            Some(group) => {
+                let acked = ids.iter().filter(|id| group.ack(id)).count();
+                response.add_integer(acked.try_into()?);
+            }
+            None => response.add_integer(0),
+        },
+        Some(_) => response.add_reply_wrong_type(),
+        None => response.add_integer(0),
+    }
+
+    Ok(())
+}
+
+fn add_reply_no_key_or_group(response: &mut Response, key: &ByteString, group: &ByteString) {
+    let msg = format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        key, group
+    );
+    response.add_error(&msg);
+}
+
+pub(crate) fn xpending_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let group_name = request.arg(1)?;
+    let mut args = &request.arguments()[2..];
+
+    let mut min_idle = None;
+    if args
+        .first()
+        .is_some_and(|arg| arg.eq_ignore_ascii_case(b"idle"))
+    {
+        match args.get(1).map(|arg| arg.parse::<i64>()) {
+            Some(Ok(idle)) => min_idle = Some(idle.max(0) as u64),
+            Some(Err(_)) => {
+                response.add_reply_not_a_number();
+                return Ok(());
+            }
+            None => {
+                response.add_error("ERR syntax error");
+                return Ok(());
+            }
+        }
+        args = &args[2..];
+    }
+
+    let extended = match args {
+        [] if min_idle.is_none() => None,
+        [start, end, count] => Some((start, end, count, None)),
+        [start, end, count, consumer] => Some((start, end, count, Some(consumer))),
+        _ => {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+    };
+
+    let extended = match extended {
+        Some((start, end, count, consumer)) => {
+            let range = match (
+                parse_range_bound(start, true),
+                parse_range_bound(end, false),
+            ) {
+                (Ok(start), Ok(end)) => (start, end),
+                (Err(e), _) | (_, Err(e)) => {
+                    response.add_error(e);
+                    return Ok(());
+                }
+            };
+            let count: i64 = parse_or_reply_with_err!(count, response);
+            Some((range, count.max(0) as usize, consumer))
+        }
+        None => None,
+    };
+
+    let stream = match db.get(key) {
+        Some(RObj::Stream(stream)) => Some(stream),
+        Some(_) => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+        None => None,
+    };
+    let group = match stream.and_then(|stream| stream.group(group_name)) {
+        Some(group) => group,
+        None => {
+            add_reply_no_key_or_group(response, key, group_name);
+            return Ok(());
+        }
+    };
+    let pending = group.pending();
+
+    match extended {
+        None if pending.is_empty() => {
+            response.add_array_len(4);
+            response.add_integer(0);
+            response.add_null_string();
+            response.add_null_string();
+            response.add_null_array();
+        }
+        None => {
+            response.add_array_len(4);
+            response.add_integer(pending.len().try_into()?);
+            for id in pending
+                .keys()
+                .next()
+                .into_iter()
+                .chain(pending.keys().next_back())
+            {
+                response.add_bulk_string(id.to_string());
+            }
+
+            let consumers: Vec<_> = group
+                .consumers()
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending().is_empty())
+                .collect();
+            response.add_array_len(consumers.len().try_into()?);
+            for (name, consumer) in consumers {
+                response.add_array_len(2);
+                response.add_bulk_string(name);
+                response.add_bulk_string(consumer.pending().len().to_string());
+            }
+        }
+        Some((range, count, consumer)) => {
+            let now = now_ms();
+            let entries: Vec<_> = pending
+                .range(range)
+                .filter(|(_, entry)| consumer.is_none_or(|consumer| entry.consumer == *consumer))
+                .filter(|(_, entry)| {
+                    min_idle
+                        .is_none_or(|min_idle| now.saturating_sub(entry.delivery_time) >= min_idle)
+                })
+                .take(count)
+                .collect();
+
+            response.add_array_len(entries.len().try_into()?);
+            for (id, entry) in entries {
+                response.add_array_len(4);
+                response.add_bulk_string(id.to_string());
+                response.add_bulk_string(&entry.consumer);
+                response.add_integer(now.saturating_sub(entry.delivery_time).try_into()?);
+                response.add_integer(entry.delivery_count.try_into()?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn add_reply_claimed(response: &mut Response, claimed: &[(StreamId, Fields)], just_id: bool) {
+    response.add_array_len(claimed.len() as i64);
+    for (id, fields) in claimed {
+        if just_id {
+            response.add_bulk_string(id.to_string());
+        } else {
+            add_reply_entry(response, id, fields);
+        }
+    }
+}
+
+/// Parses the minimum idle time of XCLAIM and XAUTOCLAIM
+fn parse_min_idle(request: &Request, response: &mut Response) -> Result<Option<u64>> {
+    match request.arg(3)?.parse::<i64>() {
+        Ok(min_idle) => Ok(Some(min_idle.max(0) as u64)),
+        Err(_) => {
+            let msg = format!(
+                "ERR Invalid min-idle-time argument for {}",
+                request.command().to_uppercase()
+            );
+            response.add_error(&msg);
+            Ok(None)
+        }
+    }
+}
+
+pub(crate) fn xclaim_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let group = request.arg(1)?;
+    let consumer = request.arg(2)?;
+    let args = &request.arguments()[4..];
+
+    let min_idle = match parse_min_idle(request, response)? {
+        Some(min_idle) => min_idle,
+        None => return Ok(()),
+    };
+
+    let mut ids = vec![];
+    let mut i = 0;
+    while let Some(id) = args.get(i).and_then(|arg| StreamId::parse(arg, 0)) {
+        ids.push(id);
+        i += 1;
+    }
+
+    let now = now_ms();
+    let mut options = ClaimOptions {
+        min_idle,
+        ..ClaimOptions::default()
+    };
+    let mut last_id = None;
+    while i < args.len() {
+        let option = &args[i];
+        let value = args.get(i + 1);
+        i += 1;
+
+        if option.eq_ignore_ascii_case(b"force") {
+            options.force = true;
+            continue;
+        }
+        if option.eq_ignore_ascii_case(b"justid") {
+            options.just_id = true;
+            continue;
+        }
+
+        let value = match value {
+            Some(value) => value,
+            None => {
+                response.add_error(&format!("ERR Unrecognized XCLAIM option '{}'", option));
+                return Ok(());
+            }
+        };
+        i += 1;
+
+        if option.eq_ignore_ascii_case(b"idle") {
+            let idle: i64 = match value.parse() {
+                Ok(idle) => idle,
+                Err(_) => {
+                    response.add_error("ERR Invalid IDLE option argument for XCLAIM");
+                    return Ok(());
+                }
+            };
+            options.delivery_time = Some(now.saturating_sub(idle.max(0) as u64));
+        } else if option.eq_ignore_ascii_case(b"time") {
+            let time: i64 = match value.parse() {
+                Ok(time) => time,
+                Err(_) => {
+                    response.add_error("ERR Invalid TIME option argument for XCLAIM");
+                    return Ok(());
+                }
+            };
+            options.delivery_time = Some(time.max(0) as u64);
+        } else if option.eq_ignore_ascii_case(b"retrycount") {
+            let retry_count: i64 = match value.parse() {
+                Ok(retry_count) => retry_count,
+                Err(_) => {
+                    response.add_error("ERR Invalid RETRYCOUNT option argument for XCLAIM");
+                    return Ok(());
+                }
+            };
+            options.retry_count = Some(retry_count.max(0) as u64);
+        } else if option.eq_ignore_ascii_case(b"lastid") {
+            match StreamId::parse(value, 0) {
+                Some(id) => last_id = Some(id),
+                None => {
+                    response.add_error(INVALID_ID);
+                    return Ok(());
+                }
+            }
+        } else {
+            response.add_error(&format!("ERR Unrecognized XCLAIM option '{}'", option));
+            return Ok(());
+        }
+    }
+    // A delivery time in the future would make for a negative idle time
+    options.delivery_time = options.delivery_time.map(|time| time.min(now));
+
+    let stream = match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => Some(stream),
+        Some(_) => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+        None => None,
+    };
+    let stream = match stream {
+        Some(stream) if stream.group(group).is_some() => stream,
+        _ => {
+            add_reply_no_key_or_group(response, key, group);
+            return Ok(());
+        }
+    };
+
+    if let Some(last_id) = last_id {
+        let group = stream.group_mut(group).expect("the group exists");
+        if last_id > group.last_id() {
+            let entries_read = group.entries_read();
+            group.set_last_id(last_id, entries_read);
+        }
+    }
+
+    let claimed = stream
+        .claim(group, consumer, &ids, &options, now)
+        .unwrap_or_default();
+    add_reply_claimed(response, &claimed, options.just_id);
+
+    Ok(())
+}
+
+pub(crate) fn xautoclaim_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let key = request.arg(0)?;
+    let group = request.arg(1)?;
+    let consumer = request.arg(2)?;
+
+    let min_idle = match parse_min_idle(request, response)? {
+        Some(min_idle) => min_idle,
+        None => return Ok(()),
+    };
+    let start = match parse_range_bound(request.arg(4)?, true) {
+        Ok(Bound::Included(start)) => start,
+        Ok(_) => StreamId::MIN,
+        Err(e) => {
+            response.add_error(e);
+            return Ok(());
+        }
+    };
+
+    let mut options = ClaimOptions {
+        min_idle,
+        ..ClaimOptions::default()
+    };
+    let mut count = 100;
+    let mut args = &request.arguments()[5..];
+    while let Some(option) = args.first() {
+        if option.eq_ignore_ascii_case(b"justid") {
+            options.just_id = true;
+            args = &args[1..];
+        } else if option.eq_ignore_ascii_case(b"count") && args.len() > 1 {
+            match args[1].parse::<i64>() {
+                Ok(n) if n >= 1 => count = n as usize,
+                _ => {
+                    response.add_error("ERR COUNT must be > 0");
+                    return Ok(());
+                }
+            }
+            args = &args[2..];
+        } else {
+            response.add_error("ERR syntax error");
+            return Ok(());
+        }
+    }
+
+    let claimed = match db.get_mut(key) {
+        Some(RObj::Stream(ref mut stream)) => {
+            stream.auto_claim(group, consumer, start, count, &options, now_ms())
+        }
+        Some(_) => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+        None => None,
+    };
+    let claimed = match claimed {
+        Some(claimed) => claimed,
+        None => {
+            add_reply_no_key_or_group(response, key, group);
+            return Ok(());
+        }
+    };
+
+    response.add_array_len(3);
+    response.add_bulk_string(claimed.next.to_string());
+    add_reply_claimed(response, &claimed.claimed, options.just_id);
+    response.add_array_len(claimed.deleted.len().try_into()?);
+    for id in &claimed.deleted {
+        response.add_bulk_string(id.to_string());
+    }
+
+    Ok(())
+}
+
+const XINFO_HELP: &[&str] = &[
+    "CONSUMERS <key> <groupname>",
+    "    Show consumers of <groupname>.",
+    "GROUPS <key>",
+    "    Show the stream consumer groups.",
+    "STREAM <key> [FULL [COUNT <count>]",
+    "    Show information about the stream.",
+];
+
+/// Replies with an integer, or null when it is unknown
+fn add_reply_optional_integer(response: &mut Response, value: Option<u64>) -> Result<()> {
+    match value {
+        Some(value) => response.add_integer(value.try_into()?),
+        None => response.add_null_string(),
+    }
+
+    Ok(())
+}
+
+fn add_reply_optional_entry(response: &mut Response, entry: Option<(&StreamId, &Fields)>) {
+    match entry {
+        Some((id, fields)) => add_reply_entry(response, id, fields),
+        None => response.add_null_string(),
+    }
+}
+
+/// Replies with the fields XINFO STREAM has in common with its FULL form.
+/// The entries are reported as if they were stored in nodes of
+/// [`NODE_ENTRIES`] entries, like Redis's radix tree.
+fn add_reply_stream_summary(response: &mut Response, stream: &Stream) -> Result<()> {
+    let nodes = stream.len().div_ceil(NODE_ENTRIES);
+
+    response.add_bulk_string("length");
+    response.add_integer(stream.len().try_into()?);
+    response.add_bulk_string("radix-tree-keys");
+    response.add_integer(nodes.try_into()?);
+    response.add_bulk_string("radix-tree-nodes");
+    response.add_integer((nodes + 1).try_into()?);
+    response.add_bulk_string("last-generated-id");
+    response.add_bulk_string(stream.last_id().to_string());
+    response.add_bulk_string("max-deleted-entry-id");
+    response.add_bulk_string(stream.max_deleted_id().to_string());
+    response.add_bulk_string("entries-added");
+    response.add_integer(stream.entries_added().try_into()?);
+    response.add_bulk_string("recorded-first-entry-id");
+    response.add_bulk_string(stream.first_id().to_string());
+
+    Ok(())
+}
+
+fn xinfo_stream(response: &mut Response, stream: &Stream, full: Option<usize>) -> Result<()> {
+    let count = match full {
+        Some(count) => count,
+        None => {
+            response.add_array_len(20);
+            add_reply_stream_summary(response, stream)?;
+            response.add_bulk_string("groups");
+            response.add_integer(stream.groups().len().try_into()?);
+            response.add_bulk_string("first-entry");
+            add_reply_optional_entry(response, stream.first_entry());
+            response.add_bulk_string("last-entry");
+            add_reply_optional_entry(response, stream.last_entry());
+            return Ok(());
+        }
+    };
+    // A count of zero means no limit
+    let count = if count == 0 { usize::MAX } else { count };
+
+    response.add_array_len(18);
+    add_reply_stream_summary(response, stream)?;
+
+    response.add_bulk_string("entries");
+    let entries: Vec<_> = stream.range(..).take(count).collect();
+    response.add_array_len(entries.len().try_into()?);
+    for (id, fields) in entries {
+        add_reply_entry(response, id, fields);
+    }
+
+    response.add_bulk_string("groups");
+    response.add_array_len(stream.groups().len().try_into()?);
+    for (name, group) in stream.groups() {
+        response.add_array_len(14);
+        response.add_bulk_string("name");
+        response.add_bulk_string(name);
+        response.add_bulk_string("last-delivered-id");
+        response.add_bulk_string(group.last_id().to_string());
+        response.add_bulk_string("entries-read");
+        add_reply_optional_integer(response, group.entries_read())?;
+        response.add_bulk_string("lag");
+        add_reply_optional_integer(response, stream.lag(group))?;
+        response.add_bulk_string("pel-count");
+        response.add_integer(group.pending().len().try_into()?);
+
+        response.add_bulk_string("pending");
+        let pending: Vec<_> = group.pending().iter().take(count).collect();
+        response.add_array_len(pending.len().try_into()?);
+        for (id, entry) in pending {
+            response.add_array_len(4);
+            response.add_bulk_string(id.to_string());
+            response.add_bulk_string(&entry.consumer);
+            response.add_integer(entry.delivery_time.try_into()?);
+            response.add_integer(entry.delivery_count.try_into()?);
+        }
+
+        response.add_bulk_string("consumers");
+        response.add_array_len(group.consumers().len().try_into()?);
+        for (name, consumer) in group.consumers() {
+            response.add_array_len(10);
+            response.add_bulk_string("name");
+            response.add_bulk_string(name);
+            response.add_bulk_string("seen-time");
+            response.add_integer(consumer.seen_time.try_into()?);
+            response.add_bulk_string("active-time");
+            match consumer.active_time {
+                Some(time) => response.add_integer(time.try_into()?),
+                None => response.add_integer(-1),
+            }
+            response.add_bulk_string("pel-count");
+            response.add_integer(consumer.pending().len().try_into()?);
+
+            response.add_bulk_string("pending");
+            let pending: Vec<_> = consumer.pending().iter().take(count).collect();
+            response.add_array_len(pending.len().try_into()?);
+            for id in pending {
+                let entry = &group.pending()[id];
+                response.add_array_len(3);
+                response.add_bulk_string(id.to_string());
+                response.add_integer(entry.delivery_time.try_into()?);
+                response.add_integer(entry.delivery_count.try_into()?);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn xinfo_groups(response: &mut Response, stream: &Stream) -> Result<()> {
+    response.add_array_len(stream.groups().len().try_into()?);
+    for (name, group) in stream.groups() {
+        response.add_array_len(12);
+        response.add_bulk_string("name");
+        response.add_bulk_string(name);
+        response.add_bulk_string("consumers");
+        response.add_integer(group.consumers().len().try_into()?);
+        response.add_bulk_string("pending");
+        response.add_integer(group.pending().len().try_into()?);
+        response.add_bulk_string("last-delivered-id");
+        response.add_bulk_string(group.last_id().to_string());
+        response.add_bulk_string("entries-read");
+        add_reply_optional_integer(response, group.entries_read())?;
+        response.add_bulk_string("lag");
+        add_reply_optional_integer(response, stream.lag(group))?;
+    }
+
+    Ok(())
+}
+
+fn xinfo_consumers(response: &mut Response, group: &ConsumerGroup) -> Result<()> {
+    let now = now_ms();
+
+    response.add_array_len(group.consumers().len().try_into()?);
+    for (name, consumer) in group.consumers() {
+        response.add_array_len(8);
+        response.add_bulk_string("name");
+        response.add_bulk_string(name);
+        response.add_bulk_string("pending");
+        response.add_integer(consumer.pending().len().try_into()?);
+        response.add_bulk_string("idle");
+        response.add_integer(now.saturating_sub(consumer.seen_time).try_into()?);
+        response.add_bulk_string("inactive");
+        match consumer.active_time {
+            Some(time) => response.add_integer(now.saturating_sub(time).try_into()?),
+            None => response.add_integer(-1),
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn xinfo_command(
+    db: &mut Database,
+    request: &Request,
+    response: &mut Response,
+) -> Result<()> {
+    let sub_command = request.arg(0)?.to_lowercase();
+    let args = &request.arguments()[1..];
+
+    let (key, rest) = match (sub_command.as_ref(), args) {
+        (b"help", []) => {
+            response.add_reply_help(request.command(), XINFO_HELP);
+            return Ok(());
+        }
+        (b"stream", [key, rest @ ..]) | (b"consumers", [key, rest @ ..]) => (key, rest),
+        (b"groups", [key]) => (key, &[][..]),
+        _ => {
+            response
+                .add_reply_subcommand_syntax_error(request.command(), sub_command.as_byte_str());
+            return Ok(());
+        }
+    };
+
+    // Check the arguments before the key, as Redis does
+    let full = match (sub_command.as_ref(), rest) {
+        (b"stream", []) => None,
+        (b"stream", [full]) if full.eq_ignore_ascii_case(b"full") => Some(10),
+        (b"stream", [full, option, count])
+            if full.eq_ignore_ascii_case(b"full") && option.eq_ignore_ascii_case(b"count") =>
+        {
+            let count: i64 = parse_or_reply_with_err!(count, response);
+            Some(count.max(0) as usize)
+        }
+        (b"consumers", [_]) | (b"groups", []) => None,
+        _ => {
+            response
+                .add_reply_subcommand_syntax_error(request.command(), sub_command.as_byte_str());
+            return Ok(());
+        }
+    };
+
+    let stream = match db.get(key) {
+        Some(RObj::Stream(stream)) => stream,
+        Some(_) => {
+            response.add_reply_wrong_type();
+            return Ok(());
+        }
+        None => {
+            response.add_error("ERR no such key");
+            return Ok(());
+        }
+    };
+
+    match sub_command.as_ref() {
+        b"stream" => xinfo_stream(response, stream, full)?,
+        b"groups" => xinfo_groups(response, stream)?,
+        _ => match stream.group(&rest[0]) {
+            Some(group) => xinfo_consumers(response, group)?,
+            None => add_reply_no_group(response, key, &rest[0]),
+        },
+    }
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use crate::{
     db::{Database, RObj},
     errors::Result,
+    notify,
     request::Request,
     response::Response,
     response_ext::ResponseExt,
@@ -49,17 +50,20 @@ pub(crate) fn set_command(
         args = &args[1..];
     }
 
-    let is_existing = db.get(key).is_some();
+    let is_existing = db.exists(key);
     if nx && is_existing || xx && !is_existing {
         response.add_null_string();
     } else {
         db.insert(key.clone(), value.clone().into());
+        db.notify_keyspace_event(notify::STRING, "set", key);
         response.add_simple_string("OK");
     }
 
     if let Some(millis) = maybe_ttl {
         let expires_at = Instant::now() + Duration::from_millis(millis.try_into()?);
-        db.set_expire(key, expires_at);
+        if db.set_expire(key, expires_at) {
+            db.notify_keyspace_event(notify::GENERIC, "expire", key);
+        }
     }
 
     Ok(())
@@ -102,6 +106,7 @@ pub(crate) fn mset_command(
 
     for [key, value] in pairs {
         db.insert(key.clone(), value.clone().into());
+        db.notify_keyspace_event(notify::STRING, "set", key);
     }
 
     response.add_simple_string("OK");
@@ -186,6 +191,7 @@ fn general_incr(
         Some(RObj::Int(old_value)) => {
             if let Some(new_value) = old_value.checked_add(increment) {
                 db.insert(key.to_owned(), new_value.into());
+                db.notify_keyspace_event(notify::STRING, "incrby", key);
                 response.add_integer(new_value);
             } else {
                 response.add_error("ERR increment or decrement would overflow")
@@ -197,6 +203,7 @@ fn general_incr(
         Some(_) => response.add_reply_wrong_type(),
         None => {
             db.insert(key.to_owned(), increment.into());
+            db.notify_keyspace_event(notify::STRING, "incrby", key);
             response.add_integer(increment);
         }
     }
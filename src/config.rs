@@ -0,0 +1,1284 @@
+//! Server configuration, loaded from a `redis.conf` style file and/or command
+//! line arguments. See: https://redis.io/topics/config
+
+use crate::{
+    listpack::ListpackLimits,
+    notify,
+    output_buffer::{ClientClass, OutputLimits},
+    tls::{self, TlsAuthClients, TlsProtocol},
+};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// More shards than cores only adds contention, this is a sanity limit
+const MAX_SHARDS: usize = 1024;
+
+pub type ConfigResult<T> = std::result::Result<T, ConfigError>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigError(String);
+
+impl std::error::Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<&str> for ConfigError {
+    fn from(other: &str) -> Self {
+        Self(other.to_owned())
+    }
+}
+
+impl From<String> for ConfigError {
+    fn from(other: String) -> Self {
+        Self(other)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaxmemoryPolicy {
+    NoEviction,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "noeviction" => Some(Self::NoEviction),
+            "allkeys-random" => Some(Self::AllKeysRandom),
+            "volatile-random" => Some(Self::VolatileRandom),
+            "volatile-ttl" => Some(Self::VolatileTtl),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub bind: Vec<String>,
+    pub port: u16,
+    /// The length of the queue of connections waiting to be accepted
+    pub tcp_backlog: u32,
+    /// Seconds a TCP connection may be idle before keepalive probes are sent,
+    /// none are when zero
+    pub tcp_keepalive: u64,
+    /// Only accept TCP connections from the loopback interface, as there is
+    /// no authentication
+    pub protected_mode: bool,
+    /// Connections beyond this many are refused
+    pub maxclients: usize,
+    /// Seconds after which an idle client is disconnected, never when zero
+    pub timeout: u64,
+    /// The path of a Unix socket to listen on as well as TCP
+    pub unixsocket: Option<PathBuf>,
+    /// The permissions of the Unix socket, left to the umask when zero
+    pub unixsocketperm: u32,
+    /// The port to accept TLS connections on, none are accepted when zero
+    pub tls_port: u16,
+    pub tls_cert_file: Option<PathBuf>,
+    pub tls_key_file: Option<PathBuf>,
+    /// The CA that client certificates are verified against
+    pub tls_ca_cert_file: Option<PathBuf>,
+    pub tls_auth_clients: TlsAuthClients,
+    /// The versions of TLS accepted, all those supported when empty
+    pub tls_protocols: Vec<TlsProtocol>,
+    /// The TLSv1.2 cipher suites accepted, all those supported when empty
+    pub tls_ciphers: Vec<String>,
+    /// The TLSv1.3 cipher suites accepted, all those supported when empty
+    pub tls_ciphersuites: Vec<String>,
+    /// The directory the RDB file is kept in
+    pub dir: PathBuf,
+    /// The name of the RDB file, which can't be a path
+    pub dbfilename: String,
+    /// Save points, as seconds and the changes made in them. The clone
+    /// doesn't save in the background, any set only turn persistence on.
+    pub save: Vec<(u64, u64)>,
+    pub maxmemory: u64,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    pub proto_max_bulk_len: u64,
+    pub proto_max_multibulk_len: u64,
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: u64,
+    /// The `notify::*` classes of keyspace events to publish
+    pub notify_keyspace_events: u32,
+    /// Milliseconds a script may run before other clients are answered BUSY
+    pub lua_time_limit: u64,
+    /// Size in bytes past which a sparse HyperLogLog is made dense
+    pub hll_sparse_max_bytes: usize,
+    /// Fields in a hash before it is converted from a listpack
+    pub hash_max_listpack_entries: usize,
+    /// Length of a hash field or value before it is converted from a listpack
+    pub hash_max_listpack_value: usize,
+    /// Entries in a list before it is converted from a listpack when
+    /// positive, or its size from -1 for 4kb to -5 for 64kb when negative
+    pub list_max_listpack_size: i64,
+    /// Members of a sorted set before it is converted from a listpack
+    pub zset_max_listpack_entries: usize,
+    /// Length of a sorted set member before it is converted from a listpack
+    pub zset_max_listpack_value: usize,
+    /// Threads the keyspace is split across by key hash, each running the
+    /// commands for its keys
+    pub shards: usize,
+    /// The output buffer limits of each `ClientClass`, by its index
+    pub client_output_buffer_limit: [OutputLimits; 3],
+    config_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1".to_owned()],
+            port: 8080,
+            tcp_backlog: 511,
+            tcp_keepalive: 300,
+            protected_mode: true,
+            maxclients: 10000,
+            timeout: 0,
+            unixsocket: None,
+            unixsocketperm: 0,
+            tls_port: 0,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: TlsAuthClients::Yes,
+            tls_protocols: vec![],
+            tls_ciphers: vec![],
+            tls_ciphersuites: vec![],
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_owned(),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            proto_max_multibulk_len: 1024 * 1024,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            notify_keyspace_events: 0,
+            lua_time_limit: 5000,
+            hll_sparse_max_bytes: 3000,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            list_max_listpack_size: -2,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+            shards: 1,
+            client_output_buffer_limit: [
+                OutputLimits::new(0, 0, 0),
+                OutputLimits::new(256 * 1024 * 1024, 64 * 1024 * 1024, 60),
+                OutputLimits::new(32 * 1024 * 1024, 8 * 1024 * 1024, 60),
+            ],
+            config_file: None,
+        }
+    }
+}
+
+/// Describes a single configuration parameter, how to read it, how to set it
+/// and whether it can be changed whilst the server is running.
+struct ConfigParam {
+    name: &'static str,
+    modifiable: bool,
+    get: fn(&Config) -> String,
+    set: fn(&mut Config, &[&str]) -> ConfigResult<()>,
+}
+
+static CONFIG_TABLE: &[ConfigParam] = &[
+    ConfigParam {
+        name: "bind",
+        modifiable: false,
+        get: |c| c.bind.join(" "),
+        set: |c, args| {
+            if args.is_empty() {
+                return Err("wrong number of arguments".into());
+            }
+            c.bind = args.iter().map(|a| (*a).to_owned()).collect();
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "port",
+        modifiable: false,
+        get: |c| c.port.to_string(),
+        set: |c, args| {
+            c.port = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tcp-backlog",
+        modifiable: false,
+        get: |c| c.tcp_backlog.to_string(),
+        set: |c, args| {
+            c.tcp_backlog = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tcp-keepalive",
+        modifiable: true,
+        get: |c| c.tcp_keepalive.to_string(),
+        set: |c, args| {
+            c.tcp_keepalive = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "protected-mode",
+        modifiable: true,
+        get: |c| yes_no(c.protected_mode),
+        set: |c, args| {
+            c.protected_mode = yes_no_arg(args)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "maxclients",
+        modifiable: true,
+        get: |c| c.maxclients.to_string(),
+        set: |c, args| {
+            c.maxclients = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            if c.maxclients == 0 {
+                return Err("argument must be greater than 0".into());
+            }
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "timeout",
+        modifiable: true,
+        get: |c| c.timeout.to_string(),
+        set: |c, args| {
+            c.timeout = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "unixsocket",
+        modifiable: false,
+        get: |c| path_value(&c.unixsocket),
+        set: |c, args| {
+            c.unixsocket = path_arg(args)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "unixsocketperm",
+        modifiable: false,
+        get: |c| format!("{:o}", c.unixsocketperm),
+        set: |c, args| {
+            c.unixsocketperm = u32::from_str_radix(single_arg(args)?, 8)
+                .ok()
+                .filter(|perm| *perm <= 0o777)
+                .ok_or("argument must be an octal number between 0 and 777")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-port",
+        modifiable: false,
+        get: |c| c.tls_port.to_string(),
+        set: |c, args| {
+            c.tls_port = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-cert-file",
+        modifiable: false,
+        get: |c| path_value(&c.tls_cert_file),
+        set: |c, args| {
+            c.tls_cert_file = path_arg(args)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-key-file",
+        modifiable: false,
+        get: |c| path_value(&c.tls_key_file),
+        set: |c, args| {
+            c.tls_key_file = path_arg(args)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-ca-cert-file",
+        modifiable: false,
+        get: |c| path_value(&c.tls_ca_cert_file),
+        set: |c, args| {
+            c.tls_ca_cert_file = path_arg(args)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-auth-clients",
+        modifiable: false,
+        get: |c| c.tls_auth_clients.name().to_owned(),
+        set: |c, args| {
+            let name = single_arg(args)?.to_lowercase();
+            c.tls_auth_clients = TlsAuthClients::from_name(&name)
+                .ok_or("argument(s) must be one of the following: no, yes, optional")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-protocols",
+        modifiable: false,
+        get: |c| {
+            c.tls_protocols
+                .iter()
+                .map(|p| p.name())
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: |c, args| {
+            // Like Redis, the protocols may be given as one quoted argument
+            c.tls_protocols = args
+                .iter()
+                .flat_map(|arg| arg.split_whitespace())
+                .map(|name| {
+                    TlsProtocol::from_name(name)
+                        .ok_or_else(|| format!("Invalid tls-protocols specified: {}", name))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-ciphers",
+        modifiable: false,
+        get: |c| c.tls_ciphers.join(":"),
+        set: |c, args| {
+            c.tls_ciphers = cipher_suites_arg(args, false)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "tls-ciphersuites",
+        modifiable: false,
+        get: |c| c.tls_ciphersuites.join(":"),
+        set: |c, args| {
+            c.tls_ciphersuites = cipher_suites_arg(args, true)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "dir",
+        modifiable: true,
+        get: |c| c.dir.display().to_string(),
+        set: |c, args| {
+            let dir = Path::new(single_arg(args)?);
+            if !dir.is_dir() {
+                return Err(format!("No such directory: {}", dir.display()).into());
+            }
+            c.dir = dir.into();
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "dbfilename",
+        modifiable: true,
+        get: |c| c.dbfilename.clone(),
+        set: |c, args| {
+            let name = single_arg(args)?;
+            if name.is_empty() || name.contains('/') {
+                return Err("dbfilename can't be a path, just a filename".into());
+            }
+            c.dbfilename = name.to_owned();
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "save",
+        modifiable: true,
+        get: |c| {
+            c.save
+                .iter()
+                .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: |c, args| {
+            // Like Redis, the save points may be given as one quoted argument
+            let args: Vec<&str> = args.iter().flat_map(|arg| arg.split_whitespace()).collect();
+            if args.len() % 2 != 0 {
+                return Err("Invalid save parameters".into());
+            }
+            c.save = args
+                .chunks(2)
+                .map(|point| Some((point[0].parse().ok()?, point[1].parse().ok()?)))
+                .collect::<Option<_>>()
+                .ok_or("Invalid save parameters")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "maxmemory",
+        modifiable: true,
+        get: |c| c.maxmemory.to_string(),
+        set: |c, args| {
+            c.maxmemory = parse_memory(single_arg(args)?)?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "maxmemory-policy",
+        modifiable: true,
+        get: |c| c.maxmemory_policy.name().to_owned(),
+        set: |c, args| {
+            let name = single_arg(args)?.to_lowercase();
+            c.maxmemory_policy =
+                MaxmemoryPolicy::from_name(&name).ok_or("argument(s) must be one of the following: noeviction, allkeys-random, volatile-random, volatile-ttl")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "proto-max-bulk-len",
+        modifiable: true,
+        get: |c| c.proto_max_bulk_len.to_string(),
+        set: |c, args| {
+            c.proto_max_bulk_len = parse_memory(single_arg(args)?)?;
+            if c.proto_max_bulk_len < 1024 * 1024 {
+                return Err("argument must be at least 1mb".into());
+            }
+            Ok(())
+        },
+    },
+    // Not a real Redis parameter, Redis has a fixed limit for this
+    ConfigParam {
+        name: "proto-max-multibulk-len",
+        modifiable: true,
+        get: |c| c.proto_max_multibulk_len.to_string(),
+        set: |c, args| {
+            c.proto_max_multibulk_len = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            if c.proto_max_multibulk_len == 0 {
+                return Err("argument must be greater than 0".into());
+            }
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "slowlog-log-slower-than",
+        modifiable: true,
+        get: |c| c.slowlog_log_slower_than.to_string(),
+        set: |c, args| {
+            c.slowlog_log_slower_than = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "notify-keyspace-events",
+        modifiable: true,
+        get: |c| notify::flags_to_string(c.notify_keyspace_events),
+        set: |c, args| {
+            c.notify_keyspace_events = notify::flags_from_str(single_arg(args)?)
+                .ok_or("Invalid event class character. Use 'Ag$lshzxeKEtmd'.")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "slowlog-max-len",
+        modifiable: true,
+        get: |c| c.slowlog_max_len.to_string(),
+        set: |c, args| {
+            c.slowlog_max_len = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "lua-time-limit",
+        modifiable: true,
+        get: |c| c.lua_time_limit.to_string(),
+        set: |c, args| {
+            c.lua_time_limit = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "hll-sparse-max-bytes",
+        modifiable: true,
+        get: |c| c.hll_sparse_max_bytes.to_string(),
+        set: |c, args| {
+            c.hll_sparse_max_bytes = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "hash-max-listpack-entries",
+        modifiable: true,
+        get: |c| c.hash_max_listpack_entries.to_string(),
+        set: |c, args| {
+            c.hash_max_listpack_entries = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "hash-max-listpack-value",
+        modifiable: true,
+        get: |c| c.hash_max_listpack_value.to_string(),
+        set: |c, args| {
+            c.hash_max_listpack_value = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "list-max-listpack-size",
+        modifiable: true,
+        get: |c| c.list_max_listpack_size.to_string(),
+        set: |c, args| {
+            c.list_max_listpack_size = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "zset-max-listpack-entries",
+        modifiable: true,
+        get: |c| c.zset_max_listpack_entries.to_string(),
+        set: |c, args| {
+            c.zset_max_listpack_entries = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    // Not a real Redis parameter, Redis runs commands on a single thread
+    ConfigParam {
+        name: "shards",
+        modifiable: false,
+        get: |c| c.shards.to_string(),
+        set: |c, args| {
+            c.shards = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            if !(1..=MAX_SHARDS).contains(&c.shards) {
+                return Err(format!("argument must be between 1 and {}", MAX_SHARDS).into());
+            }
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "zset-max-listpack-value",
+        modifiable: true,
+        get: |c| c.zset_max_listpack_value.to_string(),
+        set: |c, args| {
+            c.zset_max_listpack_value = single_arg(args)?
+                .parse()
+                .map_err(|_| "argument couldn't be parsed into an integer")?;
+            Ok(())
+        },
+    },
+    ConfigParam {
+        name: "client-output-buffer-limit",
+        modifiable: true,
+        get: |c| {
+            ClientClass::ALL
+                .iter()
+                .map(|&class| {
+                    let limits = c.client_output_buffer_limit[class.index()];
+                    format!(
+                        "{} {} {} {}",
+                        class.name(),
+                        limits.hard,
+                        limits.soft,
+                        limits.soft_seconds
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: |c, args| {
+            if args.is_empty() || args.len() % 4 != 0 {
+                return Err("Wrong number of arguments in buffer limit configuration.".into());
+            }
+
+            // Only the classes given are changed
+            for limit in args.chunks(4) {
+                let class = ClientClass::from_name(&limit[0].to_lowercase())
+                    .ok_or("Invalid client class specified in buffer limit configuration.")?;
+                let invalid =
+                    "Error in hard, soft or soft_seconds setting in buffer limit configuration.";
+                let hard = parse_memory(limit[1]).map_err(|_| invalid)?;
+                let soft = parse_memory(limit[2]).map_err(|_| invalid)?;
+                let soft_seconds = limit[3].parse().map_err(|_| invalid)?;
+
+                c.client_output_buffer_limit[class.index()] =
+                    OutputLimits::new(hard, soft, soft_seconds);
+            }
+            Ok(())
+        },
+    },
+];
+
+fn lookup(name: &str) -> Option<&'static ConfigParam> {
+    CONFIG_TABLE
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+fn single_arg<'a>(args: &[&'a str]) -> ConfigResult<&'a str> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err("wrong number of arguments".into()),
+    }
+}
+
+fn yes_no_arg(args: &[&str]) -> ConfigResult<bool> {
+    match single_arg(args)?.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".into()),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_owned()
+}
+
+/// An optional path, given as an empty string when not set
+fn path_arg(args: &[&str]) -> ConfigResult<Option<PathBuf>> {
+    let path = single_arg(args)?;
+    Ok(if path.is_empty() {
+        None
+    } else {
+        Some(path.into())
+    })
+}
+
+fn path_value(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+/// A colon separated list of cipher suites, all for TLSv1.3 or all for
+/// earlier versions
+fn cipher_suites_arg(args: &[&str], tls13: bool) -> ConfigResult<Vec<String>> {
+    single_arg(args)?
+        .split(':')
+        .filter(|name| !name.is_empty())
+        .map(|name| match tls::cipher_suite(name) {
+            Some(suite) if suite.tls13().is_some() == tls13 => Ok(name.to_owned()),
+            _ => Err(format!("Unsupported cipher suite: {}", name).into()),
+        })
+        .collect()
+}
+
+/// Parses a memory size such as `1gb` or `100k` in the same way as Redis's
+/// `memtoull`. Units without a `b` are powers of 1000, with a `b` are powers
+/// of 1024.
+pub fn parse_memory(value: &str) -> ConfigResult<u64> {
+    let value = value.to_ascii_lowercase();
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(digits_end);
+
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".into()),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".into())
+}
+
+/// Splits a config line into arguments, honouring double and single quotes in
+/// a simplified version of Redis's `sdssplitargs`.
+fn split_args(line: &str) -> ConfigResult<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let quote = match chars.peek() {
+            None => break,
+            Some(&q @ '"') | Some(&q @ '\'') => {
+                chars.next();
+                Some(q)
+            }
+            Some(_) => None,
+        };
+
+        let mut arg = String::new();
+        loop {
+            match (chars.next(), quote) {
+                (None, Some(_)) => return Err("unbalanced quotes in configuration line".into()),
+                (None, None) => break,
+                (Some(c), Some(q)) if c == q => break,
+                (Some('\\'), Some('"')) => match chars.next() {
+                    Some('n') => arg.push('\n'),
+                    Some('t') => arg.push('\t'),
+                    Some(c) => arg.push(c),
+                    None => return Err("unbalanced quotes in configuration line".into()),
+                },
+                (Some(c), None) if c.is_whitespace() => break,
+                (Some(c), _) => arg.push(c),
+            }
+        }
+
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+impl Config {
+    /// Builds the configuration from the command line arguments, not
+    /// including the program name. Like Redis, the first argument may be the
+    /// path to a config file, the rest are `--name value` overrides.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> ConfigResult<Self> {
+        let mut args = args.into_iter().peekable();
+        let mut config = Config::default();
+        let mut text = String::new();
+
+        if let Some(path) = args.next_if(|a| !a.starts_with("--")) {
+            let path = PathBuf::from(path);
+            text = fs::read_to_string(&path).map_err(|e| {
+                format!(
+                    "Fatal error, can't open config file '{}': {}",
+                    path.display(),
+                    e
+                )
+            })?;
+            config.config_file = Some(path);
+        }
+
+        // Overrides are appended as extra lines so they take precedence
+        for arg in args {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    text.push('\n');
+                    text.push_str(name);
+                }
+                None => {
+                    text.push(' ');
+                    text.push_str(&arg);
+                }
+            }
+        }
+
+        config.load_from_str(&text)?;
+
+        Ok(config)
+    }
+
+    pub fn config_file(&self) -> Option<&Path> {
+        self.config_file.as_deref()
+    }
+
+    /// Where the dataset and function libraries are saved
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// Whether the dataset is saved when shutting down, unless told
+    /// otherwise, which is when there are save points as in Redis
+    pub fn persists(&self) -> bool {
+        !self.save.is_empty()
+    }
+
+    pub(crate) fn hash_listpack_limits(&self) -> ListpackLimits {
+        ListpackLimits {
+            max_entries: self.hash_max_listpack_entries,
+            max_value: self.hash_max_listpack_value,
+            max_bytes: usize::MAX,
+        }
+    }
+
+    /// As in Redis's quicklist, a positive size limits the number of entries
+    /// and a negative one the size in bytes, with a safety limit of 8kb on a
+    /// listpack limited by its entries.
+    pub(crate) fn list_listpack_limits(&self) -> ListpackLimits {
+        match usize::try_from(self.list_max_listpack_size) {
+            Ok(max_entries) => ListpackLimits {
+                max_entries,
+                max_value: usize::MAX,
+                max_bytes: 8192,
+            },
+            Err(_) => ListpackLimits {
+                max_entries: usize::MAX,
+                max_value: usize::MAX,
+                max_bytes: 4096 << (-self.list_max_listpack_size - 1).min(4),
+            },
+        }
+    }
+
+    pub(crate) fn output_limits(&self, class: ClientClass) -> OutputLimits {
+        self.client_output_buffer_limit[class.index()]
+    }
+
+    pub(crate) fn zset_listpack_limits(&self) -> ListpackLimits {
+        ListpackLimits {
+            max_entries: self.zset_max_listpack_entries,
+            max_value: self.zset_max_listpack_value,
+            max_bytes: usize::MAX,
+        }
+    }
+
+    fn load_from_str(&mut self, text: &str) -> ConfigResult<()> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let args = split_args(line)?;
+            let (name, values) = match args.split_first() {
+                Some(split) => split,
+                None => continue,
+            };
+
+            let param = lookup(name).ok_or_else(|| {
+                format!(
+                    "Bad directive or wrong number of arguments at line {}: '{}'",
+                    i + 1,
+                    line
+                )
+            })?;
+
+            let values: Vec<&str> = values.iter().map(String::as_str).collect();
+            (param.set)(self, &values).map_err(|e| {
+                format!("Error in configuration at line {}: '{}' {}", i + 1, line, e)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// All `(name, value)` pairs whose names match the glob pattern
+    pub fn get(&self, pattern: &[u8]) -> Vec<(&'static str, String)> {
+        CONFIG_TABLE
+            .iter()
+            .filter(|p| byte_glob::glob(pattern, p.name.as_bytes()))
+            .map(|p| (p.name, (p.get)(self)))
+            .collect()
+    }
+
+    /// Sets a parameter at runtime, as with CONFIG SET. The config is left
+    /// unchanged if there is an error.
+    pub fn set(&mut self, name: &str, value: &str) -> ConfigResult<()> {
+        let param = lookup(name).ok_or_else(|| format!("Unknown option '{}'", name))?;
+
+        if !param.modifiable {
+            return Err("can't set immutable config".into());
+        }
+
+        let values = split_args(value)?;
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+        let mut updated = self.clone();
+        (param.set)(&mut updated, &values)?;
+        *self = updated;
+
+        Ok(())
+    }
+
+    /// Writes the current configuration back to the file it was loaded from.
+    /// Comments and the order of existing lines are preserved, lines for
+    /// parameters are rewritten with their current values and anything that
+    /// differs from the defaults but isn't in the file is appended.
+    pub fn rewrite(&self) -> ConfigResult<()> {
+        let path = self
+            .config_file
+            .as_ref()
+            .ok_or("The server is running without a config file")?;
+
+        let original = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Rewriting config file: {}", e).into()),
+        };
+
+        let rewritten = self.rewrite_text(&original);
+
+        let tmp_path = path.with_extension("tmp-rewrite");
+        fs::write(&tmp_path, rewritten)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|e| format!("Rewriting config file: {}", e).into())
+    }
+
+    fn rewrite_text(&self, original: &str) -> String {
+        let defaults = Config::default();
+        let mut written: Vec<&str> = vec![];
+        let mut lines: Vec<String> = vec![];
+
+        for line in original.lines() {
+            let param = split_args(line.trim())
+                .ok()
+                .filter(|_| !line.trim_start().starts_with('#'))
+                .and_then(|args| args.first().and_then(|name| lookup(name)));
+
+            match param {
+                Some(param) if written.contains(&param.name) => (),
+                Some(param) => {
+                    written.push(param.name);
+                    lines.push(format_line(param, self));
+                }
+                None => lines.push(line.to_owned()),
+            }
+        }
+
+        let mut generated_header = false;
+        for param in CONFIG_TABLE {
+            if written.contains(&param.name) || (param.get)(self) == (param.get)(&defaults) {
+                continue;
+            }
+
+            if !generated_header {
+                lines.push("# Generated by CONFIG REWRITE".to_owned());
+                generated_header = true;
+            }
+
+            lines.push(format_line(param, self));
+        }
+
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+}
+
+fn format_line(param: &ConfigParam, config: &Config) -> String {
+    let value = (param.get)(config);
+
+    if value.is_empty() || value.contains(['"', '\'']) {
+        format!("{} {:?}", param.name, value)
+    } else {
+        format!("{} {}", param.name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("0"), Ok(0));
+        assert_eq!(parse_memory("100"), Ok(100));
+        assert_eq!(parse_memory("1k"), Ok(1000));
+        assert_eq!(parse_memory("1kb"), Ok(1024));
+        assert_eq!(parse_memory("2mb"), Ok(2 * 1024 * 1024));
+        assert_eq!(parse_memory("1GB"), Ok(1024 * 1024 * 1024));
+        assert!(parse_memory("").is_err());
+        assert!(parse_memory("1xb").is_err());
+        assert!(parse_memory("-1").is_err());
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(split_args("a b  c"), Ok(args("a b c")));
+        assert_eq!(
+            split_args(r#"bind "1 2" '3'"#),
+            Ok(vec!["bind".into(), "1 2".into(), "3".into()])
+        );
+        assert_eq!(
+            split_args(r#"x "a\"b""#),
+            Ok(vec!["x".into(), "a\"b".into()])
+        );
+        assert!(split_args(r#"x "ab"#).is_err());
+    }
+
+    #[test]
+    fn test_from_args_overrides() {
+        let config = Config::from_args(args("--port 6379 --maxmemory 1gb")).unwrap();
+
+        assert_eq!(config.port, 6379);
+        assert_eq!(config.maxmemory, 1024 * 1024 * 1024);
+        assert_eq!(config.config_file(), None);
+    }
+
+    #[test]
+    fn test_from_args_with_multiple_values() {
+        let config = Config::from_args(args("--bind 127.0.0.1 ::1")).unwrap();
+
+        assert_eq!(config.bind, vec!["127.0.0.1", "::1"]);
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let config = Config::default();
+        assert_eq!(config.maxclients, 10000);
+        assert_eq!(config.get(b"protected-mode")[0].1, "yes");
+
+        let mut config = Config::from_args(args(
+            "--maxclients 2 --timeout 60 --tcp-keepalive 0 --tcp-backlog 128 --protected-mode no",
+        ))
+        .unwrap();
+        assert_eq!(config.maxclients, 2);
+        assert_eq!(config.timeout, 60);
+        assert_eq!(config.tcp_keepalive, 0);
+        assert_eq!(config.tcp_backlog, 128);
+        assert!(!config.protected_mode);
+
+        assert!(config.set("protected-mode", "maybe").is_err());
+        assert!(config.set("maxclients", "0").is_err());
+        assert!(config.set("tcp-backlog", "64").is_err());
+        assert!(config.set("protected-mode", "YES").is_ok());
+        assert!(config.protected_mode);
+    }
+
+    #[test]
+    fn test_unixsocket() {
+        let config = Config::default();
+        assert_eq!(config.unixsocket, None);
+        assert_eq!(config.get(b"unixsocket*")[0].1, "");
+        assert_eq!(config.get(b"unixsocket*")[1].1, "0");
+
+        let config =
+            Config::from_args(args("--unixsocket /tmp/redis.sock --unixsocketperm 770")).unwrap();
+        assert_eq!(config.unixsocket, Some(PathBuf::from("/tmp/redis.sock")));
+        assert_eq!(config.unixsocketperm, 0o770);
+        assert_eq!(config.get(b"unixsocketperm")[0].1, "770");
+
+        assert!(Config::from_args(args("--unixsocketperm 778")).is_err());
+        assert!(Config::from_args(args("--unixsocketperm 1777")).is_err());
+    }
+
+    #[test]
+    fn test_tls() {
+        let config = Config::default();
+        assert_eq!(config.tls_port, 0);
+        assert_eq!(config.tls_auth_clients, TlsAuthClients::Yes);
+        assert_eq!(config.get(b"tls-protocols")[0].1, "");
+
+        let config = Config::from_args(args(
+            "--tls-port 6380 --tls-cert-file redis.crt --tls-auth-clients optional \
+             --tls-protocols TLSv1.2 tlsv1.3 \
+             --tls-ciphersuites TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384",
+        ))
+        .unwrap();
+        assert_eq!(config.tls_port, 6380);
+        assert_eq!(config.tls_cert_file, Some(PathBuf::from("redis.crt")));
+        assert_eq!(config.tls_key_file, None);
+        assert_eq!(config.tls_auth_clients, TlsAuthClients::Optional);
+        assert_eq!(config.get(b"tls-protocols")[0].1, "TLSv1.2 TLSv1.3");
+        assert_eq!(
+            config.tls_ciphersuites,
+            vec!["TLS_AES_128_GCM_SHA256", "TLS_AES_256_GCM_SHA384"]
+        );
+
+        assert!(Config::from_args(args("--tls-auth-clients maybe")).is_err());
+        assert!(Config::from_args(args("--tls-protocols TLSv1.1")).is_err());
+        assert!(Config::from_args(args("--tls-ciphers TLS_AES_128_GCM_SHA256")).is_err());
+        assert!(Config::from_args(args("--tls-ciphersuites ECDHE-RSA-AES128-GCM-SHA256")).is_err());
+    }
+
+    #[test]
+    fn test_shards() {
+        assert_eq!(Config::default().shards, 1);
+        assert_eq!(Config::from_args(args("--shards 4")).unwrap().shards, 4);
+        assert!(Config::from_args(args("--shards 0")).is_err());
+        assert!(Config::from_args(args("--shards 1025")).is_err());
+    }
+
+    #[test]
+    fn test_persistence() {
+        let config = Config::default();
+        assert_eq!(config.rdb_path(), PathBuf::from("./dump.rdb"));
+        assert_eq!(config.get(b"save")[0].1, "3600 1 300 100 60 10000");
+        assert!(config.persists());
+
+        let config = Config::from_args(args("--dir /tmp --dbfilename x.rdb --save 60 1")).unwrap();
+        assert_eq!(config.rdb_path(), PathBuf::from("/tmp/x.rdb"));
+        assert_eq!(config.save, vec![(60, 1)]);
+
+        // As given by a shell for `--save ""`
+        let config = Config::from_args(vec!["--save".to_owned(), String::new()]).unwrap();
+        assert!(!config.persists());
+
+        let mut config = Config::default();
+        assert!(config.set("save", "").is_ok());
+        assert!(!config.persists());
+        assert!(config.set("save", "10").is_err());
+        assert!(config.set("dbfilename", "/tmp/x.rdb").is_err());
+        assert!(config.set("dir", "/no/such/dir").is_err());
+    }
+
+    #[test]
+    fn test_from_args_unknown_directive() {
+        let err = Config::from_args(args("--xyz 1")).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Bad directive or wrong number of arguments at line 2: 'xyz 1'"
+        );
+    }
+
+    #[test]
+    fn test_load_from_str() {
+        let mut config = Config::default();
+        config
+            .load_from_str("# comment\n\nport 7000\nMAXMEMORY-POLICY allkeys-random\n")
+            .unwrap();
+
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.maxmemory_policy, MaxmemoryPolicy::AllKeysRandom);
+    }
+
+    #[test]
+    fn test_get_with_pattern() {
+        let config = Config::default();
+
+        assert_eq!(config.get(b"port"), vec![("port", "8080".to_owned())]);
+        assert_eq!(
+            config
+                .get(b"maxmemory*")
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>(),
+            vec!["maxmemory", "maxmemory-policy"]
+        );
+        assert!(config.get(b"xyz").is_empty());
+    }
+
+    #[test]
+    fn test_set() {
+        let mut config = Config::default();
+
+        assert_eq!(config.set("maxmemory", "10mb"), Ok(()));
+        assert_eq!(config.maxmemory, 10 * 1024 * 1024);
+
+        assert_eq!(
+            config.set("port", "1234"),
+            Err("can't set immutable config".into())
+        );
+        assert_eq!(config.set("xyz", "1"), Err("Unknown option 'xyz'".into()));
+
+        // Invalid values leave the config unchanged
+        assert!(config.set("maxmemory", "lots").is_err());
+        assert_eq!(config.maxmemory, 10 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_notify_keyspace_events() {
+        let mut config = Config::default();
+
+        assert_eq!(config.set("notify-keyspace-events", "KEA"), Ok(()));
+        assert_eq!(config.get(b"notify-keyspace-events")[0].1, "AKE");
+        assert!(config.set("notify-keyspace-events", "Kq").is_err());
+    }
+
+    #[test]
+    fn test_client_output_buffer_limit() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.get(b"client-output-buffer-limit")[0].1,
+            "normal 0 0 0 slave 268435456 67108864 60 pubsub 33554432 8388608 60"
+        );
+
+        assert_eq!(
+            config.set(
+                "client-output-buffer-limit",
+                "replica 1mb 1kb 5 NORMAL 100 0 0"
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            config.output_limits(ClientClass::Replica),
+            OutputLimits::new(1024 * 1024, 1024, 5)
+        );
+        assert_eq!(
+            config.output_limits(ClientClass::Normal),
+            OutputLimits::new(100, 0, 0)
+        );
+        assert_eq!(
+            config.output_limits(ClientClass::PubSub),
+            OutputLimits::new(32 * 1024 * 1024, 8 * 1024 * 1024, 60)
+        );
+
+        assert!(config
+            .set("client-output-buffer-limit", "normal 0 0")
+            .is_err());
+        assert!(config
+            .set("client-output-buffer-limit", "other 0 0 0")
+            .is_err());
+        assert!(config
+            .set("client-output-buffer-limit", "normal x 0 0")
+            .is_err());
+    }
+
+    #[test]
+    fn test_rewrite_text() {
+        let config = Config {
+            port: 7000,
+            maxmemory: 100,
+            ..Default::default()
+        };
+
+        let original =
+            "# My config\nport 6379\n\n# Duplicates are removed\nport 6380\nbind 127.0.0.1\n";
+        let expected = "# My config\nport 7000\n\n# Duplicates are removed\nbind 127.0.0.1\n# Generated by CONFIG REWRITE\nmaxmemory 100\n";
+
+        assert_eq!(config.rewrite_text(original), expected);
+    }
+
+    #[test]
+    fn test_rewrite_without_config_file() {
+        let config = Config::default();
+
+        assert_eq!(
+            config.rewrite(),
+            Err("The server is running without a config file".into())
+        );
+    }
+}
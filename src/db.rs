@@ -1,30 +1,167 @@
+use crate::{
+    blocking::BlockRequest,
+    clients::{Activity, Clients, ClientsGuard},
+    commands::Registry,
+    config::{Config, MaxmemoryPolicy},
+    functions::Functions,
+    hash::Hash,
+    list::List,
+    listpack::ListpackLimits,
+    notify,
+    output_buffer::ClientClass,
+    pubsub::{self, PubSub},
+    response::Response,
+    scripting::{ScriptBusy, Scripting},
+    shutdown::Shutdown,
+    slowlog::{SlowLog, SlowLogEntry},
+    sorted_set::SortedSet,
+    stats::{self, Stats},
+    stream::Stream,
+};
 use byte_string::ByteString;
 use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::hash_map::{DefaultHasher, RandomState},
     collections::HashMap,
-    collections::VecDeque,
-    iter::{FromIterator, IntoIterator},
-    sync::Arc,
-    time::Instant,
+    convert::TryFrom,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    iter::IntoIterator,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+/// The number of keys sampled when choosing a volatile-ttl eviction candidate
+const MAXMEMORY_SAMPLES: usize = 5;
+
 pub struct Database {
     store: HashMap<Arc<ByteString>, RObj>,
     expires: HashMap<Arc<ByteString>, Instant>,
+    shard: usize,
+    shards: Arc<[ShardState]>,
+    config: Arc<RwLock<Config>>,
+    current_client: Option<u64>,
+    /// The keys other shards lent for the command being executed, all of
+    /// them when `None`
+    borrowed: Option<Vec<ByteString>>,
+    pubsub: Mutex<PubSub>,
+    scripting: Arc<Mutex<Scripting>>,
+    functions: Arc<Mutex<Functions>>,
+    script_busy: Arc<ScriptBusy>,
+    shutdown: Arc<Shutdown>,
+    registry: Arc<Registry>,
+    block_request: Option<BlockRequest>,
+    ready_keys: Vec<ByteString>,
 }
 
-impl Database {
-    pub fn new() -> Self {
+/// What a shard reports of itself for INFO and the like: the number of its
+/// keys, and of those with an expire, as last published, its statistics, its
+/// slow log and its copy of the clients. A shard only ever locks another's
+/// to read it, one at a time.
+struct ShardState {
+    keys: AtomicUsize,
+    expires: AtomicUsize,
+    stats: Mutex<Stats>,
+    slowlog: Mutex<SlowLog>,
+    clients: Mutex<Clients>,
+}
+
+impl ShardState {
+    fn new(slowlog_ids: &Arc<AtomicU64>) -> Self {
         Self {
-            store: HashMap::new(),
-            expires: HashMap::new(),
+            keys: AtomicUsize::new(0),
+            expires: AtomicUsize::new(0),
+            stats: Mutex::new(Stats::new()),
+            slowlog: Mutex::new(SlowLog::with_ids(Arc::clone(slowlog_ids))),
+            clients: Mutex::new(Clients::new()),
         }
     }
 
+    fn stats(&self) -> MutexGuard<'_, Stats> {
+        self.stats.lock().expect("stats lock poisoned")
+    }
+
+    fn slowlog(&self) -> MutexGuard<'_, SlowLog> {
+        self.slowlog.lock().expect("slowlog lock poisoned")
+    }
+
+    fn clients(&self) -> MutexGuard<'_, Clients> {
+        self.clients.lock().expect("clients lock poisoned")
+    }
+}
+
+/// A key along with its value and expire, as lent by the shard holding it
+/// to the one coordinating a command across shards, or as loaded from the
+/// RDB file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub key: ByteString,
+    pub value: RObj,
+    pub expires_at: Option<Instant>,
+}
+
+impl Database {
+    #[cfg(test)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let config = Arc::new(RwLock::new(Config::default()));
+        Self::sharded(config, Arc::new(Registry::new()), 1)
+            .pop()
+            .expect("there is always a shard")
+    }
+
+    /// Creates the shards of a keyspace split `count` ways by key hash. Each
+    /// keeps its own clients, Pub/Sub, statistics, scripts and so on, which
+    /// are added up when reporting on the whole server. Only the config, the
+    /// commands and data types registered and the shutdown are shared.
+    pub fn sharded(
+        config: Arc<RwLock<Config>>,
+        registry: Arc<Registry>,
+        count: usize,
+    ) -> Vec<Self> {
+        let slowlog_ids = Arc::new(AtomicU64::new(0));
+        let shards: Arc<[ShardState]> = (0..count.max(1))
+            .map(|_| ShardState::new(&slowlog_ids))
+            .collect();
+        let shutdown = Arc::new(Shutdown::default());
+
+        (0..shards.len())
+            .map(|shard| {
+                let script_busy = Arc::new(ScriptBusy::default());
+
+                Self {
+                    store: HashMap::new(),
+                    expires: HashMap::new(),
+                    shard,
+                    shards: Arc::clone(&shards),
+                    config: Arc::clone(&config),
+                    current_client: None,
+                    borrowed: Some(vec![]),
+                    pubsub: Mutex::new(PubSub::new()),
+                    scripting: Arc::new(Mutex::new(Scripting::new(Arc::clone(&script_busy)))),
+                    functions: Arc::new(Mutex::new(Functions::new(Arc::clone(&script_busy)))),
+                    script_busy,
+                    shutdown: Arc::clone(&shutdown),
+                    registry: Arc::clone(&registry),
+                    block_request: None,
+                    ready_keys: Vec::new(),
+                }
+            })
+            .collect()
+    }
+
     pub fn get<'a>(&'a mut self, key: &ByteString) -> Option<&'a RObj> {
-        if self.remove_if_expired(key) {
+        if self.remove_if_expired(key) || !self.store.contains_key(key) {
+            self.stats_mut().keyspace_misses += 1;
+            self.notify_keyspace_event(notify::KEY_MISS, "keymiss", key);
             return None;
         }
 
+        self.stats_mut().keyspace_hits += 1;
         self.store.get(key)
     }
 
@@ -36,6 +173,12 @@ impl Database {
         self.store.get_mut(key)
     }
 
+    /// Like `get` but for write paths that only need to know if the key is
+    /// present, so it is not counted as a keyspace hit or miss.
+    pub fn exists(&mut self, key: &ByteString) -> bool {
+        !self.remove_if_expired(key) && self.store.contains_key(key)
+    }
+
     pub fn filter_keys(&self, f: impl Fn(&ByteString) -> bool) -> Vec<&ByteString> {
         self.store
             .keys()
@@ -44,6 +187,359 @@ impl Database {
             .collect()
     }
 
+    /// The keys which haven't expired, with their values and expires, as
+    /// saved to the RDB file
+    pub fn entries(&self) -> impl Iterator<Item = (&ByteString, &RObj, Option<Instant>)> {
+        self.store
+            .iter()
+            .filter(move |(key, _)| !self.is_expired(key))
+            .map(move |(key, value)| (key.as_ref(), value, self.get_expire(key)))
+    }
+
+    /// The number of keys in the whole keyspace, counting the other shards
+    /// as of their last `publish_size`
+    pub fn keys_count(&self) -> usize {
+        self.other_shards()
+            .map(|state| state.keys.load(Ordering::Relaxed))
+            .sum::<usize>()
+            + self.store.len()
+    }
+
+    pub fn expires_count(&self) -> usize {
+        self.other_shards()
+            .map(|state| state.expires.load(Ordering::Relaxed))
+            .sum::<usize>()
+            + self.expires.len()
+    }
+
+    fn other_shards(&self) -> impl Iterator<Item = &ShardState> {
+        let shard = self.shard;
+        self.shards
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| *i != shard)
+            .map(|(_, state)| state)
+    }
+
+    fn own_state(&self) -> &ShardState {
+        &self.shards[self.shard]
+    }
+
+    /// Makes the size of this shard visible to the others for INFO
+    pub fn publish_size(&self) {
+        let state = self.own_state();
+        state.keys.store(self.store.len(), Ordering::Relaxed);
+        state.expires.store(self.expires.len(), Ordering::Relaxed);
+    }
+
+    /// The index of this shard, the first being the one which coordinates
+    /// the commands involving several shards
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The statistics of this shard
+    pub fn stats(&self) -> MutexGuard<'_, Stats> {
+        self.own_state().stats()
+    }
+
+    pub fn stats_mut(&self) -> MutexGuard<'_, Stats> {
+        self.stats()
+    }
+
+    /// The statistics of the whole server, added up over the shards. They
+    /// must not be called for with this shard's statistics locked.
+    pub fn total_stats(&self) -> Stats {
+        let mut total = Stats::new();
+        for state in self.shards.iter() {
+            total.add(&state.stats());
+        }
+
+        total
+    }
+
+    /// This shard's copy of the clients, locked for as long as the guard is
+    /// held, which must not be while publishing as that locks them again
+    pub fn clients(&self) -> ClientsGuard<'_> {
+        ClientsGuard::new(self.own_state().clients(), self.current_client)
+    }
+
+    /// What each client did across the shards, each of which only knows
+    /// about the commands it ran. It must not be called for with the
+    /// clients locked.
+    pub fn client_activity(&self) -> HashMap<u64, Activity> {
+        let mut activity: HashMap<u64, Activity> = HashMap::new();
+
+        for state in self.shards.iter() {
+            for client in state.clients().iter() {
+                activity
+                    .entry(client.id)
+                    .and_modify(|merged| merged.merge(&client.activity))
+                    .or_insert_with(|| client.activity.clone());
+            }
+        }
+
+        activity
+    }
+
+    /// Sets the client whose command is being executed
+    pub fn set_current_client(&mut self, id: Option<u64>) {
+        self.current_client = id;
+    }
+
+    /// The slow log of this shard
+    pub fn slowlog(&self) -> MutexGuard<'_, SlowLog> {
+        self.own_state().slowlog()
+    }
+
+    /// The slow log of the whole server, newest first, made up of the
+    /// entries of every shard up to `slowlog-max-len`
+    pub fn slowlog_entries(&self) -> Vec<SlowLogEntry> {
+        let mut entries: Vec<SlowLogEntry> = self
+            .shards
+            .iter()
+            .flat_map(|state| state.slowlog().iter().cloned().collect::<Vec<_>>())
+            .collect();
+
+        entries.sort_unstable_by_key(|entry| Reverse(entry.id));
+        entries.truncate(usize::try_from(self.config().slowlog_max_len).unwrap_or(usize::MAX));
+        entries
+    }
+
+    /// Locked after the clients when both are needed
+    pub fn pubsub(&self) -> MutexGuard<'_, PubSub> {
+        self.pubsub.lock().expect("pubsub lock poisoned")
+    }
+
+    /// The scripting engine of this shard, which is shared, rather than
+    /// borrowed, as scripts need the database while they run
+    pub fn scripting(&self) -> Arc<Mutex<Scripting>> {
+        Arc::clone(&self.scripting)
+    }
+
+    /// The function libraries, which, unlike scripts, belong to the dataset.
+    /// Each shard loads every library.
+    pub fn functions(&self) -> Arc<Mutex<Functions>> {
+        Arc::clone(&self.functions)
+    }
+
+    pub fn script_busy(&self) -> Arc<ScriptBusy> {
+        Arc::clone(&self.script_busy)
+    }
+
+    pub fn shutdown(&self) -> Arc<Shutdown> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /// The commands and data types registered on top of those built in
+    pub fn registry(&self) -> Arc<Registry> {
+        Arc::clone(&self.registry)
+    }
+
+    /// Blocks the client of the command being executed. Returns false when
+    /// blocking isn't possible, such as from a script, in which case the
+    /// command should reply as if it had timed out.
+    pub fn block_current_client(&mut self, request: BlockRequest) -> bool {
+        if self.current_client.is_none() || self.script_busy.is_running() {
+            return false;
+        }
+
+        self.block_request = Some(request);
+        true
+    }
+
+    pub fn take_block_request(&mut self) -> Option<BlockRequest> {
+        self.block_request.take()
+    }
+
+    /// Wakes the clients blocked on the key, once the current command has
+    /// finished
+    pub fn signal_key_ready(&mut self, key: &ByteString) {
+        self.ready_keys.push(key.clone());
+    }
+
+    pub fn take_ready_keys(&mut self) -> Vec<ByteString> {
+        std::mem::take(&mut self.ready_keys)
+    }
+
+    /// Removes this shard's copy of a disconnected client along with its
+    /// subscriptions
+    pub fn remove_client(&mut self, client_id: u64) {
+        let removed = self.clients().remove(client_id);
+
+        if let Some(client) = removed {
+            let mut pubsub = self.pubsub();
+            for channel in &client.channels {
+                pubsub.unsubscribe(client_id, channel);
+            }
+            for pattern in &client.patterns {
+                pubsub.punsubscribe(client_id, pattern);
+            }
+        }
+    }
+
+    /// Sends the message to the subscribers of the channel and of any
+    /// matching patterns. Returns the number of clients that received it.
+    pub fn publish(&self, channel: &ByteString, message: &ByteString) -> usize {
+        let limits = self.config().client_output_buffer_limit;
+        let clients = self.clients();
+        let pubsub = self.pubsub();
+        let mut receivers = 0;
+
+        for client_id in pubsub.subscribers(channel) {
+            if let Some(client) = clients.get(client_id) {
+                let limits = limits[client.class().index()];
+                client.push(pubsub::message(channel, message), limits);
+                receivers += 1;
+            }
+        }
+
+        for (pattern, client_id) in pubsub.pattern_subscribers(channel) {
+            if let Some(client) = clients.get(client_id) {
+                let limits = limits[client.class().index()];
+                client.push(pubsub::pmessage(pattern, channel, message), limits);
+                receivers += 1;
+            }
+        }
+
+        receivers
+    }
+
+    /// Publishes a keyspace notification if its class is enabled by
+    /// `notify-keyspace-events`. The event is sent to `__keyspace@0__:<key>`
+    /// and the key to `__keyevent@0__:<event>`.
+    pub fn notify_keyspace_event(&self, class: u32, event: &str, key: &ByteString) {
+        let flags = self.config().notify_keyspace_events;
+
+        if flags & class == 0 {
+            return;
+        }
+
+        if flags & notify::KEYSPACE != 0 {
+            let mut channel = b"__keyspace@0__:".to_vec();
+            channel.extend_from_slice(key);
+            self.publish(&channel.into(), &event.into());
+        }
+
+        if flags & notify::KEYEVENT != 0 {
+            let channel = format!("__keyevent@0__:{}", event);
+            self.publish(&channel.into(), key);
+        }
+    }
+
+    /// Records the command in the slow log if it exceeded the configured
+    /// `slowlog-log-slower-than` threshold
+    pub fn slowlog_push(&mut self, argv: &[ByteString], duration: Duration) {
+        let (slower_than, max_len) = {
+            let config = self.config();
+            (
+                config.slowlog_log_slower_than,
+                usize::try_from(config.slowlog_max_len).unwrap_or(usize::MAX),
+            )
+        };
+        let clients = self.clients();
+        let client = clients.current();
+
+        self.slowlog().push_if_needed(
+            slower_than,
+            max_len,
+            argv,
+            duration,
+            client.map(|c| c.addr.clone()),
+            client.and_then(|c| c.name.as_ref()),
+        );
+    }
+
+    /// Sends the command to every client in MONITOR mode, formatted like
+    /// `+1339518083.107412 [0 127.0.0.1:60866] "keys" "*"`
+    pub fn feed_monitors(&self, argv: &[ByteString]) {
+        let clients = self.clients();
+        if clients.monitors().next().is_none() {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let source = match clients.current() {
+            _ if self.script_busy.is_running() => "lua".to_owned(),
+            Some(client) => client.addr.to_string(),
+            None => "unknown".to_owned(),
+        };
+        let args: Vec<String> = argv.iter().map(|arg| arg.to_repr()).collect();
+        let line = format!(
+            "{}.{:06} [0 {}] {}",
+            now.as_secs(),
+            now.subsec_micros(),
+            source,
+            args.join(" ")
+        );
+
+        let limits = self.config().output_limits(ClientClass::Replica);
+        for monitor in clients.monitors() {
+            let mut response = Response::new();
+            response.add_simple_string(&line);
+            monitor.push(response, limits);
+        }
+    }
+
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().expect("config lock poisoned")
+    }
+
+    pub fn config_mut(&self) -> RwLockWriteGuard<'_, Config> {
+        self.config.write().expect("config lock poisoned")
+    }
+
+    /// Evicts keys according to the `maxmemory-policy` until memory usage is
+    /// below `maxmemory`. Returns false if that could not be achieved, in
+    /// which case commands that would use more memory must be refused.
+    pub fn perform_evictions(&mut self) -> bool {
+        let (maxmemory, policy) = {
+            let config = self.config();
+            (config.maxmemory, config.maxmemory_policy)
+        };
+
+        if maxmemory == 0 {
+            return true;
+        }
+
+        let maxmemory = usize::try_from(maxmemory).unwrap_or(usize::MAX);
+        self.evict_while(policy, || stats::used_memory() > maxmemory)
+    }
+
+    fn evict_while(
+        &mut self,
+        policy: MaxmemoryPolicy,
+        mut over_limit: impl FnMut() -> bool,
+    ) -> bool {
+        while over_limit() {
+            let candidate = match policy {
+                MaxmemoryPolicy::NoEviction => None,
+                MaxmemoryPolicy::AllKeysRandom => sample(&self.store, 1).next().map(|(k, _)| k),
+                MaxmemoryPolicy::VolatileRandom => sample(&self.expires, 1).next().map(|(k, _)| k),
+                MaxmemoryPolicy::VolatileTtl => sample(&self.expires, MAXMEMORY_SAMPLES)
+                    .min_by_key(|(_, when)| **when)
+                    .map(|(k, _)| k),
+            };
+
+            match candidate.map(Arc::clone) {
+                Some(key) => {
+                    self.remove(&key);
+                    self.stats_mut().evicted_keys += 1;
+                    self.notify_keyspace_event(notify::EVICTED, "evicted", &key);
+                }
+                None => return false,
+            }
+        }
+
+        true
+    }
+
     pub fn clear(&mut self) {
         // Clears all the key-values but retains memory
         self.store.clear();
@@ -63,6 +559,81 @@ impl Database {
         self.store.remove(key)
     }
 
+    /// Whether the key belongs in this shard rather than another
+    pub fn is_home(&self, key: &[u8]) -> bool {
+        shard_of(key, self.shard_count()) == self.shard
+    }
+
+    /// Adds keys lent by another shard to those held for the command being
+    /// executed, all of them when `keys` is `None`
+    pub fn add_borrowed(&mut self, keys: Option<&[ByteString]>) {
+        match (&mut self.borrowed, keys) {
+            (Some(borrowed), Some(keys)) => borrowed.extend_from_slice(keys),
+            (borrowed, _) => *borrowed = None,
+        }
+    }
+
+    /// Forgets the keys lent for the command, once they have been returned
+    pub fn clear_borrowed(&mut self) {
+        self.borrowed = Some(vec![]);
+    }
+
+    /// Whether the key is held by this shard for the command being executed,
+    /// as its own or lent by another shard. Like a cluster node, a shard
+    /// can't reach any other key.
+    pub fn is_local(&self, key: &[u8]) -> bool {
+        self.is_home(key)
+            || self
+                .borrowed
+                .as_ref()
+                .is_none_or(|borrowed| borrowed.iter().any(|k| k.as_ref() == key))
+    }
+
+    /// Whether every key is held by this shard for the command being
+    /// executed, as there is only the one or the others lent it theirs
+    pub fn holds_all_keys(&self) -> bool {
+        self.shard_count() == 1 || self.borrowed.is_none()
+    }
+
+    /// Removes the key, with its expire, to lend it to another shard. Keys
+    /// which have expired are not lent.
+    pub fn take(&mut self, key: &ByteString) -> Option<Entry> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
+
+        let expires_at = self.get_expire(key);
+        let value = self.remove(key)?;
+
+        Some(Entry {
+            key: key.clone(),
+            value,
+            expires_at,
+        })
+    }
+
+    /// Takes every key matching the predicate, as with `take`
+    pub fn take_where(&mut self, f: impl Fn(&ByteString) -> bool) -> Vec<Entry> {
+        let keys: Vec<ByteString> = self
+            .store
+            .keys()
+            .filter(|key| f(key))
+            .map(|key| ByteString::clone(key))
+            .collect();
+
+        keys.iter().filter_map(|key| self.take(key)).collect()
+    }
+
+    /// Puts back a key which was taken
+    pub fn restore(&mut self, entry: Entry) {
+        let key = Arc::new(entry.key);
+
+        if let Some(expires_at) = entry.expires_at {
+            self.expires.insert(Arc::clone(&key), expires_at);
+        }
+        self.store.insert(key, entry.value);
+    }
+
     pub fn set_expire(&mut self, key: &ByteString, expires_at: Instant) -> bool {
         if let Some((existing_key, _)) = self.store.get_key_value(key) {
             self.expires.insert(Arc::clone(existing_key), expires_at);
@@ -90,6 +661,8 @@ impl Database {
     fn remove_if_expired(&mut self, key: &ByteString) -> bool {
         if self.is_expired(key) {
             self.remove(key);
+            self.stats_mut().expired_keys += 1;
+            self.notify_keyspace_event(notify::EXPIRED, "expired", key);
             return true;
         }
 
@@ -97,12 +670,58 @@ impl Database {
     }
 }
 
+/// The shard holding the key when the keyspace is split `count` ways. As with
+/// Redis Cluster's hash tags, only the part of the key between the first `{`
+/// and the next `}` is hashed, when not empty, so that keys used together by
+/// multi-key commands can be kept in the same shard.
+pub fn shard_of(key: &[u8], count: usize) -> usize {
+    if count <= 1 {
+        return 0;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    hasher.write(hash_tag(key));
+    (hasher.finish() % count as u64) as usize
+}
+
+fn hash_tag(key: &[u8]) -> &[u8] {
+    let tag = key.iter().position(|&c| c == b'{').and_then(|open| {
+        let rest = &key[open + 1..];
+        rest.iter()
+            .position(|&c| c == b'}')
+            .map(|close| &rest[..close])
+    });
+
+    match tag {
+        Some(tag) if !tag.is_empty() => tag,
+        _ => key,
+    }
+}
+
+/// Up to `n` consecutive entries starting from a random position in the map
+fn sample<V>(
+    map: &HashMap<Arc<ByteString>, V>,
+    n: usize,
+) -> impl Iterator<Item = (&Arc<ByteString>, &V)> {
+    let positions = (map.len() + 1).saturating_sub(n).max(1);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(map.len());
+    let start = (hasher.finish() % positions as u64) as usize;
+
+    map.iter().skip(start).take(n)
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum RObj {
     Int(i64),
     String(ByteString),
-    List(VecDeque<ByteString>),
-    Hash(HashMap<ByteString, ByteString>),
+    List(List),
+    Hash(Hash),
+    Stream(Stream),
+    SortedSet(SortedSet),
+    /// A value of a data type registered on top of those built in
+    Custom(CustomValue),
 }
 
 impl From<i64> for RObj {
@@ -121,11 +740,108 @@ impl From<ByteString> for RObj {
 }
 
 impl RObj {
-    pub fn new_list_from(other: impl IntoIterator<Item = ByteString>) -> Self {
-        RObj::List(VecDeque::from_iter(other))
+    pub fn new_list_from(
+        other: impl IntoIterator<Item = ByteString>,
+        limits: ListpackLimits,
+    ) -> Self {
+        RObj::List(List::from_values(other, limits))
+    }
+
+    /// The bytes of a string value for modifying in place, expanding an
+    /// `Int` into its decimal representation.
+    pub fn string_bytes_mut(&mut self) -> Option<&mut ByteString> {
+        if let RObj::Int(n) = self {
+            *self = RObj::String(ByteString::from(n.to_string()));
+        }
+
+        match self {
+            RObj::String(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// The value of a registered data type, if this is one of `data_type`
+    pub fn custom<T: Any>(&self, data_type: DataType) -> Option<&T> {
+        match self {
+            RObj::Custom(custom) if custom.data_type == data_type => custom.value.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn custom_mut<T: Any>(&mut self, data_type: DataType) -> Option<&mut T> {
+        match self {
+            RObj::Custom(custom) if custom.data_type == data_type => custom.value.downcast_mut(),
+            _ => None,
+        }
+    }
+
+    /// Turns a string value modified in place back into an `Int` when it
+    /// holds exactly the decimal representation of one, as on insertion.
+    pub fn shrink_to_int(&mut self) {
+        if let RObj::String(bytes) = self {
+            if let Ok(n) = bytes.parse::<i64>() {
+                if n.to_string().as_bytes() == bytes.as_ref() {
+                    *self = RObj::Int(n);
+                }
+            }
+        }
+    }
+}
+
+/// A data type registered on top of those built in, see
+/// `Registry::register_type`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataType {
+    name: &'static str,
+}
+
+impl DataType {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Self { name }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+
+    /// Makes a value of this type that can be stored in the keyspace
+    pub fn wrap<T: Any + Send>(self, value: T) -> RObj {
+        RObj::Custom(CustomValue {
+            data_type: self,
+            value: Box::new(value),
+        })
+    }
+}
+
+/// A value of a registered data type. Values of these types are only equal
+/// to themselves.
+pub struct CustomValue {
+    data_type: DataType,
+    value: Box<dyn Any + Send>,
+}
+
+impl CustomValue {
+    pub fn data_type(&self) -> DataType {
+        self.data_type
+    }
+}
+
+impl fmt::Debug for CustomValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomValue")
+            .field("data_type", &self.data_type.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for CustomValue {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
     }
 }
 
+impl Eq for CustomValue {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,9 +849,15 @@ mod tests {
 
     #[test]
     fn test_new_list_from() {
-        let expected = RObj::List(VecDeque::from(vec!["x".into()]));
-        let actual = RObj::new_list_from(vec!["x".into()]);
-        assert_eq!(actual, expected);
+        let limits = Config::default().list_listpack_limits();
+
+        match RObj::new_list_from(vec!["x".into()], limits) {
+            RObj::List(list) => {
+                assert_eq!(list.encoding(), "listpack");
+                assert_eq!(list.iter().collect::<Vec<_>>(), vec![b"x"]);
+            }
+            other => panic!("expected a list, got {:?}", other),
+        }
     }
 
     #[test]
@@ -149,20 +871,196 @@ mod tests {
         assert_eq!(o, RObj::Int(-123_i64));
 
         // The maximum value of an i64 can be stored as an Int
-        let max = format!("{}", std::i64::MAX);
+        let max = format!("{}", i64::MAX);
         let o: RObj = ByteString::from(max).into();
-        assert_eq!(o, RObj::Int(std::i64::MAX));
+        assert_eq!(o, RObj::Int(i64::MAX));
 
         // The minimum value of an i64 can be stored as an Int
-        let min = format!("{}", std::i64::MIN);
+        let min = format!("{}", i64::MIN);
         let o: RObj = ByteString::from(min).into();
-        assert_eq!(o, RObj::Int(std::i64::MIN));
+        assert_eq!(o, RObj::Int(i64::MIN));
 
         // Overflowing the maximum value of an i64 results in a String
-        let o: RObj = ByteString::from(format!("{}1", std::i64::MAX)).into();
+        let o: RObj = ByteString::from(format!("{}1", i64::MAX)).into();
         assert_eq!(o, RObj::String(ByteString::from("92233720368547758071")));
     }
 
+    #[test]
+    fn test_string_bytes_mut() {
+        let mut o = RObj::Int(12);
+        o.string_bytes_mut().unwrap().to_mut().push(b'3');
+        assert_eq!(o, RObj::String(ByteString::from("123")));
+
+        o.shrink_to_int();
+        assert_eq!(o, RObj::Int(123));
+
+        // Only the canonical representation is turned back into an Int
+        let mut o = RObj::String(ByteString::from("0123"));
+        o.shrink_to_int();
+        assert_eq!(o, RObj::String(ByteString::from("0123")));
+
+        assert_eq!(RObj::List(List::new()).string_bytes_mut(), None);
+    }
+
+    #[test]
+    fn test_custom() {
+        let counter = DataType::new("counter01");
+        let other = DataType::new("othertype");
+
+        let mut o = counter.wrap(5_i64);
+        assert_eq!(o.custom::<i64>(counter), Some(&5));
+        *o.custom_mut::<i64>(counter).unwrap() += 1;
+        assert_eq!(o.custom::<i64>(counter), Some(&6));
+
+        // Both the data type and the Rust type must match
+        assert_eq!(o.custom::<i64>(other), None);
+        assert_eq!(o.custom::<u8>(counter), None);
+        assert_eq!(RObj::Int(6).custom::<i64>(counter), None);
+
+        assert_eq!(o, o);
+        assert_ne!(o, counter.wrap(6_i64));
+    }
+
+    #[test]
+    fn test_shard_of() {
+        assert_eq!(shard_of(b"key", 1), 0);
+        assert!((0..100).all(|n| shard_of(format!("key:{}", n).as_bytes(), 4) < 4));
+
+        // The keys are spread over every shard
+        let mut used = [false; 4];
+        (0..100).for_each(|n| used[shard_of(format!("key:{}", n).as_bytes(), 4)] = true);
+        assert_eq!(used, [true; 4]);
+
+        // Only the hash tag counts, when there is one
+        assert_eq!(hash_tag(b"{user1000}.following"), b"user1000");
+        assert_eq!(hash_tag(b"foo{}{bar}"), b"foo{}{bar}");
+        assert_eq!(hash_tag(b"foo{{bar}}zap"), b"{bar");
+        assert_eq!(hash_tag(b"foo{bar"), b"foo{bar");
+        assert_eq!(
+            shard_of(b"{user1000}.following", 16),
+            shard_of(b"{user1000}.followers", 16)
+        );
+    }
+
+    #[test]
+    fn test_take_and_restore() {
+        let mut db = Database::new();
+        let expires_at = Instant::now() + Duration::from_secs(10);
+        db.insert("a".into(), 1.into());
+        db.insert("b".into(), 2.into());
+        db.set_expire(&"b".into(), expires_at);
+        db.insert("gone".into(), 3.into());
+        db.set_expire(&"gone".into(), Instant::now() - Duration::from_secs(1));
+
+        let entry = db.take(&"b".into()).unwrap();
+        assert_eq!(entry.value, RObj::Int(2));
+        assert_eq!(entry.expires_at, Some(expires_at));
+        assert!(!db.exists(&"b".into()));
+        assert_eq!(db.expires_count(), 1);
+
+        // Expired keys are not lent
+        assert!(db.take(&"gone".into()).is_none());
+        assert!(db.take(&"missing".into()).is_none());
+
+        db.restore(entry);
+        assert_eq!(db.get_expire(&"b".into()), Some(expires_at));
+
+        let mut taken = db.take_where(|key| key.as_ref() != b"a");
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken.pop().unwrap().key, ByteString::from("b"));
+        assert_eq!(db.keys_count(), 1);
+    }
+
+    #[test]
+    fn test_sharded() {
+        let config = Arc::new(RwLock::new(Config::default()));
+        let mut shards = Database::sharded(config, Arc::new(Registry::new()), 3);
+        assert_eq!(shards.len(), 3);
+        assert_eq!(shards[2].shard(), 2);
+        assert_eq!(shards[2].shard_count(), 3);
+
+        // Each shard keeps its own keys and statistics, which are added up
+        shards[1].insert("x".into(), 1.into());
+        shards[1].get(&"x".into());
+        assert_eq!(shards[0].stats().keyspace_hits, 0);
+        assert_eq!(shards[0].total_stats().keyspace_hits, 1);
+        assert!(!shards[0].exists(&"x".into()));
+
+        // As are the slow logs
+        shards[0].config_mut().slowlog_log_slower_than = 0;
+        shards[2].slowlog_push(&["get".into(), "x".into()], Duration::from_micros(1));
+        shards[1].slowlog_push(&["get".into(), "y".into()], Duration::from_micros(1));
+        assert_eq!(shards[0].slowlog().len(), 0);
+        let entries = shards[0].slowlog_entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].argv[1], ByteString::from("y"));
+        shards[0].config_mut().slowlog_max_len = 1;
+        assert_eq!(shards[0].slowlog_entries().len(), 1);
+
+        // The other shards' sizes are as last published
+        assert_eq!(shards[0].keys_count(), 0);
+        shards[1].publish_size();
+        assert_eq!(shards[0].keys_count(), 1);
+        assert_eq!(shards[1].keys_count(), 1);
+    }
+
+    #[test]
+    fn test_client_activity() {
+        use crate::clients::Client;
+        use tokio::sync::{mpsc, oneshot};
+
+        let config = Arc::new(RwLock::new(Config::default()));
+        let shards = Database::sharded(config, Arc::new(Registry::new()), 2);
+        let (kill_switch, _) = oneshot::channel();
+        let (push_sender, _) = mpsc::channel(1);
+        let addr = "127.0.0.1:1234".parse::<std::net::SocketAddr>().unwrap();
+        let client = Client::new(1, addr.into(), addr.into(), kill_switch, push_sender);
+        let mut copy = client.for_shard();
+        copy.activity.last_interaction += Duration::from_secs(1);
+        copy.activity.last_command = Some("get".into());
+        copy.activity.blocked = true;
+        shards[0].clients().add(client);
+        shards[1].clients().add(copy);
+
+        let activity = shards[0].client_activity();
+        assert_eq!(activity[&1].last_command, Some("get".into()));
+        assert!(activity[&1].blocked);
+        assert_eq!(
+            shards[0].clients().get(1).unwrap().activity.last_command,
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_local() {
+        let config = Arc::new(RwLock::new(Config::default()));
+        let mut shards = Database::sharded(config, Arc::new(Registry::new()), 2);
+        let db = &mut shards[0];
+        let home: ByteString = (0..)
+            .map(|n| ByteString::from(format!("key:{}", n)))
+            .find(|key| db.is_home(key))
+            .unwrap();
+        let away: ByteString = (0..)
+            .map(|n| ByteString::from(format!("key:{}", n)))
+            .find(|key| !db.is_home(key))
+            .unwrap();
+
+        assert!(db.is_local(&home));
+        assert!(!db.is_local(&away));
+        assert!(!db.holds_all_keys());
+
+        db.add_borrowed(Some(std::slice::from_ref(&away)));
+        assert!(db.is_local(&away));
+        assert!(!db.holds_all_keys());
+
+        db.add_borrowed(None);
+        assert!(db.holds_all_keys());
+
+        db.clear_borrowed();
+        assert!(!db.is_local(&away));
+        assert!(Database::new().holds_all_keys());
+    }
+
     #[test]
     fn test_expires() {
         let now = Instant::now();
@@ -339,6 +1237,31 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_counts_keyspace_hits_and_misses() {
+        let mut db = Database::new();
+        let key: ByteString = "x".into();
+        db.insert(key.clone(), 123.into());
+
+        db.get(&key);
+        db.get(&key);
+        db.get(&"y".into());
+        assert_eq!(db.stats().keyspace_hits, 2);
+        assert_eq!(db.stats().keyspace_misses, 1);
+
+        // Expired keys count as misses and are recorded as expired
+        db.set_expire(&key, Instant::now() - Duration::from_millis(1));
+        db.get(&key);
+        assert_eq!(db.stats().keyspace_hits, 2);
+        assert_eq!(db.stats().keyspace_misses, 2);
+        assert_eq!(db.stats().expired_keys, 1);
+
+        // Existence checks are not counted
+        db.exists(&key);
+        assert_eq!(db.stats().keyspace_hits, 2);
+        assert_eq!(db.stats().keyspace_misses, 2);
+    }
+
     #[test]
     fn test_get_mut() {
         let mut db = Database::new();
@@ -375,6 +1298,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_evict_while() {
+        let now = Instant::now();
+        let mut db = Database::new();
+        for key in &["a", "b", "c"] {
+            db.insert(ByteString::from(*key), 1.into());
+        }
+        db.set_expire(&"b".into(), now + Duration::from_secs(10));
+        db.set_expire(&"c".into(), now + Duration::from_secs(5));
+
+        // noeviction never evicts anything
+        assert!(!db.evict_while(MaxmemoryPolicy::NoEviction, || true));
+        assert_eq!(db.keys_count(), 3);
+
+        // volatile policies only consider keys with an expiry
+        let mut limit = 4;
+        assert!(!db.evict_while(MaxmemoryPolicy::VolatileRandom, || {
+            limit -= 1;
+            limit > 0
+        }));
+        assert_eq!(db.keys_count(), 1);
+        assert!(db.store.contains_key(&ByteString::from("a")));
+
+        // allkeys policies stop evicting once under the limit
+        db.insert("d".into(), 1.into());
+        let mut limit = 2;
+        assert!(db.evict_while(MaxmemoryPolicy::AllKeysRandom, || {
+            limit -= 1;
+            limit > 0
+        }));
+        assert_eq!(db.keys_count(), 1);
+        assert_eq!(db.stats().evicted_keys, 3);
+    }
+
+    #[test]
+    fn test_evict_while_volatile_ttl() {
+        let now = Instant::now();
+        let mut db = Database::new();
+        db.insert("a".into(), 1.into());
+        db.set_expire(&"a".into(), now + Duration::from_secs(10));
+
+        db.insert("b".into(), 1.into());
+        db.set_expire(&"b".into(), now + Duration::from_secs(5));
+        db.insert("c".into(), 1.into());
+
+        // The key closest to expiring is evicted first
+        let mut limit = 2;
+        assert!(db.evict_while(MaxmemoryPolicy::VolatileTtl, || {
+            limit -= 1;
+            limit > 0
+        }));
+        assert!(db.store.contains_key(&ByteString::from("a")));
+        assert!(!db.store.contains_key(&ByteString::from("b")));
+    }
+
     #[test]
     fn test_filter_keys() {
         let mut db = Database::new();
@@ -407,4 +1385,50 @@ mod tests {
             assert!(!result.contains(&key_c.as_ref()));
         }
     }
+
+    #[test]
+    fn test_notify_keyspace_event() {
+        use crate::clients::Client;
+        use tokio::sync::{mpsc, oneshot};
+
+        let mut db = Database::new();
+        let (kill_switch, _) = oneshot::channel();
+        let (push_sender, mut push_receiver) = mpsc::channel(8);
+        let addr = "127.0.0.1:1234".parse::<std::net::SocketAddr>().unwrap();
+        db.clients().add(Client::new(
+            1,
+            addr.into(),
+            addr.into(),
+            kill_switch,
+            push_sender,
+        ));
+        db.pubsub().subscribe(1, &"__keyevent@0__:expired".into());
+        db.pubsub().psubscribe(1, &"__keyspace@0__:*".into());
+
+        let key: ByteString = "x".into();
+        db.insert(key.clone(), 1.into());
+        db.set_expire(&key, Instant::now() - Duration::from_millis(1));
+
+        // Nothing is published until notifications are enabled
+        assert!(db.get(&key).is_none());
+        assert!(push_receiver.try_recv().is_err());
+
+        db.insert(key.clone(), 1.into());
+        db.set_expire(&key, Instant::now() - Duration::from_millis(1));
+        db.config_mut().notify_keyspace_events = notify::flags_from_str("KEx").unwrap();
+
+        assert!(db.get(&key).is_none());
+        assert_eq!(
+            push_receiver.try_recv().unwrap().as_string(),
+            "*4\r\n$8\r\npmessage\r\n$16\r\n__keyspace@0__:*\r\n$16\r\n__keyspace@0__:x\r\n$7\r\nexpired\r\n"
+        );
+        assert_eq!(
+            push_receiver.try_recv().unwrap().as_string(),
+            "*3\r\n$7\r\nmessage\r\n$22\r\n__keyevent@0__:expired\r\n$1\r\nx\r\n"
+        );
+
+        // Key misses are not in the enabled classes
+        assert!(db.get(&key).is_none());
+        assert!(push_receiver.try_recv().is_err());
+    }
 }
@@ -15,6 +15,7 @@ pub enum Error {
     Io(std::io::Error),
     CastingInt(std::num::TryFromIntError),
     Utf8Error(std::string::FromUtf8Error),
+    Fmt(std::fmt::Error),
 }
 
 impl StdError for Error {}
@@ -31,6 +32,7 @@ impl Display for Error {
             Self::Io(ref source) => write!(f, "{}", source),
             Self::CastingInt(ref source) => write!(f, "{}", source),
             Self::Utf8Error(ref source) => write!(f, "{}", source),
+            Self::Fmt(ref source) => write!(f, "{}", source),
         }
     }
 }
@@ -47,6 +49,7 @@ impl PartialEq for Error {
             (&Self::CastingInt(ref a), &Self::CastingInt(ref b)) => a == b,
             (&Self::Utf8Error(_), &Self::Utf8Error(_)) => false, // cannot be compared
             (&Self::Io(_), &Self::Io(_)) => false,               // cannot be compared
+            (&Self::Fmt(_), &Self::Fmt(_)) => true,
             _ => false,
         }
     }
@@ -91,6 +94,25 @@ impl From<std::string::FromUtf8Error> for Error {
     }
 }
 
+impl From<std::fmt::Error> for Error {
+    fn from(other: std::fmt::Error) -> Self {
+        Self::Fmt(other)
+    }
+}
+
+/// An error decoding a reply, as read by a `LocalClient`
+impl From<redis_clone_client::Error> for Error {
+    fn from(other: redis_clone_client::Error) -> Self {
+        match other {
+            redis_clone_client::Error::Io(source) => Self::Io(source),
+            redis_clone_client::Error::ConnectionClosed => {
+                Self::Proto(ProtoError::ConnectionClosed)
+            }
+            other => Self::Message(other.to_string()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,574 @@
+//! Libraries of named Lua functions, loaded with FUNCTION LOAD and run with
+//! FCALL, in the manner of Redis 7's `functions.c`. Unlike EVAL scripts,
+//! libraries are part of the dataset: they are dumped and restored as RDB
+//! payloads, so they can be saved along with the keys.
+
+use crate::{
+    db::Database,
+    rdb::{self, RDB_OPCODE_FUNCTION2},
+    response::Response,
+    scripting::{error_message, LuaEngine, ScriptBusy, ScriptCall, ScriptKind},
+};
+use byte_string::ByteString;
+use mlua::{ChunkMode, Function, RegistryKey, Table, Value, Variadic};
+use std::{cell::RefCell, collections::BTreeMap, sync::Arc};
+
+/// The flags a function may be registered with
+const FUNCTION_FLAGS: &[&str] = &[
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+pub struct FunctionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+    callback: RegistryKey,
+}
+
+impl FunctionInfo {
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+}
+
+pub struct Library {
+    pub name: String,
+    pub code: ByteString,
+    pub functions: BTreeMap<String, FunctionInfo>,
+}
+
+/// What FUNCTION RESTORE does with the libraries that already exist
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestorePolicy {
+    /// Fail if a library already exists
+    Append,
+    /// Replace libraries with the same name
+    Replace,
+    /// Delete every library first
+    Flush,
+}
+
+pub struct Functions {
+    engine: LuaEngine,
+    libraries: BTreeMap<String, Library>,
+}
+
+impl Functions {
+    pub fn new(busy: Arc<ScriptBusy>) -> Self {
+        Self {
+            engine: LuaEngine::new(busy),
+            libraries: BTreeMap::new(),
+        }
+    }
+
+    /// Loads a library, returning its name
+    pub fn load(&mut self, code: &ByteString, replace: bool) -> Result<String, String> {
+        let library = self.compile(code)?;
+        let name = library.name.clone();
+        self.install(vec![library], replace)?;
+
+        Ok(name)
+    }
+
+    pub fn delete(&mut self, name: &str) -> bool {
+        self.libraries.remove(name).is_some()
+    }
+
+    /// Deletes every library, starting again with a fresh interpreter
+    pub fn flush(&mut self) {
+        *self = Self::new(Arc::clone(self.engine.busy()));
+    }
+
+    pub fn libraries(&self) -> impl Iterator<Item = &Library> {
+        self.libraries.values()
+    }
+
+    fn find(&self, name: &str) -> Option<&FunctionInfo> {
+        self.libraries
+            .values()
+            .find_map(|library| library.functions.get(name))
+    }
+
+    /// Runs a function, adding whatever it returns to the response. Read
+    /// only calls, as made by FCALL_RO, may only run `no-writes` functions.
+    pub fn call(
+        &self,
+        db: &mut Database,
+        name: &str,
+        keys: &[ByteString],
+        args: &[ByteString],
+        read_only: bool,
+        response: &mut Response,
+    ) {
+        let function = match self.find(name) {
+            Some(function) => function,
+            None => {
+                response.add_error("ERR Function not found");
+                return;
+            }
+        };
+
+        let no_writes = function.has_flag("no-writes");
+        if read_only && !no_writes {
+            response.add_error("ERR Can not execute a script with write flag using *_ro command.");
+            return;
+        }
+
+        let call = ScriptCall {
+            kind: ScriptKind::Function,
+            name,
+            keys,
+            args,
+            read_only: no_writes,
+        };
+        self.engine.run(db, &function.callback, call, response);
+    }
+
+    /// Serializes every library in the same format as Redis's FUNCTION DUMP
+    pub fn dump(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        self.write_libraries(&mut payload);
+        rdb::write_footer(&mut payload);
+
+        payload
+    }
+
+    /// Writes every library as an RDB opcode, as in a DUMP payload or an RDB
+    /// file
+    pub fn write_libraries(&self, out: &mut Vec<u8>) {
+        for library in self.libraries.values() {
+            out.push(RDB_OPCODE_FUNCTION2);
+            rdb::write_string(out, &library.code);
+        }
+    }
+
+    /// Adds the libraries read from an RDB file
+    pub fn load_libraries(&mut self, codes: &[ByteString]) -> Result<(), String> {
+        let libraries = codes
+            .iter()
+            .map(|code| self.compile(code))
+            .collect::<Result<_, _>>()?;
+        self.install(libraries, false)
+    }
+
+    pub fn restore(&mut self, payload: &[u8], policy: RestorePolicy) -> Result<(), String> {
+        let body = rdb::verify_footer(payload)
+            .map_err(|_| "ERR payload version or checksum are wrong".to_owned())?;
+
+        let mut reader = rdb::Reader::new(body);
+        let mut libraries = vec![];
+        while !reader.is_empty() {
+            let code = match reader.read_u8() {
+                Ok(RDB_OPCODE_FUNCTION2) => reader.read_string(),
+                _ => return Err("ERR given type is not a function".to_owned()),
+            }
+            .map_err(|_| "ERR payload version or checksum are wrong".to_owned())?;
+
+            libraries.push(self.compile(&code)?);
+        }
+
+        if policy == RestorePolicy::Flush {
+            self.libraries.clear();
+        }
+        self.install(libraries, policy == RestorePolicy::Replace)
+    }
+
+    /// Adds compiled libraries, unless any of their names, or the names of
+    /// their functions, are taken. Existing libraries with the same names are
+    /// replaced when allowed.
+    fn install(&mut self, libraries: Vec<Library>, replace: bool) -> Result<(), String> {
+        for (i, library) in libraries.iter().enumerate() {
+            let taken = libraries[..i]
+                .iter()
+                .any(|other| other.name == library.name)
+                || (!replace && self.libraries.contains_key(&library.name));
+            if taken {
+                return Err(format!("ERR Library '{}' already exists", library.name));
+            }
+
+            for name in library.functions.keys() {
+                let in_new = libraries[..i]
+                    .iter()
+                    .any(|other| other.functions.contains_key(name));
+                let in_existing = self.libraries.values().any(|existing| {
+                    existing.functions.contains_key(name)
+                        && !(replace && libraries.iter().any(|l| l.name == existing.name))
+                });
+
+                if in_new || in_existing {
+                    return Err(format!("ERR Function {} already exists", name));
+                }
+            }
+        }
+
+        for library in libraries {
+            self.libraries.insert(library.name.clone(), library);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the code of a library to collect the functions it registers
+    fn compile(&self, code: &ByteString) -> Result<Library, String> {
+        let (name, body) = parse_metadata(code)?;
+        let lua = self.engine.lua();
+        let registered = RefCell::new(BTreeMap::new());
+
+        lua.scope(|scope| {
+            // The library runs in an environment of its own, in which the
+            // redis library can only register functions
+            let loader = lua.create_table()?;
+            loader.raw_set(
+                "register_function",
+                scope.create_function(|lua, args: Variadic<Value>| {
+                    let function = registration(lua, args)?;
+                    let mut registered = registered.borrow_mut();
+                    if registered.contains_key(&function.name) {
+                        return Err(mlua::Error::RuntimeError(
+                            "Function already exists in the library".to_owned(),
+                        ));
+                    }
+                    registered.insert(function.name.clone(), function);
+                    Ok(())
+                })?,
+            )?;
+            let redis: Table = lua.globals().raw_get("redis")?;
+            for field in &[
+                "log",
+                "LOG_DEBUG",
+                "LOG_VERBOSE",
+                "LOG_NOTICE",
+                "LOG_WARNING",
+            ] {
+                loader.raw_set(*field, redis.raw_get::<_, Value>(*field)?)?;
+            }
+
+            let env = library_env(lua)?;
+            env.raw_set("redis", loader)?;
+
+            let chunk = lua
+                .load(body)
+                .set_name("@user_function")
+                .set_mode(ChunkMode::Text)
+                .set_environment(env.clone())
+                .into_function()
+                .map_err(|e| {
+                    mlua::Error::RuntimeError(format!(
+                        "Error compiling function: {}",
+                        error_message(&e)
+                    ))
+                })?;
+            chunk.call::<_, ()>(())?;
+
+            // Once loaded, the functions see the full redis library
+            env.raw_set("redis", redis)
+        })
+        .map_err(|e| format!("ERR {}", error_message(&e)))?;
+
+        let functions = registered.into_inner();
+        if functions.is_empty() {
+            return Err("ERR No functions registered".to_owned());
+        }
+
+        Ok(Library {
+            name,
+            code: code.clone(),
+            functions,
+        })
+    }
+}
+
+/// Reads the `#!lua name=<library>` line at the start of a library, returning
+/// the name and the code after it
+fn parse_metadata(code: &[u8]) -> Result<(String, &[u8]), String> {
+    if !code.starts_with(b"#!") {
+        return Err("ERR Missing library metadata".to_owned());
+    }
+
+    // The newline is left in the code so that line numbers stay the same
+    let end = code.iter().position(|&b| b == b'\n').unwrap_or(code.len());
+    let shebang = String::from_utf8_lossy(&code[2..end]);
+    let mut parts = shebang.split_whitespace();
+
+    let engine = parts.next().unwrap_or("");
+    if !engine.eq_ignore_ascii_case("lua") {
+        return Err(format!("ERR Engine '{}' not found", engine));
+    }
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_owned()),
+            None => return Err(format!("ERR Invalid metadata value given: {}", part)),
+        }
+    }
+
+    let name = name.ok_or("ERR Library name was not given")?;
+    if !is_valid_name(&name) {
+        return Err("ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long".to_owned());
+    }
+
+    Ok((name, &code[end..]))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// An environment which falls back to the globals, but, like them, can't
+/// have new variables added
+fn library_env(lua: &mlua::Lua) -> mlua::Result<Table<'_>> {
+    let env = lua.create_table()?;
+    let meta = lua.create_table()?;
+
+    meta.raw_set("__index", lua.globals())?;
+    meta.raw_set(
+        "__newindex",
+        lua.create_function(|_, (_, name): (Value, mlua::String)| -> mlua::Result<()> {
+            Err(mlua::Error::RuntimeError(format!(
+                "Script attempted to create global variable '{}'",
+                name.to_string_lossy()
+            )))
+        })?,
+    )?;
+    env.set_metatable(Some(meta));
+
+    Ok(env)
+}
+
+/// Reads the arguments of `redis.register_function`, either a name and a
+/// callback or a table with `function_name`, `callback`, `flags` and
+/// `description` fields
+fn registration(lua: &mlua::Lua, args: Variadic<Value>) -> mlua::Result<FunctionInfo> {
+    let fail = |msg: &str| mlua::Error::RuntimeError(msg.to_owned());
+
+    let (name, callback, flags, description) = match args.as_slice() {
+        [Value::Table(table)] => {
+            for pair in table.clone().pairs::<Value, Value>() {
+                let (key, _) = pair?;
+                let known = match key {
+                    Value::String(ref key) => matches!(
+                        key.as_bytes(),
+                        b"function_name" | b"callback" | b"flags" | b"description"
+                    ),
+                    _ => false,
+                };
+                if !known {
+                    return Err(fail("unknown argument given to redis.register_function"));
+                }
+            }
+
+            (
+                table.raw_get("function_name")?,
+                table.raw_get("callback")?,
+                table.raw_get("flags")?,
+                table.raw_get("description")?,
+            )
+        }
+        [name, callback] => (name.clone(), callback.clone(), Value::Nil, Value::Nil),
+        _ => return Err(fail("wrong number of arguments to redis.register_function")),
+    };
+
+    let name = match name {
+        Value::String(name) => name.to_str()?.to_owned(),
+        _ => {
+            return Err(fail(
+                "function_name argument given to redis.register_function must be a string",
+            ))
+        }
+    };
+    if !is_valid_name(&name) {
+        return Err(fail("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+
+    let callback = match callback {
+        Value::Function(callback) => callback,
+        _ => {
+            return Err(fail(
+                "callback argument given to redis.register_function must be a function",
+            ))
+        }
+    };
+
+    let flags = match flags {
+        Value::Nil => vec![],
+        Value::Table(flags) => {
+            let mut names = vec![];
+            for flag in flags.sequence_values::<mlua::String>() {
+                let flag = flag?.to_str()?.to_owned();
+                if !FUNCTION_FLAGS.contains(&flag.as_str()) {
+                    return Err(fail("unknown flag given"));
+                }
+                names.push(flag);
+            }
+            names
+        }
+        _ => return Err(fail(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+
+    let description = match description {
+        Value::Nil => None,
+        Value::String(description) => Some(description.to_str()?.to_owned()),
+        _ => {
+            return Err(fail(
+                "description argument given to redis.register_function must be a string",
+            ))
+        }
+    };
+
+    Ok(FunctionInfo {
+        name,
+        description,
+        flags,
+        callback: lua.create_registry_value::<Function>(callback)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIBRARY: &str = "#!lua name=mylib
+redis.register_function('echo', function(keys, args) return args[1] end)
+redis.register_function{
+    function_name = 'getkey',
+    callback = function(keys) return redis.call('get', keys[1]) end,
+    flags = { 'no-writes' },
+    description = 'Gets a key',
+}
+redis.register_function('setkey', function(keys, args) return redis.call('set', keys[1], args[1]) end)
+";
+
+    fn call(
+        db: &mut Database,
+        name: &str,
+        keys: &[&str],
+        args: &[&str],
+        read_only: bool,
+    ) -> String {
+        let functions = db.functions();
+        let functions = functions.lock().unwrap();
+        let keys: Vec<ByteString> = keys.iter().map(|&k| k.into()).collect();
+        let args: Vec<ByteString> = args.iter().map(|&a| a.into()).collect();
+        let mut response = Response::new();
+
+        functions.call(db, name, &keys, &args, read_only, &mut response);
+        response.as_string()
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        assert_eq!(
+            parse_metadata(b"#!lua name=lib\nreturn"),
+            Ok(("lib".to_owned(), &b"\nreturn"[..]))
+        );
+        assert_eq!(
+            parse_metadata(b"return"),
+            Err("ERR Missing library metadata".to_owned())
+        );
+        assert_eq!(
+            parse_metadata(b"#!js name=lib"),
+            Err("ERR Engine 'js' not found".to_owned())
+        );
+        assert_eq!(
+            parse_metadata(b"#!lua"),
+            Err("ERR Library name was not given".to_owned())
+        );
+        assert_eq!(
+            parse_metadata(b"#!lua name=lib foo=bar"),
+            Err("ERR Invalid metadata value given: foo=bar".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_load_and_call() {
+        let mut db = Database::new();
+        let functions = db.functions();
+
+        assert_eq!(
+            functions.lock().unwrap().load(&LIBRARY.into(), false),
+            Ok("mylib".to_owned())
+        );
+        assert_eq!(
+            functions.lock().unwrap().load(&LIBRARY.into(), false),
+            Err("ERR Library 'mylib' already exists".to_owned())
+        );
+        assert!(functions
+            .lock()
+            .unwrap()
+            .load(&LIBRARY.into(), true)
+            .is_ok());
+
+        assert_eq!(call(&mut db, "echo", &[], &["hi"], false), "$2\r\nhi\r\n");
+        assert_eq!(call(&mut db, "setkey", &["k"], &["v"], false), "+OK\r\n");
+        assert_eq!(call(&mut db, "getkey", &["k"], &[], true), "$1\r\nv\r\n");
+        assert_eq!(
+            call(&mut db, "setkey", &["k"], &["v"], true),
+            "-ERR Can not execute a script with write flag using *_ro command.\r\n"
+        );
+        assert_eq!(
+            call(&mut db, "nosuchfunction", &[], &[], false),
+            "-ERR Function not found\r\n"
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let mut functions = Functions::new(Arc::new(ScriptBusy::default()));
+
+        assert_eq!(
+            functions.load(&"#!lua name=empty\nlocal x = 1".into(), false),
+            Err("ERR No functions registered".to_owned())
+        );
+        assert!(functions
+            .load(&"#!lua name=bad\nreturn +".into(), false)
+            .unwrap_err()
+            .starts_with("ERR Error compiling function"));
+        assert!(functions
+            .load(&"#!lua name=bad\nredis.call('ping')".into(), false)
+            .is_err());
+        assert!(functions
+            .load(&"#!lua name=bad\nx = 1".into(), false)
+            .unwrap_err()
+            .contains("Script attempted to create global variable 'x'"));
+
+        functions.load(&LIBRARY.into(), false).unwrap();
+        assert_eq!(
+            functions.load(
+                &"#!lua name=other\nredis.register_function('echo', function() end)".into(),
+                false
+            ),
+            Err("ERR Function echo already exists".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_dump_and_restore() {
+        let mut functions = Functions::new(Arc::new(ScriptBusy::default()));
+        functions.load(&LIBRARY.into(), false).unwrap();
+        let payload = functions.dump();
+
+        assert_eq!(
+            functions.restore(&payload, RestorePolicy::Append),
+            Err("ERR Library 'mylib' already exists".to_owned())
+        );
+        assert_eq!(functions.restore(&payload, RestorePolicy::Replace), Ok(()));
+
+        functions.flush();
+        assert_eq!(functions.libraries().count(), 0);
+        assert_eq!(functions.restore(&payload, RestorePolicy::Append), Ok(()));
+        assert_eq!(functions.libraries().count(), 1);
+
+        assert_eq!(
+            functions.restore(&payload[1..], RestorePolicy::Flush),
+            Err("ERR payload version or checksum are wrong".to_owned())
+        );
+        assert_eq!(functions.libraries().count(), 1);
+    }
+}
@@ -0,0 +1,502 @@
+//! Geohash encoding of coordinates into the 52 bit integer scores of a
+//! sorted set, and the search for the members within an area, as Redis's
+//! `geohash.c` and `geohash_helper.c` do it, so that positions, hashes and
+//! the order of results agree with Redis.
+//!
+//! A hash interleaves the bits of the latitude, in the even bits, with those
+//! of the longitude, in the odd bits. Each step halves the cells along both
+//! axes, and scores use the full 26 steps.
+
+use std::f64::consts::PI;
+
+pub const LONG_MIN: f64 = -180.0;
+pub const LONG_MAX: f64 = 180.0;
+pub const LAT_MIN: f64 = -85.051_128_78;
+pub const LAT_MAX: f64 = 85.051_128_78;
+
+/// The number of steps of the hashes stored as scores
+pub const STEP_MAX: u8 = 26;
+
+const EARTH_RADIUS_IN_METERS: f64 = 6_372_797.560_856;
+const MERCATOR_MAX: f64 = 20_037_726.37;
+
+const ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Range {
+    min: f64,
+    max: f64,
+}
+
+const LONG_RANGE: Range = Range {
+    min: LONG_MIN,
+    max: LONG_MAX,
+};
+const LAT_RANGE: Range = Range {
+    min: LAT_MIN,
+    max: LAT_MAX,
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct HashBits {
+    bits: u64,
+    step: u8,
+}
+
+impl HashBits {
+    fn is_zero(self) -> bool {
+        self.bits == 0 && self.step == 0
+    }
+
+    /// The range of 52 bit scores covered by the cell, `min <= score < max`
+    fn scores(self) -> (f64, f64) {
+        let shift = 52 - u32::from(self.step) * 2;
+        (
+            (self.bits << shift) as f64,
+            ((self.bits + 1) << shift) as f64,
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Area {
+    longitude: Range,
+    latitude: Range,
+}
+
+/// The shape searched by GEOSEARCH, with its dimensions in meters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+pub fn is_valid(longitude: f64, latitude: f64) -> bool {
+    (LONG_MIN..=LONG_MAX).contains(&longitude) && (LAT_MIN..=LAT_MAX).contains(&latitude)
+}
+
+fn spread(value: u32) -> u64 {
+    let mut x = u64::from(value);
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+fn squash(value: u64) -> u32 {
+    let mut x = value & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    ((x | (x >> 16)) & 0x0000_0000_ffff_ffff) as u32
+}
+
+fn encode_in(
+    longitude: f64,
+    latitude: f64,
+    step: u8,
+    long_range: Range,
+    lat_range: Range,
+) -> HashBits {
+    let scale = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_range.min) / (lat_range.max - lat_range.min) * scale;
+    let long_offset = (longitude - long_range.min) / (long_range.max - long_range.min) * scale;
+
+    HashBits {
+        bits: spread(lat_offset as u32) | (spread(long_offset as u32) << 1),
+        step,
+    }
+}
+
+fn encode(longitude: f64, latitude: f64, step: u8) -> HashBits {
+    encode_in(longitude, latitude, step, LONG_RANGE, LAT_RANGE)
+}
+
+fn decode(hash: HashBits) -> Area {
+    let scale = (1u64 << hash.step) as f64;
+    let lat = f64::from(squash(hash.bits));
+    let long = f64::from(squash(hash.bits >> 1));
+    let lat_scale = LAT_RANGE.max - LAT_RANGE.min;
+    let long_scale = LONG_RANGE.max - LONG_RANGE.min;
+
+    Area {
+        latitude: Range {
+            min: LAT_RANGE.min + (lat / scale) * lat_scale,
+            max: LAT_RANGE.min + ((lat + 1.0) / scale) * lat_scale,
+        },
+        longitude: Range {
+            min: LONG_RANGE.min + (long / scale) * long_scale,
+            max: LONG_RANGE.min + ((long + 1.0) / scale) * long_scale,
+        },
+    }
+}
+
+/// The 52 bit score of a position, which must be valid
+pub fn score(longitude: f64, latitude: f64) -> f64 {
+    encode(longitude, latitude, STEP_MAX).bits as f64
+}
+
+/// The position at the center of the cell of a score
+pub fn position(score: f64) -> (f64, f64) {
+    let area = decode(HashBits {
+        bits: score as u64,
+        step: STEP_MAX,
+    });
+
+    let longitude = (area.longitude.min + area.longitude.max) / 2.0;
+    let latitude = (area.latitude.min + area.latitude.max) / 2.0;
+    (
+        longitude.clamp(LONG_MIN, LONG_MAX),
+        latitude.clamp(LAT_MIN, LAT_MAX),
+    )
+}
+
+/// The standard 11 character geohash of a score, which unlike the score
+/// covers latitudes from -90 to 90
+pub fn to_string(score: f64) -> String {
+    let (longitude, latitude) = position(score);
+    let hash = encode_in(
+        longitude,
+        latitude,
+        STEP_MAX,
+        LONG_RANGE,
+        Range {
+            min: -90.0,
+            max: 90.0,
+        },
+    );
+
+    (0..11)
+        .map(|i| {
+            // The 52 bits fill 10 characters and a bit, the last is 0
+            let index = if i == 10 {
+                0
+            } else {
+                (hash.bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            char::from(ALPHABET[index as usize])
+        })
+        .collect()
+}
+
+fn deg_rad(degrees: f64) -> f64 {
+    degrees * PI / 180.0
+}
+
+fn rad_deg(radians: f64) -> f64 {
+    radians / (PI / 180.0)
+}
+
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (deg_rad(lat2) - deg_rad(lat1)).abs()
+}
+
+/// The haversine distance in meters between two positions
+pub fn distance(long1: f64, lat1: f64, long2: f64, lat2: f64) -> f64 {
+    let (lat1r, long1r) = (deg_rad(lat1), deg_rad(long1));
+    let (lat2r, long2r) = (deg_rad(lat2), deg_rad(long2));
+    let v = ((long2r - long1r) / 2.0).sin();
+    // Along a meridian only the latitude matters
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+
+    let u = ((lat2r - lat1r) / 2.0).sin();
+    let a = u * u + lat1r.cos() * lat2r.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+impl Shape {
+    /// The distance of a position from the center of the shape, if within it
+    pub fn distance_if_within(
+        self,
+        center: (f64, f64),
+        longitude: f64,
+        latitude: f64,
+    ) -> Option<f64> {
+        let (center_long, center_lat) = center;
+
+        match self {
+            Shape::Radius(radius) => {
+                let distance = distance(center_long, center_lat, longitude, latitude);
+                (distance <= radius).then_some(distance)
+            }
+            Shape::Box { width, height } => {
+                if lat_distance(latitude, center_lat) > height / 2.0
+                    || distance(longitude, latitude, center_long, latitude) > width / 2.0
+                {
+                    return None;
+                }
+                Some(distance(center_long, center_lat, longitude, latitude))
+            }
+        }
+    }
+
+    /// The longitudes and latitudes bounding the shape around a center
+    fn bounding_box(self, (longitude, latitude): (f64, f64)) -> (Range, Range) {
+        let (width, height) = match self {
+            Shape::Radius(radius) => (radius, radius),
+            Shape::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+
+        let lat_delta = rad_deg(height / EARTH_RADIUS_IN_METERS);
+        let long_delta_top =
+            rad_deg(width / EARTH_RADIUS_IN_METERS / deg_rad(latitude + lat_delta).cos());
+        let long_delta_bottom =
+            rad_deg(width / EARTH_RADIUS_IN_METERS / deg_rad(latitude - lat_delta).cos());
+        // The hemispheres widen in opposite directions
+        let long_delta = if latitude < 0.0 {
+            long_delta_bottom
+        } else {
+            long_delta_top
+        };
+
+        (
+            Range {
+                min: longitude - long_delta,
+                max: longitude + long_delta,
+            },
+            Range {
+                min: latitude - lat_delta,
+                max: latitude + lat_delta,
+            },
+        )
+    }
+
+    /// The distance from the center to the furthest point of the shape
+    fn radius(self) -> f64 {
+        match self {
+            Shape::Radius(radius) => radius,
+            Shape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+        }
+    }
+}
+
+fn estimate_step(mut range: f64, latitude: f64) -> u8 {
+    if range == 0.0 {
+        return STEP_MAX;
+    }
+
+    let mut step: i32 = 1;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    // Make sure the range is included in most of the base cases
+    step -= 2;
+
+    // Cells are narrower towards the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+
+    step.clamp(1, i32::from(STEP_MAX)) as u8
+}
+
+fn move_x(hash: HashBits, d: i8) -> HashBits {
+    let x = hash.bits & 0xaaaa_aaaa_aaaa_aaaa;
+    let y = hash.bits & 0x5555_5555_5555_5555;
+    let zz = 0x5555_5555_5555_5555u64 >> (64 - u32::from(hash.step) * 2);
+
+    let x = if d > 0 {
+        x.wrapping_add(zz + 1)
+    } else {
+        (x | zz).wrapping_sub(zz + 1)
+    };
+    let x = x & (0xaaaa_aaaa_aaaa_aaaau64 >> (64 - u32::from(hash.step) * 2));
+
+    HashBits {
+        bits: x | y,
+        step: hash.step,
+    }
+}
+
+fn move_y(hash: HashBits, d: i8) -> HashBits {
+    let x = hash.bits & 0xaaaa_aaaa_aaaa_aaaa;
+    let y = hash.bits & 0x5555_5555_5555_5555;
+    let zz = 0xaaaa_aaaa_aaaa_aaaau64 >> (64 - u32::from(hash.step) * 2);
+
+    let y = if d > 0 {
+        y.wrapping_add(zz + 1)
+    } else {
+        (y | zz).wrapping_sub(zz + 1)
+    };
+    let y = y & (0x5555_5555_5555_5555u64 >> (64 - u32::from(hash.step) * 2));
+
+    HashBits {
+        bits: x | y,
+        step: hash.step,
+    }
+}
+
+/// The cells to scan for members within the shape: the one containing the
+/// center, then north, south, east, west, north east, north west, south east
+/// and south west of it, as Redis orders them
+fn search_cells(shape: Shape, center: (f64, f64)) -> Vec<HashBits> {
+    let (longitude, latitude) = center;
+    let (long_bounds, lat_bounds) = shape.bounding_box(center);
+    let mut step = estimate_step(shape.radius(), latitude);
+
+    let neighbours = |hash: HashBits| {
+        [
+            hash,
+            move_y(hash, 1),
+            move_y(hash, -1),
+            move_x(hash, 1),
+            move_x(hash, -1),
+            move_y(move_x(hash, 1), 1),
+            move_y(move_x(hash, -1), 1),
+            move_y(move_x(hash, 1), -1),
+            move_y(move_x(hash, -1), -1),
+        ]
+    };
+
+    let mut cells = neighbours(encode(longitude, latitude, step));
+
+    // The estimated step may be too coarse when the shape reaches the edge
+    // of the cells around the center
+    let [_, north, south, east, west, ..] = cells.map(decode);
+    let too_coarse = north.latitude.max < lat_bounds.max
+        || south.latitude.min > lat_bounds.min
+        || east.longitude.max < long_bounds.max
+        || west.longitude.min > long_bounds.min;
+    if step > 1 && too_coarse {
+        step -= 1;
+        cells = neighbours(encode(longitude, latitude, step));
+    }
+
+    // Leave out the cells the shape doesn't reach
+    if step >= 2 {
+        let area = decode(cells[0]);
+        let mut exclude =
+            |indices: [usize; 3]| indices.iter().for_each(|&i| cells[i] = HashBits::default());
+        if area.latitude.min < lat_bounds.min {
+            exclude([2, 7, 8]);
+        }
+        if area.latitude.max > lat_bounds.max {
+            exclude([1, 5, 6]);
+        }
+        if area.longitude.min < long_bounds.min {
+            exclude([4, 8, 6]);
+        }
+        if area.longitude.max > long_bounds.max {
+            exclude([3, 7, 5]);
+        }
+    }
+
+    cells.to_vec()
+}
+
+/// The ranges of scores holding the members which may be within the shape,
+/// in the order Redis scans them. Members found in them still need checking
+/// with `Shape::distance_if_within`.
+pub fn search_ranges(shape: Shape, center: (f64, f64)) -> Vec<(f64, f64)> {
+    let cells = search_cells(shape, center);
+    let mut ranges = Vec::with_capacity(cells.len());
+    let mut last: Option<HashBits> = None;
+
+    for cell in cells {
+        // Neighbouring cells can be the same one with huge shapes
+        if cell.is_zero() || last == Some(cell) {
+            continue;
+        }
+
+        ranges.push(cell.scores());
+        last = Some(cell);
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Palermo and Catania, from the Redis documentation
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    #[test]
+    fn test_score_and_position() {
+        let score = score(PALERMO.0, PALERMO.1);
+        assert_eq!(score, 3_479_099_956_230_698.0);
+
+        let (longitude, latitude) = position(score);
+        assert_eq!(format!("{:.17}", longitude), "13.36138933897018433");
+        assert_eq!(format!("{:.17}", latitude), "38.11555639549629859");
+    }
+
+    #[test]
+    fn test_to_string() {
+        assert_eq!(to_string(score(PALERMO.0, PALERMO.1)), "sqc8b49rny0");
+        assert_eq!(to_string(score(CATANIA.0, CATANIA.1)), "sqdtr74hyu0");
+    }
+
+    #[test]
+    fn test_distance() {
+        let (palermo, catania) = (
+            position(score(PALERMO.0, PALERMO.1)),
+            position(score(CATANIA.0, CATANIA.1)),
+        );
+        let distance = distance(palermo.0, palermo.1, catania.0, catania.1);
+        assert_eq!(format!("{:.4}", distance), "166274.1516");
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(is_valid(180.0, 85.05112878));
+        assert!(!is_valid(180.1, 0.0));
+        assert!(!is_valid(0.0, 85.06));
+    }
+
+    #[test]
+    fn test_shapes() {
+        let center = (15.0, 37.0);
+        let in_km = |distance: Option<f64>| distance.map(|d| format!("{:.4}", d / 1000.0));
+        let catania = position(score(CATANIA.0, CATANIA.1));
+        let palermo = position(score(PALERMO.0, PALERMO.1));
+
+        let shape = Shape::Radius(200_000.0);
+        assert_eq!(
+            in_km(shape.distance_if_within(center, catania.0, catania.1)),
+            Some("56.4413".into())
+        );
+        assert_eq!(
+            in_km(shape.distance_if_within(center, palermo.0, palermo.1)),
+            Some("190.4424".into())
+        );
+        assert_eq!(
+            Shape::Radius(100_000.0).distance_if_within(center, palermo.0, palermo.1),
+            None
+        );
+
+        let shape = Shape::Box {
+            width: 200_000.0,
+            height: 200_000.0,
+        };
+        assert!(shape
+            .distance_if_within(center, catania.0, catania.1)
+            .is_some());
+        assert!(shape
+            .distance_if_within(center, palermo.0, palermo.1)
+            .is_none());
+    }
+
+    #[test]
+    fn test_search_ranges_cover_the_shape() {
+        let shape = Shape::Radius(200_000.0);
+        let center = (15.0, 37.0);
+        let ranges = search_ranges(shape, center);
+        assert!(!ranges.is_empty() && ranges.len() <= 9);
+
+        for &(longitude, latitude) in &[PALERMO, CATANIA] {
+            let score = score(longitude, latitude);
+            assert!(ranges.iter().any(|&(min, max)| min <= score && score < max));
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! The hash data type. Small hashes are packed into a listpack of alternating
+//! fields and values, and converted to a hash table once they have more than
+//! `hash-max-listpack-entries` fields or a value longer than
+//! `hash-max-listpack-value`.
+
+use crate::listpack::{Listpack, ListpackLimits};
+use byte_string::ByteString;
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Hash {
+    Listpack(Listpack),
+    Table(HashMap<ByteString, ByteString>),
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Self::Listpack(Listpack::new())
+    }
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Listpack(_) => "listpack",
+            Self::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Listpack(listpack) => listpack.len() / 2,
+            Self::Table(table) => table.len(),
+        }
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&[u8]> {
+        match self {
+            Self::Listpack(listpack) => listpack
+                .pairs()
+                .find(|(f, _)| *f == field)
+                .map(|(_, value)| value),
+            Self::Table(table) => table.get(field).map(|value| value.as_ref()),
+        }
+    }
+
+    /// The fields and values, in insertion order for a listpack
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        match self {
+            Self::Listpack(listpack) => Box::new(listpack.pairs()),
+            Self::Table(table) => Box::new(
+                table
+                    .iter()
+                    .map(|(field, value)| (field.as_ref(), value.as_ref())),
+            ),
+        }
+    }
+
+    /// Sets a field, returning true if it is new
+    pub fn insert(&mut self, field: ByteString, value: ByteString, limits: ListpackLimits) -> bool {
+        let listpack = match self {
+            Self::Listpack(listpack) => listpack,
+            Self::Table(table) => return table.insert(field, value).is_none(),
+        };
+
+        let position = listpack.pairs().position(|(f, _)| f == field.as_ref());
+        let added = match position {
+            Some(index) => {
+                listpack.replace(2 * index + 1, &value);
+                false
+            }
+            None => {
+                listpack.push_back(&field);
+                listpack.push_back(&value);
+                true
+            }
+        };
+
+        if listpack.len() / 2 > limits.max_entries
+            || field.len() > limits.max_value
+            || value.len() > limits.max_value
+        {
+            let table = listpack
+                .pairs()
+                .map(|(field, value)| (ByteString::from(field), ByteString::from(value)))
+                .collect();
+            *self = Self::Table(table);
+        }
+
+        added
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ListpackLimits = ListpackLimits {
+        max_entries: 2,
+        max_value: 8,
+        max_bytes: usize::MAX,
+    };
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut hash = Hash::new();
+
+        assert!(hash.insert("a".into(), "1".into(), LIMITS));
+        assert!(hash.insert("b".into(), "2".into(), LIMITS));
+        assert!(!hash.insert("a".into(), "3".into(), LIMITS));
+        assert_eq!(hash.encoding(), "listpack");
+        assert_eq!(hash.len(), 2);
+        assert_eq!(hash.get(b"a"), Some(&b"3"[..]));
+        assert_eq!(hash.get(b"c"), None);
+        assert_eq!(
+            hash.iter().collect::<Vec<_>>(),
+            vec![(&b"a"[..], &b"3"[..]), (&b"b"[..], &b"2"[..])]
+        );
+    }
+
+    #[test]
+    fn test_converts_past_the_limits() {
+        let mut hash = Hash::new();
+        hash.insert("a".into(), "1".into(), LIMITS);
+        hash.insert("b".into(), "2".into(), LIMITS);
+        assert!(hash.insert("c".into(), "3".into(), LIMITS));
+        assert_eq!(hash.encoding(), "hashtable");
+        assert_eq!(hash.len(), 3);
+        assert_eq!(hash.get(b"c"), Some(&b"3"[..]));
+        assert!(!hash.insert("c".into(), "4".into(), LIMITS));
+
+        let mut hash = Hash::new();
+        hash.insert("a".into(), "a long value".into(), LIMITS);
+        assert_eq!(hash.encoding(), "hashtable");
+
+        let mut hash = Hash::new();
+        hash.insert("a long field".into(), "1".into(), LIMITS);
+        assert_eq!(hash.encoding(), "hashtable");
+    }
+}
@@ -0,0 +1,622 @@
+//! HyperLogLog cardinality estimation, stored in strings with exactly the
+//! byte layout of Redis's `hyperloglog.c` so that sketches can be exchanged.
+//!
+//! A sketch is a 16 byte header followed by 16384 six bit registers, either
+//! packed as they are (dense) or run-length encoded (sparse):
+//!
+//! ```text
+//! +------+---+-----+----------+
+//! | HYLL | E | N/U | Cardin.  |
+//! +------+---+-----+----------+
+//! ```
+//!
+//! `E` is the encoding, and the last 8 bytes cache the cardinality, little
+//! endian, which is invalid when the most significant bit is set.
+//!
+//! The sparse encoding uses three opcodes:
+//!
+//! * ZERO `00xxxxxx`: 1 to 64 registers set to 0
+//! * XZERO `01xxxxxx yyyyyyyy`: 1 to 16384 registers set to 0
+//! * VAL `1vvvvvxx`: 1 to 4 registers set to the value 1 to 32
+//!
+//! A sparse sketch is converted to a dense one when it grows past
+//! `hll-sparse-max-bytes`, or a register needs a value beyond 32.
+
+use std::convert::TryInto;
+
+/// The number of bits of the hash used to address the registers
+const P: u32 = 14;
+/// The number of bits of the hash used to count the run of zeroes
+const Q: u32 = 64 - P;
+pub const REGISTERS: usize = 1 << P;
+const P_MASK: u64 = (REGISTERS - 1) as u64;
+const BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << BITS) - 1;
+
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * BITS).div_ceil(8);
+const MAGIC: &[u8] = b"HYLL";
+const ENCODING: usize = 4;
+const CARD: usize = 8;
+
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+const SPARSE_XZERO_BIT: u8 = 0x40;
+const SPARSE_VAL_BIT: u8 = 0x80;
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HllError {
+    /// The string is not a HyperLogLog at all
+    Invalid,
+    /// The sparse representation doesn't add up to the registers
+    Corrupted,
+}
+
+pub type HllResult<T> = Result<T, HllError>;
+
+/// An empty sketch, which starts off sparse
+pub fn new() -> Vec<u8> {
+    let mut hll = vec![0; HEADER_LEN];
+    hll[..MAGIC.len()].copy_from_slice(MAGIC);
+    hll[ENCODING] = SPARSE;
+
+    let mut len = REGISTERS;
+    while len > 0 {
+        let run = len.min(SPARSE_XZERO_MAX_LEN);
+        push_xzero(&mut hll, run);
+        len -= run;
+    }
+
+    hll
+}
+
+/// Checks the header, as Redis does before using a string as a sketch
+pub fn validate(hll: &[u8]) -> HllResult<()> {
+    if hll.len() < HEADER_LEN
+        || &hll[..MAGIC.len()] != MAGIC
+        || hll[ENCODING] > SPARSE
+        || (hll[ENCODING] == DENSE && hll.len() != DENSE_LEN)
+    {
+        return Err(HllError::Invalid);
+    }
+
+    Ok(())
+}
+
+pub fn is_dense(hll: &[u8]) -> bool {
+    hll[ENCODING] == DENSE
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[CARD + 7] |= 1 << 7;
+}
+
+/// Adds an element, returning whether a register changed
+pub fn add(hll: &mut Vec<u8>, element: &[u8], sparse_max_bytes: usize) -> HllResult<bool> {
+    let (index, count) = pattern_len(element);
+    set(hll, index, count, sparse_max_bytes)
+}
+
+/// Raises a register to `count`, returning whether it changed
+fn set(hll: &mut Vec<u8>, index: usize, count: u8, sparse_max_bytes: usize) -> HllResult<bool> {
+    let changed = if is_dense(hll) {
+        dense_set(&mut hll[HEADER_LEN..], index, count)
+    } else {
+        sparse_set(hll, index, count, sparse_max_bytes)?
+    };
+
+    if changed {
+        invalidate_cache(hll);
+    }
+
+    Ok(changed)
+}
+
+/// The estimated cardinality, which is cached in the header
+pub fn count(hll: &mut [u8]) -> HllResult<u64> {
+    if hll[CARD + 7] & (1 << 7) == 0 {
+        let mut card = [0; 8];
+        card.copy_from_slice(&hll[CARD..CARD + 8]);
+        return Ok(u64::from_le_bytes(card));
+    }
+
+    let card = count_registers(&registers(hll)?);
+    hll[CARD..CARD + 8].copy_from_slice(&card.to_le_bytes());
+
+    Ok(card)
+}
+
+/// Unpacks the registers, one per byte
+pub fn registers(hll: &[u8]) -> HllResult<Vec<u8>> {
+    if is_dense(hll) {
+        let dense = &hll[HEADER_LEN..];
+        return Ok((0..REGISTERS).map(|i| dense_get(dense, i)).collect());
+    }
+
+    let mut registers = Vec::with_capacity(REGISTERS);
+    let mut p = HEADER_LEN;
+    while p < hll.len() {
+        let (op, len) = sparse_opcode(hll, p)?;
+        if registers.len() + len > REGISTERS {
+            return Err(HllError::Corrupted);
+        }
+
+        let value = match op {
+            Op::Val(value) => value,
+            _ => 0,
+        };
+        registers.resize(registers.len() + len, value);
+        p += op.len();
+    }
+
+    if registers.len() != REGISTERS {
+        return Err(HllError::Corrupted);
+    }
+
+    Ok(registers)
+}
+
+/// Raises the registers of a sketch to at least those given, as PFMERGE does
+pub fn merge_registers(
+    hll: &mut Vec<u8>,
+    registers: &[u8],
+    sparse_max_bytes: usize,
+) -> HllResult<()> {
+    for (index, &count) in registers.iter().enumerate() {
+        if count > 0 {
+            set(hll, index, count, sparse_max_bytes)?;
+        }
+    }
+
+    invalidate_cache(hll);
+    Ok(())
+}
+
+/// Estimates the cardinality of a set of registers, using the improved
+/// estimator of Otmar Ertl's "New cardinality estimation algorithms for
+/// HyperLogLog sketches", as Redis does
+pub fn count_registers(registers: &[u8]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut histogram = [0u32; 64];
+    for &register in registers {
+        histogram[usize::from(register & REGISTER_MAX)] += 1;
+    }
+
+    let q = Q as usize;
+    let mut z = m * tau((m - f64::from(histogram[q + 1])) / m);
+    for j in (1..=q).rev() {
+        z += f64::from(histogram[j]);
+        z *= 0.5;
+    }
+    z += m * sigma(f64::from(histogram[0]) / m);
+
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+
+/// The register an element belongs to, and the length of the run of zeroes
+/// in its hash plus one, as Redis's `hllPatLen`
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let mut hash = murmur_hash64a(element, 0xadc8_3b19);
+    let index = (hash & P_MASK) as usize;
+    hash >>= P;
+    hash |= 1 << Q;
+
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+/// Austin Appleby's MurmurHash64A, as in Redis
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= u64::from(byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+
+    h
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * BITS / 8;
+    let fb = (index * BITS) & 7;
+    let b0 = u16::from(registers[byte]);
+    // The last register doesn't reach into the byte after it
+    let b1 = u16::from(registers.get(byte + 1).copied().unwrap_or(0));
+
+    (((b0 >> fb) | (b1 << (8 - fb))) & u16::from(REGISTER_MAX)) as u8
+}
+
+fn dense_set(registers: &mut [u8], index: usize, count: u8) -> bool {
+    if dense_get(registers, index) >= count {
+        return false;
+    }
+
+    let byte = index * BITS / 8;
+    let fb = (index * BITS) & 7;
+    let value = u16::from(count);
+    let max = u16::from(REGISTER_MAX);
+
+    registers[byte] &= !((max << fb) as u8);
+    registers[byte] |= (value << fb) as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((max >> (8 - fb)) as u8);
+        *next |= (value >> (8 - fb)) as u8;
+    }
+
+    true
+}
+
+/// Converts a sparse sketch to a dense one, keeping the header otherwise
+pub fn to_dense(hll: &mut Vec<u8>) -> HllResult<()> {
+    if is_dense(hll) {
+        return Ok(());
+    }
+
+    let registers = registers(hll)?;
+    let mut dense = vec![0; DENSE_LEN];
+    dense[..HEADER_LEN].copy_from_slice(&hll[..HEADER_LEN]);
+    dense[ENCODING] = DENSE;
+    for (index, &count) in registers.iter().enumerate() {
+        if count > 0 {
+            dense_set(&mut dense[HEADER_LEN..], index, count);
+        }
+    }
+
+    *hll = dense;
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Zero,
+    XZero,
+    Val(u8),
+}
+
+impl Op {
+    /// The number of bytes of the opcode
+    fn len(self) -> usize {
+        match self {
+            Op::XZero => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Decodes the opcode at `p`, along with the number of registers it covers
+fn sparse_opcode(hll: &[u8], p: usize) -> HllResult<(Op, usize)> {
+    let byte = hll[p];
+
+    if byte & SPARSE_VAL_BIT != 0 {
+        Ok((
+            Op::Val(((byte >> 2) & 0x1f) + 1),
+            usize::from(byte & 0x3) + 1,
+        ))
+    } else if byte & SPARSE_XZERO_BIT != 0 {
+        let next = *hll.get(p + 1).ok_or(HllError::Corrupted)?;
+        Ok((
+            Op::XZero,
+            ((usize::from(byte & 0x3f) << 8) | usize::from(next)) + 1,
+        ))
+    } else {
+        Ok((Op::Zero, usize::from(byte & 0x3f) + 1))
+    }
+}
+
+fn val_opcode(value: u8, len: usize) -> u8 {
+    ((value - 1) << 2) | (len - 1) as u8 | SPARSE_VAL_BIT
+}
+
+fn push_xzero(out: &mut Vec<u8>, len: usize) {
+    let len = len - 1;
+    out.push((len >> 8) as u8 | SPARSE_XZERO_BIT);
+    out.push((len & 0xff) as u8);
+}
+
+fn push_zeroes(out: &mut Vec<u8>, len: usize) {
+    if len > SPARSE_ZERO_MAX_LEN {
+        push_xzero(out, len);
+    } else {
+        out.push((len - 1) as u8);
+    }
+}
+
+/// Sets a register of a sparse sketch, rewriting the opcode covering it in
+/// place exactly as Redis's `hllSparseSet` does, so that both produce the
+/// same bytes
+fn sparse_set(
+    hll: &mut Vec<u8>,
+    index: usize,
+    count: u8,
+    sparse_max_bytes: usize,
+) -> HllResult<bool> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote(hll, index, count);
+    }
+
+    // Find the opcode covering the register
+    let mut p = HEADER_LEN;
+    let mut first = 0;
+    let mut prev = None;
+    let mut found = None;
+    while p < hll.len() {
+        let (op, span) = sparse_opcode(hll, p)?;
+        if index < first + span {
+            found = Some((op, span));
+            break;
+        }
+
+        prev = Some(p);
+        p += op.len();
+        first += span;
+    }
+    let (op, span) = found.ok_or(HllError::Corrupted)?;
+
+    // Update it in place when it covers the register alone
+    let in_place = match op {
+        Op::Val(old) if old >= count => return Ok(false),
+        Op::Val(_) | Op::Zero => span == 1,
+        Op::XZero => false,
+    };
+
+    if in_place {
+        hll[p] = val_opcode(count, 1);
+    } else {
+        // Otherwise split it into up to three opcodes
+        let last = first + span - 1;
+        let mut seq = Vec::with_capacity(5);
+        match op {
+            Op::Val(value) => {
+                if index != first {
+                    seq.push(val_opcode(value, index - first));
+                }
+                seq.push(val_opcode(count, 1));
+                if index != last {
+                    seq.push(val_opcode(value, last - index));
+                }
+            }
+            _ => {
+                if index != first {
+                    push_zeroes(&mut seq, index - first);
+                }
+                seq.push(val_opcode(count, 1));
+                if index != last {
+                    push_zeroes(&mut seq, last - index);
+                }
+            }
+        }
+
+        if seq.len() > op.len() && hll.len() + seq.len() - op.len() > sparse_max_bytes {
+            return promote(hll, index, count);
+        }
+        hll.splice(p..p + op.len(), seq);
+    }
+
+    // Merge adjacent values, scanning up to 5 opcodes from the previous one
+    let mut p = prev.unwrap_or(HEADER_LEN);
+    let mut scan = 5;
+    while p < hll.len() && scan > 0 {
+        scan -= 1;
+
+        let (op, len) = sparse_opcode(hll, p)?;
+        let value = match op {
+            Op::Val(value) => value,
+            _ => {
+                p += op.len();
+                continue;
+            }
+        };
+
+        if let Some(&next) = hll.get(p + 1) {
+            if next & SPARSE_VAL_BIT != 0 {
+                let (next_op, next_len) = sparse_opcode(hll, p + 1)?;
+                if next_op == Op::Val(value) && len + next_len <= SPARSE_VAL_MAX_LEN {
+                    hll[p + 1] = val_opcode(value, len + next_len);
+                    hll.remove(p);
+                    // Try to merge the merged value with the one after it
+                    continue;
+                }
+            }
+        }
+        p += 1;
+    }
+
+    Ok(true)
+}
+
+fn promote(hll: &mut Vec<u8>, index: usize, count: u8) -> HllResult<bool> {
+    to_dense(hll)?;
+    Ok(dense_set(&mut hll[HEADER_LEN..], index, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPARSE_MAX_BYTES: usize = 3000;
+
+    #[test]
+    fn test_new() {
+        let hll = new();
+        assert_eq!(&hll[..], b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff");
+        assert_eq!(validate(&hll), Ok(()));
+        assert_eq!(registers(&hll).unwrap(), vec![0; REGISTERS]);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(b"HYLL"), Err(HllError::Invalid));
+        assert_eq!(
+            validate(b"HYLX\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff"),
+            Err(HllError::Invalid)
+        );
+        assert_eq!(
+            validate(b"HYLL\x02\0\0\0\0\0\0\0\0\0\0\0\x7f\xff"),
+            Err(HllError::Invalid)
+        );
+        assert_eq!(
+            validate(b"HYLL\x00\0\0\0\0\0\0\0\0\0\0\0\x7f\xff"),
+            Err(HllError::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_murmur_hash64a() {
+        // Checked against the reference implementation, for several tail lengths
+        let hash = |key: &[u8]| murmur_hash64a(key, 0xadc8_3b19);
+        assert_eq!(hash(b""), 0xd8df_ea65_85bc_9732);
+        assert_eq!(hash(b"a"), 0x53d2_470a_9b43_b1a7);
+        assert_eq!(hash(b"hello"), 0x0f65_6f01_eecf_e400);
+        assert_eq!(hash(b"abcdefgh"), 0xf3a6_5df5_5991_4567);
+        assert_eq!(hash(b"abcdefghijklmno"), 0x1035_0863_b35a_3059);
+    }
+
+    #[test]
+    fn test_sparse_set() {
+        let mut hll = new();
+
+        assert_eq!(sparse_set(&mut hll, 100, 3, SPARSE_MAX_BYTES), Ok(true));
+        assert_eq!(sparse_set(&mut hll, 100, 2, SPARSE_MAX_BYTES), Ok(false));
+        // XZERO 100, VAL 3, XZERO 16283
+        assert_eq!(&hll[HEADER_LEN..], &[0x40, 99, 0x88, 0x7f, 0x9a]);
+
+        // Neighbouring registers with the same value are merged
+        sparse_set(&mut hll, 101, 3, SPARSE_MAX_BYTES).unwrap();
+        assert_eq!(&hll[HEADER_LEN..], &[0x40, 99, 0x89, 0x7f, 0x99]);
+
+        let mut expected = vec![0; REGISTERS];
+        expected[100] = 3;
+        expected[101] = 3;
+        assert_eq!(registers(&hll).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_promotion() {
+        let mut hll = new();
+        sparse_set(&mut hll, 5, 2, SPARSE_MAX_BYTES).unwrap();
+
+        // Values over 32 need the dense representation
+        assert_eq!(set(&mut hll, 7, 40, SPARSE_MAX_BYTES), Ok(true));
+        assert!(is_dense(&hll));
+        assert_eq!(hll.len(), DENSE_LEN);
+
+        let registers = registers(&hll).unwrap();
+        assert_eq!((registers[5], registers[7]), (2, 40));
+
+        // As does growing past the maximum size
+        let mut hll = new();
+        for i in 0..100 {
+            add(&mut hll, i.to_string().as_bytes(), 50).unwrap();
+        }
+        assert!(is_dense(&hll));
+    }
+
+    #[test]
+    fn test_dense_registers() {
+        let mut registers = vec![0; DENSE_LEN - HEADER_LEN];
+        for &index in &[0, 1, 2, 3, 4, 5000, REGISTERS - 1] {
+            assert!(dense_set(&mut registers, index, 63));
+            assert!(!dense_set(&mut registers, index, 62));
+            assert_eq!(dense_get(&registers, index), 63);
+        }
+        assert_eq!(dense_get(&registers, 6), 0);
+    }
+
+    #[test]
+    fn test_count() {
+        for &n in &[0u64, 1, 10, 1000, 100_000] {
+            let mut hll = new();
+            for i in 0..n {
+                add(
+                    &mut hll,
+                    format!("element:{}", i).as_bytes(),
+                    SPARSE_MAX_BYTES,
+                )
+                .unwrap();
+            }
+
+            let estimate = count(&mut hll).unwrap() as f64;
+            let error = (estimate - n as f64).abs() / (n as f64).max(1.0);
+            assert!(error < 0.03, "{} estimated as {}", n, estimate);
+
+            // The cardinality is cached until a register changes
+            assert_eq!(hll[CARD + 7] & 0x80, 0);
+            assert_eq!(count(&mut hll).unwrap() as f64, estimate);
+        }
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let mut hll = new();
+        hll.pop();
+        assert_eq!(registers(&hll), Err(HllError::Corrupted));
+
+        let mut hll = new();
+        hll.push(0x00);
+        assert_eq!(registers(&hll), Err(HllError::Corrupted));
+    }
+}
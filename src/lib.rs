@@ -1,14 +1,38 @@
 #![forbid(unsafe_code)]
 
+pub mod config;
+pub mod local;
+pub mod module;
+pub mod protocol;
 pub mod server;
 
 #[macro_use]
 mod macros;
 
+mod bitops;
+mod blocking;
+mod clients;
 mod commands;
 mod db;
 mod errors;
-mod protocol;
+mod functions;
+mod geohash;
+mod hash;
+mod hyperloglog;
+mod list;
+mod listener;
+mod listpack;
+mod notify;
+mod output_buffer;
+mod pubsub;
+mod rdb;
 mod request;
 mod response;
 mod response_ext;
+mod scripting;
+mod shutdown;
+mod slowlog;
+mod sorted_set;
+mod stats;
+mod stream;
+mod tls;
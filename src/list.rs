@@ -0,0 +1,238 @@
+//! The list data type. Small lists are packed into a listpack and converted
+//! to a deque of strings once they grow past the `list-max-listpack-size`.
+
+use crate::listpack::{Listpack, ListpackLimits};
+use byte_string::ByteString;
+use std::collections::VecDeque;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum List {
+    Listpack(Listpack),
+    /// Reported as a `quicklist`, the encoding of larger lists in Redis
+    Deque(VecDeque<ByteString>),
+}
+
+impl Default for List {
+    fn default() -> Self {
+        Self::Listpack(Listpack::new())
+    }
+}
+
+impl List {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_values(
+        values: impl IntoIterator<Item = ByteString>,
+        limits: ListpackLimits,
+    ) -> Self {
+        let mut list = Self::new();
+        for value in values {
+            list.push_back(value, limits);
+        }
+        list
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Listpack(_) => "listpack",
+            Self::Deque(_) => "quicklist",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Listpack(listpack) => listpack.len(),
+            Self::Deque(deque) => deque.len(),
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &[u8]> + '_> {
+        match self {
+            Self::Listpack(listpack) => Box::new(listpack.iter()),
+            Self::Deque(deque) => Box::new(deque.iter().map(|value| value.as_ref())),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        match self {
+            Self::Listpack(listpack) => listpack.get(index),
+            Self::Deque(deque) => deque.get(index).map(|value| value.as_ref()),
+        }
+    }
+
+    pub fn push_back(&mut self, value: ByteString, limits: ListpackLimits) {
+        match self {
+            Self::Listpack(listpack) => listpack.push_back(&value),
+            Self::Deque(deque) => deque.push_back(value),
+        }
+        self.convert_if_needed(limits);
+    }
+
+    pub fn push_front(&mut self, value: ByteString, limits: ListpackLimits) {
+        match self {
+            Self::Listpack(listpack) => listpack.push_front(&value),
+            Self::Deque(deque) => deque.push_front(value),
+        }
+        self.convert_if_needed(limits);
+    }
+
+    pub fn pop_back(&mut self) -> Option<ByteString> {
+        match self {
+            Self::Listpack(listpack) => listpack.pop_back(),
+            Self::Deque(deque) => deque.pop_back(),
+        }
+    }
+
+    pub fn pop_front(&mut self) -> Option<ByteString> {
+        match self {
+            Self::Listpack(listpack) => listpack.pop_front(),
+            Self::Deque(deque) => deque.pop_front(),
+        }
+    }
+
+    /// Inserts a value before the one at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the length of the list
+    pub fn insert(&mut self, index: usize, value: ByteString, limits: ListpackLimits) {
+        match self {
+            Self::Listpack(listpack) => listpack.insert(index, &value),
+            Self::Deque(deque) => deque.insert(index, value),
+        }
+        self.convert_if_needed(limits);
+    }
+
+    /// Replaces the value at `index`, returning false if there isn't one
+    pub fn set(&mut self, index: usize, value: ByteString, limits: ListpackLimits) -> bool {
+        let replaced = match self {
+            Self::Listpack(listpack) => listpack.replace(index, &value),
+            Self::Deque(deque) => match deque.get_mut(index) {
+                Some(existing) => {
+                    *existing = value;
+                    true
+                }
+                None => false,
+            },
+        };
+        self.convert_if_needed(limits);
+
+        replaced
+    }
+
+    /// Keeps only the values for which `keep` returns true, given their index
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        match self {
+            Self::Listpack(listpack) => {
+                let mut kept = Listpack::new();
+                for (index, value) in listpack.iter().enumerate() {
+                    if keep(index) {
+                        kept.push_back(value);
+                    }
+                }
+                *listpack = kept;
+            }
+            Self::Deque(deque) => {
+                let mut index = 0;
+                deque.retain(|_| {
+                    index += 1;
+                    keep(index - 1)
+                });
+            }
+        }
+    }
+
+    /// Keeps only the values from `start` to `end` inclusive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `end` is not less than the length of the list, or `start`
+    /// is greater than `end`
+    pub fn trim(&mut self, start: usize, end: usize) {
+        match self {
+            Self::Listpack(listpack) => {
+                listpack.remove_range(end + 1..listpack.len());
+                listpack.remove_range(0..start);
+            }
+            Self::Deque(deque) => {
+                deque.truncate(end + 1);
+                deque.drain(0..start);
+            }
+        }
+    }
+
+    fn convert_if_needed(&mut self, limits: ListpackLimits) {
+        if let Self::Listpack(listpack) = self {
+            if listpack.len() > limits.max_entries || listpack.bytes_len() > limits.max_bytes {
+                *self = Self::Deque(listpack.iter().map(ByteString::from).collect());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: ListpackLimits = ListpackLimits {
+        max_entries: 4,
+        max_value: usize::MAX,
+        max_bytes: 64,
+    };
+
+    fn values(list: &List) -> Vec<&[u8]> {
+        list.iter().collect()
+    }
+
+    #[test]
+    fn test_converts_past_the_limits() {
+        let mut list = List::from_values(vec!["a".into(), "b".into()], LIMITS);
+        assert_eq!(list.encoding(), "listpack");
+
+        list.push_front("c".into(), LIMITS);
+        list.insert(1, "d".into(), LIMITS);
+        assert_eq!(list.encoding(), "listpack");
+
+        list.push_back("e".into(), LIMITS);
+        assert_eq!(list.encoding(), "quicklist");
+        assert_eq!(values(&list), vec![b"c", b"d", b"a", b"b", b"e"]);
+
+        let mut list = List::from_values(vec!["a".into()], LIMITS);
+        list.set(0, ByteString::from(&[b'x'; 100]), LIMITS);
+        assert_eq!(list.encoding(), "quicklist");
+        assert_eq!(list.get(0), Some(&[b'x'; 100][..]));
+    }
+
+    #[test]
+    fn test_operations_match_between_encodings() {
+        let unlimited = ListpackLimits {
+            max_entries: usize::MAX,
+            max_value: usize::MAX,
+            max_bytes: usize::MAX,
+        };
+        let none = ListpackLimits {
+            max_entries: 0,
+            ..unlimited
+        };
+        let items = || (0..10).map(|n| ByteString::from(n.to_string()));
+
+        for &limits in &[unlimited, none] {
+            let mut list = List::from_values(items(), limits);
+
+            assert_eq!(list.pop_front(), Some("0".into()));
+            assert_eq!(list.pop_back(), Some("9".into()));
+            assert!(list.set(0, "x".into(), limits));
+            assert!(!list.set(8, "x".into(), limits));
+            list.trim(1, 6);
+
+            assert_eq!(values(&list), vec![b"2", b"3", b"4", b"5", b"6", b"7"]);
+            assert_eq!(list.len(), 6);
+            assert_eq!(list.iter().next_back(), Some(&b"7"[..]));
+
+            list.retain(|index| index.is_multiple_of(2));
+            assert_eq!(values(&list), vec![b"2", b"4", b"6"]);
+        }
+    }
+}
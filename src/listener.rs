@@ -0,0 +1,152 @@
+//! The sockets client connections are accepted on. Each kind of listener
+//! gives its own kind of stream, the connections are then handled the same.
+
+use crate::clients::ClientAddr;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::{
+    convert::TryFrom,
+    future::{self, Future},
+    io,
+    net::SocketAddr,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+pub trait Listener {
+    /// The socket of a connection as it is accepted
+    type Socket: Send + 'static;
+    /// The stream the connection is served over once established
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Waits for the next connection, returning it along with the address of
+    /// the client
+    async fn accept(&self) -> io::Result<(Self::Socket, ClientAddr)>;
+
+    /// The address of the server's end of the connection
+    fn local_addr(socket: &Self::Socket) -> io::Result<ClientAddr>;
+
+    /// Turns on TCP keepalive for the connection, if it is over TCP
+    fn set_keepalive(_socket: &Self::Socket, _time: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Sets up the stream for an accepted connection, such as with a TLS
+    /// handshake. This is awaited by the connection's own task so that a slow
+    /// client doesn't hold up accepting others.
+    fn establish(
+        &self,
+        socket: Self::Socket,
+    ) -> impl Future<Output = io::Result<Self::Stream>> + Send + 'static;
+}
+
+impl Listener for TcpListener {
+    type Socket = TcpStream;
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> io::Result<(TcpStream, ClientAddr)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((stream, addr.into()))
+    }
+
+    fn local_addr(stream: &TcpStream) -> io::Result<ClientAddr> {
+        stream.local_addr().map(ClientAddr::from)
+    }
+
+    /// As in Redis, probes are sent once the connection has been idle for
+    /// `time`, then every third of that until three have gone unanswered
+    fn set_keepalive(stream: &TcpStream, time: Duration) -> io::Result<()> {
+        let keepalive = TcpKeepalive::new()
+            .with_time(time)
+            .with_interval((time / 3).max(Duration::from_secs(1)))
+            .with_retries(3);
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)
+    }
+
+    fn establish(
+        &self,
+        stream: TcpStream,
+    ) -> impl Future<Output = io::Result<TcpStream>> + Send + 'static {
+        future::ready(Ok(stream))
+    }
+}
+
+impl Listener for UnixListener {
+    type Socket = UnixStream;
+    type Stream = UnixStream;
+
+    /// The client end of a Unix socket is unnamed, so like Redis the path of
+    /// the socket stands for both
+    async fn accept(&self) -> io::Result<(UnixStream, ClientAddr)> {
+        let (stream, _) = UnixListener::accept(self).await?;
+        let addr = <Self as Listener>::local_addr(&stream)?;
+        Ok((stream, addr))
+    }
+
+    fn local_addr(stream: &UnixStream) -> io::Result<ClientAddr> {
+        let addr = stream.local_addr()?;
+        let path = addr.as_pathname().unwrap_or_else(|| "".as_ref());
+        Ok(ClientAddr::Unix(path.to_owned()))
+    }
+
+    fn establish(
+        &self,
+        stream: UnixStream,
+    ) -> impl Future<Output = io::Result<UnixStream>> + Send + 'static {
+        future::ready(Ok(stream))
+    }
+}
+
+/// Accepts TCP connections that are then served over TLS
+pub struct TlsListener {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, acceptor: TlsAcceptor) -> Self {
+        Self { listener, acceptor }
+    }
+}
+
+impl Listener for TlsListener {
+    type Socket = TcpStream;
+    type Stream = TlsStream<TcpStream>;
+
+    async fn accept(&self) -> io::Result<(TcpStream, ClientAddr)> {
+        Listener::accept(&self.listener).await
+    }
+
+    fn local_addr(stream: &TcpStream) -> io::Result<ClientAddr> {
+        <TcpListener as Listener>::local_addr(stream)
+    }
+
+    fn set_keepalive(stream: &TcpStream, time: Duration) -> io::Result<()> {
+        <TcpListener as Listener>::set_keepalive(stream, time)
+    }
+
+    fn establish(
+        &self,
+        stream: TcpStream,
+    ) -> impl Future<Output = io::Result<TlsStream<TcpStream>>> + Send + 'static {
+        self.acceptor.accept(stream)
+    }
+}
+
+/// Binds a TCP listener with room for `backlog` connections waiting to be
+/// accepted. As in Redis, a listener on IPv6 doesn't take IPv4 connections.
+pub fn bind_tcp(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX))?;
+
+    TcpListener::from_std(socket.into())
+}
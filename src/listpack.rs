@@ -0,0 +1,358 @@
+//! A listpack: a sequence of strings packed into a single allocation, used to
+//! store small collections without the per-element overhead of the general
+//! purpose structures. See `listpack.c`.
+//!
+//! Each entry is the length of its value, the value itself, then the size of
+//! the entry so far (the back length) so that the listpack can be walked in
+//! either direction. Lengths are variable length integers of 7 bits per byte,
+//! the back length is stored reversed so it can be read from its end.
+
+use byte_string::ByteString;
+use std::ops::Range;
+
+/// The size of the collections kept in a listpack. Past any of these limits
+/// a collection is converted to its full structure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListpackLimits {
+    pub max_entries: usize,
+    pub max_value: usize,
+    pub max_bytes: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Listpack {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of entries
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The size of the packed entries in bytes
+    pub fn bytes_len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            bytes: &self.bytes,
+            front: 0,
+            back: self.bytes.len(),
+            remaining: self.len,
+        }
+    }
+
+    /// The entries taken two at a time, as for the fields and values of a
+    /// hash. A trailing odd entry is ignored.
+    pub fn pairs(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        let mut iter = self.iter();
+        std::iter::from_fn(move || Some((iter.next()?, iter.next()?)))
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if index >= self.len {
+            return None;
+        }
+
+        let offset = self.offset(index);
+        Some(&self.bytes[value_range(&self.bytes, offset)])
+    }
+
+    pub fn push_back(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(&encode(value));
+        self.len += 1;
+    }
+
+    pub fn push_front(&mut self, value: &[u8]) {
+        self.insert(0, value);
+    }
+
+    /// Inserts an entry before the one at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is greater than the number of entries
+    pub fn insert(&mut self, index: usize, value: &[u8]) {
+        assert!(index <= self.len, "listpack index out of bounds");
+
+        let offset = self.offset(index);
+        self.bytes.splice(offset..offset, encode(value));
+        self.len += 1;
+    }
+
+    /// Replaces the entry at `index`, returning false if there isn't one
+    pub fn replace(&mut self, index: usize, value: &[u8]) -> bool {
+        if index >= self.len {
+            return false;
+        }
+
+        let start = self.offset(index);
+        let end = next_offset(&self.bytes, start);
+        self.bytes.splice(start..end, encode(value));
+        true
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<ByteString> {
+        let value = ByteString::from(self.get(index)?);
+        self.remove_range(index..index + 1);
+        Some(value)
+    }
+
+    /// Removes the entries in the range of indexes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "listpack range out of bounds"
+        );
+
+        let start = self.offset(range.start);
+        let end = self.offset(range.end);
+        self.bytes.drain(start..end);
+        self.len -= range.end - range.start;
+    }
+
+    pub fn pop_front(&mut self) -> Option<ByteString> {
+        self.remove(0)
+    }
+
+    pub fn pop_back(&mut self) -> Option<ByteString> {
+        self.remove(self.len.checked_sub(1)?)
+    }
+
+    /// The byte offset of the entry at `index`, or of the end of the
+    /// listpack for an index of `len`. Entries in the second half are found
+    /// by walking back from the end.
+    fn offset(&self, index: usize) -> usize {
+        if index <= self.len / 2 {
+            (0..index).fold(0, |offset, _| next_offset(&self.bytes, offset))
+        } else {
+            (index..self.len).fold(self.bytes.len(), |offset, _| {
+                prev_offset(&self.bytes, offset)
+            })
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    bytes: &'a [u8],
+    front: usize,
+    back: usize,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let value = &self.bytes[value_range(self.bytes, self.front)];
+        self.front = next_offset(self.bytes, self.front);
+        self.remaining -= 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        self.back = prev_offset(self.bytes, self.back);
+        self.remaining -= 1;
+        Some(&self.bytes[value_range(self.bytes, self.back)])
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+fn encode(value: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(value.len() + 2 * varint_len(value.len()) + 1);
+    write_varint(&mut entry, value.len());
+    entry.extend_from_slice(value);
+
+    let back_start = entry.len();
+    write_varint(&mut entry, back_start);
+    entry[back_start..].reverse();
+
+    entry
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(bytes: &[u8], offset: usize) -> (usize, usize) {
+    let mut n = 0;
+    let mut shift = 0;
+    let mut offset = offset;
+
+    loop {
+        let byte = bytes[offset];
+        n |= usize::from(byte & 0x7f) << shift;
+        shift += 7;
+        offset += 1;
+
+        if byte & 0x80 == 0 {
+            return (n, offset);
+        }
+    }
+}
+
+fn varint_len(n: usize) -> usize {
+    let mut len = 1;
+    let mut n = n >> 7;
+    while n > 0 {
+        len += 1;
+        n >>= 7;
+    }
+    len
+}
+
+/// The range of the value of the entry starting at `offset`
+fn value_range(bytes: &[u8], offset: usize) -> Range<usize> {
+    let (len, start) = read_varint(bytes, offset);
+    start..start + len
+}
+
+/// The offset of the entry following the one at `offset`
+fn next_offset(bytes: &[u8], offset: usize) -> usize {
+    let value = value_range(bytes, offset);
+    value.end + varint_len(value.end - offset)
+}
+
+/// The offset of the entry ending at `offset`, by reading its back length
+fn prev_offset(bytes: &[u8], offset: usize) -> usize {
+    let mut back_len = 0;
+    let mut shift = 0;
+    let mut offset = offset;
+
+    loop {
+        offset -= 1;
+        let byte = bytes[offset];
+        back_len |= usize::from(byte & 0x7f) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return offset - back_len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(listpack: &Listpack) -> Vec<&[u8]> {
+        listpack.iter().collect()
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut listpack = Listpack::new();
+        listpack.push_back(b"b");
+        listpack.push_front(b"a");
+        listpack.push_back(b"");
+        listpack.push_back(&[b'x'; 300]);
+
+        assert_eq!(listpack.len(), 4);
+        assert_eq!(listpack.get(3), Some(&[b'x'; 300][..]));
+        assert_eq!(listpack.get(4), None);
+
+        assert_eq!(listpack.pop_back(), Some(ByteString::from(&[b'x'; 300])));
+        assert_eq!(listpack.pop_front(), Some("a".into()));
+        assert_eq!(listpack.pop_back(), Some("".into()));
+        assert_eq!(listpack.pop_back(), Some("b".into()));
+        assert_eq!(listpack.pop_back(), None);
+        assert_eq!(listpack.bytes_len(), 0);
+    }
+
+    #[test]
+    fn test_iter_both_ways() {
+        let mut listpack = Listpack::new();
+        let values: Vec<Vec<u8>> = (0..200).map(|n| vec![b'v'; n]).collect();
+        values.iter().for_each(|v| listpack.push_back(v));
+
+        let forward: Vec<&[u8]> = entries(&listpack);
+        let mut backward: Vec<&[u8]> = listpack.iter().rev().collect();
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len(), 200);
+        assert_eq!(forward[150], &values[150][..]);
+
+        // The iterator stops when both ends meet
+        let mut iter = listpack.iter();
+        assert_eq!(iter.next(), Some(&b""[..]));
+        assert_eq!(iter.next_back(), Some(&values[199][..]));
+        assert_eq!(iter.len(), 198);
+        assert_eq!(iter.count(), 198);
+    }
+
+    #[test]
+    fn test_insert_replace_and_remove() {
+        let mut listpack = Listpack::new();
+        for value in &["a", "b", "c", "d"] {
+            listpack.push_back(value.as_bytes());
+        }
+
+        listpack.insert(1, b"x");
+        listpack.insert(5, b"y");
+        assert_eq!(entries(&listpack), vec![b"a", b"x", b"b", b"c", b"d", b"y"]);
+
+        assert!(listpack.replace(2, &[b'z'; 200]));
+        assert!(!listpack.replace(6, b"z"));
+        assert_eq!(listpack.get(2), Some(&[b'z'; 200][..]));
+        assert_eq!(listpack.get(3), Some(&b"c"[..]));
+
+        assert_eq!(listpack.remove(2), Some(ByteString::from(&[b'z'; 200])));
+        listpack.remove_range(0..2);
+        assert_eq!(entries(&listpack), vec![b"c", b"d", b"y"]);
+        listpack.remove_range(1..3);
+        assert_eq!(entries(&listpack), vec![b"c"]);
+    }
+
+    #[test]
+    fn test_pairs() {
+        let mut listpack = Listpack::new();
+        for value in &["f1", "v1", "f2", "v2", "odd"] {
+            listpack.push_back(value.as_bytes());
+        }
+
+        let pairs: Vec<(&[u8], &[u8])> = listpack.pairs().collect();
+        assert_eq!(
+            pairs,
+            vec![(&b"f1"[..], &b"v1"[..]), (&b"f2"[..], &b"v2"[..])]
+        );
+    }
+
+    #[test]
+    fn test_large_lengths() {
+        let value = vec![b'x'; 20000];
+        let entry = encode(&value);
+        assert_eq!(entry.len(), 3 + 20000 + 3);
+        assert_eq!(next_offset(&entry, 0), entry.len());
+        assert_eq!(prev_offset(&entry, entry.len()), 0);
+    }
+}
@@ -0,0 +1,93 @@
+//! Running commands in-process, without a socket. A `LocalClient` is served
+//! like any other connection, but over an in-memory stream, so it is seen by
+//! CLIENT LIST, may SUBSCRIBE or block, and so on.
+
+use crate::errors::Result;
+use redis_clone_client::{Cmd, Decoder};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+
+/// A RESP reply as sent by the server. Commands are encoded and replies
+/// decoded by the client crate, as for any other client.
+pub use redis_clone_client::Value;
+
+/// The size of the in-memory stream's buffer in either direction
+pub(crate) const STREAM_BUFFER: usize = 64 * 1024;
+
+/// A client connected to the server in-process, see
+/// [`ServerHandle::client`](crate::server::ServerHandle::client)
+#[derive(Debug)]
+pub struct LocalClient {
+    stream: DuplexStream,
+    decoder: Decoder,
+}
+
+impl LocalClient {
+    pub(crate) fn new(stream: DuplexStream) -> Self {
+        Self {
+            stream,
+            decoder: Decoder::new(),
+        }
+    }
+
+    /// Runs a command, given as its name and arguments, and waits for its
+    /// reply. An error reply is returned as `Value::Error`.
+    pub async fn execute<A: AsRef<[u8]>>(&mut self, args: &[A]) -> Result<Value> {
+        let mut request = vec![];
+        Cmd::default()
+            .args(args.iter().map(AsRef::as_ref))
+            .encode(&mut request);
+        self.stream.write_all(&request).await?;
+        self.receive().await
+    }
+
+    /// Waits for the next value sent by the server, such as a message
+    /// published to a channel the client has subscribed to
+    pub async fn receive(&mut self) -> Result<Value> {
+        Ok(self.decoder.decode(&mut self.stream).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::Error, protocol::ProtoError};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_execute() {
+        let (client, mut server) = tokio::io::duplex(STREAM_BUFFER);
+        let mut client = LocalClient::new(client);
+
+        server.write_all(b"+OK\r\n").await.unwrap();
+        let reply = client.execute(&["SET", "k", ""]).await.unwrap();
+        assert_eq!(reply, Value::SimpleString("OK".into()));
+
+        let expected = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$0\r\n\r\n";
+        let mut request = vec![0; expected.len()];
+        server.read_exact(&mut request).await.unwrap();
+        assert_eq!(request, expected);
+    }
+
+    #[tokio::test]
+    async fn test_receive() {
+        let (client, mut server) = tokio::io::duplex(STREAM_BUFFER);
+        let mut client = LocalClient::new(client);
+
+        server.write_all(b"*2\r\n$3\r\nfoo\r\n").await.unwrap();
+        server.write_all(b"$3\r\nbar\r\n:7\r\n").await.unwrap();
+        drop(server);
+
+        assert_eq!(
+            client.receive().await.unwrap(),
+            Value::Array(vec![
+                Value::BulkString(b"foo".to_vec()),
+                Value::BulkString(b"bar".to_vec()),
+            ])
+        );
+        assert_eq!(client.receive().await.unwrap(), Value::Integer(7));
+        assert_eq!(
+            client.receive().await.unwrap_err(),
+            Error::Proto(ProtoError::ConnectionClosed)
+        );
+    }
+}
@@ -1,5 +1,12 @@
 use env_logger::{self, Env};
-use log::{log_enabled, warn};
+use log::{error, log_enabled, warn};
+use redis_clone::config::Config;
+use stats_alloc::{StatsAlloc, INSTRUMENTED_SYSTEM};
+use std::alloc::System;
+
+// Counts allocations so INFO can report used_memory, like Redis's zmalloc
+#[global_allocator]
+static GLOBAL: &StatsAlloc<System> = &INSTRUMENTED_SYSTEM;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -9,7 +16,13 @@ const BITS: usize = std::mem::size_of::<usize>() * 8;
 fn main() -> Result<()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    let address = ("127.0.0.1", 8080);
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
     warn!("oO0OoO0OoO0Oo Redis Clone is starting oO0OoO0OoO0Oo");
     warn!(
@@ -24,12 +37,12 @@ fn main() -> Result<()> {
             REDIS_CLONE_VERSION,
             BITS,
             "standalone",
-            address.1,
+            config.port,
             std::process::id(),
         );
     }
 
-    redis_clone::server::serve(address)?;
+    redis_clone::server::serve(config)?;
 
     Ok(())
 }
@@ -0,0 +1,54 @@
+//! Extending the server with commands and data types of its own, as a Redis
+//! module would. They are added to a [`Registry`] that is given to the server
+//! with `ServerBuilder::registry`, and appear in COMMAND like any other.
+//!
+//! ```no_run
+//! use redis_clone::{
+//!     config::Config,
+//!     module::{CommandSpec, Database, KeySpec, Registry, Request, Response, Result},
+//!     server::ServerBuilder,
+//! };
+//!
+//! struct Counter(i64);
+//!
+//! # async fn run() -> Result<()> {
+//! let mut registry = Registry::new();
+//! let counter = registry.register_type("counter01")?;
+//!
+//! let spec = CommandSpec {
+//!     name: "counter.incr",
+//!     arity: 2,
+//!     flags: &["write", "fast"],
+//!     keys: KeySpec::Range(1, 1, 1),
+//! };
+//! registry.register(spec, move |db: &mut Database, req: &Request, resp: &mut Response| {
+//!     let key = req.arg(0)?;
+//!     if !db.exists(key) {
+//!         db.insert(key.clone(), counter.wrap(Counter(0)));
+//!     }
+//!     match db.get_mut(key).and_then(|value| value.custom_mut::<Counter>(counter)) {
+//!         Some(Counter(n)) => {
+//!             *n += 1;
+//!             resp.add_integer(*n);
+//!         }
+//!         None => resp.add_error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+//!     }
+//!     Ok(())
+//! })?;
+//!
+//! let server = ServerBuilder::new(Config::default())
+//!     .registry(registry)
+//!     .start()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub use crate::{
+    commands::{CommandHandler, CommandSpec, KeySpec, Registry},
+    db::{CustomValue, DataType, Database, RObj},
+    errors::{Error, Result},
+    request::Request,
+    response::Response,
+};
+pub use byte_string::{ByteStr, ByteString};
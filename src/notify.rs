@@ -0,0 +1,98 @@
+//! The classes of keyspace notifications selected by `notify-keyspace-events`,
+//! with the same flag characters as Redis's `notify.c`
+
+pub const KEYSPACE: u32 = 1 << 0;
+pub const KEYEVENT: u32 = 1 << 1;
+pub const GENERIC: u32 = 1 << 2;
+pub const STRING: u32 = 1 << 3;
+pub const LIST: u32 = 1 << 4;
+pub const SET: u32 = 1 << 5;
+pub const HASH: u32 = 1 << 6;
+pub const ZSET: u32 = 1 << 7;
+pub const EXPIRED: u32 = 1 << 8;
+pub const EVICTED: u32 = 1 << 9;
+pub const STREAM: u32 = 1 << 10;
+pub const KEY_MISS: u32 = 1 << 11;
+pub const MODULE: u32 = 1 << 13;
+/// The classes selected by `A`. Key misses have to be asked for explicitly.
+pub const ALL: u32 =
+    GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM | MODULE;
+
+/// The event classes and their flags, in the order Redis reports them
+const CLASS_FLAGS: &[(char, u32)] = &[
+    ('g', GENERIC),
+    ('$', STRING),
+    ('l', LIST),
+    ('s', SET),
+    ('h', HASH),
+    ('z', ZSET),
+    ('x', EXPIRED),
+    ('e', EVICTED),
+    ('t', STREAM),
+    ('d', MODULE),
+];
+
+/// Parses a flag string such as `KEA` or `Kx`. Returns `None` for unknown
+/// characters.
+pub fn flags_from_str(classes: &str) -> Option<u32> {
+    let mut flags = 0;
+
+    for c in classes.chars() {
+        flags |= match c {
+            'A' => ALL,
+            'K' => KEYSPACE,
+            'E' => KEYEVENT,
+            'm' => KEY_MISS,
+            _ => CLASS_FLAGS.iter().find(|(f, _)| *f == c)?.1,
+        };
+    }
+
+    Some(flags)
+}
+
+pub fn flags_to_string(flags: u32) -> String {
+    let mut classes = String::new();
+
+    if flags & ALL == ALL {
+        classes.push('A');
+    } else {
+        for (c, flag) in CLASS_FLAGS {
+            if flags & flag != 0 {
+                classes.push(*c);
+            }
+        }
+    }
+
+    for (c, flag) in &[('K', KEYSPACE), ('E', KEYEVENT), ('m', KEY_MISS)] {
+        if flags & flag != 0 {
+            classes.push(*c);
+        }
+    }
+
+    classes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flags_from_str() {
+        assert_eq!(flags_from_str(""), Some(0));
+        assert_eq!(flags_from_str("Kx"), Some(KEYSPACE | EXPIRED));
+        assert_eq!(flags_from_str("KEA"), Some(KEYSPACE | KEYEVENT | ALL));
+        assert_eq!(
+            flags_from_str("E$lshzxegtmd"),
+            Some(KEYEVENT | ALL | KEY_MISS)
+        );
+        assert_eq!(flags_from_str("KEy"), None);
+    }
+
+    #[test]
+    fn test_flags_to_string() {
+        assert_eq!(flags_to_string(0), "");
+        assert_eq!(flags_to_string(KEYSPACE | EXPIRED), "xK");
+        assert_eq!(flags_to_string(flags_from_str("KEA").unwrap()), "AKE");
+        assert_eq!(flags_to_string(flags_from_str("Eg$m").unwrap()), "g$Em");
+    }
+}
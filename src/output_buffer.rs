@@ -0,0 +1,226 @@
+//! The bytes queued for a connection to write: the chunks of a large reply
+//! sent as it is built, and data pushed to the client such as Pub/Sub
+//! messages. A client whose output buffer grows past the limits for its
+//! class is disconnected, see `client-output-buffer-limit`.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+
+/// The classes of clients that may be given different output buffer limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientClass {
+    Normal,
+    /// Replicas and clients in MONITOR mode
+    Replica,
+    /// Clients subscribed to a channel or pattern
+    PubSub,
+}
+
+impl ClientClass {
+    pub const ALL: [ClientClass; 3] = [Self::Normal, Self::Replica, Self::PubSub];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(Self::Normal),
+            "replica" | "slave" => Some(Self::Replica),
+            "pubsub" => Some(Self::PubSub),
+            _ => None,
+        }
+    }
+
+    /// The name used by CONFIG GET, which like Redis's is still `slave`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Replica => "slave",
+            Self::PubSub => "pubsub",
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A client is disconnected as soon as its output buffer exceeds the hard
+/// limit, or once it has stayed over the soft limit for longer than
+/// `soft_seconds`. A limit of zero is no limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputLimits {
+    pub hard: u64,
+    pub soft: u64,
+    pub soft_seconds: u64,
+}
+
+impl OutputLimits {
+    pub fn new(hard: u64, soft: u64, soft_seconds: u64) -> Self {
+        Self {
+            hard,
+            soft,
+            soft_seconds,
+        }
+    }
+}
+
+/// The size of a client's output buffer, shared by the shards queueing data
+/// for the client and the connection writing it
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    queued: AtomicUsize,
+    /// When the soft limit was first exceeded, if it still is
+    soft_limit_reached: Mutex<Option<Instant>>,
+    overflowed: AtomicBool,
+    overflow: Notify,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of bytes queued but not yet written
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Accounts for `len` more bytes about to be queued. Returns false,
+    /// without counting them, if they would take the buffer past its limits,
+    /// in which case they are to be dropped and the connection is closed.
+    pub fn queue(&self, len: usize, limits: OutputLimits, now: Instant) -> bool {
+        if self.is_overflowed() {
+            return false;
+        }
+
+        let size = (self.queued() + len) as u64;
+        if self.limits_reached(size, limits, now) {
+            self.overflow();
+            return false;
+        }
+
+        self.queued.fetch_add(len, Ordering::Relaxed);
+        true
+    }
+
+    /// Checks the limits against what is queued already, as the soft limit
+    /// may have been exceeded for too long since anything was last queued.
+    /// Returns false if the connection is to be closed.
+    pub fn check_limits(&self, limits: OutputLimits, now: Instant) -> bool {
+        self.queue(0, limits, now)
+    }
+
+    /// Accounts for `len` bytes written to the connection
+    pub fn written(&self, len: usize) {
+        self.queued.fetch_sub(len, Ordering::Relaxed);
+    }
+
+    /// Closes the connection as if the limits had been exceeded, such as for
+    /// a client that has stopped reading a reply the server waits to send
+    pub fn overflow(&self) {
+        self.overflowed.store(true, Ordering::Relaxed);
+        self.overflow.notify_one();
+    }
+
+    pub fn is_overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
+    /// Waits until data has been dropped because the limits were exceeded
+    pub async fn overflowed(&self) {
+        // The permit stored by `notify_one` covers an overflow that happened
+        // before this was called
+        self.overflow.notified().await;
+    }
+
+    fn limits_reached(&self, size: u64, limits: OutputLimits, now: Instant) -> bool {
+        if limits.hard != 0 && size >= limits.hard {
+            return true;
+        }
+
+        let mut soft_limit_reached = self
+            .soft_limit_reached
+            .lock()
+            .expect("output buffer lock poisoned");
+
+        if limits.soft == 0 || size < limits.soft {
+            *soft_limit_reached = None;
+            return false;
+        }
+
+        let since = *soft_limit_reached.get_or_insert(now);
+        now.duration_since(since) > Duration::from_secs(limits.soft_seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_limits() {
+        let output = OutputBuffer::new();
+
+        assert!(output.queue(1 << 40, OutputLimits::default(), Instant::now()));
+        assert_eq!(output.queued(), 1 << 40);
+        output.written(1 << 40);
+        assert_eq!(output.queued(), 0);
+    }
+
+    #[test]
+    fn test_hard_limit() {
+        let output = OutputBuffer::new();
+        let limits = OutputLimits::new(100, 0, 0);
+
+        assert!(output.queue(60, limits, Instant::now()));
+        assert!(!output.queue(40, limits, Instant::now()));
+        assert!(output.is_overflowed());
+        assert_eq!(output.queued(), 60);
+
+        // Nothing more is queued, even once there is room
+        output.written(60);
+        assert!(!output.queue(1, limits, Instant::now()));
+    }
+
+    #[test]
+    fn test_soft_limit() {
+        let output = OutputBuffer::new();
+        let limits = OutputLimits::new(0, 100, 10);
+        let start = Instant::now();
+
+        assert!(output.queue(100, limits, start));
+        assert!(output.queue(10, limits, start + Duration::from_secs(10)));
+
+        // Dropping back under the soft limit resets the time it was reached
+        output.written(110);
+        assert!(output.queue(1, limits, start + Duration::from_secs(11)));
+        assert!(output.queue(100, limits, start + Duration::from_secs(12)));
+        assert!(output.queue(1, limits, start + Duration::from_secs(22)));
+
+        assert!(!output.queue(1, limits, start + Duration::from_secs(23)));
+        assert!(output.is_overflowed());
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let output = OutputBuffer::new();
+        let limits = OutputLimits::new(0, 100, 10);
+        let start = Instant::now();
+
+        assert!(output.queue(200, limits, start));
+        assert!(output.check_limits(limits, start + Duration::from_secs(5)));
+        assert!(!output.check_limits(limits, start + Duration::from_secs(11)));
+    }
+
+    #[tokio::test]
+    async fn test_overflowed_before_waiting() {
+        let output = OutputBuffer::new();
+
+        output.queue(2, OutputLimits::new(1, 0, 0), Instant::now());
+        output.overflowed().await;
+    }
+}
@@ -1,209 +1,479 @@
 //! Parse "RESP Arrays of Bulk Strings" as defined in the the RESP protocol
 //! documentation here: https://redis.io/topics/protocol
+//!
+//! Requests are parsed incrementally from a buffer owned by the connection,
+//! so parsing resumes where it left off when a request arrives over several
+//! reads. The arguments are handed out as slices of the buffer, and a parsed
+//! request can be split off it to share without copying.
 
 mod errors;
 
 pub use errors::{ProtoError, ProtoResult};
 
-const MAX_ARRAY_SIZE: usize = 1024 * 1024;
-const MAX_BULK_STR_SIZE: usize = 512 * 1024 * 1024;
 const MAX_LINE_LENGTH: usize = 64 * 1024;
+/// The size of the reads from the connection, as Redis's `PROTO_IOBUF_LEN`
+const READ_SIZE: usize = 16 * 1024;
+/// A buffer grown larger than this by a big request is released once empty
+const MAX_IDLE_CAPACITY: usize = 1024 * 1024;
 const LF: u8 = b'\n';
-const CRLF: &[u8] = b"\r\n";
+const CR: u8 = b'\r';
 
-use byte_string::{ByteStr, ByteString};
-use std::convert::{TryFrom, TryInto};
+use byte_string::ByteStr;
+use bytes::{Bytes, BytesMut};
+use std::convert::TryFrom;
+use std::io;
 use std::marker::Unpin;
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
-
-pub async fn decode<T: AsyncBufRead + Unpin + Send>(mut stream: T) -> ProtoResult<Vec<ByteString>> {
-    let mut buffer = vec![];
-    let (type_sym, value_str) = read_header(&mut stream, &mut buffer).await?;
+use std::ops::Range;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Size limits for incoming requests, see the `proto-max-bulk-len` and
+/// `proto-max-multibulk-len` config parameters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    pub max_array_size: usize,
+    pub max_bulk_str_size: usize,
+}
 
-    if type_sym != b'*' {
-        return Err(ProtoError::UnsupportedSymbol(type_sym.into()));
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_array_size: 1024 * 1024,
+            max_bulk_str_size: 512 * 1024 * 1024,
+        }
     }
-
-    let len = value_str.parse().or(Err(ProtoError::InvalidArraySize))?;
-    let value = read_array(&mut stream, len).await?;
-    Ok(value)
 }
 
-async fn read_header<'a>(
-    stream: &mut (impl AsyncBufRead + Unpin + Send),
-    buffer: &'a mut Vec<u8>,
-) -> ProtoResult<(u8, ByteStr<'a>)> {
-    read_line(stream, buffer).await?;
+/// What the decoder is waiting for next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// The `*<len>` header of a request
+    ArrayHeader,
+    /// The `$<len>` header of a bulk string, with `remaining` strings to go
+    /// including this one
+    BulkHeader { remaining: usize },
+    /// A bulk string of `len` bytes and its CRLF
+    Bulk { len: usize, remaining: usize },
+}
 
-    let (&type_sym, tail) = buffer
-        .split_first()
-        .ok_or_else(|| ProtoError::from("Error parsing resp header structure"))?;
+/// Decodes the requests sent over a connection
+#[derive(Debug)]
+pub struct Decoder {
+    buffer: BytesMut,
+    /// Where the bytes yet to be parsed start
+    pos: usize,
+    /// Where the request being parsed starts
+    request_start: usize,
+    state: State,
+    /// The arguments of the request being parsed, or of the last one parsed
+    args: Vec<Range<usize>>,
+}
 
-    Ok((type_sym, ByteStr::from(tail)))
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-async fn read_line(
-    stream: &mut (impl AsyncBufRead + Unpin + Send),
-    buffer: &mut Vec<u8>,
-) -> ProtoResult<()> {
-    let limit = MAX_LINE_LENGTH.try_into().unwrap();
-    let num_bytes = stream.take(limit).read_until(LF, buffer).await?;
-
-    // If we got nothing then we can assume the connection has closed
-    if num_bytes == 0 {
-        return Err(ProtoError::ConnectionClosed);
-    }
-    // We must have at least 2 bytes for CRLF
-    if num_bytes < 2 {
-        return Err(ProtoError::InvalidTerminator);
-    }
-    // The line must be terminated by CRLF
-    if &buffer[(num_bytes - 2)..] != CRLF {
-        // We may be missing the CRLF because the line limit has been exceeded
-        if num_bytes == MAX_LINE_LENGTH {
-            return Err(ProtoError::ExceededMaxLineLength);
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            buffer: BytesMut::with_capacity(READ_SIZE),
+            pos: 0,
+            request_start: 0,
+            state: State::ArrayHeader,
+            args: vec![],
         }
+    }
 
-        return Err(ProtoError::InvalidTerminator);
+    /// Reads from the stream until a whole request has been parsed, whose
+    /// arguments are then available from `args`. Nothing is lost if this is
+    /// cancelled, the next call picks up where it was.
+    pub async fn decode<T: AsyncRead + Unpin>(
+        &mut self,
+        stream: &mut T,
+        limits: Limits,
+    ) -> ProtoResult<()> {
+        loop {
+            if self.parse(limits)? {
+                return Ok(());
+            }
+
+            if self.read(stream).await? == 0 {
+                return Err(self.eof_error());
+            }
+        }
     }
 
-    // Drop the CRLF
-    buffer.truncate(num_bytes - 2);
+    /// Parses as much of a request as has been read. Returns true once one
+    /// is complete.
+    pub fn parse(&mut self, limits: Limits) -> ProtoResult<bool> {
+        if self.state == State::ArrayHeader {
+            self.request_start = self.pos;
+            self.args.clear();
+        }
 
-    Ok(())
-}
+        loop {
+            match self.state {
+                State::ArrayHeader => {
+                    let len = match self.header(b'*', ProtoError::InvalidArraySize)? {
+                        Some(len) => len,
+                        None => return Ok(false),
+                    };
+
+                    // We don't need to support empty or null arrays in requests
+                    if len == 0 || len == -1 {
+                        self.request_start = self.pos;
+                        return Err(ProtoError::EmptyRequest);
+                    }
+
+                    let len = usize::try_from(len).or(Err(ProtoError::InvalidArraySize))?;
+                    if len > limits.max_array_size {
+                        return Err(ProtoError::InvalidArraySize);
+                    }
+
+                    self.args.reserve(len);
+                    self.state = State::BulkHeader { remaining: len };
+                }
+                State::BulkHeader { remaining } => {
+                    let len = match self.header(b'$', ProtoError::InvalidBulkStringSize)? {
+                        Some(len) => len,
+                        None => return Ok(false),
+                    };
+
+                    let len = usize::try_from(len).or(Err(ProtoError::InvalidBulkStringSize))?;
+                    if len > limits.max_bulk_str_size {
+                        return Err(ProtoError::InvalidBulkStringSize);
+                    }
+
+                    self.state = State::Bulk { len, remaining };
+                }
+                State::Bulk { len, remaining } => {
+                    // The trailing CRLF is skipped without being checked, as
+                    // by Redis
+                    if self.buffer.len() - self.pos < len + 2 {
+                        return Ok(false);
+                    }
+
+                    self.args.push(self.pos..self.pos + len);
+                    self.pos += len + 2;
+
+                    if remaining == 1 {
+                        self.state = State::ArrayHeader;
+                        return Ok(true);
+                    }
+                    self.state = State::BulkHeader {
+                        remaining: remaining - 1,
+                    };
+                }
+            }
+        }
+    }
 
-async fn read_bulk_string(
-    stream: &mut (impl AsyncBufRead + Unpin + Send),
-    len: i64,
-) -> ProtoResult<ByteString> {
-    let len = usize::try_from(len).or(Err(ProtoError::InvalidBulkStringSize))?;
+    /// The arguments of the request last parsed, valid until the next
+    /// `decode`, `parse` or `read`
+    pub fn args(&self) -> impl ExactSizeIterator<Item = &[u8]> {
+        self.args
+            .iter()
+            .map(move |range| &self.buffer[range.clone()])
+    }
 
-    if len > MAX_BULK_STR_SIZE {
-        return Err(ProtoError::InvalidBulkStringSize);
+    /// Splits the request last parsed off the buffer, returning its
+    /// arguments as slices of it, which keep it alive without copying it
+    pub fn take_args(&mut self) -> Vec<Bytes> {
+        let request = self.buffer.split_to(self.pos).freeze();
+        self.pos = 0;
+        self.request_start = 0;
+
+        self.args
+            .drain(..)
+            .map(|range| request.slice(range))
+            .collect()
     }
 
-    let mut buffer = ByteString::from(vec![0; len + 2]);
-    stream.read_exact(&mut buffer).await?;
+    /// The number of bytes read but not yet parsed
+    pub fn pending(&self) -> usize {
+        self.buffer.len() - self.pos
+    }
 
-    // Drop the trailing end of line chars
-    buffer.truncate(len);
+    /// Reads more from the stream into the buffer, returning the number of
+    /// bytes read
+    pub async fn read<T: AsyncRead + Unpin>(&mut self, stream: &mut T) -> io::Result<usize> {
+        self.compact();
 
-    Ok(buffer)
-}
+        // A bulk string is read in as few reads as possible
+        let wanted = match self.state {
+            State::Bulk { len, .. } => (len + 2).saturating_sub(self.pending()),
+            _ => 0,
+        };
+        self.buffer.reserve(wanted.max(READ_SIZE));
 
-async fn read_array(
-    stream: &mut (impl AsyncBufRead + Unpin + Send),
-    len: i64,
-) -> ProtoResult<Vec<ByteString>> {
-    // We don't need to support empty or null arrays in requests
-    if len == 0 || len == -1 {
-        return Err(ProtoError::EmptyRequest);
+        stream.read_buf(&mut self.buffer).await
     }
 
-    let len = usize::try_from(len).or(Err(ProtoError::InvalidArraySize))?;
+    /// Drops the requests already parsed from the front of the buffer
+    fn compact(&mut self) {
+        let start = match self.state {
+            State::ArrayHeader => self.pos,
+            _ => self.request_start,
+        };
+
+        if start == self.buffer.len() && self.buffer.capacity() > MAX_IDLE_CAPACITY {
+            self.buffer = BytesMut::with_capacity(READ_SIZE);
+        } else if start > 0 {
+            let _ = self.buffer.split_to(start);
+        } else {
+            return;
+        }
 
-    if len > MAX_ARRAY_SIZE {
-        return Err(ProtoError::InvalidArraySize);
+        self.pos -= start;
+        if self.state == State::ArrayHeader {
+            // The last request parsed has been dropped with the buffer
+            self.request_start = self.pos;
+            self.args.clear();
+        } else {
+            self.request_start -= start;
+            for range in &mut self.args {
+                *range = range.start - start..range.end - start;
+            }
+        }
     }
 
-    let mut elements = Vec::with_capacity(len);
-    let mut buffer = vec![];
+    /// The length given by the next header line, which must have the given
+    /// type symbol, or `None` when it hasn't been read in full yet
+    fn header(&mut self, type_sym: u8, invalid_len: ProtoError) -> ProtoResult<Option<i64>> {
+        let line = match self.line()? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
 
-    for _ in 0..len {
-        buffer.clear();
+        let (&sym, value) = self.buffer[line]
+            .split_first()
+            .ok_or_else(|| ProtoError::from("Error parsing resp header structure"))?;
 
-        let (type_sym, value_str) = read_header(stream, &mut buffer).await?;
-        if type_sym != b'$' {
-            return Err(ProtoError::UnsupportedSymbol(type_sym.into()));
+        if sym != type_sym {
+            return Err(ProtoError::UnsupportedSymbol(sym.into()));
         }
 
-        let len = value_str
-            .parse()
-            .or(Err(ProtoError::InvalidBulkStringSize))?;
-        let value = read_bulk_string(stream, len).await?;
+        match ByteStr::from(value).parse() {
+            Ok(len) => Ok(Some(len)),
+            Err(_) => Err(invalid_len),
+        }
+    }
 
-        elements.push(value);
+    /// The next line without its CRLF, consuming it
+    fn line(&mut self) -> ProtoResult<Option<Range<usize>>> {
+        let end = self.buffer.len().min(self.pos + MAX_LINE_LENGTH);
+
+        match self.buffer[self.pos..end].iter().position(|&b| b == LF) {
+            Some(lf) => {
+                let start = self.pos;
+                // The line must be terminated by CRLF
+                if lf == 0 || self.buffer[start + lf - 1] != CR {
+                    return Err(ProtoError::InvalidTerminator);
+                }
+
+                self.pos += lf + 1;
+                Ok(Some(start..start + lf - 1))
+            }
+            None if end - self.pos == MAX_LINE_LENGTH => Err(ProtoError::ExceededMaxLineLength),
+            None => Ok(None),
+        }
     }
 
-    Ok(elements)
+    /// The error for a connection closed part way through a request
+    fn eof_error(&self) -> ProtoError {
+        match self.state {
+            State::Bulk { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, "early eof").into(),
+            _ if self.pending() == 0 => ProtoError::ConnectionClosed,
+            _ => ProtoError::InvalidTerminator,
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use byte_string::ByteString;
+
+    async fn decode(mut input: &[u8], limits: Limits) -> ProtoResult<Vec<ByteString>> {
+        let mut decoder = Decoder::new();
+        decoder.decode(&mut input, limits).await?;
+        Ok(decoder.args().map(ByteString::from).collect())
+    }
+
+    /// Feeds the input a byte at a time, as if every read were partial
+    fn parse_bytewise(input: &[u8], decoder: &mut Decoder) -> Vec<Vec<ByteString>> {
+        let mut requests = vec![];
+
+        for &byte in input {
+            decoder.buffer.extend_from_slice(&[byte]);
+            while decoder.parse(Limits::default()).unwrap() {
+                requests.push(decoder.args().map(ByteString::from).collect());
+            }
+        }
+
+        requests
+    }
+
+    #[test]
+    fn test_resumes_on_partial_reads() {
+        let input = b"*2\r\n$3\r\nget\r\n$1\r\nx\r\n*1\r\n$4\r\nping\r\n";
+        let mut decoder = Decoder::new();
+
+        assert_eq!(
+            parse_bytewise(input, &mut decoder),
+            vec![
+                vec![ByteString::from("get"), ByteString::from("x")],
+                vec![ByteString::from("ping")],
+            ]
+        );
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let mut decoder = Decoder::new();
+        decoder
+            .buffer
+            .extend_from_slice(b"*1\r\n$4\r\nping\r\n*1\r\n$4\r\necho\r\n*1\r\n$2");
+
+        assert!(decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.args().collect::<Vec<_>>(), vec![b"ping"]);
+        assert!(decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.args().collect::<Vec<_>>(), vec![b"echo"]);
+        assert!(!decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.pending(), 2);
+
+        // The parsed requests are dropped from the buffer, the partial one
+        // is kept
+        decoder.compact();
+        assert_eq!(&decoder.buffer[..], b"*1\r\n$2");
+        decoder.buffer.extend_from_slice(b"\r\nhi\r\n");
+        assert!(decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.args().collect::<Vec<_>>(), vec![b"hi"]);
+    }
+
+    #[test]
+    fn test_take_args() {
+        let mut decoder = Decoder::new();
+        decoder
+            .buffer
+            .extend_from_slice(b"*2\r\n$3\r\nget\r\n$1\r\nx\r\n*1\r\n$4");
+
+        assert!(decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.take_args(), vec![&b"get"[..], &b"x"[..]]);
+        assert_eq!(decoder.args().len(), 0);
+        assert_eq!(&decoder.buffer[..], b"*1\r\n$4");
+
+        decoder.buffer.extend_from_slice(b"\r\nping\r\n");
+        assert!(decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.take_args(), vec![&b"ping"[..]]);
+        assert_eq!(decoder.pending(), 0);
+    }
 
     #[tokio::test]
-    async fn test_read_line() {
-        use std::io::Cursor;
-        use std::io::Write;
+    async fn test_reads_from_stream() {
+        let input = b"*2\r\n$3\r\nget\r\n$1\r\nx\r\n*1\r\n$4\r\nping\r\n";
+        let mut stream: &[u8] = input;
+        let mut decoder = Decoder::new();
+
+        decoder
+            .decode(&mut stream, Limits::default())
+            .await
+            .unwrap();
+        assert_eq!(decoder.args().len(), 2);
+        decoder
+            .decode(&mut stream, Limits::default())
+            .await
+            .unwrap();
+        assert_eq!(decoder.args().collect::<Vec<_>>(), vec![b"ping"]);
+        assert_eq!(
+            decoder.decode(&mut stream, Limits::default()).await,
+            Err(ProtoError::ConnectionClosed)
+        );
+    }
 
-        let mut stream = Cursor::new(Vec::new());
-        stream.write_all(b"123\r\n456\r\n").unwrap();
-        stream.set_position(0);
-        let mut buffer = vec![];
+    #[tokio::test]
+    async fn test_eof_part_way() {
+        let result = decode(b"*1\r\n$3", Limits::default()).await;
+        assert_eq!(result.unwrap_err(), ProtoError::InvalidTerminator);
 
-        read_line(&mut stream, &mut buffer).await.unwrap();
-        assert_eq!(buffer, b"123");
+        let result = decode(b"*1\r\n$3\r\nab", Limits::default()).await;
+        assert!(matches!(result, Err(ProtoError::BoxedError(e)) if e.to_string().contains("eof")));
+    }
 
-        buffer.clear();
-        read_line(&mut stream, &mut buffer).await.unwrap();
-        assert_eq!(buffer, b"456");
+    #[test]
+    fn test_empty_request_is_skipped() {
+        let mut decoder = Decoder::new();
+        decoder
+            .buffer
+            .extend_from_slice(b"*0\r\n*1\r\n$4\r\nping\r\n");
 
-        buffer.clear();
         assert_eq!(
-            read_line(&mut stream, &mut buffer).await.unwrap_err(),
-            ProtoError::ConnectionClosed
+            decoder.parse(Limits::default()),
+            Err(ProtoError::EmptyRequest)
         );
+        assert!(decoder.parse(Limits::default()).unwrap());
+        assert_eq!(decoder.args().collect::<Vec<_>>(), vec![b"ping"]);
     }
 
     #[tokio::test]
-    async fn test_read_line_invalid_terminator() {
-        // Valid case: an empty line
-        let mut buffer = vec![];
-        let mut input: &[u8] = b"\r\n";
-        read_line(&mut input, &mut buffer).await.unwrap();
-        assert_eq!(buffer, b"");
+    async fn test_invalid_terminator() {
+        // Valid case: an empty bulk string
+        let result = decode(b"*1\r\n$0\r\n\r\n", Limits::default()).await;
+        assert_eq!(result.unwrap(), vec![ByteString::new()]);
 
         // Invalid case: a single LF without a CR
-        let mut buffer = vec![];
-        let mut input: &[u8] = b"\n";
-        let result = read_line(&mut input, &mut buffer);
-        assert_eq!(result.await.unwrap_err(), ProtoError::InvalidTerminator);
+        let result = decode(b"\n", Limits::default()).await;
+        assert_eq!(result.unwrap_err(), ProtoError::InvalidTerminator);
 
         // Invalid case: a single LF preceeded by something other than a CR
-        let mut buffer = vec![];
-        let mut input: &[u8] = b"x\n";
-        let result = read_line(&mut input, &mut buffer);
-        assert_eq!(result.await.unwrap_err(), ProtoError::InvalidTerminator);
+        let result = decode(b"*1x\n", Limits::default()).await;
+        assert_eq!(result.unwrap_err(), ProtoError::InvalidTerminator);
 
         // Invalid case: a single CR followed by not a LF
-        let mut buffer = vec![];
-        let mut input: &[u8] = b"\rx";
-        let result = read_line(&mut input, &mut buffer);
-        assert_eq!(result.await.unwrap_err(), ProtoError::InvalidTerminator);
+        let result = decode(b"*1\rx", Limits::default()).await;
+        assert_eq!(result.unwrap_err(), ProtoError::InvalidTerminator);
 
         // Invalid case: no terminator
-        let mut buffer = vec![];
-        let mut input: &[u8] = b"x";
-        let result = read_line(&mut input, &mut buffer);
-        assert_eq!(result.await.unwrap_err(), ProtoError::InvalidTerminator);
+        let result = decode(b"*1", Limits::default()).await;
+        assert_eq!(result.unwrap_err(), ProtoError::InvalidTerminator);
     }
 
     #[tokio::test]
-    async fn test_read_line_gt_max() {
-        let mut buffer = vec![];
+    async fn test_line_gt_max() {
         let input: Vec<u8> = b"0".repeat(MAX_LINE_LENGTH + 1);
-        let mut slice = input.as_slice();
 
-        let result = read_line(&mut slice, &mut buffer);
+        let result = decode(&input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::ExceededMaxLineLength);
     }
 
+    #[tokio::test]
+    async fn test_large_bulk_string() {
+        let value = vec![b'v'; 3 * READ_SIZE + 5];
+        let mut input = format!("*2\r\n$3\r\nset\r\n${}\r\n", value.len()).into_bytes();
+        input.extend_from_slice(&value);
+        input.extend_from_slice(b"\r\n");
+
+        let result = decode(&input, Limits::default()).await.unwrap();
+        assert_eq!(result[1], ByteString::from(value));
+    }
+
+    #[test]
+    fn test_releases_large_buffers() {
+        let mut decoder = Decoder::new();
+        decoder.buffer.resize(MAX_IDLE_CAPACITY + 1, 0);
+        decoder.pos = decoder.buffer.len();
+
+        decoder.compact();
+        assert_eq!(decoder.buffer.capacity(), READ_SIZE);
+        assert_eq!(decoder.pos, 0);
+    }
+
     #[tokio::test]
     async fn decode_not_an_array() {
         let input: &[u8] = b"x\r\n";
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(
             result.await.unwrap_err(),
             ProtoError::UnsupportedSymbol('x')
@@ -213,21 +483,21 @@ mod test {
     #[tokio::test]
     async fn decode_null_array() {
         let input: &[u8] = b"*-1\r\n";
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::EmptyRequest);
     }
 
     #[tokio::test]
     async fn decode_empty_array() {
         let input: &[u8] = b"*0\r\n";
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::EmptyRequest);
     }
 
     #[tokio::test]
     async fn decode_array_of_bulk_string() {
         let input: &[u8] = b"*2\r\n$8\r\nabc\r\ndef\r\n$3\r\n123\r\n";
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(
             result.await.unwrap(),
             vec![ByteString::from("abc\r\ndef"), ByteString::from("123"),]
@@ -237,7 +507,7 @@ mod test {
     #[tokio::test]
     async fn decode_array_of_not_bulk_string() {
         let input: &[u8] = b"*1\r\n:1\r\n";
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(
             result.await.unwrap_err(),
             ProtoError::UnsupportedSymbol(':')
@@ -247,7 +517,7 @@ mod test {
     #[tokio::test]
     async fn decode_empty_bulk_string() {
         let input: &[u8] = b"*1\r\n$0\r\n\r\n";
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap(), vec![ByteString::new()]);
     }
 
@@ -256,7 +526,7 @@ mod test {
         // i64 max + 1
         let input: &[u8] = b"*9223372036854775808\r\n";
 
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::InvalidArraySize);
     }
 
@@ -264,7 +534,7 @@ mod test {
     async fn array_invalid_size_negative() {
         let input: &[u8] = b"*-2\r\n";
 
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::InvalidArraySize);
     }
 
@@ -273,7 +543,7 @@ mod test {
         // 1024 * 1024 + 1 is too large
         let input: &[u8] = b"*1048577\r\n";
 
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::InvalidArraySize);
     }
 
@@ -282,7 +552,7 @@ mod test {
         // i64 max + 1
         let input: &[u8] = b"*1\r\n$9223372036854775808\r\n";
 
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::InvalidBulkStringSize);
     }
 
@@ -292,7 +562,7 @@ mod test {
         // consider the -1 "null string" marker as invalid
         let input: &[u8] = b"*1\r\n$-1\r\n";
 
-        let result = decode(input);
+        let result = decode(input, Limits::default());
         assert_eq!(result.await.unwrap_err(), ProtoError::InvalidBulkStringSize);
     }
 
@@ -301,7 +571,26 @@ mod test {
         // 512 * 1024 * 1024 + 1 is too large
         let input: &[u8] = b"*1\r\n$536870913\r\n";
 
-        let result = decode(input);
+        let result = decode(input, Limits::default());
+        assert_eq!(result.await.unwrap_err(), ProtoError::InvalidBulkStringSize);
+    }
+
+    #[tokio::test]
+    async fn configured_limits() {
+        let limits = Limits {
+            max_array_size: 2,
+            max_bulk_str_size: 3,
+        };
+
+        let input: &[u8] = b"*2\r\n$3\r\nabc\r\n$3\r\n123\r\n";
+        assert!(decode(input, limits).await.is_ok());
+
+        let input: &[u8] = b"*3\r\n";
+        let result = decode(input, limits);
+        assert_eq!(result.await.unwrap_err(), ProtoError::InvalidArraySize);
+
+        let input: &[u8] = b"*1\r\n$4\r\nabcd\r\n";
+        let result = decode(input, limits);
         assert_eq!(result.await.unwrap_err(), ProtoError::InvalidBulkStringSize);
     }
 }
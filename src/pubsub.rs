@@ -0,0 +1,186 @@
+use crate::response::Response;
+use byte_string::ByteString;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// The index of channel and pattern subscriptions used to find the
+/// subscribers of a published message. Clients track their own subscriptions
+/// too, so that they can be counted and removed when the client disconnects.
+pub struct PubSub {
+    channels: HashMap<ByteString, BTreeSet<u64>>,
+    patterns: BTreeMap<ByteString, BTreeSet<u64>>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self {
+            channels: HashMap::new(),
+            patterns: BTreeMap::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self, client_id: u64, channel: &ByteString) {
+        self.channels
+            .entry(channel.clone())
+            .or_default()
+            .insert(client_id);
+    }
+
+    pub fn unsubscribe(&mut self, client_id: u64, channel: &ByteString) {
+        if let Some(subscribers) = self.channels.get_mut(channel) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                self.channels.remove(channel);
+            }
+        }
+    }
+
+    pub fn psubscribe(&mut self, client_id: u64, pattern: &ByteString) {
+        self.patterns
+            .entry(pattern.clone())
+            .or_default()
+            .insert(client_id);
+    }
+
+    pub fn punsubscribe(&mut self, client_id: u64, pattern: &ByteString) {
+        if let Some(subscribers) = self.patterns.get_mut(pattern) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                self.patterns.remove(pattern);
+            }
+        }
+    }
+
+    /// The clients subscribed to exactly this channel
+    pub fn subscribers<'a>(&'a self, channel: &ByteString) -> impl Iterator<Item = u64> + 'a {
+        self.channels
+            .get(channel)
+            .into_iter()
+            .flat_map(|subscribers| subscribers.iter().copied())
+    }
+
+    /// The clients subscribed to a pattern matching this channel, along with
+    /// the pattern that matched
+    pub fn pattern_subscribers<'a>(
+        &'a self,
+        channel: &'a ByteString,
+    ) -> impl Iterator<Item = (&'a ByteString, u64)> + 'a {
+        self.patterns
+            .iter()
+            .filter(move |(pattern, _)| byte_glob::glob(pattern, channel))
+            .flat_map(|(pattern, subscribers)| subscribers.iter().map(move |&id| (pattern, id)))
+    }
+
+    /// The active channels, those with at least one subscriber, optionally
+    /// filtered by a glob pattern
+    pub fn channels(&self, pattern: Option<&ByteString>) -> Vec<&ByteString> {
+        let mut channels: Vec<&ByteString> = self
+            .channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|p| byte_glob::glob(p, channel)))
+            .collect();
+
+        channels.sort_unstable();
+        channels
+    }
+
+    pub fn num_subscribers(&self, channel: &ByteString) -> usize {
+        self.channels.get(channel).map_or(0, BTreeSet::len)
+    }
+
+    /// The number of distinct patterns subscribed to
+    pub fn num_patterns(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+/// Adds the confirmation of a change in subscriptions, such as
+/// `["subscribe", "channel", 1]`
+pub fn add_subscription_reply(
+    response: &mut Response,
+    kind: &str,
+    channel: Option<&ByteString>,
+    count: usize,
+) {
+    response.add_array_len(3);
+    response.add_bulk_string(kind);
+    match channel {
+        Some(channel) => response.add_bulk_string(channel),
+        None => response.add_null_string(),
+    }
+    response.add_integer(count as i64);
+}
+
+pub fn message(channel: &ByteString, message: &ByteString) -> Response {
+    let mut response = Response::new();
+
+    response.add_array_len(3);
+    response.add_bulk_string("message");
+    response.add_bulk_string(channel);
+    response.add_bulk_string(message);
+
+    response
+}
+
+pub fn pmessage(pattern: &ByteString, channel: &ByteString, message: &ByteString) -> Response {
+    let mut response = Response::new();
+
+    response.add_array_len(4);
+    response.add_bulk_string("pmessage");
+    response.add_bulk_string(pattern);
+    response.add_bulk_string(channel);
+    response.add_bulk_string(message);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribers() {
+        let mut pubsub = PubSub::new();
+        let news: ByteString = "news".into();
+
+        pubsub.subscribe(1, &news);
+        pubsub.subscribe(2, &news);
+        pubsub.subscribe(2, &"sport".into());
+
+        assert_eq!(pubsub.subscribers(&news).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(pubsub.channels(None).len(), 2);
+        assert_eq!(pubsub.channels(Some(&"n*".into())), vec![&news]);
+
+        pubsub.unsubscribe(1, &news);
+        pubsub.unsubscribe(2, &news);
+        assert_eq!(pubsub.num_subscribers(&news), 0);
+        assert_eq!(pubsub.channels(None).len(), 1);
+    }
+
+    #[test]
+    fn test_pattern_subscribers() {
+        let mut pubsub = PubSub::new();
+        let pattern: ByteString = "news.*".into();
+
+        pubsub.psubscribe(3, &pattern);
+        assert_eq!(pubsub.num_patterns(), 1);
+
+        let channel: ByteString = "news.uk".into();
+        let matched: Vec<_> = pubsub.pattern_subscribers(&channel).collect();
+        assert_eq!(matched, vec![(&pattern, 3)]);
+
+        let channel: ByteString = "sport".into();
+        assert_eq!(pubsub.pattern_subscribers(&channel).count(), 0);
+
+        pubsub.punsubscribe(3, &pattern);
+        assert_eq!(pubsub.num_patterns(), 0);
+    }
+
+    #[test]
+    fn test_message() {
+        let response = message(&"ch".into(), &"hi".into());
+        assert_eq!(
+            response.as_string(),
+            "*3\r\n$7\r\nmessage\r\n$2\r\nch\r\n$2\r\nhi\r\n"
+        );
+    }
+}
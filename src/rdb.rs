@@ -0,0 +1,1182 @@
+//! The parts of Redis's RDB serialization format used by the clone, so that
+//! payloads can be exchanged with _real_ Redis, and the RDB file the dataset
+//! and function libraries are saved to. See `rdb.c`.
+
+use crate::{
+    config::Config,
+    db::{Database, Entry, RObj},
+    errors,
+    hash::Hash,
+    sorted_set::SortedSet,
+    stream::{Consumer, ConsumerGroup, Fields, PendingEntry, Stream, StreamId, NODE_ENTRIES},
+};
+use byte_string::ByteString;
+use log::warn;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::{TryFrom, TryInto},
+    fs, io,
+    path::Path,
+    process,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The RDB version written by Redis 7.0
+pub const RDB_VERSION: u16 = 10;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+
+pub const RDB_OPCODE_FUNCTION2: u8 = 245;
+const RDB_OPCODE_IDLE: u8 = 248;
+const RDB_OPCODE_FREQ: u8 = 249;
+const RDB_OPCODE_AUX: u8 = 250;
+const RDB_OPCODE_RESIZEDB: u8 = 251;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 252;
+const RDB_OPCODE_EXPIRETIME: u8 = 253;
+const RDB_OPCODE_SELECTDB: u8 = 254;
+const RDB_OPCODE_EOF: u8 = 255;
+
+/// How the nodes of a quicklist hold their elements, the one or packed
+/// into a listpack
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+const QUICKLIST_NODE_CONTAINER_PACKED: u64 = 2;
+
+/// The flags of an entry in the listpack of a stream node
+const STREAM_ITEM_FLAG_DELETED: i64 = 1 << 0;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 1 << 1;
+
+/// The magic string an RDB file starts with, followed by its version as 4
+/// digits
+const RDB_MAGIC: &[u8] = b"REDIS";
+
+const RDB_6BITLEN: u8 = 0;
+const RDB_14BITLEN: u8 = 1;
+const RDB_32BITLEN: u8 = 0x80;
+const RDB_64BITLEN: u8 = 0x81;
+const RDB_ENCVAL: u8 = 3;
+
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RdbError;
+
+pub type RdbResult<T> = Result<T, RdbError>;
+
+pub fn write_len(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push((RDB_6BITLEN << 6) | len as u8);
+    } else if len < 1 << 14 {
+        out.push((RDB_14BITLEN << 6) | (len >> 8) as u8);
+        out.push(len as u8);
+    } else if len <= u64::from(u32::MAX) {
+        out.push(RDB_32BITLEN);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(RDB_64BITLEN);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// Writes a string as is. Redis may also compress strings or encode them as
+/// integers, both of which [`Reader::read_string`] understands.
+pub fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+    write_len(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+/// Appends the footer Redis puts after a DUMP payload: the RDB version and a
+/// CRC64 checksum of everything before it
+pub fn write_footer(out: &mut Vec<u8>) {
+    out.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(out);
+    out.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// Checks the footer of a DUMP payload, returning the payload without it
+pub fn verify_footer(payload: &[u8]) -> RdbResult<&[u8]> {
+    if payload.len() < 10 {
+        return Err(RdbError);
+    }
+
+    let (body, crc) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes(body[body.len() - 2..].try_into().unwrap());
+    let crc = u64::from_le_bytes(crc.try_into().unwrap());
+
+    if version > RDB_VERSION || crc64(body) != crc {
+        return Err(RdbError);
+    }
+
+    Ok(&body[..body.len() - 2])
+}
+
+pub struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    pub fn read_u8(&mut self) -> RdbResult<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_bytes(&mut self, len: usize) -> RdbResult<&'a [u8]> {
+        if self.input.len() < len {
+            return Err(RdbError);
+        }
+
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    /// Reads a length, or the encoding of a specially encoded string, which
+    /// is flagged by the second value
+    fn read_len_or_encoding(&mut self) -> RdbResult<(u64, bool)> {
+        let first = self.read_u8()?;
+
+        match first >> 6 {
+            RDB_6BITLEN => Ok((u64::from(first & 0x3f), false)),
+            RDB_14BITLEN => {
+                let second = self.read_u8()?;
+                Ok(((u64::from(first & 0x3f) << 8) | u64::from(second), false))
+            }
+            RDB_ENCVAL => Ok((u64::from(first & 0x3f), true)),
+            _ => match first {
+                RDB_32BITLEN => {
+                    let bytes = self.read_bytes(4)?;
+                    Ok((
+                        u64::from(u32::from_be_bytes(bytes.try_into().unwrap())),
+                        false,
+                    ))
+                }
+                RDB_64BITLEN => {
+                    let bytes = self.read_bytes(8)?;
+                    Ok((u64::from_be_bytes(bytes.try_into().unwrap()), false))
+                }
+                _ => Err(RdbError),
+            },
+        }
+    }
+
+    /// Reads a time in milliseconds, which isn't length encoded
+    fn read_millis(&mut self) -> RdbResult<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    /// Reads a stream ID as its 16 bytes, which sort as the IDs do
+    fn read_raw_id(&mut self) -> RdbResult<StreamId> {
+        raw_id(self.read_bytes(16)?)
+    }
+
+    /// Reads a stream ID as the lengths of its two parts
+    fn read_id(&mut self) -> RdbResult<StreamId> {
+        Ok(StreamId::new(self.read_len()?, self.read_len()?))
+    }
+
+    /// Reads a length to be used as a count of elements
+    fn read_count(&mut self) -> RdbResult<usize> {
+        self.read_len()?.try_into().map_err(|_| RdbError)
+    }
+
+    pub fn read_len(&mut self) -> RdbResult<u64> {
+        match self.read_len_or_encoding()? {
+            (len, false) => Ok(len),
+            _ => Err(RdbError),
+        }
+    }
+
+    pub fn read_string(&mut self) -> RdbResult<ByteString> {
+        let (len, encoded) = self.read_len_or_encoding()?;
+        if !encoded {
+            let len = len.try_into().map_err(|_| RdbError)?;
+            return Ok(self.read_bytes(len)?.into());
+        }
+
+        let value = match len as u8 {
+            RDB_ENC_INT8 => i64::from(self.read_u8()? as i8),
+            RDB_ENC_INT16 => i64::from(i16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap())),
+            RDB_ENC_INT32 => i64::from(i32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap())),
+            RDB_ENC_LZF => {
+                let compressed_len = self.read_len()?.try_into().map_err(|_| RdbError)?;
+                let len = self.read_len()?.try_into().map_err(|_| RdbError)?;
+                let compressed = self.read_bytes(compressed_len)?;
+                return Ok(lzf_decompress(compressed, len)?.into());
+            }
+            _ => return Err(RdbError),
+        };
+
+        Ok(value.to_string().into())
+    }
+}
+
+/// Decompresses LZF data, as written by Redis's `lzf_c.c`
+fn lzf_decompress(input: &[u8], len: usize) -> RdbResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+
+    while i < input.len() {
+        let ctrl = usize::from(input[i]);
+        i += 1;
+
+        if ctrl < 32 {
+            // A run of literal bytes
+            let run = input.get(i..i + ctrl + 1).ok_or(RdbError)?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+        } else {
+            // A back reference, which may overlap the bytes being copied
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += usize::from(*input.get(i).ok_or(RdbError)?);
+                i += 1;
+            }
+            let offset = ((ctrl & 0x1f) << 8) + usize::from(*input.get(i).ok_or(RdbError)?) + 1;
+            i += 1;
+
+            let start = out.len().checked_sub(offset).ok_or(RdbError)?;
+            for j in 0..run + 2 {
+                out.push(out[start + j]);
+            }
+        }
+    }
+
+    if out.len() != len {
+        return Err(RdbError);
+    }
+
+    Ok(out)
+}
+
+/// What is read from an RDB file, to be loaded into the shards
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The code of each function library, which every shard loads
+    pub libraries: Vec<ByteString>,
+    /// The keys which hadn't expired, each loaded by the shard it belongs to
+    pub entries: Vec<Entry>,
+}
+
+/// Saves the dataset and the function libraries to the RDB file configured,
+/// like Redis's `rdbSave`. The shard must hold every key. The file is written
+/// in full under another name first, so that a failed save leaves the last
+/// one in place.
+pub fn save(db: &Database) -> io::Result<()> {
+    let out = serialize(db);
+    let path = db.config().rdb_path();
+    let temp = path.with_file_name(format!("temp-{}.rdb", process::id()));
+    let written = fs::write(&temp, &out).and_then(|()| fs::rename(&temp, &path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    written
+}
+
+/// Reads the RDB file at `path`, returning `None` when there is none
+pub fn load(path: &Path, config: &Config) -> errors::Result<Option<Snapshot>> {
+    let input = match fs::read(path) {
+        Ok(input) => input,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    match parse(&input, config) {
+        Ok(snapshot) => Ok(Some(snapshot)),
+        Err(RdbError) => Err(format!("Bad file format reading {}", path.display()).into()),
+    }
+}
+
+fn serialize(db: &Database) -> Vec<u8> {
+    let mut out = RDB_MAGIC.to_vec();
+    out.extend_from_slice(format!("{:04}", RDB_VERSION).as_bytes());
+
+    let ctime = unix_time_ms(SystemTime::now()) / 1000;
+    write_aux(&mut out, "redis-ver", env!("CARGO_PKG_VERSION"));
+    write_aux(&mut out, "redis-bits", &usize::BITS.to_string());
+    write_aux(&mut out, "ctime", &ctime.to_string());
+
+    db.functions()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .write_libraries(&mut out);
+
+    // Values of the types registered on top of those built in have no
+    // serialization of their own
+    let entries: Vec<_> = db
+        .entries()
+        .filter(|(key, value, _)| match value {
+            RObj::Custom(_) => {
+                warn!(
+                    "Not saving the key {}, of a type registered by the server",
+                    key
+                );
+                false
+            }
+            _ => true,
+        })
+        .collect();
+    if !entries.is_empty() {
+        let expires = entries
+            .iter()
+            .filter(|(_, _, expires_at)| expires_at.is_some());
+        out.push(RDB_OPCODE_SELECTDB);
+        write_len(&mut out, 0);
+        out.push(RDB_OPCODE_RESIZEDB);
+        write_len(&mut out, entries.len() as u64);
+        write_len(&mut out, expires.count() as u64);
+    }
+
+    let (now, now_ms) = (Instant::now(), unix_time_ms(SystemTime::now()));
+    for (key, value, expires_at) in entries {
+        if let Some(expires_at) = expires_at {
+            let ttl = expires_at.saturating_duration_since(now).as_millis() as u64;
+            out.push(RDB_OPCODE_EXPIRETIME_MS);
+            out.extend_from_slice(&(now_ms + ttl).to_le_bytes());
+        }
+        write_object(&mut out, key, value);
+    }
+
+    out.push(RDB_OPCODE_EOF);
+    let crc = crc64(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+
+    out
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.push(RDB_OPCODE_AUX);
+    write_string(out, key.as_bytes());
+    write_string(out, value.as_bytes());
+}
+
+/// Writes a key and its value. Lists, hashes and sorted sets are written as
+/// plain sequences of strings, which Redis still loads, rather than in the
+/// listpacks it writes itself.
+fn write_object(out: &mut Vec<u8>, key: &[u8], value: &RObj) {
+    let value_type = match value {
+        RObj::Int(_) | RObj::String(_) => RDB_TYPE_STRING,
+        RObj::List(_) => RDB_TYPE_LIST,
+        RObj::Hash(_) => RDB_TYPE_HASH,
+        RObj::SortedSet(_) => RDB_TYPE_ZSET_2,
+        RObj::Stream(_) => RDB_TYPE_STREAM_LISTPACKS_2,
+        RObj::Custom(_) => unreachable!("values of registered types aren't saved"),
+    };
+    out.push(value_type);
+    write_string(out, key);
+
+    match value {
+        RObj::Int(n) => write_string(out, n.to_string().as_bytes()),
+        RObj::String(s) => write_string(out, s),
+        RObj::List(list) => {
+            write_len(out, list.len() as u64);
+            list.iter().for_each(|value| write_string(out, value));
+        }
+        RObj::Hash(hash) => {
+            write_len(out, hash.len() as u64);
+            for (field, value) in hash.iter() {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        RObj::SortedSet(zset) => {
+            write_len(out, zset.len() as u64);
+            for (member, score) in zset.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        RObj::Stream(stream) => write_stream(out, stream),
+        RObj::Custom(_) => (),
+    }
+}
+
+/// Writes a stream as Redis 7.0 does: its entries in nodes, each keyed by
+/// the ID of its first entry and packed into a listpack, followed by what is
+/// kept of the entries since deleted and then the consumer groups. See
+/// `t_stream.c` for the layout of the nodes.
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let entries: Vec<_> = stream.iter().collect();
+    write_len(out, entries.chunks(NODE_ENTRIES).len() as u64);
+
+    for node in entries.chunks(NODE_ENTRIES) {
+        let (master_id, master_fields) = node[0];
+        write_string(out, &write_raw_id(*master_id));
+
+        // Entries with the same fields as the first leave them out
+        let fields: Vec<_> = master_fields.iter().step_by(2).collect();
+        let mut lp = ListpackWriter::default();
+        lp.push_int(node.len() as i64);
+        lp.push_int(0);
+        lp.push_int(fields.len() as i64);
+        fields.iter().for_each(|field| lp.push_string(field));
+        lp.push_int(0);
+
+        for (id, entry_fields) in node {
+            let same_fields = entry_fields.len() == 2 * fields.len()
+                && entry_fields.iter().step_by(2).eq(fields.iter().copied());
+            let pairs = entry_fields.len() / 2;
+
+            lp.push_int(if same_fields {
+                STREAM_ITEM_FLAG_SAMEFIELDS
+            } else {
+                0
+            });
+            lp.push_int(id.ms.wrapping_sub(master_id.ms) as i64);
+            lp.push_int(id.seq.wrapping_sub(master_id.seq) as i64);
+            if same_fields {
+                entry_fields
+                    .iter()
+                    .skip(1)
+                    .step_by(2)
+                    .for_each(|value| lp.push_string(value));
+                lp.push_int(pairs as i64 + 3);
+            } else {
+                lp.push_int(pairs as i64);
+                entry_fields.iter().for_each(|value| lp.push_string(value));
+                lp.push_int(pairs as i64 * 2 + 4);
+            }
+        }
+        write_string(out, &lp.finish());
+    }
+
+    write_len(out, stream.len() as u64);
+    write_id(out, stream.last_id());
+    write_id(out, stream.first_id());
+    write_id(out, stream.max_deleted_id());
+    write_len(out, stream.entries_added());
+
+    write_len(out, stream.groups().len() as u64);
+    for (name, group) in stream.groups() {
+        write_string(out, name);
+        write_id(out, group.last_id());
+        // An unknown count is written as -1
+        write_len(out, group.entries_read().unwrap_or(u64::MAX));
+
+        write_len(out, group.pending().len() as u64);
+        for (id, pending) in group.pending() {
+            out.extend_from_slice(&write_raw_id(*id));
+            out.extend_from_slice(&pending.delivery_time.to_le_bytes());
+            write_len(out, pending.delivery_count);
+        }
+
+        write_len(out, group.consumers().len() as u64);
+        for (name, consumer) in group.consumers() {
+            write_string(out, name);
+            out.extend_from_slice(&consumer.seen_time.to_le_bytes());
+            write_len(out, consumer.pending().len() as u64);
+            for id in consumer.pending() {
+                out.extend_from_slice(&write_raw_id(*id));
+            }
+        }
+    }
+}
+
+fn write_id(out: &mut Vec<u8>, id: StreamId) {
+    write_len(out, id.ms);
+    write_len(out, id.seq);
+}
+
+/// A stream ID as 16 big endian bytes, which sort as the IDs do
+fn write_raw_id(id: StreamId) -> [u8; 16] {
+    let mut raw = [0; 16];
+    raw[..8].copy_from_slice(&id.ms.to_be_bytes());
+    raw[8..].copy_from_slice(&id.seq.to_be_bytes());
+    raw
+}
+
+fn raw_id(raw: &[u8]) -> RdbResult<StreamId> {
+    if raw.len() != 16 {
+        return Err(RdbError);
+    }
+
+    Ok(StreamId::new(
+        u64::from_be_bytes(raw[..8].try_into().unwrap()),
+        u64::from_be_bytes(raw[8..].try_into().unwrap()),
+    ))
+}
+
+/// Parses an RDB file, checking its version and checksum, which Redis
+/// leaves as zero when checksums are turned off. Keys which have expired are
+/// left out, as Redis does when loading as a master.
+fn parse(input: &[u8], config: &Config) -> RdbResult<Snapshot> {
+    let header_len = RDB_MAGIC.len() + 4;
+    if input.len() < header_len + 9 || !input.starts_with(RDB_MAGIC) {
+        return Err(RdbError);
+    }
+
+    let version = std::str::from_utf8(&input[RDB_MAGIC.len()..header_len])
+        .ok()
+        .and_then(|version| version.parse::<u16>().ok())
+        .ok_or(RdbError)?;
+    if version > RDB_VERSION {
+        warn!("Can't handle RDB format version {}", version);
+        return Err(RdbError);
+    }
+
+    let (body, crc) = input.split_at(input.len() - 8);
+    let crc = u64::from_le_bytes(crc.try_into().unwrap());
+    if crc != 0 && crc64(body) != crc {
+        warn!("Wrong RDB checksum");
+        return Err(RdbError);
+    }
+
+    let (now, now_ms) = (Instant::now(), unix_time_ms(SystemTime::now()));
+    let mut snapshot = Snapshot::default();
+    let mut reader = Reader::new(&body[header_len..]);
+    let mut expires_ms = None;
+    loop {
+        match reader.read_u8()? {
+            RDB_OPCODE_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            RDB_OPCODE_FUNCTION2 => snapshot.libraries.push(reader.read_string()?),
+            RDB_OPCODE_SELECTDB => {
+                if reader.read_len()? != 0 {
+                    warn!("The RDB file has keys of databases other than the first");
+                    return Err(RdbError);
+                }
+            }
+            RDB_OPCODE_RESIZEDB => {
+                reader.read_len()?;
+                reader.read_len()?;
+            }
+            RDB_OPCODE_EXPIRETIME_MS => expires_ms = Some(reader.read_millis()?),
+            RDB_OPCODE_EXPIRETIME => {
+                let seconds = u32::from_le_bytes(reader.read_bytes(4)?.try_into().unwrap());
+                expires_ms = Some(u64::from(seconds) * 1000);
+            }
+            // The LRU and LFU information isn't kept
+            RDB_OPCODE_IDLE => {
+                reader.read_len()?;
+            }
+            RDB_OPCODE_FREQ => {
+                reader.read_u8()?;
+            }
+            RDB_OPCODE_EOF if reader.is_empty() => return Ok(snapshot),
+            value_type => {
+                let key = reader.read_string()?;
+                let value = read_object(&mut reader, value_type, config)?;
+                let expires_at = match expires_ms.take() {
+                    Some(ms) if ms <= now_ms => continue,
+                    Some(ms) => Some(now + Duration::from_millis(ms - now_ms)),
+                    None => None,
+                };
+
+                snapshot.entries.push(Entry {
+                    key,
+                    value,
+                    expires_at,
+                });
+            }
+        }
+    }
+}
+
+/// Reads a value of the type given, as Redis 7.0 writes it or in the plain
+/// encodings still loaded, converting it to the encoding configured
+fn read_object(reader: &mut Reader, value_type: u8, config: &Config) -> RdbResult<RObj> {
+    let value = match value_type {
+        RDB_TYPE_STRING => RObj::from(reader.read_string()?),
+        RDB_TYPE_LIST => {
+            let values = (0..reader.read_count()?)
+                .map(|_| reader.read_string())
+                .collect::<RdbResult<Vec<_>>>()?;
+            RObj::new_list_from(values, config.list_listpack_limits())
+        }
+        RDB_TYPE_LIST_QUICKLIST_2 => {
+            let mut values = vec![];
+            for _ in 0..reader.read_count()? {
+                match reader.read_len()? {
+                    QUICKLIST_NODE_CONTAINER_PLAIN => values.push(reader.read_string()?),
+                    QUICKLIST_NODE_CONTAINER_PACKED => {
+                        values.extend(read_listpack(&reader.read_string()?)?);
+                    }
+                    _ => return Err(RdbError),
+                }
+            }
+            RObj::new_list_from(values, config.list_listpack_limits())
+        }
+        RDB_TYPE_HASH => {
+            let mut values = vec![];
+            for _ in 0..reader.read_count()? {
+                values.push(reader.read_string()?);
+                values.push(reader.read_string()?);
+            }
+            RObj::Hash(read_hash(values, config)?)
+        }
+        RDB_TYPE_HASH_LISTPACK => {
+            let values = read_listpack(&reader.read_string()?)?;
+            RObj::Hash(read_hash(values, config)?)
+        }
+        RDB_TYPE_ZSET_2 => {
+            let mut zset = SortedSet::new();
+            for _ in 0..reader.read_count()? {
+                let member = reader.read_string()?;
+                let score = f64::from_le_bytes(reader.read_bytes(8)?.try_into().unwrap());
+                if score.is_nan() {
+                    return Err(RdbError);
+                }
+                zset.insert(member, score, config.zset_listpack_limits());
+            }
+            RObj::SortedSet(zset)
+        }
+        RDB_TYPE_ZSET_LISTPACK => {
+            let values = read_listpack(&reader.read_string()?)?;
+            if values.len() % 2 != 0 {
+                return Err(RdbError);
+            }
+
+            let mut zset = SortedSet::new();
+            let mut values = values.into_iter();
+            while let (Some(member), Some(score)) = (values.next(), values.next()) {
+                let score = std::str::from_utf8(&score)
+                    .ok()
+                    .and_then(|score| score.parse::<f64>().ok())
+                    .filter(|score| !score.is_nan())
+                    .ok_or(RdbError)?;
+                zset.insert(member, score, config.zset_listpack_limits());
+            }
+            RObj::SortedSet(zset)
+        }
+        RDB_TYPE_STREAM_LISTPACKS_2 => RObj::Stream(read_stream(reader)?),
+        _ => {
+            warn!("Can't load values of RDB type {}", value_type);
+            return Err(RdbError);
+        }
+    };
+
+    Ok(value)
+}
+
+fn read_hash(values: Vec<ByteString>, config: &Config) -> RdbResult<Hash> {
+    if values.len() % 2 != 0 {
+        return Err(RdbError);
+    }
+
+    let mut hash = Hash::new();
+    let mut values = values.into_iter();
+    while let (Some(field), Some(value)) = (values.next(), values.next()) {
+        if !hash.insert(field, value, config.hash_listpack_limits()) {
+            return Err(RdbError);
+        }
+    }
+
+    Ok(hash)
+}
+
+fn read_stream(reader: &mut Reader) -> RdbResult<Stream> {
+    let mut entries = BTreeMap::new();
+    for _ in 0..reader.read_count()? {
+        let master_id = raw_id(&reader.read_string()?)?;
+        let lp = read_listpack(&reader.read_string()?)?;
+        let mut items = Items(lp.into_iter());
+
+        let count = items.next_count()?;
+        let deleted = items.next_count()?;
+        let fields = (0..items.next_count()?)
+            .map(|_| items.next_string())
+            .collect::<RdbResult<Vec<_>>>()?;
+        items.next_int()?;
+
+        for _ in 0..count.checked_add(deleted).ok_or(RdbError)? {
+            let flags = items.next_int()?;
+            let id = StreamId::new(
+                master_id.ms.wrapping_add(items.next_int()? as u64),
+                master_id.seq.wrapping_add(items.next_int()? as u64),
+            );
+
+            let mut entry_fields = Fields::new();
+            if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                for field in &fields {
+                    entry_fields.push(field.clone());
+                    entry_fields.push(items.next_string()?);
+                }
+            } else {
+                for _ in 0..items.next_count()?.checked_mul(2).ok_or(RdbError)? {
+                    entry_fields.push(items.next_string()?);
+                }
+            }
+            items.next_int()?;
+
+            if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                entries.insert(id, entry_fields);
+            }
+        }
+    }
+
+    if reader.read_count()? != entries.len() {
+        return Err(RdbError);
+    }
+    let last_id = reader.read_id()?;
+    // The first ID is that of the first entry
+    reader.read_id()?;
+    let max_deleted_id = reader.read_id()?;
+    let entries_added = reader.read_len()?;
+
+    let mut groups = BTreeMap::new();
+    for _ in 0..reader.read_count()? {
+        let name = reader.read_string()?;
+        let group_last_id = reader.read_id()?;
+        let entries_read = Some(reader.read_len()?).filter(|&read| read != u64::MAX);
+
+        // The group's pending entries, each of which is then claimed by the
+        // consumer it is pending for
+        let mut unclaimed = BTreeMap::new();
+        for _ in 0..reader.read_count()? {
+            let id = reader.read_raw_id()?;
+            let delivery_time = reader.read_millis()?;
+            unclaimed.insert(id, (delivery_time, reader.read_len()?));
+        }
+
+        let mut pending = BTreeMap::new();
+        let mut consumers = BTreeMap::new();
+        for _ in 0..reader.read_count()? {
+            let consumer = reader.read_string()?;
+            let seen_time = reader.read_millis()?;
+
+            let mut ids = BTreeSet::new();
+            for _ in 0..reader.read_count()? {
+                let id = reader.read_raw_id()?;
+                let (delivery_time, delivery_count) = unclaimed.remove(&id).ok_or(RdbError)?;
+                pending.insert(
+                    id,
+                    PendingEntry {
+                        consumer: consumer.clone(),
+                        delivery_time,
+                        delivery_count,
+                    },
+                );
+                ids.insert(id);
+            }
+
+            // Redis 7.0 doesn't save when a consumer was last active, the
+            // time it was last seen is the best estimate, as in Redis 7.2
+            consumers.insert(
+                consumer,
+                Consumer::restored(seen_time, Some(seen_time), ids),
+            );
+        }
+        if !unclaimed.is_empty() {
+            return Err(RdbError);
+        }
+
+        let group = ConsumerGroup::restored(group_last_id, entries_read, pending, consumers);
+        groups.insert(name, group);
+    }
+
+    Ok(Stream::restored(
+        entries,
+        last_id,
+        max_deleted_id,
+        entries_added,
+        groups,
+    ))
+}
+
+/// The elements of a listpack read in turn, as the nodes of a stream
+struct Items(std::vec::IntoIter<ByteString>);
+
+impl Items {
+    fn next_string(&mut self) -> RdbResult<ByteString> {
+        self.0.next().ok_or(RdbError)
+    }
+
+    fn next_int(&mut self) -> RdbResult<i64> {
+        self.next_string()?.parse().map_err(|_| RdbError)
+    }
+
+    fn next_count(&mut self) -> RdbResult<usize> {
+        usize::try_from(self.next_int()?).map_err(|_| RdbError)
+    }
+}
+
+/// Builds the serialization of a listpack, as laid out by Redis's
+/// `listpack.c`: a header with its size in bytes and its number of elements,
+/// then each element's encoding and data followed by their length for
+/// traversing it backwards, and a terminator
+#[derive(Default)]
+struct ListpackWriter {
+    elements: Vec<u8>,
+    count: usize,
+}
+
+const LP_HEADER_SIZE: usize = 6;
+const LP_EOF: u8 = 0xff;
+
+impl ListpackWriter {
+    fn push_string(&mut self, value: &[u8]) {
+        let start = self.elements.len();
+        let len = value.len();
+        if len < 1 << 6 {
+            self.elements.push(0x80 | len as u8);
+        } else if len < 1 << 12 {
+            self.elements.push(0xe0 | (len >> 8) as u8);
+            self.elements.push(len as u8);
+        } else {
+            self.elements.push(0xf0);
+            self.elements.extend_from_slice(&(len as u32).to_le_bytes());
+        }
+        self.elements.extend_from_slice(value);
+        self.end_element(start);
+    }
+
+    fn push_int(&mut self, n: i64) {
+        let start = self.elements.len();
+        if (0..1 << 7).contains(&n) {
+            self.elements.push(n as u8);
+        } else if (-(1 << 12)..1 << 12).contains(&n) {
+            let n = n as u16 & 0x1fff;
+            self.elements.push(0xc0 | (n >> 8) as u8);
+            self.elements.push(n as u8);
+        } else if let Ok(n) = i16::try_from(n) {
+            self.elements.push(0xf1);
+            self.elements.extend_from_slice(&n.to_le_bytes());
+        } else if (-(1 << 23)..1 << 23).contains(&n) {
+            self.elements.push(0xf2);
+            self.elements.extend_from_slice(&n.to_le_bytes()[..3]);
+        } else if let Ok(n) = i32::try_from(n) {
+            self.elements.push(0xf3);
+            self.elements.extend_from_slice(&n.to_le_bytes());
+        } else {
+            self.elements.push(0xf4);
+            self.elements.extend_from_slice(&n.to_le_bytes());
+        }
+        self.end_element(start);
+    }
+
+    /// Appends the length of the element starting at `start`, most
+    /// significant 7 bits first and all but those flagged
+    fn end_element(&mut self, start: usize) {
+        let len = self.elements.len() - start;
+        let groups = backlen_size(len);
+        for i in (0..groups).rev() {
+            let group = (len >> (7 * i)) as u8 & 0x7f;
+            self.elements
+                .push(if i == groups - 1 { group } else { group | 0x80 });
+        }
+        self.count += 1;
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let total = LP_HEADER_SIZE + self.elements.len() + 1;
+        let mut lp = Vec::with_capacity(total);
+        lp.extend_from_slice(&(total as u32).to_le_bytes());
+        // The count saturates, it is then found by traversing the listpack
+        lp.extend_from_slice(&(self.count.min(usize::from(u16::MAX)) as u16).to_le_bytes());
+        lp.extend_from_slice(&self.elements);
+        lp.push(LP_EOF);
+        lp
+    }
+}
+
+/// How many bytes the length of an element of `len` bytes takes
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2_097_150 => 3,
+        2_097_151..=268_435_454 => 4,
+        _ => 5,
+    }
+}
+
+/// Reads the elements of a listpack, integers as their decimal
+/// representation
+fn read_listpack(lp: &[u8]) -> RdbResult<Vec<ByteString>> {
+    if lp.len() < LP_HEADER_SIZE + 1
+        || u32::from_le_bytes(lp[..4].try_into().unwrap()) as usize != lp.len()
+        || lp[lp.len() - 1] != LP_EOF
+    {
+        return Err(RdbError);
+    }
+
+    let mut reader = Reader::new(&lp[LP_HEADER_SIZE..lp.len() - 1]);
+    let mut elements = vec![];
+    while !reader.is_empty() {
+        let encoding = reader.read_u8()?;
+        let (element, len) = match encoding {
+            0x00..=0x7f => (ByteString::from(encoding.to_string()), 1),
+            0x80..=0xbf => {
+                let len = usize::from(encoding & 0x3f);
+                (reader.read_bytes(len)?.into(), 1 + len)
+            }
+            0xc0..=0xdf => {
+                let n = (u16::from(encoding & 0x1f) << 8) | u16::from(reader.read_u8()?);
+                // Sign extended from 13 bits
+                let n = i64::from((n << 3) as i16 >> 3);
+                (n.to_string().into(), 2)
+            }
+            0xe0..=0xef => {
+                let len = (usize::from(encoding & 0x0f) << 8) | usize::from(reader.read_u8()?);
+                (reader.read_bytes(len)?.into(), 2 + len)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(reader.read_bytes(4)?.try_into().unwrap()) as usize;
+                (reader.read_bytes(len)?.into(), 5 + len)
+            }
+            0xf1 => {
+                let n = i16::from_le_bytes(reader.read_bytes(2)?.try_into().unwrap());
+                (n.to_string().into(), 3)
+            }
+            0xf2 => {
+                let bytes = reader.read_bytes(3)?;
+                let n = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                (n.to_string().into(), 4)
+            }
+            0xf3 => {
+                let n = i32::from_le_bytes(reader.read_bytes(4)?.try_into().unwrap());
+                (n.to_string().into(), 5)
+            }
+            0xf4 => {
+                let n = i64::from_le_bytes(reader.read_bytes(8)?.try_into().unwrap());
+                (n.to_string().into(), 9)
+            }
+            _ => return Err(RdbError),
+        };
+
+        reader.read_bytes(backlen_size(len))?;
+        elements.push(element);
+    }
+
+    Ok(elements)
+}
+
+fn unix_time_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// The Jones CRC64 used by Redis's `crc64.c`
+pub fn crc64(bytes: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+    let mut crc = 0u64;
+    for &byte in bytes {
+        crc ^= u64::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn test_len_round_trip() {
+        for &len in &[0, 63, 64, 16383, 16384, u64::from(u32::MAX), u64::MAX] {
+            let mut out = vec![];
+            write_len(&mut out, len);
+            assert_eq!(Reader::new(&out).read_len(), Ok(len));
+        }
+    }
+
+    #[test]
+    fn test_read_encoded_strings() {
+        let mut reader = Reader::new(&[0xc0, 0xfe, 0xc1, 0x39, 0x30, 0x02, b'h', b'i']);
+
+        assert_eq!(reader.read_string(), Ok("-2".into()));
+        assert_eq!(reader.read_string(), Ok("12345".into()));
+        assert_eq!(reader.read_string(), Ok("hi".into()));
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn test_lzf_decompress() {
+        // "aaaaaaaaaa" compressed: the literal "a" then a reference to it
+        assert_eq!(
+            lzf_decompress(&[0x00, b'a', 0xe0, 0x00, 0x00], 10),
+            Ok(b"aaaaaaaaaa".to_vec())
+        );
+        assert_eq!(lzf_decompress(&[0x00, b'a', 0x20, 0x05], 4), Err(RdbError));
+    }
+
+    #[test]
+    fn test_serialize_and_parse() {
+        let config = Config::default();
+        let db = Database::new();
+        let code = ByteString::from(
+            "#!lua name=mylib\nredis.register_function('f', function() return 1 end)",
+        );
+        db.functions().lock().unwrap().load(&code, false).unwrap();
+
+        let mut rdb = serialize(&db);
+        assert!(rdb.starts_with(b"REDIS0010"));
+        assert_eq!(
+            parse(&rdb, &config),
+            Ok(Snapshot {
+                libraries: vec![code],
+                entries: vec![],
+            })
+        );
+
+        // Redis may not checksum the file
+        let len = rdb.len();
+        rdb[len - 8..].copy_from_slice(&[0; 8]);
+        assert!(parse(&rdb, &config).is_ok());
+
+        rdb[len - 9] = 0;
+        assert_eq!(parse(&rdb, &config), Err(RdbError));
+        assert_eq!(
+            parse(b"REDIS0099\xff\0\0\0\0\0\0\0\0", &config),
+            Err(RdbError)
+        );
+        assert_eq!(
+            parse(b"RADIS0010\xff\0\0\0\0\0\0\0\0", &config),
+            Err(RdbError)
+        );
+    }
+
+    #[test]
+    fn test_dataset_round_trip() {
+        let config = Config::default();
+        let mut db = Database::new();
+        let now = Instant::now();
+
+        let list = RObj::new_list_from(
+            vec!["a".into(), "b".into(), "a".into()],
+            config.list_listpack_limits(),
+        );
+        let mut hash = Hash::new();
+        hash.insert("f".into(), "v".into(), config.hash_listpack_limits());
+        let mut zset = SortedSet::new();
+        zset.insert("m".into(), 1.5, config.zset_listpack_limits());
+        zset.insert("n".into(), f64::NEG_INFINITY, config.zset_listpack_limits());
+
+        // Enough entries for more than one node, some with the fields of the
+        // first in their node and some deleted
+        let mut stream = Stream::new();
+        for n in 1..=250u64 {
+            let fields = match n % 3 {
+                0 => vec!["other".into(), n.to_string().into()],
+                _ => vec!["field".into(), n.to_string().into(), "x".into(), "y".into()],
+            };
+            stream.add(StreamId::new(1000 + n, n % 2), fields);
+        }
+        stream.delete(&StreamId::new(1002, 0));
+        let (group, consumer) = (ByteString::from("group"), ByteString::from("alice"));
+        stream.create_group(&group, StreamId::MIN, Some(0));
+        stream.read_group(&group, &consumer, Some(5), false, 12345);
+
+        db.insert("int".into(), RObj::Int(-70000));
+        db.insert("string".into(), RObj::String("hello".into()));
+        db.insert("list".into(), list);
+        db.insert("hash".into(), RObj::Hash(hash));
+        db.insert("zset".into(), RObj::SortedSet(zset));
+        db.insert("stream".into(), RObj::Stream(stream));
+        db.insert("expiring".into(), RObj::Int(1));
+        db.set_expire(&"expiring".into(), now + Duration::from_secs(100));
+        db.insert("expired".into(), RObj::Int(1));
+        db.set_expire(&"expired".into(), now);
+
+        let mut snapshot = parse(&serialize(&db), &config).unwrap();
+        snapshot.entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let keys: Vec<_> = snapshot.entries.iter().map(|e| e.key.clone()).collect();
+        assert_eq!(
+            keys,
+            ["expiring", "hash", "int", "list", "stream", "string", "zset"]
+                .iter()
+                .map(|&key| ByteString::from(key))
+                .collect::<Vec<_>>()
+        );
+
+        let expiring = ByteString::from("expiring");
+        for entry in snapshot.entries {
+            assert_eq!(Some(&entry.value), db.get(&entry.key));
+            match entry.expires_at {
+                Some(expires_at) => {
+                    assert_eq!(entry.key, expiring);
+                    let ttl = expires_at.duration_since(now);
+                    assert!(ttl > Duration::from_secs(99) && ttl <= Duration::from_secs(101));
+                }
+                None => assert_ne!(entry.key, expiring),
+            }
+        }
+    }
+
+    #[test]
+    fn test_listpack_round_trip() {
+        let strings: Vec<Vec<u8>> = vec![vec![], vec![b'a'; 63], vec![b'b'; 64], vec![b'c'; 5000]];
+        let ints = [
+            0,
+            127,
+            128,
+            -1,
+            -4096,
+            4095,
+            4096,
+            -32768,
+            32767,
+            -8_388_608,
+            8_388_607,
+            i64::from(i32::MIN),
+            i64::from(i32::MAX),
+            i64::MIN,
+            i64::MAX,
+        ];
+
+        let mut lp = ListpackWriter::default();
+        strings.iter().for_each(|s| lp.push_string(s));
+        ints.iter().for_each(|&n| lp.push_int(n));
+        let lp = lp.finish();
+        assert_eq!(
+            u16::from_le_bytes([lp[4], lp[5]]) as usize,
+            strings.len() + ints.len()
+        );
+
+        let expected: Vec<ByteString> = strings
+            .into_iter()
+            .map(ByteString::from)
+            .chain(ints.iter().map(|n| n.to_string().into()))
+            .collect();
+        assert_eq!(read_listpack(&lp), Ok(expected));
+
+        // As written by Redis: "hi" and 1000, then the terminator
+        let redis = b"\x0e\0\0\0\x02\0\x82hi\x03\xc3\xe8\x02\xff";
+        assert_eq!(read_listpack(redis), Ok(vec!["hi".into(), "1000".into()]));
+        assert_eq!(read_listpack(&redis[..13]), Err(RdbError));
+    }
+
+    #[test]
+    fn test_footer() {
+        let mut payload = vec![RDB_OPCODE_FUNCTION2];
+        write_string(&mut payload, b"code");
+        write_footer(&mut payload);
+
+        let body = verify_footer(&payload).unwrap();
+        assert_eq!(body, &[RDB_OPCODE_FUNCTION2, 4, b'c', b'o', b'd', b'e']);
+
+        payload[1] = 5;
+        assert_eq!(verify_footer(&payload), Err(RdbError));
+    }
+}
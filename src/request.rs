@@ -5,14 +5,22 @@ use crate::{
 use byte_string::{ByteStr, ByteString};
 use std::convert::{TryFrom, TryInto};
 use std::marker::Unpin;
-use tokio::io::AsyncBufRead;
-
-pub async fn parse(stream: &mut (impl AsyncBufRead + Unpin + Send)) -> Result<Request> {
-    let query = protocol::decode(stream).await?;
-    Request::try_from(query)
+use tokio::io::AsyncRead;
+
+/// Reads the next request from the stream. Its arguments share the part of
+/// the decoder's buffer it was read into, which is split off for the request
+/// to keep, rather than being copied out of it.
+pub async fn parse(
+    stream: &mut (impl AsyncRead + Unpin),
+    decoder: &mut protocol::Decoder,
+    limits: protocol::Limits,
+) -> Result<Request> {
+    decoder.decode(stream, limits).await?;
+    let query = decoder.take_args().into_iter().map(ByteString::shared);
+    Request::try_from(query.collect::<Vec<_>>())
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Request {
     query: Vec<ByteString>,
 }
@@ -37,6 +45,11 @@ impl Request {
         self.query.len().try_into().unwrap()
     }
 
+    /// The command name followed by its arguments
+    pub fn argv(&self) -> &[ByteString] {
+        &self.query
+    }
+
     pub fn arguments(&self) -> &[ByteString] {
         &self.query[1..]
     }
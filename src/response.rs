@@ -1,4 +1,39 @@
-use std::{fmt::Display, io::Write};
+use crate::output_buffer::{OutputBuffer, OutputLimits};
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io::Write,
+    mem,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// The size of the chunks a large reply is sent to the connection in, as
+/// Redis's `PROTO_REPLY_CHUNK_BYTES`
+pub const REPLY_CHUNK_BYTES: usize = 16 * 1024;
+
+/// How many chunks of a reply may be in the channel to the connection at
+/// once. The channel has room for one more message, kept for `Reply::Done`.
+pub const REPLY_QUEUE_CHUNKS: usize = 16;
+
+/// How many chunks the connection has no room for yet may be held before the
+/// shard building the reply waits for it to make room
+pub const REPLY_BACKLOG_CHUNKS: usize = 64;
+
+/// How long a shard waits for a client that has stopped reading before
+/// closing it
+const REPLY_STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a waiting shard checks whether the connection has made room
+const REPLY_WAIT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The channel a connection is sent the reply to a command through, with
+/// room for `REPLY_QUEUE_CHUNKS` chunks and the rest of the reply
+pub fn reply_channel() -> (mpsc::Sender<Reply>, mpsc::Receiver<Reply>) {
+    mpsc::channel(REPLY_QUEUE_CHUNKS + 1)
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u8)]
@@ -22,14 +57,157 @@ impl RespSym {
     }
 }
 
+/// What a connection is sent in answer to a command: any chunks of a large
+/// reply as they are built, then the rest of the reply
+#[derive(Debug)]
+pub enum Reply {
+    Chunk(Vec<u8>),
+    Done(Response),
+}
+
+/// Where the chunks of a reply are sent, and the output buffer they are
+/// counted against. Chunks the connection has no room for yet are held
+/// here, up to `REPLY_BACKLOG_CHUNKS` of them, then the shard waits for the
+/// client to read. A client that stops reading altogether is closed after
+/// `REPLY_STALL_TIMEOUT`, so it holds up the other clients of the shard for
+/// no longer than that.
+#[derive(Debug)]
+pub struct ReplySink {
+    sender: mpsc::Sender<Reply>,
+    output: Arc<OutputBuffer>,
+    limits: OutputLimits,
+    backlog: VecDeque<Vec<u8>>,
+    stall_timeout: Duration,
+}
+
+impl ReplySink {
+    pub fn new(
+        sender: mpsc::Sender<Reply>,
+        output: Arc<OutputBuffer>,
+        limits: OutputLimits,
+    ) -> Self {
+        Self {
+            sender,
+            output,
+            limits,
+            backlog: VecDeque::new(),
+            stall_timeout: REPLY_STALL_TIMEOUT,
+        }
+    }
+
+    /// Queues the chunk for the connection, unless that would take the
+    /// output buffer past its limits, in which case the connection closes
+    fn send(&mut self, chunk: Vec<u8>) {
+        if self.output.queue(chunk.len(), self.limits, Instant::now()) {
+            self.backlog.push_back(chunk);
+        }
+        self.flush_backlog();
+
+        if self.backlog.len() > REPLY_BACKLOG_CHUNKS {
+            self.wait_for_room();
+        }
+    }
+
+    /// Blocks the shard until the connection has taken enough of the held
+    /// chunks, has gone, or the client is to be closed for exceeding its
+    /// limits or for not reading at all
+    fn wait_for_room(&mut self) {
+        let mut progressed_at = Instant::now();
+
+        while self.backlog.len() > REPLY_BACKLOG_CHUNKS {
+            let now = Instant::now();
+            if self.sender.is_closed() || !self.output.check_limits(self.limits, now) {
+                self.discard_backlog();
+                return;
+            }
+            if now.duration_since(progressed_at) > self.stall_timeout {
+                self.output.overflow();
+                self.discard_backlog();
+                return;
+            }
+
+            thread::sleep(REPLY_WAIT_INTERVAL);
+            let held = self.backlog.len();
+            self.flush_backlog();
+            if self.backlog.len() < held {
+                progressed_at = Instant::now();
+            }
+        }
+    }
+
+    /// Drops the held chunks, which will never be written
+    fn discard_backlog(&mut self) {
+        let len: usize = self.backlog.drain(..).map(|chunk| chunk.len()).sum();
+        self.output.written(len);
+    }
+
+    /// Moves held chunks into the channel whilst it has room for them
+    fn flush_backlog(&mut self) {
+        while self.sender.capacity() > 1 {
+            let chunk = match self.backlog.pop_front() {
+                Some(chunk) => chunk,
+                None => return,
+            };
+            match self.sender.try_send(Reply::Chunk(chunk)) {
+                Ok(()) => (),
+                Err(TrySendError::Full(reply)) => {
+                    if let Reply::Chunk(chunk) = reply {
+                        self.backlog.push_front(chunk);
+                    }
+                    return;
+                }
+                Err(TrySendError::Closed(reply)) => {
+                    // The connection has gone
+                    if let Reply::Chunk(chunk) = reply {
+                        self.output.written(chunk.len());
+                    }
+                    self.discard_backlog();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReplySink {
+    fn drop(&mut self) {
+        self.discard_backlog();
+    }
+}
+
 #[derive(Debug)]
 pub struct Response {
     buffer: Vec<u8>,
+    sink: Option<ReplySink>,
+    /// The number of bytes already sent to the sink
+    sent: usize,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Response {
     pub fn new() -> Self {
-        Self { buffer: vec![] }
+        Self {
+            buffer: vec![],
+            sink: None,
+            sent: 0,
+        }
+    }
+
+    /// A response that is sent to the sink a chunk at a time as it is built,
+    /// rather than being held in memory until complete. Whatever is left
+    /// once the command is done still has to be sent as `Reply::Done`, along
+    /// with any chunks held back by the sink.
+    pub fn streamed(sink: ReplySink) -> Self {
+        Self {
+            buffer: Vec::with_capacity(REPLY_CHUNK_BYTES),
+            sink: Some(sink),
+            sent: 0,
+        }
     }
 
     pub fn add_array_len(&mut self, len: i64) {
@@ -45,6 +223,7 @@ impl Response {
         self.add(BulkString, value.len());
         let iter = value.iter().chain(b"\r\n");
         self.buffer.extend(iter);
+        self.flush_chunk();
     }
 
     pub fn add_null_array(&mut self) {
@@ -63,16 +242,54 @@ impl Response {
         self.add(Error, value);
     }
 
+    /// Appends another response, such as elements of an array whose length
+    /// wasn't known until they were added
+    pub fn append(&mut self, other: Response) {
+        self.buffer.extend(other.buffer);
+        self.flush_chunk();
+    }
+
     fn add(&mut self, sym: RespSym, value: impl Display) {
         #[allow(clippy::write_with_newline)]
         write!(self.buffer, "{}{}\r\n", sym.as_char(), value)
             .expect("failed write to response buffer");
+        self.flush_chunk();
     }
 
+    /// Sends what has been built so far once it fills a chunk
+    fn flush_chunk(&mut self) {
+        if let Some(sink) = &mut self.sink {
+            if self.buffer.len() >= REPLY_CHUNK_BYTES {
+                let chunk = mem::replace(&mut self.buffer, Vec::with_capacity(REPLY_CHUNK_BYTES));
+                self.sent += chunk.len();
+                sink.send(chunk);
+            }
+        }
+    }
+
+    /// The chunks of a streamed reply the connection had no room for whilst
+    /// it was built, to be written before the rest of it
+    pub fn take_held_chunks(&mut self) -> VecDeque<Vec<u8>> {
+        match &mut self.sink {
+            Some(sink) => mem::take(&mut sink.backlog),
+            None => VecDeque::new(),
+        }
+    }
+
+    /// The reply, or what is left of it when it is streamed
     pub fn as_bytes(&self) -> &[u8] {
         self.buffer.as_ref()
     }
 
+    /// The size of the whole reply, including any chunks already sent
+    pub fn len(&self) -> usize {
+        self.sent + self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     #[cfg(test)]
     pub fn as_string(&self) -> String {
         std::str::from_utf8(&self.buffer)
@@ -146,6 +363,157 @@ mod test {
         assert_eq!(builder.as_bytes(), b"$-1\r\n");
     }
 
+    #[test]
+    fn test_append() {
+        let mut builder = Response::new();
+        let mut other = Response::new();
+        other.add_integer(1);
+        builder.add_array_len(1);
+        builder.append(other);
+        assert_eq!(builder.as_bytes(), b"*1\r\n:1\r\n");
+    }
+
+    #[test]
+    fn test_streamed() {
+        let (sender, mut receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let sink = ReplySink::new(sender, Arc::clone(&output), OutputLimits::default());
+        let mut builder = Response::streamed(sink);
+
+        let value = vec![b'x'; 10000];
+        builder.add_array_len(3);
+        builder.add_bulk_string(&value);
+        assert!(receiver.try_recv().is_err());
+        builder.add_bulk_string(&value);
+        builder.add_integer(1);
+
+        let chunk = match receiver.try_recv() {
+            Ok(Reply::Chunk(chunk)) => chunk,
+            other => panic!("expected a chunk, got {:?}", other),
+        };
+        assert_eq!(chunk.len(), 4 + 2 * (8 + 10000 + 2));
+        assert_eq!(output.queued(), chunk.len());
+        assert_eq!(builder.as_bytes(), b":1\r\n");
+        assert_eq!(builder.len(), chunk.len() + 4);
+    }
+
+    #[test]
+    fn test_streamed_past_limits() {
+        let (sender, mut receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let limits = OutputLimits::new(REPLY_CHUNK_BYTES as u64, 0, 0);
+        let mut builder = Response::streamed(ReplySink::new(sender, Arc::clone(&output), limits));
+
+        builder.add_bulk_string(vec![b'x'; REPLY_CHUNK_BYTES]);
+        assert!(receiver.try_recv().is_err());
+        assert!(output.is_overflowed());
+    }
+
+    #[test]
+    fn test_streamed_to_slow_connection() {
+        let (sender, mut receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let sink = ReplySink::new(sender.clone(), Arc::clone(&output), OutputLimits::default());
+        let mut builder = Response::streamed(sink);
+
+        for _ in 0..REPLY_QUEUE_CHUNKS + 3 {
+            builder.add_bulk_string(vec![b'x'; REPLY_CHUNK_BYTES]);
+        }
+        let held = builder.take_held_chunks();
+        assert_eq!(held.len(), 3);
+        assert!(sender.try_send(Reply::Done(builder)).is_ok());
+
+        let mut chunks = 0;
+        while let Ok(Reply::Chunk(_)) = receiver.try_recv() {
+            chunks += 1;
+        }
+        assert_eq!(chunks, REPLY_QUEUE_CHUNKS);
+        assert!(output.queued() >= (REPLY_QUEUE_CHUNKS + 3) * REPLY_CHUNK_BYTES);
+    }
+
+    #[test]
+    fn test_streamed_reply_dropped() {
+        let (sender, _receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let sink = ReplySink::new(sender, Arc::clone(&output), OutputLimits::default());
+        let mut builder = Response::streamed(sink);
+
+        for _ in 0..REPLY_QUEUE_CHUNKS + 3 {
+            builder.add_bulk_string(vec![b'x'; REPLY_CHUNK_BYTES]);
+        }
+        let in_channel = output.queued();
+        drop(builder);
+        assert!(output.queued() < in_channel);
+        assert!(output.queued() > 0);
+    }
+
+    #[test]
+    fn test_streamed_waits_for_connection() {
+        let (sender, mut receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let sink = ReplySink::new(sender.clone(), Arc::clone(&output), OutputLimits::default());
+        let mut builder = Response::streamed(sink);
+
+        let written = Arc::clone(&output);
+        let reader = thread::spawn(move || {
+            let mut chunks = 0;
+            while let Some(Reply::Chunk(chunk)) = receiver.blocking_recv() {
+                written.written(chunk.len());
+                chunks += 1;
+                thread::sleep(Duration::from_micros(100));
+            }
+            chunks
+        });
+
+        let total = REPLY_QUEUE_CHUNKS + REPLY_BACKLOG_CHUNKS * 3;
+        for _ in 0..total {
+            builder.add_bulk_string(vec![b'x'; REPLY_CHUNK_BYTES]);
+            let held = output.queued() / REPLY_CHUNK_BYTES;
+            assert!(held <= REPLY_QUEUE_CHUNKS + REPLY_BACKLOG_CHUNKS + 1);
+        }
+        let held = builder.take_held_chunks().len();
+        assert!(held <= REPLY_BACKLOG_CHUNKS);
+        assert!(sender.try_send(Reply::Done(builder)).is_ok());
+        drop(sender);
+
+        assert_eq!(reader.join().unwrap() + held, total);
+        assert!(!output.is_overflowed());
+    }
+
+    #[test]
+    fn test_streamed_stops_waiting_when_dropped() {
+        let (sender, receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let sink = ReplySink::new(sender, Arc::clone(&output), OutputLimits::default());
+        let mut builder = Response::streamed(sink);
+
+        let reader = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(receiver);
+        });
+
+        for _ in 0..REPLY_QUEUE_CHUNKS + REPLY_BACKLOG_CHUNKS + 3 {
+            builder.add_bulk_string(vec![b'x'; REPLY_CHUNK_BYTES]);
+        }
+        reader.join().unwrap();
+        assert!(builder.take_held_chunks().len() <= 3);
+    }
+
+    #[test]
+    fn test_streamed_to_stalled_connection() {
+        let (sender, _receiver) = reply_channel();
+        let output = Arc::new(OutputBuffer::new());
+        let mut sink = ReplySink::new(sender, Arc::clone(&output), OutputLimits::default());
+        sink.stall_timeout = Duration::from_millis(20);
+        let mut builder = Response::streamed(sink);
+
+        for _ in 0..REPLY_QUEUE_CHUNKS + REPLY_BACKLOG_CHUNKS + 3 {
+            builder.add_bulk_string(vec![b'x'; REPLY_CHUNK_BYTES]);
+        }
+        assert!(output.is_overflowed());
+        assert!(builder.take_held_chunks().len() <= 3);
+    }
+
     #[test]
     fn test_error() {
         let mut builder = Response::new();
@@ -0,0 +1,853 @@
+//! The Lua 5.1 interpreter behind EVAL and EVALSHA, modelled on Redis's
+//! `scripting.c`. Scripts run on a shard with exclusive access to its
+//! database, so the connections watch each shard's [`ScriptBusy`] to answer
+//! other clients while a slow script is running.
+
+use crate::{db::Database, request::Request, response::Response};
+use byte_string::ByteString;
+use log::{debug, error, info, warn};
+use mlua::{
+    ChunkMode, Function, HookTriggers, Lua, LuaOptions, RegistryKey, StdLib, Table, Value, Variadic,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// How often, in Lua VM instructions, a running script checks whether it has
+/// been killed
+const KILL_CHECK_INTERVAL: u32 = 100_000;
+
+/// Defines the `redis` library and protects the globals. Returns a table that
+/// only the server can reach, holding the command dispatcher of the current
+/// script and the function used to run scripts.
+const PRELUDE: &str = r#"
+local pcall, error, type = pcall, error, type
+local internal = {}
+
+redis = {
+    LOG_DEBUG = 0,
+    LOG_VERBOSE = 1,
+    LOG_NOTICE = 2,
+    LOG_WARNING = 3,
+}
+
+function redis.call(...)
+    local reply = internal.dispatch(...)
+    if type(reply) == "table" and reply.err then
+        error(reply)
+    end
+    return reply
+end
+
+function redis.pcall(...)
+    return internal.dispatch(...)
+end
+
+function redis.error_reply(msg)
+    return { err = msg }
+end
+
+function redis.status_reply(msg)
+    return { ok = msg }
+end
+
+function internal.run(f, ...)
+    return pcall(f, ...)
+end
+
+loadfile = nil
+dofile = nil
+
+setmetatable(_G, {
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __index = function(_, name)
+        error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+    end,
+})
+
+return internal
+"#;
+
+/// Whether a script is running, shared between the API task and the
+/// connections
+#[derive(Debug, Default)]
+pub struct ScriptBusy {
+    started: Mutex<Option<Instant>>,
+    wrote: AtomicBool,
+    kill_requested: AtomicBool,
+}
+
+impl ScriptBusy {
+    /// How long the current script has been running for
+    pub fn running_for(&self) -> Option<Duration> {
+        self.started
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .map(|started| started.elapsed())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running_for().is_some()
+    }
+
+    /// Asks the running script to stop. A script which has already written
+    /// to the dataset can't be killed, as that would leave it half done.
+    pub fn kill(&self) -> bool {
+        if self.wrote.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        self.kill_requested.store(true, Ordering::SeqCst);
+        true
+    }
+
+    fn start(&self) {
+        self.wrote.store(false, Ordering::SeqCst);
+        self.kill_requested.store(false, Ordering::SeqCst);
+        *self.started.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    }
+
+    fn stop(&self) {
+        *self.started.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+/// Whether a script sees its keys and arguments as the `KEYS` and `ARGV`
+/// globals, like EVAL, or as the two parameters of a function, like FCALL
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptKind {
+    Eval,
+    Function,
+}
+
+/// What to run a script with
+pub struct ScriptCall<'a> {
+    pub kind: ScriptKind,
+    /// The name used in error messages, such as `f_<sha1>`
+    pub name: &'a str,
+    pub keys: &'a [ByteString],
+    pub args: &'a [ByteString],
+    /// Whether write commands are refused
+    pub read_only: bool,
+}
+
+/// A Lua interpreter with the `redis` library, shared by scripts and
+/// function libraries
+pub struct LuaEngine {
+    lua: Lua,
+    internal: RegistryKey,
+    busy: Arc<ScriptBusy>,
+}
+
+impl LuaEngine {
+    pub fn new(busy: Arc<ScriptBusy>) -> Self {
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+            LuaOptions::new(),
+        )
+        .expect("failed to create the Lua interpreter");
+
+        let internal =
+            init_lua(&lua, Arc::clone(&busy)).expect("failed to set up the Lua libraries");
+        let internal = lua
+            .create_registry_value(internal)
+            .expect("failed to set up the Lua libraries");
+
+        Self {
+            lua,
+            internal,
+            busy,
+        }
+    }
+
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn busy(&self) -> &Arc<ScriptBusy> {
+        &self.busy
+    }
+
+    /// Runs a compiled script or function, adding whatever it returns, or
+    /// the error it raised, to the response
+    pub fn run(
+        &self,
+        db: &mut Database,
+        function: &RegistryKey,
+        call: ScriptCall<'_>,
+        response: &mut Response,
+    ) {
+        self.busy.start();
+        let result = self.call(db, function, &call, response);
+        self.busy.stop();
+
+        let what = match call.kind {
+            ScriptKind::Eval => "script",
+            ScriptKind::Function => "function",
+        };
+
+        if let Err(e) = result {
+            let msg = format!(
+                "ERR Error running {} (call to {}): {}",
+                what,
+                call.name,
+                error_message(&e)
+            );
+            response.add_error(&sanitize_error(&msg));
+        } else if response.is_empty() {
+            // Only an error raised with something other than a string or an
+            // error reply gets here
+            let msg = format!(
+                "ERR Error running {} (call to {}): unknown error",
+                what, call.name
+            );
+            response.add_error(&msg);
+        }
+    }
+
+    fn call(
+        &self,
+        db: &mut Database,
+        function: &RegistryKey,
+        call: &ScriptCall<'_>,
+        response: &mut Response,
+    ) -> mlua::Result<()> {
+        let lua = &self.lua;
+        let db = RefCell::new(db);
+        let busy = &self.busy;
+        let read_only = call.read_only;
+
+        lua.scope(|scope| {
+            let keys = create_array(lua, call.keys)?;
+            let args = create_array(lua, call.args)?;
+
+            let dispatch = scope.create_function(|lua, args: Variadic<Value>| {
+                let mut db = db.borrow_mut();
+                match script_argv(lua, args)? {
+                    Ok(argv) => {
+                        let reply = execute_command(&mut db, busy, argv, read_only);
+                        reply_to_lua(lua, &mut reply.as_bytes())
+                    }
+                    Err(msg) => error_table(lua, msg),
+                }
+            })?;
+
+            let internal: Table = lua.registry_value(&self.internal)?;
+            internal.raw_set("dispatch", dispatch)?;
+
+            let run: Function = internal.raw_get("run")?;
+            let function: Function = lua.registry_value(function)?;
+            let (ok, value): (bool, Value) = match call.kind {
+                ScriptKind::Eval => {
+                    let globals = lua.globals();
+                    globals.raw_set("KEYS", keys)?;
+                    globals.raw_set("ARGV", args)?;
+                    run.call(function)?
+                }
+                ScriptKind::Function => run.call((function, keys, args))?,
+            };
+            internal.raw_set("dispatch", Value::Nil)?;
+
+            if ok {
+                lua_to_reply(value, response)
+            } else {
+                match value {
+                    Value::Table(table) if table.raw_get::<_, Value>("err")?.is_string() => {
+                        lua_to_reply(Value::Table(table), response)
+                    }
+                    Value::String(msg) => Err(mlua::Error::RuntimeError(msg.to_str()?.to_owned())),
+                    Value::Error(e) => Err(e),
+                    _ => Ok(()),
+                }
+            }
+        })
+    }
+}
+
+/// The scripts run by EVAL and EVALSHA
+pub struct Scripting {
+    engine: LuaEngine,
+    /// The compiled scripts by their SHA1 digest
+    scripts: HashMap<String, RegistryKey>,
+}
+
+impl Scripting {
+    pub fn new(busy: Arc<ScriptBusy>) -> Self {
+        Self {
+            engine: LuaEngine::new(busy),
+            scripts: HashMap::new(),
+        }
+    }
+
+    /// Compiles the script, unless it is already known, and returns its SHA1
+    /// digest
+    pub fn load(&mut self, body: &ByteString) -> Result<String, String> {
+        let sha = sha1hex(body);
+        if self.scripts.contains_key(&sha) {
+            return Ok(sha);
+        }
+
+        let lua = self.engine.lua();
+        let function = lua
+            .load(body.as_ref() as &[u8])
+            .set_name("@user_script")
+            .set_mode(ChunkMode::Text)
+            .into_function()
+            .and_then(|function| lua.create_registry_value(function))
+            .map_err(|e| {
+                format!(
+                    "ERR Error compiling script (new function): {}",
+                    error_message(&e)
+                )
+            })?;
+
+        self.scripts.insert(sha.clone(), function);
+        Ok(sha)
+    }
+
+    pub fn exists(&self, sha: &str) -> bool {
+        self.scripts.contains_key(&sha.to_ascii_lowercase())
+    }
+
+    /// Forgets every script, starting again with a fresh interpreter
+    pub fn flush(&mut self) {
+        *self = Self::new(Arc::clone(self.engine.busy()));
+    }
+
+    /// Runs a script loaded earlier, adding whatever it returns to the
+    /// response. Returns false when there is no script with this digest.
+    pub fn run(
+        &self,
+        db: &mut Database,
+        sha: &str,
+        keys: &[ByteString],
+        args: &[ByteString],
+        response: &mut Response,
+    ) -> bool {
+        let sha = sha.to_ascii_lowercase();
+        let function = match self.scripts.get(&sha) {
+            Some(function) => function,
+            None => return false,
+        };
+
+        let call = ScriptCall {
+            kind: ScriptKind::Eval,
+            name: &format!("f_{}", sha),
+            keys,
+            args,
+            read_only: false,
+        };
+        self.engine.run(db, function, call, response);
+
+        true
+    }
+}
+
+pub fn sha1hex(bytes: &[u8]) -> String {
+    sha1_smol::Sha1::from(bytes).digest().to_string()
+}
+
+fn init_lua(lua: &Lua, busy: Arc<ScriptBusy>) -> mlua::Result<Table<'_>> {
+    let internal: Table = lua.load(PRELUDE).set_name("@prelude").call(())?;
+    let redis: Table = lua.globals().raw_get("redis")?;
+
+    redis.raw_set(
+        "sha1hex",
+        lua.create_function(|lua, value: Value| match lua.coerce_string(value)? {
+            Some(s) => Ok(sha1hex(s.as_bytes())),
+            None => Err(mlua::Error::RuntimeError(
+                "wrong number of arguments".to_owned(),
+            )),
+        })?,
+    )?;
+    redis.raw_set("log", lua.create_function(redis_log)?)?;
+
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(KILL_CHECK_INTERVAL),
+        move |_, _| {
+            if busy.kill_requested.load(Ordering::SeqCst) {
+                Err(mlua::Error::RuntimeError(
+                    "Script killed by user with SCRIPT KILL...".to_owned(),
+                ))
+            } else {
+                Ok(())
+            }
+        },
+    );
+
+    Ok(internal)
+}
+
+fn redis_log(lua: &Lua, args: Variadic<Value>) -> mlua::Result<()> {
+    if args.len() < 2 {
+        return Err(mlua::Error::RuntimeError(
+            "redis.log() requires two arguments or more.".to_owned(),
+        ));
+    }
+
+    let level = match args[0] {
+        Value::Integer(level) => level,
+        Value::Number(level) => level as i64,
+        _ => {
+            return Err(mlua::Error::RuntimeError(
+                "First argument must be a number (log level).".to_owned(),
+            ))
+        }
+    };
+
+    let mut parts = Vec::with_capacity(args.len() - 1);
+    for arg in args.iter().skip(1) {
+        if let Some(s) = lua.coerce_string(arg.clone())? {
+            parts.push(s.to_string_lossy().into_owned());
+        }
+    }
+    let msg = parts.join(" ");
+
+    match level {
+        0 => debug!("{}", msg),
+        1 | 2 => info!("{}", msg),
+        3 => warn!("{}", msg),
+        _ => return Err(mlua::Error::RuntimeError("Invalid debug level.".to_owned())),
+    }
+
+    Ok(())
+}
+
+fn create_array<'lua>(lua: &'lua Lua, items: &[ByteString]) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table_with_capacity(items.len(), 0)?;
+    for (i, item) in items.iter().enumerate() {
+        table.raw_set(i + 1, lua.create_string(item)?)?;
+    }
+
+    Ok(table)
+}
+
+/// The arguments of `redis.call()`, or the error to reply with when they
+/// aren't strings or numbers
+fn script_argv(
+    lua: &Lua,
+    args: Variadic<Value>,
+) -> mlua::Result<Result<Vec<ByteString>, &'static str>> {
+    if args.is_empty() {
+        return Ok(Err(
+            "ERR Please specify at least one argument for this redis lib call",
+        ));
+    }
+
+    let mut argv = Vec::with_capacity(args.len());
+    for arg in args.into_iter() {
+        match arg {
+            Value::String(_) | Value::Integer(_) | Value::Number(_) => {
+                let arg = lua.coerce_string(arg)?.expect("numbers coerce to strings");
+                argv.push(ByteString::from(arg.as_bytes()));
+            }
+            _ => {
+                return Ok(Err(
+                    "ERR Lua redis lib command arguments must be strings or integers",
+                ))
+            }
+        }
+    }
+
+    Ok(Ok(argv))
+}
+
+/// Executes a command on behalf of a script, in the same way as for a client
+fn execute_command(
+    db: &mut Database,
+    busy: &ScriptBusy,
+    argv: Vec<ByteString>,
+    read_only: bool,
+) -> Response {
+    let mut response = Response::new();
+    let request = match Request::try_from(argv) {
+        Ok(request) => request,
+        Err(_) => {
+            response.add_error("ERR Please specify at least one argument for this redis lib call");
+            return response;
+        }
+    };
+
+    let registry = db.registry();
+    let cmd = match registry.lookup(request.command()) {
+        Some(cmd) => cmd,
+        None => {
+            response.add_error("ERR Unknown Redis command called from script");
+            return response;
+        }
+    };
+
+    if cmd.has_flag("noscript") {
+        response.add_error("ERR This Redis command is not allowed from script");
+        return response;
+    }
+
+    // As in a cluster, a script only reaches the keys it declared, which
+    // are those its shard was lent
+    let argv = request.argv();
+    let is_local = match cmd.wants_all_keys(argv) {
+        true => db.holds_all_keys(),
+        false => cmd
+            .key_positions(argv)
+            .into_iter()
+            .all(|i| db.is_local(&argv[i])),
+    };
+    if !is_local {
+        response
+            .add_error("ERR Script attempted to access a non local key in a cluster node script");
+        return response;
+    }
+
+    if cmd.has_flag("denyoom") && !db.perform_evictions() {
+        response.add_error("OOM command not allowed when used memory > 'maxmemory'.");
+        return response;
+    }
+
+    if cmd.has_flag("write") {
+        if read_only {
+            response.add_error("ERR Write commands are not allowed from read-only scripts.");
+            return response;
+        }
+        busy.wrote.store(true, Ordering::SeqCst);
+    }
+
+    db.feed_monitors(request.argv());
+
+    let start = Instant::now();
+    let result = cmd.execute(db, &request, &mut response);
+    db.stats_mut().record_command(cmd.name, start.elapsed());
+
+    if let Err(e) = result {
+        error!(
+            "ERROR handling command `{}` from script with args {}: '{}'",
+            request.command(),
+            request.argv_to_string(),
+            e
+        );
+        response = Response::new();
+        response.add_error("ERR server error");
+    }
+
+    response
+}
+
+fn error_table<'lua>(lua: &'lua Lua, msg: &str) -> mlua::Result<Value<'lua>> {
+    let table = lua.create_table()?;
+    table.raw_set("err", msg)?;
+
+    Ok(Value::Table(table))
+}
+
+/// Converts a RESP reply into Lua values the same way as Redis: integers to
+/// numbers, bulk strings to strings, nulls to false, arrays to tables, and
+/// status and error replies to tables with a single `ok` or `err` field.
+fn reply_to_lua<'lua>(lua: &'lua Lua, reply: &mut &[u8]) -> mlua::Result<Value<'lua>> {
+    if reply.is_empty() {
+        return Ok(Value::Nil);
+    }
+
+    let kind = reply[0];
+    let line = read_line(reply)?;
+
+    match kind {
+        b'+' | b'-' => {
+            let table = lua.create_table()?;
+            let field = if kind == b'+' { "ok" } else { "err" };
+            table.raw_set(field, lua.create_string(line)?)?;
+            Ok(Value::Table(table))
+        }
+        b':' => Ok(Value::Number(parse_number(line)? as f64)),
+        b'$' => {
+            let len = parse_number(line)?;
+            if len < 0 {
+                return Ok(Value::Boolean(false));
+            }
+
+            let len = len as usize;
+            if reply.len() < len + 2 {
+                return Err(malformed_reply());
+            }
+            let string = lua.create_string(&reply[..len])?;
+            *reply = &reply[len + 2..];
+            Ok(Value::String(string))
+        }
+        b'*' => {
+            let len = parse_number(line)?;
+            if len < 0 {
+                return Ok(Value::Boolean(false));
+            }
+
+            let table = lua.create_table_with_capacity(len as usize, 0)?;
+            for i in 1..=len {
+                table.raw_set(i, reply_to_lua(lua, reply)?)?;
+            }
+            Ok(Value::Table(table))
+        }
+        _ => Err(malformed_reply()),
+    }
+}
+
+/// Removes the first line of the reply, returning it without its type byte
+fn read_line<'a>(reply: &mut &'a [u8]) -> mlua::Result<&'a [u8]> {
+    let end = reply
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .ok_or_else(malformed_reply)?;
+    let line = &reply[1..end];
+    *reply = &reply[end + 2..];
+
+    Ok(line)
+}
+
+fn parse_number(line: &[u8]) -> mlua::Result<i64> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(malformed_reply)
+}
+
+fn malformed_reply() -> mlua::Error {
+    mlua::Error::RuntimeError("malformed reply from command".to_owned())
+}
+
+/// Converts the value returned by a script into a reply: numbers are
+/// truncated to integers, true becomes 1, false and nil become a null reply,
+/// tables with an `err` or `ok` field become error and status replies, and
+/// other tables become arrays of their elements up to the first nil.
+fn lua_to_reply(value: Value, response: &mut Response) -> mlua::Result<()> {
+    match value {
+        Value::Boolean(true) => response.add_integer(1),
+        Value::Integer(n) => response.add_integer(n),
+        Value::Number(n) => response.add_integer(n as i64),
+        Value::String(s) => response.add_bulk_string(s.as_bytes()),
+        Value::Table(table) => {
+            if let Value::String(err) = table.raw_get("err")? {
+                response.add_error(&sanitize_error(&err.to_string_lossy()));
+                return Ok(());
+            }
+            if let Value::String(ok) = table.raw_get("ok")? {
+                response.add_simple_string(&sanitize_error(&ok.to_string_lossy()));
+                return Ok(());
+            }
+
+            let mut items = vec![];
+            for i in 1.. {
+                match table.raw_get(i)? {
+                    Value::Nil => break,
+                    item => items.push(item),
+                }
+            }
+
+            response.add_array_len(items.len() as i64);
+            for item in items {
+                lua_to_reply(item, response)?;
+            }
+        }
+        _ => response.add_null_string(),
+    }
+
+    Ok(())
+}
+
+/// Status and error replies end at the first newline, so any in the message
+/// are replaced with spaces
+pub fn sanitize_error(msg: &str) -> String {
+    msg.replace(['\r', '\n'], " ")
+}
+
+pub fn error_message(e: &mlua::Error) -> String {
+    match e {
+        mlua::Error::RuntimeError(msg) | mlua::Error::SyntaxError { message: msg, .. } => {
+            msg.clone()
+        }
+        mlua::Error::CallbackError { cause, .. } => error_message(cause),
+        e => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(db: &mut Database, script: &str, keys: &[&str], args: &[&str]) -> String {
+        let scripting = db.scripting();
+        let mut scripting = scripting.lock().unwrap();
+        let mut response = Response::new();
+
+        match scripting.load(&script.into()) {
+            Ok(sha) => {
+                let keys: Vec<ByteString> = keys.iter().map(|&k| k.into()).collect();
+                let args: Vec<ByteString> = args.iter().map(|&a| a.into()).collect();
+                assert!(scripting.run(db, &sha, &keys, &args, &mut response));
+            }
+            Err(msg) => response.add_error(&msg),
+        }
+
+        response.as_string()
+    }
+
+    #[test]
+    fn test_sha1hex() {
+        assert_eq!(sha1hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            sha1hex(b"return 1"),
+            "e0e1f9fabfc9d4800c877a703b823ac0578ff8db"
+        );
+    }
+
+    #[test]
+    fn test_return_values() {
+        let mut db = Database::new();
+
+        assert_eq!(eval(&mut db, "return 1", &[], &[]), ":1\r\n");
+        assert_eq!(eval(&mut db, "return 3.99", &[], &[]), ":3\r\n");
+        assert_eq!(eval(&mut db, "return true", &[], &[]), ":1\r\n");
+        assert_eq!(eval(&mut db, "return false", &[], &[]), "$-1\r\n");
+        assert_eq!(eval(&mut db, "return nil", &[], &[]), "$-1\r\n");
+        assert_eq!(eval(&mut db, "return 'a'", &[], &[]), "$1\r\na\r\n");
+        assert_eq!(
+            eval(&mut db, "return {1, 'b', {2}, nil, 3}", &[], &[]),
+            "*3\r\n:1\r\n$1\r\nb\r\n*1\r\n:2\r\n"
+        );
+        assert_eq!(
+            eval(&mut db, "return redis.status_reply('FINE')", &[], &[]),
+            "+FINE\r\n"
+        );
+        assert_eq!(
+            eval(&mut db, "return redis.error_reply('ERR bad')", &[], &[]),
+            "-ERR bad\r\n"
+        );
+        assert_eq!(
+            eval(&mut db, "return {KEYS[1], ARGV[1]}", &["k"], &["v"]),
+            "*2\r\n$1\r\nk\r\n$1\r\nv\r\n"
+        );
+    }
+
+    #[test]
+    fn test_redis_call() {
+        let mut db = Database::new();
+
+        assert_eq!(
+            eval(
+                &mut db,
+                "return redis.call('set', KEYS[1], ARGV[1])",
+                &["x"],
+                &["1"]
+            ),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            eval(&mut db, "return redis.call('incrby', 'x', 41)", &[], &[]),
+            ":42\r\n"
+        );
+        assert_eq!(
+            eval(
+                &mut db,
+                "return redis.call('get', 'missing') == false",
+                &[],
+                &[]
+            ),
+            ":1\r\n"
+        );
+        assert_eq!(
+            eval(&mut db, "return redis.call('rpush', 'x', 'a')", &[], &[]),
+            "-WRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(
+            eval(
+                &mut db,
+                "return redis.pcall('rpush', 'x', 'a')['err']",
+                &[],
+                &[]
+            ),
+            "$65\r\nWRONGTYPE Operation against a key holding the wrong kind of value\r\n"
+        );
+        assert_eq!(
+            eval(&mut db, "return redis.call('nosuchcommand')", &[], &[]),
+            "-ERR Unknown Redis command called from script\r\n"
+        );
+        assert_eq!(
+            eval(
+                &mut db,
+                "return redis.call('eval', 'return 1', 0)",
+                &[],
+                &[]
+            ),
+            "-ERR This Redis command is not allowed from script\r\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut db = Database::new();
+
+        assert!(eval(&mut db, "return +", &[], &[])
+            .starts_with("-ERR Error compiling script (new function): user_script:1:"));
+        assert!(eval(&mut db, "return nosuchvar", &[], &[])
+            .contains("Script attempted to access nonexistent global variable 'nosuchvar'"));
+        assert!(eval(&mut db, "newvar = 1", &[], &[])
+            .contains("Script attempted to create global variable 'newvar'"));
+        assert!(eval(&mut db, "error('boom')", &[], &[])
+            .starts_with("-ERR Error running script (call to f_"));
+    }
+
+    #[test]
+    fn test_non_local_keys() {
+        let config = Arc::new(std::sync::RwLock::new(crate::config::Config::default()));
+        let registry = Arc::new(crate::commands::Registry::new());
+        let mut shards = Database::sharded(config, registry, 2);
+        let db = &mut shards[0];
+        let key = |home: bool| {
+            (0..)
+                .map(|n| format!("key:{}", n))
+                .find(|key| db.is_home(key.as_bytes()) == home)
+                .unwrap()
+        };
+        let (home, away) = (key(true), key(false));
+
+        assert_eq!(
+            eval(db, "return redis.call('set', KEYS[1], 'a')", &[&home], &[]),
+            "+OK\r\n"
+        );
+        assert_eq!(
+            eval(db, "return redis.call('get', ARGV[1])", &[], &[&away]),
+            "-ERR Script attempted to access a non local key in a cluster node script\r\n"
+        );
+        assert_eq!(
+            eval(db, "return redis.pcall('keys', '*')", &[], &[]),
+            "-ERR Script attempted to access a non local key in a cluster node script\r\n"
+        );
+
+        db.add_borrowed(Some(&[away.as_str().into()]));
+        assert_eq!(
+            eval(db, "return redis.call('get', KEYS[1])", &[&away], &[]),
+            "$-1\r\n"
+        );
+        db.clear_borrowed();
+    }
+
+    #[test]
+    fn test_kill() {
+        let busy = Arc::new(ScriptBusy::default());
+
+        assert!(!busy.is_running());
+        busy.start();
+        assert!(busy.is_running());
+        assert!(busy.kill());
+
+        busy.start();
+        busy.wrote.store(true, Ordering::SeqCst);
+        assert!(!busy.kill());
+        busy.stop();
+        assert!(!busy.is_running());
+    }
+}
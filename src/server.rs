@@ -1,76 +1,1134 @@
 use crate::{
-    commands::{self, RedisCommand},
-    db::Database,
+    blocking::BlockRequest,
+    clients::{Client, ClientAddr, PauseMode, ReplyMode},
+    commands::{RedisCommand, Registry},
+    config::Config,
+    db::{self, Database, Entry},
     errors::{Error, Result},
-    protocol::ProtoError,
+    listener::{self, Listener, TlsListener},
+    local::{self, LocalClient},
+    output_buffer::OutputBuffer,
+    protocol::{self, ProtoError},
+    rdb,
     request::{self, Request},
-    response::Response,
+    response::{self, Reply, ReplySink, Response},
+    scripting::ScriptBusy,
+    shutdown::{Shutdown, ShutdownFlags},
+    tls,
 };
-use byte_string::ByteStr;
-use log::{debug, error, info};
+use byte_string::{ByteStr, ByteString};
+use futures::future;
+use log::{debug, error, info, warn};
 use std::{
-    fmt::Debug,
+    collections::VecDeque,
+    convert::{TryFrom, TryInto},
+    fs,
+    future::Future,
+    net::SocketAddr,
+    os::unix::fs::PermissionsExt,
     panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use tokio::{
-    io::{AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
-    runtime::Runtime,
-    sync::mpsc::{self, Sender},
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{self, TcpListener, UnixListener},
+    runtime::{self, Runtime},
+    signal::unix::{signal, SignalKind},
+    sync::{
+        mpsc::{self, Sender},
+        oneshot, watch,
+    },
+    task::{JoinError, JoinHandle},
+    time,
 };
 
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+/// How many pushed messages, such as MONITOR output, may be waiting to be
+/// written to a client before further messages are dropped
+const PUSH_BUFFER_LEN: usize = 1024;
+/// How long to wait before accepting again after an accept fails
+const ACCEPT_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug)]
-struct Message {
+enum Message {
+    ClientConnected(Box<Client>),
+    ClientDisconnected {
+        client_id: u64,
+    },
+    /// A connection was refused, see `maxclients` and `protected-mode`
+    ClientRejected,
+    /// The server is shutting down, so commands still waiting are dropped
+    /// along with their connections
+    Shutdown,
+    /// A command to execute, with the channel for its reply. The channel is
+    /// `None` on the shards running a command only to keep their state in
+    /// step with the one replying, see [`shared_state_update`].
+    Command {
+        client_id: u64,
+        qbuf: usize,
+        request: Queued,
+        response_sender: Option<mpsc::Sender<Reply>>,
+    },
+    /// Asks a shard for its keys, or all of them, for a command the first
+    /// shard is running across shards. The shard runs nothing else until
+    /// they are returned, so the command is atomic.
+    Lend {
+        keys: Option<Vec<ByteString>>,
+        lent: oneshot::Sender<Vec<Entry>>,
+        returned: oneshot::Receiver<Vec<Entry>>,
+    },
+    /// Asks the first shard to save the RDB file, borrowing every key of
+    /// the others, once the server is shutting down and the connections have
+    /// closed
+    Save {
+        saved: oneshot::Sender<io::Result<()>>,
+    },
+}
+
+/// A request waiting in a shard's queue. Its connection may take it back to
+/// answer it itself, as when a script keeps the shard busy, so whichever of
+/// the shard and the connection takes it first handles it.
+#[derive(Clone, Debug)]
+struct Queued(Arc<Mutex<Option<Request>>>);
+
+impl Queued {
+    fn new(request: Request) -> Self {
+        Self(Arc::new(Mutex::new(Some(request))))
+    }
+
+    fn take(&self) -> Option<Request> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).take()
+    }
+
+    fn is_taken(&self) -> bool {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).is_none()
+    }
+}
+
+/// The API of each shard of the keyspace. Commands go to the shard holding
+/// all their keys, or to the first shard when they have none or their keys
+/// are spread across shards. Each shard keeps its own clients, statistics,
+/// scripts and so on, so the commands changing those run on all of them.
+#[derive(Clone)]
+struct Shards {
+    senders: Arc<[Sender<Message>]>,
+    registry: Arc<Registry>,
+}
+
+impl Shards {
+    fn primary(&self) -> &Sender<Message> {
+        &self.senders[0]
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Sender<Message>> {
+        self.senders.iter()
+    }
+
+    /// The index of the shard to send the request to
+    fn route(&self, request: &Request) -> usize {
+        let count = self.senders.len();
+        let cmd = match self.registry.lookup(request.command()) {
+            Some(cmd) if count > 1 => cmd,
+            _ => return 0,
+        };
+
+        let argv = request.argv();
+        let mut shards = cmd
+            .key_positions(argv)
+            .into_iter()
+            .map(|i| db::shard_of(&argv[i], count));
+
+        match shards.next() {
+            Some(shard) if shards.all(|other| other == shard) => shard,
+            _ => 0,
+        }
+    }
+}
+
+/// What the shards other than the one a request is routed to run along with
+/// it, to keep their copy of the state every shard has in step: the clients'
+/// names, pauses and subscriptions, the scripts and libraries loaded and so
+/// on. Their replies are dropped.
+fn shared_state_update(request: &Request) -> Option<Request> {
+    let subcommand = request.arguments().first().map(|arg| arg.to_lowercase());
+    let is_one_of = |names: &[&[u8]]| {
+        subcommand
+            .as_ref()
+            .is_some_and(|sub| names.contains(&sub.as_ref()))
+    };
+
+    let updates = match request.command().to_lowercase().as_ref() {
+        b"monitor" | b"subscribe" | b"unsubscribe" | b"psubscribe" | b"punsubscribe" => true,
+        b"client" => is_one_of(&[b"setname", b"pause", b"unpause", b"reply", b"no-evict"]),
+        b"config" => is_one_of(&[b"resetstat"]),
+        b"slowlog" => is_one_of(&[b"reset"]),
+        b"script" => is_one_of(&[b"load", b"flush"]),
+        b"function" => is_one_of(&[b"load", b"delete", b"flush", b"restore"]),
+        // Every shard caches the script, so that EVALSHA finds it wherever
+        // it is routed
+        b"eval" => {
+            let script = request.arguments().first()?.clone();
+            return Request::try_from(vec!["script".into(), "load".into(), script]).ok();
+        }
+        _ => false,
+    };
+
+    updates.then(|| request.clone())
+}
+
+/// Keys the first shard borrowed from another to run a command across
+/// shards, all of its keys when `keys` is `None`
+struct Borrowed {
+    shard: usize,
+    keys: Option<Vec<ByteString>>,
+    returned: oneshot::Sender<Vec<Entry>>,
+}
+
+/// A command that arrived while clients were paused, to be executed once the
+/// pause is lifted
+struct PostponedCommand {
+    client_id: u64,
+    qbuf: usize,
     request: Request,
-    response_sender: Sender<Response>,
+    response_sender: Option<mpsc::Sender<Reply>>,
 }
 
-pub fn serve(address: impl ToSocketAddrs + Debug) -> Result<()> {
-    let db = Database::new();
+/// A command waiting for one of its keys to be ready, see [`BlockRequest`]
+struct BlockedCommand {
+    command: PostponedCommand,
+    keys: Vec<ByteString>,
+    blocked_at: Instant,
+    deadline: Option<Instant>,
+    timeout_reply: Response,
+}
 
+/// A command which couldn't reply straight away
+enum Deferred {
+    Postponed(PostponedCommand),
+    Blocked(BlockedCommand),
+}
+
+/// The commands the API is holding on to
+#[derive(Default)]
+struct Waiting {
+    postponed: VecDeque<PostponedCommand>,
+    blocked: Vec<BlockedCommand>,
+}
+
+impl Waiting {
+    fn push(&mut self, deferred: Deferred) {
+        match deferred {
+            Deferred::Postponed(command) => self.postponed.push_back(command),
+            Deferred::Blocked(command) => self.blocked.push(command),
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.blocked.iter().filter_map(|b| b.deadline).min()
+    }
+}
+
+/// Runs the server until it is shut down, on a runtime of its own. The
+/// server also shuts down on SIGTERM or SIGINT.
+pub fn serve(config: Config) -> Result<()> {
     let rt = Runtime::new().unwrap();
     rt.block_on(async move {
-        let api = start_api(db);
-
-        start_network(api, address).await
+        let server = ServerBuilder::new(config)
+            .handle_signals(true)
+            .start()
+            .await?;
+        server.stopped().await
     })
 }
 
-fn start_api(mut db: Database) -> Sender<Message> {
-    let (sender, mut receiver) = mpsc::channel::<Message>(512);
+/// Sets up a server to be run alongside other code, such as in a test
+/// harness, rather than by [`serve`]
+pub struct ServerBuilder {
+    config: Config,
+    listener: Option<std::net::TcpListener>,
+    ephemeral_port: bool,
+    runtime: Option<runtime::Handle>,
+    handle_signals: bool,
+    registry: Registry,
+}
 
-    tokio::spawn(async move {
-        while let Some(message) = receiver.recv().await {
-            let request = message.request;
-            let mut response = Response::new();
+impl ServerBuilder {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            listener: None,
+            ephemeral_port: false,
+            runtime: None,
+            handle_signals: false,
+            registry: Registry::new(),
+        }
+    }
 
-            if let Some(cmd) = commands::lookup(request.command()) {
-                api_handle_command(cmd, &mut db, &request, &mut response);
-            } else {
-                let msg = format!(
-                    "ERR unknown command `{}`, with args beginning with: {}",
-                    request.command(),
-                    request.argv_to_string()
-                );
-                response.add_error(&msg);
+    /// Accepts connections on a listener that is already bound, instead of
+    /// binding `bind` and `port`
+    pub fn listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.listener = Some(listener);
+        self
+    }
+
+    /// Binds `bind` to a port chosen by the system rather than `port`, see
+    /// [`ServerHandle::local_addr`]
+    pub fn ephemeral_port(mut self) -> Self {
+        self.ephemeral_port = true;
+        self
+    }
+
+    /// The runtime the connections are served on, the one the server is
+    /// started from by default. It must have both IO and time enabled.
+    pub fn runtime(mut self, runtime: runtime::Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// The commands and data types to add to those built in
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Whether to shut down on SIGTERM or SIGINT, and exit the process on a
+    /// second signal, as `serve` does. Off by default.
+    pub fn handle_signals(mut self, handle_signals: bool) -> Self {
+        self.handle_signals = handle_signals;
+        self
+    }
+
+    /// Binds the listeners and starts serving on them, returning once the
+    /// server is ready for connections
+    pub async fn start(self) -> Result<ServerHandle> {
+        let runtime = self.runtime.unwrap_or_else(runtime::Handle::current);
+        let shard_count = self.config.shards;
+        let config = Arc::new(RwLock::new(self.config));
+        let registry = Arc::new(self.registry);
+        let mut dbs = Database::sharded(Arc::clone(&config), Arc::clone(&registry), shard_count);
+        load_rdb(&mut dbs)?;
+        let script_busy = dbs.iter().map(Database::script_busy).collect();
+        let shutdown = dbs[0].shutdown();
+
+        if self.handle_signals {
+            let _entered = runtime.enter();
+            runtime.spawn(handle_signals(Arc::clone(&shutdown))?);
+        }
+
+        let others: Vec<_> = dbs.drain(1..).map(|db| start_api(db, vec![])).collect();
+        let primary = start_api(dbs.remove(0), others.clone());
+        let shards = Shards {
+            senders: std::iter::once(primary).chain(others).collect(),
+            registry,
+        };
+
+        let (close, closing) = watch::channel(false);
+        let (open, all_closed) = mpsc::channel(1);
+        let context = Context {
+            shards,
+            config,
+            script_busy,
+            closing: Closing {
+                shutdown: Arc::clone(&shutdown),
+                signal: closing,
+                _open: open,
+            },
+            connections: ConnectionCount::default(),
+        };
+        let context = Arc::new(Mutex::new(Some(context)));
+        let bind = Bind {
+            listener: self.listener,
+            ephemeral_port: self.ephemeral_port,
+        };
+
+        // The listeners are bound by the server's task so that they are
+        // driven by its runtime
+        let (started, bound) = oneshot::channel();
+        let stopped = runtime.spawn(run(Arc::clone(&context), bind, close, all_closed, started));
+        let local_addrs = match bound.await {
+            Ok(bound) => bound?,
+            Err(_) => return Err(server_gone(stopped.await)),
+        };
+
+        Ok(ServerHandle {
+            local_addrs,
+            context,
+            shutdown,
+            stopped,
+            runtime,
+        })
+    }
+}
+
+/// A running server, as started by [`ServerBuilder`]. The server keeps
+/// running if this is dropped.
+pub struct ServerHandle {
+    local_addrs: Vec<SocketAddr>,
+    context: Arc<Mutex<Option<Context>>>,
+    shutdown: Arc<Shutdown>,
+    stopped: JoinHandle<Result<()>>,
+    runtime: runtime::Handle,
+}
+
+impl ServerHandle {
+    /// The address of the first TCP listener, or `None` when only listening
+    /// on a Unix socket
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs.first().copied()
+    }
+
+    /// The addresses of the TCP listeners, followed by those of TLS
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Connects a client in-process, without a socket. It is served and
+    /// admitted like any other client.
+    pub fn client(&self) -> Result<LocalClient> {
+        let context = self
+            .context
+            .lock()
+            .expect("server context lock poisoned")
+            .clone()
+            .ok_or("The server is shutting down")?;
+        let (client, server) = io::duplex(local::STREAM_BUFFER);
+        self.runtime.spawn(serve_connection(
+            server,
+            ClientAddr::Local,
+            ClientAddr::Local,
+            context,
+        ));
+
+        Ok(LocalClient::new(client))
+    }
+
+    /// Shuts the server down as SHUTDOWN NOSAVE would, waiting until it has
+    pub async fn shutdown(self) -> Result<()> {
+        self.shutdown.request(ShutdownFlags {
+            save: Some(false),
+            ..ShutdownFlags::default()
+        });
+        self.stopped().await
+    }
+
+    /// Waits for the server to be shut down, such as by SHUTDOWN
+    pub async fn stopped(self) -> Result<()> {
+        match self.stopped.await {
+            Ok(stopped) => stopped,
+            Err(err) => Err(server_gone(Err(err))),
+        }
+    }
+}
+
+fn server_gone(stopped: std::result::Result<Result<()>, JoinError>) -> Error {
+    match stopped {
+        Ok(Err(err)) => err,
+        Ok(Ok(())) => "The server stopped before it started".into(),
+        Err(err) => format!("The server task failed: {}", err).into(),
+    }
+}
+
+/// Where to accept TCP connections, if not as configured
+struct Bind {
+    listener: Option<std::net::TcpListener>,
+    ephemeral_port: bool,
+}
+
+/// Serves connections until the server is shut down. The addresses of the
+/// TCP listeners are sent to `started` once they have been bound.
+async fn run(
+    context: Arc<Mutex<Option<Context>>>,
+    bind: Bind,
+    close: watch::Sender<bool>,
+    mut all_closed: mpsc::Receiver<()>,
+    started: oneshot::Sender<Result<Vec<SocketAddr>>>,
+) -> Result<()> {
+    let current = context
+        .lock()
+        .expect("server context lock poisoned")
+        .clone()
+        .expect("server context taken before starting");
+    let Context {
+        shards,
+        config,
+        script_busy,
+        closing,
+        ..
+    } = current.clone();
+    let shutdown = Arc::clone(&closing.shutdown);
+    drop(closing);
+
+    let accept_loops = match start_network(current, bind).await {
+        Ok((accept_loops, local_addrs)) => {
+            let _ = started.send(Ok(local_addrs));
+            accept_loops
+        }
+        Err(err) => {
+            let _ = started.send(Err(err));
+            return Ok(());
+        }
+    };
+
+    // Dropping the accept loops closes the listeners
+    let flags = tokio::select! {
+        // The loops only end if a listener does, which leaves the server
+        // deaf to new clients
+        _ = future::join_all(accept_loops) => {
+            error!("Stopped accepting connections");
+            return Err("The server stopped accepting connections".into());
+        }
+        flags = shutdown.requested() => flags,
+    };
+
+    // Each connection finishes the command it is in the middle of and
+    // commands waiting on keys or paused clients are dropped
+    let _ = close.send(true);
+    let context = context.lock().expect("server context lock poisoned").take();
+    if let Some(context) = context {
+        for shard in context.shards.all() {
+            let _ = shard.send(Message::Shutdown).await;
+        }
+    }
+
+    let script_running = loop {
+        tokio::select! {
+            _ = all_closed.recv() => break false,
+            _ = time::sleep(CRON_INTERVAL) => {
+                // Like Redis, a busy script is abandoned rather than
+                // waited for
+                if is_script_busy(&script_busy, &config) {
+                    warn!("Exiting with a script still running");
+                    break true;
+                }
             }
+        }
+    };
+
+    let save = flags
+        .save
+        .unwrap_or_else(|| config.read().expect("config lock poisoned").persists());
+    if save && script_running {
+        warn!("Not saving the DB, as the first shard is busy with the script");
+    } else if save {
+        warn!("Saving the final RDB snapshot before exiting.");
+        let (saved, result) = oneshot::channel();
+        if shards.primary().send(Message::Save { saved }).await.is_ok() {
+            match result.await {
+                Ok(Ok(())) => info!("DB saved on disk"),
+                Ok(Err(e)) => error!("Error trying to save the DB: {}", e),
+                Err(_) => error!("Error trying to save the DB: the first shard has gone"),
+            }
+        }
+    }
+
+    let unixsocket = config
+        .read()
+        .expect("config lock poisoned")
+        .unixsocket
+        .clone();
+    if let Some(path) = unixsocket {
+        info!("Removing the unix socket file.");
+        let _ = fs::remove_file(path);
+    }
+    warn!("Redis is now ready to exit, bye bye...");
+
+    Ok(())
+}
+
+/// Loads the RDB file configured, as Redis does on startup. Every shard
+/// loads the function libraries and each key goes to the shard it belongs
+/// to.
+fn load_rdb(dbs: &mut [Database]) -> Result<()> {
+    let start = Instant::now();
+    let loaded = {
+        let config = dbs[0].config();
+        rdb::load(&config.rdb_path(), &config)
+    };
+    let snapshot = match loaded? {
+        Some(snapshot) => snapshot,
+        None => return Ok(()),
+    };
 
-            if let Err(e) = message.response_sender.send(response).await {
-                error!("Client receiver has gone: {:?}", e);
+    for db in dbs.iter() {
+        db.functions()
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .load_libraries(&snapshot.libraries)
+            .map_err(|e| format!("Failed loading the function libraries: {}", e))?;
+    }
+
+    let count = dbs.len();
+    let keys = snapshot.entries.len();
+    for entry in snapshot.entries {
+        dbs[db::shard_of(&entry.key, count)].restore(entry);
+    }
+    dbs.iter().for_each(Database::publish_size);
+
+    info!("Done loading RDB, keys loaded: {}.", keys);
+    info!(
+        "DB loaded from disk: {:.3} seconds",
+        start.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
+
+/// Shuts the server down on SIGTERM or SIGINT, or exits straight away if
+/// either is received while it already is. The handlers are installed
+/// before the returned future is first polled.
+fn handle_signals(shutdown: Arc<Shutdown>) -> io::Result<impl Future<Output = ()>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+
+    Ok(async move {
+        loop {
+            let name = tokio::select! {
+                _ = sigterm.recv() => "SIGTERM",
+                _ = sigint.recv() => "SIGINT",
+            };
+
+            if shutdown.is_requested() {
+                warn!("You insist... exiting now.");
+                std::process::exit(1);
             }
+
+            warn!("Received {} scheduling shutdown...", name);
+            shutdown.request(ShutdownFlags::default());
         }
+    })
+}
+
+/// Runs the API for a shard on a thread of its own, so that the connections
+/// keep being served while it is blocked, such as by a long running script.
+/// The first shard is given the API of the others, its `peers`, to borrow
+/// their keys.
+fn start_api(mut db: Database, peers: Vec<Sender<Message>>) -> Sender<Message> {
+    let (sender, mut receiver) = mpsc::channel::<Message>(512);
+    let rt = runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("failed to build the API runtime");
+
+    thread::spawn(move || {
+        rt.block_on(async move {
+            let mut cron = time::interval(CRON_INTERVAL);
+            let mut waiting = Waiting::default();
+            let mut shutting_down = false;
+
+            loop {
+                let next_deadline = waiting.next_deadline();
+                let timeout = time::sleep_until(next_deadline.unwrap_or_else(Instant::now).into());
+
+                let message = tokio::select! {
+                    message = receiver.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = cron.tick() => {
+                        server_cron(&mut db, &waiting);
+                        process_postponed(&mut db, &mut waiting, &peers).await;
+                        process_ready_keys(&mut db, &mut waiting, &peers).await;
+                        continue;
+                    }
+                    _ = timeout, if next_deadline.is_some() => {
+                        expire_blocked(&mut db, &mut waiting).await;
+                        continue;
+                    }
+                };
+
+                match message {
+                    Message::ClientConnected(client) => {
+                        // Every shard is given a copy, the first counts it
+                        if db.shard() == 0 {
+                            db.stats_mut().total_connections_received += 1;
+                        }
+                        db.clients().add(*client);
+                    }
+                    Message::ClientDisconnected { client_id } => {
+                        waiting.blocked.retain(|b| b.command.client_id != client_id);
+                        db.remove_client(client_id);
+                    }
+                    Message::ClientRejected => {
+                        db.stats_mut().rejected_connections += 1;
+                    }
+                    Message::Shutdown => {
+                        shutting_down = true;
+                        waiting = Waiting::default();
+                    }
+                    Message::Lend {
+                        keys,
+                        lent,
+                        returned,
+                    } => {
+                        lend_keys(&mut db, keys, lent, returned).await;
+                        process_ready_keys(&mut db, &mut waiting, &peers).await;
+                    }
+                    Message::Save { saved } => {
+                        let borrowed = borrow(&mut db, vec![None; peers.len()], &peers).await;
+                        let _ = saved.send(rdb::save(&db));
+                        return_keys(&mut db, borrowed);
+                    }
+                    Message::Command {
+                        client_id,
+                        qbuf,
+                        request,
+                        response_sender,
+                    } => {
+                        // Taken back by a connection answering for a busy script
+                        let request = match request.take() {
+                            Some(request) => request,
+                            None => continue,
+                        };
+                        let command = PostponedCommand {
+                            client_id,
+                            qbuf,
+                            request,
+                            response_sender,
+                        };
+
+                        if let Some(deferred) = process_command(&mut db, command, &peers).await {
+                            if !shutting_down {
+                                waiting.push(deferred);
+                            }
+                        }
+
+                        process_postponed(&mut db, &mut waiting, &peers).await;
+                        process_ready_keys(&mut db, &mut waiting, &peers).await;
+                    }
+                }
+            }
+        })
     });
 
     sender
 }
 
+/// Executes a command on behalf of a client and sends back the reply.
+/// Returns the command back to the caller when it must be postponed because
+/// clients are paused, or when it blocked.
+async fn process_command(
+    db: &mut Database,
+    command: PostponedCommand,
+    peers: &[Sender<Message>],
+) -> Option<Deferred> {
+    let PostponedCommand {
+        client_id,
+        qbuf,
+        request,
+        response_sender,
+    } = &command;
+
+    let registry = db.registry();
+    let cmd = registry.lookup(request.command());
+
+    let paused = match (db.clients().pause_mode(Instant::now()), cmd) {
+        (Some(PauseMode::All), _) => true,
+        (Some(PauseMode::Write), Some(cmd)) => cmd.has_flag("write"),
+        _ => false,
+    };
+    if paused {
+        return Some(Deferred::Postponed(command));
+    }
+
+    let (previous_reply_mode, output) = match db.clients().get_mut(*client_id) {
+        Some(client) => {
+            let activity = &mut client.activity;
+            activity.last_interaction = Instant::now();
+            activity.qbuf = *qbuf;
+            activity.argv_mem = request.arguments().iter().map(|arg| arg.len()).sum();
+            activity.blocked = false;
+            if let Some(cmd) = cmd {
+                activity.last_command = Some(cmd.name.into());
+            }
+            (client.reply_mode, Some((client.output(), client.class())))
+        }
+        None => (ReplyMode::On, None),
+    };
+
+    let borrowed = match cmd {
+        Some(cmd) if !peers.is_empty() => borrow_keys(db, cmd, request, peers).await,
+        _ => vec![],
+    };
+
+    // A large reply is sent as it is built, unless it may be suppressed
+    let mut response = match (output, response_sender) {
+        (Some((output, class)), Some(sender)) if previous_reply_mode == ReplyMode::On => {
+            let limits = db.config().output_limits(class);
+            let sink = ReplySink::new(sender.clone(), output, limits);
+            Response::streamed(sink)
+        }
+        _ => Response::new(),
+    };
+    let subscribed = db
+        .clients()
+        .get(*client_id)
+        .is_some_and(|c| c.subscription_count() > 0);
+
+    db.set_current_client(Some(*client_id));
+    if let Some(cmd) = cmd {
+        if subscribed && !is_allowed_when_subscribed(cmd) {
+            let msg = format!(
+                "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE are allowed in this context",
+                ByteStr::from(cmd.name)
+            );
+            response.add_error(&msg);
+        } else if response_sender.is_some() {
+            api_handle_command(cmd, db, request, &mut response);
+        } else {
+            // Only the shard replying monitors and counts the command
+            let _ = catch_unwind(AssertUnwindSafe(|| cmd.execute(db, request, &mut response)));
+        }
+    } else {
+        let msg = format!(
+            "ERR unknown command `{}`, with args beginning with: {}",
+            request.command(),
+            request.argv_to_string()
+        );
+        response.add_error(&msg);
+    }
+    db.set_current_client(None);
+    return_keys(db, borrowed);
+    db.publish_size();
+
+    if let Some(block) = db.take_block_request() {
+        return Some(Deferred::Blocked(block_command(db, command, block)));
+    }
+
+    let mut suppress_reply = false;
+    if let Some(client) = db.clients().get_mut(*client_id) {
+        match (previous_reply_mode, client.reply_mode) {
+            (_, ReplyMode::Off) => suppress_reply = true,
+            (ReplyMode::Skip, mode) => {
+                suppress_reply = true;
+                if mode == ReplyMode::Skip {
+                    client.reply_mode = ReplyMode::On;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // The connection still waits for a response to unblock, even when the
+    // reply is suppressed, so an empty one is sent instead
+    if suppress_reply {
+        response = Response::new();
+    }
+
+    if let Some(response_sender) = response_sender {
+        if response_sender.try_send(Reply::Done(response)).is_err() {
+            debug!("Client receiver has gone");
+        }
+    }
+
+    None
+}
+
+/// Borrows the keys of the command held by the other shards, waiting for
+/// each in turn. Only the first shard borrows keys, so two shards never wait
+/// on each other.
+async fn borrow_keys(
+    db: &mut Database,
+    cmd: &RedisCommand<'_>,
+    request: &Request,
+    peers: &[Sender<Message>],
+) -> Vec<Borrowed> {
+    let argv = request.argv();
+    let mut wanted: Vec<Option<Vec<ByteString>>> = vec![Some(vec![]); peers.len()];
+
+    if cmd.wants_all_keys(argv) {
+        wanted.iter_mut().for_each(|keys| *keys = None);
+    } else {
+        for i in cmd.key_positions(argv) {
+            let key = &argv[i];
+            let shard = db::shard_of(key, db.shard_count());
+            if let Some(Some(keys)) = shard.checked_sub(1).map(|i| &mut wanted[i]) {
+                if !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+    }
+
+    borrow(db, wanted, peers).await
+}
+
+/// Borrows the keys wanted of each of the other shards, all of them when
+/// `None`
+async fn borrow(
+    db: &mut Database,
+    wanted: Vec<Option<Vec<ByteString>>>,
+    peers: &[Sender<Message>],
+) -> Vec<Borrowed> {
+    let mut borrowed = vec![];
+    for (i, keys) in wanted.into_iter().enumerate() {
+        if keys.as_ref().is_some_and(|keys| keys.is_empty()) {
+            continue;
+        }
+
+        let (lent, lent_receiver) = oneshot::channel();
+        let (returned, returned_receiver) = oneshot::channel();
+        let message = Message::Lend {
+            keys: keys.clone(),
+            lent,
+            returned: returned_receiver,
+        };
+        if peers[i].send(message).await.is_err() {
+            error!("Shard {} has gone", i + 1);
+            continue;
+        }
+
+        if let Ok(entries) = lent_receiver.await {
+            entries.into_iter().for_each(|entry| db.restore(entry));
+            db.add_borrowed(keys.as_deref());
+            borrowed.push(Borrowed {
+                shard: i + 1,
+                keys,
+                returned,
+            });
+        }
+    }
+
+    borrowed
+}
+
+/// Gives the borrowed keys back to their shards, less those the command
+/// deleted and plus those it created
+fn return_keys(db: &mut Database, borrowed: Vec<Borrowed>) {
+    let count = db.shard_count();
+
+    db.clear_borrowed();
+
+    for Borrowed {
+        shard,
+        keys,
+        returned,
+    } in borrowed
+    {
+        let entries = match keys {
+            Some(keys) => keys.iter().filter_map(|key| db.take(key)).collect(),
+            None => db.take_where(|key| db::shard_of(key, count) == shard),
+        };
+
+        if returned.send(entries).is_err() {
+            error!("Shard {} has gone", shard);
+        }
+    }
+}
+
+/// Lends keys to the first shard then waits for them to be returned, waking
+/// any commands blocked on them
+async fn lend_keys(
+    db: &mut Database,
+    keys: Option<Vec<ByteString>>,
+    lent: oneshot::Sender<Vec<Entry>>,
+    returned: oneshot::Receiver<Vec<Entry>>,
+) {
+    let entries = match keys {
+        Some(keys) => keys.iter().filter_map(|key| db.take(key)).collect(),
+        None => db.take_where(|_| true),
+    };
+
+    let entries = match lent.send(entries) {
+        Ok(()) => returned.await.unwrap_or_else(|_| {
+            error!("The first shard has gone with keys of shard {}", db.shard());
+            vec![]
+        }),
+        // The command was abandoned so the keys are kept
+        Err(entries) => entries,
+    };
+
+    for entry in entries {
+        db.signal_key_ready(&entry.key);
+        db.restore(entry);
+    }
+    db.publish_size();
+}
+
+fn block_command(
+    db: &mut Database,
+    mut command: PostponedCommand,
+    block: BlockRequest,
+) -> BlockedCommand {
+    if let Some(client) = db.clients().get_mut(command.client_id) {
+        client.activity.blocked = true;
+    }
+
+    if let Some(argv) = block.argv {
+        command.request = argv
+            .try_into()
+            .expect("a blocked command must be rewritten to a valid request");
+    }
+
+    let now = Instant::now();
+    BlockedCommand {
+        command,
+        keys: block.keys,
+        blocked_at: now,
+        deadline: block.timeout.map(|timeout| now + timeout),
+        timeout_reply: block.timeout_reply,
+    }
+}
+
+async fn process_postponed(db: &mut Database, waiting: &mut Waiting, peers: &[Sender<Message>]) {
+    if waiting.postponed.is_empty()
+        || db.clients().pause_mode(Instant::now()) == Some(PauseMode::All)
+    {
+        return;
+    }
+
+    for command in std::mem::take(&mut waiting.postponed) {
+        if let Some(deferred) = process_command(db, command, peers).await {
+            waiting.push(deferred);
+        }
+    }
+}
+
+/// Executes the commands blocked on keys which have been signalled as ready,
+/// oldest first. Those which find nothing to reply with block again, keeping
+/// their deadline.
+async fn process_ready_keys(db: &mut Database, waiting: &mut Waiting, peers: &[Sender<Message>]) {
+    loop {
+        let ready = db.take_ready_keys();
+        if ready.is_empty() {
+            break;
+        }
+
+        let (woken, blocked): (Vec<_>, Vec<_>) = std::mem::take(&mut waiting.blocked)
+            .into_iter()
+            .partition(|b| b.keys.iter().any(|key| ready.contains(key)));
+        waiting.blocked = blocked;
+
+        for BlockedCommand {
+            command,
+            blocked_at,
+            deadline,
+            ..
+        } in woken
+        {
+            match process_command(db, command, peers).await {
+                Some(Deferred::Blocked(mut command)) => {
+                    command.blocked_at = blocked_at;
+                    command.deadline = deadline;
+                    waiting.blocked.push(command);
+                }
+                Some(deferred) => waiting.push(deferred),
+                None => (),
+            }
+        }
+
+        waiting.blocked.sort_by_key(|b| b.blocked_at);
+    }
+}
+
+/// Replies to the blocked commands whose timeout has expired
+async fn expire_blocked(db: &mut Database, waiting: &mut Waiting) {
+    let now = Instant::now();
+    let (expired, blocked): (Vec<_>, Vec<_>) = std::mem::take(&mut waiting.blocked)
+        .into_iter()
+        .partition(|b| b.deadline.is_some_and(|deadline| deadline <= now));
+    waiting.blocked = blocked;
+
+    for BlockedCommand {
+        command,
+        timeout_reply,
+        ..
+    } in expired
+    {
+        if let Some(client) = db.clients().get_mut(command.client_id) {
+            client.activity.blocked = false;
+        }
+
+        if let Some(response_sender) = command.response_sender {
+            if response_sender
+                .try_send(Reply::Done(timeout_reply))
+                .is_err()
+            {
+                debug!("Client receiver has gone");
+            }
+        }
+    }
+}
+
+/// Whilst subscribed to a channel or pattern a client may only change its
+/// subscriptions
+fn is_allowed_when_subscribed(cmd: &RedisCommand) -> bool {
+    let allowed: &[&[u8]] = &[b"subscribe", b"unsubscribe", b"psubscribe", b"punsubscribe"];
+
+    allowed.contains(&cmd.name)
+}
+
+/// Periodic housekeeping, similar in spirit to Redis's `serverCron`. The
+/// clients are looked after by the first shard, whose copies can kill them.
+fn server_cron(db: &mut Database, waiting: &Waiting) {
+    let now = Instant::now();
+    db.stats_mut().sample(now);
+
+    if db.shard() != 0 {
+        return;
+    }
+
+    let timeout = db.config().timeout;
+    if timeout != 0 {
+        let activity = db.client_activity();
+        for client in db.clients().iter_mut() {
+            let timed_out = activity.get(&client.id).is_some_and(|activity| {
+                client.is_timed_out(activity, Duration::from_secs(timeout), now)
+            });
+            if !client.is_killed() && timed_out {
+                debug!("Closing idle client");
+                client.kill();
+            }
+        }
+    }
+
+    // A client over its soft limit is otherwise only closed when something
+    // more is queued for it
+    let limits = db.config().client_output_buffer_limit;
+    for client in db.clients().iter() {
+        client
+            .output()
+            .check_limits(limits[client.class().index()], now);
+    }
+
+    // Commands blocked on keys held by other shards aren't woken by writes
+    // to them, so they are retried instead
+    for blocked in &waiting.blocked {
+        for key in &blocked.keys {
+            if !db.is_home(key) {
+                db.signal_key_ready(key);
+            }
+        }
+    }
+}
+
 fn api_handle_command(
     cmd: &RedisCommand,
     db: &mut Database,
     request: &Request,
     response: &mut Response,
 ) {
+    if cmd.has_flag("denyoom") && !db.perform_evictions() {
+        response.add_error("OOM command not allowed when used memory > 'maxmemory'.");
+        return;
+    }
+
+    if !cmd.has_flag("admin") {
+        db.feed_monitors(request.argv());
+    }
+
+    let start = Instant::now();
     let result = catch_unwind(AssertUnwindSafe(|| cmd.execute(db, request, response)));
+    let duration = start.elapsed();
+    db.stats_mut().record_command(cmd.name, duration);
+    db.slowlog_push(request.argv(), duration);
 
     match result {
         Ok(Err(e)) => {
@@ -94,29 +1152,491 @@ fn api_handle_command(
     }
 }
 
-async fn start_network(api: Sender<Message>, address: impl ToSocketAddrs + Debug) -> Result<()> {
-    let listener = TcpListener::bind(&address).await?;
+/// What serving a connection needs, shared by the listeners
+#[derive(Clone)]
+struct Context {
+    shards: Shards,
+    config: Arc<RwLock<Config>>,
+    /// Whether each shard is running a script
+    script_busy: Arc<[Arc<ScriptBusy>]>,
+    closing: Closing,
+    connections: ConnectionCount,
+}
+
+type AcceptLoop = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Binds the listeners, returning the loops accepting connections on them
+/// along with the addresses of those over TCP
+async fn start_network(context: Context, bind: Bind) -> Result<(Vec<AcceptLoop>, Vec<SocketAddr>)> {
+    let config = Arc::clone(&context.config);
+    let (bind_addrs, port, tcp_backlog, unixsocket, unixsocketperm, tls_port, tls_acceptor) = {
+        let config = config.read().expect("config lock poisoned");
+        let tls_acceptor = if config.tls_port != 0 {
+            Some(tls::acceptor(&config)?)
+        } else {
+            None
+        };
+        (
+            config.bind.clone(),
+            config.port,
+            config.tcp_backlog,
+            config.unixsocket.clone(),
+            config.unixsocketperm,
+            config.tls_port,
+            tls_acceptor,
+        )
+    };
+
+    let listeners = match bind.listener {
+        Some(listener) => {
+            listener.set_nonblocking(true)?;
+            vec![TcpListener::from_std(listener)?]
+        }
+        None if bind.ephemeral_port => bind_tcp(&bind_addrs, 0, tcp_backlog).await?,
+        None => bind_tcp(&bind_addrs, port, tcp_backlog).await?,
+    };
+    let mut local_addrs = listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<io::Result<Vec<_>>>()?;
+    // The port actually listened on is the one reported, such as by INFO
+    if let Some(addr) = local_addrs.first() {
+        config.write().expect("config lock poisoned").port = addr.port();
+    }
+
+    let mut accept_loops: Vec<AcceptLoop> = vec![];
+    for listener in listeners {
+        accept_loops.push(Box::pin(accept_loop(listener, context.clone())));
+    }
+
+    if let Some(acceptor) = tls_acceptor {
+        for listener in bind_tcp(&bind_addrs, tls_port, tcp_backlog).await? {
+            local_addrs.push(listener.local_addr()?);
+            accept_loops.push(Box::pin(accept_loop(
+                TlsListener::new(listener, acceptor.clone()),
+                context.clone(),
+            )));
+        }
+    }
+
+    if let Some(path) = unixsocket {
+        // A socket left behind by a previous run would fail the bind
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+        if unixsocketperm != 0 {
+            fs::set_permissions(&path, fs::Permissions::from_mode(unixsocketperm))?;
+        }
+        info!("Listening at {}", path.display());
+        accept_loops.push(Box::pin(accept_loop(listener, context)));
+    }
+
+    Ok((accept_loops, local_addrs))
+}
+
+/// Binds the addresses given by `bind`. Like Redis, `*` stands for every IPv4
+/// address and `::*` for every IPv6 one, and an address prefixed with `-` is
+/// skipped if it can't be bound.
+async fn bind_tcp(bind: &[String], port: u16, backlog: u32) -> Result<Vec<TcpListener>> {
+    let mut listeners = vec![];
 
+    for address in bind {
+        let (address, optional) = match address.strip_prefix('-') {
+            Some(address) => (address, true),
+            None => (address.as_str(), false),
+        };
+        let address = match address {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            address => address,
+        };
+
+        let bound = async {
+            let mut bound = vec![];
+            for addr in net::lookup_host((address, port)).await? {
+                bound.push(listener::bind_tcp(addr, backlog)?);
+            }
+            io::Result::Ok(bound)
+        };
+        match bound.await {
+            Ok(bound) => {
+                info!("Listening at {:?}", (address, port));
+                listeners.extend(bound);
+            }
+            Err(err) if optional => {
+                warn!("Skipping optional address {}:{}: {}", address, port, err);
+            }
+            Err(err) => {
+                let msg = format!(
+                    "Could not create server TCP listening socket {}:{}: {}",
+                    address, port, err
+                );
+                return Err(msg.into());
+            }
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// Counts the connections open across the listeners, to keep them within
+/// `maxclients`
+#[derive(Clone, Default)]
+struct ConnectionCount(Arc<AtomicUsize>);
+
+/// A connection's place in the count, given up when dropped
+struct CountedConnection(Arc<AtomicUsize>);
+
+impl ConnectionCount {
+    fn try_add(&self, max: usize) -> Option<CountedConnection> {
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .ok()?;
+
+        Some(CountedConnection(Arc::clone(&self.0)))
+    }
+}
+
+impl Drop for CountedConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+const PROTECTED_MODE_DENIED: &str = "-DENIED Redis is running in protected mode because protected mode is enabled. In this mode connections are only accepted from the loopback interface. If you want to connect from external computers to Redis you may adopt one of the following solutions: 1) Just disable protected mode sending the command 'CONFIG SET protected-mode no' from the loopback interface by connecting to Redis from the same host the server is running, however MAKE SURE Redis is not publicly accessible from internet if you do so. Use CONFIG REWRITE to make this change permanent. 2) Alternatively you can just disable the protected mode by editing the Redis configuration file, and setting the protected mode option to 'no', and then restarting the server. 3) If you started the server manually just for testing, restart it with the '--protected-mode no' option. NOTE: You only need to do one of the above things in order for the server to start accepting connections from the outside.\r\n";
+
+/// Counts a new connection, unless it is to be refused because there are
+/// `maxclients` already or because of protected mode, in which case the
+/// error to send the client is returned instead
+fn admit(
+    connections: &ConnectionCount,
+    config: &RwLock<Config>,
+    addr: &ClientAddr,
+) -> std::result::Result<CountedConnection, &'static str> {
+    let (maxclients, protected_mode) = {
+        let config = config.read().expect("config lock poisoned");
+        (config.maxclients, config.protected_mode)
+    };
+
+    let counted = connections
+        .try_add(maxclients)
+        .ok_or("-ERR max number of clients reached\r\n")?;
+    if protected_mode && !addr.is_local() {
+        return Err(PROTECTED_MODE_DENIED);
+    }
+
+    Ok(counted)
+}
+
+async fn accept_loop<L: Listener>(listener: L, context: Context) {
     // accept connections and process them serially
-    info!("Listening at {:?}", address);
-    while let Ok((stream, _)) = listener.accept().await {
-        let api = api.clone();
+    loop {
+        let (socket, addr) = accept(&listener).await;
+        let tcp_keepalive = context
+            .config
+            .read()
+            .expect("config lock poisoned")
+            .tcp_keepalive;
+        if tcp_keepalive != 0 {
+            if let Err(err) = L::set_keepalive(&socket, Duration::from_secs(tcp_keepalive)) {
+                warn!("Error setting keepalive: {}", err);
+            }
+        }
+
+        let context = context.clone();
+        let laddr = L::local_addr(&socket);
+        let stream = listener.establish(socket);
         tokio::spawn(async move {
-            if let Err(ref err) = handle_client(stream, api).await {
-                error!("Error handling client: {}", err);
+            match stream.await.and_then(|stream| Ok((stream, laddr?))) {
+                Ok((stream, laddr)) => serve_connection(stream, addr, laddr, context).await,
+                Err(err) => error!("Error accepting client: {}", err),
             }
         });
     }
+}
+
+/// Waits for the next connection. Like Redis, a failed accept, such as when
+/// the server has run out of file descriptors, is logged and the listener
+/// tried again after a while, rather than given up on.
+async fn accept<L: Listener>(listener: &L) -> (L::Socket, ClientAddr) {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return accepted,
+            Err(err) => {
+                warn!("Accepting client connection: {}", err);
+                time::sleep(ACCEPT_RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Serves an established connection until it is closed, unless it is refused
+/// straight away
+async fn serve_connection(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    addr: ClientAddr,
+    laddr: ClientAddr,
+    context: Context,
+) {
+    let Context {
+        shards,
+        config,
+        script_busy,
+        closing,
+        connections,
+    } = context;
+
+    let _counted = match admit(&connections, &config, &addr) {
+        Ok(counted) => counted,
+        Err(rejection) => {
+            let _ = stream.write_all(rejection.as_bytes()).await;
+            let _ = stream.shutdown().await;
+            let _ = shards.primary().send(Message::ClientRejected).await;
+            return;
+        }
+    };
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+    let (kill_switch, killed) = oneshot::channel();
+    let (push_sender, push_receiver) = mpsc::channel(PUSH_BUFFER_LEN);
+    let client = Client::new(client_id, addr, laddr, kill_switch, push_sender);
+    let channels = ClientChannels {
+        killed,
+        pushes: push_receiver,
+        output: client.output(),
+    };
+
+    // Every shard keeps a copy of the client, only the first's can kill it
+    let mut copies: Vec<Client> = shards.all().skip(1).map(|_| client.for_shard()).collect();
+    copies.insert(0, client);
+    for (shard, client) in shards.all().zip(copies) {
+        if shard
+            .send(Message::ClientConnected(Box::new(client)))
+            .await
+            .is_err()
+        {
+            error!("Api receiver has gone");
+            return;
+        }
+    }
+
+    if let Err(ref err) = handle_client(
+        stream,
+        client_id,
+        channels,
+        shards.clone(),
+        config,
+        script_busy,
+        closing,
+    )
+    .await
+    {
+        error!("Error handling client: {}", err);
+    }
+
+    for shard in shards.all() {
+        let _ = shard.send(Message::ClientDisconnected { client_id }).await;
+    }
+}
+
+fn proto_limits(config: &RwLock<Config>) -> protocol::Limits {
+    let config = config.read().expect("config lock poisoned");
+
+    protocol::Limits {
+        max_array_size: config
+            .proto_max_multibulk_len
+            .try_into()
+            .unwrap_or(usize::MAX),
+        max_bulk_str_size: config.proto_max_bulk_len.try_into().unwrap_or(usize::MAX),
+    }
+}
+
+/// The shards running a script for longer than `lua-time-limit`
+fn busy_scripts<'a>(
+    script_busy: &'a [Arc<ScriptBusy>],
+    config: &RwLock<Config>,
+) -> impl Iterator<Item = &'a Arc<ScriptBusy>> {
+    let time_limit = {
+        let config = config.read().expect("config lock poisoned");
+        Duration::from_millis(config.lua_time_limit)
+    };
+
+    script_busy.iter().filter(move |busy| {
+        busy.running_for()
+            .is_some_and(|running_for| running_for >= time_limit)
+    })
+}
+
+/// Whether a script has run for longer than `lua-time-limit` on any shard
+fn is_script_busy(script_busy: &[Arc<ScriptBusy>], config: &RwLock<Config>) -> bool {
+    busy_scripts(script_busy, config).next().is_some()
+}
+
+/// How long until a running script reaches `lua-time-limit`, when the
+/// commands waiting for its shard are to be answered by their connections.
+/// As a script may yet start ahead of them, it is no longer than the cron
+/// interval.
+fn time_to_busy(script_busy: &[Arc<ScriptBusy>], config: &RwLock<Config>) -> Duration {
+    let time_limit = {
+        let config = config.read().expect("config lock poisoned");
+        Duration::from_millis(config.lua_time_limit)
+    };
+
+    script_busy
+        .iter()
+        .filter_map(|busy| busy.running_for())
+        .map(|running_for| time_limit.saturating_sub(running_for))
+        .fold(CRON_INTERVAL, Duration::min)
+}
+
+/// Once a script has run for longer than `lua-time-limit` its shard stays
+/// busy with it, so, as in Redis, every client is answered from its
+/// connection instead: SCRIPT KILL or FUNCTION KILL stops the script,
+/// SHUTDOWN NOSAVE shuts the server down without waiting for it and
+/// everything else is refused.
+fn busy_script_reply(
+    script_busy: &[Arc<ScriptBusy>],
+    config: &RwLock<Config>,
+    shutdown: &Shutdown,
+    request: &Request,
+) -> Option<Response> {
+    if !is_script_busy(script_busy, config) {
+        return None;
+    }
+
+    Some(answer_while_busy(script_busy, config, shutdown, request))
+}
+
+/// The reply to a request made while a script is busy
+fn answer_while_busy(
+    script_busy: &[Arc<ScriptBusy>],
+    config: &RwLock<Config>,
+    shutdown: &Shutdown,
+    request: &Request,
+) -> Response {
+    let mut response = Response::new();
+    if request.command().eq_ignore_ascii_case(b"shutdown") {
+        if let Ok(Some(flags)) = ShutdownFlags::parse(request.arguments()) {
+            if flags.save == Some(false) {
+                warn!("User requested shutdown...");
+                shutdown.request(flags);
+                return response;
+            }
+        }
+    }
+
+    let is_kill = (request.command().eq_ignore_ascii_case(b"script")
+        || request.command().eq_ignore_ascii_case(b"function"))
+        && request.arguments().len() == 1
+        && request.arguments()[0].eq_ignore_ascii_case(b"kill");
+
+    if !is_kill {
+        response.add_error("BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.");
+    } else if busy_scripts(script_busy, config).all(|busy| busy.kill()) {
+        response.add_simple_string("OK");
+    } else {
+        response.add_error("UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command.");
+    }
+
+    response
+}
+
+/// A connection's part in shutting down: it may ask for a shutdown, is told
+/// when to close, and is waited for until it has by the `open` sender it
+/// holds on to
+#[derive(Clone)]
+struct Closing {
+    shutdown: Arc<Shutdown>,
+    signal: watch::Receiver<bool>,
+    _open: mpsc::Sender<()>,
+}
+
+impl Closing {
+    fn is_closing(&self) -> bool {
+        *self.signal.borrow()
+    }
+
+    async fn wait(&mut self) {
+        while !self.is_closing() {
+            if self.signal.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The ends of the channels through which the shards reach a connection
+struct ClientChannels {
+    killed: oneshot::Receiver<()>,
+    pushes: mpsc::Receiver<Response>,
+    output: Arc<OutputBuffer>,
+}
+
+/// Writes data queued for the client, giving up if the client's output
+/// buffer overflows meanwhile as it may never read it
+async fn write_output(
+    out_stream: &mut (impl AsyncWrite + Unpin),
+    output: &OutputBuffer,
+    bytes: &[u8],
+) -> Result<()> {
+    tokio::select! {
+        written = out_stream.write_all(bytes) => written?,
+        _ = output.overflowed() => return Err(output_overflow_error()),
+    }
+    output.written(bytes.len());
+
     Ok(())
 }
 
-async fn handle_client(mut stream: TcpStream, api: Sender<Message>) -> Result<()> {
-    let (read_half, mut out_stream) = stream.split();
-    let mut reader = BufReader::new(read_half);
-    let (response_sender, mut response_receiver) = mpsc::channel(1);
+fn output_overflow_error() -> Error {
+    "Client closed for overcoming of output buffer limits".into()
+}
+
+async fn handle_client(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    client_id: u64,
+    channels: ClientChannels,
+    shards: Shards,
+    config: Arc<RwLock<Config>>,
+    script_busy: Arc<[Arc<ScriptBusy>]>,
+    mut closing: Closing,
+) -> Result<()> {
+    let ClientChannels {
+        mut killed,
+        mut pushes,
+        output,
+    } = channels;
+    let (mut read_half, mut out_stream) = io::split(stream);
+    let mut decoder = protocol::Decoder::new();
 
-    loop {
-        let request = match request::parse(&mut reader).await {
+    while !closing.is_closing() {
+        let limits = proto_limits(&config);
+        // Pushed data is written while waiting for the next request
+        let parsed = {
+            let parse = request::parse(&mut read_half, &mut decoder, limits);
+            tokio::pin!(parse);
+
+            loop {
+                tokio::select! {
+                    parsed = &mut parse => break Some(parsed),
+                    Some(push) = pushes.recv() => {
+                        write_output(&mut out_stream, &output, push.as_bytes()).await?;
+                    }
+                    _ = output.overflowed() => return Err(output_overflow_error()),
+                    _ = &mut killed => break None,
+                    _ = closing.wait() => break None,
+                }
+            }
+        };
+        let parsed = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                debug!("Client killed or closed on shutdown");
+                break;
+            }
+        };
+
+        let request = match parsed {
             Ok(request) => request,
             Err(Error::Proto(ProtoError::ConnectionClosed)) => {
                 debug!("Client closed connection");
@@ -138,19 +1658,111 @@ async fn handle_client(mut stream: TcpStream, api: Sender<Message>) -> Result<()
 
         debug!("{:?}", request);
 
-        let message = Message {
-            request,
-            response_sender: response_sender.clone(),
+        if let Some(response) =
+            busy_script_reply(&script_busy, &config, &closing.shutdown, &request)
+        {
+            out_stream.write_all(response.as_bytes()).await?;
+            continue;
+        }
+
+        // Each command has a channel of its own, so that a command dropped by
+        // the API, such as when shutting down, is seen as the channel closing
+        let (response_sender, mut response_receiver) = response::reply_channel();
+        let routed = shards.route(&request);
+        let qbuf = decoder.pending();
+        let mut update = shared_state_update(&request);
+        let queued = Queued::new(request);
+
+        let message = Message::Command {
+            client_id,
+            qbuf,
+            request: queued.clone(),
+            response_sender: Some(response_sender),
         };
 
-        if let Err(e) = api.send(message).await {
+        if let Err(e) = shards.senders[routed].send(message).await {
             let msg = format!("Api receiver has gone: {}", e);
             return Err(msg.into());
         }
 
-        match response_receiver.recv().await {
+        // The chunks of a large reply are written as they arrive. A blocked
+        // client may wait for its reply indefinitely, so the connection is
+        // watched for being closed or killed meanwhile. A command still
+        // queued once a script makes its shard busy is answered from here.
+        let mut watch_eof = decoder.pending() == 0;
+        let response = loop {
+            tokio::select! {
+                biased;
+                reply = response_receiver.recv() => match reply {
+                    Some(Reply::Chunk(chunk)) => {
+                        write_output(&mut out_stream, &output, &chunk).await?;
+                    }
+                    Some(Reply::Done(mut response)) => {
+                        for chunk in response.take_held_chunks() {
+                            write_output(&mut out_stream, &output, &chunk).await?;
+                        }
+                        break Some(Some(response));
+                    }
+                    None => break Some(None),
+                },
+                _ = output.overflowed() => return Err(output_overflow_error()),
+                _ = &mut killed => break None,
+                read = decoder.read(&mut read_half), if watch_eof => match read {
+                    Ok(0) | Err(_) => break None,
+                    Ok(_) => watch_eof = false,
+                },
+                _ = time::sleep(time_to_busy(&script_busy, &config)), if !queued.is_taken() => {
+                    if !is_script_busy(&script_busy, &config) {
+                        continue;
+                    }
+                    if let Some(request) = queued.take() {
+                        update = None;
+                        let shutdown = &closing.shutdown;
+                        break Some(Some(answer_while_busy(&script_busy, &config, shutdown, &request)));
+                    }
+                }
+            }
+        };
+        let response = match response {
+            Some(response) => response,
+            None => {
+                debug!("Client went away whilst awaiting a reply");
+                break;
+            }
+        };
+
+        match response {
             Some(response) => {
+                // The other shards are sent their update before the client
+                // has the reply, so it is ahead of any command it sends next
+                for (_, shard) in shards.all().enumerate().filter(|(i, _)| *i != routed) {
+                    let request = match &update {
+                        Some(update) => Queued::new(update.clone()),
+                        None => break,
+                    };
+                    let message = Message::Command {
+                        client_id,
+                        qbuf,
+                        request,
+                        response_sender: None,
+                    };
+                    if let Err(e) = shard.send(message).await {
+                        let msg = format!("Api receiver has gone: {}", e);
+                        return Err(msg.into());
+                    }
+                }
+
                 out_stream.write_all(response.as_bytes()).await?;
+
+                // A client may have killed itself
+                if killed.try_recv().is_ok() {
+                    break;
+                }
+            }
+            // Commands waiting when the server shuts down are dropped
+            None if closing.is_closing() => {
+                debug!("Client closed on shutdown whilst awaiting a reply");
+                break;
             }
             None => {
                 error!("Api sender gone");
@@ -161,3 +1773,56 @@ async fn handle_client(mut stream: TcpStream, api: Sender<Message>) -> Result<()
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    /// Fails as if out of file descriptors until it has failed `failures`
+    /// times, then accepts an in-memory connection
+    struct FailingListener {
+        failures: AtomicUsize,
+    }
+
+    impl Listener for FailingListener {
+        type Socket = DuplexStream;
+        type Stream = DuplexStream;
+
+        async fn accept(&self) -> io::Result<(DuplexStream, ClientAddr)> {
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                // EMFILE
+                return Err(io::Error::from_raw_os_error(24));
+            }
+            let (socket, _) = io::duplex(64);
+            Ok((socket, ClientAddr::Local))
+        }
+
+        fn local_addr(_socket: &DuplexStream) -> io::Result<ClientAddr> {
+            Ok(ClientAddr::Local)
+        }
+
+        fn establish(
+            &self,
+            socket: DuplexStream,
+        ) -> impl Future<Output = io::Result<DuplexStream>> + Send + 'static {
+            future::ready(Ok(socket))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accept_retries_after_failure() {
+        let listener = FailingListener {
+            failures: AtomicUsize::new(2),
+        };
+
+        let started = Instant::now();
+        let (_socket, addr) = time::timeout(Duration::from_secs(5), accept(&listener))
+            .await
+            .expect("accept gave up");
+        assert_eq!(addr, ClientAddr::Local);
+        assert_eq!(listener.failures.load(Ordering::Relaxed), 0);
+        assert!(started.elapsed() >= ACCEPT_RETRY_INTERVAL * 2);
+    }
+}
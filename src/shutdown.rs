@@ -0,0 +1,135 @@
+//! Shutting the server down, as asked by SHUTDOWN or by SIGTERM or SIGINT.
+//! The request is picked up by the task running the network, which stops
+//! accepting connections and lets those open finish the command they are in
+//! the middle of, then saves the dataset as asked before the server exits.
+
+use byte_string::ByteString;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// The options of a shutdown, see SHUTDOWN
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownFlags {
+    /// Given by SAVE or NOSAVE, otherwise the dataset is saved if there are
+    /// save points
+    pub save: Option<bool>,
+    /// Don't wait for lagging replicas
+    pub now: bool,
+    /// Exit even if the dataset can't be saved
+    pub force: bool,
+}
+
+impl ShutdownFlags {
+    /// Parses the arguments of SHUTDOWN. Returns `None` for ABORT, which
+    /// can't be combined with the other flags, or an error for a syntax error.
+    pub fn parse(args: &[ByteString]) -> Result<Option<Self>, ()> {
+        let mut flags = Self::default();
+        let mut abort = false;
+
+        for arg in args {
+            match arg.to_lowercase().as_ref() {
+                b"nosave" if flags.save.is_none() => flags.save = Some(false),
+                b"save" if flags.save.is_none() => flags.save = Some(true),
+                b"now" => flags.now = true,
+                b"force" => flags.force = true,
+                b"abort" => abort = true,
+                _ => return Err(()),
+            }
+        }
+
+        match abort {
+            false => Ok(Some(flags)),
+            true if args.len() == 1 => Ok(None),
+            true => Err(()),
+        }
+    }
+}
+
+/// A shutdown that has been asked for, shared between the shards and the
+/// network
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    requested: Mutex<Option<ShutdownFlags>>,
+    notify: Notify,
+}
+
+impl Shutdown {
+    /// Asks for the server to shut down. Only the first request is acted on.
+    pub fn request(&self, flags: ShutdownFlags) {
+        let mut requested = self.requested.lock().expect("shutdown lock poisoned");
+        if requested.is_none() {
+            *requested = Some(flags);
+            self.notify.notify_one();
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested
+            .lock()
+            .expect("shutdown lock poisoned")
+            .is_some()
+    }
+
+    /// Waits until a shutdown has been asked for
+    pub async fn requested(&self) -> ShutdownFlags {
+        loop {
+            if let Some(flags) = *self.requested.lock().expect("shutdown lock poisoned") {
+                return flags;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<ByteString> {
+        line.split_whitespace().map(ByteString::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ShutdownFlags::parse(&[]),
+            Ok(Some(ShutdownFlags::default()))
+        );
+        assert_eq!(
+            ShutdownFlags::parse(&args("NOSAVE now")),
+            Ok(Some(ShutdownFlags {
+                save: Some(false),
+                now: true,
+                force: false,
+            }))
+        );
+        assert_eq!(
+            ShutdownFlags::parse(&args("save force")),
+            Ok(Some(ShutdownFlags {
+                save: Some(true),
+                now: false,
+                force: true,
+            }))
+        );
+        assert_eq!(ShutdownFlags::parse(&args("abort")), Ok(None));
+
+        assert!(ShutdownFlags::parse(&args("save nosave")).is_err());
+        assert!(ShutdownFlags::parse(&args("abort force")).is_err());
+        assert!(ShutdownFlags::parse(&args("later")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_requested() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.is_requested());
+
+        shutdown.request(ShutdownFlags {
+            force: true,
+            ..ShutdownFlags::default()
+        });
+        shutdown.request(ShutdownFlags::default());
+
+        assert!(shutdown.is_requested());
+        assert!(shutdown.requested().await.force);
+    }
+}
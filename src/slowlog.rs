@@ -0,0 +1,190 @@
+use crate::clients::ClientAddr;
+use byte_string::ByteString;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Only this many arguments of a command are kept in an entry
+const SLOWLOG_ENTRY_MAX_ARGC: usize = 32;
+/// Arguments are truncated to this many bytes
+const SLOWLOG_ENTRY_MAX_STRING: usize = 128;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlowLogEntry {
+    pub id: u64,
+    /// Unix time in seconds at which the command was logged
+    pub timestamp: u64,
+    pub duration: Duration,
+    pub argv: Vec<ByteString>,
+    pub client_addr: Option<ClientAddr>,
+    pub client_name: Option<ByteString>,
+}
+
+/// A bounded log of the commands which exceeded `slowlog-log-slower-than`,
+/// newest first, in the same way as Redis's `slowlog.c`.
+pub struct SlowLog {
+    entries: VecDeque<SlowLogEntry>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl SlowLog {
+    pub fn new() -> Self {
+        Self::with_ids(Arc::default())
+    }
+
+    /// A log whose entries are numbered by a counter shared with the logs of
+    /// the other shards, so that their entries can be put back in order
+    pub fn with_ids(next_id: Arc<AtomicU64>) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            next_id,
+        }
+    }
+
+    /// Logs the command if it took longer than `slower_than` microseconds. A
+    /// negative threshold disables the log and zero logs every command.
+    pub fn push_if_needed(
+        &mut self,
+        slower_than: i64,
+        max_len: usize,
+        argv: &[ByteString],
+        duration: Duration,
+        client_addr: Option<ClientAddr>,
+        client_name: Option<&ByteString>,
+    ) {
+        if slower_than < 0 || duration.as_micros() < slower_than as u128 {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        self.entries.push_front(SlowLogEntry {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            timestamp,
+            duration,
+            argv: truncate_argv(argv),
+            client_addr,
+            client_name: client_name.cloned(),
+        });
+
+        self.entries.truncate(max_len);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The most recent entries first
+    pub fn iter(&self) -> impl Iterator<Item = &SlowLogEntry> {
+        self.entries.iter()
+    }
+}
+
+fn truncate_argv(argv: &[ByteString]) -> Vec<ByteString> {
+    let argc = argv.len().min(SLOWLOG_ENTRY_MAX_ARGC);
+    let mut truncated = Vec::with_capacity(argc);
+
+    for (i, arg) in argv.iter().take(argc).enumerate() {
+        if argc != argv.len() && i == argc - 1 {
+            // Use the last slot to say how many arguments were left out
+            let more = format!("... ({} more arguments)", argv.len() - argc + 1);
+            truncated.push(more.into());
+        } else if arg.len() > SLOWLOG_ENTRY_MAX_STRING {
+            let more = format!("... ({} more bytes)", arg.len() - SLOWLOG_ENTRY_MAX_STRING);
+            let mut shortened = arg[..SLOWLOG_ENTRY_MAX_STRING].to_vec();
+            shortened.extend_from_slice(more.as_bytes());
+            truncated.push(shortened.into());
+        } else {
+            truncated.push(arg.clone());
+        }
+    }
+
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(args: &[&str]) -> Vec<ByteString> {
+        args.iter().map(|&a| a.into()).collect()
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut log = SlowLog::new();
+        let args = argv(&["get", "x"]);
+
+        log.push_if_needed(100, 10, &args, Duration::from_micros(99), None, None);
+        assert_eq!(log.len(), 0);
+
+        log.push_if_needed(100, 10, &args, Duration::from_micros(100), None, None);
+        assert_eq!(log.len(), 1);
+
+        log.push_if_needed(-1, 10, &args, Duration::from_secs(1), None, None);
+        assert_eq!(log.len(), 1);
+
+        log.push_if_needed(0, 10, &args, Duration::from_micros(0), None, None);
+        assert_eq!(log.len(), 2);
+    }
+
+    #[test]
+    fn test_bounded_newest_first() {
+        let mut log = SlowLog::new();
+
+        for i in 0..5 {
+            let args = argv(&["set", "x", &i.to_string()]);
+            log.push_if_needed(0, 3, &args, Duration::from_micros(1), None, None);
+        }
+
+        let ids: Vec<u64> = log.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![4, 3, 2]);
+
+        log.reset();
+        assert_eq!(log.len(), 0);
+    }
+
+    #[test]
+    fn test_shared_ids() {
+        let ids = Arc::new(AtomicU64::new(0));
+        let mut first = SlowLog::with_ids(Arc::clone(&ids));
+        let mut second = SlowLog::with_ids(ids);
+        let args = argv(&["get", "x"]);
+
+        first.push_if_needed(0, 10, &args, Duration::from_micros(1), None, None);
+        second.push_if_needed(0, 10, &args, Duration::from_micros(1), None, None);
+        first.push_if_needed(0, 10, &args, Duration::from_micros(1), None, None);
+
+        assert_eq!(first.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2, 0]);
+        assert_eq!(second.iter().map(|e| e.id).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_truncate_argv() {
+        let long_arg = "x".repeat(SLOWLOG_ENTRY_MAX_STRING + 10);
+        let truncated = truncate_argv(&argv(&["set", "x", &long_arg]));
+
+        assert_eq!(
+            truncated[2],
+            ByteString::from(format!("{}... (10 more bytes)", "x".repeat(128)))
+        );
+
+        let many: Vec<ByteString> = (0..40).map(|i| i.to_string().into()).collect();
+        let truncated = truncate_argv(&many);
+
+        assert_eq!(truncated.len(), SLOWLOG_ENTRY_MAX_ARGC);
+        assert_eq!(truncated[30], ByteString::from("30"));
+        assert_eq!(truncated[31], ByteString::from("... (9 more arguments)"));
+    }
+}
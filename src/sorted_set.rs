@@ -0,0 +1,286 @@
+//! The sorted set data type: unique members ordered by a floating point
+//! score, then lexicographically. Small sorted sets are packed into a
+//! listpack, larger ones index members both by name, for score lookups, and
+//! by score in a B-tree, in place of Redis's skiplist.
+
+use crate::listpack::{Listpack, ListpackLimits};
+use byte_string::ByteString;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+};
+
+/// A score, ordered totally so it can key the B-tree. NaN is never stored.
+#[derive(Clone, Copy, Debug)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SortedSet {
+    /// Alternating members and scores, ordered by score then member. Scores
+    /// are stored as the bytes of the `f64`.
+    Listpack(Listpack),
+    Skiplist(Skiplist),
+}
+
+#[derive(Debug, Default)]
+pub struct Skiplist {
+    scores: HashMap<ByteString, f64>,
+    ordered: BTreeSet<(Score, ByteString)>,
+}
+
+impl PartialEq for Skiplist {
+    fn eq(&self, other: &Self) -> bool {
+        self.ordered == other.ordered
+    }
+}
+
+impl Eq for Skiplist {}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        Self::Listpack(Listpack::new())
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Listpack(_) => "listpack",
+            Self::Skiplist(_) => "skiplist",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Listpack(listpack) => listpack.len() / 2,
+            Self::Skiplist(skiplist) => skiplist.scores.len(),
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        match self {
+            Self::Listpack(listpack) => listpack
+                .pairs()
+                .find(|(m, _)| *m == member)
+                .map(|(_, score)| decode_score(score)),
+            Self::Skiplist(skiplist) => skiplist.scores.get(member).copied(),
+        }
+    }
+
+    /// Adds a member or updates its score, returning the old score
+    pub fn insert(
+        &mut self,
+        member: ByteString,
+        score: f64,
+        limits: ListpackLimits,
+    ) -> Option<f64> {
+        debug_assert!(!score.is_nan());
+
+        let listpack = match self {
+            Self::Listpack(listpack) => listpack,
+            Self::Skiplist(skiplist) => return skiplist.insert(member, score),
+        };
+
+        let existing = listpack
+            .pairs()
+            .enumerate()
+            .find(|(_, (m, _))| *m == member.as_ref())
+            .map(|(index, (_, score))| (index, decode_score(score)));
+        if let Some((index, _)) = existing {
+            listpack.remove_range(2 * index..2 * index + 2);
+        }
+
+        let key = (Score(score), member.as_ref());
+        let index = listpack
+            .pairs()
+            .take_while(|(m, s)| (Score(decode_score(s)), *m) < key)
+            .count();
+        listpack.insert(2 * index, &member);
+        listpack.insert(2 * index + 1, &score.to_le_bytes());
+
+        if listpack.len() / 2 > limits.max_entries || member.len() > limits.max_value {
+            let mut skiplist = Skiplist::default();
+            for (member, score) in listpack.pairs() {
+                skiplist.insert(ByteString::from(member), decode_score(score));
+            }
+            *self = Self::Skiplist(skiplist);
+        }
+
+        existing.map(|(_, score)| score)
+    }
+
+    /// The members and their scores, in order
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        match self {
+            Self::Listpack(listpack) => Box::new(
+                listpack
+                    .pairs()
+                    .map(|(member, score)| (member, decode_score(score))),
+            ),
+            Self::Skiplist(skiplist) => Box::new(
+                skiplist
+                    .ordered
+                    .iter()
+                    .map(|(score, member)| (member.as_ref(), score.0)),
+            ),
+        }
+    }
+
+    /// The members with a score in the range `min <= score < max`, in order
+    pub fn range_by_score(
+        &self,
+        min: f64,
+        max: f64,
+    ) -> Box<dyn Iterator<Item = (&[u8], f64)> + '_> {
+        match self {
+            Self::Listpack(listpack) => Box::new(
+                listpack
+                    .pairs()
+                    .map(|(member, score)| (member, decode_score(score)))
+                    .skip_while(move |(_, score)| Score(*score) < Score(min))
+                    .take_while(move |(_, score)| *score < max),
+            ),
+            Self::Skiplist(skiplist) => {
+                let start = Bound::Included((Score(min), ByteString::new()));
+
+                Box::new(
+                    skiplist
+                        .ordered
+                        .range((start, Bound::Unbounded))
+                        .take_while(move |(score, _)| score.0 < max)
+                        .map(|(score, member)| (member.as_ref(), score.0)),
+                )
+            }
+        }
+    }
+}
+
+impl Skiplist {
+    fn insert(&mut self, member: ByteString, score: f64) -> Option<f64> {
+        let old = self.scores.insert(member.clone(), score);
+        if let Some(old) = old {
+            self.ordered.remove(&(Score(old), member.clone()));
+        }
+        self.ordered.insert((Score(score), member));
+
+        old
+    }
+}
+
+fn decode_score(bytes: &[u8]) -> f64 {
+    let mut le_bytes = [0; 8];
+    le_bytes.copy_from_slice(bytes);
+    f64::from_le_bytes(le_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNLIMITED: ListpackLimits = ListpackLimits {
+        max_entries: usize::MAX,
+        max_value: usize::MAX,
+        max_bytes: usize::MAX,
+    };
+
+    const NONE: ListpackLimits = ListpackLimits {
+        max_entries: 0,
+        ..UNLIMITED
+    };
+
+    fn members<'a>(iter: impl Iterator<Item = (&'a [u8], f64)>) -> Vec<(String, f64)> {
+        iter.map(|(member, score)| (String::from_utf8_lossy(member).into_owned(), score))
+            .collect()
+    }
+
+    #[test]
+    fn test_insert() {
+        for &limits in &[UNLIMITED, NONE] {
+            let mut zset = SortedSet::new();
+
+            assert_eq!(zset.insert("b".into(), 2.0, limits), None);
+            assert_eq!(zset.insert("a".into(), 2.0, limits), None);
+            assert_eq!(zset.insert("c".into(), 1.0, limits), None);
+            assert_eq!(zset.insert("c".into(), 3.0, limits), Some(1.0));
+            assert_eq!(zset.len(), 3);
+            assert_eq!(zset.score(b"c"), Some(3.0));
+            assert_eq!(zset.score(b"d"), None);
+
+            // Equal scores are ordered by member
+            assert_eq!(
+                members(zset.range_by_score(f64::NEG_INFINITY, f64::INFINITY)),
+                vec![("a".into(), 2.0), ("b".into(), 2.0), ("c".into(), 3.0)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_range_by_score() {
+        for &limits in &[UNLIMITED, NONE] {
+            let mut zset = SortedSet::new();
+            for (i, member) in ["a", "b", "c", "d"].iter().enumerate() {
+                zset.insert((*member).into(), i as f64, limits);
+            }
+
+            assert_eq!(
+                members(zset.range_by_score(1.0, 3.0)),
+                vec![("b".into(), 1.0), ("c".into(), 2.0)]
+            );
+            assert_eq!(members(zset.range_by_score(1.5, 1.6)), vec![]);
+            assert_eq!(
+                members(zset.range_by_score(f64::NEG_INFINITY, 0.5)).len(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_converts_past_the_limits() {
+        let limits = ListpackLimits {
+            max_entries: 2,
+            max_value: 4,
+            max_bytes: usize::MAX,
+        };
+
+        let mut zset = SortedSet::new();
+        zset.insert("a".into(), 1.0, limits);
+        zset.insert("b".into(), 2.0, limits);
+        assert_eq!(zset.encoding(), "listpack");
+        zset.insert("c".into(), 0.5, limits);
+        assert_eq!(zset.encoding(), "skiplist");
+        assert_eq!(
+            members(zset.range_by_score(0.0, 10.0)),
+            vec![("c".into(), 0.5), ("a".into(), 1.0), ("b".into(), 2.0)]
+        );
+
+        let mut zset = SortedSet::new();
+        zset.insert("long member".into(), 1.0, limits);
+        assert_eq!(zset.encoding(), "skiplist");
+        assert_eq!(zset.score(b"long member"), Some(1.0));
+    }
+}
@@ -0,0 +1,307 @@
+use stats_alloc::INSTRUMENTED_SYSTEM;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    time::{Duration, Instant},
+};
+
+const STATS_METRIC_SAMPLES: usize = 16;
+
+/// Counters reported by INFO. Each shard keeps its own, which are added up
+/// for the whole server.
+pub struct Stats {
+    pub start_instant: Instant,
+    pub total_connections_received: u64,
+    pub total_commands_processed: u64,
+    /// Connections refused because of `maxclients` or protected mode
+    pub rejected_connections: u64,
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+    pub expired_keys: u64,
+    pub evicted_keys: u64,
+    pub used_memory_peak: usize,
+    command_stats: HashMap<Vec<u8>, CommandStats>,
+    ops_sec: InstantaneousMetric,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub usec: u64,
+}
+
+impl CommandStats {
+    pub fn usec_per_call(&self) -> f64 {
+        if self.calls == 0 {
+            return 0.0;
+        }
+
+        self.usec as f64 / self.calls as f64
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            start_instant: Instant::now(),
+            total_connections_received: 0,
+            total_commands_processed: 0,
+            rejected_connections: 0,
+            keyspace_hits: 0,
+            keyspace_misses: 0,
+            expired_keys: 0,
+            evicted_keys: 0,
+            used_memory_peak: 0,
+            command_stats: HashMap::new(),
+            ops_sec: InstantaneousMetric::new(Instant::now()),
+        }
+    }
+
+    /// Resets the counters as with CONFIG RESETSTAT
+    pub fn reset(&mut self) {
+        let start_instant = self.start_instant;
+
+        *self = Self::new();
+        self.start_instant = start_instant;
+    }
+
+    /// Adds the counters of another shard to these
+    pub fn add(&mut self, other: &Stats) {
+        self.start_instant = self.start_instant.min(other.start_instant);
+        self.total_connections_received += other.total_connections_received;
+        self.total_commands_processed += other.total_commands_processed;
+        self.rejected_connections += other.rejected_connections;
+        self.keyspace_hits += other.keyspace_hits;
+        self.keyspace_misses += other.keyspace_misses;
+        self.expired_keys += other.expired_keys;
+        self.evicted_keys += other.evicted_keys;
+        self.used_memory_peak = self.used_memory_peak.max(other.used_memory_peak);
+
+        for (name, stats) in &other.command_stats {
+            let entry = self.command_stats.entry(name.clone()).or_default();
+            entry.calls += stats.calls;
+            entry.usec += stats.usec;
+        }
+        self.ops_sec.add(&other.ops_sec);
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.start_instant.elapsed()
+    }
+
+    pub fn record_command(&mut self, name: &[u8], duration: Duration) {
+        let usec = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+
+        self.total_commands_processed += 1;
+
+        match self.command_stats.get_mut(name) {
+            Some(entry) => {
+                entry.calls += 1;
+                entry.usec += usec;
+            }
+            None => {
+                let entry = CommandStats { calls: 1, usec };
+                self.command_stats.insert(name.to_vec(), entry);
+            }
+        }
+    }
+
+    /// Per command statistics, sorted by command name
+    pub fn command_stats(&self) -> Vec<(&[u8], CommandStats)> {
+        let mut stats: Vec<(&[u8], CommandStats)> = self
+            .command_stats
+            .iter()
+            .map(|(name, stats)| (name.as_ref(), *stats))
+            .collect();
+
+        stats.sort_unstable_by_key(|(name, _)| *name);
+        stats
+    }
+
+    /// Called periodically from the server cron to track the ops/sec rate
+    pub fn sample(&mut self, now: Instant) {
+        self.ops_sec.sample(now, self.total_commands_processed);
+        self.used_memory_peak = self.used_memory_peak.max(used_memory());
+    }
+
+    pub fn instantaneous_ops_per_sec(&self) -> u64 {
+        self.ops_sec.average()
+    }
+}
+
+/// The number of bytes currently allocated on the heap. This relies on the
+/// binary installing `stats_alloc::INSTRUMENTED_SYSTEM` as its global
+/// allocator, otherwise it always reports zero.
+pub fn used_memory() -> usize {
+    let stats = INSTRUMENTED_SYSTEM.stats();
+    let used =
+        stats.bytes_allocated as isize - stats.bytes_deallocated as isize + stats.bytes_reallocated;
+
+    used.max(0) as usize
+}
+
+/// The resident set size of the process as reported by the OS. Only
+/// supported on Linux, reports zero elsewhere.
+pub fn used_memory_rss() -> usize {
+    const PAGE_SIZE: usize = 4096;
+
+    std::fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse::<usize>().ok())
+        .map_or(0, |pages| pages * PAGE_SIZE)
+}
+
+/// Formats a byte count in the same style as Redis's `bytesToHuman`
+pub fn bytes_to_human(n: usize) -> String {
+    const UNITS: &[(f64, &str)] = &[
+        (1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0, "P"),
+        (1024.0 * 1024.0 * 1024.0 * 1024.0, "T"),
+        (1024.0 * 1024.0 * 1024.0, "G"),
+        (1024.0 * 1024.0, "M"),
+        (1024.0, "K"),
+    ];
+
+    let d = n as f64;
+    for (size, unit) in UNITS {
+        if d >= *size {
+            return format!("{:.2}{}", d / size, unit);
+        }
+    }
+
+    format!("{}B", n)
+}
+
+/// Tracks the rate at which a counter increases over a rolling window of
+/// samples, in the same way as Redis's `trackInstantaneousMetric`.
+struct InstantaneousMetric {
+    last_sample_time: Instant,
+    last_sample_count: u64,
+    samples: [u64; STATS_METRIC_SAMPLES],
+    idx: usize,
+}
+
+impl InstantaneousMetric {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_sample_time: now,
+            last_sample_count: 0,
+            samples: [0; STATS_METRIC_SAMPLES],
+            idx: 0,
+        }
+    }
+
+    fn sample(&mut self, now: Instant, current_count: u64) {
+        let elapsed = now.saturating_duration_since(self.last_sample_time);
+        let millis = elapsed.as_millis().max(1) as u64;
+        let ops = current_count.saturating_sub(self.last_sample_count);
+
+        self.samples[self.idx] = ops * 1000 / millis;
+        self.idx = (self.idx + 1) % STATS_METRIC_SAMPLES;
+        self.last_sample_time = now;
+        self.last_sample_count = current_count;
+    }
+
+    /// Adds the rates sampled by another metric to these
+    fn add(&mut self, other: &InstantaneousMetric) {
+        for (sample, other) in self.samples.iter_mut().zip(other.samples.iter()) {
+            *sample += other;
+        }
+    }
+
+    fn average(&self) -> u64 {
+        self.samples.iter().sum::<u64>() / STATS_METRIC_SAMPLES as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_command() {
+        let mut stats = Stats::new();
+
+        stats.record_command(b"set", Duration::from_micros(10));
+        stats.record_command(b"get", Duration::from_micros(3));
+        stats.record_command(b"set", Duration::from_micros(20));
+
+        assert_eq!(stats.total_commands_processed, 3);
+        assert_eq!(
+            stats.command_stats(),
+            vec![
+                (b"get" as &[u8], CommandStats { calls: 1, usec: 3 }),
+                (b"set" as &[u8], CommandStats { calls: 2, usec: 30 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut stats = Stats::new();
+        stats.keyspace_hits = 3;
+        stats.record_command(b"set", Duration::from_micros(10));
+
+        stats.reset();
+
+        assert_eq!(stats.keyspace_hits, 0);
+        assert_eq!(stats.total_commands_processed, 0);
+        assert!(stats.command_stats().is_empty());
+    }
+
+    #[test]
+    fn test_add() {
+        let mut stats = Stats::new();
+        stats.keyspace_hits = 3;
+        stats.record_command(b"set", Duration::from_micros(10));
+
+        let mut other = Stats::new();
+        other.keyspace_hits = 2;
+        other.used_memory_peak = 100;
+        other.record_command(b"set", Duration::from_micros(5));
+        other.record_command(b"get", Duration::from_micros(1));
+
+        stats.add(&other);
+
+        assert_eq!(stats.keyspace_hits, 5);
+        assert_eq!(stats.used_memory_peak, 100);
+        assert_eq!(stats.total_commands_processed, 3);
+        assert_eq!(
+            stats.command_stats(),
+            vec![
+                (b"get" as &[u8], CommandStats { calls: 1, usec: 1 }),
+                (b"set" as &[u8], CommandStats { calls: 2, usec: 15 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_usec_per_call() {
+        assert_eq!(CommandStats::default().usec_per_call(), 0.0);
+        assert_eq!(CommandStats { calls: 4, usec: 10 }.usec_per_call(), 2.5);
+    }
+
+    #[test]
+    fn test_bytes_to_human() {
+        assert_eq!(bytes_to_human(0), "0B");
+        assert_eq!(bytes_to_human(1023), "1023B");
+        assert_eq!(bytes_to_human(1024), "1.00K");
+        assert_eq!(bytes_to_human(1536 * 1024), "1.50M");
+        assert_eq!(bytes_to_human(3 * 1024 * 1024 * 1024), "3.00G");
+    }
+
+    #[test]
+    fn test_instantaneous_metric() {
+        let start = Instant::now();
+        let mut metric = InstantaneousMetric::new(start);
+
+        // 100 ops in 100ms is 1000 ops/sec for a single sample
+        metric.sample(start + Duration::from_millis(100), 100);
+        assert_eq!(metric.average(), 1000 / STATS_METRIC_SAMPLES as u64);
+
+        // Once every sample is filled the average is the steady rate
+        for i in 2..=(STATS_METRIC_SAMPLES as u64) {
+            metric.sample(start + Duration::from_millis(100 * i), 100 * i);
+        }
+        assert_eq!(metric.average(), 1000);
+    }
+}
@@ -0,0 +1,1049 @@
+//! The stream data type: an append-only log of field-value entries ordered
+//! by `<ms>-<seq>` IDs, as in Redis's `t_stream.c`. Entries are kept in a
+//! B-tree rather than Redis's radix tree of listpacks.
+//!
+//! Consumer groups track the last entry delivered to the group and, for each
+//! of their consumers, the entries delivered but not yet acknowledged.
+
+use byte_string::ByteString;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    ops::{Bound, RangeBounds},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parses `<ms>-<seq>`, or just `<ms>`, in which case the sequence number
+    /// is `missing_seq`
+    pub fn parse(id: &[u8], missing_seq: u64) -> Option<Self> {
+        let id = std::str::from_utf8(id).ok()?;
+
+        match id.split_once('-') {
+            Some((ms, seq)) => Some(Self::new(parse_u64(ms)?, parse_u64(seq)?)),
+            None => Some(Self::new(parse_u64(id)?, missing_seq)),
+        }
+    }
+
+    /// The smallest ID greater than this one
+    pub fn incr(self) -> Option<Self> {
+        if self.seq < u64::MAX {
+            Some(Self::new(self.ms, self.seq + 1))
+        } else if self.ms < u64::MAX {
+            Some(Self::new(self.ms + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    /// The largest ID smaller than this one
+    pub fn decr(self) -> Option<Self> {
+        if self.seq > 0 {
+            Some(Self::new(self.ms, self.seq - 1))
+        } else if self.ms > 0 {
+            Some(Self::new(self.ms - 1, u64::MAX))
+        } else {
+            None
+        }
+    }
+}
+
+/// Only plain decimal digits are accepted, without a sign
+fn parse_u64(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The ID given to XADD
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddId {
+    /// `*`: generated from the current time
+    Auto,
+    /// `<ms>-*`: the next sequence number for the given time
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+impl AddId {
+    pub fn parse(id: &[u8]) -> Option<Self> {
+        if id == b"*" {
+            return Some(Self::Auto);
+        }
+
+        if let Some(ms) = id.strip_suffix(b"-*") {
+            let ms = parse_u64(std::str::from_utf8(ms).ok()?)?;
+            return Some(Self::AutoSeq(ms));
+        }
+
+        StreamId::parse(id, 0).map(Self::Explicit)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddError {
+    /// The ID is not greater than the last one
+    TooSmall,
+    /// An explicit `0-0` ID
+    Zero,
+    /// The last ID is the greatest there can be
+    Exhausted,
+}
+
+/// How XADD and XTRIM trim a stream
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trim {
+    /// Keep at most this many entries
+    MaxLen(u64),
+    /// Evict the entries with IDs lower than this
+    MinId(StreamId),
+}
+
+/// The number of entries in each of the nodes of a Redis stream, by default,
+/// which approximate trimming works in multiples of
+pub const NODE_ENTRIES: usize = 100;
+
+/// The fields and values of an entry, interleaved
+pub type Fields = Vec<ByteString>;
+
+/// An entry delivered to a consumer but not yet acknowledged
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingEntry {
+    pub consumer: ByteString,
+    /// When it was last delivered, in milliseconds since the epoch
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Consumer {
+    /// When it last attempted to read or claim, in milliseconds since the
+    /// epoch
+    pub seen_time: u64,
+    /// When it last had entries delivered, if ever
+    pub active_time: Option<u64>,
+    pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    fn new(now: u64) -> Self {
+        Self {
+            seen_time: now,
+            active_time: None,
+            pending: BTreeSet::new(),
+        }
+    }
+
+    /// A consumer as it was saved, with the IDs of the entries pending for
+    /// it
+    pub fn restored(seen_time: u64, active_time: Option<u64>, pending: BTreeSet<StreamId>) -> Self {
+        Self {
+            seen_time,
+            active_time,
+            pending,
+        }
+    }
+
+    pub fn pending(&self) -> &BTreeSet<StreamId> {
+        &self.pending
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    last_id: StreamId,
+    /// How many entries the group has read, when it is known
+    entries_read: Option<u64>,
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<ByteString, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_id: StreamId, entries_read: Option<u64>) -> Self {
+        Self {
+            last_id,
+            entries_read,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        }
+    }
+
+    /// A group as it was saved. Each pending entry is pending for one of
+    /// the consumers.
+    pub fn restored(
+        last_id: StreamId,
+        entries_read: Option<u64>,
+        pending: BTreeMap<StreamId, PendingEntry>,
+        consumers: BTreeMap<ByteString, Consumer>,
+    ) -> Self {
+        Self {
+            last_id,
+            entries_read,
+            pending,
+            consumers,
+        }
+    }
+
+    /// The ID of the last entry delivered to the group
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn entries_read(&self) -> Option<u64> {
+        self.entries_read
+    }
+
+    pub fn set_last_id(&mut self, last_id: StreamId, entries_read: Option<u64>) {
+        self.last_id = last_id;
+        self.entries_read = entries_read;
+    }
+
+    pub fn pending(&self) -> &BTreeMap<StreamId, PendingEntry> {
+        &self.pending
+    }
+
+    pub fn consumers(&self) -> &BTreeMap<ByteString, Consumer> {
+        &self.consumers
+    }
+
+    /// Creates a consumer unless it exists, returning whether it was created
+    pub fn create_consumer(&mut self, name: &ByteString, now: u64) -> bool {
+        if self.consumers.contains_key(name) {
+            return false;
+        }
+
+        self.consumers.insert(name.clone(), Consumer::new(now));
+        true
+    }
+
+    /// Marks a consumer as seen, creating it when missing. Returns whether it
+    /// was created.
+    pub fn touch_consumer(&mut self, name: &ByteString, now: u64) -> bool {
+        let created = self.create_consumer(name, now);
+        if let Some(consumer) = self.consumers.get_mut(name) {
+            consumer.seen_time = now;
+        }
+
+        created
+    }
+
+    /// Deletes a consumer along with its pending entries, returning how many
+    /// were pending
+    pub fn delete_consumer(&mut self, name: &ByteString) -> Option<usize> {
+        let consumer = self.consumers.remove(name)?;
+        for id in &consumer.pending {
+            self.pending.remove(id);
+        }
+
+        Some(consumer.pending.len())
+    }
+
+    /// Records an entry as delivered to a consumer, which must exist, taking
+    /// it over from any consumer it was pending for
+    fn assign(
+        &mut self,
+        id: StreamId,
+        consumer: &ByteString,
+        delivery_time: u64,
+        delivery_count: u64,
+    ) {
+        let entry = PendingEntry {
+            consumer: consumer.clone(),
+            delivery_time,
+            delivery_count,
+        };
+        if let Some(previous) = self.pending.insert(id, entry) {
+            if let Some(previous) = self.consumers.get_mut(&previous.consumer) {
+                previous.pending.remove(&id);
+            }
+        }
+
+        if let Some(consumer) = self.consumers.get_mut(consumer) {
+            consumer.pending.insert(id);
+            consumer.active_time = Some(delivery_time);
+        }
+    }
+
+    /// Acknowledges an entry, returning whether it was pending
+    pub fn ack(&mut self, id: &StreamId) -> bool {
+        match self.pending.remove(id) {
+            Some(entry) => {
+                if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+                    consumer.pending.remove(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// How XCLAIM and XAUTOCLAIM claim entries
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClaimOptions {
+    /// Only entries idle for at least this long are claimed
+    pub min_idle: u64,
+    /// The delivery time to record, rather than now, for XCLAIM
+    pub delivery_time: Option<u64>,
+    /// The delivery count to record, rather than incrementing it, for XCLAIM
+    pub retry_count: Option<u64>,
+    /// Claim entries which aren't pending for any consumer, for XCLAIM
+    pub force: bool,
+    /// Leave the delivery count be, unless given
+    pub just_id: bool,
+}
+
+/// The result of XAUTOCLAIM
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AutoClaimed {
+    /// Where to continue scanning from, or `0-0` once all has been scanned
+    pub next: StreamId,
+    pub claimed: Vec<(StreamId, Fields)>,
+    /// The pending entries which no longer exist in the stream
+    pub deleted: Vec<StreamId>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    last_id: StreamId,
+    max_deleted_id: StreamId,
+    entries_added: u64,
+    groups: BTreeMap<ByteString, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A stream as it was saved, along with what is kept of the entries
+    /// since deleted
+    pub fn restored(
+        entries: BTreeMap<StreamId, Fields>,
+        last_id: StreamId,
+        max_deleted_id: StreamId,
+        entries_added: u64,
+        groups: BTreeMap<ByteString, ConsumerGroup>,
+    ) -> Self {
+        Self {
+            entries,
+            last_id,
+            max_deleted_id,
+            entries_added,
+            groups,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The entries in order
+    pub fn iter(&self) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.iter()
+    }
+
+    /// The greatest ID ever added, even if that entry has since been deleted
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// The greatest ID deleted by XDEL
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    /// The number of entries ever added
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn first_entry(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.iter().next()
+    }
+
+    pub fn last_entry(&self) -> Option<(&StreamId, &Fields)> {
+        self.entries.iter().next_back()
+    }
+
+    /// The ID of the first entry, or `0-0` when empty
+    pub fn first_id(&self) -> StreamId {
+        self.first_entry().map_or(StreamId::MIN, |(id, _)| *id)
+    }
+
+    /// Works out the ID for a new entry. `now_ms` is used for generated IDs.
+    pub fn next_id(&self, id: AddId, now_ms: u64) -> Result<StreamId, AddError> {
+        if self.last_id == StreamId::MAX {
+            return Err(AddError::Exhausted);
+        }
+
+        let id = match id {
+            AddId::Auto if now_ms > self.last_id.ms => StreamId::new(now_ms, 0),
+            AddId::Auto => self.last_id.incr().ok_or(AddError::TooSmall)?,
+            AddId::AutoSeq(ms) if ms > self.last_id.ms => StreamId::new(ms, 0),
+            AddId::AutoSeq(ms) if ms == self.last_id.ms => {
+                if self.last_id.seq == u64::MAX {
+                    return Err(AddError::TooSmall);
+                }
+                StreamId::new(ms, self.last_id.seq + 1)
+            }
+            AddId::AutoSeq(_) => return Err(AddError::TooSmall),
+            AddId::Explicit(id) if id == StreamId::MIN => return Err(AddError::Zero),
+            AddId::Explicit(id) => id,
+        };
+
+        if id <= self.last_id {
+            return Err(AddError::TooSmall);
+        }
+
+        Ok(id)
+    }
+
+    /// Appends an entry, which must have an ID from [`Stream::next_id`]
+    pub fn add(&mut self, id: StreamId, fields: Fields) {
+        debug_assert!(id > self.last_id);
+
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    pub fn delete(&mut self, id: &StreamId) -> bool {
+        if self.entries.remove(id).is_none() {
+            return false;
+        }
+
+        self.max_deleted_id = self.max_deleted_id.max(*id);
+        true
+    }
+
+    /// Evicts the oldest entries, at most `limit` of them when given, and
+    /// returns how many were evicted. Approximate trimming only evicts whole
+    /// nodes of [`NODE_ENTRIES`] entries, as Redis does.
+    pub fn trim(&mut self, trim: Trim, approx: bool, limit: Option<usize>) -> usize {
+        let mut evict = match trim {
+            Trim::MaxLen(max_len) => (self.entries.len() as u64).saturating_sub(max_len) as usize,
+            Trim::MinId(min_id) => self.entries.range(..min_id).count(),
+        };
+        if let Some(limit) = limit {
+            evict = evict.min(limit);
+        }
+        if approx {
+            evict -= evict % NODE_ENTRIES;
+        }
+
+        for _ in 0..evict {
+            self.entries.pop_first();
+        }
+
+        evict
+    }
+
+    pub fn range(
+        &self,
+        range: impl RangeBounds<StreamId>,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        // BTreeMap panics given a range which ends before it starts
+        let empty = match (range.start_bound(), range.end_bound()) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s), Bound::Excluded(e))
+            | (Bound::Excluded(s), Bound::Included(e))
+            | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+            _ => false,
+        };
+        let range = if empty {
+            (
+                Bound::Included(StreamId::MIN),
+                Bound::Excluded(StreamId::MIN),
+            )
+        } else {
+            (range.start_bound().cloned(), range.end_bound().cloned())
+        };
+
+        self.entries.range(range)
+    }
+
+    /// The entries after the given ID, as read by XREAD
+    pub fn after(&self, id: StreamId) -> impl Iterator<Item = (&StreamId, &Fields)> {
+        self.entries.range((Bound::Excluded(id), Bound::Unbounded))
+    }
+
+    pub fn groups(&self) -> &BTreeMap<ByteString, ConsumerGroup> {
+        &self.groups
+    }
+
+    pub fn group(&self, name: &ByteString) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+
+    pub fn group_mut(&mut self, name: &ByteString) -> Option<&mut ConsumerGroup> {
+        self.groups.get_mut(name)
+    }
+
+    /// Creates a group unless it exists, returning whether it was created
+    pub fn create_group(
+        &mut self,
+        name: &ByteString,
+        last_id: StreamId,
+        entries_read: Option<u64>,
+    ) -> bool {
+        if self.groups.contains_key(name) {
+            return false;
+        }
+
+        self.groups
+            .insert(name.clone(), ConsumerGroup::new(last_id, entries_read));
+        true
+    }
+
+    pub fn destroy_group(&mut self, name: &ByteString) -> bool {
+        self.groups.remove(name).is_some()
+    }
+
+    /// Whether an entry at or after `start` has been deleted, which makes a
+    /// group's count of entries read unreliable
+    fn has_tombstones(&self, start: StreamId) -> bool {
+        if self.entries.is_empty()
+            || self.max_deleted_id == StreamId::MIN
+            || self.first_id() > self.max_deleted_id
+        {
+            return false;
+        }
+
+        self.max_deleted_id >= start
+    }
+
+    /// Works out how many entries had been added up to the given ID, when
+    /// that is possible, as Redis's `streamEstimateDistanceFromFirstEverEntry`
+    fn estimate_entries_read(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if self.entries.is_empty() && id <= self.last_id {
+            return Some(self.entries_added);
+        }
+        if id == self.last_id {
+            return Some(self.entries_added);
+        }
+        if id > self.last_id {
+            return None;
+        }
+
+        let first_id = self.first_id();
+        if self.max_deleted_id == StreamId::MIN || self.max_deleted_id < first_id {
+            let len = self.entries.len() as u64;
+            if id < first_id {
+                return Some(self.entries_added - len);
+            }
+            if id == first_id {
+                return Some(self.entries_added - len + 1);
+            }
+        }
+
+        None
+    }
+
+    /// How many entries a group has yet to read, when that can be known
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+
+        let entries_read = match group.entries_read {
+            Some(entries_read) if !self.has_tombstones(group.last_id) => Some(entries_read),
+            _ => self.estimate_entries_read(group.last_id),
+        };
+
+        entries_read.map(|entries_read| self.entries_added.saturating_sub(entries_read))
+    }
+
+    /// Delivers the entries added after the last one delivered to the group,
+    /// recording them as pending for the consumer unless `no_ack` is set.
+    /// Returns `None` when there is no such group.
+    pub fn read_group(
+        &mut self,
+        group: &ByteString,
+        consumer: &ByteString,
+        count: Option<usize>,
+        no_ack: bool,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Fields)>> {
+        let (last_id, mut entries_read) = {
+            let group = self.groups.get(group)?;
+            (group.last_id, group.entries_read)
+        };
+
+        let entries: Vec<(StreamId, Fields)> = self
+            .after(last_id)
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect();
+
+        for (id, _) in &entries {
+            if entries_read.is_some() && !self.has_tombstones(*id) {
+                entries_read = entries_read.map(|n| n + 1);
+            } else if self.entries_added > 0 {
+                entries_read = self.estimate_entries_read(*id);
+            }
+        }
+
+        let group = self.groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+        if let Some((id, _)) = entries.last() {
+            group.last_id = *id;
+            group.entries_read = entries_read;
+        }
+        for (id, _) in &entries {
+            if no_ack {
+                if let Some(consumer) = group.consumers.get_mut(consumer) {
+                    consumer.active_time = Some(now);
+                }
+            } else {
+                group.assign(*id, consumer, now, 1);
+            }
+        }
+
+        Some(entries)
+    }
+
+    /// Delivers again the entries pending for the consumer after the given
+    /// ID. Entries which have since been deleted have no fields.
+    pub fn read_group_history(
+        &mut self,
+        group: &ByteString,
+        consumer: &ByteString,
+        after: StreamId,
+        count: Option<usize>,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Option<Fields>)>> {
+        let Self {
+            entries, groups, ..
+        } = self;
+        let group = groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+
+        let ids: Vec<StreamId> = group.consumers[consumer]
+            .pending
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX))
+            .copied()
+            .collect();
+
+        let mut history = Vec::with_capacity(ids.len());
+        for id in ids {
+            let fields = entries.get(&id).cloned();
+            if fields.is_some() {
+                if let Some(pending) = group.pending.get_mut(&id) {
+                    pending.delivery_time = now;
+                    pending.delivery_count += 1;
+                }
+            }
+            history.push((id, fields));
+        }
+
+        Some(history)
+    }
+
+    /// Transfers the given pending entries to a consumer, as XCLAIM does.
+    /// Entries which have since been deleted are acknowledged instead.
+    /// Returns `None` when there is no such group.
+    pub fn claim(
+        &mut self,
+        group: &ByteString,
+        consumer: &ByteString,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+        now: u64,
+    ) -> Option<Vec<(StreamId, Fields)>> {
+        let Self {
+            entries, groups, ..
+        } = self;
+        let group = groups.get_mut(group)?;
+
+        let mut claimed = vec![];
+        for id in ids {
+            let fields = match entries.get(id) {
+                Some(fields) => fields,
+                None => {
+                    group.ack(id);
+                    continue;
+                }
+            };
+
+            let delivery_count = match group.pending.get(id) {
+                Some(pending) if now.saturating_sub(pending.delivery_time) < options.min_idle => {
+                    continue
+                }
+                Some(pending) => pending.delivery_count,
+                None if options.force => 1,
+                None => continue,
+            };
+            let delivery_count = match options.retry_count {
+                Some(retry_count) => retry_count,
+                None if options.just_id => delivery_count,
+                None => delivery_count + 1,
+            };
+
+            group.touch_consumer(consumer, now);
+            group.assign(
+                *id,
+                consumer,
+                options.delivery_time.unwrap_or(now),
+                delivery_count,
+            );
+            if let Some(consumer) = group.consumers.get_mut(consumer) {
+                consumer.active_time = Some(now);
+            }
+            claimed.push((*id, fields.clone()));
+        }
+
+        Some(claimed)
+    }
+
+    /// Scans the pending entries from `start`, transferring to the consumer
+    /// at most `count` of those idle for at least `options.min_idle`, as
+    /// XAUTOCLAIM does
+    pub fn auto_claim(
+        &mut self,
+        group: &ByteString,
+        consumer: &ByteString,
+        start: StreamId,
+        count: usize,
+        options: &ClaimOptions,
+        now: u64,
+    ) -> Option<AutoClaimed> {
+        let Self {
+            entries, groups, ..
+        } = self;
+        let group = groups.get_mut(group)?;
+        group.touch_consumer(consumer, now);
+
+        let mut result = AutoClaimed::default();
+        let mut attempts = count.saturating_mul(10);
+        let mut remaining = count;
+        let candidates: Vec<(StreamId, PendingEntry)> = group
+            .pending
+            .range(start..)
+            .map(|(id, pending)| (*id, pending.clone()))
+            .collect();
+        let mut candidates = candidates.into_iter().peekable();
+
+        while remaining > 0 && attempts > 0 {
+            let (id, pending) = match candidates.next() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            attempts -= 1;
+
+            let fields = match entries.get(&id) {
+                Some(fields) => fields,
+                None => {
+                    group.ack(&id);
+                    result.deleted.push(id);
+                    continue;
+                }
+            };
+            if now.saturating_sub(pending.delivery_time) < options.min_idle {
+                continue;
+            }
+
+            let delivery_count = pending.delivery_count + u64::from(!options.just_id);
+            group.assign(id, consumer, now, delivery_count);
+            result.claimed.push((id, fields.clone()));
+            remaining -= 1;
+        }
+
+        result.next = candidates.peek().map_or(StreamId::MIN, |(id, _)| *id);
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> Fields {
+        vec!["f".into(), value.into()]
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(StreamId::parse(b"1-2", 0), Some(StreamId::new(1, 2)));
+        assert_eq!(StreamId::parse(b"5", 0), Some(StreamId::new(5, 0)));
+        assert_eq!(
+            StreamId::parse(b"5", u64::MAX),
+            Some(StreamId::new(5, u64::MAX))
+        );
+        assert_eq!(StreamId::parse(b"1-", 0), None);
+        assert_eq!(StreamId::parse(b"-1", 0), None);
+        assert_eq!(StreamId::parse(b"+1-2", 0), None);
+        assert_eq!(StreamId::parse(b"a-b", 0), None);
+
+        assert_eq!(AddId::parse(b"*"), Some(AddId::Auto));
+        assert_eq!(AddId::parse(b"7-*"), Some(AddId::AutoSeq(7)));
+        assert_eq!(
+            AddId::parse(b"7-1"),
+            Some(AddId::Explicit(StreamId::new(7, 1)))
+        );
+    }
+
+    #[test]
+    fn test_next_id() {
+        let mut stream = Stream::new();
+
+        assert_eq!(stream.next_id(AddId::Auto, 10), Ok(StreamId::new(10, 0)));
+        assert_eq!(
+            stream.next_id(AddId::AutoSeq(0), 10),
+            Ok(StreamId::new(0, 1))
+        );
+        assert_eq!(
+            stream.next_id(AddId::Explicit(StreamId::MIN), 10),
+            Err(AddError::Zero)
+        );
+
+        stream.add(StreamId::new(10, 5), fields("a"));
+
+        // The clock going backwards still gives increasing IDs
+        assert_eq!(stream.next_id(AddId::Auto, 9), Ok(StreamId::new(10, 6)));
+        assert_eq!(
+            stream.next_id(AddId::AutoSeq(10), 0),
+            Ok(StreamId::new(10, 6))
+        );
+        assert_eq!(
+            stream.next_id(AddId::AutoSeq(9), 0),
+            Err(AddError::TooSmall)
+        );
+        assert_eq!(
+            stream.next_id(AddId::Explicit(StreamId::new(10, 5)), 0),
+            Err(AddError::TooSmall)
+        );
+
+        stream.add(StreamId::MAX, fields("b"));
+        assert_eq!(stream.next_id(AddId::Auto, 0), Err(AddError::Exhausted));
+        assert_eq!(
+            stream.next_id(AddId::Explicit(StreamId::new(1, 0)), 0),
+            Err(AddError::Exhausted)
+        );
+    }
+
+    #[test]
+    fn test_range_and_delete() {
+        let mut stream = Stream::new();
+        for i in 1..=5 {
+            stream.add(StreamId::new(i, 0), fields(&i.to_string()));
+        }
+
+        let ids: Vec<u64> = stream
+            .range(StreamId::new(2, 0)..=StreamId::new(4, 0))
+            .map(|(id, _)| id.ms)
+            .collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert_eq!(
+            stream
+                .range(StreamId::new(4, 0)..=StreamId::new(2, 0))
+                .count(),
+            0
+        );
+
+        assert!(stream.delete(&StreamId::new(3, 0)));
+        assert!(!stream.delete(&StreamId::new(3, 0)));
+
+        let ids: Vec<u64> = stream
+            .after(StreamId::new(2, 0))
+            .map(|(id, _)| id.ms)
+            .collect();
+        assert_eq!(ids, vec![4, 5]);
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut stream = Stream::new();
+        for i in 1..=10 {
+            stream.add(StreamId::new(i, 0), fields("x"));
+        }
+
+        assert_eq!(stream.trim(Trim::MaxLen(8), false, None), 2);
+        assert_eq!(
+            stream.trim(Trim::MinId(StreamId::new(6, 0)), false, Some(1)),
+            1
+        );
+        assert_eq!(stream.range(..).next().map(|(id, _)| id.ms), Some(4));
+        assert_eq!(
+            stream.trim(Trim::MinId(StreamId::new(6, 0)), false, None),
+            2
+        );
+        assert_eq!(stream.len(), 5);
+        assert_eq!(stream.last_id(), StreamId::new(10, 0));
+    }
+
+    #[test]
+    fn test_trim_approx() {
+        let mut stream = Stream::new();
+        for i in 1..=250 {
+            stream.add(StreamId::new(i, 0), fields("x"));
+        }
+
+        assert_eq!(stream.trim(Trim::MaxLen(10), true, None), 200);
+        assert_eq!(stream.trim(Trim::MaxLen(10), true, None), 0);
+        assert_eq!(stream.len(), 50);
+        assert_eq!(stream.trim(Trim::MaxLen(10), false, None), 40);
+    }
+
+    fn stream_with_group(n: u64) -> Stream {
+        let mut stream = Stream::new();
+        for i in 1..=n {
+            stream.add(StreamId::new(i, 0), fields(&i.to_string()));
+        }
+        stream.create_group(&"g".into(), StreamId::MIN, None);
+        stream
+    }
+
+    #[test]
+    fn test_read_group_and_ack() {
+        let mut stream = stream_with_group(3);
+        let (g, alice, bob) = ("g".into(), "alice".into(), "bob".into());
+
+        let read = stream.read_group(&g, &alice, Some(2), false, 100).unwrap();
+        assert_eq!(read.len(), 2);
+        let read = stream.read_group(&g, &bob, None, false, 100).unwrap();
+        assert_eq!(read, vec![(StreamId::new(3, 0), fields("3"))]);
+        assert!(stream
+            .read_group(&g, &bob, None, false, 100)
+            .unwrap()
+            .is_empty());
+        assert!(stream
+            .read_group(&"x".into(), &bob, None, false, 100)
+            .is_none());
+
+        let group = stream.group(&g).unwrap();
+        assert_eq!(group.last_id(), StreamId::new(3, 0));
+        assert_eq!(group.entries_read(), Some(3));
+        assert_eq!(group.pending().len(), 3);
+        assert_eq!(group.consumers()[&alice].pending().len(), 2);
+
+        // Reading the history delivers the entries again
+        let history = stream
+            .read_group_history(&g, &alice, StreamId::MIN, None, 200)
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        let group = stream.group_mut(&g).unwrap();
+        assert_eq!(group.pending()[&StreamId::new(1, 0)].delivery_count, 2);
+
+        assert!(group.ack(&StreamId::new(1, 0)));
+        assert!(!group.ack(&StreamId::new(1, 0)));
+        assert_eq!(group.consumers()[&alice].pending().len(), 1);
+        assert_eq!(group.delete_consumer(&alice), Some(1));
+        assert_eq!(group.pending().len(), 1);
+    }
+
+    #[test]
+    fn test_claim() {
+        let mut stream = stream_with_group(3);
+        let (g, alice, bob) = ("g".into(), "alice".into(), "bob".into());
+        stream.read_group(&g, &alice, None, false, 100).unwrap();
+        stream.delete(&StreamId::new(2, 0));
+
+        let ids = [StreamId::new(1, 0), StreamId::new(2, 0)];
+        let options = ClaimOptions {
+            min_idle: 50,
+            ..ClaimOptions::default()
+        };
+        assert!(stream
+            .claim(&g, &bob, &ids, &options, 120)
+            .unwrap()
+            .is_empty());
+
+        let claimed = stream.claim(&g, &bob, &ids, &options, 150).unwrap();
+        assert_eq!(claimed, vec![(StreamId::new(1, 0), fields("1"))]);
+
+        // The deleted entry is no longer pending
+        let group = stream.group(&g).unwrap();
+        assert_eq!(group.pending().len(), 2);
+        assert_eq!(
+            group.pending()[&StreamId::new(1, 0)],
+            PendingEntry {
+                consumer: bob.clone(),
+                delivery_time: 150,
+                delivery_count: 2,
+            }
+        );
+        assert!(group.consumers()[&alice]
+            .pending()
+            .contains(&StreamId::new(3, 0)));
+        assert!(!group.consumers()[&alice]
+            .pending()
+            .contains(&StreamId::new(1, 0)));
+    }
+
+    #[test]
+    fn test_auto_claim() {
+        let mut stream = stream_with_group(4);
+        let (g, alice, bob) = ("g".into(), "alice".into(), "bob".into());
+        stream.read_group(&g, &alice, None, false, 100).unwrap();
+        stream.delete(&StreamId::new(1, 0));
+
+        let options = ClaimOptions::default();
+        let claimed = stream
+            .auto_claim(&g, &bob, StreamId::MIN, 2, &options, 200)
+            .unwrap();
+        assert_eq!(claimed.next, StreamId::new(4, 0));
+        assert_eq!(claimed.deleted, vec![StreamId::new(1, 0)]);
+        assert_eq!(
+            claimed
+                .claimed
+                .iter()
+                .map(|(id, _)| id.ms)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        let claimed = stream
+            .auto_claim(&g, &bob, claimed.next, 2, &options, 200)
+            .unwrap();
+        assert_eq!(claimed.next, StreamId::MIN);
+        assert_eq!(claimed.claimed.len(), 1);
+    }
+
+    #[test]
+    fn test_lag() {
+        let mut stream = stream_with_group(5);
+        let g = "g".into();
+
+        assert_eq!(stream.lag(stream.group(&g).unwrap()), Some(5));
+        stream
+            .read_group(&g, &"c".into(), Some(2), true, 0)
+            .unwrap();
+        assert_eq!(stream.lag(stream.group(&g).unwrap()), Some(3));
+
+        // Deleting an entry yet to be read leaves the lag unknown
+        stream.delete(&StreamId::new(4, 0));
+        assert_eq!(stream.lag(stream.group(&g).unwrap()), None);
+
+        stream.read_group(&g, &"c".into(), None, true, 0).unwrap();
+        assert_eq!(stream.lag(stream.group(&g).unwrap()), Some(0));
+    }
+}
@@ -0,0 +1,410 @@
+//! TLS for client connections accepted on `tls-port`. As in Redis the
+//! certificates and keys are read from PEM files, and clients may be required
+//! to present a certificate signed by the configured CA.
+
+use crate::{config::Config, errors::Result};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use std::{path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        server::{danger::ClientCertVerifier, WebPkiClientVerifier},
+        version, RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+        ALL_VERSIONS,
+    },
+    TlsAcceptor,
+};
+
+/// Whether clients must present a certificate, see `tls-auth-clients`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsAuthClients {
+    No,
+    Yes,
+    /// A certificate is verified if one is given, but isn't required
+    Optional,
+}
+
+impl TlsAuthClients {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "no" => Some(Self::No),
+            "yes" => Some(Self::Yes),
+            "optional" => Some(Self::Optional),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::No => "no",
+            Self::Yes => "yes",
+            Self::Optional => "optional",
+        }
+    }
+}
+
+/// The versions of TLS that may be enabled by `tls-protocols`. The older
+/// versions Redis accepts aren't supported by rustls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TlsProtocol {
+    Tls12,
+    Tls13,
+}
+
+impl TlsProtocol {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "tlsv1.2" => Some(Self::Tls12),
+            "tlsv1.3" => Some(Self::Tls13),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tls12 => "TLSv1.2",
+            Self::Tls13 => "TLSv1.3",
+        }
+    }
+
+    fn version(self) -> &'static SupportedProtocolVersion {
+        match self {
+            Self::Tls12 => &version::TLS12,
+            Self::Tls13 => &version::TLS13,
+        }
+    }
+}
+
+/// Looks up a cipher suite by its OpenSSL name, which is how `tls-ciphers`
+/// and `tls-ciphersuites` name them in Redis
+pub fn cipher_suite(name: &str) -> Option<SupportedCipherSuite> {
+    use ring::cipher_suite::*;
+
+    let suite = match name {
+        "TLS_AES_256_GCM_SHA384" => TLS13_AES_256_GCM_SHA384,
+        "TLS_AES_128_GCM_SHA256" => TLS13_AES_128_GCM_SHA256,
+        "TLS_CHACHA20_POLY1305_SHA256" => TLS13_CHACHA20_POLY1305_SHA256,
+        "ECDHE-ECDSA-AES256-GCM-SHA384" => TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+        "ECDHE-ECDSA-AES128-GCM-SHA256" => TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        "ECDHE-ECDSA-CHACHA20-POLY1305" => TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        "ECDHE-RSA-AES256-GCM-SHA384" => TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+        "ECDHE-RSA-AES128-GCM-SHA256" => TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+        "ECDHE-RSA-CHACHA20-POLY1305" => TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        _ => return None,
+    };
+    Some(suite)
+}
+
+/// Builds the acceptor for TLS connections from the `tls-*` parameters
+pub fn acceptor(config: &Config) -> Result<TlsAcceptor> {
+    let cert_file = config
+        .tls_cert_file
+        .as_deref()
+        .ok_or("No tls-cert-file configured")?;
+    let key_file = config
+        .tls_key_file
+        .as_deref()
+        .ok_or("No tls-key-file configured")?;
+
+    let certs = load_certs(cert_file)?;
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .map_err(|e| format!("Failed to load private key: {}: {}", key_file.display(), e))?;
+
+    let provider = Arc::new(crypto_provider(config));
+    let versions: Vec<_> = if config.tls_protocols.is_empty() {
+        ALL_VERSIONS.to_vec()
+    } else {
+        config.tls_protocols.iter().map(|p| p.version()).collect()
+    };
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&versions)
+        .map_err(|e| format!("Failed to configure TLS: {}", e))?;
+
+    let builder = match client_verifier(config, provider)? {
+        Some(verifier) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+
+    let server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Failed to configure TLS: {}", e))?;
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// Verifies client certificates against `tls-ca-cert-file`, unless clients
+/// aren't authenticated
+fn client_verifier(
+    config: &Config,
+    provider: Arc<CryptoProvider>,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>> {
+    if config.tls_auth_clients == TlsAuthClients::No {
+        return Ok(None);
+    }
+
+    let ca_cert_file = config
+        .tls_ca_cert_file
+        .as_deref()
+        .ok_or("tls-ca-cert-file must be specified when tls-auth-clients is 'yes' or 'optional'")?;
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_cert_file)? {
+        roots.add(cert).map_err(|e| {
+            format!(
+                "Failed to load CA certificate: {}: {}",
+                ca_cert_file.display(),
+                e
+            )
+        })?;
+    }
+
+    let mut verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    if config.tls_auth_clients == TlsAuthClients::Optional {
+        verifier = verifier.allow_unauthenticated();
+    }
+    let verifier = verifier
+        .build()
+        .map_err(|e| format!("Failed to configure TLS: {}", e))?;
+
+    Ok(Some(verifier))
+}
+
+/// The default provider with its cipher suites narrowed to those given by
+/// `tls-ciphers` for TLSv1.2 and `tls-ciphersuites` for TLSv1.3, where set
+fn crypto_provider(config: &Config) -> CryptoProvider {
+    let mut provider = ring::default_provider();
+
+    provider.cipher_suites.retain(|suite| {
+        let names = match suite {
+            SupportedCipherSuite::Tls12(_) => &config.tls_ciphers,
+            SupportedCipherSuite::Tls13(_) => &config.tls_ciphersuites,
+        };
+        names.is_empty()
+            || names
+                .iter()
+                .filter_map(|name| cipher_suite(name))
+                .any(|s| s.suite() == suite.suite())
+    });
+
+    provider
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to load certificate: {}: {}", path.display(), e))?;
+
+    if certs.is_empty() {
+        return Err(format!(
+            "Failed to load certificate: {}: no certificates found",
+            path.display()
+        )
+        .into());
+    }
+
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{CertificateParams, CertifiedKey, KeyPair};
+    use std::{
+        convert::TryFrom,
+        fs,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig},
+        TlsConnector,
+    };
+
+    /// A directory for a test's certificates and keys, removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "redis-clone-tls-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.0.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A self-signed certificate for localhost, which also serves as its
+    /// own CA
+    fn self_signed() -> CertifiedKey {
+        rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap()
+    }
+
+    /// A client certificate signed by `ca`
+    fn signed_by(ca: &CertifiedKey) -> CertifiedKey {
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["client".to_owned()])
+            .unwrap()
+            .signed_by(&key_pair, &ca.cert, &ca.key_pair)
+            .unwrap();
+        CertifiedKey { cert, key_pair }
+    }
+
+    fn server_config(dir: &TestDir, server: &CertifiedKey, extra: &str) -> Config {
+        let cert_file = dir.write("server.crt", &server.cert.pem());
+        let key_file = dir.write("server.key", &server.key_pair.serialize_pem());
+        let args = format!(
+            "--tls-port 6380 --tls-cert-file {} --tls-key-file {} --tls-ca-cert-file {} {}",
+            cert_file.display(),
+            key_file.display(),
+            cert_file.display(),
+            extra
+        );
+        Config::from_args(args.split_whitespace().map(str::to_owned)).unwrap()
+    }
+
+    fn connector(server: &CertifiedKey, client: Option<&CertifiedKey>) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots.add(server.cert.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+
+        let config = match client {
+            Some(client) => builder
+                .with_client_auth_cert(
+                    vec![client.cert.der().clone()],
+                    PrivateKeyDer::try_from(client.key_pair.serialize_der()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// Runs a handshake between the acceptor and connector, then echoes a
+    /// message back from the server. Returns the version of TLS agreed.
+    async fn echo(acceptor: TlsAcceptor, connector: TlsConnector) -> io::Result<String> {
+        let (client_io, server_io) = io::duplex(64 * 1024);
+
+        let server = tokio::spawn(async move {
+            let mut stream = acceptor.accept(server_io).await?;
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await?;
+            stream.write_all(&buf).await?;
+            stream.flush().await?;
+            io::Result::Ok(())
+        });
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, client_io).await?;
+        stream.write_all(b"PING").await?;
+        stream.flush().await?;
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(&buf, b"PING");
+        server.await.unwrap()?;
+
+        let version = stream.get_ref().1.protocol_version().unwrap();
+        Ok(format!("{:?}", version))
+    }
+
+    #[test]
+    fn test_cipher_suite() {
+        assert!(matches!(
+            cipher_suite("TLS_AES_128_GCM_SHA256"),
+            Some(SupportedCipherSuite::Tls13(_))
+        ));
+        assert!(matches!(
+            cipher_suite("ECDHE-RSA-AES128-GCM-SHA256"),
+            Some(SupportedCipherSuite::Tls12(_))
+        ));
+        assert!(cipher_suite("DES-CBC3-SHA").is_none());
+    }
+
+    #[test]
+    fn test_acceptor_requires_cert_and_key() {
+        let err = acceptor(&Config::default()).err().unwrap();
+        assert_eq!(err.to_string(), "No tls-cert-file configured");
+
+        let dir = TestDir::new();
+        let mut config = server_config(&dir, &self_signed(), "");
+        config.tls_key_file = Some(dir.0.join("missing.key"));
+        let err = acceptor(&config).err().unwrap();
+        assert!(err.to_string().starts_with("Failed to load private key"));
+    }
+
+    #[test]
+    fn test_acceptor_requires_ca_to_auth_clients() {
+        let dir = TestDir::new();
+        let mut config = server_config(&dir, &self_signed(), "");
+        config.tls_ca_cert_file = None;
+        assert!(acceptor(&config).is_err());
+
+        config.tls_auth_clients = TlsAuthClients::No;
+        assert!(acceptor(&config).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let dir = TestDir::new();
+        let server = self_signed();
+        let config = server_config(&dir, &server, "--tls-auth-clients no");
+
+        let version = echo(acceptor(&config).unwrap(), connector(&server, None)).await;
+        assert_eq!(version.unwrap(), "TLSv1_3");
+    }
+
+    #[tokio::test]
+    async fn test_protocols() {
+        let dir = TestDir::new();
+        let server = self_signed();
+        let config = server_config(
+            &dir,
+            &server,
+            "--tls-auth-clients no --tls-protocols TLSv1.2 --tls-ciphers ECDHE-ECDSA-AES128-GCM-SHA256",
+        );
+
+        let version = echo(acceptor(&config).unwrap(), connector(&server, None)).await;
+        assert_eq!(version.unwrap(), "TLSv1_2");
+    }
+
+    #[tokio::test]
+    async fn test_auth_clients() {
+        let dir = TestDir::new();
+        let server = self_signed();
+        let client = signed_by(&server);
+        let config = server_config(&dir, &server, "");
+
+        let acceptor = acceptor(&config).unwrap();
+        assert!(echo(acceptor.clone(), connector(&server, Some(&client)))
+            .await
+            .is_ok());
+        assert!(echo(acceptor, connector(&server, None)).await.is_err());
+
+        // Any certificate given must still be valid
+        let config = server_config(&dir, &server, "--tls-auth-clients optional");
+        let acceptor = super::acceptor(&config).unwrap();
+        assert!(echo(acceptor.clone(), connector(&server, None))
+            .await
+            .is_ok());
+        assert!(echo(acceptor, connector(&server, Some(&self_signed())))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,234 @@
+//! Shared by the integration tests. Each test starts a server of its own
+//! in-process on an ephemeral port and drives it over TCP. When
+//! `TEST_REAL_REDIS` is set the tests are run against that Redis instead,
+//! at `127.0.0.1:6379` unless it is set to another address, to check the
+//! clone behaves the same.
+
+#![allow(dead_code)]
+
+use redis_clone::{
+    config::Config,
+    server::{ServerBuilder, ServerHandle},
+};
+use redis_clone_client::{cmd, Connection, Error, Result, Value};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Debug,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+pub const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+/// Tests share the one database of a real Redis, so only one runs at a time
+static REAL_REDIS: Mutex<()> = Mutex::new(());
+
+/// A directory of its own for a server's RDB file, removed once the last
+/// server using it has stopped
+struct DataDir(PathBuf);
+
+impl DataDir {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "redis-clone-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for DataDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub struct TestServer {
+    server: Option<ServerHandle>,
+    addr: String,
+    /// Parameters changed by the test, with the values to restore them to
+    config: Vec<(String, String)>,
+    /// What the clone was started with, to restart it
+    started_with: Option<(Config, Arc<DataDir>)>,
+    _exclusive: Option<MutexGuard<'static, ()>>,
+}
+
+impl TestServer {
+    pub async fn start() -> Self {
+        Self::start_with(Config::default()).await
+    }
+
+    /// Starts the clone with the config given, saving to a directory of its
+    /// own. A real Redis is used as it is configured.
+    pub async fn start_with(config: Config) -> Self {
+        if let Some(addr) = real_redis_addr() {
+            let exclusive = REAL_REDIS.lock().unwrap_or_else(|err| err.into_inner());
+            let server = Self {
+                server: None,
+                addr,
+                config: vec![],
+                started_with: None,
+                _exclusive: Some(exclusive),
+            };
+            server.connect().await.flushdb().await.unwrap();
+            return server;
+        }
+
+        Self::start_in(config, Arc::new(DataDir::new())).await
+    }
+
+    async fn start_in(mut config: Config, dir: Arc<DataDir>) -> Self {
+        config.dir = dir.0.clone();
+        let server = ServerBuilder::new(config.clone())
+            .ephemeral_port()
+            .start()
+            .await
+            .expect("server failed to start");
+        let addr = server
+            .local_addr()
+            .expect("server has a TCP listener")
+            .to_string();
+
+        Self {
+            server: Some(server),
+            addr,
+            config: vec![],
+            started_with: Some((config, dir)),
+            _exclusive: None,
+        }
+    }
+
+    /// Shuts the clone down with SHUTDOWN, which saves it as configured, and
+    /// starts it again. Only the clone can be restarted.
+    pub async fn restart(self) -> Self {
+        let mut conn = self.connect().await;
+        let server = self.server.expect("only the clone can be restarted");
+        let (config, dir) = self.started_with.expect("the clone's config is kept");
+
+        // The connection is closed rather than answered
+        let _ = cmd("SHUTDOWN").query::<()>(&mut conn).await;
+        server.stopped().await.unwrap();
+
+        Self::start_in(config, dir).await
+    }
+
+    pub fn is_real_redis(&self) -> bool {
+        self.server.is_none()
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub async fn connect(&self) -> Connection {
+        Connection::connect(self.addr.as_str())
+            .await
+            .expect("failed to connect")
+    }
+
+    /// Remembers the values of config parameters the test is going to
+    /// change, so that `stop` can put them back
+    pub async fn preserve_config(&mut self, parameters: &[&str]) {
+        let mut conn = self.connect().await;
+        for parameter in parameters {
+            let value = config_get(&mut conn, parameter).await;
+            self.config.push(((*parameter).to_owned(), value));
+        }
+    }
+
+    /// Shuts the clone down, or restores the config of a real Redis and
+    /// empties it
+    pub async fn stop(self) {
+        match self.server {
+            Some(server) => server.shutdown().await.unwrap(),
+            None => {
+                let mut conn = self.connect().await;
+                for (parameter, value) in &self.config {
+                    conn.config_set(parameter, value).await.unwrap();
+                }
+                conn.flushdb().await.unwrap();
+            }
+        }
+    }
+}
+
+/// The address of the real Redis to test against, if any
+fn real_redis_addr() -> Option<String> {
+    let addr = env::var("TEST_REAL_REDIS").ok()?;
+    if addr.contains(':') {
+        Some(addr)
+    } else {
+        Some("127.0.0.1:6379".to_owned())
+    }
+}
+
+/// Asserts the result is the error reply given
+#[track_caller]
+pub fn assert_error<T: Debug>(result: Result<T>, expected: &str) {
+    match result {
+        Err(Error::Server(msg)) => assert_eq!(msg, expected),
+        other => panic!("expected the error {:?}, got {:?}", expected, other),
+    }
+}
+
+/// Asserts the result is an error reply starting with the text given
+#[track_caller]
+pub fn assert_error_starts_with<T: Debug>(result: Result<T>, expected: &str) {
+    match result {
+        Err(Error::Server(msg)) => assert!(
+            msg.starts_with(expected),
+            "expected an error starting with {:?}, got {:?}",
+            expected,
+            msg
+        ),
+        other => panic!("expected an error starting {:?}, got {:?}", expected, other),
+    }
+}
+
+/// The arity of a command as reported by COMMAND INFO
+pub async fn command_arity(conn: &mut Connection, name: &str) -> i64 {
+    let info: Vec<Vec<Value>> = cmd("COMMAND")
+        .arg("INFO")
+        .arg(name)
+        .query(conn)
+        .await
+        .unwrap();
+    match info[0][1] {
+        Value::Integer(arity) => arity,
+        ref other => panic!("expected an arity, got {:?}", other),
+    }
+}
+
+pub async fn object_encoding(conn: &mut Connection, key: &str) -> Option<String> {
+    cmd("OBJECT")
+        .arg("ENCODING")
+        .arg(key)
+        .query(conn)
+        .await
+        .unwrap()
+}
+
+pub async fn config_get(conn: &mut Connection, parameter: &str) -> String {
+    let mut config: HashMap<String, String> = conn.config_get(parameter).await.unwrap();
+    config
+        .remove(parameter)
+        .unwrap_or_else(|| panic!("no such parameter {}", parameter))
+}
+
+/// The fields of a section of INFO, or of its default sections
+pub async fn info(conn: &mut Connection, section: Option<&str>) -> HashMap<String, String> {
+    let text = conn.info(section.as_slice()).await.unwrap();
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
@@ -0,0 +1,169 @@
+RSpec.describe "Bitmap commands", include_connection: true do
+  describe "arity" do
+    specify "the arity for each command is correctly specified" do
+      expect(redis.command("info", "setbit").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "getbit").dig(0, 1)).to eql(3)
+      expect(redis.command("info", "bitcount").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "bitpos").dig(0, 1)).to eql(-3)
+      expect(redis.command("info", "bitop").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "bitfield").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "bitfield_ro").dig(0, 1)).to eql(-2)
+    end
+  end
+
+  describe "commands used against the wrong type" do
+    specify "raise an error" do
+      redis.rpush("x", 1)
+
+      expected_error = "WRONGTYPE Operation against a key holding the wrong kind of value"
+      expect { redis.setbit("x", 0, 1) }.to raise_error(expected_error)
+      expect { redis.getbit("x", 0) }.to raise_error(expected_error)
+      expect { redis.bitcount("x") }.to raise_error(expected_error)
+      expect { redis.bitpos("x", 1) }.to raise_error(expected_error)
+      expect { redis.bitop("and", "y", "x") }.to raise_error(expected_error)
+      expect { redis.bitfield("x", "get", "i8", 0) }.to raise_error(expected_error)
+    end
+  end
+
+  describe "SETBIT" do
+    it "grows the string and returns the old bit" do
+      expect(redis.setbit("x", 7, 1)).to eql(0)
+      expect(redis.setbit("x", 7, 0)).to eql(1)
+      expect(redis.get("x")).to eql("\x00")
+    end
+
+    it "works on integers" do
+      redis.set("x", 12)
+
+      expect(redis.setbit("x", 6, 1)).to eql(0)
+      expect(redis.get("x")).to eql("32")
+      expect(redis.incr("x")).to eql(33)
+    end
+
+    it "rejects invalid offsets and bits" do
+      expect { redis.setbit("x", -1, 1) }
+        .to raise_error("ERR bit offset is not an integer or out of range")
+      expect { redis.setbit("x", 4_294_967_296, 1) }
+        .to raise_error("ERR bit offset is not an integer or out of range")
+      expect { redis.setbit("x", 0, 2) }
+        .to raise_error("ERR bit is not an integer or out of range")
+    end
+  end
+
+  describe "GETBIT" do
+    it "returns 0 past the end of the string or for a missing key" do
+      redis.set("x", "\x80")
+
+      expect(redis.getbit("x", 0)).to eql(1)
+      expect(redis.getbit("x", 100)).to eql(0)
+      expect(redis.getbit("y", 0)).to eql(0)
+    end
+  end
+
+  describe "BITCOUNT" do
+    before { redis.set("x", "foobar") }
+
+    it "counts the set bits in byte or bit ranges" do
+      expect(redis.bitcount("x")).to eql(26)
+      expect(redis.bitcount("x", 0, 0)).to eql(4)
+      expect(redis.bitcount("x", 1, 1)).to eql(6)
+      expect(redis.call("bitcount", "x", 1, 1, "byte")).to eql(6)
+      expect(redis.call("bitcount", "x", 5, 30, "bit")).to eql(17)
+      expect(redis.bitcount("x", -2, -1)).to eql(7)
+      expect(redis.bitcount("missing")).to eql(0)
+    end
+
+    it "rejects incomplete ranges" do
+      expect { redis.call("bitcount", "x", 0) }.to raise_error("ERR syntax error")
+      expect { redis.call("bitcount", "x", 0, 1, "nibble") }.to raise_error("ERR syntax error")
+    end
+  end
+
+  describe "BITPOS" do
+    it "finds the first set or clear bit" do
+      redis.set("x", "\xff\xf0\x00")
+
+      expect(redis.bitpos("x", 0)).to eql(12)
+      expect(redis.bitpos("x", 1)).to eql(0)
+      expect(redis.bitpos("x", 0, 2, -1)).to eql(16)
+      expect(redis.call("bitpos", "x", 0, 7, 15, "bit")).to eql(12)
+    end
+
+    it "treats the string as padded with clear bits unless an end is given" do
+      redis.set("x", "\xff\xff")
+
+      expect(redis.bitpos("x", 0)).to eql(16)
+      expect(redis.bitpos("x", 0, 0, -1)).to eql(-1)
+    end
+
+    it "handles missing keys and invalid bits" do
+      expect(redis.bitpos("x", 0)).to eql(0)
+      expect(redis.bitpos("x", 1)).to eql(-1)
+      expect { redis.bitpos("x", 2) }.to raise_error("ERR The bit argument must be 1 or 0.")
+    end
+  end
+
+  describe "BITOP" do
+    before do
+      redis.set("a", "abc")
+      redis.set("b", "a")
+    end
+
+    it "combines the strings padded with zeroes" do
+      expect(redis.bitop("and", "d", "a", "b")).to eql(3)
+      expect(redis.get("d")).to eql("a\x00\x00")
+      expect(redis.bitop("or", "d", "a", "b")).to eql(3)
+      expect(redis.get("d")).to eql("abc")
+      expect(redis.bitop("xor", "d", "a", "b")).to eql(3)
+      expect(redis.get("d")).to eql("\x00bc")
+      expect(redis.bitop("not", "d", "a")).to eql(3)
+      expect(redis.get("d").b).to eql("\x9e\x9d\x9c".b)
+    end
+
+    it "deletes the destination when the result is empty" do
+      expect(redis.bitop("and", "a", "x", "y")).to eql(0)
+      expect(redis.exists?("a")).to be(false)
+    end
+
+    it "rejects NOT with several keys" do
+      expect { redis.bitop("not", "d", "a", "b") }
+        .to raise_error("ERR BITOP NOT must be called with a single source key.")
+    end
+  end
+
+  describe "BITFIELD" do
+    it "gets, sets and increments fields handling overflows" do
+      expect(
+        redis.bitfield(
+          "x",
+          "set", "i8", 0, 100,
+          "get", "i8", 0,
+          "incrby", "i8", 0, 100,
+          "overflow", "sat", "incrby", "i8", 0, 100,
+          "overflow", "fail", "incrby", "i8", 0, 100,
+          "get", "u4", "#1"
+        )
+      ).to eql([0, 100, -56, 44, nil, 12])
+    end
+
+    it "doesn't create the key for GETs" do
+      expect(redis.bitfield("x", "get", "u8", 0)).to eql([0])
+      expect(redis.exists?("x")).to be(false)
+    end
+
+    it "rejects invalid types" do
+      expect { redis.bitfield("x", "get", "u64", 0) }
+        .to raise_error(/ERR Invalid bitfield type/)
+    end
+  end
+
+  describe "BITFIELD_RO" do
+    it "only supports GET" do
+      redis.bitfield("x", "set", "u8", 0, 255)
+
+      expect(redis.call("bitfield_ro", "x", "get", "u8", 0)).to eql([255])
+      expect { redis.call("bitfield_ro", "x", "set", "u8", 0, 1) }
+        .to raise_error("ERR BITFIELD_RO only supports the GET subcommand")
+    end
+  end
+end
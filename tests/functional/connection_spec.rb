@@ -0,0 +1,77 @@
+RSpec.describe "Connection commands", include_connection: true do
+  describe "CLIENT" do
+    let(:other) { Redis.new(port: port) }
+
+    after(:example) { other.close }
+
+    describe "ID" do
+      it "returns a unique id for each connection" do
+        id = redis.client("id")
+        expect(id).to be > 0
+        expect(other.client("id")).not_to eql(id)
+      end
+    end
+
+    describe "SETNAME and GETNAME" do
+      it "names the connection" do
+        expect(redis.client("getname")).to be_nil
+        expect(redis.client("setname", "worker")).to eql("OK")
+        expect(redis.client("getname")).to eql("worker")
+      end
+
+      it "rejects names with spaces" do
+        expect { redis.client("setname", "a b") }
+          .to raise_error(/^ERR Client names cannot contain spaces/)
+      end
+    end
+
+    describe "LIST" do
+      it "lists every connection" do
+        redis.client("setname", "lister")
+        other.client("setname", "other")
+
+        list = redis.client("list")
+        expect(list).to match(/name=lister/)
+        expect(list).to match(/name=other/)
+      end
+
+      it "filters by id" do
+        id = other.client("id")
+        list = redis.client("list", "id", id).lines
+
+        expect(list.count).to eql(1)
+        expect(list[0]).to start_with("id=#{id} ")
+      end
+    end
+
+    describe "INFO" do
+      it "describes the current connection" do
+        id = redis.client("id")
+        expect(redis.client("info")).to match(/^id=#{id} .* cmd=client/)
+      end
+    end
+
+    describe "KILL" do
+      it "closes connections matching the filters" do
+        id = other.client("id")
+        expect(redis.client("kill", "id", id)).to eql(1)
+      end
+
+      it "skips the calling connection by default" do
+        id = redis.client("id")
+        expect(redis.client("kill", "id", id)).to eql(0)
+      end
+    end
+
+    describe "PAUSE" do
+      after(:example) { redis.client("unpause") }
+
+      it "delays write commands" do
+        expect(redis.client("pause", 200, "write")).to eql("OK")
+        started = Time.now
+        other.set("x", "1")
+        expect(Time.now - started).to be >= 0.15
+      end
+    end
+  end
+end
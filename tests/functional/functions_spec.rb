@@ -0,0 +1,119 @@
+RSpec.describe "Function commands", include_connection: true do
+  let(:library) do
+    <<~LUA
+      #!lua name=mylib
+      redis.register_function('echo', function(keys, args) return args[1] end)
+      redis.register_function{
+        function_name = 'getkey',
+        callback = function(keys) return redis.call('get', keys[1]) end,
+        flags = { 'no-writes' },
+        description = 'Gets a key',
+      }
+      redis.register_function('setkey', function(keys, args) return redis.call('set', keys[1], args[1]) end)
+    LUA
+  end
+
+  before(:example) { redis.call("function", "flush") }
+  after(:example) { redis.call("function", "flush") }
+
+  describe "FUNCTION LOAD" do
+    it "returns the library name" do
+      expect(redis.call("function", "load", library)).to eql("mylib")
+    end
+
+    it "refuses to load a library twice unless replacing it" do
+      redis.call("function", "load", library)
+
+      expect { redis.call("function", "load", library) }
+        .to raise_error(Redis::CommandError, "ERR Library 'mylib' already exists")
+      expect(redis.call("function", "load", "replace", library)).to eql("mylib")
+    end
+
+    it "requires the library metadata" do
+      expect { redis.call("function", "load", "return 1") }
+        .to raise_error(Redis::CommandError, "ERR Missing library metadata")
+      expect { redis.call("function", "load", "#!lua name=empty\nlocal x = 1") }
+        .to raise_error(Redis::CommandError, "ERR No functions registered")
+    end
+
+    it "refuses functions with the names of existing ones" do
+      redis.call("function", "load", library)
+      other = "#!lua name=other\nredis.register_function('echo', function() end)"
+
+      expect { redis.call("function", "load", other) }
+        .to raise_error(Redis::CommandError, "ERR Function echo already exists")
+    end
+  end
+
+  describe "FCALL and FCALL_RO" do
+    before(:example) { redis.call("function", "load", library) }
+
+    it "calls functions with keys and arguments" do
+      expect(redis.call("fcall", "echo", "0", "hi")).to eql("hi")
+      expect(redis.call("fcall", "setkey", "1", "k", "v")).to eql("OK")
+      expect(redis.call("fcall_ro", "getkey", "1", "k")).to eql("v")
+    end
+
+    it "only runs no-writes functions with FCALL_RO" do
+      expect { redis.call("fcall_ro", "setkey", "1", "k", "v") }
+        .to raise_error(Redis::CommandError, "ERR Can not execute a script with write flag using *_ro command.")
+    end
+
+    it "fails for unknown functions" do
+      expect { redis.call("fcall", "nosuchfunction", "0") }
+        .to raise_error(Redis::CommandError, "ERR Function not found")
+    end
+  end
+
+  describe "FUNCTION LIST" do
+    before(:example) { redis.call("function", "load", library) }
+
+    it "describes the libraries and their functions" do
+      expect(redis.call("function", "list", "libraryname", "my*")).to eql([
+        [
+          "library_name", "mylib",
+          "engine", "LUA",
+          "functions", [
+            ["name", "echo", "description", nil, "flags", []],
+            ["name", "getkey", "description", "Gets a key", "flags", ["no-writes"]],
+            ["name", "setkey", "description", nil, "flags", []],
+          ],
+        ],
+      ])
+    end
+
+    it "includes the code when asked" do
+      expect(redis.call("function", "list", "withcode").first.last).to eql(library)
+    end
+  end
+
+  describe "FUNCTION DELETE" do
+    it "deletes a library" do
+      redis.call("function", "load", library)
+
+      expect(redis.call("function", "delete", "mylib")).to eql("OK")
+      expect { redis.call("function", "delete", "mylib") }
+        .to raise_error(Redis::CommandError, "ERR Library not found")
+    end
+  end
+
+  describe "FUNCTION DUMP and RESTORE" do
+    it "restores the dumped libraries" do
+      redis.call("function", "load", library)
+      payload = redis.call("function", "dump")
+
+      expect { redis.call("function", "restore", payload) }
+        .to raise_error(Redis::CommandError, "ERR Library 'mylib' already exists")
+      expect(redis.call("function", "restore", payload, "replace")).to eql("OK")
+
+      redis.call("function", "flush")
+      expect(redis.call("function", "restore", payload)).to eql("OK")
+      expect(redis.call("fcall", "echo", "0", "back")).to eql("back")
+    end
+
+    it "rejects corrupt payloads" do
+      expect { redis.call("function", "restore", "bad payload") }
+        .to raise_error(Redis::CommandError, "ERR payload version or checksum are wrong")
+    end
+  end
+end
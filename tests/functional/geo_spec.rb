@@ -0,0 +1,160 @@
+RSpec.describe "Geospatial commands", include_connection: true do
+  describe "arity" do
+    specify "the arity for each command is correctly specified" do
+      expect(redis.command("info", "geoadd").dig(0, 1)).to eql(-5)
+      expect(redis.command("info", "geopos").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "geodist").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "geohash").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "geosearch").dig(0, 1)).to eql(-7)
+      expect(redis.command("info", "geosearchstore").dig(0, 1)).to eql(-8)
+    end
+  end
+
+  describe "commands used against the wrong type" do
+    specify "raise an error" do
+      redis.set("x", "y")
+
+      expected_error = "WRONGTYPE Operation against a key holding the wrong kind of value"
+      expect { redis.geoadd("x", 1, 1, "a") }.to raise_error(expected_error)
+      expect { redis.geopos("x", "a") }.to raise_error(expected_error)
+      expect { redis.geodist("x", "a", "b") }.to raise_error(expected_error)
+      expect { redis.geohash("x", "a") }.to raise_error(expected_error)
+      expect { redis.call("geosearch", "x", "fromlonlat", 1, 1, "byradius", 1, "m") }
+        .to raise_error(expected_error)
+    end
+  end
+
+  before do
+    redis.geoadd("Sicily", 13.361389, 38.115556, "Palermo", 15.087269, 37.502669, "Catania")
+  end
+
+  describe "GEOADD" do
+    it "stores the members in a sorted set" do
+      expect(redis.type("Sicily")).to eql("zset")
+      expect(redis.object("encoding", "Sicily")).to eql("listpack")
+    end
+
+    it "converts to a skiplist past the listpack limits" do
+      redis.config("set", "zset-max-listpack-entries", 2)
+      redis.geoadd("Sicily", 12.758489, 38.788135, "edge1")
+      redis.config("set", "zset-max-listpack-entries", 128)
+
+      expect(redis.object("encoding", "Sicily")).to eql("skiplist")
+      expect(redis.geohash("Sicily", "Palermo")).to eql(["sqc8b49rny0"])
+    end
+
+    it "supports NX, XX and CH" do
+      expect(redis.call("geoadd", "Sicily", "xx", "ch", 13.361389, 38.115, "Palermo", 1, 1, "new")).to eql(1)
+      expect(redis.call("geoadd", "Sicily", "nx", 1, 1, "Catania")).to eql(0)
+      expect(redis.geopos("Sicily", "new")).to eql([nil])
+      expect { redis.call("geoadd", "Sicily", "nx", "xx", 1, 1, "x") }
+        .to raise_error("ERR XX and NX options at the same time are not compatible")
+    end
+
+    it "rejects invalid positions" do
+      expect { redis.geoadd("Sicily", 181, 0, "x") }
+        .to raise_error("ERR invalid longitude,latitude pair 181.000000,0.000000")
+      expect { redis.geoadd("Sicily", 0, 86, "x") }
+        .to raise_error("ERR invalid longitude,latitude pair 0.000000,86.000000")
+    end
+  end
+
+  describe "GEOPOS" do
+    it "returns the positions of the members" do
+      expect(redis.geopos("Sicily", "Palermo", "Catania", "NonExisting")).to eql(
+        [
+          ["13.36138933897018433", "38.11555639549629859"],
+          ["15.08726745843887329", "37.50266842333162032"],
+          nil
+        ]
+      )
+    end
+  end
+
+  describe "GEODIST" do
+    it "returns the distance in the given unit" do
+      expect(redis.geodist("Sicily", "Palermo", "Catania")).to eql("166274.1516")
+      expect(redis.geodist("Sicily", "Palermo", "Catania", "km")).to eql("166.2742")
+      expect(redis.geodist("Sicily", "Palermo", "Catania", "mi")).to eql("103.3182")
+      expect(redis.geodist("Sicily", "Foo", "Bar")).to be_nil
+    end
+  end
+
+  describe "GEOHASH" do
+    it "returns standard geohash strings" do
+      expect(redis.geohash("Sicily", %w[Palermo Catania NonExisting]))
+        .to eql(["sqc8b49rny0", "sqdtr74hyu0", nil])
+    end
+  end
+
+  describe "GEOSEARCH" do
+    before do
+      redis.geoadd("Sicily", 12.758489, 38.788135, "edge1", 17.241510, 38.788135, "edge2")
+    end
+
+    it "searches by radius" do
+      expect(redis.call("geosearch", "Sicily", "fromlonlat", 15, 37, "byradius", 200, "km", "asc"))
+        .to eql(%w[Catania Palermo])
+    end
+
+    it "searches by box" do
+      expect(
+        redis.call(
+          "geosearch", "Sicily", "fromlonlat", 15, 37, "bybox", 400, 400, "km", "asc", "withcoord", "withdist"
+        )
+      ).to eql(
+        [
+          ["Catania", "56.4413", ["15.08726745843887329", "37.50266842333162032"]],
+          ["Palermo", "190.4424", ["13.36138933897018433", "38.11555639549629859"]],
+          ["edge2", "279.7403", ["17.24151045083999634", "38.78813451624225195"]],
+          ["edge1", "279.7405", ["12.7584877610206604", "38.78813451624225195"]]
+        ]
+      )
+    end
+
+    it "searches from a member with COUNT and DESC" do
+      expect(
+        redis.call("geosearch", "Sicily", "frommember", "Palermo", "byradius", 200, "km", "desc", "count", 2)
+      ).to eql(%w[Catania edge1])
+    end
+
+    it "returns hashes" do
+      expect(
+        redis.call("geosearch", "Sicily", "frommember", "Palermo", "byradius", 1, "km", "withhash")
+      ).to eql([["Palermo", 3_479_099_956_230_698]])
+    end
+
+    it "validates its options" do
+      expect { redis.call("geosearch", "Sicily", "byradius", 1, "km", "byradius", 1, "km") }
+        .to raise_error("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch")
+      expect { redis.call("geosearch", "Sicily", "fromlonlat", 1, 1, "fromlonlat", 1, 1) }
+        .to raise_error("ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch")
+      expect { redis.call("geosearch", "Sicily", "fromlonlat", 1, 1, "byradius", 1, "m", "any") }
+        .to raise_error("ERR the ANY argument requires COUNT argument")
+      expect { redis.call("geosearch", "Sicily", "frommember", "nobody", "byradius", 1, "m") }
+        .to raise_error("ERR could not decode requested zset member")
+    end
+  end
+
+  describe "GEOSEARCHSTORE" do
+    it "stores the members found, or their distances" do
+      expect(
+        redis.call("geosearchstore", "dst", "Sicily", "fromlonlat", 15, 37, "byradius", 200, "km")
+      ).to eql(2)
+      expect(redis.geohash("dst", "Palermo")).to eql(["sqc8b49rny0"])
+
+      expect(
+        redis.call("geosearchstore", "dst", "Sicily", "fromlonlat", 15, 37, "byradius", 100, "km", "storedist")
+      ).to eql(1)
+    end
+
+    it "deletes the destination when nothing is found" do
+      redis.set("dst", "x")
+
+      expect(
+        redis.call("geosearchstore", "dst", "Sicily", "fromlonlat", 0, 0, "byradius", 1, "m")
+      ).to eql(0)
+      expect(redis.exists?("dst")).to be(false)
+    end
+  end
+end
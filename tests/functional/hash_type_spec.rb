@@ -147,4 +147,33 @@ RSpec.describe "Hash commands", include_connection: true do
       end
     end
   end
+
+  describe "encoding" do
+    after do
+      redis.config("set", "hash-max-listpack-entries", 128)
+      redis.config("set", "hash-max-listpack-value", 64)
+    end
+
+    it "packs small hashes into a listpack" do
+      redis.hset("x", "a", "1", "b", "2")
+      expect(redis.object("encoding", "x")).to eql("listpack")
+    end
+
+    it "converts to a hashtable past the entries limit" do
+      redis.config("set", "hash-max-listpack-entries", 2)
+      redis.hset("x", "a", "1", "b", "2")
+      expect(redis.object("encoding", "x")).to eql("listpack")
+
+      redis.hset("x", "c", "3")
+      expect(redis.object("encoding", "x")).to eql("hashtable")
+      expect(redis.hgetall("x")).to eql("a" => "1", "b" => "2", "c" => "3")
+    end
+
+    it "converts to a hashtable past the value limit" do
+      redis.config("set", "hash-max-listpack-value", 4)
+      redis.hset("x", "a", "12345")
+      expect(redis.object("encoding", "x")).to eql("hashtable")
+      expect(redis.hget("x", "a")).to eql("12345")
+    end
+  end
 end
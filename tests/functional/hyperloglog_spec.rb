@@ -0,0 +1,99 @@
+RSpec.describe "HyperLogLog commands", include_connection: true do
+  describe "arity" do
+    specify "the arity for each command is correctly specified" do
+      expect(redis.command("info", "pfadd").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "pfcount").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "pfmerge").dig(0, 1)).to eql(-2)
+    end
+  end
+
+  describe "commands used against the wrong type" do
+    specify "non strings raise WRONGTYPE" do
+      redis.rpush("x", 1)
+
+      expect { redis.pfadd("x", "a") }
+        .to raise_error("WRONGTYPE Operation against a key holding the wrong kind of value")
+      expect { redis.pfcount("x") }
+        .to raise_error("WRONGTYPE Operation against a key holding the wrong kind of value")
+    end
+
+    specify "strings which are not sketches raise WRONGTYPE" do
+      redis.set("x", "hello")
+      redis.set("y", 123)
+
+      expect { redis.pfadd("x", "a") }
+        .to raise_error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+      expect { redis.pfcount("y") }
+        .to raise_error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+      expect { redis.pfmerge("z", "x") }
+        .to raise_error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+    end
+  end
+
+  describe "PFADD" do
+    it "creates an empty sparse sketch" do
+      expect(redis.pfadd("x", [])).to be(true)
+      expect(redis.get("x").b).to eql("HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x7f\xff".b)
+    end
+
+    it "returns whether a register was changed" do
+      expect(redis.pfadd("x", %w[a b c])).to be(true)
+      expect(redis.pfadd("x", %w[a b c])).to be(false)
+    end
+  end
+
+  describe "PFCOUNT" do
+    it "returns 0 for a missing key" do
+      expect(redis.pfcount("x")).to eql(0)
+    end
+
+    it "estimates the cardinality within the standard error" do
+      10.times { |i| redis.pfadd("x", (i * 1000...(i + 1) * 1000).map { |j| "e#{j}" }) }
+
+      expect(redis.pfcount("x")).to be_within(10_000 * 0.05).of(10_000)
+    end
+
+    it "counts the union of several keys" do
+      redis.pfadd("x", %w[a b c])
+      redis.pfadd("y", %w[c d e])
+
+      expect(redis.pfcount("x", "y", "missing")).to eql(5)
+    end
+
+    it "works with sketches copied with GET and SET" do
+      redis.pfadd("x", %w[a b c d e f g])
+      redis.set("y", redis.get("x"))
+
+      expect(redis.pfcount("y")).to eql(7)
+    end
+
+    it "detects corrupted sketches" do
+      redis.pfadd("x", %w[a b c])
+      corrupted = redis.get("x").b
+      corrupted.setbyte(15, corrupted.getbyte(15) | 0x80)
+      redis.set("x", corrupted[0...-1])
+
+      expect { redis.pfcount("x") }.to raise_error("INVALIDOBJ Corrupted HLL object detected")
+    end
+  end
+
+  describe "PFMERGE" do
+    it "merges the sources into the destination, including its own registers" do
+      redis.pfadd("x", %w[a b c])
+      redis.pfadd("y", %w[c d e])
+      redis.pfadd("z", %w[f])
+
+      expect(redis.pfmerge("z", "x", "y")).to eql("OK")
+      expect(redis.pfcount("z")).to eql(6)
+    end
+
+    it "creates a dense sketch when a source is dense" do
+      redis.config("set", "hll-sparse-max-bytes", 0)
+      redis.pfadd("x", %w[a])
+      redis.config("set", "hll-sparse-max-bytes", 3000)
+
+      redis.pfmerge("y", "x")
+      expect(redis.get("y").bytesize).to eql(16 + 12_288)
+    end
+  end
+end
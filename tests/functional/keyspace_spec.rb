@@ -170,9 +170,9 @@ RSpec.describe "Keyspace commands", include_connection: true do
             expect(redis.object("encoding", "d")).to eql("ziplist")
           else
             expect(redis.object("encoding", "a")).to eql("byte_string")
-            expect(redis.object("encoding", "b")).to eql("vecdeque")
+            expect(redis.object("encoding", "b")).to eql("listpack")
             expect(redis.object("encoding", "c")).to eql("int")
-            expect(redis.object("encoding", "d")).to eql("hash_map")
+            expect(redis.object("encoding", "d")).to eql("listpack")
           end
         end
       end
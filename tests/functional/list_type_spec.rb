@@ -448,4 +448,31 @@ RSpec.describe "List commands", include_connection: true do
       end
     end
   end
+
+  describe "encoding" do
+    after { redis.config("set", "list-max-listpack-size", -2) }
+
+    it "packs small lists into a listpack" do
+      redis.rpush("x", %w[a b c])
+      expect(redis.object("encoding", "x")).to eql("listpack")
+    end
+
+    it "converts to a quicklist past the entries limit" do
+      redis.config("set", "list-max-listpack-size", 3)
+      redis.rpush("x", %w[a b c])
+      expect(redis.object("encoding", "x")).to eql("listpack")
+
+      redis.lpush("x", "d")
+      expect(redis.object("encoding", "x")).to eql("quicklist")
+      expect(redis.lrange("x", 0, -1)).to eql(%w[d a b c])
+    end
+
+    it "converts to a quicklist past the size limit" do
+      redis.config("set", "list-max-listpack-size", -1)
+      redis.rpush("x", "a")
+      redis.lset("x", 0, "x" * 5000)
+      expect(redis.object("encoding", "x")).to eql("quicklist")
+      expect(redis.lindex("x", 0)).to eql("x" * 5000)
+    end
+  end
 end
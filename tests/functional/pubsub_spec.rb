@@ -0,0 +1,81 @@
+RSpec.describe "Pub/Sub commands", include_connection: true do
+  let(:subscriber) { Redis.new(port: port) }
+
+  after(:example) { subscriber.close }
+
+  def receive_messages(count, &block)
+    received = Queue.new
+    thread = Thread.new do
+      subscriber.psubscribe("*") do |on|
+        on.pmessage do |_pattern, channel, message|
+          received << [channel, message]
+          subscriber.punsubscribe if received.size == count
+        end
+      end
+    end
+    # Give the subscriber time to subscribe
+    sleep 0.1
+
+    block.call
+    thread.join(1)
+
+    Array.new(received.size) { received.pop }
+  end
+
+  describe "PUBLISH" do
+    it "delivers messages to subscribers and returns their count" do
+      messages = receive_messages(1) do
+        expect(redis.publish("news", "hello")).to eql(1)
+      end
+
+      expect(messages).to eql([["news", "hello"]])
+    end
+
+    it "returns zero when there are no subscribers" do
+      expect(redis.publish("news", "hello")).to eql(0)
+    end
+  end
+
+  describe "PUBSUB" do
+    it "reports the number of pattern subscriptions" do
+      expect(redis.pubsub("numpat")).to eql(0)
+    end
+
+    it "reports the subscribers of channels" do
+      expect(redis.pubsub("numsub", "news")).to eql(["news", 0])
+    end
+  end
+
+  describe "keyspace notifications" do
+    around(:example) do |example|
+      redis.config("set", "notify-keyspace-events", "KEA")
+      example.run
+    ensure
+      redis.config("set", "notify-keyspace-events", "")
+    end
+
+    it "publishes events for changes to keys" do
+      messages = receive_messages(4) do
+        redis.set("x", "1")
+        redis.del("x")
+      end
+
+      expect(messages).to eql([
+        ["__keyspace@0__:x", "set"],
+        ["__keyevent@0__:set", "x"],
+        ["__keyspace@0__:x", "del"],
+        ["__keyevent@0__:del", "x"],
+      ])
+    end
+
+    it "publishes expired events" do
+      messages = receive_messages(6) do
+        redis.set("x", "1", px: 10)
+        sleep 0.05
+        redis.get("x")
+      end
+
+      expect(messages).to include(["__keyevent@0__:expired", "x"])
+    end
+  end
+end
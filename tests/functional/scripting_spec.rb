@@ -0,0 +1,115 @@
+require "digest/sha1"
+
+RSpec.describe "Scripting commands", include_connection: true do
+  after(:example) { redis.script(:flush) }
+
+  describe "EVAL" do
+    it "converts Lua values to replies" do
+      expect(redis.eval("return 1")).to eql(1)
+      expect(redis.eval("return 3.99")).to eql(3)
+      expect(redis.eval("return true")).to eql(1)
+      expect(redis.eval("return false")).to be_nil
+      expect(redis.eval("return 'a'")).to eql("a")
+      expect(redis.eval("return {1, 'b', {2}, nil, 3}")).to eql([1, "b", [2]])
+      expect(redis.eval("return redis.status_reply('FINE')")).to eql("FINE")
+    end
+
+    it "passes keys and arguments" do
+      result = redis.eval("return {KEYS[1], KEYS[2], ARGV[1]}", keys: ["a", "b"], argv: ["c"])
+      expect(result).to eql(["a", "b", "c"])
+    end
+
+    it "calls commands against the dataset" do
+      expect(redis.eval("return redis.call('set', KEYS[1], ARGV[1])", keys: ["x"], argv: ["1"])).to eql("OK")
+      expect(redis.eval("return redis.call('incrby', 'x', 41)")).to eql(42)
+      expect(redis.get("x")).to eql("42")
+    end
+
+    it "raises command errors from redis.call but returns them from redis.pcall" do
+      redis.set("x", "1")
+
+      expect { redis.eval("return redis.call('rpush', 'x', 'a')") }
+        .to raise_error(Redis::CommandError, /^WRONGTYPE/)
+      expect(redis.eval("return redis.pcall('rpush', 'x', 'a')['err']")).to match(/^WRONGTYPE/)
+    end
+
+    it "refuses commands which aren't allowed from scripts" do
+      expect { redis.eval("return redis.call('eval', 'return 1', 0)") }
+        .to raise_error(Redis::CommandError, "ERR This Redis command is not allowed from script")
+      expect { redis.eval("return redis.call('nosuchcommand')") }
+        .to raise_error(Redis::CommandError, "ERR Unknown Redis command called from script")
+    end
+
+    it "protects the globals" do
+      expect { redis.eval("newvar = 1") }
+        .to raise_error(Redis::CommandError, /Script attempted to create global variable 'newvar'/)
+      expect { redis.eval("return nosuchvar") }
+        .to raise_error(Redis::CommandError, /Script attempted to access nonexistent global variable 'nosuchvar'/)
+    end
+
+    it "reports compile and runtime errors" do
+      expect { redis.eval("return +") }
+        .to raise_error(Redis::CommandError, /^ERR Error compiling script/)
+      expect { redis.eval("error('boom')") }
+        .to raise_error(Redis::CommandError, /^ERR Error running script/)
+    end
+
+    it "validates the number of keys" do
+      expect { redis.call("eval", "return 1", "1") }
+        .to raise_error(Redis::CommandError, "ERR Number of keys can't be greater than number of args")
+      expect { redis.call("eval", "return 1", "-1") }
+        .to raise_error(Redis::CommandError, "ERR Number of keys can't be negative")
+    end
+  end
+
+  describe "EVALSHA and SCRIPT" do
+    let(:script) { "return ARGV[1]" }
+    let(:sha) { Digest::SHA1.hexdigest(script) }
+
+    it "runs scripts loaded into the cache" do
+      expect { redis.evalsha(sha, argv: ["hi"]) }
+        .to raise_error(Redis::CommandError, "NOSCRIPT No matching script. Please use EVAL.")
+
+      expect(redis.script(:load, script)).to eql(sha)
+      expect(redis.evalsha(sha, argv: ["hi"])).to eql("hi")
+    end
+
+    it "reports which scripts exist and flushes them" do
+      redis.script(:load, script)
+      expect(redis.script(:exists, [sha, "nope"])).to eql([true, false])
+
+      expect(redis.script(:flush)).to eql("OK")
+      expect(redis.script(:exists, sha)).to eql(false)
+    end
+
+    it "has nothing to kill when no script is running" do
+      expect { redis.script(:kill) }
+        .to raise_error(Redis::CommandError, "NOTBUSY No scripts in execution right now.")
+    end
+  end
+
+  describe "a busy script" do
+    around(:example) do |example|
+      redis.config(:set, "lua-time-limit", "100")
+      example.run
+    ensure
+      redis.config(:set, "lua-time-limit", "5000")
+    end
+
+    it "makes other clients busy until it is killed" do
+      other = Redis.new(port: port)
+      thread = Thread.new do
+        redis.eval("while true do end")
+      rescue Redis::CommandError => e
+        e
+      end
+      sleep 0.3
+
+      expect { other.get("x") }.to raise_error(Redis::CommandError, /^BUSY/)
+      expect(other.script(:kill)).to eql("OK")
+      expect(thread.value.message).to match(/Script killed by user with SCRIPT KILL/)
+    ensure
+      other.close
+    end
+  end
+end
@@ -1,3 +1,5 @@
+require "tmpdir"
+
 RSpec.describe "Server commands", include_connection: true do
   describe "arity" do
     let(:command_arity) do
@@ -67,6 +69,28 @@ RSpec.describe "Server commands", include_connection: true do
           expect(output[1].first).to eql("set")
         end
       end
+
+      it "returns the first key, last key and step" do
+        expect(redis.command("info", "get", "mset", "eval").map { |c| c[3..5] })
+          .to eql([[1, 1, 1], [1, -1, 2], [0, 0, 0]])
+      end
+    end
+
+    describe "GETKEYS" do
+      it "returns the keys of the command" do
+        expect(redis.command("getkeys", "mset", "a", "1", "b", "2")).to eql(%w[a b])
+        expect(redis.command("getkeys", "eval", "return 1", "2", "a", "b", "c")).to eql(%w[a b])
+        expect(redis.command("getkeys", "xread", "streams", "s1", "s2", "0", "0")).to eql(%w[s1 s2])
+      end
+
+      it "rejects commands without keys, unknown commands and wrong arities" do
+        expect { redis.command("getkeys", "info") }
+          .to raise_error("ERR The command has no key arguments")
+        expect { redis.command("getkeys", "xxx") }
+          .to raise_error("ERR Invalid command specified")
+        expect { redis.command("getkeys", "get") }
+          .to raise_error("ERR Invalid number of arguments specified for command")
+      end
     end
 
     context "when the subcommand is not supported" do
@@ -102,4 +126,231 @@ RSpec.describe "Server commands", include_connection: true do
       expect(redis.get("y")).to be_nil
     end
   end
+
+  describe "INFO" do
+    it "returns the default sections" do
+      info = redis.info
+      expect(info).to include("redis_version", "connected_clients", "used_memory")
+      expect(info).to include("total_commands_processed", "keyspace_hits")
+      expect(info).not_to include("cmdstat_info")
+    end
+
+    it "filters by section" do
+      output = redis.call("info", "clients")
+      expect(output).to start_with("# Clients")
+      expect(output).not_to include("# Server")
+    end
+
+    it "counts keyspace hits and misses" do
+      before = redis.info("stats")
+      redis.set("x", "1")
+      redis.get("x")
+      redis.get("y")
+      after = redis.info("stats")
+
+      expect(after["keyspace_hits"].to_i - before["keyspace_hits"].to_i).to eql(1)
+      expect(after["keyspace_misses"].to_i - before["keyspace_misses"].to_i).to eql(1)
+    end
+
+    it "reports the number of keys and expires per database" do
+      redis.set("x", "1")
+      redis.set("y", "2", ex: 100)
+      expect(redis.info("keyspace")["db0"]).to start_with("keys=2,expires=1")
+    end
+
+    it "reports per command statistics" do
+      redis.get("x")
+      stats = redis.info("commandstats")["get"]
+      expect(stats["calls"].to_i).to be >= 1
+      expect(stats).to include("usec", "usec_per_call")
+    end
+  end
+
+  describe "CONFIG" do
+    describe "GET" do
+      it "returns the parameters matching a glob pattern" do
+        result = redis.config("get", "maxmemory*")
+        expect(result.keys).to include("maxmemory", "maxmemory-policy")
+      end
+
+      it "returns an empty result for unknown parameters" do
+        expect(redis.config("get", "xyz")).to be_empty
+      end
+    end
+
+    describe "SET" do
+      around(:example) do |example|
+        original = redis.config("get", "maxmemory")["maxmemory"]
+        example.run
+      ensure
+        redis.config("set", "maxmemory", original)
+      end
+
+      it "changes a runtime parameter" do
+        expect(redis.config("set", "maxmemory", "100mb")).to eql("OK")
+        expect(redis.config("get", "maxmemory")["maxmemory"]).to eql("104857600")
+      end
+
+      it "rejects invalid values" do
+        expect { redis.config("set", "maxmemory", "lots") }
+          .to raise_error(/^ERR CONFIG SET failed \(possibly related to argument 'maxmemory'\)/)
+      end
+    end
+
+    describe "client-output-buffer-limit" do
+      around(:example) do |example|
+        original = redis.config("get", "client-output-buffer-limit")["client-output-buffer-limit"]
+        example.run
+      ensure
+        redis.config("set", "client-output-buffer-limit", original)
+      end
+
+      it "sets the limits of the classes given" do
+        expect(redis.config("set", "client-output-buffer-limit", "normal 1mb 512kb 10")).to eql("OK")
+        expect(redis.config("get", "client-output-buffer-limit")["client-output-buffer-limit"])
+          .to eql("normal 1048576 524288 10 slave 268435456 67108864 60 pubsub 33554432 8388608 60")
+      end
+
+      it "disconnects a client that doesn't read a reply past the hard limit" do
+        redis.set("big", "x" * 20 * 1024 * 1024)
+        redis.config("set", "client-output-buffer-limit", "normal 1mb 0 0")
+
+        socket = TCPSocket.new("127.0.0.1", port)
+        socket.setsockopt(Socket::SOL_SOCKET, Socket::SO_RCVBUF, 4096)
+        socket.write("*2\r\n$3\r\nGET\r\n$3\r\nbig\r\n")
+        sleep 0.5
+
+        expect(redis.client("list")).not_to include("cmd=get")
+      ensure
+        socket&.close
+      end
+
+      it "streams large replies to clients that keep up" do
+        redis.set("big", "x" * 20 * 1024 * 1024)
+        redis.config("set", "client-output-buffer-limit", "normal 64mb 0 0")
+
+        expect(redis.get("big").bytesize).to eql(20 * 1024 * 1024)
+      end
+    end
+
+    describe "RESETSTAT" do
+      it "resets the INFO statistics" do
+        redis.get("x")
+        expect(redis.config("resetstat")).to eql("OK")
+        expect(redis.info("stats")["keyspace_misses"]).to eql("0")
+      end
+    end
+  end
+
+  describe "SLOWLOG" do
+    around(:example) do |example|
+      original = redis.config("get", "slowlog-log-slower-than")["slowlog-log-slower-than"]
+      redis.config("set", "slowlog-log-slower-than", "0")
+      redis.slowlog("reset")
+      example.run
+    ensure
+      redis.config("set", "slowlog-log-slower-than", original)
+    end
+
+    it "records commands slower than the threshold" do
+      redis.set("x", "1")
+
+      entry = redis.slowlog("get", 2).find { |e| e[3][0] == "set" }
+      expect(entry[3]).to eql(["set", "x", "1"])
+      expect(entry[2]).to be >= 0
+    end
+
+    it "reports the number of entries" do
+      redis.get("x")
+      expect(redis.slowlog("len")).to be >= 1
+    end
+
+    it "truncates long arguments" do
+      redis.set("x", "a" * 200)
+
+      entry = redis.slowlog("get", 2).find { |e| e[3][0] == "set" }
+      expect(entry[3][2]).to eql("a" * 128 + "... (72 more bytes)")
+    end
+  end
+
+  describe "MONITOR" do
+    it "streams the commands processed by the server" do
+      lines = Queue.new
+      monitor = Thread.new do
+        Redis.new(port: port).monitor do |line|
+          lines << line
+          break if line.include?('"get"')
+        end
+      end
+      # Give the monitor time to start
+      sleep 0.1
+
+      redis.set("x", "a\nb")
+      redis.get("x")
+      monitor.join(1)
+
+      expect(lines.pop).to match(/^\d+\.\d{6} \[0 [\d.:]+\] "set" "x" "a\\nb"$/)
+      expect(lines.pop).to match(/"get" "x"$/)
+    end
+  end
+
+  describe "maxclients" do
+    around(:example) do |example|
+      original = redis.config("get", "maxclients")["maxclients"]
+      example.run
+    ensure
+      redis.config("set", "maxclients", original)
+    end
+
+    it "refuses connections beyond the limit" do
+      connected = redis.info("clients")["connected_clients"].to_i
+      redis.config("set", "maxclients", connected.to_s)
+
+      expect { Redis.new(port: port).ping }.to raise_error(Redis::CommandError, "ERR max number of clients reached")
+    end
+  end
+
+  describe "timeout" do
+    around(:example) do |example|
+      original = redis.config("get", "timeout")["timeout"]
+      example.run
+    ensure
+      Redis.new(port: port).config("set", "timeout", original)
+    end
+
+    it "closes clients left idle for too long" do
+      id = Redis.new(port: port).client("id")
+      redis.config("set", "timeout", "1")
+      sleep 2.5
+
+      expect(Redis.new(port: port).client("list")).not_to include("id=#{id} ")
+    end
+  end
+
+  describe "SHUTDOWN" do
+    it "has no shutdown to abort" do
+      expect { redis.shutdown("abort") }.to raise_error(Redis::CommandError, "ERR No shutdown in progress.")
+    end
+
+    it "rejects ABORT along with other flags" do
+      expect { redis.call("shutdown", "abort", "now") }.to raise_error(Redis::CommandError, "ERR syntax error")
+    end
+
+    it "rejects unknown flags" do
+      expect { redis.call("shutdown", "later") }.to raise_error(Redis::CommandError, "ERR syntax error")
+    end
+
+    it "refuses to exit when the dataset can't be saved" do
+      original = redis.config("get", "dir")["dir"]
+      # The directory is gone by the time the dataset is saved to it
+      gone = Dir.mktmpdir
+      redis.config("set", "dir", gone)
+      Dir.rmdir(gone)
+
+      expect { redis.call("shutdown", "save") }
+        .to raise_error(Redis::CommandError, "ERR Errors trying to SHUTDOWN. Check logs.")
+    ensure
+      redis.config("set", "dir", original)
+    end
+  end
 end
@@ -0,0 +1,366 @@
+RSpec.describe "Stream commands", include_connection: true do
+  let(:other) { Redis.new(port: port) }
+
+  describe "arity" do
+    specify "the arity for each command is correctly specified" do
+      expect(redis.command("info", "xadd").dig(0, 1)).to eql(-5)
+      expect(redis.command("info", "xrange").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "xrevrange").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "xlen").dig(0, 1)).to eql(2)
+      expect(redis.command("info", "xdel").dig(0, 1)).to eql(-3)
+      expect(redis.command("info", "xtrim").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "xread").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "xreadgroup").dig(0, 1)).to eql(-7)
+      expect(redis.command("info", "xgroup").dig(0, 1)).to eql(-2)
+      expect(redis.command("info", "xack").dig(0, 1)).to eql(-4)
+      expect(redis.command("info", "xpending").dig(0, 1)).to eql(-3)
+      expect(redis.command("info", "xclaim").dig(0, 1)).to eql(-6)
+      expect(redis.command("info", "xautoclaim").dig(0, 1)).to eql(-6)
+      expect(redis.command("info", "xinfo").dig(0, 1)).to eql(-2)
+    end
+  end
+
+  describe "commands used against the wrong type" do
+    let(:expected_error) { "WRONGTYPE Operation against a key holding the wrong kind of value" }
+
+    specify "raise an error" do
+      redis.set("x", "not a stream")
+
+      expect { redis.xadd("x", { "a" => "1" }) }
+        .to raise_error(expected_error)
+      expect { redis.xrange("x") }
+        .to raise_error(expected_error)
+      expect { redis.xlen("x") }
+        .to raise_error(expected_error)
+      expect { redis.xdel("x", "1-1") }
+        .to raise_error(expected_error)
+      expect { redis.xread("x", "0") }
+        .to raise_error(expected_error)
+    end
+  end
+
+  describe "XADD" do
+    it "creates a stream" do
+      expect(redis.xadd("s", { "a" => "1" }, id: "1-1")).to eql("1-1")
+      expect(redis.type("s")).to eql("stream")
+      expect(redis.object("encoding", "s")).to eql("stream")
+    end
+
+    it "generates increasing IDs" do
+      first = redis.xadd("s", { "a" => "1" })
+      second = redis.xadd("s", { "a" => "2" })
+
+      expect(first).to match(/\A\d+-\d+\z/)
+      expect(redis.xrange("s").map(&:first)).to eql([first, second])
+    end
+
+    it "generates the sequence number for a given time" do
+      redis.xadd("s", { "a" => "1" }, id: "5-3")
+      expect(redis.xadd("s", { "a" => "1" }, id: "5-*")).to eql("5-4")
+      expect(redis.xadd("s", { "a" => "1" }, id: "6-*")).to eql("6-0")
+    end
+
+    it "refuses IDs which are not greater than the last one" do
+      redis.xadd("s", { "a" => "1" }, id: "5-5")
+
+      expect { redis.xadd("s", { "a" => "1" }, id: "5-5") }
+        .to raise_error("ERR The ID specified in XADD is equal or smaller than the target stream top item")
+      expect { redis.xadd("t", { "a" => "1" }, id: "0-0") }
+        .to raise_error("ERR The ID specified in XADD must be greater than 0-0")
+      expect { redis.xadd("t", { "a" => "1" }, id: "x") }
+        .to raise_error("ERR Invalid stream ID specified as stream command argument")
+    end
+
+    it "refuses entries once the last possible ID has been used" do
+      redis.xadd("s", { "a" => "1" }, id: "18446744073709551615-18446744073709551615")
+
+      expect { redis.xadd("s", { "a" => "1" }) }
+        .to raise_error("ERR The stream has exhausted the last possible ID, unable to add more items")
+    end
+
+    it "does not create the stream with NOMKSTREAM" do
+      expect(redis.xadd("s", { "a" => "1" }, nomkstream: true)).to be_nil
+      expect(redis.exists?("s")).to be false
+    end
+
+    it "trims the stream" do
+      5.times { |i| redis.xadd("s", { "a" => i.to_s }, id: "#{i + 1}-0") }
+
+      redis.xadd("s", { "a" => "5" }, id: "6-0", maxlen: 3)
+      expect(redis.xrange("s").map(&:first)).to eql(["4-0", "5-0", "6-0"])
+
+      redis.xadd("s", { "a" => "6" }, id: "7-0", minid: "6")
+      expect(redis.xrange("s").map(&:first)).to eql(["6-0", "7-0"])
+    end
+
+    it "only trims whole nodes when trimming approximately" do
+      150.times { redis.xadd("s", { "a" => "1" }) }
+
+      redis.xadd("s", { "a" => "1" }, maxlen: 10, approximate: true)
+      expect(redis.xlen("s")).to eql(51)
+    end
+
+    it "needs ~ to be given a LIMIT" do
+      expect { redis.call("xadd", "s", "maxlen", "1", "limit", "1", "*", "a", "1") }
+        .to raise_error("ERR syntax error, LIMIT cannot be used without the special ~ option")
+    end
+  end
+
+  describe "XRANGE and XREVRANGE" do
+    before do
+      (1..4).each { |i| redis.xadd("s", { "f" => i.to_s }, id: "#{i}-1") }
+    end
+
+    it "returns the entries within the interval" do
+      expect(redis.xrange("s", "2", "3")).to eql([["2-1", { "f" => "2" }], ["3-1", { "f" => "3" }]])
+      expect(redis.xrange("s", "(2-1", "+").map(&:first)).to eql(["3-1", "4-1"])
+      expect(redis.xrange("s", "-", "(4-1").map(&:first)).to eql(["1-1", "2-1", "3-1"])
+      expect(redis.xrange("s", "-", "+", count: 2).map(&:first)).to eql(["1-1", "2-1"])
+      expect(redis.xrange("missing")).to eql([])
+    end
+
+    it "returns the entries in reverse" do
+      expect(redis.xrevrange("s", "+", "-", count: 2).map(&:first)).to eql(["4-1", "3-1"])
+      expect(redis.xrevrange("s", "3", "2").map(&:first)).to eql(["3-1", "2-1"])
+    end
+  end
+
+  describe "XLEN, XDEL and XTRIM" do
+    it "removes entries" do
+      (1..5).each { |i| redis.xadd("s", { "f" => i.to_s }, id: "#{i}-0") }
+
+      expect(redis.xdel("s", ["2-0", "9-0"])).to eql(1)
+      expect(redis.xlen("s")).to eql(4)
+      expect(redis.xtrim("s", 2)).to eql(2)
+      expect(redis.xrange("s").map(&:first)).to eql(["4-0", "5-0"])
+      expect(redis.call("xtrim", "s", "minid", "5")).to eql(1)
+      expect(redis.xlen("missing")).to eql(0)
+    end
+
+    it "keeps generating IDs after the last one added" do
+      redis.xadd("s", { "f" => "1" }, id: "5-0")
+      redis.xdel("s", "5-0")
+
+      expect(redis.xlen("s")).to eql(0)
+      expect(redis.xadd("s", { "f" => "1" }, id: "5-*")).to eql("5-1")
+    end
+  end
+
+  describe "XREAD" do
+    before do
+      (1..3).each { |i| redis.xadd("s", { "f" => i.to_s }, id: "#{i}-0") }
+    end
+
+    it "returns the entries after the given IDs" do
+      expect(redis.xread(["s", "t"], ["1", "0"])).to eql("s" => [["2-0", { "f" => "2" }], ["3-0", { "f" => "3" }]])
+      expect(redis.xread("s", "0", count: 1)).to eql("s" => [["1-0", { "f" => "1" }]])
+      expect(redis.xread("s", "3")).to eql({})
+    end
+
+    it "needs an ID for each stream" do
+      expect { redis.call("xread", "streams", "s", "t", "0") }
+        .to raise_error("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.")
+    end
+
+    it "times out when blocking" do
+      expect(redis.xread("s", "$", block: 100)).to eql({})
+    end
+
+    it "blocks until an entry is added" do
+      thread = Thread.new { other.xread("s", "$", block: 0) }
+      sleep 0.2
+      expect(redis.info["blocked_clients"]).to eql("1")
+
+      redis.xadd("s", { "f" => "new" }, id: "4-0")
+      expect(thread.value).to eql("s" => [["4-0", { "f" => "new" }]])
+      expect(redis.info["blocked_clients"]).to eql("0")
+    end
+  end
+
+  describe "consumer groups" do
+    before do
+      (1..4).each { |i| redis.xadd("s", { "f" => i.to_s }, id: "#{i}-0") }
+      redis.xgroup(:create, "s", "g", "0")
+    end
+
+    describe "XGROUP" do
+      it "needs the stream to exist unless MKSTREAM is given" do
+        expect { redis.xgroup(:create, "t", "g", "$") }
+          .to raise_error(/ERR The XGROUP subcommand requires the key to exist/)
+        expect(redis.xgroup(:create, "t", "g", "$", mkstream: true)).to eql("OK")
+        expect(redis.xlen("t")).to eql(0)
+      end
+
+      it "refuses to create a group twice" do
+        expect { redis.xgroup(:create, "s", "g", "$") }
+          .to raise_error("BUSYGROUP Consumer Group name already exists")
+      end
+
+      it "manages consumers" do
+        expect(redis.xgroup(:createconsumer, "s", "g", "alice")).to eql(1)
+        expect(redis.xgroup(:createconsumer, "s", "g", "alice")).to eql(0)
+        redis.xreadgroup("g", "alice", "s", ">", count: 2)
+        expect(redis.xgroup(:delconsumer, "s", "g", "alice")).to eql(2)
+        expect(redis.xpending("s", "g")["size"]).to eql(0)
+      end
+
+      it "sets the last delivered ID and destroys groups" do
+        expect(redis.xgroup(:setid, "s", "g", "3")).to eql("OK")
+        expect(redis.xreadgroup("g", "alice", "s", ">")).to eql("s" => [["4-0", { "f" => "4" }]])
+        expect { redis.xgroup(:setid, "s", "nope", "0") }
+          .to raise_error("NOGROUP No such consumer group 'nope' for key name 's'")
+
+        expect(redis.xgroup(:destroy, "s", "g")).to eql(1)
+        expect(redis.xgroup(:destroy, "s", "g")).to eql(0)
+      end
+    end
+
+    describe "XREADGROUP" do
+      it "delivers each entry to one consumer of the group" do
+        expect(redis.xreadgroup("g", "alice", "s", ">", count: 3).fetch("s").map(&:first))
+          .to eql(["1-0", "2-0", "3-0"])
+        expect(redis.xreadgroup("g", "bob", "s", ">").fetch("s").map(&:first))
+          .to eql(["4-0"])
+        expect(redis.xreadgroup("g", "bob", "s", ">")).to eql({})
+      end
+
+      it "reads the history of the consumer" do
+        redis.xreadgroup("g", "alice", "s", ">", count: 2)
+        redis.xdel("s", "2-0")
+
+        expect(redis.call("xreadgroup", "group", "g", "alice", "streams", "s", "0"))
+          .to eql([["s", [["1-0", ["f", "1"]], ["2-0", nil]]]])
+      end
+
+      it "does not track entries read with NOACK" do
+        redis.xreadgroup("g", "alice", "s", ">", noack: true)
+        expect(redis.xpending("s", "g")["size"]).to eql(0)
+      end
+
+      it "needs the group to exist" do
+        expect { redis.xreadgroup("nope", "alice", "s", ">") }
+          .to raise_error("NOGROUP No such key 's' or consumer group 'nope' in XREADGROUP with GROUP option")
+      end
+
+      it "blocks until an entry is added" do
+        redis.xreadgroup("g", "alice", "s", ">")
+
+        thread = Thread.new { other.xreadgroup("g", "bob", "s", ">", block: 0) }
+        sleep 0.2
+        redis.xadd("s", { "f" => "5" }, id: "5-0")
+
+        expect(thread.value).to eql("s" => [["5-0", { "f" => "5" }]])
+        expect(redis.xreadgroup("g", "alice", "s", ">", block: 100)).to eql({})
+      end
+    end
+
+    describe "XACK and XPENDING" do
+      before do
+        redis.xreadgroup("g", "alice", "s", ">", count: 3)
+        redis.xreadgroup("g", "bob", "s", ">")
+      end
+
+      it "summarises the pending entries" do
+        expect(redis.call("xpending", "s", "g"))
+          .to eql([4, "1-0", "4-0", [["alice", "3"], ["bob", "1"]]])
+      end
+
+      it "lists the pending entries" do
+        pending = redis.call("xpending", "s", "g", "-", "+", "10", "alice")
+
+        expect(pending.map(&:first)).to eql(["1-0", "2-0", "3-0"])
+        expect(pending.map(&:last)).to eql([1, 1, 1])
+        expect(redis.call("xpending", "s", "g", "IDLE", "100000", "-", "+", "10")).to eql([])
+      end
+
+      it "acknowledges entries" do
+        expect(redis.xack("s", "g", "1-0", "2-0", "9-0")).to eql(2)
+        expect(redis.call("xpending", "s", "g"))
+          .to eql([2, "3-0", "4-0", [["alice", "1"], ["bob", "1"]]])
+        expect(redis.xack("s", "g", "1-0")).to eql(0)
+      end
+    end
+
+    describe "XCLAIM and XAUTOCLAIM" do
+      before do
+        redis.xreadgroup("g", "alice", "s", ">")
+      end
+
+      it "transfers pending entries idle for long enough" do
+        expect(redis.xclaim("s", "g", "bob", 100_000, "1-0")).to eql([])
+        expect(redis.xclaim("s", "g", "bob", 0, "1-0", "2-0", justid: true)).to eql(["1-0", "2-0"])
+
+        pending = redis.call("xpending", "s", "g", "-", "+", "10", "bob")
+        expect(pending.map { |entry| entry.values_at(0, 1, 3) })
+          .to eql([["1-0", "bob", 1], ["2-0", "bob", 1]])
+      end
+
+      it "increments the delivery count" do
+        redis.xclaim("s", "g", "bob", 0, "1-0")
+        expect(redis.call("xpending", "s", "g", "-", "+", "1").dig(0, 3)).to eql(2)
+
+        redis.call("xclaim", "s", "g", "bob", "0", "1-0", "RETRYCOUNT", "7")
+        expect(redis.call("xpending", "s", "g", "-", "+", "1").dig(0, 3)).to eql(7)
+      end
+
+      it "scans the pending entries" do
+        redis.xdel("s", "2-0")
+
+        expect(redis.call("xautoclaim", "s", "g", "bob", "0", "0", "COUNT", "2"))
+          .to eql(["4-0", [["1-0", ["f", "1"]], ["3-0", ["f", "3"]]], ["2-0"]])
+        expect(redis.call("xautoclaim", "s", "g", "bob", "0", "3-0", "JUSTID"))
+          .to eql(["0-0", ["3-0", "4-0"], []])
+      end
+    end
+
+    describe "XINFO" do
+      before do
+        redis.xreadgroup("g", "alice", "s", ">", count: 3)
+      end
+
+      it "describes the stream" do
+        info = Hash[*redis.call("xinfo", "stream", "s")]
+
+        expect(info["length"]).to eql(4)
+        expect(info["last-generated-id"]).to eql("4-0")
+        expect(info["entries-added"]).to eql(4)
+        expect(info["groups"]).to eql(1)
+        expect(info["first-entry"]).to eql(["1-0", ["f", "1"]])
+        expect(info["last-entry"]).to eql(["4-0", ["f", "4"]])
+      end
+
+      it "describes the groups" do
+        groups = redis.call("xinfo", "groups", "s").map { |group| Hash[*group] }
+
+        expect(groups.size).to eql(1)
+        expect(groups[0]).to include(
+          "name" => "g",
+          "consumers" => 1,
+          "pending" => 3,
+          "last-delivered-id" => "3-0",
+          "entries-read" => 3,
+          "lag" => 1
+        )
+      end
+
+      it "describes the consumers" do
+        consumers = redis.call("xinfo", "consumers", "s", "g").map { |consumer| Hash[*consumer] }
+
+        expect(consumers.map { |consumer| consumer.values_at("name", "pending") })
+          .to eql([["alice", 3]])
+        expect { redis.call("xinfo", "consumers", "s", "nope") }
+          .to raise_error("NOGROUP No such consumer group 'nope' for key name 's'")
+      end
+
+      it "gives the full description of the stream" do
+        info = Hash[*redis.call("xinfo", "stream", "s", "full")]
+        group = Hash[*info["groups"][0]]
+
+        expect(info["entries"].map(&:first)).to eql(["1-0", "2-0", "3-0", "4-0"])
+        expect(group["pel-count"]).to eql(3)
+        expect(group["pending"].map { |entry| entry.values_at(0, 1, 3) })
+          .to eql([["1-0", "alice", 1], ["2-0", "alice", 1], ["3-0", "alice", 1]])
+      end
+    end
+  end
+end
@@ -0,0 +1,310 @@
+//! Ported from tests/functional/hash_type_spec.rb
+
+mod common;
+
+use common::*;
+use redis_clone_client::{cmd, Connection, Value};
+use std::collections::HashMap;
+
+fn hash(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs
+        .iter()
+        .map(|(field, value)| ((*field).to_owned(), (*value).to_owned()))
+        .collect()
+}
+
+#[tokio::test]
+async fn arity() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    assert_eq!(command_arity(&mut redis, "hset").await, -4);
+    assert_eq!(command_arity(&mut redis, "hmset").await, -4);
+    assert_eq!(command_arity(&mut redis, "hget").await, 3);
+    assert_eq!(command_arity(&mut redis, "hmget").await, -3);
+    assert_eq!(command_arity(&mut redis, "hgetall").await, 2);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn wrong_type() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+    redis.set("x", "not a hash").await.unwrap();
+
+    assert_error(redis.hset("x", &[("y", "z")]).await, WRONGTYPE);
+    assert_error(
+        redis.hget::<_, _, Option<String>>("x", "y").await,
+        WRONGTYPE,
+    );
+    assert_error(
+        cmd("HMSET")
+            .arg("x")
+            .arg("y")
+            .arg("z")
+            .query::<()>(&mut redis)
+            .await,
+        WRONGTYPE,
+    );
+    assert_error(
+        redis.hmget::<_, _, Vec<Value>>("x", &["y"]).await,
+        WRONGTYPE,
+    );
+    assert_error(redis.hgetall::<_, Vec<Value>>("x").await, WRONGTYPE);
+
+    server.stop().await;
+}
+
+mod hset {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.hset("x", &[("y", "z")]).await.unwrap(), 1);
+        assert_eq!(redis.key_type("x").await.unwrap(), "hash");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn overwrites_the_value() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.hset("x", &[("y", "z")]).await.unwrap();
+        assert_eq!(redis.hget::<_, _, String>("x", "y").await.unwrap(), "z");
+        redis.hset("x", &[("y", "zz")]).await.unwrap();
+        assert_eq!(redis.hget::<_, _, String>("x", "y").await.unwrap(), "zz");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_number_of_fields_added() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.hset("x", &[("a", 1), ("b", 2)]).await.unwrap(), 2);
+        assert_eq!(redis.hget::<_, _, String>("x", "a").await.unwrap(), "1");
+        assert_eq!(redis.hget::<_, _, String>("x", "b").await.unwrap(), "2");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn adds_new_fields_and_updates_existing_ones() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.hset("x", &[("a", 1)]).await.unwrap();
+        assert_eq!(redis.hset("x", &[("a", 2), ("b", 2)]).await.unwrap(), 1);
+        assert_eq!(redis.hget::<_, _, String>("x", "a").await.unwrap(), "2");
+        assert_eq!(redis.hget::<_, _, String>("x", "b").await.unwrap(), "2");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_an_uneven_number_of_arguments() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            cmd("HSET")
+                .arg("x")
+                .arg("a")
+                .arg(2)
+                .arg("b")
+                .query::<i64>(&mut redis)
+                .await,
+            "ERR wrong number of arguments for HMSET",
+        );
+
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn hmset_is_hset_with_a_different_reply() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    for pairs in &[["a", "1", "b", "2"], ["a", "1", "c", "2"]] {
+        let reply: String = cmd("HMSET")
+            .arg("x")
+            .args(pairs)
+            .query(&mut redis)
+            .await
+            .unwrap();
+        assert_eq!(reply, "OK");
+    }
+
+    server.stop().await;
+}
+
+mod hget {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_nil_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(
+            redis.hget::<_, _, Option<String>>("x", "y").await.unwrap(),
+            None
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_value() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        // When the value is a string
+        redis.hset("x", &[("y", "z")]).await.unwrap();
+        assert_eq!(redis.hget::<_, _, String>("x", "y").await.unwrap(), "z");
+
+        // When the value is an int
+        redis.hset("x", &[("y", "1")]).await.unwrap();
+        assert_eq!(redis.hget::<_, _, String>("x", "y").await.unwrap(), "1");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_nil_for_a_missing_field() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.hset("x", &[("y", "z")]).await.unwrap();
+        assert_eq!(
+            redis.hget::<_, _, Option<String>>("x", "z").await.unwrap(),
+            None
+        );
+
+        server.stop().await;
+    }
+}
+
+mod hmget {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_nils_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let values: Vec<Option<String>> = redis.hmget("x", &["y"]).await.unwrap();
+        assert_eq!(values, vec![None]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_nil_for_missing_fields() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.hset("x", &[("a", "1"), ("c", "3")]).await.unwrap();
+        let values: Vec<Option<String>> = redis.hmget("x", &["a", "b", "c"]).await.unwrap();
+        assert_eq!(
+            values,
+            vec![Some("1".to_owned()), None, Some("3".to_owned())]
+        );
+
+        server.stop().await;
+    }
+}
+
+mod hgetall {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_an_empty_array_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(
+            redis.hgetall::<_, Value>("x").await.unwrap(),
+            Value::Array(vec![])
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_fields_and_values() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.hset("x", &[("y", "z"), ("1", "2")]).await.unwrap();
+        let all: HashMap<String, String> = redis.hgetall("x").await.unwrap();
+        assert_eq!(all, hash(&[("y", "z"), ("1", "2")]));
+
+        server.stop().await;
+    }
+}
+
+mod encoding {
+    use super::*;
+
+    async fn start() -> (TestServer, Connection) {
+        let mut server = TestServer::start().await;
+        server
+            .preserve_config(&["hash-max-listpack-entries", "hash-max-listpack-value"])
+            .await;
+        let redis = server.connect().await;
+        (server, redis)
+    }
+
+    #[tokio::test]
+    async fn packs_small_hashes_into_a_listpack() {
+        let (server, mut redis) = start().await;
+
+        redis.hset("x", &[("a", "1"), ("b", "2")]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "listpack");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn converts_to_a_hashtable_past_the_entries_limit() {
+        let (server, mut redis) = start().await;
+
+        redis
+            .config_set("hash-max-listpack-entries", 2)
+            .await
+            .unwrap();
+        redis.hset("x", &[("a", "1"), ("b", "2")]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "listpack");
+
+        redis.hset("x", &[("c", "3")]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "hashtable");
+        let all: HashMap<String, String> = redis.hgetall("x").await.unwrap();
+        assert_eq!(all, hash(&[("a", "1"), ("b", "2"), ("c", "3")]));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn converts_to_a_hashtable_past_the_value_limit() {
+        let (server, mut redis) = start().await;
+
+        redis
+            .config_set("hash-max-listpack-value", 4)
+            .await
+            .unwrap();
+        redis.hset("x", &[("a", "12345")]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "hashtable");
+        assert_eq!(redis.hget::<_, _, String>("x", "a").await.unwrap(), "12345");
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,439 @@
+//! Ported from tests/functional/keyspace_spec.rb
+
+mod common;
+
+use common::*;
+use redis_clone_client::{cmd, Connection};
+use std::time::Duration;
+use tokio::time::sleep;
+
+async fn keys(redis: &mut Connection, pattern: &str) -> Vec<String> {
+    let mut keys: Vec<String> = redis.keys(pattern).await.unwrap();
+    keys.sort_unstable();
+    keys
+}
+
+#[tokio::test]
+async fn arity() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    assert_eq!(command_arity(&mut redis, "del").await, -2);
+    assert_eq!(command_arity(&mut redis, "exists").await, -2);
+    assert_eq!(command_arity(&mut redis, "expire").await, 3);
+    assert_eq!(command_arity(&mut redis, "keys").await, 2);
+    assert_eq!(command_arity(&mut redis, "object").await, -2);
+    assert_eq!(command_arity(&mut redis, "persist").await, 2);
+    assert_eq!(command_arity(&mut redis, "ttl").await, 2);
+    assert_eq!(command_arity(&mut redis, "type").await, 2);
+
+    server.stop().await;
+}
+
+mod keys {
+    use super::*;
+
+    const KEYNAMES: &[&str] = &["foo_a", "foo_b", "foo_c", "key_x", "key_y", "key_z"];
+
+    async fn start() -> (TestServer, Connection) {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        for key in KEYNAMES {
+            redis.set(key, "hello").await.unwrap();
+        }
+        (server, redis)
+    }
+
+    #[tokio::test]
+    async fn returns_nothing_when_there_are_no_keys() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert!(keys(&mut redis, "*").await.is_empty());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_key_names() {
+        let (server, mut redis) = start().await;
+
+        assert_eq!(keys(&mut redis, "*").await, KEYNAMES);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_keys_matching_a_glob_pattern() {
+        let (server, mut redis) = start().await;
+
+        assert_eq!(keys(&mut redis, "foo_*").await, ["foo_a", "foo_b", "foo_c"]);
+        assert_eq!(keys(&mut redis, "foo_[a-b]").await, ["foo_a", "foo_b"]);
+        assert_eq!(keys(&mut redis, "foo_[ac]").await, ["foo_a", "foo_c"]);
+        assert_eq!(keys(&mut redis, "*_x").await, ["key_x"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_nothing_for_an_invalid_pattern() {
+        let (server, mut redis) = start().await;
+
+        assert!(keys(&mut redis, "[]").await.is_empty());
+        assert!(keys(&mut redis, "[ab").await.is_empty());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn does_not_list_expired_keys() {
+        let (server, mut redis) = start().await;
+
+        redis.expire("key_x", 1).await.unwrap();
+        sleep(Duration::from_millis(1500)).await;
+        let remaining: Vec<_> = KEYNAMES
+            .iter()
+            .filter(|&&key| key != "key_x")
+            .copied()
+            .collect();
+        assert_eq!(keys(&mut redis, "*").await, remaining);
+
+        server.stop().await;
+    }
+}
+
+mod del {
+    use super::*;
+
+    #[tokio::test]
+    async fn removes_the_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "123").await.unwrap();
+        assert_eq!(redis.del(&["x"]).await.unwrap(), 1);
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_0_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.del(&["does-not-exist"]).await.unwrap(), 0);
+        assert_eq!(redis.exists(&["does-not-exist"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_number_of_keys_removed() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "123").await.unwrap();
+        redis.set("y", "123").await.unwrap();
+        assert_eq!(redis.del(&["x", "y", "does-not-exist"]).await.unwrap(), 2);
+        assert_eq!(redis.exists(&["x", "y"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn exists_counts_the_keys_that_exist() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    redis.set("a", "123").await.unwrap();
+    redis.set("b", "123").await.unwrap();
+
+    assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+    assert_eq!(redis.exists(&["a"]).await.unwrap(), 1);
+    assert_eq!(redis.exists(&["a", "b"]).await.unwrap(), 2);
+    assert_eq!(redis.exists(&["a", "a", "b"]).await.unwrap(), 3);
+    assert_eq!(redis.exists(&["a", "a", "x"]).await.unwrap(), 2);
+
+    server.stop().await;
+}
+
+mod type_ {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_none_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.key_type("does-not-exist").await.unwrap(), "none");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_type_of_the_value() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "123").await.unwrap();
+        assert_eq!(redis.key_type("x").await.unwrap(), "string");
+        redis.incr("x").await.unwrap();
+        assert_eq!(redis.key_type("x").await.unwrap(), "string");
+
+        redis.rpush("y", &[1]).await.unwrap();
+        assert_eq!(redis.key_type("y").await.unwrap(), "list");
+
+        redis.hset("z", &[("y", 1)]).await.unwrap();
+        assert_eq!(redis.key_type("z").await.unwrap(), "hash");
+
+        server.stop().await;
+    }
+}
+
+mod object {
+    use super::*;
+
+    #[tokio::test]
+    async fn help() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let help: Vec<String> = cmd("OBJECT").arg("HELP").query(&mut redis).await.unwrap();
+        assert_eq!(help.len(), 5);
+        assert_eq!(
+            help[0],
+            "OBJECT <subcommand> arg arg ... arg. Subcommands are:"
+        );
+        assert!(help[1].starts_with("ENCODING"));
+        assert!(help[2].starts_with("FREQ"));
+        assert!(help[3].starts_with("IDLETIME"));
+        assert!(help[4].starts_with("REFCOUNT"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn encoding_rejects_a_missing_key_argument() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            cmd("object").arg("encoding").query::<()>(&mut redis).await,
+            "ERR Unknown subcommand or wrong number of arguments for 'encoding'. Try OBJECT HELP.",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn encoding_of_a_missing_key_is_nil() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(object_encoding(&mut redis, "does-not-exist").await, None);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn encoding_of_values() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("a", "string").await.unwrap();
+        redis.rpush("b", &["1", "2"]).await.unwrap();
+        redis.set("c", 1).await.unwrap();
+        redis.hset("d", &[("x", "y")]).await.unwrap();
+
+        let expected = if server.is_real_redis() {
+            ["embstr", "quicklist", "int", "ziplist"]
+        } else {
+            ["byte_string", "listpack", "int", "listpack"]
+        };
+        for (key, expected) in ["a", "b", "c", "d"].iter().zip(&expected) {
+            assert_eq!(object_encoding(&mut redis, key).await.unwrap(), *expected);
+        }
+
+        server.stop().await;
+    }
+}
+
+mod expire {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_false_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert!(!redis.expire("does-not-exist", 10).await.unwrap());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn sets_the_expiration() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        assert!(redis.expire("x", 10).await.unwrap());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn updates_a_future_expiration() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        redis.expire("x", 10).await.unwrap();
+        redis.expire("x", 100).await.unwrap();
+        let ttl = redis.ttl("x").await.unwrap();
+        assert!((0..=100).contains(&ttl));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn deletes_the_key_when_the_ttl_is_not_positive() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        for ttl in &[0, -1] {
+            redis.set("x", "abc").await.unwrap();
+            assert!(redis.expire("x", *ttl).await.unwrap());
+            assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_a_key_that_has_expired() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        assert!(redis.expire("x", 1).await.unwrap());
+        sleep(Duration::from_millis(1500)).await;
+        assert!(!redis.expire("x", 1).await.unwrap());
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_ttl_that_is_not_an_integer() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "123").await.unwrap();
+        assert_error(
+            cmd("EXPIRE")
+                .arg("x")
+                .arg("abc")
+                .query::<bool>(&mut redis)
+                .await,
+            "ERR value is not an integer or out of range",
+        );
+
+        server.stop().await;
+    }
+}
+
+mod persist {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_false_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert!(!redis.persist("does-not-exist").await.unwrap());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_the_expiration() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        redis.expire("x", 10).await.unwrap();
+        assert!(redis.persist("x").await.unwrap());
+        assert_eq!(redis.ttl("x").await.unwrap(), -1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_a_key_that_has_expired() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        assert!(redis.expire("x", 1).await.unwrap());
+        sleep(Duration::from_millis(1500)).await;
+        assert!(!redis.persist("x").await.unwrap());
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+}
+
+mod ttl {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_minus_2_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.ttl("does-not-exist").await.unwrap(), -2);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_minus_2_and_removes_a_key_that_has_expired() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        redis.expire("x", 1).await.unwrap();
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(redis.ttl("x").await.unwrap(), -2);
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_minus_1_for_a_key_without_an_expiration() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        assert_eq!(redis.ttl("x").await.unwrap(), -1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_time_to_live() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "abc").await.unwrap();
+        redis.expire("x", 10).await.unwrap();
+        let ttl = redis.ttl("x").await.unwrap();
+        assert!((0..=10).contains(&ttl));
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,800 @@
+//! Ported from tests/functional/list_type_spec.rb
+
+mod common;
+
+use common::*;
+use redis_clone_client::{cmd, Connection, Result};
+
+async fn linsert(
+    redis: &mut Connection,
+    key: &str,
+    position: &str,
+    pivot: &str,
+    value: &str,
+) -> Result<i64> {
+    cmd("LINSERT")
+        .arg(key)
+        .arg(position)
+        .arg(pivot)
+        .arg(value)
+        .query(redis)
+        .await
+}
+
+async fn lrange(redis: &mut Connection, key: &str, start: i64, stop: i64) -> Vec<String> {
+    redis.lrange(key, start, stop).await.unwrap()
+}
+
+#[tokio::test]
+async fn arity() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    assert_eq!(command_arity(&mut redis, "rpush").await, -3);
+    assert_eq!(command_arity(&mut redis, "lpush").await, -3);
+    assert_eq!(command_arity(&mut redis, "linsert").await, 5);
+    assert_eq!(command_arity(&mut redis, "rpop").await, 2);
+    assert_eq!(command_arity(&mut redis, "lpop").await, 2);
+    assert_eq!(command_arity(&mut redis, "llen").await, 2);
+    assert_eq!(command_arity(&mut redis, "lindex").await, 3);
+    assert_eq!(command_arity(&mut redis, "lset").await, 4);
+    assert_eq!(command_arity(&mut redis, "lrange").await, 4);
+    assert_eq!(command_arity(&mut redis, "ltrim").await, 4);
+    assert_eq!(command_arity(&mut redis, "lrem").await, 4);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn wrong_type() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+    redis.set("x", "not a list").await.unwrap();
+
+    assert_error(redis.rpush("x", &["y"]).await, WRONGTYPE);
+    assert_error(redis.lpush("x", &["y"]).await, WRONGTYPE);
+    assert_error(
+        linsert(&mut redis, "x", "BEFORE", "1", "0").await,
+        WRONGTYPE,
+    );
+    assert_error(redis.rpop::<_, Option<String>>("x").await, WRONGTYPE);
+    assert_error(redis.lpop::<_, Option<String>>("x").await, WRONGTYPE);
+    assert_error(redis.llen("x").await, WRONGTYPE);
+    assert_error(redis.lindex::<_, Option<String>>("x", 0).await, WRONGTYPE);
+    assert_error(redis.lset("x", 0, "y").await, WRONGTYPE);
+    assert_error(redis.lrange::<_, Vec<String>>("x", 0, -1).await, WRONGTYPE);
+    assert_error(redis.ltrim("x", 0, -1).await, WRONGTYPE);
+    assert_error(redis.lrem("x", 1, "y").await, WRONGTYPE);
+
+    server.stop().await;
+}
+
+/// Taken and converted from tests/unit/type/list.tcl in the Redis source
+#[tokio::test]
+async fn push_llen_lindex_and_pop_work_together() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    // first lpush then rpush
+    assert_eq!(redis.lpush("myziplist1", &["aa"]).await.unwrap(), 1);
+    assert_eq!(redis.rpush("myziplist1", &["bb"]).await.unwrap(), 2);
+    assert_eq!(redis.rpush("myziplist1", &["cc"]).await.unwrap(), 3);
+    assert_eq!(redis.llen("myziplist1").await.unwrap(), 3);
+    for (index, expected) in [Some("aa"), Some("bb"), Some("cc"), None]
+        .iter()
+        .enumerate()
+    {
+        let value: Option<String> = redis.lindex("myziplist1", index as i64).await.unwrap();
+        assert_eq!(value.as_deref(), *expected);
+    }
+    assert_eq!(redis.rpop::<_, String>("myziplist1").await.unwrap(), "cc");
+    assert_eq!(redis.lpop::<_, String>("myziplist1").await.unwrap(), "aa");
+
+    // first rpush then lpush
+    assert_eq!(redis.rpush("myziplist2", &["a"]).await.unwrap(), 1);
+    assert_eq!(redis.lpush("myziplist2", &["b"]).await.unwrap(), 2);
+    assert_eq!(redis.lpush("myziplist2", &["c"]).await.unwrap(), 3);
+    assert_eq!(redis.llen("myziplist2").await.unwrap(), 3);
+    for (index, expected) in [Some("c"), Some("b"), Some("a"), None].iter().enumerate() {
+        let value: Option<String> = redis.lindex("myziplist2", index as i64).await.unwrap();
+        assert_eq!(value.as_deref(), *expected);
+    }
+    assert_eq!(redis.rpop::<_, String>("myziplist2").await.unwrap(), "a");
+    assert_eq!(redis.lpop::<_, String>("myziplist2").await.unwrap(), "c");
+
+    server.stop().await;
+}
+
+mod push {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_list() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.lpush("x", &["abc"]).await.unwrap(), 1);
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 1);
+
+        assert_eq!(redis.rpush("y", &["abc"]).await.unwrap(), 1);
+        assert_eq!(redis.exists(&["y"]).await.unwrap(), 1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn lpush_adds_to_the_left() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.rpush("x", &["1"]).await.unwrap(), 1);
+        assert_eq!(redis.lpush("x", &["2"]).await.unwrap(), 2);
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["2", "1"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rpush_adds_to_the_right() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.lpush("x", &["1"]).await.unwrap(), 1);
+        assert_eq!(redis.rpush("x", &["2"]).await.unwrap(), 2);
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["1", "2"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn lpush_pushes_several_elements() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.lpush("x", &["5", "4"]).await.unwrap(), 2);
+        assert_eq!(redis.lpush("x", &["3", "2", "1"]).await.unwrap(), 5);
+        assert_eq!(
+            lrange(&mut redis, "x", 0, -1).await,
+            ["1", "2", "3", "4", "5"]
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rpush_pushes_several_elements() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.rpush("x", &["1", "2"]).await.unwrap(), 2);
+        assert_eq!(redis.rpush("x", &["3", "4", "5"]).await.unwrap(), 5);
+        assert_eq!(
+            lrange(&mut redis, "x", 0, -1).await,
+            ["1", "2", "3", "4", "5"]
+        );
+
+        server.stop().await;
+    }
+}
+
+mod llen {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_0_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.llen("x").await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_length() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &[1, 2, 3]).await.unwrap();
+        assert_eq!(redis.llen("x").await.unwrap(), 3);
+        redis.rpop::<_, String>("x").await.unwrap();
+        assert_eq!(redis.llen("x").await.unwrap(), 2);
+
+        server.stop().await;
+    }
+}
+
+mod lrange {
+    use super::*;
+
+    /// Checks LRANGE of the list 1, 2, 3 for each of the ranges given
+    async fn check_ranges(ranges: &[(i64, i64, &[&str])]) {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        redis.rpush("x", &[1, 2, 3]).await.unwrap();
+
+        for (start, stop, expected) in ranges {
+            assert_eq!(
+                lrange(&mut redis, "x", *start, *stop).await,
+                *expected,
+                "LRANGE x {} {}",
+                start,
+                stop
+            );
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_an_empty_list_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert!(lrange(&mut redis, "x", 0, -1).await.is_empty());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn ranging_over_the_start_index() {
+        check_ranges(&[
+            (-4, 2, &["1", "2", "3"]),
+            (-3, 2, &["1", "2", "3"]),
+            (-2, 2, &["2", "3"]),
+            (-1, 2, &["3"]),
+            (0, 2, &["1", "2", "3"]),
+            (1, 2, &["2", "3"]),
+            (2, 2, &["3"]),
+            (3, 2, &[]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn ranging_over_the_end_index() {
+        check_ranges(&[
+            (0, -4, &[]),
+            (0, -3, &["1"]),
+            (0, -2, &["1", "2"]),
+            (0, -1, &["1", "2", "3"]),
+            (0, 0, &["1"]),
+            (0, 1, &["1", "2"]),
+            (0, 2, &["1", "2", "3"]),
+            (0, 3, &["1", "2", "3"]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn ranging_over_both_in_lock_step() {
+        check_ranges(&[
+            (-4, -4, &[]),
+            (-3, -3, &["1"]),
+            (-2, -2, &["2"]),
+            (-1, -1, &["3"]),
+            (0, 0, &["1"]),
+            (1, 1, &["2"]),
+            (2, 2, &["3"]),
+            (3, 3, &[]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn start_past_the_end() {
+        check_ranges(&[(2, 1, &[]), (4, 4, &[]), (4, 5, &[])]).await;
+    }
+}
+
+mod linsert {
+    use super::*;
+
+    #[tokio::test]
+    async fn does_nothing_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(
+            linsert(&mut redis, "x", "BEFORE", "1", "0").await.unwrap(),
+            0
+        );
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn inserts_and_returns_the_length() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "4"]).await.unwrap();
+        assert_eq!(
+            linsert(&mut redis, "x", "BEFORE", "1", "0").await.unwrap(),
+            3
+        );
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["0", "1", "4"]);
+        assert_eq!(
+            linsert(&mut redis, "x", "AFTER", "1", "2").await.unwrap(),
+            4
+        );
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["0", "1", "2", "4"]);
+        assert_eq!(
+            linsert(&mut redis, "x", "BEFore", "4", "3").await.unwrap(),
+            5
+        );
+        assert_eq!(
+            lrange(&mut redis, "x", 0, -1).await,
+            ["0", "1", "2", "3", "4"]
+        );
+        assert_eq!(
+            linsert(&mut redis, "x", "AFter", "4", "5").await.unwrap(),
+            6
+        );
+        assert_eq!(
+            lrange(&mut redis, "x", 0, -1).await,
+            ["0", "1", "2", "3", "4", "5"]
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_position_other_than_before_or_after() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1"]).await.unwrap();
+        assert_error(
+            linsert(&mut redis, "x", "bang", "1", "0").await,
+            "ERR syntax error",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_minus_1_when_the_pivot_is_not_found() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1"]).await.unwrap();
+        assert_eq!(
+            linsert(&mut redis, "x", "BEFORE", "7", "0").await.unwrap(),
+            -1
+        );
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["1"]);
+
+        server.stop().await;
+    }
+}
+
+mod pop {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_nil_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.lpop::<_, Option<String>>("x").await.unwrap(), None);
+        assert_eq!(redis.rpop::<_, Option<String>>("x").await.unwrap(), None);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn lpop_removes_the_leftmost_element() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "2"]).await.unwrap();
+        assert_eq!(redis.lpop::<_, String>("x").await.unwrap(), "1");
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["2"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rpop_removes_the_rightmost_element() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "2"]).await.unwrap();
+        assert_eq!(redis.rpop::<_, String>("x").await.unwrap(), "2");
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["1"]);
+
+        server.stop().await;
+    }
+}
+
+mod lindex {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_nil_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(
+            redis.lindex::<_, Option<String>>("x", 0).await.unwrap(),
+            None
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_value_or_nil_when_out_of_range() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "2"]).await.unwrap();
+        for (index, expected) in &[(0, Some("1")), (1, Some("2")), (2, None)] {
+            let value: Option<String> = redis.lindex("x", *index).await.unwrap();
+            assert_eq!(value.as_deref(), *expected);
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn counts_negative_indexes_from_the_end() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "2"]).await.unwrap();
+        for (index, expected) in &[(-1, Some("2")), (-2, Some("1")), (-3, None)] {
+            let value: Option<String> = redis.lindex("x", *index).await.unwrap();
+            assert_eq!(value.as_deref(), *expected);
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_an_index_that_is_not_a_number() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1"]).await.unwrap();
+        assert_error(
+            cmd("LINDEX")
+                .arg("x")
+                .arg("bang")
+                .query::<Option<String>>(&mut redis)
+                .await,
+            "ERR value is not an integer or out of range",
+        );
+
+        server.stop().await;
+    }
+}
+
+mod lset {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(redis.lset("x", 0, 1).await, "ERR no such key");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn sets_the_value_at_the_index() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "2"]).await.unwrap();
+        redis.lset("x", 0, "one").await.unwrap();
+        redis.lset("x", 1, "two").await.unwrap();
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["one", "two"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn counts_negative_indexes_from_the_end() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1", "2"]).await.unwrap();
+        redis.lset("x", -1, "two").await.unwrap();
+        redis.lset("x", -2, "one").await.unwrap();
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["one", "two"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_an_index_that_is_not_a_number() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1"]).await.unwrap();
+        assert_error(
+            cmd("LSET")
+                .arg("x")
+                .arg("bang")
+                .arg("one")
+                .query::<()>(&mut redis)
+                .await,
+            "ERR value is not an integer or out of range",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_an_index_out_of_range() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("x", &["1"]).await.unwrap();
+        assert_error(redis.lset("x", 1, "one").await, "ERR index out of range");
+        assert_error(redis.lset("x", -2, "one").await, "ERR index out of range");
+
+        server.stop().await;
+    }
+}
+
+mod ltrim {
+    use super::*;
+    use redis_clone_client::Pipeline;
+
+    /// Trims the list 1, 2, 3 and returns what is left of it
+    async fn trim_list(redis: &mut Connection, start: i64, stop: i64) -> Vec<String> {
+        let (_, _, trimmed, list): (i64, i64, String, Vec<String>) = Pipeline::new()
+            .cmd(cmd("DEL").arg("mylist"))
+            .cmd(cmd("RPUSH").arg("mylist").args(&["1", "2", "3"]))
+            .cmd(cmd("LTRIM").arg("mylist").arg(start).arg(stop))
+            .cmd(cmd("LRANGE").arg("mylist").arg(0).arg(-1))
+            .query(redis)
+            .await
+            .unwrap();
+
+        assert_eq!(trimmed, "OK");
+        list
+    }
+
+    /// Checks what is left of the list 1, 2, 3 trimmed to each of the ranges
+    /// given
+    async fn check_trims(ranges: &[(i64, i64, &[&str])]) {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        for (start, stop, expected) in ranges {
+            assert_eq!(
+                trim_list(&mut redis, *start, *stop).await,
+                *expected,
+                "LTRIM mylist {} {}",
+                start,
+                stop
+            );
+        }
+
+        server.stop().await;
+    }
+
+    /// Checks trimming the list to each of the ranges given removes it
+    async fn check_removed(ranges: &[(i64, i64)]) {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        for (start, stop) in ranges {
+            assert!(trim_list(&mut redis, *start, *stop).await.is_empty());
+            assert_eq!(redis.exists(&["mylist"]).await.unwrap(), 0);
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_ok_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.ltrim("x", 0, -1).await.unwrap();
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn ranging_over_the_start_index() {
+        check_trims(&[
+            (-4, -1, &["1", "2", "3"]),
+            (-3, -1, &["1", "2", "3"]),
+            (-2, -1, &["2", "3"]),
+            (-1, -1, &["3"]),
+            (0, -1, &["1", "2", "3"]),
+            (1, -1, &["2", "3"]),
+            (2, -1, &["3"]),
+            (3, -1, &[]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn ranging_over_the_last_index() {
+        check_trims(&[
+            (0, -4, &[]),
+            (0, -3, &["1"]),
+            (0, -2, &["1", "2"]),
+            (0, -1, &["1", "2", "3"]),
+            (0, 0, &["1"]),
+            (0, 1, &["1", "2"]),
+            (0, 2, &["1", "2", "3"]),
+            (0, 3, &["1", "2", "3"]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn ranging_over_both_in_lock_step() {
+        check_trims(&[
+            (-5, -4, &[]),
+            (-4, -3, &["1"]),
+            (-3, -2, &["1", "2"]),
+            (-2, -1, &["2", "3"]),
+            (-1, 0, &[]),
+            (0, 1, &["1", "2"]),
+            (1, 2, &["2", "3"]),
+            (2, 3, &["3"]),
+            (3, 4, &[]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn removes_the_key_when_start_is_past_end() {
+        check_removed(&[(0, -4), (4, 3), (2, 1)]).await;
+    }
+
+    #[tokio::test]
+    async fn removes_the_key_when_both_are_past_the_length() {
+        check_removed(&[(3, 3)]).await;
+    }
+
+    #[tokio::test]
+    async fn removes_the_key_when_both_are_before_the_start() {
+        check_removed(&[(-4, -4)]).await;
+    }
+}
+
+mod lrem {
+    use super::*;
+
+    const LIST: &[&str] = &[
+        "foo", "bar", "foobar", "foobared", "zap", "bar", "test", "foo",
+    ];
+
+    #[tokio::test]
+    async fn returns_0_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.lrem("x", 0, "blah").await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_all_occurrences() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("mylist", LIST).await.unwrap();
+        assert_eq!(redis.lrem("mylist", 0, "bar").await.unwrap(), 2);
+        assert_eq!(
+            lrange(&mut redis, "mylist", 0, -1).await,
+            ["foo", "foobar", "foobared", "zap", "test", "foo"]
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_the_first_occurrence() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("mylist", LIST).await.unwrap();
+        assert_eq!(redis.lrem("mylist", 1, "bar").await.unwrap(), 1);
+        assert_eq!(
+            lrange(&mut redis, "mylist", 0, -1).await,
+            ["foo", "foobar", "foobared", "zap", "bar", "test", "foo"]
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_nothing_when_the_element_is_missing() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("mylist", LIST).await.unwrap();
+        assert_eq!(redis.lrem("mylist", 1, "not-in-the-list").await.unwrap(), 0);
+        assert_eq!(lrange(&mut redis, "mylist", 0, -1).await, LIST);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn removes_from_the_back() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.rpush("mylist", LIST).await.unwrap();
+        assert_eq!(redis.lrem("mylist", -1, "bar").await.unwrap(), 1);
+        assert_eq!(
+            lrange(&mut redis, "mylist", 0, -1).await,
+            ["foo", "bar", "foobar", "foobared", "zap", "test", "foo"]
+        );
+        assert_eq!(redis.lrem("mylist", -2, "foo").await.unwrap(), 2);
+        assert_eq!(
+            lrange(&mut redis, "mylist", 0, -1).await,
+            ["bar", "foobar", "foobared", "zap", "test"]
+        );
+
+        server.stop().await;
+    }
+}
+
+mod encoding {
+    use super::*;
+
+    async fn start() -> (TestServer, Connection) {
+        let mut server = TestServer::start().await;
+        server.preserve_config(&["list-max-listpack-size"]).await;
+        let redis = server.connect().await;
+        (server, redis)
+    }
+
+    #[tokio::test]
+    async fn packs_small_lists_into_a_listpack() {
+        let (server, mut redis) = start().await;
+
+        redis.rpush("x", &["a", "b", "c"]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "listpack");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn converts_to_a_quicklist_past_the_entries_limit() {
+        let (server, mut redis) = start().await;
+
+        redis.config_set("list-max-listpack-size", 3).await.unwrap();
+        redis.rpush("x", &["a", "b", "c"]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "listpack");
+
+        redis.lpush("x", &["d"]).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "quicklist");
+        assert_eq!(lrange(&mut redis, "x", 0, -1).await, ["d", "a", "b", "c"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn converts_to_a_quicklist_past_the_size_limit() {
+        let (server, mut redis) = start().await;
+
+        redis
+            .config_set("list-max-listpack-size", -1)
+            .await
+            .unwrap();
+        redis.rpush("x", &["a"]).await.unwrap();
+        let long = "x".repeat(5000);
+        redis.lset("x", 0, &long).await.unwrap();
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "quicklist");
+        assert_eq!(redis.lindex::<_, String>("x", 0).await.unwrap(), long);
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,114 @@
+//! Scripts and function libraries, as they run alongside other clients and
+//! as they are kept across restarts
+
+mod common;
+
+use common::*;
+use redis_clone::config::Config;
+use redis_clone_client::{cmd, Value};
+use std::{fs, path::Path, time::Duration};
+use tokio::time::{sleep, timeout};
+
+const LIBRARY: &str = "#!lua name=mylib
+redis.register_function('echo', function(keys, args) return args[1] end)";
+
+const BUSY: &str =
+    "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE.";
+
+#[tokio::test]
+async fn commands_sent_before_the_time_limit_are_answered_busy() {
+    let mut server = TestServer::start().await;
+    server.preserve_config(&["lua-time-limit"]).await;
+    let mut redis = server.connect().await;
+    redis.config_set("lua-time-limit", 200).await.unwrap();
+
+    let mut script = server.connect().await;
+    script
+        .send(&[cmd("EVAL").arg("while true do end").arg(0)])
+        .await
+        .unwrap();
+
+    // Sent while the script is running, but before it is deemed busy
+    sleep(Duration::from_millis(50)).await;
+    let mut other = server.connect().await;
+    other.send(&[cmd("PING")]).await.unwrap();
+    let reply = timeout(Duration::from_secs(2), other.receive())
+        .await
+        .expect("no reply once the time limit passed");
+    assert_eq!(reply.unwrap(), Value::Error(BUSY.to_owned()));
+
+    let killed: String = cmd("SCRIPT").arg("kill").query(&mut other).await.unwrap();
+    assert_eq!(killed, "OK");
+    match script.receive().await.unwrap() {
+        Value::Error(msg) => assert!(msg.contains("Script killed by user"), "{}", msg),
+        other => panic!("expected the script to be killed, got {:?}", other),
+    }
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn function_libraries_are_saved_with_the_dataset() {
+    let server = TestServer::start().await;
+    if server.is_real_redis() {
+        return server.stop().await;
+    }
+    let mut redis = server.connect().await;
+
+    let name: String = cmd("FUNCTION")
+        .arg("load")
+        .arg(LIBRARY)
+        .query(&mut redis)
+        .await
+        .unwrap();
+    assert_eq!(name, "mylib");
+
+    // Loading a library doesn't save the dataset, shutting down does
+    let path = Path::new(&config_get(&mut redis, "dir").await).join("dump.rdb");
+    assert!(!path.exists());
+    drop(redis);
+
+    let server = server.restart().await;
+    let rdb = fs::read(path).unwrap();
+    assert!(rdb.starts_with(b"REDIS"));
+    assert!(rdb
+        .windows(LIBRARY.len())
+        .any(|code| code == LIBRARY.as_bytes()));
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn function_libraries_are_loaded_on_every_shard_at_startup() {
+    let mut config = Config::default();
+    config.shards = 4;
+    let server = TestServer::start_with(config).await;
+    if server.is_real_redis() {
+        return server.stop().await;
+    }
+    let mut redis = server.connect().await;
+    cmd("FUNCTION")
+        .arg("load")
+        .arg(LIBRARY)
+        .query::<String>(&mut redis)
+        .await
+        .unwrap();
+    drop(redis);
+
+    let server = server.restart().await;
+    let mut redis = server.connect().await;
+    // Keys spread across the shards, each running FCALL for its own
+    for n in 0..16 {
+        let echoed: String = cmd("FCALL")
+            .arg("echo")
+            .arg(1)
+            .arg(format!("key:{}", n))
+            .arg("hi")
+            .query(&mut redis)
+            .await
+            .unwrap();
+        assert_eq!(echoed, "hi");
+    }
+
+    server.stop().await;
+}
@@ -0,0 +1,1014 @@
+//! Ported from tests/functional/server_spec.rb
+
+mod common;
+
+use common::*;
+use redis_clone_client::{cmd, Connection, FromValue, Value};
+use std::{collections::HashMap, fs, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    net::TcpSocket,
+    time::{sleep, Instant},
+};
+
+async fn command<T: FromValue>(redis: &mut Connection, args: &[&str]) -> T {
+    cmd("COMMAND").args(args).query(redis).await.unwrap()
+}
+
+/// The name of a command in the reply to COMMAND or COMMAND INFO
+fn command_name(entry: &Value) -> &[u8] {
+    match entry {
+        Value::Array(fields) => fields[0].as_bytes().unwrap(),
+        other => panic!("expected a command, got {:?}", other),
+    }
+}
+
+async fn redis_5_or_older(redis: &mut Connection) -> bool {
+    let server = info(redis, Some("server")).await;
+    let major = server["redis_version"].split('.').next().unwrap();
+    major.parse::<u32>().unwrap() <= 5
+}
+
+#[tokio::test]
+async fn arity() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    // Real Redis v5 wrongly report an arity of 0 for COMMAND. This has been
+    // fixed for upcoming Redis 6
+    // See: https://github.com/antirez/redis/commit/385f6190a3a9f8d2d5775bd058aaa2173dc05c8c
+    let command_arity_expected = if server.is_real_redis() && redis_5_or_older(&mut redis).await {
+        0
+    } else {
+        -1
+    };
+    assert_eq!(
+        command_arity(&mut redis, "command").await,
+        command_arity_expected
+    );
+    assert_eq!(command_arity(&mut redis, "debug").await, -2);
+    assert_eq!(command_arity(&mut redis, "flushdb").await, -1);
+
+    server.stop().await;
+}
+
+mod command {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_the_supported_commands() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let commands: Vec<Value> = command(&mut redis, &[]).await;
+        let count: usize = command(&mut redis, &["count"]).await;
+        assert_eq!(commands.len(), count);
+
+        let set: Vec<Value> = commands
+            .into_iter()
+            .find(|entry| command_name(entry) == b"set")
+            .map(|entry| Vec::from_value(entry).unwrap())
+            .unwrap();
+        assert_eq!(set[1], Value::Integer(-3));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn help() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let help: Vec<String> = command(&mut redis, &["help"]).await;
+        assert_eq!(help.len(), 5);
+        assert_eq!(
+            help[0],
+            "COMMAND <subcommand> arg arg ... arg. Subcommands are:"
+        );
+        assert!(help[1].starts_with("(no subcommand)"));
+        assert!(help[2].starts_with("COUNT"));
+        assert!(help[3].starts_with("GETKEYS"));
+        assert!(help[4].starts_with("INFO"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn count() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let count: i64 = command(&mut redis, &["count"]).await;
+        assert!(count > 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn info_returns_the_commands_requested() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let info: Vec<Value> = command(&mut redis, &["info", "set", "get"]).await;
+        assert_eq!(info.len(), 2);
+        assert_eq!(command_name(&info[0]), b"set");
+        assert_eq!(command_name(&info[1]), b"get");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn info_of_no_commands_is_empty() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let info: Vec<Value> = command(&mut redis, &["info"]).await;
+        assert!(info.is_empty());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn info_of_an_unknown_command_is_nil() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let info: Vec<Value> = command(&mut redis, &["info", "xxx", "set"]).await;
+        assert_eq!(info.len(), 2);
+        assert_eq!(info[0], Value::Nil);
+        assert_eq!(command_name(&info[1]), b"set");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn info_returns_the_first_key_last_key_and_step() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let info: Vec<Vec<Value>> = command(&mut redis, &["info", "get", "mset", "eval"]).await;
+        let keys: Vec<Vec<i64>> = info
+            .into_iter()
+            .map(|fields| Vec::from_value(Value::Array(fields[3..6].to_vec())).unwrap())
+            .collect();
+        assert_eq!(keys, [[1, 1, 1], [1, -1, 2], [0, 0, 0]]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn getkeys_returns_the_keys_of_the_command() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let keys: Vec<String> = command(&mut redis, &["getkeys", "mset", "a", "1", "b", "2"]).await;
+        assert_eq!(keys, ["a", "b"]);
+        let keys: Vec<String> = command(
+            &mut redis,
+            &["getkeys", "eval", "return 1", "2", "a", "b", "c"],
+        )
+        .await;
+        assert_eq!(keys, ["a", "b"]);
+        let keys: Vec<String> = command(
+            &mut redis,
+            &["getkeys", "xread", "streams", "s1", "s2", "0", "0"],
+        )
+        .await;
+        assert_eq!(keys, ["s1", "s2"]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn getkeys_rejects_commands_without_keys_unknown_commands_and_wrong_arities() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let getkeys = |args: &[&str]| cmd("COMMAND").arg("getkeys").args(args);
+        assert_error(
+            getkeys(&["info"]).query::<Value>(&mut redis).await,
+            "ERR The command has no key arguments",
+        );
+        assert_error(
+            getkeys(&["xxx"]).query::<Value>(&mut redis).await,
+            "ERR Invalid command specified",
+        );
+        assert_error(
+            getkeys(&["get"]).query::<Value>(&mut redis).await,
+            "ERR Invalid number of arguments specified for command",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_an_unknown_subcommand() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error_starts_with(
+            cmd("COMMAND").arg("xyz").query::<Value>(&mut redis).await,
+            "ERR Unknown subcommand or wrong number of arguments for 'xyz'",
+        );
+
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn debug_help() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    let help: Vec<String> = cmd("DEBUG").arg("help").query(&mut redis).await.unwrap();
+    assert!(help.len() > 1);
+    assert_eq!(
+        help[0],
+        "DEBUG <subcommand> arg arg ... arg. Subcommands are:"
+    );
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn flushdb_deletes_all_the_keys() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    redis.set("x", "123").await.unwrap();
+    redis.set("y", "456").await.unwrap();
+
+    redis.flushdb().await.unwrap();
+
+    assert_eq!(redis.get::<_, Option<String>>("x").await.unwrap(), None);
+    assert_eq!(redis.get::<_, Option<String>>("y").await.unwrap(), None);
+
+    server.stop().await;
+}
+
+mod info {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_the_default_sections() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let info = info(&mut redis, None).await;
+        for field in &[
+            "redis_version",
+            "connected_clients",
+            "used_memory",
+            "total_commands_processed",
+            "keyspace_hits",
+        ] {
+            assert!(info.contains_key(*field), "{} is missing", field);
+        }
+        assert!(!info.contains_key("cmdstat_info"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn filters_by_section() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let output = redis.info(&["clients"]).await.unwrap();
+        assert!(output.starts_with("# Clients"));
+        assert!(!output.contains("# Server"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn counts_keyspace_hits_and_misses() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let before = info(&mut redis, Some("stats")).await;
+        redis.set("x", "1").await.unwrap();
+        redis.get::<_, Option<String>>("x").await.unwrap();
+        redis.get::<_, Option<String>>("y").await.unwrap();
+        let after = info(&mut redis, Some("stats")).await;
+
+        let change = |field: &str| {
+            after[field].parse::<i64>().unwrap() - before[field].parse::<i64>().unwrap()
+        };
+        assert_eq!(change("keyspace_hits"), 1);
+        assert_eq!(change("keyspace_misses"), 1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reports_the_keys_and_expires_per_database() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "1").await.unwrap();
+        redis.set_ex("y", "2", 100).await.unwrap();
+        let keyspace = info(&mut redis, Some("keyspace")).await;
+        assert!(keyspace["db0"].starts_with("keys=2,expires=1"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reports_per_command_statistics() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.get::<_, Option<String>>("x").await.unwrap();
+        let commandstats = info(&mut redis, Some("commandstats")).await;
+        let stats: HashMap<&str, &str> = commandstats["cmdstat_get"]
+            .split(',')
+            .filter_map(|stat| stat.split_once('='))
+            .collect();
+        assert!(stats["calls"].parse::<i64>().unwrap() >= 1);
+        assert!(stats.contains_key("usec"));
+        assert!(stats.contains_key("usec_per_call"));
+
+        server.stop().await;
+    }
+}
+
+mod config {
+    use super::*;
+
+    #[tokio::test]
+    async fn get_returns_the_parameters_matching_a_pattern() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let config: HashMap<String, String> = redis.config_get("maxmemory*").await.unwrap();
+        assert!(config.contains_key("maxmemory"));
+        assert!(config.contains_key("maxmemory-policy"));
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn get_of_an_unknown_parameter_is_empty() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let config: HashMap<String, String> = redis.config_get("xyz").await.unwrap();
+        assert!(config.is_empty());
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn set_changes_a_parameter() {
+        let mut server = TestServer::start().await;
+        server.preserve_config(&["maxmemory"]).await;
+        let mut redis = server.connect().await;
+
+        redis.config_set("maxmemory", "100mb").await.unwrap();
+        assert_eq!(config_get(&mut redis, "maxmemory").await, "104857600");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn set_rejects_invalid_values() {
+        let mut server = TestServer::start().await;
+        server.preserve_config(&["maxmemory"]).await;
+        let mut redis = server.connect().await;
+
+        assert_error_starts_with(
+            redis.config_set("maxmemory", "lots").await,
+            "ERR CONFIG SET failed (possibly related to argument 'maxmemory')",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn resetstat_resets_the_statistics() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.get::<_, Option<String>>("x").await.unwrap();
+        let reply: String = cmd("CONFIG")
+            .arg("resetstat")
+            .query(&mut redis)
+            .await
+            .unwrap();
+        assert_eq!(reply, "OK");
+        assert_eq!(
+            info(&mut redis, Some("stats")).await["keyspace_misses"],
+            "0"
+        );
+
+        server.stop().await;
+    }
+}
+
+mod client_output_buffer_limit {
+    use super::*;
+
+    const BIG: usize = 20 * 1024 * 1024;
+
+    async fn start() -> (TestServer, Connection) {
+        let mut server = TestServer::start().await;
+        server
+            .preserve_config(&["client-output-buffer-limit"])
+            .await;
+        let redis = server.connect().await;
+        (server, redis)
+    }
+
+    #[tokio::test]
+    async fn sets_the_limits_of_the_classes_given() {
+        let (server, mut redis) = start().await;
+
+        redis
+            .config_set("client-output-buffer-limit", "normal 1mb 512kb 10")
+            .await
+            .unwrap();
+        assert_eq!(
+            config_get(&mut redis, "client-output-buffer-limit").await,
+            "normal 1048576 524288 10 slave 268435456 67108864 60 pubsub 33554432 8388608 60"
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn disconnects_a_client_that_does_not_read_past_the_hard_limit() {
+        let (server, mut redis) = start().await;
+
+        redis.set("big", "x".repeat(BIG)).await.unwrap();
+        redis
+            .config_set("client-output-buffer-limit", "normal 1mb 0 0")
+            .await
+            .unwrap();
+
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.set_recv_buffer_size(4096).unwrap();
+        let mut stream = socket
+            .connect(server.addr().parse().unwrap())
+            .await
+            .unwrap();
+        stream
+            .write_all(b"*2\r\n$3\r\nGET\r\n$3\r\nbig\r\n")
+            .await
+            .unwrap();
+
+        // The client is closed once its reply has filled the buffer
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let clients: String = cmd("CLIENT").arg("list").query(&mut redis).await.unwrap();
+            if !clients.contains("cmd=get") {
+                break;
+            }
+            assert!(Instant::now() < deadline, "{}", clients);
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn streams_large_replies_to_clients_that_keep_up() {
+        let (server, mut redis) = start().await;
+
+        redis.set("big", "x".repeat(BIG)).await.unwrap();
+        redis
+            .config_set("client-output-buffer-limit", "normal 64mb 0 0")
+            .await
+            .unwrap();
+
+        assert_eq!(redis.get::<_, Vec<u8>>("big").await.unwrap().len(), BIG);
+
+        server.stop().await;
+    }
+}
+
+mod slowlog {
+    use super::*;
+
+    async fn start() -> (TestServer, Connection) {
+        let mut server = TestServer::start().await;
+        server.preserve_config(&["slowlog-log-slower-than"]).await;
+        let mut redis = server.connect().await;
+        redis
+            .config_set("slowlog-log-slower-than", 0)
+            .await
+            .unwrap();
+        cmd("SLOWLOG")
+            .arg("reset")
+            .query::<()>(&mut redis)
+            .await
+            .unwrap();
+        (server, redis)
+    }
+
+    /// Sets x, spelling the command in lowercase as the log echoes it as sent
+    async fn set(redis: &mut Connection, value: &str) {
+        cmd("set")
+            .arg("x")
+            .arg(value)
+            .query::<()>(redis)
+            .await
+            .unwrap();
+    }
+
+    /// The arguments and duration of the latest SET logged
+    async fn logged_set(redis: &mut Connection) -> (Vec<String>, i64) {
+        let entries: Vec<Vec<Value>> = cmd("SLOWLOG").arg("get").arg(2).query(redis).await.unwrap();
+        entries
+            .into_iter()
+            .map(|entry| {
+                let args = Vec::<String>::from_value(entry[3].clone()).unwrap();
+                let duration = i64::from_value(entry[2].clone()).unwrap();
+                (args, duration)
+            })
+            .find(|(args, _)| args[0] == "set")
+            .expect("SET was not logged")
+    }
+
+    #[tokio::test]
+    async fn records_commands_slower_than_the_threshold() {
+        let (server, mut redis) = start().await;
+
+        set(&mut redis, "1").await;
+        let (args, duration) = logged_set(&mut redis).await;
+        assert_eq!(args, ["set", "x", "1"]);
+        assert!(duration >= 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reports_the_number_of_entries() {
+        let (server, mut redis) = start().await;
+
+        redis.get::<_, Option<String>>("x").await.unwrap();
+        let len: i64 = cmd("SLOWLOG").arg("len").query(&mut redis).await.unwrap();
+        assert!(len >= 1);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn truncates_long_arguments() {
+        let (server, mut redis) = start().await;
+
+        set(&mut redis, &"a".repeat(200)).await;
+        let (args, _) = logged_set(&mut redis).await;
+        assert_eq!(args[2], format!("{}... (72 more bytes)", "a".repeat(128)));
+
+        server.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn monitor_streams_the_commands_processed() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    let mut monitor = server.connect().await;
+    monitor.send(&[cmd("MONITOR")]).await.unwrap();
+    assert_eq!(
+        monitor.receive().await.unwrap(),
+        Value::SimpleString("OK".to_owned())
+    );
+
+    // Spelled in lowercase, as MONITOR echoes commands as they were sent
+    cmd("set")
+        .arg("x")
+        .arg("a\nb")
+        .query::<()>(&mut redis)
+        .await
+        .unwrap();
+    cmd("get")
+        .arg("x")
+        .query::<String>(&mut redis)
+        .await
+        .unwrap();
+
+    let set = String::from_value(monitor.receive().await.unwrap()).unwrap();
+    let get = String::from_value(monitor.receive().await.unwrap()).unwrap();
+
+    // Such as 1700000000.123456 [0 127.0.0.1:50000] "set" "x" "a\nb"
+    let (timestamp, rest) = set.split_once(' ').unwrap();
+    let (seconds, micros) = timestamp.split_once('.').unwrap();
+    assert!(seconds.parse::<u64>().is_ok());
+    assert!(micros.len() == 6 && micros.parse::<u32>().is_ok());
+    let (client, command) = rest.split_once("] ").unwrap();
+    assert!(client.starts_with("[0 "), "{}", set);
+    assert_eq!(command, r#""set" "x" "a\nb""#);
+    assert!(get.ends_with(r#""get" "x""#), "{}", get);
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn maxclients_refuses_connections_beyond_the_limit() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+    let original = config_get(&mut redis, "maxclients").await;
+
+    let connected = info(&mut redis, Some("clients")).await["connected_clients"].clone();
+    redis.config_set("maxclients", &connected).await.unwrap();
+
+    let mut refused = server.connect().await;
+    assert_error(
+        refused.get::<_, Option<String>>("x").await,
+        "ERR max number of clients reached",
+    );
+
+    // Restored on the connection that was let in, as another wouldn't be
+    redis.config_set("maxclients", original).await.unwrap();
+    drop(redis);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn timeout_closes_clients_left_idle() {
+    let mut server = TestServer::start().await;
+    server.preserve_config(&["timeout"]).await;
+    let mut redis = server.connect().await;
+
+    let mut idle = server.connect().await;
+    let id: i64 = cmd("CLIENT").arg("id").query(&mut idle).await.unwrap();
+    redis.config_set("timeout", 1).await.unwrap();
+    sleep(Duration::from_millis(2500)).await;
+
+    let mut other = server.connect().await;
+    let clients: String = cmd("CLIENT").arg("list").query(&mut other).await.unwrap();
+    assert!(!clients.contains(&format!("id={} ", id)), "{}", clients);
+
+    server.stop().await;
+}
+
+mod shutdown {
+    use super::*;
+
+    #[tokio::test]
+    async fn has_no_shutdown_to_abort() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            cmd("SHUTDOWN").arg("abort").query::<()>(&mut redis).await,
+            "ERR No shutdown in progress.",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_abort_along_with_other_flags() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            cmd("SHUTDOWN")
+                .arg("abort")
+                .arg("now")
+                .query::<()>(&mut redis)
+                .await,
+            "ERR syntax error",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_flags() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            cmd("SHUTDOWN").arg("later").query::<()>(&mut redis).await,
+            "ERR syntax error",
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn refuses_to_exit_when_the_dataset_cant_be_saved() {
+        let mut server = TestServer::start().await;
+        server.preserve_config(&["dir"]).await;
+        let mut redis = server.connect().await;
+
+        // The directory is gone by the time the dataset is saved to it
+        let dir = std::env::temp_dir().join(format!("redis-clone-gone-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        redis
+            .config_set("dir", dir.to_str().unwrap())
+            .await
+            .unwrap();
+        fs::remove_dir(&dir).unwrap();
+
+        assert_error(
+            cmd("SHUTDOWN").arg("save").query::<()>(&mut redis).await,
+            "ERR Errors trying to SHUTDOWN. Check logs.",
+        );
+        // Still running
+        redis.set("key", "value").await.unwrap();
+
+        server.stop().await;
+    }
+
+    /// Writes a key of each type, with a `suffix` to spread them across the
+    /// shards when there are many
+    async fn write_every_type(redis: &mut Connection, suffix: &str) {
+        let key = |name: &str| format!("{}{}", name, suffix);
+        let commands = vec![
+            cmd("SET").arg(key("string")).arg("hello"),
+            cmd("SET").arg(key("int")).arg(42),
+            cmd("SET")
+                .arg(key("expiring"))
+                .arg("soon")
+                .arg("PX")
+                .arg(100_000),
+            cmd("SET").arg(key("expired")).arg("gone").arg("PX").arg(1),
+            cmd("RPUSH").arg(key("list")).args(["a", "b", "c"]),
+            cmd("HSET").arg(key("hash")).args(["f", "v", "g", "w"]),
+            cmd("GEOADD")
+                .arg(key("geo"))
+                .args(["13.361389", "38.115556", "Palermo"]),
+            cmd("XADD").arg(key("stream")).args(["1-1", "f", "v"]),
+            cmd("XADD").arg(key("stream")).args(["2-1", "g", "w"]),
+            cmd("XGROUP")
+                .arg("CREATE")
+                .arg(key("stream"))
+                .args(["group", "0"]),
+            cmd("XREADGROUP")
+                .args(["GROUP", "group", "alice", "COUNT", "1", "STREAMS"])
+                .arg(key("stream"))
+                .arg(">"),
+        ];
+        for command in &commands {
+            redis.query::<Value>(command).await.unwrap();
+        }
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    async fn check_every_type(redis: &mut Connection, suffix: &str) {
+        let key = |name: &str| format!("{}{}", name, suffix);
+
+        let string: String = redis.get(key("string")).await.unwrap();
+        assert_eq!(string, "hello");
+        let int: i64 = redis.get(key("int")).await.unwrap();
+        assert_eq!(int, 42);
+        let ttl = redis.ttl(key("expiring")).await.unwrap();
+        assert!(ttl > 90 && ttl <= 100, "{}", ttl);
+        assert_eq!(redis.exists(&[key("expired")]).await.unwrap(), 0);
+
+        let list: Vec<String> = redis.lrange(key("list"), 0, -1).await.unwrap();
+        assert_eq!(list, ["a", "b", "c"]);
+        let hash: HashMap<String, String> = redis.hgetall(key("hash")).await.unwrap();
+        assert_eq!(hash["f"], "v");
+        assert_eq!(hash["g"], "w");
+        let geohash: Vec<String> = cmd("GEOHASH")
+            .arg(key("geo"))
+            .arg("Palermo")
+            .query(redis)
+            .await
+            .unwrap();
+        assert_eq!(geohash, ["sqc8b49rny0"]);
+
+        let entries: Vec<(String, Vec<String>)> = cmd("XRANGE")
+            .arg(key("stream"))
+            .args(["-", "+"])
+            .query(redis)
+            .await
+            .unwrap();
+        assert_eq!(
+            entries,
+            [
+                ("1-1".to_owned(), vec!["f".to_owned(), "v".to_owned()]),
+                ("2-1".to_owned(), vec!["g".to_owned(), "w".to_owned()]),
+            ]
+        );
+        let pending: (i64, String, String, Vec<(String, String)>) = cmd("XPENDING")
+            .arg(key("stream"))
+            .arg("group")
+            .query(redis)
+            .await
+            .unwrap();
+        assert_eq!(
+            pending,
+            (
+                1,
+                "1-1".to_owned(),
+                "1-1".to_owned(),
+                vec![("alice".to_owned(), "1".to_owned())]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn saves_the_dataset_for_the_next_start() {
+        let server = TestServer::start().await;
+        if server.is_real_redis() {
+            return server.stop().await;
+        }
+        write_every_type(&mut server.connect().await, "").await;
+
+        let server = server.restart().await;
+        check_every_type(&mut server.connect().await, "").await;
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn saves_the_keys_of_every_shard() {
+        let mut config = redis_clone::config::Config::default();
+        config.shards = 4;
+        let server = TestServer::start_with(config).await;
+        if server.is_real_redis() {
+            return server.stop().await;
+        }
+        let mut redis = server.connect().await;
+        for n in 0..4 {
+            write_every_type(&mut redis, &format!(":{}", n)).await;
+        }
+        drop(redis);
+
+        let server = server.restart().await;
+        let mut redis = server.connect().await;
+        for n in 0..4 {
+            check_every_type(&mut redis, &format!(":{}", n)).await;
+        }
+
+        server.stop().await;
+    }
+}
+
+mod sharding {
+    use super::*;
+    use redis_clone::config::Config;
+
+    async fn start() -> TestServer {
+        let mut config = Config::default();
+        config.shards = 4;
+        TestServer::start_with(config).await
+    }
+
+    /// Keys spread across the shards, as there are many more than shards
+    fn keys() -> Vec<String> {
+        (0..16).map(|n| format!("key:{}", n)).collect()
+    }
+
+    #[tokio::test]
+    async fn scripts_reach_the_keys_they_declare() {
+        let server = start().await;
+        let mut redis = server.connect().await;
+        let keys = keys();
+
+        let script = "for i, key in ipairs(KEYS) do redis.call('set', key, i) end \
+                      return redis.call('mget', unpack(KEYS))";
+        let values: Vec<String> = cmd("EVAL")
+            .arg(script)
+            .arg(keys.len())
+            .args(&keys)
+            .query(&mut redis)
+            .await
+            .unwrap();
+        let expected: Vec<String> = (1..=keys.len()).map(|i| i.to_string()).collect();
+        assert_eq!(values, expected);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn scripts_are_cached_by_every_shard() {
+        let server = start().await;
+        let mut redis = server.connect().await;
+
+        let sha: String = cmd("SCRIPT")
+            .arg("load")
+            .arg("return redis.call('incr', KEYS[1])")
+            .query(&mut redis)
+            .await
+            .unwrap();
+        for key in keys() {
+            let value: i64 = cmd("EVALSHA")
+                .arg(&sha)
+                .arg(1)
+                .arg(&key)
+                .query(&mut redis)
+                .await
+                .unwrap();
+            assert_eq!(value, 1);
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn scripts_cant_reach_keys_on_other_shards() {
+        let server = start().await;
+        // A single Redis holds every key
+        if server.is_real_redis() {
+            return server.stop().await;
+        }
+        let mut redis = server.connect().await;
+
+        let mut refused = 0;
+        for key in keys() {
+            redis.set(&key, "a").await.unwrap();
+            let result = cmd("EVAL")
+                .arg("return redis.call('get', ARGV[1])")
+                .arg(0)
+                .arg(&key)
+                .query::<String>(&mut redis)
+                .await;
+            match result {
+                Ok(value) => assert_eq!(value, "a"),
+                Err(_) => {
+                    assert_error(
+                        result,
+                        "ERR Script attempted to access a non local key in a cluster node script",
+                    );
+                    refused += 1;
+                }
+            }
+        }
+        assert!(refused > 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reports_on_the_whole_server() {
+        let mut server = start().await;
+        server.preserve_config(&["slowlog-log-slower-than"]).await;
+        let mut redis = server.connect().await;
+        redis
+            .config_set("slowlog-log-slower-than", 0)
+            .await
+            .unwrap();
+        cmd("SLOWLOG")
+            .arg("reset")
+            .query::<()>(&mut redis)
+            .await
+            .unwrap();
+        cmd("CLIENT")
+            .arg("setname")
+            .arg("spread")
+            .query::<()>(&mut redis)
+            .await
+            .unwrap();
+
+        let keys = keys();
+        for key in &keys {
+            redis.set(key, "a").await.unwrap();
+        }
+
+        let stats = info(&mut redis, Some("commandstats")).await;
+        assert!(
+            stats["cmdstat_set"].starts_with(&format!("calls={},", keys.len())),
+            "{:?}",
+            stats
+        );
+        let len: usize = cmd("SLOWLOG").arg("len").query(&mut redis).await.unwrap();
+        assert!(len > keys.len(), "{}", len);
+
+        // Named on every shard, so whichever lists the clients knows it
+        for key in &keys {
+            redis.get::<_, String>(key).await.unwrap();
+            let name: String = cmd("CLIENT")
+                .arg("getname")
+                .query(&mut redis)
+                .await
+                .unwrap();
+            assert_eq!(name, "spread");
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_messages_published_from_any_shard() {
+        let server = start().await;
+        let mut redis = server.connect().await;
+
+        let mut subscriber = server.connect().await;
+        subscriber
+            .send(&[cmd("SUBSCRIBE").arg("news")])
+            .await
+            .unwrap();
+        subscriber.receive().await.unwrap();
+
+        let receivers: i64 = cmd("PUBLISH")
+            .arg("news")
+            .arg("hello")
+            .query(&mut redis)
+            .await
+            .unwrap();
+        assert_eq!(receivers, 1);
+        let message = Vec::<String>::from_value(subscriber.receive().await.unwrap()).unwrap();
+        assert_eq!(message, ["message", "news", "hello"]);
+
+        server.stop().await;
+    }
+}
@@ -0,0 +1,761 @@
+//! Ported from tests/functional/strings_spec.rb
+
+mod common;
+
+use common::*;
+use redis_clone_client::cmd;
+
+#[tokio::test]
+async fn arity() {
+    let server = TestServer::start().await;
+    let mut redis = server.connect().await;
+
+    assert_eq!(command_arity(&mut redis, "set").await, -3);
+    assert_eq!(command_arity(&mut redis, "get").await, 2);
+    assert_eq!(command_arity(&mut redis, "mset").await, -3);
+    assert_eq!(command_arity(&mut redis, "mget").await, -2);
+    assert_eq!(command_arity(&mut redis, "incr").await, 2);
+    assert_eq!(command_arity(&mut redis, "incrby").await, 3);
+    assert_eq!(command_arity(&mut redis, "decr").await, 2);
+    assert_eq!(command_arity(&mut redis, "decrby").await, 3);
+
+    server.stop().await;
+}
+
+mod wrong_type {
+    use super::*;
+
+    #[tokio::test]
+    async fn all_but_set_raise_an_error() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        redis.rpush("x", &[1]).await.unwrap();
+
+        assert_error(redis.get::<_, String>("x").await, WRONGTYPE);
+        assert_error(redis.incr("x").await, WRONGTYPE);
+        assert_error(redis.incr_by("x", 1).await, WRONGTYPE);
+        assert_error(redis.decr("x").await, WRONGTYPE);
+        assert_error(redis.decr_by("x", 1).await, WRONGTYPE);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn set_overwrites_with_the_new_type() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        redis.rpush("x", &[1]).await.unwrap();
+
+        let reply: String = cmd("SET")
+            .arg("x")
+            .arg("y")
+            .query(&mut redis)
+            .await
+            .unwrap();
+        assert_eq!(reply, "OK");
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "y");
+
+        server.stop().await;
+    }
+}
+
+mod set {
+    use super::*;
+    use redis_clone_client::{Connection, Result};
+
+    /// SET with options, giving `None` when the key isn't set because of NX
+    /// or XX
+    async fn set_with(
+        redis: &mut Connection,
+        key: &str,
+        value: &str,
+        options: &[&str],
+    ) -> Result<Option<String>> {
+        cmd("SET")
+            .arg(key)
+            .arg(value)
+            .args(options)
+            .query(redis)
+            .await
+    }
+
+    #[tokio::test]
+    async fn sets_a_new_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(
+            set_with(&mut redis, "x", "abc", &[])
+                .await
+                .unwrap()
+                .unwrap(),
+            "OK"
+        );
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "abc");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn sets_a_new_key_with_nx() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert!(set_with(&mut redis, "x", "123", &["NX"])
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "123");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn does_not_set_a_new_key_with_xx() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert!(set_with(&mut redis, "x", "abc", &["XX"])
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(redis.exists(&["x"]).await.unwrap(), 0);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn overwrites_an_existing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        redis.set("x", "123").await.unwrap();
+
+        assert_eq!(
+            set_with(&mut redis, "x", "456", &[])
+                .await
+                .unwrap()
+                .unwrap(),
+            "OK"
+        );
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "456");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn overwrites_an_existing_key_with_xx() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        redis.set("x", "123").await.unwrap();
+
+        assert!(set_with(&mut redis, "x", "456", &["XX"])
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "456");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn does_not_overwrite_an_existing_key_with_nx() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+        redis.set("x", "123").await.unwrap();
+
+        assert!(set_with(&mut redis, "x", "456", &["NX"])
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "123");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_both_nx_and_xx() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            set_with(&mut redis, "x", "y", &["XX", "NX"]).await,
+            "ERR syntax error",
+        );
+
+        server.stop().await;
+    }
+
+    mod expire_times {
+        use super::*;
+
+        async fn ttl_between(redis: &mut Connection, key: &str, min: i64, max: i64) {
+            let ttl = redis.ttl(key).await.unwrap();
+            assert!(
+                (min..=max).contains(&ttl),
+                "{} is not within {}..={}",
+                ttl,
+                min,
+                max
+            );
+        }
+
+        #[tokio::test]
+        async fn sets_a_ttl_on_a_new_key() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            set_with(&mut redis, "x", "a", &["EX", "10"]).await.unwrap();
+            ttl_between(&mut redis, "x", 0, 10).await;
+
+            set_with(&mut redis, "y", "a", &["PX", "20000"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "y", 10, 20).await;
+
+            server.stop().await;
+        }
+
+        #[tokio::test]
+        async fn sets_a_ttl_on_a_persistent_key() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            redis.set("x", "a").await.unwrap();
+            assert_eq!(redis.ttl("x").await.unwrap(), -1);
+            set_with(&mut redis, "x", "a", &["EX", "10"]).await.unwrap();
+            ttl_between(&mut redis, "x", 0, 10).await;
+
+            redis.set("y", "a").await.unwrap();
+            assert_eq!(redis.ttl("y").await.unwrap(), -1);
+            set_with(&mut redis, "y", "a", &["PX", "20000"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "y", 10, 20).await;
+
+            server.stop().await;
+        }
+
+        #[tokio::test]
+        async fn overwrites_an_existing_ttl() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            set_with(&mut redis, "x", "a", &["EX", "10"]).await.unwrap();
+            ttl_between(&mut redis, "x", 0, 10).await;
+            set_with(&mut redis, "x", "a", &["EX", "20"]).await.unwrap();
+            ttl_between(&mut redis, "x", 10, 20).await;
+
+            set_with(&mut redis, "y", "a", &["PX", "10000"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "y", 0, 10).await;
+            set_with(&mut redis, "y", "a", &["PX", "20000"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "y", 10, 20).await;
+
+            server.stop().await;
+        }
+
+        #[tokio::test]
+        async fn rejects_a_ttl_that_is_not_positive() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            for options in &[["EX", "0"], ["EX", "-1"], ["PX", "0"], ["PX", "-1"]] {
+                assert_error(
+                    set_with(&mut redis, "x", "a", options).await,
+                    "ERR invalid expire time in set",
+                );
+            }
+
+            server.stop().await;
+        }
+
+        #[tokio::test]
+        async fn rejects_a_missing_ttl() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            assert_error(
+                set_with(&mut redis, "x", "a", &["EX"]).await,
+                "ERR syntax error",
+            );
+            assert_error(
+                set_with(&mut redis, "x", "a", &["PX"]).await,
+                "ERR syntax error",
+            );
+
+            server.stop().await;
+        }
+
+        #[tokio::test]
+        async fn rejects_both_ex_and_px() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            assert_error(
+                set_with(&mut redis, "x", "y", &["EX", "1", "PX", "1000"]).await,
+                "ERR syntax error",
+            );
+
+            server.stop().await;
+        }
+
+        #[tokio::test]
+        async fn combinations_of_arguments() {
+            let server = TestServer::start().await;
+            let mut redis = server.connect().await;
+
+            // Can use ex and nx together
+            set_with(&mut redis, "key", "val", &["ex", "10", "nx"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "key", 0, 10).await;
+
+            // Can use ex and xx together
+            set_with(&mut redis, "key", "val", &["ex", "10", "xx"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "key", 0, 10).await;
+
+            // For an existing key, nx prevents the value changing but not the ttl
+            set_with(&mut redis, "key", "xyz", &["px", "20000", "nx"])
+                .await
+                .unwrap();
+            assert_eq!(redis.get::<_, String>("key").await.unwrap(), "val");
+            ttl_between(&mut redis, "key", 10, 20).await;
+
+            // For an existing key with xx, both the value and the ttl change
+            set_with(&mut redis, "key", "val", &["px", "30000", "xx"])
+                .await
+                .unwrap();
+            ttl_between(&mut redis, "key", 20, 30).await;
+
+            // The order of the arguments does not matter
+            set_with(&mut redis, "key", "xyz", &["nx", "px", "40000"])
+                .await
+                .unwrap();
+            assert_eq!(redis.get::<_, String>("key").await.unwrap(), "val");
+            ttl_between(&mut redis, "key", 30, 40).await;
+
+            server.stop().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn encodes_numeric_strings_as_integers() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "a").await.unwrap();
+        let expected = if server.is_real_redis() {
+            "embstr"
+        } else {
+            "byte_string"
+        };
+        assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), expected);
+        redis.del(&["x"]).await.unwrap();
+
+        for value in &["-1", "0", "1"] {
+            redis.set("x", value).await.unwrap();
+            assert_eq!(object_encoding(&mut redis, "x").await.unwrap(), "int");
+        }
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn supports_binary_keys_and_values() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        // An invalid UTF-8 sequence
+        let binary = b"\xe2\x28\xa1";
+        redis.set(binary, binary).await.unwrap();
+        assert_eq!(redis.get::<_, Vec<u8>>(binary).await.unwrap(), binary);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn permits_empty_keys_and_values() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("", "").await.unwrap();
+        assert_eq!(redis.get::<_, String>("").await.unwrap(), "");
+
+        server.stop().await;
+    }
+}
+
+mod get {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_nil_for_a_missing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(
+            redis
+                .get::<_, Option<String>>("non-existent")
+                .await
+                .unwrap(),
+            None
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_the_value() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "123").await.unwrap();
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "123");
+
+        server.stop().await;
+    }
+}
+
+mod mset {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.mset(&[("x", "y")]).await.unwrap();
+        assert_eq!(redis.key_type("x").await.unwrap(), "string");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn overwrites_an_existing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "y").await.unwrap();
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "y");
+        redis.mset(&[("x", "yy")]).await.unwrap();
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "yy");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn adds_new_keys() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.mset(&[("a", 1), ("b", 2)]).await.unwrap();
+        assert_eq!(redis.get::<_, String>("a").await.unwrap(), "1");
+        assert_eq!(redis.get::<_, String>("b").await.unwrap(), "2");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn adds_new_keys_and_updates_existing_ones() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("a", 1).await.unwrap();
+        redis.mset(&[("a", 2), ("b", 2)]).await.unwrap();
+        assert_eq!(redis.get::<_, String>("a").await.unwrap(), "2");
+        assert_eq!(redis.get::<_, String>("b").await.unwrap(), "2");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_an_uneven_number_of_arguments() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_error(
+            cmd("MSET")
+                .arg("a")
+                .arg(1)
+                .arg("b")
+                .query::<()>(&mut redis)
+                .await,
+            "ERR wrong number of arguments for MSET",
+        );
+
+        server.stop().await;
+    }
+}
+
+mod mget {
+    use super::*;
+
+    #[tokio::test]
+    async fn returns_nils_when_no_keys_exist() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        let values: Vec<Option<String>> = redis.mget(&["x", "y"]).await.unwrap();
+        assert_eq!(values, vec![None, None]);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_nil_for_missing_keys() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.mset(&[("a", "one"), ("c", "3")]).await.unwrap();
+        let values: Vec<Option<String>> = redis.mget(&["a", "b", "c"]).await.unwrap();
+        assert_eq!(
+            values,
+            vec![Some("one".to_owned()), None, Some("3".to_owned())]
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn returns_nil_for_keys_of_other_types() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.mset(&[("a", "one"), ("c", "3")]).await.unwrap();
+        redis.rpush("b", &["1"]).await.unwrap();
+        let values: Vec<Option<String>> = redis.mget(&["a", "b", "c"]).await.unwrap();
+        assert_eq!(
+            values,
+            vec![Some("one".to_owned()), None, Some("3".to_owned())]
+        );
+
+        server.stop().await;
+    }
+}
+
+mod incr {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_key_set_to_1() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.incr("x").await.unwrap(), 1);
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "1");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn increments_a_key_created_by_incr() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.incr("x").await.unwrap(), 1);
+        assert_eq!(redis.incr("x").await.unwrap(), 2);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn increments_a_key_created_by_set() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "100").await.unwrap();
+        assert_eq!(redis.incr("x").await.unwrap(), 101);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_overflow() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "9223372036854775807").await.unwrap();
+        assert_error(
+            redis.incr("x").await,
+            "ERR increment or decrement would overflow",
+        );
+        assert_eq!(
+            redis.get::<_, String>("x").await.unwrap(),
+            "9223372036854775807"
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_surrounding_spaces() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        for value in &[" 1", "1 ", " 1 "] {
+            redis.set("x", value).await.unwrap();
+            assert_error(
+                redis.incr("x").await,
+                "ERR value is not an integer or out of range",
+            );
+        }
+
+        server.stop().await;
+    }
+}
+
+mod incrby {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_key_set_to_the_increment() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.incr_by("x", 2).await.unwrap(), 2);
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "2");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn increments_an_existing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", 100).await.unwrap();
+        assert_eq!(redis.incr_by("x", 2).await.unwrap(), 102);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_non_numeric_value() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "nnn").await.unwrap();
+        assert_error(
+            redis.incr_by("x", 2).await,
+            "ERR value is not an integer or out of range",
+        );
+
+        server.stop().await;
+    }
+}
+
+mod decr {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_key_set_to_minus_1() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.decr("x").await.unwrap(), -1);
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "-1");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn decrements_a_key_created_by_decr() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.decr("x").await.unwrap(), -1);
+        assert_eq!(redis.decr("x").await.unwrap(), -2);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn decrements_a_key_created_by_set() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "100").await.unwrap();
+        assert_eq!(redis.decr("x").await.unwrap(), 99);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_overflow() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "-9223372036854775808").await.unwrap();
+        assert_error(
+            redis.decr("x").await,
+            "ERR increment or decrement would overflow",
+        );
+        assert_eq!(
+            redis.get::<_, String>("x").await.unwrap(),
+            "-9223372036854775808"
+        );
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_surrounding_spaces() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        for value in &[" 1", "1 ", " 1 "] {
+            redis.set("x", value).await.unwrap();
+            assert_error(
+                redis.decr("x").await,
+                "ERR value is not an integer or out of range",
+            );
+        }
+
+        server.stop().await;
+    }
+}
+
+mod decrby {
+    use super::*;
+
+    #[tokio::test]
+    async fn creates_a_new_key_set_to_the_decrement() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        assert_eq!(redis.decr_by("x", 2).await.unwrap(), -2);
+        assert_eq!(redis.get::<_, String>("x").await.unwrap(), "-2");
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn decrements_an_existing_key() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", 100).await.unwrap();
+        assert_eq!(redis.decr_by("x", 2).await.unwrap(), 98);
+
+        server.stop().await;
+    }
+
+    #[tokio::test]
+    async fn rejects_a_non_numeric_value() {
+        let server = TestServer::start().await;
+        let mut redis = server.connect().await;
+
+        redis.set("x", "nnn").await.unwrap();
+        assert_error(
+            redis.decr_by("x", 2).await,
+            "ERR value is not an integer or out of range",
+        );
+
+        server.stop().await;
+    }
+}